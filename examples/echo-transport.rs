@@ -0,0 +1,111 @@
+/*
+ * libgit2 custom smart transport example
+ *
+ * Written by the libgit2 contributors
+ *
+ * To the extent possible under law, the author(s) have dedicated all copyright
+ * and related and neighboring rights to this software to the public domain
+ * worldwide. This software is distributed without any warranty.
+ *
+ * You should have received a copy of the CC0 Public Domain Dedication along
+ * with this software. If not, see
+ * <http://creativecommons.org/publicdomain/zero/1.0/>.
+ */
+
+#![deny(warnings)]
+
+use git2::transport::{self, Service, SmartSubtransport, SmartSubtransportStream};
+use git2::{Direction, Error, Repository};
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+struct Args {
+    #[structopt(name = "path")]
+    /// path of the repository to list through the echo transport
+    arg_path: String,
+}
+
+// A subtransport for `echo://<path>` urls, which echoes the git protocol to a
+// `git upload-pack` or `git receive-pack` process serving the repository at
+// `<path>`, much like `git` does for local repositories.
+struct EchoTransport;
+
+// The stream of one connection: the standard input and output of the process.
+struct EchoStream {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl SmartSubtransport for EchoTransport {
+    fn action(
+        &self,
+        url: &str,
+        action: Service,
+    ) -> Result<Box<dyn SmartSubtransportStream>, Error> {
+        let path = url.trim_start_matches("echo://");
+        let mut child = Command::new("git")
+            .arg(&action.program()["git-".len()..])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::from_str(&format!("failed to run git: {}", e)))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        Ok(Box::new(EchoStream {
+            child,
+            stdin,
+            stdout,
+        }))
+    }
+}
+
+impl Read for EchoStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for EchoStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Drop for EchoStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn run(args: &Args) -> Result<(), Error> {
+    // The process serves a whole session, so the protocol is not stateless.
+    unsafe {
+        transport::register_smart("echo", false, |_remote| Ok(EchoTransport))?;
+    }
+
+    let repo = Repository::open(".")?;
+    let url = format!("echo://{}", args.arg_path);
+    let mut remote = repo.remote_anonymous(&url)?;
+    let connection = remote.connect_auth(Direction::Fetch, None, None)?;
+    for head in connection.list()?.iter() {
+        println!("{}\t{}", head.oid(), head.name());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    match run(&args) {
+        Ok(()) => {}
+        Err(e) => println!("error: {}", e),
+    }
+}
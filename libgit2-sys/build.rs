@@ -12,7 +12,11 @@ fn main() {
     // To use zlib-ng in zlib-compat mode, we have to build libgit2 ourselves.
     if !zlib_ng_compat {
         let mut cfg = pkg_config::Config::new();
-        if let Ok(lib) = cfg.atleast_version("1.1.0").probe("libgit2") {
+        // The bindings match the structs of libgit2 1.1. Later releases add
+        // fields, such as `remote_ready` in `git_remote_callbacks`, which
+        // would make libgit2 read clone and fetch options at the wrong
+        // offsets, so those are built from the bundled sources instead.
+        if let Ok(lib) = cfg.range_version("1.1.0".."1.2.0").probe("libgit2") {
             for include in &lib.include_paths {
                 println!("cargo:root={}", include.display());
             }
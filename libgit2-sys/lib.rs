@@ -4,7 +4,7 @@
 // This is required to link libz when libssh2-sys is not included.
 extern crate libz_sys as libz;
 
-use libc::{c_char, c_int, c_uchar, c_uint, c_void, size_t};
+use libc::{c_char, c_int, c_uchar, c_uint, c_ushort, c_void, size_t};
 #[cfg(feature = "ssh")]
 use libssh2_sys as libssh2;
 use std::ffi::CStr;
@@ -59,7 +59,6 @@ pub enum git_index {}
 pub enum git_index_conflict_iterator {}
 pub enum git_object {}
 pub enum git_reference {}
-pub enum git_reference_iterator {}
 pub enum git_annotated_commit {}
 pub enum git_refdb {}
 pub enum git_refspec {}
@@ -253,6 +252,26 @@ git_enum! {
     }
 }
 
+git_enum! {
+    pub enum git_repository_item_t {
+        GIT_REPOSITORY_ITEM_GITDIR,
+        GIT_REPOSITORY_ITEM_WORKDIR,
+        GIT_REPOSITORY_ITEM_COMMONDIR,
+        GIT_REPOSITORY_ITEM_INDEX,
+        GIT_REPOSITORY_ITEM_OBJECTS,
+        GIT_REPOSITORY_ITEM_REFS,
+        GIT_REPOSITORY_ITEM_PACKED_REFS,
+        GIT_REPOSITORY_ITEM_REMOTES,
+        GIT_REPOSITORY_ITEM_CONFIG,
+        GIT_REPOSITORY_ITEM_INFO,
+        GIT_REPOSITORY_ITEM_HOOKS,
+        GIT_REPOSITORY_ITEM_LOGS,
+        GIT_REPOSITORY_ITEM_MODULES,
+        GIT_REPOSITORY_ITEM_WORKTREES,
+        GIT_REPOSITORY_ITEM__LAST,
+    }
+}
+
 git_enum! {
     pub enum git_direction {
         GIT_DIRECTION_FETCH,
@@ -366,6 +385,7 @@ pub struct git_remote_callbacks {
     pub push_update_reference: git_push_update_reference_cb,
     pub push_negotiation: git_push_negotiation,
     pub transport: git_transport_cb,
+    pub remote_ready: *mut c_void,
     pub payload: *mut c_void,
     pub resolve_url: git_url_resolve_cb,
 }
@@ -506,6 +526,9 @@ pub struct git_diff_file {
 
 pub type git_repository_create_cb =
     Option<extern "C" fn(*mut *mut git_repository, *const c_char, c_int, *mut c_void) -> c_int>;
+pub type git_repository_fetchhead_foreach_cb = Option<
+    extern "C" fn(*const c_char, *const c_char, *const git_oid, c_uint, *mut c_void) -> c_int,
+>;
 pub type git_remote_create_cb = Option<
     extern "C" fn(
         *mut *mut git_remote,
@@ -623,6 +646,9 @@ pub struct git_status_entry {
     pub index_to_workdir: *mut git_diff_delta,
 }
 
+pub type git_status_cb =
+    Option<extern "C" fn(path: *const c_char, status_flags: c_uint, payload: *mut c_void) -> c_int>;
+
 git_enum! {
     pub enum git_checkout_strategy_t {
         GIT_CHECKOUT_NONE = 0,
@@ -807,6 +833,20 @@ pub struct git_index_entry {
     pub path: *const c_char,
 }
 
+#[repr(C)]
+pub struct git_index_reuc_entry {
+    pub mode: [u32; 3],
+    pub oid: [git_oid; 3],
+    pub path: *mut c_char,
+}
+
+#[repr(C)]
+pub struct git_index_name_entry {
+    pub ancestor: *mut c_char,
+    pub ours: *mut c_char,
+    pub theirs: *mut c_char,
+}
+
 pub const GIT_INDEX_ENTRY_NAMEMASK: u16 = 0xfff;
 pub const GIT_INDEX_ENTRY_STAGEMASK: u16 = 0x3000;
 pub const GIT_INDEX_ENTRY_STAGESHIFT: u16 = 12;
@@ -1339,6 +1379,49 @@ git_enum! {
     }
 }
 
+pub enum git_merge_driver_source {}
+
+#[repr(C)]
+pub struct git_merge_driver {
+    pub version: c_uint,
+    pub initialize: Option<extern "C" fn(*mut git_merge_driver) -> c_int>,
+    pub shutdown: Option<extern "C" fn(*mut git_merge_driver)>,
+    pub apply: Option<
+        extern "C" fn(
+            *mut git_merge_driver,
+            *mut *const c_char,
+            *mut u32,
+            *mut git_buf,
+            *const c_char,
+            *const git_merge_driver_source,
+        ) -> c_int,
+    >,
+}
+
+pub const GIT_MERGE_DRIVER_VERSION: c_uint = 1;
+
+#[repr(C)]
+pub struct git_merge_file_options {
+    pub version: c_uint,
+    pub ancestor_label: *const c_char,
+    pub our_label: *const c_char,
+    pub their_label: *const c_char,
+    pub favor: git_merge_file_favor_t,
+    pub flags: u32,
+    pub marker_size: c_ushort,
+}
+
+pub const GIT_MERGE_FILE_OPTIONS_VERSION: c_uint = 1;
+
+#[repr(C)]
+pub struct git_merge_file_result {
+    pub automergeable: c_uint,
+    pub path: *const c_char,
+    pub mode: c_uint,
+    pub ptr: *const c_char,
+    pub len: size_t,
+}
+
 pub type git_transport_cb = Option<
     extern "C" fn(
         out: *mut *mut git_transport,
@@ -1508,6 +1591,14 @@ pub struct git_odb_writepack {
     pub free: Option<unsafe extern "C" fn(*mut git_odb_writepack)>,
 }
 
+#[repr(C)]
+pub struct git_reference_iterator {
+    pub db: *mut git_refdb,
+    pub next: Option<extern "C" fn(*mut *mut git_reference, *mut git_reference_iterator) -> c_int>,
+    pub next_name: Option<extern "C" fn(*mut *const c_char, *mut git_reference_iterator) -> c_int>,
+    pub free: Option<extern "C" fn(*mut git_reference_iterator)>,
+}
+
 #[repr(C)]
 pub struct git_refdb_backend {
     pub version: c_uint,
@@ -1954,10 +2045,27 @@ extern "C" {
     ) -> c_int;
     pub fn git_repository_index(out: *mut *mut git_index, repo: *mut git_repository) -> c_int;
     pub fn git_repository_set_index(repo: *mut git_repository, index: *mut git_index) -> c_int;
+    pub fn git_repository_hashfile(
+        out: *mut git_oid,
+        repo: *mut git_repository,
+        path: *const c_char,
+        kind: git_object_t,
+        as_path: *const c_char,
+    ) -> c_int;
 
     pub fn git_repository_message(buf: *mut git_buf, repo: *mut git_repository) -> c_int;
 
     pub fn git_repository_message_remove(repo: *mut git_repository) -> c_int;
+    pub fn git_repository_fetchhead_foreach(
+        repo: *mut git_repository,
+        callback: git_repository_fetchhead_foreach_cb,
+        payload: *mut c_void,
+    ) -> c_int;
+    pub fn git_repository_item_path(
+        out: *mut git_buf,
+        repo: *const git_repository,
+        item: git_repository_item_t,
+    ) -> c_int;
     pub fn git_repository_config(out: *mut *mut git_config, repo: *mut git_repository) -> c_int;
     pub fn git_repository_set_config(repo: *mut git_repository, config: *mut git_config) -> c_int;
     pub fn git_repository_config_snapshot(
@@ -2204,6 +2312,12 @@ extern "C" {
     pub fn git_signature_dup(dest: *mut *mut git_signature, sig: *const git_signature) -> c_int;
 
     // status
+    pub fn git_status_foreach_ext(
+        repo: *mut git_repository,
+        opts: *const git_status_options,
+        callback: git_status_cb,
+        payload: *mut c_void,
+    ) -> c_int;
     pub fn git_status_list_new(
         out: *mut *mut git_status_list,
         repo: *mut git_repository,
@@ -2308,6 +2422,15 @@ extern "C" {
         glob: *const c_char,
     ) -> c_int;
     pub fn git_reference_iterator_free(iter: *mut git_reference_iterator);
+    pub fn git_reference__alloc(
+        name: *const c_char,
+        oid: *const git_oid,
+        peel: *const git_oid,
+    ) -> *mut git_reference;
+    pub fn git_reference__alloc_symbolic(
+        name: *const c_char,
+        target: *const c_char,
+    ) -> *mut git_reference;
     pub fn git_reference_next(
         out: *mut *mut git_reference,
         iter: *mut git_reference_iterator,
@@ -2543,6 +2666,7 @@ extern "C" {
     pub fn git_tree_entrycount(tree: *const git_tree) -> size_t;
     pub fn git_tree_free(tree: *mut git_tree);
     pub fn git_tree_id(tree: *const git_tree) -> *const git_oid;
+    pub fn git_tree_owner(tree: *const git_tree) -> *mut git_repository;
     pub fn git_tree_lookup(
         tree: *mut *mut git_tree,
         repo: *mut git_repository,
@@ -2805,6 +2929,7 @@ extern "C" {
     pub fn git_index_has_conflicts(index: *const git_index) -> c_int;
     pub fn git_index_new(index: *mut *mut git_index) -> c_int;
     pub fn git_index_open(index: *mut *mut git_index, index_path: *const c_char) -> c_int;
+    pub fn git_index_owner(index: *const git_index) -> *mut git_repository;
     pub fn git_index_path(index: *const git_index) -> *const c_char;
     pub fn git_index_read(index: *mut git_index, force: c_int) -> c_int;
     pub fn git_index_read_tree(index: *mut git_index, tree: *const git_tree) -> c_int;
@@ -2816,6 +2941,43 @@ extern "C" {
         payload: *mut c_void,
     ) -> c_int;
     pub fn git_index_remove_bypath(index: *mut git_index, path: *const c_char) -> c_int;
+    pub fn git_index_reuc_entrycount(index: *mut git_index) -> size_t;
+    pub fn git_index_reuc_find(
+        at_pos: *mut size_t,
+        index: *mut git_index,
+        path: *const c_char,
+    ) -> c_int;
+    pub fn git_index_reuc_get_bypath(
+        index: *mut git_index,
+        path: *const c_char,
+    ) -> *const git_index_reuc_entry;
+    pub fn git_index_reuc_get_byindex(
+        index: *mut git_index,
+        n: size_t,
+    ) -> *const git_index_reuc_entry;
+    pub fn git_index_reuc_add(
+        index: *mut git_index,
+        path: *const c_char,
+        ancestor_mode: c_int,
+        ancestor_id: *const git_oid,
+        our_mode: c_int,
+        our_id: *const git_oid,
+        their_mode: c_int,
+        their_id: *const git_oid,
+    ) -> c_int;
+    pub fn git_index_reuc_remove(index: *mut git_index, n: size_t) -> c_int;
+    pub fn git_index_reuc_clear(index: *mut git_index);
+    pub fn git_index_name_entrycount(index: *mut git_index) -> size_t;
+    pub fn git_index_name_get_byindex(
+        index: *mut git_index,
+        n: size_t,
+    ) -> *const git_index_name_entry;
+    pub fn git_index_name_add(
+        index: *mut git_index,
+        ancestor: *const c_char,
+        ours: *const c_char,
+        theirs: *const c_char,
+    ) -> c_int;
     pub fn git_index_remove_directory(
         index: *mut git_index,
         dir: *const c_char,
@@ -3112,8 +3274,32 @@ extern "C" {
         their_tree: *const git_tree,
         opts: *const git_merge_options,
     ) -> c_int;
+    pub fn git_merge_file_from_index(
+        out: *mut git_merge_file_result,
+        repo: *mut git_repository,
+        ancestor: *const git_index_entry,
+        ours: *const git_index_entry,
+        theirs: *const git_index_entry,
+        opts: *const git_merge_file_options,
+    ) -> c_int;
+    pub fn git_merge_file_result_free(result: *mut git_merge_file_result);
     pub fn git_repository_state_cleanup(repo: *mut git_repository) -> c_int;
 
+    // merge drivers
+    pub fn git_merge_driver_register(name: *const c_char, driver: *mut git_merge_driver) -> c_int;
+    pub fn git_merge_driver_unregister(name: *const c_char) -> c_int;
+    pub fn git_merge_driver_source_repo(src: *const git_merge_driver_source)
+        -> *mut git_repository;
+    pub fn git_merge_driver_source_ancestor(
+        src: *const git_merge_driver_source,
+    ) -> *const git_index_entry;
+    pub fn git_merge_driver_source_ours(
+        src: *const git_merge_driver_source,
+    ) -> *const git_index_entry;
+    pub fn git_merge_driver_source_theirs(
+        src: *const git_merge_driver_source,
+    ) -> *const git_index_entry;
+
     // merge analysis
 
     pub fn git_merge_analysis(
@@ -1,9 +1,9 @@
 //! git_apply support
 //! see original: <https://github.com/libgit2/libgit2/blob/master/include/git2/apply.h>
 
-use crate::{panic, raw, util::Binding, DiffDelta, DiffHunk};
+use crate::{panic, raw, util, util::Binding, DiffDelta, DiffHunk, Error, Patch};
 use libc::c_int;
-use std::{ffi::c_void, mem};
+use std::{ffi::c_void, mem, path::Path};
 
 /// Possible application locations for git_apply
 /// see <https://libgit2.org/libgit2/#HEAD/type/git_apply_options>
@@ -41,6 +41,7 @@ pub struct ApplyOptions<'cb> {
     raw: raw::git_apply_options,
     hunk_cb: Option<Box<HunkCB<'cb>>>,
     delta_cb: Option<Box<DeltaCB<'cb>>>,
+    three_way: bool,
 }
 
 type HunkCB<'a> = dyn FnMut(Option<DiffHunk<'_>>) -> bool + 'a;
@@ -93,6 +94,7 @@ impl<'cb> ApplyOptions<'cb> {
             raw: unsafe { mem::zeroed() },
             hunk_cb: None,
             delta_cb: None,
+            three_way: false,
         };
         assert_eq!(
             unsafe { raw::git_apply_options_init(&mut opts.raw, raw::GIT_APPLY_OPTIONS_VERSION) },
@@ -142,12 +144,236 @@ impl<'cb> ApplyOptions<'cb> {
         self
     }
 
+    /// When a delta does not apply to its file, fall back on a three-way
+    /// merge of the file, as `git apply --3way` does.
+    ///
+    /// This is only honored by `Repository::apply_to_tree`, which then
+    /// returns an index with conflict entries for the files whose merge
+    /// conflicts instead of failing. The blobs the diff was made from must
+    /// be in the repository.
+    pub fn three_way(&mut self, three_way: bool) -> &mut Self {
+        self.three_way = three_way;
+        self
+    }
+
+    pub(crate) fn is_three_way(&self) -> bool {
+        self.three_way
+    }
+
+    /// Runs the delta callback, if any, on `delta`, returning whether it
+    /// should be applied.
+    pub(crate) fn accepts_delta(&mut self, delta: DiffDelta<'_>) -> bool {
+        match self.delta_cb {
+            Some(ref mut cb) => cb(Some(delta)),
+            None => true,
+        }
+    }
+
     /// Pointer to a raw git_stash_apply_options
     pub unsafe fn raw(&mut self) -> *const raw::git_apply_options {
         &self.raw as *const _
     }
 }
 
+/// The hunks of a file which `Repository::apply_with_rejects` could not
+/// apply, as `git apply --reject` writes them to a `.rej` file.
+#[derive(Debug, Clone)]
+pub struct RejectedFile {
+    old_path: Option<Vec<u8>>,
+    new_path: Option<Vec<u8>>,
+    hunks: Vec<RejectedHunk>,
+}
+
+/// A hunk of a `RejectedFile`.
+#[derive(Debug, Clone)]
+pub struct RejectedHunk {
+    index: usize,
+    old_start: u32,
+    old_lines: u32,
+    new_start: u32,
+    new_lines: u32,
+    header: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl RejectedFile {
+    /// Copies the hunks at `hunks` of `patch` out of it.
+    pub(crate) fn new(patch: &Patch<'_>, hunks: &[usize]) -> Result<RejectedFile, Error> {
+        let delta = patch.delta();
+        let mut rejected = Vec::with_capacity(hunks.len());
+        for &index in hunks {
+            let (hunk, lines) = patch.hunk(index)?;
+            let mut body = Vec::new();
+            for line in 0..lines {
+                let line = patch.line_in_hunk(index, line)?;
+                match line.origin() {
+                    ' ' | '+' | '-' => body.push(line.origin() as u8),
+                    _ => {}
+                }
+                body.extend_from_slice(line.content());
+            }
+            rejected.push(RejectedHunk {
+                index,
+                old_start: hunk.old_start(),
+                old_lines: hunk.old_lines(),
+                new_start: hunk.new_start(),
+                new_lines: hunk.new_lines(),
+                header: hunk.header().to_vec(),
+                body,
+            });
+        }
+        Ok(RejectedFile {
+            old_path: delta.old_file().path_bytes().map(|p| p.to_vec()),
+            new_path: delta.new_file().path_bytes().map(|p| p.to_vec()),
+            hunks: rejected,
+        })
+    }
+
+    /// Returns the path of the file before the diff, if any.
+    pub fn old_path(&self) -> Option<&Path> {
+        self.old_path.as_ref().map(|p| util::bytes2path(p))
+    }
+
+    /// Returns the path of the file after the diff, if any.
+    pub fn new_path(&self) -> Option<&Path> {
+        self.new_path.as_ref().map(|p| util::bytes2path(p))
+    }
+
+    /// Returns the path of the file the hunks were meant for, next to which
+    /// git writes the `.rej` file.
+    pub fn path(&self) -> &Path {
+        self.new_path().or_else(|| self.old_path()).unwrap()
+    }
+
+    /// Returns the hunks which did not apply, in patch order.
+    pub fn hunks(&self) -> &[RejectedHunk] {
+        &self.hunks
+    }
+
+    /// Formats the rejected hunks as the contents of the `.rej` file
+    /// `git apply --reject` writes for this file.
+    pub fn to_rej(&self) -> Vec<u8> {
+        let old = self.old_path.as_ref().or(self.new_path.as_ref());
+        let new = self.new_path.as_ref().or(self.old_path.as_ref());
+        let mut rej = b"diff a/".to_vec();
+        rej.extend_from_slice(old.unwrap());
+        rej.extend_from_slice(b" b/");
+        rej.extend_from_slice(new.unwrap());
+        rej.extend_from_slice(b"\t(rejected hunks)\n");
+        for hunk in &self.hunks {
+            rej.extend_from_slice(&hunk.header);
+            rej.extend_from_slice(&hunk.body);
+        }
+        rej
+    }
+}
+
+/// Returns the indices of the hunks of `patch` which do not apply to
+/// `source`.
+///
+/// This follows what libgit2 does when applying a patch: each hunk must match
+/// exactly at the line it was made for, shifted by the hunks before it which
+/// were applied or skipped.
+pub(crate) fn rejected_hunks(patch: &Patch<'_>, source: &[u8]) -> Result<Vec<usize>, Error> {
+    let mut image = source
+        .split_inclusive(|&b| b == b'\n')
+        .map(|line| line.to_vec())
+        .collect::<Vec<_>>();
+    let mut skipped_old = 0;
+    let mut skipped_new = 0;
+    let mut rejected = Vec::new();
+    for index in 0..patch.num_hunks() {
+        let (hunk, lines) = patch.hunk(index)?;
+        let mut preimage: Vec<Vec<u8>> = Vec::new();
+        let mut postimage: Vec<Vec<u8>> = Vec::new();
+        // Which of the images the previous line went to, for the markers of
+        // a missing newline at the end of the file.
+        let mut last = (false, false);
+        for line in 0..lines {
+            let line = patch.line_in_hunk(index, line)?;
+            let content = line.content().to_vec();
+            match line.origin() {
+                ' ' => {
+                    preimage.push(content.clone());
+                    postimage.push(content);
+                    last = (true, true);
+                }
+                '-' => {
+                    preimage.push(content);
+                    last = (true, false);
+                }
+                '+' => {
+                    postimage.push(content);
+                    last = (false, true);
+                }
+                '=' | '>' | '<' => {
+                    for (used, image) in [(last.0, &mut preimage), (last.1, &mut postimage)] {
+                        if let (true, Some(prev)) = (used, image.last_mut()) {
+                            if prev.last() == Some(&b'\n') {
+                                prev.pop();
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let start = if hunk.new_start() > 0 {
+            (hunk.new_start() as usize + skipped_old).saturating_sub(skipped_new + 1)
+        } else {
+            0
+        };
+        let start = start.min(image.len());
+        let end = start + preimage.len();
+        if end <= image.len() && image[start..end] == preimage[..] {
+            image.splice(start..end, postimage);
+        } else {
+            skipped_old += hunk.old_lines() as usize;
+            skipped_new += hunk.new_lines() as usize;
+            rejected.push(index);
+        }
+    }
+    Ok(rejected)
+}
+
+impl RejectedHunk {
+    /// Returns the index of the hunk among the hunks of its delta.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Starting line number in old_file
+    pub fn old_start(&self) -> u32 {
+        self.old_start
+    }
+
+    /// Number of lines in old_file
+    pub fn old_lines(&self) -> u32 {
+        self.old_lines
+    }
+
+    /// Starting line number in new_file
+    pub fn new_start(&self) -> u32 {
+        self.new_start
+    }
+
+    /// Number of lines in new_file
+    pub fn new_lines(&self) -> u32 {
+        self.new_lines
+    }
+
+    /// Header text, including the trailing newline.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// The lines of the hunk, each prefixed with its origin as in a patch.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,4 +431,80 @@ mod tests {
         assert_eq!(count_delta, 1);
         assert_eq!(count_hunks, 1);
     }
+
+    #[test]
+    fn three_way() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree = |a: &str, b: &str| {
+            let mut builder = t!(repo.treebuilder(None));
+            t!(builder.insert("a", t!(repo.blob(a.as_bytes())), 0o100644));
+            t!(builder.insert("b", t!(repo.blob(b.as_bytes())), 0o100644));
+            t!(repo.find_tree(t!(builder.write())))
+        };
+        let base = tree("1\n2\n3\n4\n5\n6\n", "x\n");
+        let patched = tree("1\ntwo\n3\n4\n5\n6\n", "y\n");
+        let ours = tree("1\n2\n3\nfour\n5\n6\n", "z\n");
+        let diff = t!(repo.diff_tree_to_tree(Some(&base), Some(&patched), None));
+        assert!(repo.apply_to_tree(&ours, &diff, None).is_err());
+
+        let mut opts = ApplyOptions::new();
+        opts.three_way(true);
+        let index = t!(repo.apply_to_tree(&ours, &diff, Some(&mut opts)));
+        assert!(index.has_conflicts());
+        let conflicts = t!(index.conflicts()).map(|c| t!(c)).collect::<Vec<_>>();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].our.as_ref().unwrap().path, b"b");
+        assert_eq!(
+            conflicts[0].their.as_ref().unwrap().id,
+            patched.get_name("b").unwrap().id()
+        );
+        let a = index.get_path(Path::new("a"), 0).unwrap();
+        assert_eq!(
+            t!(repo.find_blob(a.id)).content(),
+            b"1\ntwo\n3\nfour\n5\n6\n"
+        );
+    }
+
+    #[test]
+    fn rejects() {
+        let (td, repo) = crate::test::repo_init();
+        let path = td.path().join("f");
+        let lines = (1..=20).map(|i| format!("{}\n", i)).collect::<Vec<_>>();
+        t!(std::fs::write(&path, lines.concat()));
+        t!(t!(repo.index()).add_path(Path::new("f")));
+        let mut patched = lines.clone();
+        patched[1] = "two\n".to_string();
+        patched[17] = "eighteen\n".to_string();
+        let tree = |contents: &str| {
+            let mut builder = t!(repo.treebuilder(None));
+            t!(builder.insert("f", t!(repo.blob(contents.as_bytes())), 0o100644));
+            t!(repo.find_tree(t!(builder.write())))
+        };
+        let base = tree(&lines.concat());
+        let patched = tree(&patched.concat());
+        let diff = t!(repo.diff_tree_to_tree(Some(&base), Some(&patched), None));
+
+        let mut ours = lines.clone();
+        ours[17] = "XVIII\n".to_string();
+        t!(std::fs::write(&path, ours.concat()));
+        let rejects = t!(repo.apply_with_rejects(&diff, ApplyLocation::WorkDir));
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].path(), Path::new("f"));
+        let hunks = rejects[0].hunks();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].index(), 1);
+        assert_eq!(hunks[0].old_start(), 15);
+        let rej = String::from_utf8(rejects[0].to_rej()).unwrap();
+        assert!(rej.starts_with("diff a/f b/f\t(rejected hunks)\n@@ -15,6 +15,6 @@"));
+        assert!(rej.contains("\n-18\n+eighteen\n"));
+
+        ours[1] = "two\n".to_string();
+        assert_eq!(t!(std::fs::read_to_string(&path)), ours.concat());
+
+        // None of the hunks apply any more, so the file is left alone.
+        let rejects = t!(repo.apply_with_rejects(&diff, ApplyLocation::WorkDir));
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].hunks().len(), 2);
+        assert_eq!(t!(std::fs::read_to_string(&path)), ours.concat());
+    }
 }
@@ -0,0 +1,365 @@
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+use crate::cert::Cert;
+use crate::{Cred, CredentialType, Error, FetchOptions, Oid, PushOptions};
+use crate::{RemoteCallbacks, Repository};
+
+type CredentialsCb = dyn FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, Error> + Send;
+type CertificateCheckCb = dyn FnMut(&Cert<'_>, &str) -> bool + Send;
+
+/// A remote whose fetches and pushes run on tokio's blocking thread pool, so
+/// that network operations don't block the tasks of an async service.
+///
+/// Each operation opens its own handle to the repository on a worker thread
+/// and reports progress as a stream of `RemoteEvent`s. Operations must be
+/// started from within a tokio runtime.
+///
+/// Available with the `async` feature.
+#[derive(Clone)]
+pub struct AsyncRemote {
+    path: PathBuf,
+    name: String,
+    credentials: Option<Arc<Mutex<Box<CredentialsCb>>>>,
+    certificate_check: Option<Arc<Mutex<Box<CertificateCheckCb>>>>,
+}
+
+/// A fetch, push or clone running on a worker thread.
+///
+/// The operation is a `Stream` of the progress events reported by libgit2,
+/// which ends once the operation has completed; its result is returned by
+/// `finish`. Dropping the operation cancels the transfer at the next progress
+/// report.
+pub struct RemoteOperation<T> {
+    events: UnboundedReceiver<RemoteEvent>,
+    task: JoinHandle<Result<T, Error>>,
+}
+
+/// Progress reported by a `RemoteOperation`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteEvent {
+    /// Text sent by the remote, such as the `Counting objects` lines shown by
+    /// `git fetch`.
+    Sideband(Vec<u8>),
+    /// Progress of the download and indexing of a packfile.
+    Transfer {
+        /// Number of objects in the packfile.
+        total_objects: usize,
+        /// Number of objects downloaded so far.
+        received_objects: usize,
+        /// Number of downloaded objects which have been hashed.
+        indexed_objects: usize,
+        /// Number of local objects injected to fix a thin pack.
+        local_objects: usize,
+        /// Number of deltas in the packfile.
+        total_deltas: usize,
+        /// Number of deltas which have been resolved.
+        indexed_deltas: usize,
+        /// Size of the packfile downloaded so far.
+        received_bytes: usize,
+    },
+    /// A reference was updated by a fetch.
+    UpdateTip {
+        /// The full name of the reference.
+        refname: String,
+        /// The previous target of the reference, zero if it was created.
+        old: Oid,
+        /// The new target of the reference.
+        new: Oid,
+    },
+    /// Progress of the upload of a packfile by a push.
+    PushTransfer {
+        /// Number of objects sent so far.
+        current: usize,
+        /// Number of objects to send.
+        total: usize,
+        /// Number of bytes sent so far.
+        bytes: usize,
+    },
+    /// The remote reported the result of updating a reference by a push.
+    PushUpdate {
+        /// The full name of the reference on the remote.
+        refname: String,
+        /// The reason the remote rejected the update, or `None` if it was
+        /// accepted.
+        status: Option<String>,
+    },
+}
+
+impl AsyncRemote {
+    /// Creates an async handle to the remote `name` of `repo`.
+    pub fn new(repo: &Repository, name: &str) -> Result<AsyncRemote, Error> {
+        repo.find_remote(name)?;
+        Ok(AsyncRemote {
+            path: repo.path().to_path_buf(),
+            name: name.to_string(),
+            credentials: None,
+            certificate_check: None,
+        })
+    }
+
+    /// Returns the name of the remote.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The callback through which to fetch credentials if required, as for
+    /// `RemoteCallbacks::credentials`.
+    ///
+    /// The callback runs on the worker thread of each operation.
+    pub fn credentials<F>(&mut self, cb: F) -> &mut AsyncRemote
+    where
+        F: FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, Error> + Send + 'static,
+    {
+        self.credentials = Some(Arc::new(Mutex::new(Box::new(cb))));
+        self
+    }
+
+    /// The callback deciding whether to connect despite a certificate which
+    /// could not be verified, as for `RemoteCallbacks::certificate_check`.
+    ///
+    /// The callback runs on the worker thread of each operation.
+    pub fn certificate_check<F>(&mut self, cb: F) -> &mut AsyncRemote
+    where
+        F: FnMut(&Cert<'_>, &str) -> bool + Send + 'static,
+    {
+        self.certificate_check = Some(Arc::new(Mutex::new(Box::new(cb))));
+        self
+    }
+
+    /// Starts fetching `refspecs` from the remote, or its configured refspecs
+    /// if `refspecs` is empty, as with `Remote::fetch`.
+    pub fn fetch(&self, refspecs: &[&str], reflog_msg: Option<&str>) -> RemoteOperation<()> {
+        let refspecs = refspecs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let reflog_msg = reflog_msg.map(|s| s.to_string());
+        let remote = self.clone();
+        RemoteOperation::spawn(move |events| {
+            let repo = Repository::open(&remote.path)?;
+            let mut opts = FetchOptions::new();
+            opts.remote_callbacks(remote.callbacks(events));
+            let mut origin = repo.find_remote(&remote.name)?;
+            origin.fetch(&refspecs, Some(&mut opts), reflog_msg.as_deref())
+        })
+    }
+
+    /// Starts pushing `refspecs` to the remote, or its configured refspecs if
+    /// `refspecs` is empty, as with `Remote::push`.
+    ///
+    /// References rejected by the remote are reported as `PushUpdate` events
+    /// with a status.
+    pub fn push(&self, refspecs: &[&str]) -> RemoteOperation<()> {
+        let refspecs = refspecs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let remote = self.clone();
+        RemoteOperation::spawn(move |events| {
+            let repo = Repository::open(&remote.path)?;
+            let mut opts = PushOptions::new();
+            opts.remote_callbacks(remote.callbacks(events));
+            let mut origin = repo.find_remote(&remote.name)?;
+            origin.push(&refspecs, Some(&mut opts))
+        })
+    }
+
+    fn callbacks(&self, events: &UnboundedSender<RemoteEvent>) -> RemoteCallbacks<'static> {
+        let mut callbacks = forward(events);
+        if let Some(cb) = self.credentials.clone() {
+            callbacks.credentials(move |url, username, allowed| {
+                let mut cb = cb.lock().unwrap_or_else(|e| e.into_inner());
+                (*cb)(url, username, allowed)
+            });
+        }
+        if let Some(cb) = self.certificate_check.clone() {
+            callbacks.certificate_check(move |cert, host| {
+                let mut cb = cb.lock().unwrap_or_else(|e| e.into_inner());
+                (*cb)(cert, host)
+            });
+        }
+        callbacks
+    }
+}
+
+/// Returns callbacks sending the progress of an operation to `events`.
+///
+/// The transfer is cancelled once nothing receives the events anymore.
+pub(crate) fn forward(events: &UnboundedSender<RemoteEvent>) -> RemoteCallbacks<'static> {
+    let mut callbacks = RemoteCallbacks::new();
+    let tx = events.clone();
+    callbacks.sideband_progress(move |data| tx.send(RemoteEvent::Sideband(data.to_vec())).is_ok());
+    let tx = events.clone();
+    callbacks.transfer_progress(move |progress| {
+        tx.send(RemoteEvent::Transfer {
+            total_objects: progress.total_objects(),
+            received_objects: progress.received_objects(),
+            indexed_objects: progress.indexed_objects(),
+            local_objects: progress.local_objects(),
+            total_deltas: progress.total_deltas(),
+            indexed_deltas: progress.indexed_deltas(),
+            received_bytes: progress.received_bytes(),
+        })
+        .is_ok()
+    });
+    let tx = events.clone();
+    callbacks.update_tips(move |refname, old, new| {
+        let refname = refname.to_string();
+        let _ = tx.send(RemoteEvent::UpdateTip { refname, old, new });
+        true
+    });
+    let tx = events.clone();
+    callbacks.push_transfer_progress(move |current, total, bytes| {
+        let _ = tx.send(RemoteEvent::PushTransfer {
+            current,
+            total,
+            bytes,
+        });
+    });
+    let tx = events.clone();
+    callbacks.push_update_reference(move |refname, status| {
+        let _ = tx.send(RemoteEvent::PushUpdate {
+            refname: refname.to_string(),
+            status: status.map(|s| s.to_string()),
+        });
+        Ok(())
+    });
+    callbacks
+}
+
+impl<T: Send + 'static> RemoteOperation<T> {
+    /// Runs `f` on tokio's blocking thread pool, handing it the sender of the
+    /// operation's events.
+    pub(crate) fn spawn<F>(f: F) -> RemoteOperation<T>
+    where
+        F: FnOnce(&UnboundedSender<RemoteEvent>) -> Result<T, Error> + Send + 'static,
+    {
+        let (tx, events) = mpsc::unbounded_channel();
+        let task = tokio::task::spawn_blocking(move || f(&tx));
+        RemoteOperation { events, task }
+    }
+}
+
+impl<T> RemoteOperation<T> {
+    /// Waits for the next progress event, returning `None` once the
+    /// operation has completed.
+    ///
+    /// This is the same as polling the operation as a `Stream`.
+    pub async fn next_event(&mut self) -> Option<RemoteEvent> {
+        self.events.recv().await
+    }
+
+    /// Waits for the operation to complete and returns its result.
+    ///
+    /// Events which haven't been received yet are discarded. If the
+    /// operation panicked, the panic is resumed here.
+    pub async fn finish(self) -> Result<T, Error> {
+        let RemoteOperation { events, task } = self;
+        let result = task.await;
+        drop(events);
+        match result {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(Error::from_str("remote operation was cancelled")),
+        }
+    }
+}
+
+impl<T> Stream for RemoteOperation<T> {
+    type Item = RemoteEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<RemoteEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use tempfile::TempDir;
+
+    use crate::build::RepoBuilder;
+    use crate::{AsyncRemote, BranchType, RemoteEvent, Repository};
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn clone_fetch_push() {
+        let (td, repo) = crate::test::repo_init();
+        let url = crate::test::path2url(td.path());
+        let td2 = TempDir::new().unwrap();
+        let path = td2.path().join("clone");
+
+        let clone = block_on(async {
+            let mut op = RepoBuilder::clone_async(&url, &path, |builder, _| {
+                builder.branch("main");
+            });
+            while op.next_event().await.is_some() {}
+            op.finish().await.unwrap()
+        });
+        let head = repo.head().unwrap().target().unwrap();
+        assert_eq!(clone.head().unwrap().target(), Some(head));
+        assert_eq!(
+            clone.refname_to_id("refs/remotes/origin/main").unwrap(),
+            head
+        );
+        let tree = repo.find_commit(head).unwrap().tree_id();
+        assert_eq!(clone.find_commit(head).unwrap().tree_id(), tree);
+        assert!(clone.find_tree(tree).is_ok());
+
+        let (new, _) = crate::test::commit(&repo);
+        let remote = AsyncRemote::new(&clone, "origin").unwrap();
+        assert_eq!(remote.name(), "origin");
+        let events = block_on(async {
+            let mut op = remote.fetch(&[], None);
+            let mut events = Vec::new();
+            while let Some(event) = op.next_event().await {
+                events.push(event);
+            }
+            op.finish().await.unwrap();
+            events
+        });
+        assert!(events.contains(&RemoteEvent::UpdateTip {
+            refname: "refs/remotes/origin/main".to_string(),
+            old: head,
+            new,
+        }));
+        assert_eq!(
+            clone.refname_to_id("refs/remotes/origin/main").unwrap(),
+            new
+        );
+
+        // libgit2 can only push to local repositories which are bare.
+        let td3 = TempDir::new().unwrap();
+        let bare = Repository::init_bare(td3.path()).unwrap();
+        clone
+            .remote("bare", &crate::test::path2url(td3.path()))
+            .unwrap();
+        let remote = AsyncRemote::new(&clone, "bare").unwrap();
+        let commit = clone.find_commit(head).unwrap();
+        clone.branch("topic", &commit, false).unwrap();
+        let events = block_on(async {
+            let mut op = remote.push(&["refs/heads/topic"]);
+            let mut events = Vec::new();
+            while let Some(event) = op.next_event().await {
+                events.push(event);
+            }
+            op.finish().await.unwrap();
+            events
+        });
+        assert!(events.contains(&RemoteEvent::PushUpdate {
+            refname: "refs/heads/topic".to_string(),
+            status: None,
+        }));
+        let topic = bare.find_branch("topic", BranchType::Local).unwrap();
+        assert_eq!(topic.get().target(), Some(head));
+
+        assert!(AsyncRemote::new(&clone, "missing").is_err());
+    }
+}
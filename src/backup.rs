@@ -0,0 +1,431 @@
+//! Incremental backups of a repository's objects and references.
+//!
+//! A `Snapshot` consists of a pack with the objects which are new since the
+//! previous snapshot, and a `Manifest` of all references at the time it was
+//! taken. Snapshots can be taken while the repository is in use, and
+//! restoring them in order into an empty repository recreates its objects
+//! and references.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use crate::{Buf, Error, ErrorClass, ErrorCode, ObjectType, Oid, ReferenceType, Repository};
+
+/// The target of a reference recorded in a `Manifest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefTarget {
+    /// The reference points at an object.
+    Direct(Oid),
+    /// The reference points at another reference.
+    Symbolic(String),
+}
+
+/// The references of a repository at the time a `Snapshot` was taken.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pack: Option<Oid>,
+    refs: BTreeMap<String, RefTarget>,
+}
+
+/// An incremental backup of a repository: the objects added since a
+/// previous snapshot, as a pack, and a manifest of all references.
+pub struct Snapshot {
+    manifest: Manifest,
+    pack: Vec<u8>,
+}
+
+impl Manifest {
+    /// Record the references of `repo`, including `HEAD`.
+    pub fn from_repo(repo: &Repository) -> Result<Manifest, Error> {
+        let mut refs = BTreeMap::new();
+        let head = repo.find_reference("HEAD")?;
+        for reference in Some(Ok(head)).into_iter().chain(repo.references()?) {
+            let reference = reference?;
+            let name = match reference.name() {
+                Some(name) => name.to_string(),
+                None => return Err(Error::from_str("reference name is not valid utf-8")),
+            };
+            let target = match reference.kind() {
+                Some(ReferenceType::Symbolic) => match reference.symbolic_target() {
+                    Some(target) => RefTarget::Symbolic(target.to_string()),
+                    None => return Err(Error::from_str("reference target is not valid utf-8")),
+                },
+                _ => match reference.target() {
+                    Some(id) => RefTarget::Direct(id),
+                    None => continue,
+                },
+            };
+            refs.insert(name, target);
+        }
+        Ok(Manifest { pack: None, refs })
+    }
+
+    /// Returns the recorded references, sorted by name.
+    pub fn refs(&self) -> impl Iterator<Item = (&str, &RefTarget)> {
+        self.refs.iter().map(|(name, target)| (&name[..], target))
+    }
+
+    /// Returns the recorded target of the reference `name`.
+    pub fn get(&self, name: &str) -> Option<&RefTarget> {
+        self.refs.get(name)
+    }
+
+    /// Returns the checksum of the snapshot's pack, or `None` if the snapshot
+    /// has no new objects.
+    pub fn pack(&self) -> Option<Oid> {
+        self.pack
+    }
+
+    /// Serialize this manifest.
+    ///
+    /// The format is a line per reference, `<id> <name>` for direct and
+    /// `symref <target> <name>` for symbolic references, preceded by a
+    /// `pack <checksum>` line if the snapshot has a pack.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+        if let Some(pack) = self.pack {
+            out.push_str(&format!("pack {}\n", pack));
+        }
+        for (name, target) in self.refs.iter() {
+            match *target {
+                RefTarget::Direct(id) => out.push_str(&format!("{} {}\n", id, name)),
+                RefTarget::Symbolic(ref target) => {
+                    out.push_str(&format!("symref {} {}\n", target, name))
+                }
+            }
+        }
+        out.into_bytes()
+    }
+
+    /// Deserialize a manifest written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Manifest, Error> {
+        let text =
+            str::from_utf8(bytes).map_err(|_| Error::from_str("manifest is not valid utf-8"))?;
+        let mut manifest = Manifest::default();
+        for line in text.lines() {
+            let mut parts = line.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("pack"), Some(id), None) => manifest.pack = Some(Oid::from_str(id)?),
+                (Some("symref"), Some(target), Some(name)) => {
+                    manifest
+                        .refs
+                        .insert(name.to_string(), RefTarget::Symbolic(target.to_string()));
+                }
+                (Some(id), Some(name), None) => {
+                    manifest
+                        .refs
+                        .insert(name.to_string(), RefTarget::Direct(Oid::from_str(id)?));
+                }
+                _ => {
+                    return Err(Error::from_str(&format!(
+                        "invalid manifest line '{}'",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn direct_targets(&self) -> impl Iterator<Item = Oid> + '_ {
+        self.refs.values().filter_map(|target| match *target {
+            RefTarget::Direct(id) => Some(id),
+            RefTarget::Symbolic(..) => None,
+        })
+    }
+}
+
+impl Snapshot {
+    /// Take a snapshot of `repo`.
+    ///
+    /// If `previous` is the manifest of an earlier snapshot, only objects
+    /// which are not reachable from the references it records are included,
+    /// so restoring the snapshot requires the earlier ones to be restored
+    /// first. Otherwise all reachable objects are included.
+    pub fn create(repo: &Repository, previous: Option<&Manifest>) -> Result<Snapshot, Error> {
+        let mut manifest = Manifest::from_repo(repo)?;
+        let known = previous
+            .map(|m| m.direct_targets().collect::<HashSet<_>>())
+            .unwrap_or_default();
+
+        let mut builder = repo.packbuilder()?;
+        let mut walk = repo.revwalk()?;
+        let mut pushed = false;
+        for id in manifest.direct_targets() {
+            if known.contains(&id) {
+                continue;
+            }
+            let mut object = repo.find_object(id, None)?;
+            while let Some(tag) = object.as_tag() {
+                builder.insert_object(tag.id(), None)?;
+                object = tag.target()?;
+            }
+            match object.kind() {
+                Some(ObjectType::Commit) => {
+                    walk.push(object.id())?;
+                    pushed = true;
+                }
+                _ => builder.insert_recursive(object.id(), None)?,
+            }
+        }
+        for id in known {
+            let commit = repo
+                .find_object(id, None)
+                .and_then(|object| object.peel_to_commit());
+            if let Ok(commit) = commit {
+                walk.hide(commit.id())?;
+            }
+        }
+        if pushed {
+            builder.insert_walk(&mut walk)?;
+        }
+
+        let mut pack = Vec::new();
+        if builder.object_count() > 0 {
+            let mut buf = Buf::new();
+            builder.write_buf(&mut buf)?;
+            pack = buf.to_vec();
+            manifest.pack = Some(pack_checksum(&pack)?);
+        }
+        Ok(Snapshot { manifest, pack })
+    }
+
+    /// Returns the manifest of this snapshot, to be passed to `create` when
+    /// taking the next one.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Returns the pack of new objects, which is empty if there are none.
+    pub fn pack(&self) -> &[u8] {
+        &self.pack
+    }
+
+    /// Returns the number of objects in the pack.
+    pub fn object_count(&self) -> usize {
+        if self.pack.len() < 12 {
+            return 0;
+        }
+        let mut count = [0; 4];
+        count.copy_from_slice(&self.pack[8..12]);
+        u32::from_be_bytes(count) as usize
+    }
+
+    /// Write this snapshot to `<dir>/<name>.refs` and, if it has a pack,
+    /// `<dir>/<name>.pack`.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<(), Error> {
+        let (refs, pack) = snapshot_paths(dir, name);
+        if !self.pack.is_empty() {
+            write_file(&pack, &self.pack)?;
+        }
+        write_file(&refs, &self.manifest.to_bytes())
+    }
+
+    /// Read a snapshot written by `save`.
+    ///
+    /// Returns an error if the pack is missing or does not match the
+    /// checksum recorded in the manifest.
+    pub fn load(dir: &Path, name: &str) -> Result<Snapshot, Error> {
+        let (refs, pack) = snapshot_paths(dir, name);
+        let manifest = Manifest::from_bytes(&read_file(&refs)?)?;
+        let pack = match manifest.pack {
+            Some(_) => read_file(&pack)?,
+            None => Vec::new(),
+        };
+        let snapshot = Snapshot { manifest, pack };
+        snapshot.verify_pack()?;
+        Ok(snapshot)
+    }
+
+    /// Restore this snapshot into `repo`.
+    ///
+    /// The objects of the pack are added to the repository, and its
+    /// references are updated to match the manifest: references it records
+    /// are created or overwritten, and other references are deleted.
+    ///
+    /// Before any reference is touched, the pack's checksum is verified while
+    /// indexing it, and the history of every recorded reference is walked to
+    /// make sure that its commits and their root trees are present, stopping
+    /// at commits the repository's references already pointed to. An error
+    /// is returned if anything is missing, for example because an earlier
+    /// snapshot was not restored first.
+    pub fn restore(&self, repo: &Repository) -> Result<(), Error> {
+        self.verify_pack()?;
+        let before = Manifest::from_repo(repo).unwrap_or_default();
+        let odb = repo.odb()?;
+        if !self.pack.is_empty() {
+            let mut writer = odb.packwriter()?;
+            writer
+                .write_all(&self.pack)
+                .map_err(|e| Error::from_str(&format!("failed to write pack: {}", e)))?;
+            writer.commit()?;
+        }
+
+        let mut walk = repo.revwalk()?;
+        for id in self.manifest.direct_targets() {
+            if !odb.exists(id) {
+                return Err(missing(id));
+            }
+            if let Ok(commit) = repo.find_object(id, None)?.peel_to_commit() {
+                walk.push(commit.id())?;
+            }
+        }
+        for id in before.direct_targets() {
+            let commit = repo
+                .find_object(id, None)
+                .and_then(|object| object.peel_to_commit());
+            if let Ok(commit) = commit {
+                walk.hide(commit.id())?;
+            }
+        }
+        for id in walk {
+            let commit = repo.find_commit(id?)?;
+            if !odb.exists(commit.tree_id()) {
+                return Err(missing(commit.tree_id()));
+            }
+        }
+
+        let message = "backup: restore snapshot";
+        for (name, _) in before.refs() {
+            if name.starts_with("refs/") && self.manifest.get(name).is_none() {
+                repo.find_reference(name)?.delete()?;
+            }
+        }
+        for (name, target) in self.manifest.refs() {
+            if let RefTarget::Direct(id) = *target {
+                repo.reference(name, id, true, message)?;
+            }
+        }
+        for (name, target) in self.manifest.refs() {
+            if let RefTarget::Symbolic(ref target) = *target {
+                repo.reference_symbolic(name, target, true, message)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_pack(&self) -> Result<(), Error> {
+        let actual = if self.pack.is_empty() {
+            None
+        } else {
+            Some(pack_checksum(&self.pack)?)
+        };
+        if actual != self.manifest.pack {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Odb,
+                "snapshot pack does not match its manifest",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn missing(id: Oid) -> Error {
+    Error::new(
+        ErrorCode::NotFound,
+        ErrorClass::Odb,
+        format!("snapshot is incomplete: object {} is missing", id),
+    )
+}
+
+/// The checksum in the trailer of a pack, which is also its name.
+fn pack_checksum(pack: &[u8]) -> Result<Oid, Error> {
+    if pack.len() < 32 || &pack[..4] != b"PACK" {
+        return Err(Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Odb,
+            "snapshot pack is truncated or corrupt",
+        ));
+    }
+    Oid::from_bytes(&pack[pack.len() - 20..])
+}
+
+fn snapshot_paths(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    (
+        dir.join(format!("{}.refs", name)),
+        dir.join(format!("{}.pack", name)),
+    )
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    fs::write(path, data).map_err(|e| {
+        Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Os,
+            format!("failed to write '{}': {}", path.display(), e),
+        )
+    })
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|e| {
+        Error::new(
+            ErrorCode::NotFound,
+            ErrorClass::Os,
+            format!("failed to read '{}': {}", path.display(), e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Manifest, RefTarget, Snapshot};
+    use crate::Repository;
+    use tempfile::TempDir;
+
+    #[test]
+    fn incremental() {
+        let (_td, repo) = crate::test::repo_init();
+        let first = Snapshot::create(&repo, None).unwrap();
+        assert_eq!(first.object_count(), 2);
+        let (commit, _) = crate::test::commit(&repo);
+        let head = repo.head().unwrap().target().unwrap();
+        assert_eq!(head, commit);
+        let second = Snapshot::create(&repo, Some(first.manifest())).unwrap();
+        // The new commit, its tree and the blob of "foo".
+        assert_eq!(second.object_count(), 3);
+        let third = Snapshot::create(&repo, Some(second.manifest())).unwrap();
+        assert_eq!(third.object_count(), 0);
+        assert!(third.pack().is_empty());
+
+        let branch = repo.head().unwrap().name().unwrap().to_string();
+        let manifest = second.manifest();
+        assert_eq!(manifest.get(&branch), Some(&RefTarget::Direct(commit)));
+        assert_eq!(
+            manifest.get("HEAD"),
+            Some(&RefTarget::Symbolic(branch.clone()))
+        );
+        assert_eq!(
+            &Manifest::from_bytes(&manifest.to_bytes()).unwrap(),
+            manifest
+        );
+
+        let dir = TempDir::new().unwrap();
+        first.save(dir.path(), "1").unwrap();
+        second.save(dir.path(), "2").unwrap();
+        third.save(dir.path(), "3").unwrap();
+
+        // Restoring out of order is detected before touching any reference.
+        let td = TempDir::new().unwrap();
+        let restored = Repository::init_bare(td.path()).unwrap();
+        let second = Snapshot::load(dir.path(), "2").unwrap();
+        assert!(second.restore(&restored).is_err());
+        assert!(restored.find_reference(&branch).is_err());
+
+        for name in &["1", "2", "3"] {
+            let snapshot = Snapshot::load(dir.path(), name).unwrap();
+            snapshot.restore(&restored).unwrap();
+        }
+        assert_eq!(restored.refname_to_id(&branch).unwrap(), commit);
+        assert_eq!(restored.head().unwrap().target(), Some(commit));
+        restored.find_commit(commit).unwrap().tree().unwrap();
+
+        // A pack which does not match its manifest is rejected.
+        std::fs::write(dir.path().join("2.pack"), first.pack()).unwrap();
+        assert!(Snapshot::load(dir.path(), "2").is_err());
+    }
+}
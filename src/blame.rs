@@ -1,5 +1,6 @@
 use crate::util::{self, Binding};
 use crate::{raw, signature, Oid, Repository, Signature};
+use std::collections::HashMap;
 use std::marker;
 use std::mem;
 use std::ops::Range;
@@ -22,6 +23,17 @@ pub struct BlameOptions {
     raw: raw::git_blame_options,
 }
 
+/// Lines owned per commit and per author across a set of files.
+///
+/// Created by [`Repository::blame_summary`](crate::Repository::blame_summary).
+#[derive(Debug, Default, Clone)]
+pub struct BlameSummary {
+    files: usize,
+    lines: usize,
+    by_commit: HashMap<Oid, usize>,
+    by_author: HashMap<String, usize>,
+}
+
 /// An iterator over the hunks in a blame.
 pub struct BlameIter<'blame> {
     range: Range<usize>,
@@ -144,6 +156,53 @@ impl<'blame> BlameHunk<'blame> {
     }
 }
 
+impl BlameSummary {
+    /// Number of files which were blamed.
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Total number of lines which were blamed.
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Number of lines last changed by each commit.
+    pub fn lines_by_commit(&self) -> &HashMap<Oid, usize> {
+        &self.by_commit
+    }
+
+    /// Number of lines last changed by each author, keyed by email.
+    ///
+    /// The mailmap is applied when the `use_mailmap` blame option is set.
+    pub fn lines_by_author(&self) -> &HashMap<String, usize> {
+        &self.by_author
+    }
+
+    pub(crate) fn add_blame(&mut self, blame: &Blame<'_>) {
+        self.files += 1;
+        for hunk in blame.iter() {
+            let lines = hunk.lines_in_hunk();
+            let signature = hunk.final_signature();
+            let email = String::from_utf8_lossy(signature.email_bytes());
+            self.lines += lines;
+            *self.by_commit.entry(hunk.final_commit_id()).or_insert(0) += lines;
+            *self.by_author.entry(email.into_owned()).or_insert(0) += lines;
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: BlameSummary) {
+        self.files += other.files;
+        self.lines += other.lines;
+        for (id, lines) in other.by_commit {
+            *self.by_commit.entry(id).or_insert(0) += lines;
+        }
+        for (email, lines) in other.by_author {
+            *self.by_author.entry(email).or_insert(0) += lines;
+        }
+    }
+}
+
 impl Default for BlameOptions {
     fn default() -> Self {
         Self::new()
@@ -164,6 +223,11 @@ impl BlameOptions {
         }
     }
 
+    /// Creates a copy of these options which can be sent to another thread.
+    pub(crate) fn duplicate(&self) -> BlameOptions {
+        BlameOptions { raw: self.raw }
+    }
+
     fn flag(&mut self, opt: u32, val: bool) -> &mut BlameOptions {
         if val {
             self.raw.flags |= opt;
@@ -311,6 +375,7 @@ impl<'blame> ExactSizeIterator for BlameIter<'blame> {}
 
 #[cfg(test)]
 mod tests {
+    use crate::{Repository, Signature};
     use std::fs::{self, File};
     use std::path::Path;
 
@@ -347,4 +412,42 @@ mod tests {
         assert_eq!(hunk.lines_in_hunk(), 0);
         assert!(!hunk.is_boundary())
     }
+
+    fn commit_file(repo: &Repository, path: &str, data: &str, email: &str) {
+        let root = repo.workdir().unwrap();
+        fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+        fs::write(root.join(path), data).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(path)).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("name", email).unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &[&parent])
+            .unwrap();
+    }
+
+    #[test]
+    fn summary() {
+        let (_td, repo) = crate::test::repo_init();
+        commit_file(&repo, "foo/bar", "a\nb\n", "one@example.com");
+        commit_file(&repo, "baz", "c\n", "one@example.com");
+        commit_file(&repo, "baz", "c\nd\n", "two@example.com");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+
+        for threads in 1..3 {
+            let summary = repo
+                .blame_summary(&head, &[] as &[&str], None, threads)
+                .unwrap();
+            assert_eq!(summary.files(), 2);
+            assert_eq!(summary.lines(), 4);
+            assert_eq!(summary.lines_by_commit().len(), 3);
+            assert_eq!(summary.lines_by_commit()[&head.id()], 1);
+            assert_eq!(summary.lines_by_author()["one@example.com"], 3);
+            assert_eq!(summary.lines_by_author()["two@example.com"], 1);
+        }
+
+        let summary = repo.blame_summary(&head, &["foo"], None, 2).unwrap();
+        assert_eq!(summary.files(), 1);
+        assert_eq!(summary.lines(), 2);
+    }
 }
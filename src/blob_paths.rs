@@ -0,0 +1,111 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::{Error, Oid, Tree};
+
+/// An index from blobs to the paths where they appear in trees, for looking
+/// up many blobs in the same trees.
+///
+/// Each tree is traversed once, the first time a blob is looked up in it;
+/// later lookups in that tree are answered from the index. The index holds
+/// every path of the trees it has seen, so it should be dropped or cleared
+/// once those trees are no longer of interest.
+#[derive(Debug, Clone, Default)]
+pub struct BlobPathCache {
+    trees: HashMap<Oid, HashMap<Oid, Vec<PathBuf>>>,
+}
+
+impl BlobPathCache {
+    /// Creates an empty cache.
+    pub fn new() -> BlobPathCache {
+        BlobPathCache::default()
+    }
+
+    /// Returns the paths of the blobs with id `id` in `tree` and its
+    /// subtrees, in the order `git ls-tree -r` lists them.
+    pub fn paths_of(&mut self, tree: &Tree<'_>, id: Oid) -> Result<&[PathBuf], Error> {
+        let blobs = match self.trees.entry(tree.id()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let mut blobs = HashMap::<Oid, Vec<PathBuf>>::new();
+                tree.for_each_blob(&mut |blob, path| {
+                    blobs.entry(blob).or_default().push(path.to_path_buf());
+                })?;
+                e.insert(blobs)
+            }
+        };
+        Ok(blobs.get(&id).map(|p| &p[..]).unwrap_or(&[]))
+    }
+
+    /// Returns the number of trees indexed.
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    /// Returns `true` if no tree has been indexed.
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Drops the index of every tree.
+    pub fn clear(&mut self) {
+        self.trees.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::{BlobPathCache, Oid};
+
+    #[test]
+    fn paths_of() {
+        let (td, repo) = crate::test::repo_init();
+        fs::create_dir_all(td.path().join("a/b")).unwrap();
+        fs::write(td.path().join("secret"), "token").unwrap();
+        fs::write(td.path().join("a/b/copy"), "token").unwrap();
+        fs::write(td.path().join("a/other"), "other").unwrap();
+        let mut index = repo.index().unwrap();
+        for path in &["secret", "a/b/copy", "a/other"] {
+            index.add_path(Path::new(path)).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "add", &tree, &[&head])
+            .unwrap();
+        let token = Oid::hash_object(crate::ObjectType::Blob, b"token").unwrap();
+        let expected = [PathBuf::from("a/b/copy"), PathBuf::from("secret")];
+
+        assert_eq!(tree.paths_of(token).unwrap(), expected);
+        assert!(tree.paths_of(Oid::zero()).unwrap().is_empty());
+
+        let mut cache = BlobPathCache::new();
+        assert!(cache.is_empty());
+        assert_eq!(cache.paths_of(&tree, token).unwrap(), expected);
+        let other = Oid::hash_object(crate::ObjectType::Blob, b"other").unwrap();
+        assert_eq!(
+            cache.paths_of(&tree, other).unwrap(),
+            [PathBuf::from("a/other")]
+        );
+        assert_eq!(cache.len(), 1);
+
+        let paths = repo
+            .find_paths_containing_blob("HEAD", token, None)
+            .unwrap();
+        assert_eq!(paths, expected);
+        let paths = repo
+            .find_paths_containing_blob("HEAD~", token, Some(&mut cache))
+            .unwrap();
+        assert!(paths.is_empty());
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!(repo
+            .find_paths_containing_blob("missing", token, None)
+            .is_err());
+    }
+}
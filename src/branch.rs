@@ -1,10 +1,11 @@
 use std::ffi::CString;
+use std::fmt;
 use std::marker;
 use std::ptr;
 use std::str;
 
 use crate::util::Binding;
-use crate::{raw, BranchType, Error, Reference, References};
+use crate::{raw, BranchType, Error, ErrorClass, ErrorCode, Reference, References};
 
 /// A structure to represent a git [branch][1]
 ///
@@ -16,6 +17,16 @@ pub struct Branch<'repo> {
     inner: Reference<'repo>,
 }
 
+/// The validated name of a local branch, such as `main` for
+/// `refs/heads/main`.
+///
+/// Returned by `Remote::default_branch_name` and
+/// `Repository::guess_default_branch`. It displays as the short name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BranchName {
+    refname: String,
+}
+
 /// An iterator over the branches inside of a repository.
 pub struct Branches<'repo> {
     raw: *mut raw::git_branch_iterator,
@@ -149,9 +160,49 @@ impl<'repo> Drop for Branches<'repo> {
     }
 }
 
+impl BranchName {
+    /// Validates the name of a local branch, given either as a short name
+    /// such as `main` or as a full reference name such as `refs/heads/main`.
+    pub fn new(name: &str) -> Result<BranchName, Error> {
+        let refname = if name.starts_with("refs/heads/") {
+            name.to_string()
+        } else {
+            format!("refs/heads/{}", name)
+        };
+        if refname.len() == "refs/heads/".len()
+            || refname == "refs/heads/HEAD"
+            || !Reference::is_valid_name(&refname)
+        {
+            return Err(Error::new(
+                ErrorCode::InvalidSpec,
+                ErrorClass::Reference,
+                format!("'{}' is not a valid branch name", name),
+            ));
+        }
+        Ok(BranchName { refname })
+    }
+
+    /// Returns the short name of the branch, such as `main`.
+    pub fn name(&self) -> &str {
+        &self.refname["refs/heads/".len()..]
+    }
+
+    /// Returns the full reference name of the branch, such as
+    /// `refs/heads/main`.
+    pub fn refname(&self) -> &str {
+        &self.refname
+    }
+}
+
+impl fmt::Display for BranchName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::BranchType;
+    use crate::{BranchName, BranchType};
 
     #[test]
     fn smoke() {
@@ -175,4 +226,21 @@ mod tests {
 
         b1.delete().unwrap();
     }
+
+    #[test]
+    fn branch_name() {
+        let name = BranchName::new("main").unwrap();
+        assert_eq!(name.name(), "main");
+        assert_eq!(name.refname(), "refs/heads/main");
+        assert_eq!(name.to_string(), "main");
+        assert_eq!(BranchName::new("refs/heads/main").unwrap(), name);
+        assert_eq!(
+            BranchName::new("feature/x").unwrap().refname(),
+            "refs/heads/feature/x"
+        );
+        assert!(BranchName::new("").is_err());
+        assert!(BranchName::new("HEAD").is_err());
+        assert!(BranchName::new("a..b").is_err());
+        assert!(BranchName::new("refs/heads/").is_err());
+    }
 }
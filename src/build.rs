@@ -1,14 +1,19 @@
 //! Builder-pattern objects for configuration various git operations.
 
 use libc::{c_char, c_int, c_uint, c_void, size_t};
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
-use std::mem;
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::mem::{self, ManuallyDrop};
+use std::path::{Path, PathBuf};
 use std::ptr;
 
 use crate::util::{self, Binding};
 use crate::{panic, raw, Error, FetchOptions, IntoCString, Oid, Repository, Tree};
-use crate::{CheckoutNotificationType, DiffFile, FileMode, Remote};
+use crate::{CheckoutNotificationType, ConflictStyle, DiffFile, FileMode, Remote};
+#[cfg(feature = "async")]
+use crate::{RemoteCallbacks, RemoteOperation};
 
 /// A builder struct which is used to build configuration for cloning a new git
 /// repository.
@@ -56,6 +61,7 @@ pub struct RepoBuilder<'cb> {
     fetch_opts: Option<FetchOptions<'cb>>,
     clone_local: Option<CloneLocal>,
     remote_create: Option<Box<RemoteCreate<'cb>>>,
+    reference: Option<(PathBuf, bool)>,
 }
 
 /// Type of callback passed to `RepoBuilder::remote_create`.
@@ -85,6 +91,10 @@ pub struct CheckoutBuilder<'cb> {
     progress: Option<Box<Progress<'cb>>>,
     notify: Option<Box<Notify<'cb>>>,
     notify_flags: CheckoutNotificationType,
+    file_change: Option<Box<FileChange<'cb>>>,
+    pending_changes: Vec<(PathBuf, Option<Oid>, Option<Oid>)>,
+    progress_paths: Vec<PathBuf>,
+    repo: *mut raw::git_repository,
 }
 
 /// Checkout progress notification callback.
@@ -109,6 +119,13 @@ pub type Notify<'a> = dyn FnMut(
     ) -> bool
     + 'a;
 
+/// Checkout file change callback.
+///
+/// The first argument is the path of the file which was changed, followed by
+/// the id of its blob before the change and the id of its blob after the
+/// change. The ids are `None` for files which were created or removed.
+pub type FileChange<'a> = dyn FnMut(&Path, Option<Oid>, Option<Oid>) + 'a;
+
 impl<'cb> Default for RepoBuilder<'cb> {
     fn default() -> Self {
         Self::new()
@@ -153,6 +170,7 @@ impl<'cb> RepoBuilder<'cb> {
             checkout: None,
             fetch_opts: None,
             remote_create: None,
+            reference: None,
         }
     }
 
@@ -228,6 +246,55 @@ impl<'cb> RepoBuilder<'cb> {
         self
     }
 
+    /// Borrow objects from the local repository at `path` instead of storing
+    /// them in the new repository, like `git clone --reference`.
+    ///
+    /// The reference repository is added to the new repository's
+    /// `objects/info/alternates`, and once the clone is done, objects which
+    /// are reachable from the reference repository's references are dropped
+    /// from the new repository. The reference repository must then be kept
+    /// around, and must not lose those objects.
+    ///
+    /// With `dissociate`, like `git clone --reference --dissociate`, the new
+    /// repository is instead repacked so that it contains all of its objects
+    /// and the alternate is removed again once the clone is done.
+    ///
+    /// The whole history is still transferred during the clone itself, so
+    /// this saves disk space rather than bandwidth.
+    pub fn reference(&mut self, path: &Path, dissociate: bool) -> &mut RepoBuilder<'cb> {
+        self.reference = Some((path.to_path_buf(), dissociate));
+        self
+    }
+
+    /// Clone a remote repository on tokio's blocking thread pool.
+    ///
+    /// Builders hold callbacks which can't be sent to another thread, so the
+    /// builder is created on the worker thread and handed to `configure`,
+    /// along with the remote callbacks used for the clone. The callbacks
+    /// already forward the progress of the clone to the returned
+    /// `RemoteOperation`; fetch options set on the builder by `configure` are
+    /// replaced.
+    ///
+    /// Must be called from within a tokio runtime. Available with the `async`
+    /// feature.
+    #[cfg(feature = "async")]
+    pub fn clone_async<F>(url: &str, into: &Path, configure: F) -> RemoteOperation<Repository>
+    where
+        F: FnOnce(&mut RepoBuilder<'_>, &mut RemoteCallbacks<'_>) + Send + 'static,
+    {
+        let url = url.to_string();
+        let into = into.to_path_buf();
+        RemoteOperation::spawn(move |events| {
+            let mut builder = RepoBuilder::new();
+            let mut callbacks = crate::async_remote::forward(events);
+            configure(&mut builder, &mut callbacks);
+            let mut opts = FetchOptions::new();
+            opts.remote_callbacks(callbacks);
+            builder.fetch_options(opts);
+            builder.clone(&url, &into)
+        })
+    }
+
     /// Clone a remote repository.
     ///
     /// This will use the options configured so far to clone the specified url
@@ -272,17 +339,126 @@ impl<'cb> RepoBuilder<'cb> {
             opts.remote_cb_payload = callback as *mut _ as *mut _;
         }
 
+        let mut alternate = match self.reference {
+            Some((ref path, _)) => {
+                Some(Repository::open(path)?.item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?)
+            }
+            None => None,
+        };
+        if let Some(ref mut alternate) = alternate {
+            opts.repository_cb = Some(repository_create_cb);
+            opts.repository_cb_payload = alternate as *mut PathBuf as *mut _;
+        }
+
         let url = CString::new(url)?;
         // Normal file path OK (does not need Windows conversion).
         let into = into.into_c_string()?;
         let mut raw = ptr::null_mut();
-        unsafe {
+        let repo: Repository = unsafe {
             try_call!(raw::git_clone(&mut raw, url, into, &opts));
-            Ok(Binding::from_raw(raw))
+            Binding::from_raw(raw)
+        };
+        match self.reference {
+            Some((ref reference, dissociate)) => {
+                let path = repo.path().to_path_buf();
+                // Drop the repository so that its packs are closed before
+                // they are replaced.
+                drop(repo);
+                repack_against_reference(&path, reference, dissociate)?;
+                Repository::open(&path)
+            }
+            None => Ok(repo),
         }
     }
 }
 
+extern "C" fn repository_create_cb(
+    out: *mut *mut raw::git_repository,
+    path: *const c_char,
+    bare: c_int,
+    payload: *mut c_void,
+) -> c_int {
+    let res = panic::wrap(|| unsafe {
+        let path = util::bytes2path(CStr::from_ptr(path).to_bytes());
+        let alternate = &*(payload as *const PathBuf);
+        let res = if bare != 0 {
+            Repository::init_bare(path)
+        } else {
+            Repository::init(path)
+        };
+        let repo = match res {
+            Ok(repo) => repo,
+            Err(e) => return e.raw_code(),
+        };
+        let info = repo.path().join("objects/info");
+        let mut contents = match alternate.as_path().into_c_string() {
+            Ok(s) => s.into_bytes(),
+            Err(e) => return e.raw_code(),
+        };
+        contents.push(b'\n');
+        let written =
+            fs::create_dir_all(&info).and_then(|()| fs::write(info.join("alternates"), contents));
+        if written.is_err() {
+            return raw::GIT_ERROR;
+        }
+        *out = repo.raw();
+        mem::forget(repo);
+        0
+    });
+    res.unwrap_or(-1)
+}
+
+/// Replaces the packs of a repository cloned with `RepoBuilder::reference`
+/// with a single pack containing either all of its objects or only those the
+/// reference repository does not have.
+fn repack_against_reference(path: &Path, reference: &Path, dissociate: bool) -> Result<(), Error> {
+    let repo = Repository::open(path)?;
+    let pack_dir = repo
+        .item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?
+        .join("pack");
+    let io_err = |e: io::Error| Error::from_str(&format!("{}: {}", pack_dir.display(), e));
+    let old_packs = fs::read_dir(&pack_dir)
+        .map_err(io_err)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with("pack-")
+        })
+        .collect::<Vec<_>>();
+
+    let hide = if dissociate {
+        None
+    } else {
+        Some(Repository::open(reference)?)
+    };
+    // This checks that the new pack is complete, so the old ones can go.
+    let keep = repo
+        .pack_reachable(hide.as_ref())?
+        .map(|hash| format!("pack-{}.", hash));
+    drop(repo);
+
+    for pack in old_packs {
+        let name = pack.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if keep.as_ref().map(|k| name.starts_with(&**k)) == Some(true) {
+            continue;
+        }
+        // Packs are read-only, which prevents removing them on Windows.
+        if let Ok(meta) = fs::metadata(&pack) {
+            let mut perms = meta.permissions();
+            perms.set_readonly(false);
+            let _ = fs::set_permissions(&pack, perms);
+        }
+        fs::remove_file(&pack).map_err(io_err)?;
+    }
+    if dissociate {
+        let alternates = path.join("objects/info/alternates");
+        fs::remove_file(&alternates)
+            .map_err(|e| Error::from_str(&format!("{}: {}", alternates.display(), e)))?;
+    }
+    Ok(())
+}
+
 extern "C" fn remote_create_cb(
     out: *mut *mut raw::git_remote,
     repo: *mut raw::git_repository,
@@ -334,6 +510,10 @@ impl<'cb> CheckoutBuilder<'cb> {
             progress: None,
             notify: None,
             notify_flags: CheckoutNotificationType::empty(),
+            file_change: None,
+            pending_changes: Vec::new(),
+            progress_paths: Vec::new(),
+            repo: ptr::null_mut(),
         }
     }
 
@@ -483,6 +663,14 @@ impl<'cb> CheckoutBuilder<'cb> {
         self.flag(raw::GIT_CHECKOUT_CONFLICT_STYLE_DIFF3, on)
     }
 
+    /// Write conflicts in the given style, such as the one configured by
+    /// `merge.conflictStyle` as returned by `ConflictStyle::from_config`.
+    pub fn conflict_style(&mut self, style: ConflictStyle) -> &mut CheckoutBuilder<'cb> {
+        let diff3 = style == ConflictStyle::Diff3;
+        self.conflict_style_merge(!diff3);
+        self.conflict_style_diff3(diff3)
+    }
+
     /// Indicate whether to apply filters like CRLF conversion.
     pub fn disable_filters(&mut self, disable: bool) -> &mut CheckoutBuilder<'cb> {
         self.disable_filters = disable;
@@ -569,6 +757,29 @@ impl<'cb> CheckoutBuilder<'cb> {
         self
     }
 
+    /// Set a callback to receive every change the checkout made to a file.
+    ///
+    /// This lets external indexes of the working directory, such as code
+    /// search or language server caches, be updated incrementally. Since
+    /// merges, resets, reverts and cherry-picks check out their results with
+    /// a `CheckoutBuilder`, their changes are reported as well.
+    ///
+    /// Unlike `notify`, the callback is invoked once all files have been
+    /// written. Dry runs and checkouts which fail report nothing.
+    ///
+    /// libgit2 does not send notifications for removed files, so their old id
+    /// is looked up in the tree of `HEAD`. This is only possible when the
+    /// checkout is run by `Repository::checkout_*`, `reset`, `merge`,
+    /// `cherrypick` or `revert`; otherwise removed files are reported with no
+    /// old id.
+    pub fn file_changes<F>(&mut self, cb: F) -> &mut CheckoutBuilder<'cb>
+    where
+        F: FnMut(&Path, Option<Oid>, Option<Oid>) + 'cb,
+    {
+        self.file_change = Some(Box::new(cb) as Box<FileChange<'cb>>);
+        self
+    }
+
     /// Configure a raw checkout options based on this configuration.
     ///
     /// This method is unsafe as there is no guarantee that this structure will
@@ -596,17 +807,78 @@ impl<'cb> CheckoutBuilder<'cb> {
         if let Some(ref c) = self.their_label {
             opts.their_label = c.as_ptr();
         }
-        if self.progress.is_some() {
+        self.pending_changes.clear();
+        self.progress_paths.clear();
+        self.repo = ptr::null_mut();
+        if self.progress.is_some() || self.file_change.is_some() {
             opts.progress_cb = Some(progress_cb);
             opts.progress_payload = self as *mut _ as *mut _;
         }
-        if self.notify.is_some() {
+        if self.notify.is_some() || self.file_change.is_some() {
+            let mut flags = self.notify_flags;
+            if self.file_change.is_some() {
+                flags |= CheckoutNotificationType::UPDATED;
+            }
             opts.notify_cb = Some(notify_cb);
             opts.notify_payload = self as *mut _ as *mut _;
-            opts.notify_flags = self.notify_flags.bits() as c_uint;
+            opts.notify_flags = flags.bits() as c_uint;
         }
         opts.checkout_strategy = self.checkout_opts as c_uint;
     }
+
+    /// Set the repository the checkout runs in, used to look up the old id
+    /// of removed files. Must be called after `configure`.
+    pub(crate) fn set_repository(&mut self, repo: &Repository) {
+        self.repo = repo.raw();
+    }
+
+    /// Report every file the checkout touched to the `file_changes` callback.
+    ///
+    /// Files which were updated have been collected from notifications, every
+    /// other path reported as progress was removed.
+    unsafe fn flush_file_changes(&mut self) {
+        let callback = match self.file_change {
+            Some(ref mut c) => c,
+            None => return,
+        };
+        let updated = self
+            .pending_changes
+            .iter()
+            .map(|(path, _, _)| path.clone())
+            .collect::<HashSet<_>>();
+        for (path, old, new) in self.pending_changes.drain(..) {
+            callback(&path, old, new);
+        }
+
+        let mut removed = self
+            .progress_paths
+            .drain(..)
+            .filter(|path| !updated.contains(path))
+            .collect::<Vec<_>>();
+        if removed.is_empty() {
+            return;
+        }
+        removed.sort();
+        removed.dedup();
+        // The repository is borrowed from the caller and must not be freed.
+        let repo = if self.repo.is_null() {
+            None
+        } else {
+            Some(ManuallyDrop::new(Repository::from_raw(self.repo)))
+        };
+        // HEAD is only moved once the checkout has completed, so its tree is
+        // still the baseline of the checkout.
+        let head = repo
+            .as_ref()
+            .and_then(|repo| repo.head().and_then(|h| h.peel_to_tree()).ok());
+        for path in removed {
+            let old = head
+                .as_ref()
+                .and_then(|tree| tree.get_path(&path).ok())
+                .map(|entry| entry.id());
+            callback(&path, old, None);
+        }
+    }
 }
 
 extern "C" fn progress_cb(
@@ -617,16 +889,23 @@ extern "C" fn progress_cb(
 ) {
     panic::wrap(|| unsafe {
         let payload = &mut *(data as *mut CheckoutBuilder<'_>);
-        let callback = match payload.progress {
-            Some(ref mut c) => c,
-            None => return,
-        };
-        let path = if path.is_null() {
-            None
-        } else {
-            Some(util::bytes2path(CStr::from_ptr(path).to_bytes()))
-        };
-        callback(path, completed as usize, total as usize)
+        if let Some(ref mut callback) = payload.progress {
+            let path = if path.is_null() {
+                None
+            } else {
+                Some(util::bytes2path(CStr::from_ptr(path).to_bytes()))
+            };
+            callback(path, completed as usize, total as usize);
+        }
+        if payload.file_change.is_some() && !path.is_null() {
+            let path = util::bytes2path(CStr::from_ptr(path).to_bytes());
+            payload.progress_paths.push(path.to_path_buf());
+        }
+        // Notifications are sent before anything is written, so changes are
+        // only reported once the last step of the checkout has completed.
+        if completed == total && total > 0 {
+            payload.flush_file_changes();
+        }
     });
 }
 
@@ -641,6 +920,30 @@ extern "C" fn notify_cb(
     // pack callback etc
     panic::wrap(|| unsafe {
         let payload = &mut *(data as *mut CheckoutBuilder<'_>);
+        let why = CheckoutNotificationType::from_bits_truncate(why as u32);
+        if payload.file_change.is_some()
+            && why.contains(CheckoutNotificationType::UPDATED)
+            && !path.is_null()
+        {
+            let id = |file: *const raw::git_diff_file| {
+                if file.is_null() {
+                    return None;
+                }
+                let id = DiffFile::from_raw(file).id();
+                if id.is_zero() {
+                    None
+                } else {
+                    Some(id)
+                }
+            };
+            let changed = util::bytes2path(CStr::from_ptr(path).to_bytes()).to_path_buf();
+            payload
+                .pending_changes
+                .push((changed, id(baseline), id(target)));
+        }
+        if !payload.notify_flags.intersects(why) {
+            return 0;
+        }
         let callback = match payload.notify {
             Some(ref mut c) => c,
             None => return 0,
@@ -669,7 +972,6 @@ extern "C" fn notify_cb(
             Some(DiffFile::from_raw(workdir))
         };
 
-        let why = CheckoutNotificationType::from_bits_truncate(why as u32);
         let keep_going = callback(why, path, baseline, target, workdir);
         if keep_going {
             0
@@ -751,10 +1053,10 @@ impl TreeUpdateBuilder {
 
 #[cfg(test)]
 mod tests {
-    use super::{CheckoutBuilder, RepoBuilder, TreeUpdateBuilder};
-    use crate::{CheckoutNotificationType, FileMode, Repository};
+    use super::{CheckoutBuilder, CloneLocal, RepoBuilder, TreeUpdateBuilder};
+    use crate::{CheckoutNotificationType, FileMode, Oid, Repository, ResetType};
     use std::fs;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
     use tempfile::TempDir;
 
     #[test]
@@ -782,6 +1084,59 @@ mod tests {
         assert!(RepoBuilder::new().branch("foo").clone(&url, &dst).is_err());
     }
 
+    #[test]
+    fn clone_reference() {
+        let (td, origin) = crate::test::repo_init();
+        let url = crate::test::path2url(td.path());
+        let shared = origin.refname_to_id("HEAD").unwrap();
+        // A tag of a tree, whose objects are not part of any history.
+        let blob = origin.blob(b"tagged").unwrap();
+        let mut builder = origin.treebuilder(None).unwrap();
+        builder.insert("file", blob, 0o100644).unwrap();
+        let tree = builder.write().unwrap();
+        let tree_object = origin.find_object(tree, None).unwrap();
+        let sig = origin.signature().unwrap();
+        let tag = origin.tag("tree", &tree_object, &sig, "", false).unwrap();
+        let reference = TempDir::new().unwrap();
+        RepoBuilder::new()
+            .bare(true)
+            .clone(&url, reference.path())
+            .unwrap();
+        let (new, _) = crate::test::commit(&origin);
+
+        let dst = TempDir::new().unwrap();
+        let repo = RepoBuilder::new()
+            .clone_local(CloneLocal::None)
+            .reference(reference.path(), false)
+            .clone(&url, dst.path())
+            .unwrap();
+        let alternates = repo.path().join("objects/info/alternates");
+        assert!(alternates.exists());
+        repo.find_commit(shared).unwrap();
+        repo.find_commit(new).unwrap();
+        assert!(dst.path().join("foo").exists());
+
+        // Without the alternate, only the objects the reference doesn't have
+        // are left.
+        fs::remove_file(&alternates).unwrap();
+        let repo = Repository::open(dst.path()).unwrap();
+        assert!(repo.find_commit(shared).is_err());
+        repo.find_commit(new).unwrap();
+
+        let dst = TempDir::new().unwrap();
+        let repo = RepoBuilder::new()
+            .clone_local(CloneLocal::None)
+            .reference(reference.path(), true)
+            .clone(&url, dst.path())
+            .unwrap();
+        assert!(!repo.path().join("objects/info/alternates").exists());
+        repo.find_commit(shared).unwrap();
+        repo.find_commit(new).unwrap();
+        assert_eq!(repo.refname_to_id("refs/tags/tree").unwrap(), tag);
+        repo.find_tree(tree).unwrap();
+        repo.find_blob(blob).unwrap();
+    }
+
     #[test]
     fn smoke_tree_create_updated() {
         let (_tempdir, repo) = crate::test::repo_init();
@@ -848,4 +1203,58 @@ mod tests {
         repo.checkout_index(Some(&mut index), Some(&mut checkout_opts))
             .unwrap();
     }
+
+    #[test]
+    fn file_changes_callback() {
+        let (td, repo) = crate::test::repo_init();
+        let commit = |files: &[(&str, &str)]| {
+            let mut index = repo.index().unwrap();
+            index.clear().unwrap();
+            for &(name, contents) in files {
+                fs::write(td.path().join(name), contents).unwrap();
+                index.add_path(Path::new(name)).unwrap();
+            }
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = repo.signature().unwrap();
+            let parent = repo.head().unwrap().peel_to_commit().unwrap();
+            let id = repo
+                .commit(Some("HEAD"), &sig, &sig, "commit", &tree, &[&parent])
+                .unwrap();
+            repo.find_commit(id).unwrap()
+        };
+        let blob = |commit: &crate::Commit<'_>, name: &str| {
+            commit.tree().unwrap().get_name(name).map(|e| e.id())
+        };
+        let c1 = commit(&[("a", "one\n"), ("b", "b\n")]);
+        fs::remove_file(td.path().join("b")).unwrap();
+        let c2 = commit(&[("a", "two\n"), ("c", "c\n")]);
+
+        let mut changes: Vec<(PathBuf, Option<Oid>, Option<Oid>)> = Vec::new();
+        {
+            let mut opts = CheckoutBuilder::new();
+            opts.file_changes(|path, old, new| changes.push((path.to_path_buf(), old, new)));
+            repo.reset(c1.as_object(), ResetType::Hard, Some(&mut opts))
+                .unwrap();
+        }
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (PathBuf::from("a"), blob(&c2, "a"), blob(&c1, "a")),
+                (PathBuf::from("b"), None, blob(&c1, "b")),
+                (PathBuf::from("c"), blob(&c2, "c"), None),
+            ]
+        );
+        assert!(td.path().join("b").exists());
+
+        let mut changes = Vec::new();
+        {
+            let mut opts = CheckoutBuilder::new();
+            opts.dry_run();
+            opts.file_changes(|path, _, _| changes.push(path.to_path_buf()));
+            repo.checkout_tree(c2.as_object(), Some(&mut opts)).unwrap();
+        }
+        assert!(changes.is_empty());
+    }
 }
@@ -2,12 +2,13 @@ use std::mem;
 
 use crate::build::CheckoutBuilder;
 use crate::merge::MergeOptions;
-use crate::raw;
+use crate::{raw, Oid, Repository};
 use std::ptr;
 
 /// Options to specify when cherry picking
 pub struct CherrypickOptions<'cb> {
     mainline: u32,
+    append_origin: bool,
     checkout_builder: Option<CheckoutBuilder<'cb>>,
     merge_opts: Option<MergeOptions>,
 }
@@ -17,6 +18,7 @@ impl<'cb> CherrypickOptions<'cb> {
     pub fn new() -> CherrypickOptions<'cb> {
         CherrypickOptions {
             mainline: 0,
+            append_origin: false,
             checkout_builder: None,
             merge_opts: None,
         }
@@ -24,12 +26,26 @@ impl<'cb> CherrypickOptions<'cb> {
 
     /// Set the mainline value
     ///
-    /// For merge commits, the "mainline" is treated as the parent.
+    /// For merge commits, the "mainline" is treated as the parent: the
+    /// changes picked are those relative to the parent with this number,
+    /// starting from 1, like `git cherry-pick -m`. Picking a merge commit
+    /// fails unless it is set.
     pub fn mainline(&mut self, mainline: u32) -> &mut Self {
         self.mainline = mainline;
         self
     }
 
+    /// Append a "(cherry picked from commit ...)" line to the message
+    /// prepared in `MERGE_MSG`, like `git cherry-pick -x`.
+    pub fn append_origin(&mut self, append: bool) -> &mut Self {
+        self.append_origin = append;
+        self
+    }
+
+    pub(crate) fn is_append_origin(&self) -> bool {
+        self.append_origin
+    }
+
     /// Set the checkout builder
     pub fn checkout_builder(&mut self, cb: CheckoutBuilder<'cb>) -> &mut Self {
         self.checkout_builder = Some(cb);
@@ -42,6 +58,14 @@ impl<'cb> CherrypickOptions<'cb> {
         self
     }
 
+    /// Set the repository the checkout runs in, see
+    /// `CheckoutBuilder::set_repository`.
+    pub(crate) fn set_repository(&mut self, repo: &Repository) {
+        if let Some(ref mut cb) = self.checkout_builder {
+            cb.set_repository(repo);
+        }
+    }
+
     /// Obtain the raw struct
     pub fn raw(&mut self) -> raw::git_cherrypick_options {
         unsafe {
@@ -70,3 +94,64 @@ impl<'cb> CherrypickOptions<'cb> {
         }
     }
 }
+
+/// Appends the line `git cherry-pick -x` records to a cherry-picked
+/// commit's `message`, keeping it in the trailer block if the message ends
+/// with one, and before the conflicts libgit2 lists at the end.
+pub(crate) fn append_origin(message: &str, id: Oid) -> String {
+    let (message, conflicts) = crate::message::split_conflicts(message);
+    let message = message.trim_end_matches('\n');
+    let last = message.rsplit("\n\n").next().unwrap_or("");
+    let trailers = !last.is_empty() && message != last && last.lines().all(is_trailer);
+    format!(
+        "{}{}(cherry picked from commit {})\n{}",
+        message,
+        if trailers { "\n" } else { "\n\n" },
+        id,
+        conflicts
+    )
+}
+
+pub(crate) fn is_trailer(line: &str) -> bool {
+    if line.starts_with("(cherry picked from commit ") {
+        return true;
+    }
+    match line.find(": ") {
+        Some(i) => {
+            i > 0
+                && line[..i]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::append_origin;
+    use crate::Oid;
+
+    #[test]
+    fn origin() {
+        let id = Oid::from_str("1234567890123456789012345678901234567890").unwrap();
+        let line = format!("(cherry picked from commit {})\n", id);
+        assert_eq!(append_origin("fix\n", id), format!("fix\n\n{}", line));
+        assert_eq!(
+            append_origin("fix\n\nbody\n\nSigned-off-by: a <b>\n", id),
+            format!("fix\n\nbody\n\nSigned-off-by: a <b>\n{}", line)
+        );
+        assert_eq!(
+            append_origin("Fix: it\n", id),
+            format!("Fix: it\n\n{}", line)
+        );
+        assert_eq!(
+            append_origin("fix\n\n#Conflicts:\n#\tfile\n", id),
+            format!("fix\n\n{}\n#Conflicts:\n#\tfile\n", line)
+        );
+        assert_eq!(
+            append_origin("fix\nConflicts:\n\tfile\n", id),
+            format!("fix\n\n{}\nConflicts:\n\tfile\n", line)
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::util;
+use crate::{Commit, DiffOptions, Error, ObjectType, Oid, Patch, Repository, Tree};
+
+/// The number of unchanged lines shown around changes.
+const CONTEXT: usize = 3;
+
+/// A combined diff of a merge commit against all of its parents, like
+/// `git diff --cc` or `git show -c`.
+///
+/// Created with `Repository::combined_diff`. Only files which differ from
+/// every parent are included, and each line records how it relates to each
+/// of the parents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedDiff {
+    parents: Vec<Oid>,
+    files: Vec<CombinedFile>,
+}
+
+/// A file in a `CombinedDiff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedFile {
+    path: Vec<u8>,
+    parent_ids: Vec<Oid>,
+    id: Oid,
+    binary: bool,
+    hunks: Vec<CombinedHunk>,
+}
+
+/// A hunk of a `CombinedFile`, with a line range in each parent and in the
+/// merge result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedHunk {
+    old_starts: Vec<u32>,
+    old_lines: Vec<u32>,
+    new_start: u32,
+    new_lines: u32,
+    lines: Vec<CombinedLine>,
+}
+
+/// A line of a `CombinedHunk`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedLine {
+    origins: Vec<char>,
+    new_lineno: Option<u32>,
+    content: Vec<u8>,
+}
+
+impl CombinedDiff {
+    pub(crate) fn new(
+        repo: &Repository,
+        commit: &Commit<'_>,
+        dense: bool,
+    ) -> Result<CombinedDiff, Error> {
+        let tree = commit.tree()?;
+        let parents = commit.parents().collect::<Vec<_>>();
+        let parent_trees = parents
+            .iter()
+            .map(|p| p.tree())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Only paths which differ from every parent are interesting.
+        let mut changed = BTreeMap::new();
+        for parent_tree in parent_trees.iter() {
+            let diff = repo.diff_tree_to_tree(Some(parent_tree), Some(&tree), None)?;
+            for delta in diff.deltas() {
+                let path = delta
+                    .new_file()
+                    .path_bytes()
+                    .or_else(|| delta.old_file().path_bytes());
+                if let Some(path) = path {
+                    *changed.entry(path.to_vec()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut files = Vec::new();
+        for (path, count) in changed {
+            if count < parents.len() {
+                continue;
+            }
+            let (id, result) = blob_at(repo, &tree, &path)?;
+            let mut parent_ids = Vec::new();
+            let mut parent_contents = Vec::new();
+            for parent_tree in parent_trees.iter() {
+                let (id, content) = blob_at(repo, parent_tree, &path)?;
+                parent_ids.push(id);
+                parent_contents.push(content);
+            }
+            let binary = is_binary(&result) || parent_contents.iter().any(|c| is_binary(c));
+            let hunks = if binary {
+                Vec::new()
+            } else {
+                let parent_contents = parent_contents.iter().map(|c| &c[..]).collect::<Vec<_>>();
+                combine(&parent_contents, &result, dense)?
+            };
+            if dense && !binary && hunks.is_empty() {
+                continue;
+            }
+            files.push(CombinedFile {
+                path,
+                parent_ids,
+                id,
+                binary,
+                hunks,
+            });
+        }
+        Ok(CombinedDiff {
+            parents: parents.iter().map(|p| p.id()).collect(),
+            files,
+        })
+    }
+
+    /// Returns the ids of the parents of the merge commit, in order.
+    pub fn parents(&self) -> &[Oid] {
+        &self.parents
+    }
+
+    /// Returns the files of this diff.
+    pub fn files(&self) -> &[CombinedFile] {
+        &self.files
+    }
+
+    /// Format this diff as text, in the format of `git diff --cc`.
+    pub fn to_text(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for file in self.files.iter() {
+            out.extend_from_slice(b"diff --cc ");
+            out.extend_from_slice(&file.path);
+            out.extend_from_slice(b"\nindex ");
+            let ids = file
+                .parent_ids
+                .iter()
+                .map(|id| abbrev(*id))
+                .collect::<Vec<_>>();
+            out.extend_from_slice(ids.join(",").as_bytes());
+            out.extend_from_slice(b"..");
+            out.extend_from_slice(abbrev(file.id).as_bytes());
+            out.push(b'\n');
+            if file.binary {
+                out.extend_from_slice(b"Binary files differ\n");
+                continue;
+            }
+            out.extend_from_slice(b"--- a/");
+            out.extend_from_slice(&file.path);
+            out.extend_from_slice(b"\n+++ b/");
+            out.extend_from_slice(&file.path);
+            out.push(b'\n');
+            for hunk in file.hunks.iter() {
+                out.extend_from_slice(hunk.header().as_bytes());
+                out.push(b'\n');
+                for line in hunk.lines.iter() {
+                    out.extend(line.origins.iter().map(|&c| c as u8));
+                    out.extend_from_slice(&line.content);
+                    if !line.content.ends_with(b"\n") {
+                        out.extend_from_slice(b"\n\\ No newline at end of file\n");
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+impl CombinedFile {
+    /// Returns the path of the file as a byte slice.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        util::bytes2path(&self.path)
+    }
+
+    /// Returns the ids of the file's blob in each parent, or the zero id
+    /// where the file does not exist.
+    pub fn parent_ids(&self) -> &[Oid] {
+        &self.parent_ids
+    }
+
+    /// Returns the id of the file's blob in the merge commit, or the zero id
+    /// if it was deleted.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// Returns `true` if the file is binary in the merge or in any parent,
+    /// in which case it has no hunks.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Returns the hunks of this file.
+    pub fn hunks(&self) -> &[CombinedHunk] {
+        &self.hunks
+    }
+}
+
+impl CombinedHunk {
+    /// Returns the starting line number of this hunk in the parent at index
+    /// `parent`.
+    pub fn old_start(&self, parent: usize) -> u32 {
+        self.old_starts[parent]
+    }
+
+    /// Returns the number of lines of this hunk in the parent at index
+    /// `parent`.
+    pub fn old_lines(&self, parent: usize) -> u32 {
+        self.old_lines[parent]
+    }
+
+    /// Returns the starting line number of this hunk in the merge result.
+    pub fn new_start(&self) -> u32 {
+        self.new_start
+    }
+
+    /// Returns the number of lines of this hunk in the merge result.
+    pub fn new_lines(&self) -> u32 {
+        self.new_lines
+    }
+
+    /// Returns the lines of this hunk.
+    pub fn lines(&self) -> &[CombinedLine] {
+        &self.lines
+    }
+
+    /// Returns the header of this hunk, e.g. `@@@ -1,3 -1,4 +1,4 @@@`.
+    pub fn header(&self) -> String {
+        let marker = "@".repeat(self.old_starts.len() + 1);
+        let mut header = marker.clone();
+        for (start, lines) in self.old_starts.iter().zip(self.old_lines.iter()) {
+            header.push_str(&format!(" -{},{}", start, lines));
+        }
+        header.push_str(&format!(
+            " +{},{} {}",
+            self.new_start, self.new_lines, marker
+        ));
+        header
+    }
+}
+
+impl CombinedLine {
+    /// Returns the origin of this line relative to each parent: `+` if the
+    /// line was added compared to that parent, `-` if it was removed from
+    /// that parent, and a space if it is unchanged or belongs to another
+    /// parent.
+    pub fn origins(&self) -> &[char] {
+        &self.origins
+    }
+
+    /// Returns the origin of this line relative to the parent at index
+    /// `parent`.
+    pub fn origin(&self, parent: usize) -> char {
+        self.origins[parent]
+    }
+
+    /// Returns the line number in the merge result, or `None` for lines
+    /// which were removed.
+    pub fn new_lineno(&self) -> Option<u32> {
+        self.new_lineno
+    }
+
+    /// Returns the content of the line, including its line terminator.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+}
+
+fn abbrev(id: Oid) -> String {
+    id.to_string()[..7].to_string()
+}
+
+fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+fn blob_at(repo: &Repository, tree: &Tree<'_>, path: &[u8]) -> Result<(Oid, Vec<u8>), Error> {
+    let entry = match tree.get_path(util::bytes2path(path)) {
+        Ok(entry) => entry,
+        Err(..) => return Ok((Oid::zero(), Vec::new())),
+    };
+    if entry.kind() != Some(ObjectType::Blob) {
+        return Ok((entry.id(), Vec::new()));
+    }
+    let blob = repo.find_blob(entry.id())?;
+    Ok((entry.id(), blob.content().to_vec()))
+}
+
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, &b) in content.iter().enumerate() {
+        if b == b'\n' {
+            lines.push(&content[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < content.len() {
+        lines.push(&content[start..]);
+    }
+    lines
+}
+
+/// Combine the diffs of each parent against the result into hunks.
+fn combine(parents: &[&[u8]], result: &[u8], dense: bool) -> Result<Vec<CombinedHunk>, Error> {
+    let n = parents.len();
+    let lines = split_lines(result);
+    // For each line of the result, whether it was added compared to each
+    // parent.
+    let mut added = vec![vec![false; n]; lines.len()];
+    // For each position before a line of the result (and at the end), the
+    // lines removed there, with the parents they were removed from. The same
+    // line removed from several parents is only listed once.
+    let mut lost: Vec<Vec<(Vec<u8>, Vec<bool>)>> = vec![Vec::new(); lines.len() + 1];
+
+    let mut opts = DiffOptions::new();
+    opts.context_lines(0);
+    for (i, parent) in parents.iter().enumerate() {
+        let patch = Patch::from_buffers(parent, None, result, None, Some(&mut opts))?;
+        for hunk_idx in 0..patch.num_hunks() {
+            let (hunk, _) = patch.hunk(hunk_idx)?;
+            let slot = if hunk.new_lines() == 0 {
+                hunk.new_start()
+            } else {
+                hunk.new_start() - 1
+            } as usize;
+            let mut cursor = 0;
+            for line in patch.lines_in_hunk(hunk_idx)? {
+                match line.origin() {
+                    '+' => {
+                        if let Some(lineno) = line.new_lineno() {
+                            added[lineno as usize - 1][i] = true;
+                        }
+                    }
+                    '-' => {
+                        let entries = &mut lost[slot];
+                        let found = entries[cursor..]
+                            .iter()
+                            .position(|(content, mask)| !mask[i] && content[..] == *line.content());
+                        match found {
+                            Some(pos) => {
+                                entries[cursor + pos].1[i] = true;
+                                cursor += pos + 1;
+                            }
+                            None => {
+                                let mut mask = vec![false; n];
+                                mask[i] = true;
+                                entries.push((line.content().to_vec(), mask));
+                                cursor = entries.len();
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Show changed lines and the context around them.
+    let mut shown = vec![false; lines.len()];
+    let mut show = |lo: usize, hi: usize| {
+        for s in shown.iter_mut().take(hi).skip(lo) {
+            *s = true;
+        }
+    };
+    for (j, added) in added.iter().enumerate() {
+        if added.iter().any(|&a| a) {
+            show(j.saturating_sub(CONTEXT), j + CONTEXT + 1);
+        }
+    }
+    for (j, lost) in lost.iter().enumerate() {
+        if !lost.is_empty() {
+            show(j.saturating_sub(CONTEXT), j + CONTEXT);
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut current: Option<CombinedHunk> = None;
+    let mut old_counts = vec![0u32; n];
+    let start = |old_counts: &[u32], j: usize| CombinedHunk {
+        old_starts: old_counts.iter().map(|c| c + 1).collect(),
+        old_lines: vec![0; n],
+        new_start: j as u32 + 1,
+        new_lines: 0,
+        lines: Vec::new(),
+    };
+    for j in 0..=lines.len() {
+        for (content, mask) in lost[j].iter() {
+            let hunk = current.get_or_insert_with(|| start(&old_counts, j));
+            for i in 0..n {
+                if mask[i] {
+                    old_counts[i] += 1;
+                    hunk.old_lines[i] += 1;
+                }
+            }
+            hunk.lines.push(CombinedLine {
+                origins: mask.iter().map(|&m| if m { '-' } else { ' ' }).collect(),
+                new_lineno: None,
+                content: content.clone(),
+            });
+        }
+        if j == lines.len() {
+            break;
+        }
+        if shown[j] {
+            let hunk = current.get_or_insert_with(|| start(&old_counts, j));
+            for i in 0..n {
+                if !added[j][i] {
+                    old_counts[i] += 1;
+                    hunk.old_lines[i] += 1;
+                }
+            }
+            hunk.new_lines += 1;
+            hunk.lines.push(CombinedLine {
+                origins: added[j]
+                    .iter()
+                    .map(|&a| if a { '+' } else { ' ' })
+                    .collect(),
+                new_lineno: Some(j as u32 + 1),
+                content: lines[j].to_vec(),
+            });
+        } else {
+            hunks.extend(current.take());
+            for count in old_counts.iter_mut() {
+                *count += 1;
+            }
+        }
+    }
+    hunks.extend(current.take());
+
+    for hunk in hunks.iter_mut() {
+        for i in 0..n {
+            if hunk.old_lines[i] == 0 {
+                hunk.old_starts[i] -= 1;
+            }
+        }
+        if hunk.new_lines == 0 {
+            hunk.new_start -= 1;
+        }
+    }
+    if dense {
+        // A hunk which matches one of the parents exactly only repeats a
+        // change made on another side, and is not interesting.
+        hunks.retain(|hunk| !(0..n).any(|i| hunk.lines.iter().all(|line| line.origins[i] == ' ')));
+    }
+    Ok(hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Commit, Oid, Repository, Signature};
+
+    fn commit(repo: &Repository, files: &[(&str, String)], parents: &[&Commit<'_>]) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for &(name, ref content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let sig = Signature::now("name", "email").unwrap();
+        repo.commit(None, &sig, &sig, "msg", &tree, parents)
+            .unwrap()
+    }
+
+    fn lines(changes: &[(usize, &str)]) -> String {
+        (1..=20)
+            .map(|i| match changes.iter().find(|c| c.0 == i) {
+                Some(c) => format!("{}\n", c.1),
+                None => format!("line{}\n", i),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = commit(&repo, &[("file", lines(&[])), ("other", lines(&[]))], &[]);
+        let base = repo.find_commit(base).unwrap();
+        let ours = commit(
+            &repo,
+            &[
+                ("file", lines(&[(2, "ours")])),
+                ("other", lines(&[(5, "x")])),
+            ],
+            &[&base],
+        );
+        let ours = repo.find_commit(ours).unwrap();
+        let theirs = commit(
+            &repo,
+            &[("file", lines(&[(18, "theirs")])), ("other", lines(&[]))],
+            &[&base],
+        );
+        let theirs = repo.find_commit(theirs).unwrap();
+        let merge = commit(
+            &repo,
+            &[
+                ("file", lines(&[(2, "ours"), (10, "evil"), (18, "theirs")])),
+                ("other", lines(&[(5, "x")])),
+            ],
+            &[&ours, &theirs],
+        );
+        let merge = repo.find_commit(merge).unwrap();
+
+        let combined = repo.combined_diff(&merge, false).unwrap();
+        assert_eq!(combined.parents(), &[ours.id(), theirs.id()][..]);
+        assert_eq!(combined.files().len(), 1);
+        let file = &combined.files()[0];
+        assert_eq!(file.path_bytes(), b"file");
+        assert_eq!(file.parent_ids().len(), 2);
+        assert_eq!(file.hunks().len(), 3);
+
+        let dense = repo.combined_diff(&merge, true).unwrap();
+        let file = &dense.files()[0];
+        assert_eq!(file.hunks().len(), 1);
+        let hunk = &file.hunks()[0];
+        assert_eq!(hunk.header(), "@@@ -7,7 -7,7 +7,7 @@@");
+        let evil = hunk
+            .lines()
+            .iter()
+            .map(|l| (l.origins().iter().collect::<String>(), l.content().to_vec()))
+            .filter(|l| l.0 != "  ")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            evil,
+            vec![
+                ("--".to_string(), b"line10\n".to_vec()),
+                ("++".to_string(), b"evil\n".to_vec()),
+            ]
+        );
+        assert_eq!(hunk.lines()[4].new_lineno(), Some(10));
+        let text = String::from_utf8(dense.to_text()).unwrap();
+        assert!(text.starts_with("diff --cc file\nindex "));
+        assert!(text.contains("\n--line10\n++evil\n"));
+
+        let first = &combined.files()[0].hunks()[0];
+        assert_eq!(first.old_start(0), 1);
+        assert_eq!(first.lines()[1].origins(), &[' ', '-'][..]);
+        assert_eq!(first.lines()[2].origins(), &[' ', '+'][..]);
+    }
+}
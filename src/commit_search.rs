@@ -0,0 +1,430 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use crate::status_cache::{escape, unescape};
+use crate::{Commit, Error, ErrorClass, ErrorCode, LockFile, LockOptions, Oid, Repository, Sort};
+
+/// What `Repository::search_commits` looks for.
+///
+/// A query matches the message and the author of commits, the author being
+/// written as `Name <email>`. Both are searched by default.
+pub struct CommitQuery<'a> {
+    matcher: Box<dyn FnMut(&str) -> bool + 'a>,
+    message: bool,
+    author: bool,
+}
+
+/// Options for `Repository::search_commits`.
+#[derive(Debug, Clone, Default)]
+pub struct CommitSearchOptions {
+    tips: Vec<Oid>,
+    limit: Option<usize>,
+    index: Option<PathBuf>,
+}
+
+/// The messages and authors of commits, saved to a file so that searches
+/// don't need to read every commit of the history again.
+///
+/// The index covers the history of the commits it was updated with, and
+/// each `update` only reads the commits added since the previous one.
+#[derive(Debug, Clone, Default)]
+pub struct CommitSearchIndex {
+    tips: Vec<Oid>,
+    commits: HashMap<Oid, IndexedCommit>,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedCommit {
+    time: i64,
+    parents: Vec<Oid>,
+    author: String,
+    message: String,
+}
+
+const HEADER: &str = "git2-commit-search-index 1";
+
+impl<'a> CommitQuery<'a> {
+    /// Match commits containing `needle`.
+    pub fn substring(needle: &str) -> CommitQuery<'a> {
+        let needle = needle.to_string();
+        CommitQuery::matching(move |text| text.contains(&needle[..]))
+    }
+
+    /// Match commits for which `matcher` returns `true`.
+    ///
+    /// The matcher is given the message and the author of each commit; a
+    /// regular expression from a crate such as `regex` can be plugged in
+    /// here.
+    pub fn matching<F>(matcher: F) -> CommitQuery<'a>
+    where
+        F: FnMut(&str) -> bool + 'a,
+    {
+        CommitQuery {
+            matcher: Box::new(matcher),
+            message: true,
+            author: true,
+        }
+    }
+
+    /// Whether to search the messages of commits.
+    pub fn message(&mut self, enabled: bool) -> &mut CommitQuery<'a> {
+        self.message = enabled;
+        self
+    }
+
+    /// Whether to search the authors of commits.
+    pub fn author(&mut self, enabled: bool) -> &mut CommitQuery<'a> {
+        self.author = enabled;
+        self
+    }
+
+    fn matches(&mut self, commit: &IndexedCommit) -> bool {
+        (self.message && (self.matcher)(&commit.message))
+            || (self.author && (self.matcher)(&commit.author))
+    }
+}
+
+impl CommitSearchOptions {
+    /// Creates options searching the history of `HEAD` without an index.
+    pub fn new() -> CommitSearchOptions {
+        CommitSearchOptions::default()
+    }
+
+    /// Search the history of `oid` instead of `HEAD`. This can be called
+    /// several times to search the history of several commits.
+    pub fn push(&mut self, oid: Oid) -> &mut CommitSearchOptions {
+        self.tips.push(oid);
+        self
+    }
+
+    /// Return at most `limit` commits.
+    pub fn limit(&mut self, limit: usize) -> &mut CommitSearchOptions {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Search with the `CommitSearchIndex` saved at `path`, updating it
+    /// first with the commits it doesn't cover yet.
+    pub fn index<P: AsRef<Path>>(&mut self, path: P) -> &mut CommitSearchOptions {
+        self.index = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
+impl CommitSearchIndex {
+    /// Creates an empty index.
+    pub fn new() -> CommitSearchIndex {
+        CommitSearchIndex::default()
+    }
+
+    /// Loads the index saved at `path`.
+    ///
+    /// A missing or invalid file gives an empty index, which the next
+    /// `update` fills again.
+    pub fn open(path: &Path) -> CommitSearchIndex {
+        fs::read(path)
+            .ok()
+            .and_then(|data| CommitSearchIndex::from_bytes(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Adds the history of `tips` to the index, reading only the commits
+    /// which are not indexed yet. Returns the number of commits added.
+    pub fn update(&mut self, repo: &Repository, tips: &[Oid]) -> Result<usize, Error> {
+        let new_tips = tips
+            .iter()
+            .filter(|id| !self.commits.contains_key(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        if new_tips.is_empty() {
+            return Ok(0);
+        }
+        let mut walk = repo.revwalk()?;
+        for tip in new_tips.iter() {
+            walk.push(*tip)?;
+        }
+        // The history of the previous tips is indexed already, unless they
+        // were garbage collected since.
+        let odb = repo.odb()?;
+        for tip in self.tips.iter().filter(|id| odb.exists(**id)) {
+            walk.hide(*tip)?;
+        }
+        let mut added = 0;
+        let mut parents = HashSet::new();
+        for id in walk {
+            let id = id?;
+            if self.commits.contains_key(&id) {
+                continue;
+            }
+            let commit = IndexedCommit::new(&repo.find_commit(id)?);
+            parents.extend(commit.parents.iter().cloned());
+            self.commits.insert(id, commit);
+            added += 1;
+        }
+        for tip in new_tips {
+            if !self.tips.contains(&tip) {
+                self.tips.push(tip);
+            }
+        }
+        // Tips which are now in the history of other tips are redundant.
+        self.tips.retain(|id| !parents.contains(id));
+        Ok(added)
+    }
+
+    /// Save this index to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut lock = LockFile::acquire(path, &LockOptions::new())?;
+        lock.write_all(&self.to_bytes()).map_err(|e| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to write '{}': {}", path.display(), e),
+            )
+        })?;
+        lock.commit()
+    }
+
+    /// Returns `true` if `id` is indexed.
+    pub fn contains(&self, id: Oid) -> bool {
+        self.commits.contains_key(&id)
+    }
+
+    /// Returns the number of indexed commits.
+    pub fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    /// Returns `true` if no commit is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+
+    /// Returns the commits in the history of `tips` which match `query`,
+    /// newest first. Commits which are not indexed are skipped.
+    pub fn search(
+        &self,
+        query: &mut CommitQuery<'_>,
+        tips: &[Oid],
+        limit: Option<usize>,
+    ) -> Vec<Oid> {
+        let mut reachable = HashSet::new();
+        let mut pending = tips.to_vec();
+        while let Some(id) = pending.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            if let Some(commit) = self.commits.get(&id) {
+                pending.extend(commit.parents.iter().cloned());
+            }
+        }
+        let mut found = reachable
+            .into_iter()
+            .filter_map(|id| self.commits.get(&id).map(|commit| (id, commit)))
+            .filter(|(_, commit)| query.matches(commit))
+            .map(|(id, commit)| (commit.time, id))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| b.cmp(a));
+        found
+            .into_iter()
+            .map(|(_, id)| id)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// Serialize this index.
+    ///
+    /// After a header line, the format is a `tip <id>` line per tip, then
+    /// for each commit a `commit <id> <time> <parents>...` line followed by
+    /// `author` and `message` lines, with newlines and backslashes escaped.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("{}\n", HEADER).into_bytes();
+        for tip in self.tips.iter() {
+            out.extend(format!("tip {}\n", tip).into_bytes());
+        }
+        let mut ids = self.commits.keys().collect::<Vec<_>>();
+        ids.sort();
+        for id in ids {
+            let commit = &self.commits[id];
+            out.extend(format!("commit {} {}", id, commit.time).into_bytes());
+            for parent in commit.parents.iter() {
+                out.extend(format!(" {}", parent).into_bytes());
+            }
+            out.extend(b"\nauthor ");
+            escape(&mut out, commit.author.as_bytes());
+            out.extend(b"message ");
+            escape(&mut out, commit.message.as_bytes());
+        }
+        out
+    }
+
+    /// Deserialize an index written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CommitSearchIndex, Error> {
+        let text = str::from_utf8(bytes)
+            .map_err(|_| Error::from_str("commit search index is not valid utf-8"))?;
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(Error::from_str("not a commit search index"));
+        }
+        let invalid_line =
+            |line: &str| Error::from_str(&format!("invalid commit search index line '{}'", line));
+        let mut index = CommitSearchIndex::new();
+        let mut last = None;
+        for line in lines.filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            let (kind, rest) = match (parts.next(), parts.next()) {
+                (Some(kind), Some(rest)) => (kind, rest),
+                _ => return Err(invalid_line(line)),
+            };
+            let text = || String::from_utf8_lossy(&unescape(rest.as_bytes())).into_owned();
+            match (kind, last.and_then(|id| index.commits.get_mut(&id))) {
+                ("tip", _) => index.tips.push(Oid::from_str(rest)?),
+                ("commit", _) => {
+                    let mut fields = rest.split(' ');
+                    let id = Oid::from_str(fields.next().unwrap_or(""))?;
+                    let time = fields
+                        .next()
+                        .and_then(|time| time.parse().ok())
+                        .ok_or_else(|| invalid_line(line))?;
+                    let parents = fields.map(Oid::from_str).collect::<Result<_, _>>()?;
+                    index.commits.insert(
+                        id,
+                        IndexedCommit {
+                            time,
+                            parents,
+                            author: String::new(),
+                            message: String::new(),
+                        },
+                    );
+                    last = Some(id);
+                }
+                ("author", Some(commit)) => commit.author = text(),
+                ("message", Some(commit)) => commit.message = text(),
+                _ => return Err(invalid_line(line)),
+            }
+        }
+        Ok(index)
+    }
+}
+
+impl IndexedCommit {
+    fn new(commit: &Commit<'_>) -> IndexedCommit {
+        let author = commit.author();
+        IndexedCommit {
+            time: commit.time().seconds(),
+            parents: commit.parent_ids().collect(),
+            author: format!(
+                "{} <{}>",
+                String::from_utf8_lossy(author.name_bytes()),
+                String::from_utf8_lossy(author.email_bytes())
+            ),
+            message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+        }
+    }
+}
+
+pub(crate) fn search_commits(
+    repo: &Repository,
+    query: &mut CommitQuery<'_>,
+    opts: &CommitSearchOptions,
+) -> Result<Vec<Oid>, Error> {
+    let tips = if opts.tips.is_empty() {
+        vec![repo.head()?.peel_to_commit()?.id()]
+    } else {
+        opts.tips.clone()
+    };
+    if let Some(ref path) = opts.index {
+        let mut index = CommitSearchIndex::open(path);
+        if index.update(repo, &tips)? > 0 {
+            // The index is only a cache, so failing to save it is not fatal.
+            let _ = index.save(path);
+        }
+        return Ok(index.search(query, &tips, opts.limit));
+    }
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TIME)?;
+    for tip in tips {
+        walk.push(tip)?;
+    }
+    let mut found = Vec::new();
+    for id in walk {
+        if opts.limit == Some(found.len()) {
+            break;
+        }
+        let id = id?;
+        if query.matches(&IndexedCommit::new(&repo.find_commit(id)?)) {
+            found.push(id);
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CommitQuery, CommitSearchIndex, CommitSearchOptions, Oid, Repository, Signature};
+
+    fn commit(repo: &Repository, parent: Oid, message: &str, author: &str, time: i64) -> Oid {
+        let parent = repo.find_commit(parent).unwrap();
+        let tree = parent.tree().unwrap();
+        let time = crate::Time::new(time, 0);
+        let sig = Signature::new(author, "author@example.com", &time).unwrap();
+        repo.commit(None, &sig, &sig, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    #[test]
+    fn search() {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit(
+            &repo,
+            base,
+            "Fix JIRA-123\n\nDetails",
+            "alice",
+            2_000_000_000,
+        );
+        let b = commit(&repo, a, "Refactor", "bob", 2_000_000_100);
+        let c = commit(&repo, b, "Follow up on JIRA-123", "carol", 2_000_000_200);
+        let side = commit(&repo, base, "Side JIRA-123", "dave", 2_000_000_300);
+        repo.reference("refs/heads/main", c, true, "").unwrap();
+
+        let path = td.path().join("commit-index");
+        let mut indexed = CommitSearchOptions::new();
+        indexed.index(&path);
+        for opts in [CommitSearchOptions::new(), indexed.clone()].iter() {
+            let mut query = CommitQuery::substring("JIRA-123");
+            assert_eq!(repo.search_commits(&mut query, Some(opts)).unwrap(), [c, a]);
+            let mut query = CommitQuery::substring("bob");
+            assert_eq!(repo.search_commits(&mut query, Some(opts)).unwrap(), [b]);
+            query.author(false);
+            assert!(repo
+                .search_commits(&mut query, Some(opts))
+                .unwrap()
+                .is_empty());
+            let mut query = CommitQuery::matching(|text| text.starts_with("Fix"));
+            query.author(false);
+            assert_eq!(repo.search_commits(&mut query, Some(opts)).unwrap(), [a]);
+        }
+
+        let index = CommitSearchIndex::open(&path);
+        assert!(index.contains(c) && !index.contains(side));
+        let len = index.len();
+        let mut opts = indexed.clone();
+        opts.push(c).push(side).limit(2);
+        let mut query = CommitQuery::substring("JIRA-123");
+        assert_eq!(
+            repo.search_commits(&mut query, Some(&opts)).unwrap(),
+            [side, c]
+        );
+        let mut index = CommitSearchIndex::open(&path);
+        assert_eq!(index.len(), len + 1);
+        assert_eq!(index.update(&repo, &[c, side]).unwrap(), 0);
+
+        let copy = CommitSearchIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(copy.to_bytes(), index.to_bytes());
+        let mut query = CommitQuery::substring("Details");
+        assert_eq!(copy.search(&mut query, &[c], None), [a]);
+    }
+}
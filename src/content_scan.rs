@@ -0,0 +1,601 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+use crate::{Error, ErrorClass, ErrorCode, ObjectType, Oid, RefUpdate, Remote, RemoteCallbacks};
+use crate::{Repository, Sort, Tree};
+
+/// Callback of a `ContentScanner`, called with each chunk of every new blob.
+///
+/// The callback returns a message describing a finding, such as a leaked
+/// secret, or `None` if the chunk is clean. Returning an error stops the
+/// scan.
+pub type ScanCallback<'a> = dyn FnMut(&ScanChunk<'_>) -> Result<Option<String>, Error> + 'a;
+
+/// Scans the contents of the objects introduced by reference updates, such
+/// as those received by a push or downloaded by a fetch, before the updates
+/// are accepted.
+///
+/// Only the blobs which are new to the repository are read: the commits
+/// reachable from the new targets of the updates but not from the existing
+/// references, and within their trees only the objects not already present
+/// in the trees those commits are based on. Each blob is read once and
+/// handed to the callback in chunks, along with the path and commit through
+/// which it was first found.
+///
+/// A scanner can run from a `pre-receive` hook with `scan`, between
+/// `Remote::download` and `Remote::update_tips` with `scan_download`, or on
+/// the pushes made through some `RemoteCallbacks` with `enforce`.
+pub struct ContentScanner<'a> {
+    callback: Box<ScanCallback<'a>>,
+    chunk_size: usize,
+    overlap: usize,
+    max_blob_size: Option<usize>,
+    skip_binary: bool,
+}
+
+/// A chunk of a blob being scanned by a `ContentScanner`.
+pub struct ScanChunk<'a> {
+    blob: Oid,
+    path: &'a Path,
+    commit: Oid,
+    refname: &'a str,
+    offset: usize,
+    data: &'a [u8],
+    last: bool,
+}
+
+/// A finding reported by the callback of a `ContentScanner`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanFinding {
+    blob: Oid,
+    path: PathBuf,
+    commit: Oid,
+    refname: String,
+    offset: usize,
+    message: String,
+}
+
+impl<'a> ContentScanner<'a> {
+    /// Creates a scanner handing the chunks of new blobs to `callback`.
+    ///
+    /// Chunks are 64 KiB long and don't overlap by default.
+    pub fn new<F>(callback: F) -> ContentScanner<'a>
+    where
+        F: FnMut(&ScanChunk<'_>) -> Result<Option<String>, Error> + 'a,
+    {
+        ContentScanner {
+            callback: Box::new(callback),
+            chunk_size: 64 * 1024,
+            overlap: 0,
+            max_blob_size: None,
+            skip_binary: false,
+        }
+    }
+
+    /// Set the length of the chunks handed to the callback.
+    pub fn chunk_size(&mut self, size: usize) -> &mut ContentScanner<'a> {
+        self.chunk_size = size.max(1);
+        self
+    }
+
+    /// Start each chunk after the first with the last `len` bytes of the
+    /// previous one, so that matches of up to `len + 1` bytes are never
+    /// split between two chunks.
+    ///
+    /// The overlap is capped below the chunk size.
+    pub fn overlap(&mut self, len: usize) -> &mut ContentScanner<'a> {
+        self.overlap = len;
+        self
+    }
+
+    /// Skip blobs larger than `size` bytes, whose size is read from the
+    /// object header without loading their contents.
+    pub fn max_blob_size(&mut self, size: usize) -> &mut ContentScanner<'a> {
+        self.max_blob_size = Some(size);
+        self
+    }
+
+    /// Skip blobs which libgit2 considers binary.
+    pub fn skip_binary(&mut self, skip: bool) -> &mut ContentScanner<'a> {
+        self.skip_binary = skip;
+        self
+    }
+
+    /// Scan the objects introduced by `updates`, as given to a `pre-receive`
+    /// hook and parsed by `RefUpdate::parse_hook_input`.
+    ///
+    /// Objects reachable from any reference of `repo` other than the updated
+    /// ones, or from the old targets of the updates, are not scanned.
+    pub fn scan(
+        &mut self,
+        repo: &Repository,
+        updates: &[RefUpdate],
+    ) -> Result<Vec<ScanFinding>, Error> {
+        self.scan_updates(repo, updates, "refs/", true)
+    }
+
+    /// Scan the objects downloaded by `Remote::download`, before
+    /// `Remote::update_tips` makes them reachable.
+    ///
+    /// `remote` must still be connected, so that the references it
+    /// advertised can be listed. Objects reachable from the references of
+    /// `repo` are not scanned.
+    pub fn scan_download(
+        &mut self,
+        repo: &Repository,
+        remote: &Remote<'_>,
+    ) -> Result<Vec<ScanFinding>, Error> {
+        let odb = repo.odb()?;
+        let mut updates = Vec::new();
+        for head in remote.list()? {
+            if odb.exists(head.oid()) {
+                updates.push(RefUpdate::new(head.name(), Oid::zero(), head.oid()));
+            }
+        }
+        self.scan_updates(repo, &updates, "refs/", false)
+    }
+
+    /// Scan the objects sent by the pushes made with `callbacks`.
+    ///
+    /// Objects reachable from the old targets of the updates or from the
+    /// remote-tracking branches of `repo` are assumed to be known to the
+    /// remote and are not scanned. A push for which the callback reports a
+    /// finding is rejected before anything is sent, and `Remote::push`
+    /// returns an error listing the findings.
+    pub fn enforce<'b>(&'b mut self, repo: &'b Repository, callbacks: &mut RemoteCallbacks<'b>)
+    where
+        'a: 'b,
+    {
+        callbacks.push_negotiation(move |updates| {
+            let updates = updates.iter().map(RefUpdate::from).collect::<Vec<_>>();
+            let findings = self.scan_updates(repo, &updates, "refs/remotes/", false)?;
+            if findings.is_empty() {
+                return Ok(());
+            }
+            let message = findings
+                .iter()
+                .map(|f| format!("{}: {}", f.path.display(), f.message))
+                .collect::<Vec<_>>()
+                .join("; ");
+            Err(Error::new(
+                ErrorCode::User,
+                ErrorClass::Callback,
+                &format!("push rejected by content scan: {}", message),
+            ))
+        });
+    }
+
+    fn scan_updates(
+        &mut self,
+        repo: &Repository,
+        updates: &[RefUpdate],
+        hidden_refs: &str,
+        skip_updated: bool,
+    ) -> Result<Vec<ScanFinding>, Error> {
+        let mut scan = Scan {
+            repo,
+            seen: HashSet::new(),
+            findings: Vec::new(),
+        };
+        let commits = new_commits(repo, updates, hidden_refs, skip_updated)?;
+        let new = commits.iter().map(|&(id, _)| id).collect::<HashSet<_>>();
+
+        // Everything in the trees of the commits the new ones are based on
+        // is already known.
+        for &(id, _) in commits.iter() {
+            for parent in repo.find_commit(id)?.parents() {
+                if !new.contains(&parent.id()) {
+                    scan.mark_known(&parent.tree()?)?;
+                }
+            }
+        }
+        for &(id, refname) in commits.iter() {
+            let tree = repo.find_commit(id)?.tree()?;
+            let mut prefix = Vec::new();
+            scan.walk(self, &tree, &mut prefix, id, refname)?;
+        }
+        Ok(scan.findings)
+    }
+
+    fn scan_blob(
+        &mut self,
+        repo: &Repository,
+        blob: Oid,
+        path: &Path,
+        commit: Oid,
+        refname: &str,
+        findings: &mut Vec<ScanFinding>,
+    ) -> Result<(), Error> {
+        if let Some(max) = self.max_blob_size {
+            if repo.odb()?.read_header(blob)?.0 > max {
+                return Ok(());
+            }
+        }
+        let object = repo.find_blob(blob)?;
+        if self.skip_binary && object.is_binary() {
+            return Ok(());
+        }
+        let data = object.content();
+        let overlap = self.overlap.min(self.chunk_size - 1);
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + self.chunk_size).min(data.len());
+            let offset = if start == 0 { 0 } else { start - overlap };
+            let chunk = ScanChunk {
+                blob,
+                path,
+                commit,
+                refname,
+                offset,
+                data: &data[offset..end],
+                last: end == data.len(),
+            };
+            if let Some(message) = (self.callback)(&chunk)? {
+                findings.push(ScanFinding {
+                    blob,
+                    path: path.to_path_buf(),
+                    commit,
+                    refname: refname.to_string(),
+                    offset,
+                    message,
+                });
+            }
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+struct Scan<'r> {
+    repo: &'r Repository,
+    seen: HashSet<Oid>,
+    findings: Vec<ScanFinding>,
+}
+
+impl<'r> Scan<'r> {
+    fn mark_known(&mut self, tree: &Tree<'_>) -> Result<(), Error> {
+        if !self.seen.insert(tree.id()) {
+            return Ok(());
+        }
+        for entry in tree.iter() {
+            match entry.kind() {
+                Some(ObjectType::Tree) => self.mark_known(&self.repo.find_tree(entry.id())?)?,
+                Some(ObjectType::Blob) => {
+                    self.seen.insert(entry.id());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn walk(
+        &mut self,
+        scanner: &mut ContentScanner<'_>,
+        tree: &Tree<'_>,
+        prefix: &mut Vec<u8>,
+        commit: Oid,
+        refname: &str,
+    ) -> Result<(), Error> {
+        if !self.seen.insert(tree.id()) {
+            return Ok(());
+        }
+        for entry in tree.iter() {
+            let len = prefix.len();
+            prefix.extend_from_slice(entry.name_bytes());
+            match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    let subtree = self.repo.find_tree(entry.id())?;
+                    prefix.push(b'/');
+                    self.walk(scanner, &subtree, prefix, commit, refname)?;
+                }
+                Some(ObjectType::Blob) if self.seen.insert(entry.id()) => {
+                    let path = util::bytes2path(prefix);
+                    scanner.scan_blob(
+                        self.repo,
+                        entry.id(),
+                        path,
+                        commit,
+                        refname,
+                        &mut self.findings,
+                    )?;
+                }
+                _ => {}
+            }
+            prefix.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+/// Lists the commits introduced by `updates`, oldest first, with the name of
+/// the first update through which each is reachable.
+///
+/// Commits reachable from the old targets of the updates, or from the
+/// references under `hidden_refs`, are not new. If `skip_updated` is set the
+/// updated references themselves are not hidden, as they may already point
+/// to their new targets.
+fn new_commits<'u>(
+    repo: &Repository,
+    updates: &'u [RefUpdate],
+    hidden_refs: &str,
+    skip_updated: bool,
+) -> Result<Vec<(Oid, &'u str)>, Error> {
+    let peel = |id: Oid| repo.find_object(id, None).and_then(|o| o.peel_to_commit());
+    let mut hidden = Vec::new();
+    for update in updates {
+        if let Ok(commit) = peel(update.old()) {
+            hidden.push(commit.id());
+        }
+    }
+    for reference in repo.references()? {
+        let reference = reference?;
+        let name = reference.name_bytes();
+        if !name.starts_with(hidden_refs.as_bytes())
+            || (skip_updated && updates.iter().any(|u| u.refname().as_bytes() == name))
+        {
+            continue;
+        }
+        if let Ok(commit) = reference.peel_to_commit() {
+            hidden.push(commit.id());
+        }
+    }
+
+    // Each update is walked on its own, hiding the targets of the ones
+    // before it, so that commits are attributed to the first update.
+    let mut commits = Vec::new();
+    for update in updates {
+        let tip = match peel(update.new_target()) {
+            Ok(commit) if !update.is_delete() => commit.id(),
+            _ => continue,
+        };
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(tip)?;
+        for &id in hidden.iter() {
+            walk.hide(id)?;
+        }
+        for id in walk {
+            commits.push((id?, update.refname()));
+        }
+        hidden.push(tip);
+    }
+    Ok(commits)
+}
+
+impl<'a> ScanChunk<'a> {
+    /// Returns the id of the blob.
+    pub fn blob(&self) -> Oid {
+        self.blob
+    }
+
+    /// Returns the path at which the blob was first found.
+    ///
+    /// A blob found at several paths is only scanned once.
+    pub fn path(&self) -> &Path {
+        self.path
+    }
+
+    /// Returns the commit through which the blob was first found.
+    pub fn commit(&self) -> Oid {
+        self.commit
+    }
+
+    /// Returns the name of the updated reference through which the blob was
+    /// found.
+    pub fn refname(&self) -> &str {
+        self.refname
+    }
+
+    /// Returns the offset of this chunk in the blob.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the contents of this chunk.
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    /// Returns `true` for the last chunk of the blob.
+    pub fn is_last(&self) -> bool {
+        self.last
+    }
+}
+
+impl ScanFinding {
+    /// Returns the id of the blob.
+    pub fn blob(&self) -> Oid {
+        self.blob
+    }
+
+    /// Returns the path at which the blob was first found.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the commit through which the blob was first found.
+    pub fn commit(&self) -> Oid {
+        self.commit
+    }
+
+    /// Returns the name of the updated reference through which the blob was
+    /// found.
+    pub fn refname(&self) -> &str {
+        &self.refname
+    }
+
+    /// Returns the offset in the blob of the chunk the finding was reported
+    /// for.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the message returned by the callback.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+
+    use crate::build::TreeUpdateBuilder;
+    use crate::{ContentScanner, Direction, FileMode, Oid, PushOptions, RefUpdate};
+    use crate::{RemoteCallbacks, Repository};
+
+    fn commit(repo: &Repository, parent: Oid, files: &[(&str, &str)]) -> Oid {
+        let parent = repo.find_commit(parent).unwrap();
+        let mut update = TreeUpdateBuilder::new();
+        for &(path, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            update.upsert(path, blob, FileMode::Blob);
+        }
+        let tree = update
+            .create_updated(repo, &parent.tree().unwrap())
+            .unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(None, &sig, &sig, "commit", &tree, &[&parent])
+            .unwrap()
+    }
+
+    // Creates `clean.txt` on `main`, then two commits adding secrets.
+    fn setup(repo: &Repository) -> (Oid, Oid, Oid) {
+        let c1 = repo.head().unwrap().target().unwrap();
+        let c2 = commit(repo, c1, &[("clean.txt", "nothing here")]);
+        repo.reference("refs/heads/main", c2, true, "clean")
+            .unwrap();
+        let secret = "token=abcdef\npassword=hunter2\n";
+        let c3 = commit(
+            repo,
+            c2,
+            &[("config/secret.env", secret), ("copy.env", secret)],
+        );
+        let c4 = commit(repo, c3, &[("notes.txt", "xx password=1")]);
+        (c2, c3, c4)
+    }
+
+    fn findings(
+        scanner: &mut ContentScanner<'_>,
+        repo: &Repository,
+        update: RefUpdate,
+    ) -> Vec<(PathBuf, Oid, usize)> {
+        scanner
+            .scan(repo, &[update])
+            .unwrap()
+            .iter()
+            .map(|f| (f.path().to_path_buf(), f.commit(), f.offset()))
+            .collect()
+    }
+
+    fn check(chunk: &crate::ScanChunk<'_>) -> Option<String> {
+        let data = String::from_utf8_lossy(chunk.data());
+        if data.contains("password=") {
+            Some("password".to_string())
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn scan() {
+        let (_td, repo) = crate::test::repo_init();
+        let (c2, c3, c4) = setup(&repo);
+
+        let mut scanned = Vec::new();
+        {
+            let mut scanner = ContentScanner::new(|chunk| {
+                scanned.push((chunk.path().to_path_buf(), chunk.offset(), chunk.is_last()));
+                Ok(check(chunk))
+            });
+            scanner.chunk_size(8).overlap(8);
+            let expected = vec![
+                (PathBuf::from("config/secret.env"), c3, 9),
+                (PathBuf::from("notes.txt"), c4, 1),
+            ];
+            let update = RefUpdate::new("refs/heads/main", c2, c4);
+            assert_eq!(findings(&mut scanner, &repo, update), expected);
+            let update = RefUpdate::new("refs/heads/topic", Oid::zero(), c4);
+            assert_eq!(findings(&mut scanner, &repo, update), expected);
+            let update = RefUpdate::new("refs/heads/main", c4, Oid::zero());
+            assert!(findings(&mut scanner, &repo, update).is_empty());
+        }
+        let secret = PathBuf::from("config/secret.env");
+        let offsets = scanned
+            .iter()
+            .take(4)
+            .map(|&(ref path, offset, last)| {
+                assert_eq!(*path, secret);
+                (offset, last)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [(0, false), (1, false), (9, false), (17, true)]);
+        assert_eq!(scanned[4], (PathBuf::from("notes.txt"), 0, false));
+        assert!(!scanned.iter().any(|s| s.0 == Path::new("clean.txt")));
+        assert!(!scanned.iter().any(|s| s.0 == Path::new("copy.env")));
+
+        let mut scanner = ContentScanner::new(|chunk| Ok(check(chunk)));
+        scanner.max_blob_size(20);
+        let update = RefUpdate::new("refs/heads/main", c2, c4);
+        let found = findings(&mut scanner, &repo, update);
+        assert_eq!(found, [(PathBuf::from("notes.txt"), c4, 0)]);
+    }
+
+    #[test]
+    fn scan_download() {
+        let (td, repo) = crate::test::repo_init();
+        let (_, _, c4) = setup(&repo);
+        repo.reference("refs/heads/main", c4, true, "secrets")
+            .unwrap();
+
+        let td2 = TempDir::new().unwrap();
+        let local = Repository::init(td2.path()).unwrap();
+        let mut remote = local
+            .remote("origin", &crate::test::path2url(td.path()))
+            .unwrap();
+        remote.connect(Direction::Fetch).unwrap();
+        remote.download(&["refs/heads/main"], None).unwrap();
+        let mut scanner = ContentScanner::new(|chunk| Ok(check(chunk)));
+        let found = scanner.scan_download(&local, &remote).unwrap();
+        let paths = found.iter().map(|f| f.path()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [Path::new("config/secret.env"), Path::new("notes.txt")]
+        );
+        assert!(local.find_reference("refs/remotes/origin/main").is_err());
+    }
+
+    #[test]
+    fn enforce_on_push() {
+        let (_td, repo) = crate::test::repo_init();
+        let (_, _, c4) = setup(&repo);
+        let td2 = TempDir::new().unwrap();
+        let mut opts = crate::RepositoryInitOptions::new();
+        opts.bare(true);
+        Repository::init_opts(td2.path(), &opts).unwrap();
+        let mut remote = repo
+            .remote("origin", &crate::test::path2url(td2.path()))
+            .unwrap();
+
+        let mut scanner = ContentScanner::new(|chunk| Ok(check(chunk)));
+        let mut callbacks = RemoteCallbacks::new();
+        scanner.enforce(&repo, &mut callbacks);
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        repo.reference("refs/heads/main", c4, true, "secrets")
+            .unwrap();
+        let err = remote
+            .push(&["refs/heads/main"], Some(&mut options))
+            .err()
+            .unwrap();
+        assert!(err.message().contains("push rejected by content scan"));
+        assert!(err.message().contains("config/secret.env: password"));
+
+        let remote_repo = Repository::open(td2.path()).unwrap();
+        assert!(remote_repo.find_reference("refs/heads/main").is_err());
+    }
+}
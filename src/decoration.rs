@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use crate::{Error, ObjectType, Oid, Reference, Repository};
+
+/// The references pointing at each object of a repository, such as the
+/// branches and tags `git log --decorate` shows next to commits.
+///
+/// Created with `Repository::decorations`, which reads every reference once,
+/// so that a log view can look up the decorations of each commit it shows.
+#[derive(Debug, Clone, Default)]
+pub struct Decorations {
+    objects: HashMap<Oid, Vec<Decoration>>,
+    head_branch: Option<String>,
+}
+
+/// A reference pointing at an object, as part of `Decorations`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoration {
+    kind: DecorationKind,
+    name: String,
+}
+
+/// The kinds of references a `Decoration` can be, in the order in which
+/// they are listed for an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DecorationKind {
+    /// `HEAD`.
+    Head,
+    /// A branch under `refs/heads/`.
+    LocalBranch,
+    /// A remote-tracking branch under `refs/remotes/`.
+    RemoteBranch,
+    /// A tag under `refs/tags/`.
+    Tag,
+    /// Any other reference, such as `refs/stash`.
+    Other,
+}
+
+impl Decorations {
+    pub(crate) fn new(repo: &Repository, peel: bool) -> Result<Decorations, Error> {
+        let mut decorations = Decorations::default();
+        if let Ok(head) = repo.find_reference("HEAD") {
+            decorations.head_branch = head.symbolic_target().map(|s| s.to_string());
+            if let Some(id) = target(&head, peel) {
+                decorations.add(id, DecorationKind::Head, "HEAD");
+            }
+        }
+        for reference in repo.references()? {
+            let reference = reference?;
+            let name = match reference.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let kind = if reference.is_branch() {
+                DecorationKind::LocalBranch
+            } else if reference.is_remote() {
+                DecorationKind::RemoteBranch
+            } else if reference.is_tag() {
+                DecorationKind::Tag
+            } else {
+                DecorationKind::Other
+            };
+            if let Some(id) = target(&reference, peel) {
+                decorations.add(id, kind, name);
+            }
+        }
+        for list in decorations.objects.values_mut() {
+            list.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+        }
+        Ok(decorations)
+    }
+
+    fn add(&mut self, id: Oid, kind: DecorationKind, name: &str) {
+        self.objects.entry(id).or_default().push(Decoration {
+            kind,
+            name: name.to_string(),
+        });
+    }
+
+    /// Returns the references pointing at `id`, sorted by kind and name.
+    pub fn get(&self, id: Oid) -> &[Decoration] {
+        self.objects.get(&id).map(|list| &list[..]).unwrap_or(&[])
+    }
+
+    /// Returns every decorated object with its references.
+    pub fn iter(&self) -> impl Iterator<Item = (Oid, &[Decoration])> {
+        self.objects.iter().map(|(id, list)| (*id, &list[..]))
+    }
+
+    /// Returns the full name of the branch `HEAD` points to, or `None` if
+    /// `HEAD` is detached.
+    pub fn head_branch(&self) -> Option<&str> {
+        self.head_branch.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns the number of decorated objects.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Returns `true` if no object is decorated.
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl Decoration {
+    /// Returns the kind of the reference.
+    pub fn kind(&self) -> DecorationKind {
+        self.kind
+    }
+
+    /// Returns the full name of the reference, such as `refs/heads/main`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the name `git log --decorate` shows, such as `main`,
+    /// `origin/main` or `tag: v1.0`.
+    pub fn shorthand(&self) -> String {
+        let strip = |prefix: &str| self.name.get(prefix.len()..).unwrap_or("").to_string();
+        match self.kind {
+            DecorationKind::Head => self.name.clone(),
+            DecorationKind::LocalBranch => strip("refs/heads/"),
+            DecorationKind::RemoteBranch => strip("refs/remotes/"),
+            DecorationKind::Tag => format!("tag: {}", strip("refs/tags/")),
+            DecorationKind::Other => strip("refs/"),
+        }
+    }
+}
+
+/// Returns the object a reference points to, following symbolic references
+/// and, if `peel` is set, annotated tags down to their commit. Tags of
+/// other objects keep their own id.
+fn target(reference: &Reference<'_>, peel: bool) -> Option<Oid> {
+    let resolved = reference.resolve().ok()?;
+    if peel {
+        if let Ok(commit) = resolved.peel(ObjectType::Commit) {
+            return Some(commit.id());
+        }
+    }
+    resolved.target()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DecorationKind, Oid};
+
+    #[test]
+    fn decorations() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        let commit = repo.find_commit(head).unwrap();
+        let sig = repo.signature().unwrap();
+        let tag = repo
+            .tag("v1", commit.as_object(), &sig, "v1", false)
+            .unwrap();
+        repo.tag_lightweight("light", commit.as_object(), false)
+            .unwrap();
+        repo.reference("refs/remotes/origin/main", head, false, "fetch")
+            .unwrap();
+        repo.reference_symbolic(
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/main",
+            false,
+            "",
+        )
+        .unwrap();
+        repo.branch("other", &commit, false).unwrap();
+
+        let decorations = repo.decorations(true).unwrap();
+        assert_eq!(decorations.len(), 1);
+        assert_eq!(decorations.head_branch(), Some("refs/heads/main"));
+        let names = decorations
+            .get(head)
+            .iter()
+            .map(|d| d.shorthand())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "HEAD",
+                "main",
+                "other",
+                "origin/HEAD",
+                "origin/main",
+                "tag: light",
+                "tag: v1"
+            ]
+        );
+        assert_eq!(decorations.get(head)[0].kind(), DecorationKind::Head);
+        assert_eq!(decorations.get(head)[6].name(), "refs/tags/v1");
+        assert!(decorations.get(Oid::zero()).is_empty());
+
+        let decorations = repo.decorations(false).unwrap();
+        assert_eq!(decorations.len(), 2);
+        assert_eq!(decorations.get(tag).len(), 1);
+        assert_eq!(decorations.get(tag)[0].kind(), DecorationKind::Tag);
+        assert_eq!(decorations.get(head).len(), 6);
+    }
+}
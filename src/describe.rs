@@ -6,12 +6,13 @@ use std::ptr;
 use libc::{c_int, c_uint};
 
 use crate::util::Binding;
-use crate::{raw, Buf, Error, Repository};
+use crate::{raw, Buf, Error, Repository, Statuses};
 
 /// The result of a `describe` operation on either an `Describe` or a
 /// `Repository`.
 pub struct Describe<'repo> {
     raw: *mut raw::git_describe_result,
+    dirty: Option<bool>,
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
@@ -19,6 +20,7 @@ pub struct Describe<'repo> {
 pub struct DescribeOptions {
     raw: raw::git_describe_options,
     pattern: CString,
+    dirty_untracked: bool,
 }
 
 /// Options which can be used to customize how a description is formatted.
@@ -30,13 +32,50 @@ pub struct DescribeFormatOptions {
 impl<'repo> Describe<'repo> {
     /// Prints this describe result, returning the result as a string.
     pub fn format(&self, opts: Option<&DescribeFormatOptions>) -> Result<String, Error> {
+        let dirty = match self.dirty {
+            Some(dirty) => dirty,
+            None => return self.format_raw(opts.map(|o| &o.raw)),
+        };
+        // The dirtiness was decided here rather than by libgit2, so leave the
+        // suffix out of its output and append it ourselves.
+        let mut raw = DescribeFormatOptions::new().raw;
+        if let Some(opts) = opts {
+            raw.abbreviated_size = opts.raw.abbreviated_size;
+            raw.always_use_long_format = opts.raw.always_use_long_format;
+        }
+        let mut description = self.format_raw(Some(&raw))?;
+        if let (true, Some(opts)) = (dirty, opts) {
+            if !opts.raw.dirty_suffix.is_null() {
+                description.push_str(opts.dirty_suffix.to_str().unwrap());
+            }
+        }
+        Ok(description)
+    }
+
+    fn format_raw(&self, opts: Option<&raw::git_describe_format_options>) -> Result<String, Error> {
         let buf = Buf::new();
-        let opts = opts.map(|o| &o.raw as *const _).unwrap_or(ptr::null());
+        let opts = opts.map(|o| o as *const _).unwrap_or(ptr::null());
         unsafe {
             try_call!(raw::git_describe_format(buf.raw(), self.raw, opts));
         }
         Ok(String::from_utf8(buf.to_vec()).unwrap())
     }
+
+    /// Overrides whether the working directory is considered dirty.
+    pub(crate) fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = Some(dirty);
+    }
+}
+
+/// Returns whether `statuses` make a working directory dirty for `describe`.
+///
+/// Ignored entries never count, and untracked ones only if `untracked` is
+/// set, which is what `git describe --dirty` considers as well.
+pub(crate) fn is_dirty(statuses: &Statuses<'_>, untracked: bool) -> bool {
+    statuses.iter().any(|entry| {
+        let status = entry.status();
+        !status.is_empty() && !status.is_ignored() && (untracked || !status.is_wt_new())
+    })
 }
 
 impl<'repo> Binding for Describe<'repo> {
@@ -45,6 +84,7 @@ impl<'repo> Binding for Describe<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_describe_result) -> Describe<'repo> {
         Describe {
             raw: raw,
+            dirty: None,
             _marker: marker::PhantomData,
         }
     }
@@ -114,6 +154,7 @@ impl DescribeOptions {
         let mut opts = DescribeOptions {
             raw: unsafe { mem::zeroed() },
             pattern: CString::new(Vec::new()).unwrap(),
+            dirty_untracked: false,
         };
         opts.raw.version = 1;
         opts.raw.max_candidates_tags = 10;
@@ -163,6 +204,21 @@ impl DescribeOptions {
         self.raw.pattern = self.pattern.as_ptr();
         self
     }
+
+    /// Sets whether untracked files which are not ignored make the working
+    /// directory dirty when describing it with `Repository::describe` or
+    /// `Repository::describe_with_statuses`.
+    ///
+    /// By default only changes to tracked files do, as with
+    /// `git describe --dirty`.
+    pub fn dirty_untracked(&mut self, untracked: bool) -> &mut Self {
+        self.dirty_untracked = untracked;
+        self
+    }
+
+    pub(crate) fn is_dirty_untracked(&self) -> bool {
+        self.dirty_untracked
+    }
 }
 
 impl Binding for DescribeOptions {
@@ -178,7 +234,9 @@ impl Binding for DescribeOptions {
 
 #[cfg(test)]
 mod tests {
-    use crate::DescribeOptions;
+    use std::fs;
+
+    use crate::{DescribeFormatOptions, DescribeOptions, StatusOptions};
 
     #[test]
     fn smoke() {
@@ -198,4 +256,42 @@ mod tests {
         let d = t!(obj.describe(&DescribeOptions::new()));
         assert_eq!(t!(d.format(None)), "foo");
     }
+
+    #[test]
+    fn dirty_untracked() {
+        let (td, repo) = crate::test::repo_init();
+        let head = t!(repo.head()).target().unwrap();
+        let obj = t!(repo.find_object(head, None));
+        let sig = t!(repo.signature());
+        t!(repo.tag("v1", &obj, &sig, "message", true));
+        t!(fs::write(td.path().join("untracked"), "x"));
+
+        let mut format = DescribeFormatOptions::new();
+        format.dirty_suffix("-dirty");
+        let mut opts = DescribeOptions::new();
+        let d = t!(repo.describe(&opts));
+        assert_eq!(t!(d.format(Some(&format))), "v1");
+
+        opts.dirty_untracked(true);
+        let d = t!(repo.describe(&opts));
+        assert_eq!(t!(d.format(Some(&format))), "v1-dirty");
+        assert_eq!(t!(d.format(None)), "v1");
+
+        let mut status_opts = StatusOptions::new();
+        status_opts.include_untracked(true);
+        let statuses = t!(repo.statuses(Some(&mut status_opts)));
+        let d = t!(repo.describe_with_statuses(&opts, &statuses));
+        assert_eq!(t!(d.format(Some(&format))), "v1-dirty");
+        opts.dirty_untracked(false);
+        let d = t!(repo.describe_with_statuses(&opts, &statuses));
+        assert_eq!(t!(d.format(Some(&format))), "v1");
+
+        t!(fs::write(
+            td.path().join(".gitignore"),
+            "untracked\n.gitignore\n"
+        ));
+        opts.dirty_untracked(true);
+        let d = t!(repo.describe(&opts));
+        assert_eq!(t!(d.format(Some(&format))), "v1");
+    }
 }
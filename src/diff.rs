@@ -1,4 +1,5 @@
 use libc::{c_char, c_int, c_void, size_t};
+use std::cell::RefCell;
 use std::ffi::CString;
 use std::marker;
 use std::mem;
@@ -7,9 +8,12 @@ use std::path::Path;
 use std::ptr;
 use std::slice;
 
+use crate::function_context::{self, Pending};
 use crate::util::{self, Binding};
-use crate::{panic, raw, Buf, Delta, DiffFormat, Error, FileMode, Oid, Repository};
-use crate::{DiffFlags, DiffStatsFormat, IntoCString};
+use crate::StructuredDiff;
+use crate::SubmoduleChange;
+use crate::{panic, raw, Blob, Buf, Delta, DiffFormat, Error, FileMode, Oid, Repository};
+use crate::{DiffFlags, DiffStatsFormat, IntoCString, MovedLines, Patch, Pickaxe};
 
 /// The diff object that contains all individual file deltas.
 ///
@@ -18,6 +22,8 @@ use crate::{DiffFlags, DiffStatsFormat, IntoCString};
 /// or other `diff_*` functions).
 pub struct Diff<'repo> {
     raw: *mut raw::git_diff,
+    repo: *mut raw::git_repository,
+    function_context: Option<u32>,
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
@@ -45,14 +51,36 @@ pub struct DiffOptions {
     pathspec_ptrs: Vec<*const c_char>,
     old_prefix: Option<CString>,
     new_prefix: Option<CString>,
+    function_context: Option<u32>,
     raw: raw::git_diff_options,
 }
 
+/// The settings of a diff driver, registered with
+/// `Repository::register_diff_driver`.
+///
+/// Paths are assigned to a driver with a `diff=<driver>` entry in
+/// `.gitattributes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffDriver {
+    xfuncname: Option<String>,
+    binary: Option<bool>,
+    textconv: Option<String>,
+}
+
 /// Control behavior of rename and copy detection
 pub struct DiffFindOptions {
     raw: raw::git_diff_find_options,
 }
 
+/// What `Diff::find_similar_with_stats` did to a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffFindStats {
+    rename_limit: usize,
+    rename_limit_exceeded: bool,
+    renames: usize,
+    copies: usize,
+}
+
 /// Control behavior of formatting emails
 pub struct DiffFormatEmailOptions {
     raw: raw::git_diff_format_email_options,
@@ -84,6 +112,26 @@ pub struct DiffHunk<'a> {
 /// Structure describing a hunk of a diff.
 pub struct DiffStats {
     raw: *mut raw::git_diff_stats,
+    files: Vec<DiffFileStats>,
+}
+
+/// The number of lines a diff changes in a single file, as printed by
+/// `git diff --numstat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFileStats {
+    old_path: Option<Vec<u8>>,
+    new_path: Option<Vec<u8>>,
+    insertions: usize,
+    deletions: usize,
+    binary: bool,
+}
+
+/// The totals of a diff, as printed by `git diff --shortstat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffShortStat {
+    files_changed: usize,
+    insertions: usize,
+    deletions: usize,
 }
 
 /// Structure describing the binary contents of a diff.
@@ -98,11 +146,46 @@ pub struct DiffBinaryFile<'a> {
     _marker: marker::PhantomData<&'a raw::git_diff_binary_file>,
 }
 
+/// An owned copy of the binary data of a delta, as returned by
+/// `Diff::binary`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryPatch {
+    contains_data: bool,
+    old_file: BinaryPatchFile,
+    new_file: BinaryPatchFile,
+}
+
+/// An owned copy of the binary data of one side of a delta.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinaryPatchFile {
+    kind: DiffBinaryKind,
+    data: Vec<u8>,
+    inflated_len: usize,
+}
+
+/// The contents of both sides of a file in a diff, as passed to the
+/// callbacks of `Diff::print_with_content` and `Diff::foreach_with_content`.
+///
+/// Contents are read from the object database where possible, which does
+/// not copy them, and from the working directory otherwise. A side which
+/// does not exist is empty.
+pub struct DiffContent<'repo> {
+    old: DiffContentSource<'repo>,
+    new: DiffContentSource<'repo>,
+    old_lines: Vec<usize>,
+    new_lines: Vec<usize>,
+}
+
+enum DiffContentSource<'repo> {
+    Blob(Blob<'repo>),
+    Data(Vec<u8>),
+}
+
 /// When producing a binary diff, the binary data returned will be
 /// either the deflated full ("literal") contents of the file, or
 /// the deflated binary delta between the two sides (whichever is
 /// smaller).
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DiffBinaryKind {
     /// There is no binary delta
     None,
@@ -118,6 +201,8 @@ pub type FileCb<'a> = dyn FnMut(DiffDelta<'_>, f32) -> bool + 'a;
 pub type BinaryCb<'a> = dyn FnMut(DiffDelta<'_>, DiffBinary<'_>) -> bool + 'a;
 pub type HunkCb<'a> = dyn FnMut(DiffDelta<'_>, DiffHunk<'_>) -> bool + 'a;
 pub type LineCb<'a> = dyn FnMut(DiffDelta<'_>, Option<DiffHunk<'_>>, DiffLine<'_>) -> bool + 'a;
+pub type ContentLineCb<'a> =
+    dyn FnMut(DiffDelta<'_>, Option<DiffHunk<'_>>, DiffLine<'_>, &DiffContent<'_>) -> bool + 'a;
 
 pub struct DiffCallbacks<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h> {
     pub file: Option<&'a mut FileCb<'b>>,
@@ -142,6 +227,14 @@ impl<'repo> Diff<'repo> {
         Ok(())
     }
 
+    pub(crate) fn set_function_context(&mut self, context: Option<u32>) {
+        self.function_context = context;
+    }
+
+    pub(crate) fn set_repo(&mut self, repo: &'repo Repository) {
+        self.repo = repo.raw();
+    }
+
     /// Returns an iterator over the deltas in this diff.
     pub fn deltas(&self) -> Deltas<'_> {
         let num_deltas = unsafe { raw::git_diff_num_deltas(&*self.raw) };
@@ -172,7 +265,13 @@ impl<'repo> Diff<'repo> {
     where
         F: FnMut(DiffDelta<'_>, Option<DiffHunk<'_>>, DiffLine<'_>) -> bool,
     {
-        let mut cb: &mut PrintCb<'_> = &mut cb;
+        match self.function_context {
+            Some(context) => self.print_function_context(format, context, &mut cb),
+            None => self.print_raw(format, &mut cb),
+        }
+    }
+
+    fn print_raw(&self, format: DiffFormat, mut cb: &mut PrintCb<'_>) -> Result<(), Error> {
         let ptr = &mut cb as *mut _;
         let print: raw::git_diff_line_cb = Some(print_cb);
         unsafe {
@@ -181,6 +280,100 @@ impl<'repo> Diff<'repo> {
         }
     }
 
+    fn print_function_context(
+        &self,
+        format: DiffFormat,
+        context: u32,
+        cb: &mut PrintCb<'_>,
+    ) -> Result<(), Error> {
+        let mut pending = Pending::new(context);
+        self.print_raw(
+            format,
+            &mut |delta, hunk, line| match (hunk, line.origin()) {
+                (Some(_), 'H') => true,
+                (Some(_), _) => {
+                    pending.push(&delta, &line);
+                    true
+                }
+                (None, _) => {
+                    pending.flush(&mut |delta, hunk, line| print_trimmed(cb, delta, hunk, line))
+                        && cb(delta, None, line)
+                }
+            },
+        )?;
+        if pending.flush(&mut |delta, hunk, line| print_trimmed(cb, delta, hunk, line)) {
+            Ok(())
+        } else {
+            Err(function_context::callback_error("git_diff_print"))
+        }
+    }
+
+    /// Like `print`, but also passes the contents of both sides of the
+    /// current file to the callback.
+    ///
+    /// This lets the callback slice lines out of the original contents, e.g.
+    /// with `DiffContent::old_range`, instead of copying each line. The
+    /// contents are loaded once per file, from `repo`'s object database or
+    /// working directory.
+    pub fn print_with_content<F>(
+        &self,
+        repo: &Repository,
+        format: DiffFormat,
+        mut cb: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(DiffDelta<'_>, Option<DiffHunk<'_>>, DiffLine<'_>, &DiffContent<'_>) -> bool,
+    {
+        let mut loaded = None;
+        let mut error = None;
+        let res = self.print(format, |delta, hunk, line| {
+            match DiffContent::for_delta(repo, &mut loaded, &delta) {
+                Ok(content) => cb(delta, hunk, line, content),
+                Err(e) => {
+                    error = Some(e);
+                    false
+                }
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => res,
+        }
+    }
+
+    /// Loop over all lines in a diff, passing the contents of both sides of
+    /// the current file to the callback along with each line.
+    ///
+    /// See `print_with_content` for how contents are loaded. Returning
+    /// `false` from the callback will terminate the iteration and return an
+    /// error from this function.
+    pub fn foreach_with_content(
+        &self,
+        repo: &Repository,
+        line_cb: &mut ContentLineCb<'_>,
+    ) -> Result<(), Error> {
+        let mut loaded = None;
+        let mut error = None;
+        let res = self.foreach(
+            &mut |_, _| true,
+            None,
+            None,
+            Some(
+                &mut |delta, hunk, line| match DiffContent::for_delta(repo, &mut loaded, &delta) {
+                    Ok(content) => line_cb(delta, hunk, line, content),
+                    Err(e) => {
+                        error = Some(e);
+                        false
+                    }
+                },
+            ),
+        );
+        match error {
+            Some(e) => Err(e),
+            None => res,
+        }
+    }
+
     /// Loop over all deltas in a diff issuing callbacks.
     ///
     /// Returning `false` from any callback will terminate the iteration and
@@ -191,6 +384,52 @@ impl<'repo> Diff<'repo> {
         binary_cb: Option<&mut BinaryCb<'_>>,
         hunk_cb: Option<&mut HunkCb<'_>>,
         line_cb: Option<&mut LineCb<'_>>,
+    ) -> Result<(), Error> {
+        match self.function_context {
+            Some(context) if hunk_cb.is_some() || line_cb.is_some() => {
+                self.foreach_function_context(context, file_cb, binary_cb, hunk_cb, line_cb)
+            }
+            _ => self.foreach_raw(file_cb, binary_cb, hunk_cb, line_cb),
+        }
+    }
+
+    fn foreach_function_context(
+        &self,
+        context: u32,
+        file_cb: &mut FileCb<'_>,
+        binary_cb: Option<&mut BinaryCb<'_>>,
+        mut hunk_cb: Option<&mut HunkCb<'_>>,
+        mut line_cb: Option<&mut LineCb<'_>>,
+    ) -> Result<(), Error> {
+        let pending = RefCell::new(Pending::new(context));
+        let emit: &mut dyn FnMut(DiffDelta<'_>, DiffHunk<'_>, Option<DiffLine<'_>>) -> bool =
+            &mut |delta, hunk, line| match (line, &mut hunk_cb, &mut line_cb) {
+                (Some(line), _, Some(cb)) => cb(delta, Some(hunk), line),
+                (None, Some(cb), _) => cb(delta, hunk),
+                _ => true,
+            };
+        self.foreach_raw(
+            &mut |delta, progress| pending.borrow_mut().flush(emit) && file_cb(delta, progress),
+            binary_cb,
+            None,
+            Some(&mut |delta, _hunk, line| {
+                pending.borrow_mut().push(&delta, &line);
+                true
+            }),
+        )?;
+        if pending.borrow_mut().flush(emit) {
+            Ok(())
+        } else {
+            Err(function_context::callback_error("git_diff_foreach"))
+        }
+    }
+
+    fn foreach_raw(
+        &self,
+        file_cb: &mut FileCb<'_>,
+        binary_cb: Option<&mut BinaryCb<'_>>,
+        hunk_cb: Option<&mut HunkCb<'_>>,
+        line_cb: Option<&mut LineCb<'_>>,
     ) -> Result<(), Error> {
         let mut cbs = DiffCallbacks {
             file: Some(file_cb),
@@ -228,13 +467,107 @@ impl<'repo> Diff<'repo> {
         }
     }
 
+    /// Find the deltas whose changes match a pickaxe search, like
+    /// `git diff -S` and `git diff -G`.
+    ///
+    /// Returns the indices of the matching deltas.
+    pub fn pickaxe(&self, pickaxe: &mut Pickaxe<'_>) -> Result<Vec<usize>, Error> {
+        let mut found = Vec::new();
+        for idx in 0..self.deltas().len() {
+            if pickaxe.matches_delta(self, idx)? {
+                found.push(idx);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Generate the patches of all deltas of this diff, giving access to
+    /// their hunks and lines.
+    ///
+    /// Deltas for which no patch can be generated, such as unmodified files,
+    /// are skipped.
+    pub fn patches(&self) -> Result<Vec<Patch<'repo>>, Error> {
+        let mut patches = Vec::new();
+        for idx in 0..self.deltas().len() {
+            if let Some(patch) = Patch::from_diff(self, idx)? {
+                patches.push(patch);
+            }
+        }
+        Ok(patches)
+    }
+
+    /// Find the blocks of removed and added lines which are moves of each
+    /// other, within a file or across files, like `git diff --color-moved`.
+    ///
+    /// Lines are matched by their exact content, and a block only counts as
+    /// moved if it contains at least 20 alphanumeric characters.
+    pub fn moved_lines(&self) -> Result<MovedLines, Error> {
+        MovedLines::new(self)
+    }
+
+    /// Convert this diff to an owned representation of its files, hunks and
+    /// lines.
+    ///
+    /// With the `serde` feature enabled the result can be serialized, e.g.
+    /// to JSON, which saves consumers from parsing the unified diff text.
+    pub fn to_structured(&self) -> Result<StructuredDiff, Error> {
+        StructuredDiff::from_diff(self)
+    }
+
+    /// Get the binary data of the delta at index `idx`.
+    ///
+    /// Returns `None` if the delta's files are not binary. The literal or
+    /// delta payloads are only included if the diff was generated with
+    /// `DiffOptions::show_binary`, or parsed from a patch containing them.
+    pub fn binary(&self, idx: usize) -> Result<Option<BinaryPatch>, Error> {
+        let target = match self.get_delta(idx) {
+            Some(delta) => delta.raw,
+            None => return Err(Error::from_str("delta index out of range")),
+        };
+        let mut found = None;
+        self.foreach(
+            &mut |_, _| true,
+            Some(&mut |delta, binary| {
+                if delta.raw == target {
+                    found = Some(BinaryPatch {
+                        contains_data: binary.contains_data(),
+                        old_file: BinaryPatchFile::new(&binary.old_file()),
+                        new_file: BinaryPatchFile::new(&binary.new_file()),
+                    });
+                }
+                true
+            }),
+            None,
+            None,
+        )?;
+        Ok(found)
+    }
+
     /// Accumulate diff statistics for all patches.
     pub fn stats(&self) -> Result<DiffStats, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let mut stats: DiffStats = unsafe {
             try_call!(raw::git_diff_get_stats(&mut ret, self.raw));
-            Ok(Binding::from_raw(ret))
+            Binding::from_raw(ret)
+        };
+        for (idx, delta) in self.deltas().enumerate() {
+            let (insertions, deletions, binary) = match Patch::from_diff(self, idx)? {
+                Some(ref patch) if !patch.delta().flags().is_binary() => {
+                    let (_, insertions, deletions) = patch.line_stats()?;
+                    (insertions, deletions, false)
+                }
+                Some(_) => (0, 0, true),
+                None => (0, 0, delta.flags().is_binary()),
+            };
+            stats.files.push(DiffFileStats {
+                old_path: delta.old_file().path_bytes().map(|p| p.to_vec()),
+                new_path: delta.new_file().path_bytes().map(|p| p.to_vec()),
+                insertions,
+                deletions,
+                binary,
+            });
         }
+        Ok(stats)
     }
 
     /// Transform a diff marking file renames, copies, etc.
@@ -251,6 +584,127 @@ impl<'repo> Diff<'repo> {
         Ok(())
     }
 
+    /// Same as `find_similar`, but also reports how many renames and copies
+    /// were found, which rename limit was used and whether it was exceeded.
+    ///
+    /// For a diff generated from a repository, the `diff.renames` and
+    /// `diff.renameLimit` config is taken into account the same way libgit2
+    /// does.
+    pub fn find_similar_with_stats(
+        &mut self,
+        opts: Option<&mut DiffFindOptions>,
+    ) -> Result<DiffFindStats, Error> {
+        let config = if self.repo.is_null() {
+            None
+        } else {
+            let mut raw = ptr::null_mut();
+            unsafe {
+                try_call!(raw::git_repository_config(&mut raw, self.repo));
+                Some(crate::Config::from_raw(raw))
+            }
+        };
+        let (flags, rename_limit) = {
+            let given = opts.as_ref().map(|opts| &opts.raw);
+            let mut flags = given.map_or(0, |opts| opts.flags);
+            if flags & raw::GIT_DIFF_FIND_ALL == raw::GIT_DIFF_FIND_BY_CONFIG {
+                let rule = config
+                    .as_ref()
+                    .and_then(|config| config.get_string("diff.renames").ok());
+                flags |= match rule.as_deref() {
+                    None => raw::GIT_DIFF_FIND_RENAMES,
+                    Some("copies") | Some("copy") => {
+                        raw::GIT_DIFF_FIND_RENAMES | raw::GIT_DIFF_FIND_COPIES
+                    }
+                    Some(rule) => match crate::Config::parse_bool(rule) {
+                        Ok(false) => 0,
+                        _ => raw::GIT_DIFF_FIND_RENAMES,
+                    },
+                };
+            }
+            if flags & raw::GIT_DIFF_FIND_EXACT_MATCH_ONLY != 0 {
+                flags &= !(raw::GIT_DIFF_FIND_REWRITES
+                    | raw::GIT_DIFF_BREAK_REWRITES
+                    | raw::GIT_DIFF_FIND_RENAMES_FROM_REWRITES);
+            }
+            if flags & raw::GIT_DIFF_FIND_RENAMES_FROM_REWRITES != 0 {
+                flags |= raw::GIT_DIFF_FIND_RENAMES;
+            }
+            if flags & raw::GIT_DIFF_FIND_COPIES_FROM_UNMODIFIED != 0 {
+                flags |= raw::GIT_DIFF_FIND_COPIES;
+            }
+            if flags & raw::GIT_DIFF_BREAK_REWRITES != 0 {
+                flags |= raw::GIT_DIFF_FIND_REWRITES;
+            }
+
+            let mut rename_limit = given.map_or(0, |opts| opts.rename_limit) as i64;
+            if rename_limit == 0 {
+                rename_limit = config
+                    .as_ref()
+                    .and_then(|config| config.get_i64("diff.renameLimit").ok())
+                    .unwrap_or(0);
+            }
+            if rename_limit <= 0 {
+                rename_limit = 200;
+            }
+            (flags, rename_limit as usize)
+        };
+
+        // libgit2 compares each target with at most `rename_limit + 1`
+        // sources and silently skips the rest, so count the candidates
+        // beforehand. Whether a modified file is a candidate for rewrite
+        // detection depends on its similarity with itself, which is only
+        // known afterwards, so such files are always counted.
+        let rewrites =
+            flags & (raw::GIT_DIFF_FIND_REWRITES | raw::GIT_DIFF_FIND_RENAMES_FROM_REWRITES) != 0;
+        let (mut sources, mut targets) = (0, 0);
+        for delta in self.deltas() {
+            let is_blob = |mode: u16| u32::from(mode) & 0o170000 == 0o100000;
+            let (old_mode, new_mode) = unsafe {
+                let raw = &*delta.raw;
+                (raw.old_file.mode, raw.new_file.mode)
+            };
+            let status = delta.status();
+            let source = is_blob(old_mode)
+                && match status {
+                    Delta::Deleted | Delta::Typechange => true,
+                    Delta::Unmodified => flags & raw::GIT_DIFF_FIND_COPIES_FROM_UNMODIFIED != 0,
+                    Delta::Modified | Delta::Renamed | Delta::Copied => {
+                        flags & raw::GIT_DIFF_FIND_COPIES != 0 || rewrites
+                    }
+                    _ => false,
+                };
+            let target = is_blob(new_mode)
+                && match status {
+                    Delta::Unmodified | Delta::Deleted | Delta::Ignored | Delta::Conflicted => {
+                        false
+                    }
+                    Delta::Modified => rewrites,
+                    Delta::Untracked => flags & raw::GIT_DIFF_FIND_FOR_UNTRACKED != 0,
+                    _ => true,
+                };
+            sources += source as usize;
+            targets += target as usize;
+        }
+
+        self.find_similar(opts)?;
+        let mut stats = DiffFindStats {
+            rename_limit,
+            rename_limit_exceeded: flags & raw::GIT_DIFF_FIND_ALL != 0
+                && targets > 0
+                && sources > rename_limit + 1,
+            renames: 0,
+            copies: 0,
+        };
+        for delta in self.deltas() {
+            match delta.status() {
+                Delta::Renamed => stats.renames += 1,
+                Delta::Copied => stats.copies += 1,
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+
     /// Create an e-mail ready patch from a diff.
     ///
     /// Matches the format created by `git format-patch`
@@ -301,6 +755,16 @@ impl<'repo> Diff<'repo> {
     // TODO: num_deltas_of_type, find_similar
 }
 impl Diff<'static> {
+    /// Parse the text of a unified diff, such as the output of `git diff` or
+    /// `git format-patch`, into a `Diff`.
+    ///
+    /// The parsed hunks and lines can be inspected through `patches`, and
+    /// the diff can be applied with `Repository::apply`. Fails if the text
+    /// contains no patch.
+    pub fn from_patch_text(text: &[u8]) -> Result<Diff<'static>, Error> {
+        Diff::from_buffer(text)
+    }
+
     /// Read the contents of a git patch file into a `git_diff` object.
     ///
     /// The diff object produced is similar to the one that would be
@@ -322,6 +786,32 @@ impl Diff<'static> {
     }
 }
 
+/// Passes a line of a delta trimmed to function context to a `print`
+/// callback, printing a header line at the start of each hunk.
+fn print_trimmed(
+    cb: &mut PrintCb<'_>,
+    delta: DiffDelta<'_>,
+    hunk: DiffHunk<'_>,
+    line: Option<DiffLine<'_>>,
+) -> bool {
+    match line {
+        Some(line) => cb(delta, Some(hunk), line),
+        None => {
+            let header = hunk.header();
+            let line = raw::git_diff_line {
+                origin: raw::GIT_DIFF_LINE_HUNK_HDR as c_char,
+                old_lineno: -1,
+                new_lineno: -1,
+                num_lines: 1,
+                content_len: header.len(),
+                content_offset: -1,
+                content: header.as_ptr() as *const c_char,
+            };
+            cb(delta, Some(hunk), unsafe { DiffLine::from_raw(&line) })
+        }
+    }
+}
+
 pub extern "C" fn print_cb(
     delta: *const raw::git_diff_delta,
     hunk: *const raw::git_diff_hunk,
@@ -447,6 +937,8 @@ impl<'repo> Binding for Diff<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_diff) -> Diff<'repo> {
         Diff {
             raw: raw,
+            repo: ptr::null_mut(),
+            function_context: None,
             _marker: marker::PhantomData,
         }
     }
@@ -493,10 +985,13 @@ impl<'a> DiffDelta<'a> {
         result
     }
 
-    // TODO: expose when diffs are more exposed
-    // pub fn similarity(&self) -> u16 {
-    //     unsafe { (*self.raw).similarity }
-    // }
+    /// Returns the similarity score of a renamed or copied entry, between 0
+    /// and 100.
+    ///
+    /// This is only meaningful when rename or copy detection has been run.
+    pub fn similarity(&self) -> u16 {
+        unsafe { (*self.raw).similarity }
+    }
 
     /// Returns the number of files in this delta.
     pub fn nfiles(&self) -> u16 {
@@ -538,6 +1033,42 @@ impl<'a> DiffDelta<'a> {
     pub fn new_file(&self) -> DiffFile<'a> {
         unsafe { Binding::from_raw(&(*self.raw).new_file as *const _) }
     }
+
+    /// If this delta is a submodule, returns the commits recorded for it on
+    /// either side.
+    ///
+    /// Returns `None` if neither side of the delta is a submodule.
+    pub fn submodule_change(&self) -> Option<SubmoduleChange> {
+        let (old, new) = (self.old_file(), self.new_file());
+        if old.mode() != FileMode::Commit && new.mode() != FileMode::Commit {
+            return None;
+        }
+        let commit = |file: &DiffFile<'_>| {
+            if file.mode() == FileMode::Commit && !file.id().is_zero() {
+                Some(file.id())
+            } else {
+                None
+            }
+        };
+        let path = new.path().or_else(|| old.path())?.to_path_buf();
+        Some(SubmoduleChange::new(path, commit(&old), commit(&new)))
+    }
+
+    /// Like `submodule_change`, but also summarizes the change from the
+    /// submodule's repository in `repo`: the number of commits added and
+    /// removed, and whether its working directory is dirty.
+    ///
+    /// The summary is left out if the submodule is not checked out or does
+    /// not contain the commits.
+    pub fn submodule_summary(&self, repo: &Repository) -> Result<Option<SubmoduleChange>, Error> {
+        match self.submodule_change() {
+            Some(mut change) => {
+                change.summarize(repo)?;
+                Ok(Some(change))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 impl<'a> Binding for DiffDelta<'a> {
@@ -573,6 +1104,12 @@ impl<'a> DiffFile<'a> {
         unsafe { Binding::from_raw(&(*self.raw).id as *const _) }
     }
 
+    /// Returns the number of leading hex digits of `id` which are known, as
+    /// a diff parsed from a patch may only record abbreviated ids.
+    pub(crate) fn id_abbrev(&self) -> usize {
+        unsafe { (*self.raw).id_abbrev as usize }
+    }
+
     /// Returns the path, in bytes, of the entry relative to the working
     /// directory of the repository.
     pub fn path_bytes(&self) -> Option<&'a [u8]> {
@@ -658,6 +1195,56 @@ impl Default for DiffOptions {
     }
 }
 
+impl DiffDriver {
+    /// Creates a driver which leaves every setting to its default.
+    pub fn new() -> DiffDriver {
+        DiffDriver::default()
+    }
+
+    /// Set the pattern matching the lines shown in hunk headers, stored as
+    /// `diff.<driver>.xfuncname`.
+    ///
+    /// The nearest line preceding a hunk which matches the pattern (or its
+    /// first capture group) is shown after the hunk's line numbers.
+    pub fn xfuncname(&mut self, pattern: &str) -> &mut DiffDriver {
+        self.xfuncname = Some(pattern.to_string());
+        self
+    }
+
+    /// Set whether files are always treated as binary (or always as text),
+    /// stored as `diff.<driver>.binary`.
+    pub fn binary(&mut self, binary: bool) -> &mut DiffDriver {
+        self.binary = Some(binary);
+        self
+    }
+
+    /// Set the command converting files to text before they are compared,
+    /// stored as `diff.<driver>.textconv`.
+    ///
+    /// The command is run by the shell with the path of a temporary file
+    /// holding the contents as its argument, and prints the text to compare.
+    /// Only `Repository::textconv` and `Repository::textconv_patch` apply
+    /// it; diffs computed by libgit2 compare the original contents.
+    pub fn textconv(&mut self, command: &str) -> &mut DiffDriver {
+        self.textconv = Some(command.to_string());
+        self
+    }
+
+    pub(crate) fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = Vec::new();
+        if let Some(ref pattern) = self.xfuncname {
+            entries.push(("xfuncname", pattern.clone()));
+        }
+        if let Some(binary) = self.binary {
+            entries.push(("binary", binary.to_string()));
+        }
+        if let Some(ref command) = self.textconv {
+            entries.push(("textconv", command.clone()));
+        }
+        entries
+    }
+}
+
 impl DiffOptions {
     /// Creates a new set of empty diff options.
     ///
@@ -670,6 +1257,7 @@ impl DiffOptions {
             raw: unsafe { mem::zeroed() },
             old_prefix: None,
             new_prefix: None,
+            function_context: None,
         };
         assert_eq!(unsafe { raw::git_diff_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -731,6 +1319,9 @@ impl DiffOptions {
     }
 
     /// Flag indicating whether file mode changes are ignored.
+    ///
+    /// This only affects comparisons against the working directory, where
+    /// mode changes are also ignored when `core.filemode` is disabled.
     pub fn ignore_filemode(&mut self, ignore: bool) -> &mut DiffOptions {
         self.flag(raw::GIT_DIFF_IGNORE_FILEMODE, ignore)
     }
@@ -858,10 +1449,45 @@ impl DiffOptions {
     ///
     /// The default value for this is 3.
     pub fn context_lines(&mut self, lines: u32) -> &mut DiffOptions {
-        self.raw.context_lines = lines;
+        match self.function_context {
+            Some(ref mut context) => *context = lines,
+            None => self.raw.context_lines = lines,
+        }
         self
     }
 
+    /// Show the whole function around each change as context, like
+    /// `git diff -W`, in addition to the usual context lines.
+    ///
+    /// A function starts at the closest line before the change which starts
+    /// with a letter, `_` or `$`, the rule git uses when no `xfuncname`
+    /// pattern is configured, and ends before the next such line. The
+    /// trimmed hunks are produced by `Diff::print` and `Diff::foreach`, and
+    /// everything built on them; other consumers of the diff, such as
+    /// `Patch::from_diff`, see the whole file as a single hunk.
+    ///
+    /// Defaults to false.
+    pub fn function_context(&mut self, enabled: bool) -> &mut DiffOptions {
+        match (enabled, self.function_context) {
+            (true, None) => {
+                self.function_context = Some(self.raw.context_lines);
+                self.raw.context_lines = function_context::WHOLE_FILE;
+            }
+            (false, Some(context)) => {
+                self.raw.context_lines = context;
+                self.function_context = None;
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the number of context lines to keep around changes besides
+    /// their functions, if function context is enabled.
+    pub(crate) fn function_context_lines(&self) -> Option<u32> {
+        self.function_context
+    }
+
     /// Set the maximum number of unchanged lines between hunk boundaries before
     /// the hunks will be merged into one.
     ///
@@ -1006,7 +1632,130 @@ impl Binding for DiffLineType {
     }
 }
 
+impl<'repo> DiffContent<'repo> {
+    /// Returns the content of `delta`, reusing `loaded` if it is for the
+    /// same delta.
+    fn for_delta<'a>(
+        repo: &'repo Repository,
+        loaded: &'a mut Option<(*const raw::git_diff_delta, DiffContent<'repo>)>,
+        delta: &DiffDelta<'_>,
+    ) -> Result<&'a DiffContent<'repo>, Error> {
+        let current = match *loaded {
+            Some((raw, _)) => ptr::eq(raw, delta.raw),
+            None => false,
+        };
+        if !current {
+            let old = DiffContentSource::load(repo, &delta.old_file())?;
+            let new = DiffContentSource::load(repo, &delta.new_file())?;
+            let content = DiffContent {
+                old_lines: line_starts(old.bytes()),
+                new_lines: line_starts(new.bytes()),
+                old,
+                new,
+            };
+            *loaded = Some((delta.raw as *const _, content));
+        }
+        Ok(&loaded.as_ref().unwrap().1)
+    }
+
+    /// Returns the contents of the old side of the file.
+    pub fn old_content(&self) -> &[u8] {
+        self.old.bytes()
+    }
+
+    /// Returns the contents of the new side of the file.
+    pub fn new_content(&self) -> &[u8] {
+        self.new.bytes()
+    }
+
+    /// Returns the byte range of `line` in the old contents, or `None` if
+    /// the line is not part of the old file, such as an added line.
+    pub fn old_range(&self, line: &DiffLine<'_>) -> Option<Range<usize>> {
+        line_range(&self.old_lines, self.old_content().len(), line.old_lineno())
+    }
+
+    /// Returns the byte range of `line` in the new contents, or `None` if
+    /// the line is not part of the new file, such as a removed line.
+    pub fn new_range(&self, line: &DiffLine<'_>) -> Option<Range<usize>> {
+        line_range(&self.new_lines, self.new_content().len(), line.new_lineno())
+    }
+
+    /// Returns the content of `line` in the old contents, if it is part of
+    /// the old file.
+    pub fn old_slice(&self, line: &DiffLine<'_>) -> Option<&[u8]> {
+        self.old_range(line).map(|range| &self.old_content()[range])
+    }
+
+    /// Returns the content of `line` in the new contents, if it is part of
+    /// the new file.
+    pub fn new_slice(&self, line: &DiffLine<'_>) -> Option<&[u8]> {
+        self.new_range(line).map(|range| &self.new_content()[range])
+    }
+}
+
+impl<'repo> DiffContentSource<'repo> {
+    fn load(repo: &'repo Repository, file: &DiffFile<'_>) -> Result<Self, Error> {
+        if !file.exists() {
+            return Ok(DiffContentSource::Data(Vec::new()));
+        }
+        if !file.id().is_zero() {
+            if let Ok(blob) = repo.find_blob(file.id()) {
+                return Ok(DiffContentSource::Blob(blob));
+            }
+        }
+        let path = match (repo.workdir(), file.path()) {
+            (Some(workdir), Some(path)) => workdir.join(path),
+            _ => return Ok(DiffContentSource::Data(Vec::new())),
+        };
+        match std::fs::read(&path) {
+            Ok(data) => Ok(DiffContentSource::Data(data)),
+            Err(e) => Err(Error::from_str(&format!(
+                "failed to read '{}': {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            DiffContentSource::Blob(ref blob) => blob.content(),
+            DiffContentSource::Data(ref data) => data,
+        }
+    }
+}
+
+fn line_starts(content: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(
+        content
+            .iter()
+            .enumerate()
+            .filter(|&(_, &b)| b == b'\n')
+            .map(|(i, _)| i + 1),
+    );
+    starts
+}
+
+fn line_range(starts: &[usize], len: usize, lineno: Option<u32>) -> Option<Range<usize>> {
+    let idx = (lineno? as usize).checked_sub(1)?;
+    let start = *starts.get(idx)?;
+    let end = starts.get(idx + 1).cloned().unwrap_or(len);
+    if start >= len {
+        return None;
+    }
+    Some(start..end)
+}
+
 impl<'a> DiffLine<'a> {
+    /// Returns the id of the moved block this line of `delta` belongs to,
+    /// according to `moves`, or `None` if it was not moved.
+    ///
+    /// See `Diff::moved_lines`.
+    pub fn moved_block(&self, delta: &DiffDelta<'_>, moves: &MovedLines) -> Option<usize> {
+        moves.block(delta, self)
+    }
+
     /// Line number in old file or `None` for added line
     pub fn old_lineno(&self) -> Option<u32> {
         match unsafe { (*self.raw).old_lineno } {
@@ -1179,6 +1928,21 @@ impl DiffStats {
         unsafe { raw::git_diff_stats_deletions(&*self.raw) as usize }
     }
 
+    /// Get the totals of the diff, as printed by `git diff --shortstat`.
+    pub fn shortstat(&self) -> DiffShortStat {
+        DiffShortStat {
+            files_changed: self.files_changed(),
+            insertions: self.insertions(),
+            deletions: self.deletions(),
+        }
+    }
+
+    /// Iterate over the statistics of each file of the diff, in the order of
+    /// its deltas, as printed by `git diff --numstat`.
+    pub fn files(&self) -> std::slice::Iter<'_, DiffFileStats> {
+        self.files.iter()
+    }
+
     /// Print diff statistics to a Buf
     pub fn to_buf(&self, format: DiffStatsFormat, width: usize) -> Result<Buf, Error> {
         let buf = Buf::new();
@@ -1198,7 +1962,10 @@ impl Binding for DiffStats {
     type Raw = *mut raw::git_diff_stats;
 
     unsafe fn from_raw(raw: *mut raw::git_diff_stats) -> DiffStats {
-        DiffStats { raw: raw }
+        DiffStats {
+            raw: raw,
+            files: Vec::new(),
+        }
     }
     fn raw(&self) -> *mut raw::git_diff_stats {
         self.raw
@@ -1221,6 +1988,142 @@ impl std::fmt::Debug for DiffStats {
     }
 }
 
+impl DiffFileStats {
+    /// Returns the path of the file, or its old path if it was deleted, as a
+    /// byte slice.
+    pub fn path_bytes(&self) -> &[u8] {
+        self.new_path
+            .as_ref()
+            .or(self.old_path.as_ref())
+            .map(|p| &p[..])
+            .unwrap_or(&[])
+    }
+
+    /// Returns the path of the file, or its old path if it was deleted.
+    pub fn path(&self) -> &Path {
+        util::bytes2path(self.path_bytes())
+    }
+
+    /// Returns the path of the file before the change, which differs from
+    /// `path` for renames and copies.
+    pub fn old_path(&self) -> Option<&Path> {
+        self.old_path.as_ref().map(|p| util::bytes2path(p))
+    }
+
+    /// Returns the number of lines added to the file.
+    ///
+    /// This is always 0 for binary files.
+    pub fn insertions(&self) -> usize {
+        self.insertions
+    }
+
+    /// Returns the number of lines removed from the file.
+    ///
+    /// This is always 0 for binary files.
+    pub fn deletions(&self) -> usize {
+        self.deletions
+    }
+
+    /// Returns `true` if the file is binary, which `--numstat` shows as
+    /// `-` lines added and removed.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+}
+
+impl DiffShortStat {
+    /// Returns the number of files changed.
+    pub fn files_changed(&self) -> usize {
+        self.files_changed
+    }
+
+    /// Returns the number of lines added.
+    pub fn insertions(&self) -> usize {
+        self.insertions
+    }
+
+    /// Returns the number of lines removed.
+    pub fn deletions(&self) -> usize {
+        self.deletions
+    }
+}
+
+impl std::fmt::Display for DiffShortStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            " {} file{} changed",
+            self.files_changed,
+            plural(self.files_changed)
+        )?;
+        if self.files_changed == 0 {
+            return Ok(());
+        }
+        if self.insertions > 0 || self.deletions == 0 {
+            write!(
+                f,
+                ", {} insertion{}(+)",
+                self.insertions,
+                plural(self.insertions)
+            )?;
+        }
+        if self.deletions > 0 || self.insertions == 0 {
+            write!(
+                f,
+                ", {} deletion{}(-)",
+                self.deletions,
+                plural(self.deletions)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl BinaryPatch {
+    /// Returns whether the payloads of the files were included.
+    ///
+    /// See `DiffBinary::contains_data`.
+    pub fn contains_data(&self) -> bool {
+        self.contains_data
+    }
+
+    /// The data of the old file.
+    pub fn old_file(&self) -> &BinaryPatchFile {
+        &self.old_file
+    }
+
+    /// The data of the new file.
+    pub fn new_file(&self) -> &BinaryPatchFile {
+        &self.new_file
+    }
+}
+
+impl BinaryPatchFile {
+    fn new(file: &DiffBinaryFile<'_>) -> BinaryPatchFile {
+        BinaryPatchFile {
+            kind: file.kind(),
+            data: file.data().to_vec(),
+            inflated_len: file.inflated_len(),
+        }
+    }
+
+    /// The type of binary data for this file.
+    pub fn kind(&self) -> DiffBinaryKind {
+        self.kind
+    }
+
+    /// The binary data, deflated.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The length of the binary data after inflation.
+    pub fn inflated_len(&self) -> usize {
+        self.inflated_len
+    }
+}
+
 impl<'a> DiffBinary<'a> {
     /// Returns whether there is data in this binary structure or not.
     ///
@@ -1265,6 +2168,9 @@ impl<'a> DiffBinaryFile<'a> {
     /// The binary data, deflated
     pub fn data(&self) -> &[u8] {
         unsafe {
+            if (*self.raw).data.is_null() || (*self.raw).datalen == 0 {
+                return &[];
+            }
             slice::from_raw_parts((*self.raw).data as *const u8, (*self.raw).datalen as usize)
         }
     }
@@ -1368,6 +2274,18 @@ impl DiffFindOptions {
         self.flag(raw::GIT_DIFF_FIND_COPIES_FROM_UNMODIFIED, find)
     }
 
+    /// Look for copies, considering all files as sources, like
+    /// `git diff --find-copies-harder` (or `-C -C`).
+    ///
+    /// This sets both `copies` and `copies_from_unmodified`, so the initial
+    /// diff should be generated with `include_unmodified`.
+    pub fn find_copies_harder(&mut self, find: bool) -> &mut DiffFindOptions {
+        self.flag(
+            raw::GIT_DIFF_FIND_COPIES | raw::GIT_DIFF_FIND_COPIES_FROM_UNMODIFIED,
+            find,
+        )
+    }
+
     /// Mark significant rewrites for split.
     pub fn rewrites(&mut self, find: bool) -> &mut DiffFindOptions {
         self.flag(raw::GIT_DIFF_FIND_REWRITES, find)
@@ -1459,6 +2377,10 @@ impl DiffFindOptions {
     }
 
     /// Similarity to split modify into delete/add pair (default 60)
+    ///
+    /// Only used with `rewrites` and `break_rewrites`: a modified file which
+    /// is less similar than this to its previous version is treated as a
+    /// rewrite.
     pub fn break_rewrite_threshold(&mut self, thresh: u16) -> &mut DiffFindOptions {
         self.raw.break_rewrite_threshold = thresh;
         self
@@ -1467,7 +2389,8 @@ impl DiffFindOptions {
     /// Maximum similarity sources to examine for a file (somewhat like
     /// git-diff's `-l` option or `diff.renameLimit` config)
     ///
-    /// Defaults to 200
+    /// Defaults to 200. `Diff::find_similar_with_stats` reports the limit
+    /// which was used.
     pub fn rename_limit(&mut self, limit: usize) -> &mut DiffFindOptions {
         self.raw.rename_limit = limit as size_t;
         self
@@ -1476,6 +2399,36 @@ impl DiffFindOptions {
     // TODO: expose git_diff_similarity_metric
 }
 
+impl DiffFindStats {
+    /// Returns the rename limit in effect: the one set in the options, else
+    /// the `diff.renameLimit` config, else libgit2's default of 200.
+    ///
+    /// Each rename or copy target is compared with at most this many
+    /// sources, so some renames and copies may be missed in larger diffs.
+    pub fn rename_limit(&self) -> usize {
+        self.rename_limit
+    }
+
+    /// Returns whether there were more rename or copy sources than the
+    /// rename limit allows, so some of them were not considered.
+    ///
+    /// Modified files are counted as sources whenever rewrite detection is
+    /// enabled, so this may report an overflow which did not happen.
+    pub fn rename_limit_exceeded(&self) -> bool {
+        self.rename_limit_exceeded
+    }
+
+    /// Returns the number of renames in the diff.
+    pub fn renames(&self) -> usize {
+        self.renames
+    }
+
+    /// Returns the number of copies in the diff.
+    pub fn copies(&self) -> usize {
+        self.copies
+    }
+}
+
 impl Default for DiffFormatEmailOptions {
     fn default() -> Self {
         Self::new()
@@ -1536,11 +2489,13 @@ impl DiffPatchidOptions {
 
 #[cfg(test)]
 mod tests {
-    use crate::{DiffLineType, DiffOptions, Oid, Signature, Time};
+    use crate::{Diff, DiffBinaryKind, DiffFindOptions, DiffFormat, DiffLineType, DiffOptions};
+    use crate::{Oid, Signature, Time};
     use std::borrow::Borrow;
     use std::fs::File;
     use std::io::Write;
     use std::path::Path;
+    use std::str;
 
     #[test]
     fn smoke() {
@@ -1555,6 +2510,194 @@ mod tests {
         assert_ne!(patchid, Oid::zero());
     }
 
+    #[test]
+    fn find_similar_stats() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree_of = |files: &[(&str, &str)]| {
+            let mut builder = t!(repo.treebuilder(None));
+            for &(name, content) in files {
+                let blob = t!(repo.blob(content.as_bytes()));
+                t!(builder.insert(name, blob, 0o100644));
+            }
+            t!(repo.find_tree(t!(builder.write())))
+        };
+        let contents = ["one\n", "two\n", "three\n"];
+        let old = tree_of(&[("a", contents[0]), ("b", contents[1]), ("c", contents[2])]);
+        let new = tree_of(&[("x", contents[0]), ("y", contents[1]), ("z", contents[2])]);
+
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        let stats = t!(diff.find_similar_with_stats(None));
+        assert_eq!(stats.renames(), 3);
+        assert_eq!(stats.rename_limit(), 200);
+        assert!(!stats.rename_limit_exceeded());
+
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        let mut opts = DiffFindOptions::new();
+        opts.renames(true).rename_limit(2);
+        let stats = t!(diff.find_similar_with_stats(Some(&mut opts)));
+        assert_eq!(stats.rename_limit(), 2);
+        assert!(!stats.rename_limit_exceeded());
+
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        opts.rename_limit(1);
+        let stats = t!(diff.find_similar_with_stats(Some(&mut opts)));
+        assert_eq!(stats.rename_limit(), 1);
+        assert!(stats.rename_limit_exceeded());
+
+        let mut config = t!(repo.config());
+        t!(config.set_i32("diff.renameLimit", 50));
+        t!(config.set_bool("diff.renames", false));
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        let stats = t!(diff.find_similar_with_stats(None));
+        assert_eq!(stats.renames(), 0);
+        assert_eq!(stats.rename_limit(), 50);
+        assert!(!stats.rename_limit_exceeded());
+        t!(config.remove("diff.renames"));
+
+        // A copy of an unmodified file is only found with -C -C.
+        let copied = tree_of(&[("a", contents[0]), ("b", contents[1]), ("d", contents[0])]);
+        let old = tree_of(&[("a", contents[0]), ("b", contents[1])]);
+        let mut diffopts = DiffOptions::new();
+        diffopts.include_unmodified(true);
+        let mut opts = DiffFindOptions::new();
+        opts.copies(true);
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&copied), Some(&mut diffopts)));
+        let stats = t!(diff.find_similar_with_stats(Some(&mut opts)));
+        assert_eq!(stats.copies(), 0);
+        opts.find_copies_harder(true).remove_unmodified(true);
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&copied), Some(&mut diffopts)));
+        let stats = t!(diff.find_similar_with_stats(Some(&mut opts)));
+        assert_eq!(stats.copies(), 1);
+        assert_eq!(diff.deltas().len(), 1);
+        let delta = diff.get_delta(0).unwrap();
+        assert_eq!(delta.old_file().path(), Some(Path::new("a")));
+        assert_eq!(delta.new_file().path(), Some(Path::new("d")));
+    }
+
+    #[test]
+    fn from_patch_text() {
+        let (td, repo) = crate::test::repo_init();
+        t!(std::fs::write(td.path().join("foo"), "a\nb\nc\n"));
+        let text = "diff --git a/foo b/foo\n\
+                    --- a/foo\n\
+                    +++ b/foo\n\
+                    @@ -1,3 +1,3 @@\n \
+                    a\n\
+                    -b\n\
+                    +B\n \
+                    c\n";
+        let diff = t!(Diff::from_patch_text(text.as_bytes()));
+        assert_eq!(diff.deltas().len(), 1);
+        let patches = t!(diff.patches());
+        assert_eq!(patches.len(), 1);
+        let hunks = t!(patches[0].hunks());
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].old_start(), 1);
+        assert_eq!(hunks[0].new_lines(), 3);
+        let lines = t!(patches[0].lines_in_hunk(0));
+        let lines = lines
+            .iter()
+            .map(|l| (l.origin(), l.content().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                (' ', b"a\n".to_vec()),
+                ('-', b"b\n".to_vec()),
+                ('+', b"B\n".to_vec()),
+                (' ', b"c\n".to_vec()),
+            ]
+        );
+
+        t!(repo.apply(&diff, crate::ApplyLocation::WorkDir, None));
+        let contents = t!(std::fs::read_to_string(td.path().join("foo")));
+        assert_eq!(contents, "a\nB\nc\n");
+
+        assert!(Diff::from_patch_text(b"not a patch").is_err());
+    }
+
+    #[test]
+    fn numstat() {
+        let (td, repo) = crate::test::repo_init();
+        let diff = t!(repo.diff_tree_to_workdir(None, None));
+        assert_eq!(t!(diff.stats()).shortstat().to_string(), " 0 files changed");
+
+        t!(std::fs::write(td.path().join("text"), "a\nb\nc\n"));
+        t!(std::fs::write(td.path().join("bin"), b"\0\x01\x02"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new("text")));
+        t!(index.add_path(Path::new("bin")));
+        let tree = t!(repo.find_tree(t!(index.write_tree())));
+        t!(std::fs::write(td.path().join("text"), "a\nB\n"));
+
+        let diff = t!(repo.diff_tree_to_workdir(Some(&tree), None));
+        t!(std::fs::write(td.path().join("bin"), b"\0\x03"));
+        let diff2 = t!(repo.diff_tree_to_workdir(Some(&tree), None));
+        assert_eq!(diff.deltas().len(), 1);
+        let stats = t!(diff.stats());
+        let files = stats.files().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path(), Path::new("text"));
+        assert_eq!(files[0].insertions(), 1);
+        assert_eq!(files[0].deletions(), 2);
+        assert!(!files[0].is_binary());
+        let shortstat = stats.shortstat();
+        assert_eq!(shortstat.files_changed(), 1);
+        assert_eq!(
+            shortstat.to_string(),
+            " 1 file changed, 1 insertion(+), 2 deletions(-)"
+        );
+
+        let stats = t!(diff2.stats());
+        let files = stats.files().collect::<Vec<_>>();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), Path::new("bin"));
+        assert!(files[0].is_binary());
+        assert_eq!(files[0].insertions(), 0);
+        assert_eq!(files[1].path(), Path::new("text"));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let (_td, repo) = crate::test::repo_init();
+        let old = (0..200u8).map(|i| i % 7).chain(Some(0)).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[100] = 0xff;
+        let tree = |contents: &[u8]| {
+            let mut builder = t!(repo.treebuilder(None));
+            let blob = t!(repo.blob(contents));
+            t!(builder.insert("file.bin", blob, 0o100644));
+            t!(repo.find_tree(t!(builder.write())))
+        };
+        let (old_tree, new_tree) = (tree(&old), tree(&new));
+
+        let diff = t!(repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None));
+        let binary = t!(diff.binary(0)).unwrap();
+        assert!(!binary.contains_data());
+
+        let mut opts = DiffOptions::new();
+        opts.show_binary(true);
+        let diff = t!(repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), Some(&mut opts)));
+        let binary = t!(diff.binary(0)).unwrap();
+        assert!(binary.contains_data());
+        assert_ne!(binary.new_file().kind(), DiffBinaryKind::None);
+        assert!(binary.new_file().inflated_len() > 0);
+
+        let mut text = Vec::new();
+        t!(diff.print(crate::DiffFormat::Patch, |_, _, line| {
+            if let '+' | '-' | ' ' = line.origin() {
+                text.push(line.origin() as u8);
+            }
+            text.extend_from_slice(line.content());
+            true
+        }));
+        let parsed = t!(Diff::from_buffer(&text));
+        assert!(t!(parsed.binary(0)).unwrap().contains_data());
+        let base = t!(repo.find_blob(t!(repo.blob(&old))));
+        let id = t!(repo.apply_to_blob(&parsed, 0, Some(&base))).unwrap();
+        assert_eq!(t!(repo.find_blob(id)).content(), &new[..]);
+    }
+
     #[test]
     fn foreach_smoke() {
         let (_td, repo) = crate::test::repo_init();
@@ -1811,4 +2954,146 @@ mod tests {
         assert_eq!(origin_values.len(), 1);
         assert_eq!(origin_values[0], DiffLineType::Addition);
     }
+
+    #[test]
+    fn diff_driver_xfuncname() {
+        let path = Path::new("lib.foo");
+        let (td, repo) = crate::test::repo_init();
+        t!(repo.set_diff_driver_xfuncname("foo", "^fn ([a-z]+)"));
+        t!(t!(File::create(&td.path().join(".gitattributes"))).write_all(b"*.foo diff=foo\n"));
+        let mut contents = b"fn alpha() {\n".to_vec();
+        contents.extend(b"    x\n".repeat(8));
+        t!(t!(File::create(&td.path().join(path))).write_all(&contents));
+        let mut index = t!(repo.index());
+        t!(index.add_path(path));
+        t!(index.write());
+
+        contents.extend(b"}\n");
+        t!(t!(File::create(&td.path().join(path))).write_all(&contents));
+        let diff = t!(repo.diff_index_to_workdir(None, None));
+        let mut headers = Vec::new();
+        t!(diff.foreach(
+            &mut |_file, _progress| { true },
+            None,
+            Some(&mut |_file, hunk| {
+                headers.push(hunk.header().to_vec());
+                true
+            }),
+            None
+        ));
+        assert_eq!(headers, vec![b"@@ -7,3 +7,4 @@ alpha\n".to_vec()]);
+    }
+
+    #[test]
+    fn function_context() {
+        let path = Path::new("foo.c");
+        let (td, repo) = crate::test::repo_init();
+        let old = "fn a() {\n    a\n}\n\nfn b() {\n    1\n    2\n    3\n    4\n    5\n}\n\nfn c() {\n    c\n}\n";
+        t!(t!(File::create(&td.path().join(path))).write_all(old.as_bytes()));
+        let mut index = t!(repo.index());
+        t!(index.add_path(path));
+        t!(index.write());
+        let new = old.replace("    3\n", "    three\n");
+        t!(t!(File::create(&td.path().join(path))).write_all(new.as_bytes()));
+
+        let mut opts = DiffOptions::new();
+        opts.function_context(true).context_lines(1);
+        let diff = t!(repo.diff_index_to_workdir(None, Some(&mut opts)));
+        let mut text = String::new();
+        t!(diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+            match line.origin() {
+                ' ' | '+' | '-' => text.push(line.origin()),
+                'H' => {}
+                _ => return true,
+            }
+            text.push_str(str::from_utf8(line.content()).unwrap());
+            true
+        }));
+        assert_eq!(
+            text,
+            "@@ -5,7 +5,7 @@ fn a() {\n fn b() {\n     1\n     2\n-    3\n+    three\n     4\n     5\n }\n"
+        );
+
+        let mut hunks = Vec::new();
+        let mut lines = 0;
+        t!(diff.foreach(
+            &mut |_file, _progress| true,
+            None,
+            Some(&mut |_file, hunk| {
+                hunks.push((hunk.old_start(), hunk.old_lines()));
+                true
+            }),
+            Some(&mut |_file, _hunk, _line| {
+                lines += 1;
+                true
+            })
+        ));
+        assert_eq!(hunks, vec![(5, 7)]);
+        assert_eq!(lines, 8);
+
+        opts.function_context(false);
+        let diff = t!(repo.diff_index_to_workdir(None, Some(&mut opts)));
+        let mut hunks = Vec::new();
+        t!(diff.foreach(
+            &mut |_file, _progress| true,
+            None,
+            Some(&mut |_file, hunk| {
+                hunks.push((hunk.old_start(), hunk.old_lines()));
+                true
+            }),
+            None
+        ));
+        assert_eq!(hunks, vec![(7, 3)]);
+    }
+
+    #[test]
+    fn foreach_with_content() {
+        let path = Path::new("foo");
+        let (td, repo) = crate::test::repo_init();
+        t!(t!(File::create(&td.path().join(path))).write_all(b"a\nb\nc\nd"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(path));
+        t!(index.write());
+        t!(t!(File::create(&td.path().join(path))).write_all(b"a\nx\nc\nd\ne\n"));
+        let diff = t!(repo.diff_index_to_workdir(None, None));
+
+        let mut origins = Vec::new();
+        t!(
+            diff.foreach_with_content(&repo, &mut |_delta, _hunk, line, content| {
+                assert_eq!(content.old_content(), b"a\nb\nc\nd");
+                assert_eq!(content.new_content(), b"a\nx\nc\nd\ne\n");
+                match line.origin() {
+                    '-' => {
+                        assert_eq!(content.old_slice(&line), Some(line.content()));
+                        assert_eq!(content.new_range(&line), None);
+                    }
+                    '+' => {
+                        assert_eq!(content.new_slice(&line), Some(line.content()));
+                        assert_eq!(content.old_range(&line), None);
+                    }
+                    ' ' => {
+                        assert_eq!(content.old_slice(&line), Some(line.content()));
+                        assert_eq!(content.new_slice(&line), Some(line.content()));
+                    }
+                    _ => {}
+                }
+                origins.push(line.origin());
+                true
+            })
+        );
+        assert!(origins.contains(&'-'));
+        assert!(origins.contains(&'+'));
+        assert!(origins.contains(&' '));
+
+        let mut ranges = Vec::new();
+        t!(
+            diff.print_with_content(&repo, DiffFormat::Patch, |_delta, _hunk, line, content| {
+                if line.origin() == '+' {
+                    ranges.push(content.new_range(&line).unwrap());
+                }
+                true
+            })
+        );
+        assert_eq!(ranges, vec![2..4, 6..8, 8..10]);
+    }
 }
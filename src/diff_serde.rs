@@ -0,0 +1,149 @@
+//! `Serialize` implementations for the diff types, enabled by the `serde`
+//! feature.
+//!
+//! Paths and contents which are not valid utf-8 are serialized lossily, and
+//! object ids as hex strings.
+
+use serde::ser::{Error as _, Serialize, SerializeStruct, Serializer};
+
+use crate::{Diff, DiffDelta, DiffFile, DiffFileStats, DiffHunk, DiffLine, DiffStats};
+
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Serialized as its `StructuredDiff`, i.e. a struct with the list of files
+/// and their hunks and lines.
+impl Serialize for Diff<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let structured = self.to_structured().map_err(S::Error::custom)?;
+        structured.serialize(serializer)
+    }
+}
+
+impl Serialize for DiffDelta<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DiffDelta", 6)?;
+        s.serialize_field("status", &self.status())?;
+        s.serialize_field("nfiles", &self.nfiles())?;
+        s.serialize_field("similarity", &self.similarity())?;
+        s.serialize_field("binary", &self.flags().is_binary())?;
+        s.serialize_field("old_file", &self.old_file())?;
+        s.serialize_field("new_file", &self.new_file())?;
+        s.end()
+    }
+}
+
+impl Serialize for DiffFile<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DiffFile", 5)?;
+        s.serialize_field("path", &self.path_bytes().map(lossy))?;
+        s.serialize_field("id", &self.id().to_string())?;
+        s.serialize_field("size", &self.size())?;
+        s.serialize_field("mode", &self.mode())?;
+        s.serialize_field("exists", &self.exists())?;
+        s.end()
+    }
+}
+
+impl Serialize for DiffHunk<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DiffHunk", 5)?;
+        s.serialize_field("header", &lossy(self.header()))?;
+        s.serialize_field("old_start", &self.old_start())?;
+        s.serialize_field("old_lines", &self.old_lines())?;
+        s.serialize_field("new_start", &self.new_start())?;
+        s.serialize_field("new_lines", &self.new_lines())?;
+        s.end()
+    }
+}
+
+impl Serialize for DiffLine<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DiffLine", 6)?;
+        s.serialize_field("origin", &self.origin())?;
+        s.serialize_field("old_lineno", &self.old_lineno())?;
+        s.serialize_field("new_lineno", &self.new_lineno())?;
+        s.serialize_field("num_lines", &self.num_lines())?;
+        s.serialize_field("content_offset", &self.content_offset())?;
+        s.serialize_field("content", &lossy(self.content()))?;
+        s.end()
+    }
+}
+
+impl Serialize for DiffStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DiffStats", 4)?;
+        s.serialize_field("files_changed", &self.files_changed())?;
+        s.serialize_field("insertions", &self.insertions())?;
+        s.serialize_field("deletions", &self.deletions())?;
+        s.serialize_field("files", &self.files().collect::<Vec<_>>())?;
+        s.end()
+    }
+}
+
+impl Serialize for DiffFileStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DiffFileStats", 5)?;
+        s.serialize_field("path", &lossy(self.path_bytes()))?;
+        s.serialize_field(
+            "old_path",
+            &self.old_path().map(|p| p.to_string_lossy().into_owned()),
+        )?;
+        s.serialize_field("insertions", &self.insertions())?;
+        s.serialize_field("deletions", &self.deletions())?;
+        s.serialize_field("binary", &self.is_binary())?;
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn to_json() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join("foo"), "a\nb\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path("foo".as_ref()).unwrap();
+        index.write().unwrap();
+        fs::write(td.path().join("foo"), "a\nc\n").unwrap();
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+
+        let json = serde_json::to_value(&diff).unwrap();
+        let file = &json["files"][0];
+        assert_eq!(file["status"], "Modified");
+        assert_eq!(file["new_path"], "foo");
+        let lines = &file["hunks"][0]["lines"];
+        assert_eq!(lines[1]["origin"], "-");
+        assert_eq!(lines[1]["content"], "b\n");
+        assert_eq!(lines[2]["new_lineno"], 2);
+
+        let delta = serde_json::to_value(&diff.get_delta(0).unwrap()).unwrap();
+        assert_eq!(delta["status"], "Modified");
+        assert_eq!(delta["old_file"]["path"], "foo");
+        assert_eq!(delta["new_file"]["mode"], "Blob");
+
+        let stats = serde_json::to_value(&diff.stats().unwrap()).unwrap();
+        assert_eq!(stats["files_changed"], 1);
+        assert_eq!(stats["files"][0]["insertions"], 1);
+        assert_eq!(stats["files"][0]["path"], "foo");
+
+        diff.foreach(
+            &mut |_, _| true,
+            None,
+            Some(&mut |_, hunk| {
+                let hunk = serde_json::to_value(&hunk).unwrap();
+                assert_eq!(hunk["header"], "@@ -1,2 +1,2 @@\n");
+                true
+            }),
+            Some(&mut |_, _, line| {
+                let line = serde_json::to_value(&line).unwrap();
+                assert!(line["content"].is_string());
+                true
+            }),
+        )
+        .unwrap();
+    }
+}
@@ -287,6 +287,8 @@ impl Error {
             GIT_EEOF,
             GIT_EINVALID,
             GIT_EUNCOMMITTED,
+            GIT_EDIRECTORY,
+            GIT_EMERGECONFLICT,
             GIT_PASSTHROUGH,
             GIT_ITEROVER,
             GIT_RETRY,
@@ -392,5 +394,8 @@ mod tests {
         let err = repo.find_submodule("does_not_exist").err().unwrap();
         assert_eq!(err.code(), ErrorCode::NotFound);
         assert_eq!(err.class(), ErrorClass::Submodule);
+
+        let err = crate::Error::new(ErrorCode::MergeConflict, ErrorClass::Merge, "conflict");
+        assert_eq!(err.code(), ErrorCode::MergeConflict);
     }
 }
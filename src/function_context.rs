@@ -0,0 +1,309 @@
+//! Emulation of `git diff -W`, which libgit2 does not support.
+//!
+//! A diff with function context is generated with the whole file as context,
+//! and the lines of each delta are buffered and trimmed back here to the
+//! functions around each change before being handed to the callbacks of
+//! `Diff::foreach` and `Diff::print`.
+
+use libc::c_char;
+use std::cmp;
+
+use crate::util::Binding;
+use crate::{raw, DiffDelta, DiffHunk, DiffLine, Error, ErrorClass, ErrorCode};
+
+/// The number of context lines requested from libgit2 so that every delta
+/// is a single hunk spanning the whole file.
+pub(crate) const WHOLE_FILE: u32 = 1 << 24;
+
+/// The longest function name shown in a hunk header, as in git.
+const MAX_FUNCNAME: usize = 80;
+
+/// A line of a delta, copied out of libgit2 as its buffers are freed before
+/// the next delta starts.
+struct Line {
+    origin: u8,
+    old_lineno: i32,
+    new_lineno: i32,
+    num_lines: i32,
+    content_offset: i64,
+    content: Vec<u8>,
+}
+
+impl Line {
+    fn is_old(&self) -> bool {
+        self.origin == b' ' || self.origin == b'-'
+    }
+
+    fn is_new(&self) -> bool {
+        self.origin == b' ' || self.origin == b'+'
+    }
+
+    fn is_change(&self) -> bool {
+        self.origin == b'+' || self.origin == b'-'
+    }
+
+    /// Whether this is one of the "no newline at end of file" markers, which
+    /// belong with the line before them.
+    fn is_eofnl(&self) -> bool {
+        self.origin == b'=' || self.origin == b'>' || self.origin == b'<'
+    }
+
+    fn is_function(&self) -> bool {
+        self.is_old() && is_function(&self.content)
+    }
+
+    fn to_raw(&self) -> raw::git_diff_line {
+        raw::git_diff_line {
+            origin: self.origin as c_char,
+            old_lineno: self.old_lineno,
+            new_lineno: self.new_lineno,
+            num_lines: self.num_lines,
+            content_len: self.content.len(),
+            content_offset: self.content_offset,
+            content: self.content.as_ptr() as *const c_char,
+        }
+    }
+}
+
+/// A hunk of a trimmed delta, covering `start..end` of its lines.
+struct Hunk {
+    old_start: i32,
+    old_lines: i32,
+    new_start: i32,
+    new_lines: i32,
+    header: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl Hunk {
+    fn to_raw(&self) -> raw::git_diff_hunk {
+        let mut header = [0; 128];
+        let len = cmp::min(self.header.len(), header.len());
+        for (dst, src) in header.iter_mut().zip(&self.header[..len]) {
+            *dst = *src as c_char;
+        }
+        raw::git_diff_hunk {
+            old_start: self.old_start,
+            old_lines: self.old_lines,
+            new_start: self.new_start,
+            new_lines: self.new_lines,
+            header_len: len,
+            header,
+        }
+    }
+}
+
+/// Returns the error libgit2 reports when a callback of `action` returns
+/// false, for when one does so while a trimmed delta is being flushed after
+/// libgit2 has finished.
+pub(crate) fn callback_error(action: &str) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Callback,
+        format!("{} callback returned -1", action),
+    )
+}
+
+/// The buffered lines of the delta currently being generated.
+pub(crate) struct Pending {
+    context: u32,
+    delta: Option<*mut raw::git_diff_delta>,
+    lines: Vec<Line>,
+}
+
+impl Pending {
+    /// Creates an empty buffer which keeps `context` lines of context around
+    /// each change in addition to its function.
+    pub(crate) fn new(context: u32) -> Pending {
+        Pending {
+            context,
+            delta: None,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Buffers a line of `delta`.
+    pub(crate) fn push(&mut self, delta: &DiffDelta<'_>, line: &DiffLine<'_>) {
+        self.delta = Some(delta.raw());
+        self.lines.push(Line {
+            origin: line.origin() as u8,
+            old_lineno: line.old_lineno().map_or(-1, |n| n as i32),
+            new_lineno: line.new_lineno().map_or(-1, |n| n as i32),
+            num_lines: line.num_lines() as i32,
+            content_offset: line.content_offset(),
+            content: line.content().to_vec(),
+        });
+    }
+
+    /// Trims the buffered delta and passes its hunks to `emit`, first with
+    /// no line for the start of each hunk and then with each of its lines.
+    ///
+    /// Returns `false` as soon as `emit` does, and `true` otherwise.
+    pub(crate) fn flush(
+        &mut self,
+        emit: &mut dyn FnMut(DiffDelta<'_>, DiffHunk<'_>, Option<DiffLine<'_>>) -> bool,
+    ) -> bool {
+        let delta = match self.delta.take() {
+            Some(delta) => delta,
+            None => return true,
+        };
+        let lines = std::mem::take(&mut self.lines);
+        for hunk in trim(&lines, self.context) {
+            let raw_hunk = hunk.to_raw();
+            unsafe {
+                let hunk_start = emit(
+                    DiffDelta::from_raw(delta),
+                    DiffHunk::from_raw(&raw_hunk),
+                    None,
+                );
+                if !hunk_start {
+                    return false;
+                }
+                for line in &lines[hunk.start..hunk.end] {
+                    let raw_line = line.to_raw();
+                    let line = DiffLine::from_raw(&raw_line);
+                    if !emit(
+                        DiffDelta::from_raw(delta),
+                        DiffHunk::from_raw(&raw_hunk),
+                        Some(line),
+                    ) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Whether `line` starts a function, according to the rule git uses when no
+/// `xfuncname` pattern is configured.
+fn is_function(line: &[u8]) -> bool {
+    match line.first() {
+        Some(&b) => b.is_ascii_alphabetic() || b == b'_' || b == b'$',
+        None => false,
+    }
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(|b| b.is_ascii_whitespace())
+}
+
+/// Splits the lines of a whole-file delta into hunks spanning the functions
+/// around its changes, plus `context` lines on either side of each change.
+fn trim(lines: &[Line], context: u32) -> Vec<Hunk> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if !line.is_change() {
+            continue;
+        }
+
+        let mut start = (0..=i).rev().find(|&j| lines[j].is_function()).unwrap_or(0);
+        let mut s = i;
+        let mut n = 0;
+        while s > 0 && n < context {
+            s -= 1;
+            if lines[s].origin == b' ' {
+                n += 1;
+            }
+        }
+        start = cmp::min(start, s);
+
+        let mut end = match (i + 1..lines.len()).find(|&j| lines[j].is_function()) {
+            Some(mut end) => {
+                while end > i + 1
+                    && lines[end - 1].origin == b' '
+                    && is_blank(&lines[end - 1].content)
+                {
+                    end -= 1;
+                }
+                end
+            }
+            None => lines.len(),
+        };
+        let mut e = i + 1;
+        let mut n = 0;
+        while e < lines.len() && n < context {
+            if lines[e].origin == b' ' {
+                n += 1;
+            }
+            e += 1;
+        }
+        end = cmp::max(end, e);
+        while end < lines.len() && lines[end].is_eofnl() {
+            end += 1;
+        }
+
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = cmp::max(last.1, end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| hunk(lines, start, end))
+        .collect()
+}
+
+fn hunk(lines: &[Line], start: usize, end: usize) -> Hunk {
+    let range = &lines[start..end];
+    let old_lines = range.iter().filter(|l| l.is_old()).count() as i32;
+    let new_lines = range.iter().filter(|l| l.is_new()).count() as i32;
+    let old_start = match range.iter().find(|l| l.is_old()) {
+        Some(line) => line.old_lineno,
+        None => lines[..start]
+            .iter()
+            .rev()
+            .find(|l| l.is_old())
+            .map_or(0, |l| l.old_lineno),
+    };
+    let new_start = match range.iter().find(|l| l.is_new()) {
+        Some(line) => line.new_lineno,
+        None => lines[..start]
+            .iter()
+            .rev()
+            .find(|l| l.is_new())
+            .map_or(0, |l| l.new_lineno),
+    };
+
+    let mut header = format!(
+        "@@ -{}{} +{}{} @@",
+        old_start,
+        count(old_lines),
+        new_start,
+        count(new_lines)
+    )
+    .into_bytes();
+    if let Some(function) = lines[..start].iter().rev().find(|l| l.is_function()) {
+        let mut name = &function.content[..];
+        while let Some((last, rest)) = name.split_last() {
+            if !last.is_ascii_whitespace() {
+                break;
+            }
+            name = rest;
+        }
+        header.push(b' ');
+        header.extend_from_slice(&name[..cmp::min(name.len(), MAX_FUNCNAME)]);
+    }
+    header.push(b'\n');
+
+    Hunk {
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        header,
+        start,
+        end,
+    }
+}
+
+/// Formats the line count of a hunk range, which git leaves out when it is 1.
+fn count(lines: i32) -> String {
+    if lines == 1 {
+        String::new()
+    } else {
+        format!(",{}", lines)
+    }
+}
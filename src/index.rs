@@ -1,7 +1,10 @@
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsString};
+use std::fs;
+use std::io::{self, Write};
 use std::marker;
+use std::mem;
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::slice;
 
@@ -9,7 +12,9 @@ use libc::{c_char, c_int, c_uint, c_void, size_t};
 
 use crate::util::{self, path_to_repo_path, Binding};
 use crate::IntoCString;
-use crate::{panic, raw, Error, IndexAddOption, IndexTime, Oid, Repository, Tree};
+use crate::{panic, raw, BlobWriter, Error, FileMode, IndexAddOption, IndexTime, Oid};
+use crate::{ErrorClass, ErrorCode, IndexEntryExtendedFlag, IndexEntryFlag, ObjectType};
+use crate::{LockFile, LockOptions, Repository, Tree};
 
 /// A structure to represent a git [index][1]
 ///
@@ -42,6 +47,54 @@ pub struct IndexConflict {
     pub their: Option<IndexEntry>,
 }
 
+/// An iterator over the resolve-undo entries in an index
+pub struct IndexReucEntries<'index> {
+    range: Range<usize>,
+    index: &'index Index,
+}
+
+/// A structure to represent a resolve-undo (REUC) entry of an index.
+///
+/// When a conflicted path is resolved (e.g. through `add_path`), the stages
+/// that made up the conflict are recorded in the resolve-undo extension of the
+/// index, which allows the conflict to be recreated later on.
+///
+/// Each side holds the mode and the id of the blob at that stage, or is `None`
+/// if the path did not exist on that side.
+pub struct IndexReucEntry {
+    /// The path of the entry, in the same format as `IndexEntry::path`.
+    pub path: Vec<u8>,
+    /// The mode and id of the ancestor (stage 1) of the conflict
+    pub ancestor: Option<(u32, Oid)>,
+    /// The mode and id of 'our' side (stage 2) of the conflict
+    pub our: Option<(u32, Oid)>,
+    /// The mode and id of 'their' side (stage 3) of the conflict
+    pub their: Option<(u32, Oid)>,
+}
+
+/// Options controlling how to wait for the `index.lock` file of an index to
+/// be released by another process.
+///
+/// Used by `IndexLock::acquire` and `Index::write_opts`.
+pub type IndexLockOptions = LockOptions;
+
+/// A lock on the on-disk file of an index.
+///
+/// git and libgit2 take this lock by creating an `index.lock` file next to the
+/// index file while they update it. Holding an `IndexLock` keeps other tools
+/// from modifying the index; the lock is released when the `IndexLock` is
+/// dropped, or by `IndexLock::commit`, which writes the index through it.
+#[derive(Debug)]
+pub struct IndexLock {
+    lock: LockFile,
+}
+
+/// An index file in version 2 with no entries: the header followed by its
+/// SHA-1 checksum.
+const EMPTY_INDEX: &[u8] = b"DIRC\x00\x00\x00\x02\x00\x00\x00\x00\
+    \x39\xd8\x90\x13\x9e\xe5\x35\x6c\x7e\xf5\
+    \x72\x21\x6c\xeb\xcd\x27\xaa\x41\xf9\xdf";
+
 /// A callback function to filter index matches.
 ///
 /// Used by `Index::{add_all,remove_all,update_all}`.  The first argument is the
@@ -49,6 +102,30 @@ pub struct IndexConflict {
 /// the operation on the item, > 0 to skip the item, and < 0 to abort the scan.
 pub type IndexMatchedPath<'a> = dyn FnMut(&Path, &[u8]) -> i32 + 'a;
 
+/// A callback function to monitor the progress of `Index::{add_all_opts,
+/// update_all_opts}`.
+///
+/// The first argument is the path which is about to be processed and the
+/// second is the number of entries processed so far, including this one.
+/// Return `false` to abort the scan.
+pub type IndexUpdateProgress<'a> = dyn FnMut(&Path, usize) -> bool + 'a;
+
+/// Options which can be used to configure `Index::add_all_opts` and
+/// `Index::update_all_opts`.
+pub struct IndexUpdateOptions<'cb> {
+    flags: IndexAddOption,
+    dry_run: bool,
+    pathspec_magic: bool,
+    progress: Option<Box<IndexUpdateProgress<'cb>>>,
+    matched: Option<Box<IndexMatchedPath<'cb>>>,
+}
+
+struct IndexUpdateCbData<'a, 'cb> {
+    opts: &'a mut IndexUpdateOptions<'cb>,
+    processed: usize,
+    paths: Vec<PathBuf>,
+}
+
 /// A structure to represent an entry or a file inside of an index.
 ///
 /// All fields of an entry are public for modification and inspection. This is
@@ -243,6 +320,148 @@ impl Index {
         }
     }
 
+    /// Add or update an index entry from a stream of data.
+    ///
+    /// The contents of `reader` are streamed into the object database of the
+    /// repository that owns the index, and an entry for `path` with the given
+    /// `mode` is then added for the resulting blob. Unlike `add_frombuffer`,
+    /// the contents never have to be held in memory as a whole, which makes
+    /// this suitable for very large files.
+    ///
+    /// The data is stored as-is, no filters (such as line ending conversion)
+    /// are applied. The stat information of the new entry is left empty.
+    ///
+    /// If a previous index entry exists that has the same path, it will be
+    /// replaced. Returns the id of the blob that was written.
+    ///
+    /// This method will fail in bare index instances.
+    pub fn add_from_reader<R: io::Read>(
+        &mut self,
+        path: &Path,
+        mut reader: R,
+        mode: FileMode,
+    ) -> Result<Oid, Error> {
+        let path = path_to_repo_path(path)?;
+        let repo = unsafe { raw::git_index_owner(self.raw) };
+        if repo.is_null() {
+            return Err(Error::from_str(
+                "cannot stream into an index that is not backed by a repository",
+            ));
+        }
+        let mut writer = unsafe {
+            let mut out = ptr::null_mut();
+            try_call!(raw::git_blob_create_fromstream(&mut out, repo, ptr::null()));
+            BlobWriter::from_raw(out)
+        };
+        let size = io::copy(&mut reader, &mut writer)
+            .map_err(|e| Error::from_str(&format!("failed to stream blob: {}", e)))?;
+        let id = writer.commit()?;
+
+        self.add(&IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: mode.into(),
+            uid: 0,
+            gid: 0,
+            // like git, only the lower 32 bits of the size are recorded
+            file_size: size as u32,
+            id: id,
+            flags: 0,
+            flags_extended: 0,
+            path: path.into_bytes(),
+        })?;
+        Ok(id)
+    }
+
+    /// Record an intent-to-add entry for a path, like `git add -N`.
+    ///
+    /// An entry pointing to the empty blob and flagged as intent-to-add is
+    /// added for `path`, so that the file shows up as an addition when
+    /// diffing without its contents being staged yet. The mode of the entry
+    /// is taken from the file in the working directory when it exists.
+    ///
+    /// The contents still have to be staged (e.g. through `add_path`) before
+    /// committing, otherwise an empty file would be recorded.
+    pub fn add_intent(&mut self, path: &Path) -> Result<(), Error> {
+        let repo_path = path_to_repo_path(path)?;
+        let repo = unsafe { raw::git_index_owner(self.raw) };
+        let mut mode = FileMode::Blob;
+        let id = if repo.is_null() {
+            Oid::hash_object(ObjectType::Blob, &[])?
+        } else {
+            let mut raw = raw::git_oid {
+                id: [0; raw::GIT_OID_RAWSZ],
+            };
+            unsafe {
+                // make sure the empty blob exists for anyone looking it up
+                try_call!(raw::git_blob_create_frombuffer(
+                    &mut raw,
+                    repo,
+                    b"".as_ptr() as *const c_void,
+                    0
+                ));
+                let workdir = raw::git_repository_workdir(repo);
+                if !workdir.is_null() {
+                    let workdir = util::bytes2path(CStr::from_ptr(workdir).to_bytes());
+                    if let Ok(meta) = fs::symlink_metadata(workdir.join(path)) {
+                        mode = util::file_mode(&meta);
+                    }
+                }
+                Binding::from_raw(&raw as *const _)
+            }
+        };
+
+        self.add(&IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: mode.into(),
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: id,
+            flags: IndexEntryFlag::EXTENDED.bits(),
+            flags_extended: IndexEntryExtendedFlag::INTENT_TO_ADD.bits(),
+            path: repo_path.into_bytes(),
+        })
+    }
+
+    /// Change the mode of the stage 0 entry of a path, like
+    /// `git update-index --chmod`.
+    ///
+    /// This allows staging a mode-only change (such as toggling the executable
+    /// bit) deliberately, independently of the working directory and of the
+    /// `core.filemode` setting. Only `Blob`, `BlobExecutable` and `Link` are
+    /// accepted, and only for entries which are not submodules.
+    ///
+    /// Spurious mode changes in the working directory can be ignored through
+    /// `core.filemode` or `DiffOptions::ignore_filemode`.
+    pub fn set_mode(&mut self, path: &Path, mode: FileMode) -> Result<(), Error> {
+        path_to_repo_path(path)?;
+        match mode {
+            FileMode::Blob | FileMode::BlobExecutable | FileMode::Link => {}
+            _ => return Err(Error::from_str("only blob and link modes can be set")),
+        }
+        let mut entry = match self.get_path(path, 0) {
+            Some(entry) => entry,
+            None => {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::Index,
+                    format!("'{}' is not in the index", path.display()),
+                ))
+            }
+        };
+        if entry.mode == u32::from(FileMode::Commit) {
+            return Err(Error::from_str("cannot change the mode of a submodule"));
+        }
+        entry.mode = mode.into();
+        self.add(&entry)
+    }
+
     /// Add or update an index entry from a file on disk
     ///
     /// The file path must be relative to the repository's working folder and
@@ -337,6 +556,61 @@ impl Index {
         Ok(())
     }
 
+    /// Add or update index entries matching files in the working directory,
+    /// configured through `IndexUpdateOptions`.
+    ///
+    /// This behaves like `add_all`, but additionally supports a progress
+    /// callback, a dry-run mode and git's pathspec magic (see
+    /// `IndexUpdateOptions` for details).
+    ///
+    /// Returns the paths which were added or updated, or which would have
+    /// been in dry-run mode.
+    ///
+    /// # Example
+    ///
+    /// Preview `git add src/`:
+    ///
+    /// ```no_run
+    /// use git2::{IndexUpdateOptions, Repository};
+    ///
+    /// let repo = Repository::open("/path/to/a/repo").expect("failed to open");
+    /// let mut index = repo.index().expect("cannot get the Index file");
+    /// let mut opts = IndexUpdateOptions::new();
+    /// opts.dry_run(true);
+    /// for path in index.add_all_opts(["src"].iter(), &mut opts).unwrap() {
+    ///     println!("add '{}'", path.display());
+    /// }
+    /// ```
+    pub fn add_all_opts<T, I>(
+        &mut self,
+        pathspecs: I,
+        opts: &mut IndexUpdateOptions<'_>,
+    ) -> Result<Vec<PathBuf>, Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let pathspecs = opts.prepare_pathspecs(pathspecs)?;
+        let (_a, _b, raw_strarray) = crate::util::iter2cstrs_paths(pathspecs)?;
+        let flags = opts.flags.bits() as c_uint;
+        let cb: raw::git_index_matched_path_cb = Some(index_update_cb);
+        let mut data = IndexUpdateCbData {
+            opts,
+            processed: 0,
+            paths: Vec::new(),
+        };
+        unsafe {
+            try_call!(raw::git_index_add_all(
+                self.raw,
+                &raw_strarray,
+                flags,
+                cb,
+                &mut data as *mut _ as *mut c_void
+            ));
+        }
+        Ok(data.paths)
+    }
+
     /// Clear the contents (all the entries) of an index object.
     ///
     /// This clears the index object in memory; changes must be explicitly
@@ -391,6 +665,127 @@ impl Index {
         }
     }
 
+    /// Get the count of resolve-undo entries currently in the index
+    pub fn reuc_len(&self) -> usize {
+        unsafe { raw::git_index_reuc_entrycount(self.raw) as usize }
+    }
+
+    /// Get one of the resolve-undo entries in the index by its position.
+    pub fn reuc_get(&self, n: usize) -> Option<IndexReucEntry> {
+        unsafe { reuc_from_raw(raw::git_index_reuc_get_byindex(self.raw, n as size_t)) }
+    }
+
+    /// Get the resolve-undo entry of a path, if one was recorded.
+    pub fn reuc_get_path(&self, path: &Path) -> Option<IndexReucEntry> {
+        let path = path_to_repo_path(path).unwrap();
+        unsafe { reuc_from_raw(call!(raw::git_index_reuc_get_bypath(self.raw, path))) }
+    }
+
+    /// Get an iterator over the resolve-undo entries in this index.
+    pub fn reuc_iter(&self) -> IndexReucEntries<'_> {
+        IndexReucEntries {
+            range: 0..self.reuc_len(),
+            index: self,
+        }
+    }
+
+    /// Add or update a resolve-undo entry.
+    ///
+    /// If an entry for the same path already exists, it is replaced.
+    pub fn reuc_add(&mut self, entry: &IndexReucEntry) -> Result<(), Error> {
+        let path = CString::new(&entry.path[..])?;
+        let side = |side: &Option<(u32, Oid)>| match *side {
+            Some((mode, ref id)) => (mode as c_int, id.raw()),
+            None => (0, ptr::null()),
+        };
+        let (ancestor_mode, ancestor_id) = side(&entry.ancestor);
+        let (our_mode, our_id) = side(&entry.our);
+        let (their_mode, their_id) = side(&entry.their);
+        unsafe {
+            try_call!(raw::git_index_reuc_add(
+                self.raw,
+                path,
+                ancestor_mode,
+                ancestor_id,
+                our_mode,
+                our_id,
+                their_mode,
+                their_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove the resolve-undo entry at the given position.
+    pub fn reuc_remove(&mut self, n: usize) -> Result<(), Error> {
+        unsafe {
+            try_call!(raw::git_index_reuc_remove(self.raw, n as size_t));
+        }
+        Ok(())
+    }
+
+    /// Remove all resolve-undo entries from the index.
+    pub fn reuc_clear(&mut self) {
+        unsafe { raw::git_index_reuc_clear(self.raw) }
+    }
+
+    /// Recreate the conflict of a path from its resolve-undo entry.
+    ///
+    /// This is the index part of `git checkout -m -- <path>`: any entry of the
+    /// path is replaced by the conflicting stages recorded when the conflict
+    /// was resolved, and the resolve-undo entry is removed. To also recreate
+    /// the conflict markers in the working directory, check out the path
+    /// afterwards, e.g. through `Repository::checkout_index`.
+    ///
+    /// Returns an error with the `NotFound` code if no resolve-undo entry
+    /// exists for the path.
+    pub fn reopen_conflict(&mut self, path: &Path) -> Result<(), Error> {
+        let cpath = path_to_repo_path(path)?;
+        let reuc = match self.reuc_get_path(path) {
+            Some(reuc) => reuc,
+            None => {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::Index,
+                    format!("no resolve-undo information for '{}'", path.display()),
+                ))
+            }
+        };
+
+        let mut flags = cpath.as_bytes().len() as u16;
+        if flags > raw::GIT_INDEX_ENTRY_NAMEMASK {
+            flags = raw::GIT_INDEX_ENTRY_NAMEMASK;
+        }
+        let stage = |side: &Option<(u32, Oid)>| {
+            side.as_ref().map(|&(mode, ref id)| unsafe {
+                let mut entry: raw::git_index_entry = mem::zeroed();
+                entry.mode = mode;
+                entry.id = *id.raw();
+                entry.flags = flags;
+                entry.path = cpath.as_ptr();
+                entry
+            })
+        };
+        let ancestor = stage(&reuc.ancestor);
+        let our = stage(&reuc.our);
+        let their = stage(&reuc.their);
+        let as_ptr = |entry: &Option<raw::git_index_entry>| {
+            entry.as_ref().map(|e| e as *const _).unwrap_or(ptr::null())
+        };
+        unsafe {
+            try_call!(raw::git_index_conflict_add(
+                self.raw,
+                as_ptr(&ancestor),
+                as_ptr(&our),
+                as_ptr(&their)
+            ));
+            let mut pos = 0;
+            try_call!(raw::git_index_reuc_find(&mut pos, self.raw, cpath));
+            try_call!(raw::git_index_reuc_remove(self.raw, pos));
+        }
+        Ok(())
+    }
+
     /// Get one of the entries in the index by its path.
     pub fn get_path(&self, path: &Path, stage: i32) -> Option<IndexEntry> {
         let path = path_to_repo_path(path).unwrap();
@@ -552,6 +947,43 @@ impl Index {
         Ok(())
     }
 
+    /// Update all index entries to match the working directory, configured
+    /// through `IndexUpdateOptions`.
+    ///
+    /// This behaves like `update_all`, but additionally supports a progress
+    /// callback, a dry-run mode and git's pathspec magic. The `flags` of the
+    /// options are ignored.
+    ///
+    /// Returns the paths which were updated or removed, or which would have
+    /// been in dry-run mode.
+    pub fn update_all_opts<T, I>(
+        &mut self,
+        pathspecs: I,
+        opts: &mut IndexUpdateOptions<'_>,
+    ) -> Result<Vec<PathBuf>, Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let pathspecs = opts.prepare_pathspecs(pathspecs)?;
+        let (_a, _b, raw_strarray) = crate::util::iter2cstrs_paths(pathspecs)?;
+        let cb: raw::git_index_matched_path_cb = Some(index_update_cb);
+        let mut data = IndexUpdateCbData {
+            opts,
+            processed: 0,
+            paths: Vec::new(),
+        };
+        unsafe {
+            try_call!(raw::git_index_update_all(
+                self.raw,
+                &raw_strarray,
+                cb,
+                &mut data as *mut _ as *mut c_void
+            ));
+        }
+        Ok(data.paths)
+    }
+
     /// Write an existing index object from memory back to disk using an atomic
     /// file lock.
     pub fn write(&mut self) -> Result<(), Error> {
@@ -561,6 +993,96 @@ impl Index {
         Ok(())
     }
 
+    /// Write an existing index object from memory back to disk, waiting for
+    /// any concurrent lock on the index file to be released.
+    ///
+    /// This behaves like `write`, but if the index is locked by another
+    /// process the write is retried as configured by `opts` instead of failing
+    /// right away. An error with the `Locked` code is returned if the lock is
+    /// not released in time.
+    pub fn write_opts(&mut self, opts: &LockOptions) -> Result<(), Error> {
+        let lock = self.lock_path()?;
+        opts.retry(&lock, || self.write())
+    }
+
+    /// Take the lock on the on-disk file of this index, waiting for another
+    /// process to release it as configured by `opts`.
+    ///
+    /// Holding the lock keeps other tools, libgit2 included, from modifying
+    /// the index, and `write` fails while it is held. Use `write_locked` to
+    /// write the index and release the lock.
+    /// An error with the `Locked` code is returned if the lock could not be
+    /// taken in time. This fails for in-memory indexes.
+    pub fn lock(&self, opts: &LockOptions) -> Result<LockFile, Error> {
+        match self.path() {
+            Some(path) => LockFile::acquire(path, opts),
+            None => Err(Error::from_str("an in-memory index cannot be locked")),
+        }
+    }
+
+    /// Write this index back to disk through `lock`, the lock on its file
+    /// taken with `lock` or `Repository::lock_index`, and release the lock.
+    ///
+    /// The lock is held until the new index has replaced the old one, so no
+    /// other process can modify the index in between.
+    pub fn write_locked(&mut self, mut lock: LockFile) -> Result<(), Error> {
+        if Some(lock.path()) != self.path() {
+            return Err(Error::from_str(&format!(
+                "'{}' is not the lock of this index",
+                lock.lock_path().display()
+            )));
+        }
+        // libgit2 takes `<file>.lock` itself to write an index, so the held
+        // lock is made a valid empty index and opened as an index of its
+        // own. libgit2 then writes it under `index.lock.lock` and renames the
+        // result over the lock file, which exists the whole time.
+        lock.write_all(EMPTY_INDEX).map_err(|e| {
+            Error::from_str(&format!(
+                "failed to write '{}': {}",
+                lock.lock_path().display(),
+                e
+            ))
+        })?;
+        self.write_copy(lock.lock_path())?;
+        lock.commit()?;
+        self.read(false)
+    }
+
+    /// Writes a copy of this index over the index file at `path`.
+    fn write_copy(&self, path: &Path) -> Result<(), Error> {
+        let mut copy = Index::open(path)?;
+        copy.clear()?;
+        copy.set_version(self.version())?;
+        for entry in self.iter() {
+            copy.add(&entry)?;
+        }
+        for entry in self.reuc_iter() {
+            copy.reuc_add(&entry)?;
+        }
+        unsafe {
+            for n in 0..raw::git_index_name_entrycount(self.raw) {
+                let name = raw::git_index_name_get_byindex(self.raw, n);
+                try_call!(raw::git_index_name_add(
+                    copy.raw,
+                    (*name).ancestor as *const c_char,
+                    (*name).ours as *const c_char,
+                    (*name).theirs as *const c_char
+                ));
+            }
+        }
+        copy.write()
+    }
+
+    fn lock_path(&self) -> Result<PathBuf, Error> {
+        let path = match self.path() {
+            Some(path) => path,
+            None => return Err(Error::from_str("an in-memory index cannot be locked")),
+        };
+        let mut lock = OsString::from(path);
+        lock.push(".lock");
+        Ok(PathBuf::from(lock))
+    }
+
     /// Write the index as a tree.
     ///
     /// This method will scan the index and write a representation of its
@@ -597,6 +1119,140 @@ impl Index {
     }
 }
 
+impl<'cb> Default for IndexUpdateOptions<'cb> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'cb> IndexUpdateOptions<'cb> {
+    /// Creates a new set of options with the default flags, no callbacks and
+    /// dry-run mode disabled.
+    pub fn new() -> IndexUpdateOptions<'cb> {
+        IndexUpdateOptions {
+            flags: IndexAddOption::DEFAULT,
+            dry_run: false,
+            pathspec_magic: false,
+            progress: None,
+            matched: None,
+        }
+    }
+
+    /// Set the flags used when adding files, see `Index::add_all`.
+    ///
+    /// These are ignored by `Index::update_all_opts`.
+    pub fn flags(&mut self, flags: IndexAddOption) -> &mut IndexUpdateOptions<'cb> {
+        self.flags = flags;
+        self
+    }
+
+    /// When enabled, no entry of the index is modified; the matching paths
+    /// are only reported back to the caller.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut IndexUpdateOptions<'cb> {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Interpret git's pathspec magic in the given pathspecs.
+    ///
+    /// The supported magic is `:(top)` (or `:/`), `:(exclude)` (or `:!` and
+    /// `:^`), `:(literal)` and `:(glob)`. Excluding pathspecs are applied
+    /// regardless of their position in the list. Any other magic results in
+    /// an error.
+    pub fn pathspec_magic(&mut self, enabled: bool) -> &mut IndexUpdateOptions<'cb> {
+        self.pathspec_magic = enabled;
+        self
+    }
+
+    /// Callback invoked for each matching item before it is processed.
+    ///
+    /// This has the same semantics as the callback given to `Index::add_all`:
+    /// return 0 to process the item, > 0 to skip it and < 0 to abort the scan.
+    /// Skipped items are neither counted as processed nor reported back.
+    pub fn matched_cb<F>(&mut self, cb: F) -> &mut IndexUpdateOptions<'cb>
+    where
+        F: FnMut(&Path, &[u8]) -> i32 + 'cb,
+    {
+        self.matched = Some(Box::new(cb));
+        self
+    }
+
+    /// Callback invoked with the number of entries processed so far.
+    ///
+    /// Return `false` to abort the scan, in which case an error is returned.
+    pub fn progress<F>(&mut self, cb: F) -> &mut IndexUpdateOptions<'cb>
+    where
+        F: FnMut(&Path, usize) -> bool + 'cb,
+    {
+        self.progress = Some(Box::new(cb));
+        self
+    }
+
+    fn prepare_pathspecs<T, I>(&self, pathspecs: I) -> Result<Vec<CString>, Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let pathspecs = pathspecs
+            .into_iter()
+            .map(|p| p.into_c_string())
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.pathspec_magic {
+            util::pathspec_magic(pathspecs)
+        } else {
+            Ok(pathspecs)
+        }
+    }
+}
+
+impl<'a, 'cb> IndexUpdateCbData<'a, 'cb> {
+    fn call(&mut self, path: &Path, matched_pathspec: &[u8]) -> c_int {
+        if let Some(ref mut matched) = self.opts.matched {
+            let r = matched(path, matched_pathspec);
+            if r != 0 {
+                return r as c_int;
+            }
+        }
+        self.processed += 1;
+        if let Some(ref mut progress) = self.opts.progress {
+            if !progress(path, self.processed) {
+                return -1;
+            }
+        }
+        self.paths.push(path.to_path_buf());
+        if self.opts.dry_run {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+impl IndexLock {
+    /// Take the lock on the on-disk file of `index`, waiting for another
+    /// process to release it as configured by `opts`.
+    ///
+    /// An error with the `Locked` code is returned if the lock could not be
+    /// taken in time. This fails for in-memory indexes.
+    pub fn acquire(index: &Index, opts: &IndexLockOptions) -> Result<IndexLock, Error> {
+        index.lock(opts).map(|lock| IndexLock { lock })
+    }
+
+    /// Get the path of the lock file.
+    pub fn path(&self) -> &Path {
+        self.lock.lock_path()
+    }
+
+    /// Write `index` back to disk through this lock and release it.
+    ///
+    /// The lock is held until the new index has replaced the old one, so no
+    /// other process can modify the index in between. See
+    /// `Index::write_locked`.
+    pub fn commit(self, index: &mut Index) -> Result<(), Error> {
+        index.write_locked(self.lock)
+    }
+}
+
 impl Binding for Index {
     type Raw = *mut raw::git_index;
     unsafe fn from_raw(raw: *mut raw::git_index) -> Index {
@@ -638,6 +1294,28 @@ extern "C" fn index_matched_path_cb(
     }
 }
 
+extern "C" fn index_update_cb(
+    path: *const c_char,
+    matched_pathspec: *const c_char,
+    payload: *mut c_void,
+) -> c_int {
+    unsafe {
+        let path = CStr::from_ptr(path).to_bytes();
+        // libgit2 gives no pathspec when everything is matched
+        let matched_pathspec = if matched_pathspec.is_null() {
+            &[][..]
+        } else {
+            CStr::from_ptr(matched_pathspec).to_bytes()
+        };
+
+        panic::wrap(|| {
+            let data = &mut *(payload as *mut IndexUpdateCbData<'_, '_>);
+            data.call(util::bytes2path(path), matched_pathspec)
+        })
+        .unwrap_or(-1)
+    }
+}
+
 impl Drop for Index {
     fn drop(&mut self) {
         unsafe { raw::git_index_free(self.raw) }
@@ -657,6 +1335,35 @@ impl<'index> Iterator for IndexEntries<'index> {
     }
 }
 
+impl<'index> Iterator for IndexReucEntries<'index> {
+    type Item = IndexReucEntry;
+    fn next(&mut self) -> Option<IndexReucEntry> {
+        self.range.next().map(|i| self.index.reuc_get(i).unwrap())
+    }
+}
+
+unsafe fn reuc_from_raw(raw: *const raw::git_index_reuc_entry) -> Option<IndexReucEntry> {
+    if raw.is_null() {
+        return None;
+    }
+    let side = |i: usize| {
+        if (*raw).mode[i] == 0 {
+            None
+        } else {
+            Some((
+                (*raw).mode[i],
+                Binding::from_raw(&(*raw).oid[i] as *const _),
+            ))
+        }
+    };
+    Some(IndexReucEntry {
+        path: CStr::from_ptr((*raw).path).to_bytes().to_vec(),
+        ancestor: side(0),
+        our: side(1),
+        their: side(2),
+    })
+}
+
 impl<'index> Iterator for IndexConflicts<'index> {
     type Item = Result<IndexConflict, Error>;
     fn next(&mut self) -> Option<Result<IndexConflict, Error>> {
@@ -743,9 +1450,11 @@ impl Binding for IndexEntry {
 mod tests {
     use std::fs::{self, File};
     use std::path::Path;
+    use std::time::Duration;
     use tempfile::TempDir;
 
-    use crate::{Index, IndexEntry, IndexTime, Oid, Repository, ResetType};
+    use crate::{ErrorCode, IndexEntryExtendedFlag, IndexLock, LockOptions, ResetType};
+    use crate::{FileMode, Index, IndexEntry, IndexReucEntry, IndexTime, Oid, Repository};
 
     #[test]
     fn smoke() {
@@ -815,6 +1524,44 @@ mod tests {
         assert!(called);
     }
 
+    #[test]
+    fn add_all_opts() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+
+        let root = repo.path().parent().unwrap();
+        fs::create_dir(&root.join("foo")).unwrap();
+        File::create(&root.join("foo/bar")).unwrap();
+        File::create(&root.join("foo/baz")).unwrap();
+        File::create(&root.join("qux")).unwrap();
+
+        let mut seen = Vec::new();
+        let mut opts = crate::IndexUpdateOptions::new();
+        opts.dry_run(true).pathspec_magic(true).progress(|_, n| {
+            seen.push(n);
+            true
+        });
+        let paths = index
+            .add_all_opts(["foo", ":!foo/baz"].iter(), &mut opts)
+            .unwrap();
+        drop(opts);
+        assert_eq!(paths, vec![Path::new("foo/bar").to_path_buf()]);
+        assert_eq!(seen, vec![1]);
+        assert!(index.is_empty());
+
+        let mut opts = crate::IndexUpdateOptions::new();
+        let paths = index.add_all_opts(["*"].iter(), &mut opts).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(index.len(), 3);
+
+        fs::remove_file(&root.join("qux")).unwrap();
+        let mut opts = crate::IndexUpdateOptions::new();
+        opts.dry_run(true);
+        let paths = index.update_all_opts(["*"].iter(), &mut opts).unwrap();
+        assert_eq!(paths, vec![Path::new("qux").to_path_buf()]);
+        assert_eq!(index.len(), 3);
+    }
+
     #[test]
     fn smoke_add() {
         let (_td, repo) = crate::test::repo_init();
@@ -875,6 +1622,163 @@ mod tests {
         assert_eq!(b.content(), content);
     }
 
+    #[test]
+    fn add_from_reader() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+
+        let content = vec![b'x'; 100_000];
+        let id = index
+            .add_from_reader(Path::new("foo/big"), &content[..], FileMode::BlobExecutable)
+            .unwrap();
+        let e = index.get_path(Path::new("foo/big"), 0).unwrap();
+        assert_eq!(e.id, id);
+        assert_eq!(e.mode, u32::from(FileMode::BlobExecutable));
+        assert_eq!(e.file_size, 100_000);
+        assert_eq!(repo.find_blob(id).unwrap().content(), &content[..]);
+
+        let mut index = Index::new().unwrap();
+        assert!(index
+            .add_from_reader(Path::new("foo"), &b"bar"[..], FileMode::Blob)
+            .is_err());
+    }
+
+    #[test]
+    fn reuc() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        assert_eq!(index.reuc_len(), 0);
+        assert!(index.reopen_conflict(Path::new("foo")).is_err());
+
+        let base = repo.blob(b"base").unwrap();
+        let ours = repo.blob(b"ours").unwrap();
+        index
+            .reuc_add(&IndexReucEntry {
+                path: b"foo".to_vec(),
+                ancestor: Some((0o100644, base)),
+                our: Some((0o100644, ours)),
+                their: None,
+            })
+            .unwrap();
+        assert_eq!(index.reuc_len(), 1);
+        let reuc = index.reuc_get_path(Path::new("foo")).unwrap();
+        assert_eq!(reuc.path, b"foo");
+        assert_eq!(reuc.ancestor, Some((0o100644, base)));
+        assert_eq!(reuc.our, Some((0o100644, ours)));
+        assert_eq!(reuc.their, None);
+        assert_eq!(index.reuc_iter().count(), 1);
+
+        index.reopen_conflict(Path::new("foo")).unwrap();
+        assert_eq!(index.reuc_len(), 0);
+        assert!(index.has_conflicts());
+        let conflict = index.conflicts().unwrap().next().unwrap().unwrap();
+        assert_eq!(conflict.ancestor.unwrap().id, base);
+        assert_eq!(conflict.our.unwrap().id, ours);
+        assert!(conflict.their.is_none());
+
+        // resolving the conflict records the resolve-undo entry again
+        let mut e = entry();
+        e.path = b"foo".to_vec();
+        index.add_frombuffer(&e, b"resolved").unwrap();
+        assert!(!index.has_conflicts());
+        assert_eq!(index.reuc_len(), 1);
+        index.reuc_clear();
+        assert_eq!(index.reuc_len(), 0);
+    }
+
+    #[test]
+    fn lock() {
+        let (td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        assert!(Index::new().unwrap().lock(&LockOptions::new()).is_err());
+
+        let mut opts = LockOptions::new();
+        opts.timeout(Duration::from_millis(50))
+            .retry_interval(Duration::from_millis(10));
+        let lock = index.lock(&opts).unwrap();
+        let lock_path = lock.lock_path().to_path_buf();
+        assert!(lock_path.exists());
+        let err = index.lock(&opts).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Locked);
+        assert_eq!(
+            index.write_opts(&opts).unwrap_err().code(),
+            ErrorCode::Locked
+        );
+        fs::write(td.path().join("two"), "two").unwrap();
+        index.add_path(Path::new("two")).unwrap();
+        assert_eq!(index.write().unwrap_err().code(), ErrorCode::Locked);
+        index.write_locked(lock).unwrap();
+        assert!(!lock_path.exists());
+        let on_disk = Index::open(&repo.path().join("index")).unwrap();
+        assert!(on_disk.get_path(Path::new("two"), 0).is_some());
+        assert!(index.lock(&opts).unwrap().commit().is_err());
+
+        let lock = IndexLock::acquire(&index, &opts).unwrap();
+        assert_eq!(lock.path(), lock_path);
+        assert!(IndexLock::acquire(&Index::new().unwrap(), &opts).is_err());
+        index.remove_path(Path::new("two")).unwrap();
+        lock.commit(&mut index).unwrap();
+        let on_disk = Index::open(&repo.path().join("index")).unwrap();
+        assert!(on_disk.get_path(Path::new("two"), 0).is_none());
+        let mut names = fs::read_dir(repo.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("index"))
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["index"]);
+
+        // a lock left behind is taken over once it is stale
+        let lock = index.lock(&opts).unwrap();
+        let path = lock.lock_path().to_path_buf();
+        std::mem::forget(lock);
+        assert!(!opts.is_stale(&path));
+        opts.stale_after(Duration::from_secs(0));
+        assert!(opts.is_stale(&path));
+        index.write_opts(&opts).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn add_intent() {
+        let (td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        File::create(&td.path().join("foo")).unwrap();
+        index.add_intent(Path::new("foo")).unwrap();
+        index.write().unwrap();
+
+        let e = index.get_path(Path::new("foo"), 0).unwrap();
+        let flags = IndexEntryExtendedFlag::from_bits_truncate(e.flags_extended);
+        assert!(flags.is_intent_to_add());
+        assert_eq!(e.mode, 0o100644);
+        let empty = repo.find_blob(e.id).unwrap();
+        assert_eq!(empty.size(), 0);
+
+        let index = repo.index().unwrap();
+        let e = index.get_path(Path::new("foo"), 0).unwrap();
+        let flags = IndexEntryExtendedFlag::from_bits_truncate(e.flags_extended);
+        assert!(flags.is_intent_to_add());
+    }
+
+    #[test]
+    fn set_mode() {
+        let (td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        assert!(index.set_mode(Path::new("foo"), FileMode::Blob).is_err());
+
+        File::create(&td.path().join("foo")).unwrap();
+        index.add_path(Path::new("foo")).unwrap();
+        assert!(index.set_mode(Path::new("foo"), FileMode::Tree).is_err());
+        index
+            .set_mode(Path::new("foo"), FileMode::BlobExecutable)
+            .unwrap();
+        let e = index.get_path(Path::new("foo"), 0).unwrap();
+        assert_eq!(e.mode, 0o100755);
+        index.set_mode(Path::new("foo"), FileMode::Blob).unwrap();
+        let e = index.get_path(Path::new("foo"), 0).unwrap();
+        assert_eq!(e.mode, 0o100644);
+    }
+
     fn entry() -> IndexEntry {
         IndexEntry {
             ctime: IndexTime::new(0, 0),
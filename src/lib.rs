@@ -76,62 +76,102 @@ use libgit2_sys as raw;
 
 use std::ffi::{CStr, CString};
 use std::fmt;
+use std::path::PathBuf;
 use std::str;
 use std::sync::Once;
 
-pub use crate::apply::{ApplyLocation, ApplyOptions};
+pub use crate::apply::{ApplyLocation, ApplyOptions, RejectedFile, RejectedHunk};
+#[cfg(feature = "async")]
+pub use crate::async_remote::{AsyncRemote, RemoteEvent, RemoteOperation};
 pub use crate::attr::AttrValue;
-pub use crate::blame::{Blame, BlameHunk, BlameIter, BlameOptions};
+pub use crate::blame::{Blame, BlameHunk, BlameIter, BlameOptions, BlameSummary};
 pub use crate::blob::{Blob, BlobWriter};
-pub use crate::branch::{Branch, Branches};
+pub use crate::blob_paths::BlobPathCache;
+pub use crate::branch::{Branch, BranchName, Branches};
 pub use crate::buf::Buf;
 pub use crate::cherrypick::CherrypickOptions;
+pub use crate::combined_diff::{CombinedDiff, CombinedFile, CombinedHunk, CombinedLine};
 pub use crate::commit::{Commit, Parents};
+pub use crate::commit_search::{CommitQuery, CommitSearchIndex, CommitSearchOptions};
 pub use crate::config::{Config, ConfigEntries, ConfigEntry};
+pub use crate::content_scan::{ContentScanner, ScanCallback, ScanChunk, ScanFinding};
 pub use crate::cred::{Cred, CredentialHelper};
+pub use crate::decoration::{Decoration, DecorationKind, Decorations};
 pub use crate::describe::{Describe, DescribeFormatOptions, DescribeOptions};
-pub use crate::diff::{Deltas, Diff, DiffDelta, DiffFile, DiffOptions};
-pub use crate::diff::{DiffBinary, DiffBinaryFile, DiffBinaryKind};
-pub use crate::diff::{DiffFindOptions, DiffHunk, DiffLine, DiffLineType, DiffStats};
+pub use crate::diff::DiffDriver;
+pub use crate::diff::{BinaryPatch, BinaryPatchFile, DiffBinary, DiffBinaryFile, DiffBinaryKind};
+pub use crate::diff::{Deltas, Diff, DiffContent, DiffDelta, DiffFile, DiffOptions};
+pub use crate::diff::{DiffFileStats, DiffShortStat, DiffStats};
+pub use crate::diff::{DiffFindOptions, DiffFindStats, DiffHunk, DiffLine, DiffLineType};
 pub use crate::error::Error;
 pub use crate::index::{
-    Index, IndexConflict, IndexConflicts, IndexEntries, IndexEntry, IndexMatchedPath,
+    Index, IndexConflict, IndexConflicts, IndexEntries, IndexEntry, IndexLock, IndexLockOptions,
+    IndexMatchedPath, IndexReucEntries, IndexReucEntry, IndexUpdateOptions, IndexUpdateProgress,
 };
 pub use crate::indexer::{IndexerProgress, Progress};
+pub use crate::lockfile::{LockFile, LockOptions};
 pub use crate::mempack::Mempack;
-pub use crate::merge::{AnnotatedCommit, MergeOptions};
+pub use crate::merge::{
+    AnnotatedCommit, CommitMessageTemplate, FetchHeadEntry, MergeConflict, MergeFileInput,
+    MergeFileStatus, MergeOptions, MergeWithBase,
+};
 pub use crate::message::{message_prettify, DEFAULT_COMMENT_CHAR};
+pub use crate::metadata::{MetadataResolve, MetadataStore, MetadataValue};
+pub use crate::moved_lines::MovedLines;
 pub use crate::note::{Note, Notes};
 pub use crate::object::Object;
+pub use crate::object_builder::{BlobObjectBuilder, CommitObjectBuilder, TreeObjectBuilder};
 pub use crate::odb::{Odb, OdbObject, OdbPackwriter, OdbReader, OdbWriter};
+pub use crate::odb_cache::{CacheMetrics, MemoryCache, ObjectCache};
 pub use crate::oid::Oid;
 pub use crate::packbuilder::{PackBuilder, PackBuilderStage};
-pub use crate::patch::Patch;
+pub use crate::packed_refs::{PackedRef, PackedRefs, PackedRefsView};
+pub use crate::patch::{IntralineChange, IntralineGranularity, Patch};
 pub use crate::pathspec::{Pathspec, PathspecFailedEntries, PathspecMatchList};
 pub use crate::pathspec::{PathspecDiffEntries, PathspecEntries};
+pub use crate::pickaxe::{Pickaxe, PickaxeWalk};
+pub use crate::policy::{Policy, PolicyCheck, PolicyRule, PolicyViolation, RefUpdate};
+pub use crate::prompt_info::PromptInfo;
 pub use crate::proxy_options::ProxyOptions;
-pub use crate::rebase::{Rebase, RebaseOperation, RebaseOperationType, RebaseOptions};
+pub use crate::push_update::PushUpdate;
+pub use crate::rebase::{
+    Rebase, RebaseConflicts, RebaseOperation, RebaseOperationType, RebaseOptions,
+};
+pub use crate::rebase_todo::{RebaseTodo, RebaseTodoCommand, RebaseTodoItem};
+pub use crate::ref_delta::{RefDelta, RefDeltaReport};
 pub use crate::reference::{Reference, ReferenceNames, References};
 pub use crate::reflog::{Reflog, ReflogEntry, ReflogIter};
 pub use crate::refspec::Refspec;
 pub use crate::remote::{
     FetchOptions, PushOptions, Refspecs, Remote, RemoteConnection, RemoteHead,
 };
-pub use crate::remote_callbacks::{Credentials, RemoteCallbacks};
+pub use crate::remote_callbacks::{Credentials, PushNegotiation, RemoteCallbacks};
 pub use crate::remote_callbacks::{TransportMessage, UpdateTips};
 pub use crate::repo::{Repository, RepositoryInitOptions};
+pub use crate::repo_lock::{RepositoryReadGuard, RepositoryWriteGuard};
 pub use crate::revert::RevertOptions;
 pub use crate::revspec::Revspec;
 pub use crate::revwalk::Revwalk;
+pub use crate::sequencer::{Sequencer, SequencerOutcome};
 pub use crate::signature::Signature;
 pub use crate::stash::{StashApplyOptions, StashApplyProgressCb, StashCb};
-pub use crate::status::{StatusEntry, StatusIter, StatusOptions, StatusShow, Statuses};
-pub use crate::submodule::{Submodule, SubmoduleUpdateOptions};
+pub use crate::status::{
+    PorcelainV2Options, StatusCb, StatusEntry, StatusIter, StatusOptions, StatusShow, Statuses,
+    UntrackedEntry,
+};
+pub use crate::status_cache::{ConflictDetail, ConflictKind, StatusCache};
+pub use crate::status_classifier::{
+    PrecedenceClassifier, ShortStatus, StatusChange, StatusClassifier,
+};
+pub use crate::structured_diff::{StructuredDiff, StructuredFile, StructuredHunk, StructuredLine};
+pub use crate::submodule::{Submodule, SubmoduleChange, SubmoduleSummary, SubmoduleUpdateOptions};
 pub use crate::tag::Tag;
 pub use crate::time::{IndexTime, Time};
 pub use crate::tree::{Tree, TreeEntry, TreeIter, TreeWalkMode, TreeWalkResult};
+pub use crate::tree_rules::TreeRules;
 pub use crate::treebuilder::TreeBuilder;
 pub use crate::util::IntoCString;
+pub use crate::walk_checkpoint::{ResumableWalk, WalkCheckpoint};
 pub use crate::worktree::{Worktree, WorktreeAddOptions, WorktreeLockStatus, WorktreePruneOptions};
 
 // Create a convinience method on bitflag struct which checks the given flag
@@ -401,6 +441,46 @@ pub enum FileFavor {
     Union,
 }
 
+/// How conflicts are written into files, as configured by
+/// `merge.conflictStyle`.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum ConflictStyle {
+    /// Only our and their sides of the conflict are written. This is the
+    /// default.
+    Merge,
+    /// The merge base's version is written between our and their sides.
+    Diff3,
+}
+
+impl ConflictStyle {
+    /// Returns the conflict style configured by `merge.conflictStyle`, or
+    /// `Merge` if it is not set.
+    ///
+    /// libgit2 cannot write conflicts in the `zdiff3` style, so an error is
+    /// returned if it is configured.
+    pub fn from_config(config: &Config) -> Result<ConflictStyle, Error> {
+        let style = match config.get_string("merge.conflictStyle") {
+            Ok(style) => style,
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(ConflictStyle::Merge),
+            Err(e) => return Err(e),
+        };
+        match &style[..] {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            "zdiff3" => Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Invalid,
+                "the zdiff3 conflict style is not supported by libgit2",
+            )),
+            _ => Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Config,
+                format!("unknown style '{}' given for 'merge.conflictstyle'", style),
+            )),
+        }
+    }
+}
+
 bitflags! {
     /// Orderings that may be specified for Revwalk iteration.
     pub struct Sort: u32 {
@@ -629,6 +709,68 @@ impl MergePreference {
     is_bit_set!(is_fastforward_only, MergePreference::FASTFORWARD_ONLY);
 }
 
+/// Why a merge cannot be done by fast-forwarding, as reported by
+/// `Repository::no_fast_forward_reason` and `Repository::merge_decision`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoFastForwardReason {
+    /// `HEAD` and the merged commit both have commits since their merge
+    /// base.
+    Diverged,
+    /// `HEAD` and the merged commit have no common ancestor.
+    NoMergeBase,
+    /// Several commits are merged at once, which always takes a merge
+    /// commit.
+    Octopus,
+    /// A fast-forward is possible, but `merge.ff` (or `pull.ff`) is set to
+    /// `false`.
+    Disabled,
+}
+
+/// The action recommended by `Repository::merge_decision` and
+/// `Repository::pull_decision` to merge a commit into `HEAD`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MergeDecision {
+    /// The commit is already merged, there is nothing to do.
+    UpToDate,
+    /// `HEAD` is unborn: its branch should be created at this commit.
+    Unborn(Oid),
+    /// `HEAD` should be fast-forwarded to this commit.
+    FastForward(Oid),
+    /// A merge commit should be created, for this reason.
+    Merge(NoFastForwardReason),
+    /// The merge should be refused, as `merge.ff` (or `pull.ff`) is set to
+    /// `only` and a merge commit would be needed for this reason.
+    Abort(NoFastForwardReason),
+}
+
+/// What `Repository::merge_and_commit` did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The commit was already merged, nothing was done.
+    UpToDate,
+    /// `HEAD` was fast-forwarded to this commit, or its unborn branch was
+    /// created at it.
+    FastForward(Oid),
+    /// This merge commit was created.
+    Merged(Oid),
+    /// The merge stopped on conflicts in these files. The repository is left
+    /// merging, with the conflicts in the index and the working directory
+    /// and the prepared message in `MERGE_MSG`.
+    Conflicts(Vec<PathBuf>),
+}
+
+/// How `Repository::create_branch_and_switch` sets up the upstream of the
+/// branch it creates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetUpstream {
+    /// Don't set an upstream.
+    None,
+    /// Track a branch of the `origin` remote: the one the branch is started
+    /// from if it is one of its remote-tracking branches, and otherwise the
+    /// branch of the same name, which a later push creates.
+    Origin,
+}
+
 #[cfg(test)]
 #[macro_use]
 mod test;
@@ -638,57 +780,93 @@ mod attr;
 mod call;
 mod util;
 
+pub mod backup;
 pub mod build;
 pub mod cert;
+pub mod merge_driver;
 pub mod oid_array;
 pub mod opts;
 pub mod string_array;
+#[cfg(feature = "test-support")]
+pub mod test_support;
 pub mod transport;
 
 mod apply;
+#[cfg(feature = "async")]
+mod async_remote;
 mod blame;
 mod blob;
+mod blob_paths;
 mod branch;
 mod buf;
 mod cherrypick;
+mod combined_diff;
 mod commit;
+mod commit_search;
 mod config;
+mod content_scan;
 mod cred;
+mod decoration;
 mod describe;
 mod diff;
+#[cfg(feature = "serde")]
+mod diff_serde;
 mod error;
+mod function_context;
 mod index;
 mod indexer;
+mod lockfile;
+mod memory_refdb;
 mod mempack;
 mod merge;
 mod message;
+mod metadata;
+mod moved_lines;
+#[cfg(feature = "precompose-unicode")]
+mod nfd;
 mod note;
 mod object;
+mod object_builder;
 mod odb;
+mod odb_cache;
 mod oid;
 mod packbuilder;
+mod packed_refs;
 mod patch;
 mod pathspec;
+mod pickaxe;
+mod policy;
+mod prompt_info;
 mod proxy_options;
+mod push_update;
 mod rebase;
+mod rebase_todo;
+mod ref_delta;
 mod reference;
 mod reflog;
 mod refspec;
 mod remote;
 mod remote_callbacks;
 mod repo;
+mod repo_lock;
 mod revert;
 mod revspec;
 mod revwalk;
+mod sequencer;
 mod signature;
 mod stash;
 mod status;
+mod status_cache;
+mod status_classifier;
+mod structured_diff;
 mod submodule;
 mod tag;
 mod tagforeach;
 mod time;
 mod tree;
+mod tree_rules;
 mod treebuilder;
+mod walk_checkpoint;
 mod worktree;
 
 fn init() {
@@ -1037,6 +1215,7 @@ impl RepositoryInitMode {
 
 /// What type of change is described by a `DiffDelta`?
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Delta {
     /// No changes
     Unmodified,
@@ -1064,6 +1243,7 @@ pub enum Delta {
 
 /// Valid modes for index and tree entries.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FileMode {
     /// Unreadable
     Unreadable,
@@ -1192,8 +1372,29 @@ impl SubmoduleStatus {
     is_bit_set!(is_wd_added, SubmoduleStatus::WD_ADDED);
     is_bit_set!(is_wd_deleted, SubmoduleStatus::WD_DELETED);
     is_bit_set!(is_wd_modified, SubmoduleStatus::WD_MODIFIED);
+    is_bit_set!(is_wd_index_modified, SubmoduleStatus::WD_INDEX_MODIFIED);
     is_bit_set!(is_wd_wd_modified, SubmoduleStatus::WD_WD_MODIFIED);
     is_bit_set!(is_wd_untracked, SubmoduleStatus::WD_UNTRACKED);
+
+    /// Returns whether the submodule has a different commit checked out than
+    /// the one recorded in the index, reported as "new commits" by
+    /// `git status`.
+    pub fn has_new_commits(&self) -> bool {
+        self.contains(SubmoduleStatus::WD_MODIFIED)
+    }
+
+    /// Returns whether tracked files of the submodule are modified, in its
+    /// index or its working directory, reported as "modified content" by
+    /// `git status`.
+    pub fn has_modified_content(&self) -> bool {
+        self.intersects(SubmoduleStatus::WD_INDEX_MODIFIED | SubmoduleStatus::WD_WD_MODIFIED)
+    }
+
+    /// Returns whether the submodule contains untracked files, reported as
+    /// "untracked content" by `git status`.
+    pub fn has_untracked_content(&self) -> bool {
+        self.contains(SubmoduleStatus::WD_UNTRACKED)
+    }
 }
 
 /// Submodule ignore values
@@ -1373,6 +1574,33 @@ pub enum FetchPrune {
     Off,
 }
 
+/// A file holding ignore rules, as edited by `Repository::add_ignore_pattern`
+/// and `Repository::remove_ignore_pattern`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IgnoreFile {
+    /// The repository's `info/exclude` file, which is not shared with other
+    /// clones.
+    InfoExclude,
+    /// The `.gitignore` file in a directory of the working directory, given
+    /// relative to the working directory. An empty path refers to the
+    /// top-level `.gitignore`.
+    Gitignore(PathBuf),
+}
+
+/// How durably a repository writes objects and references, as chosen with
+/// `Repository::set_fsync_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncMode {
+    /// Leave flushing written files to the operating system. After a crash
+    /// or power loss, recently written objects may be missing or truncated
+    /// while references already point at them. This is the default, as in
+    /// git.
+    Buffered,
+    /// Sync each loose object and reference file, and the directory it is
+    /// renamed into, to disk before the write is reported as done.
+    Durable,
+}
+
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub enum StashApplyProgress {
@@ -1457,6 +1685,9 @@ bitflags! {
         const INDEX_ONLY = raw::GIT_ATTR_CHECK_INDEX_ONLY as u32;
         /// Do not use the system gitattributes file.
         const NO_SYSTEM = raw::GIT_ATTR_CHECK_NO_SYSTEM as u32;
+        /// Also check `.gitattributes` files committed in `HEAD`, which
+        /// allows attributes to be looked up in bare repositories.
+        const INCLUDE_HEAD = raw::GIT_ATTR_CHECK_INCLUDE_HEAD as u32;
     }
 }
 
@@ -1523,7 +1754,7 @@ impl Default for ReferenceFormat {
 
 #[cfg(test)]
 mod tests {
-    use super::{FileMode, ObjectType};
+    use super::{ConflictStyle, FileMode, ObjectType};
 
     #[test]
     fn convert() {
@@ -1539,4 +1770,18 @@ mod tests {
         assert_eq!(u32::from(FileMode::Blob), 0o100644);
         assert_eq!(u32::from(FileMode::BlobExecutable), 0o100755);
     }
+
+    #[test]
+    fn conflict_style_from_config() {
+        let (_td, repo) = crate::test::repo_init();
+        let style = || ConflictStyle::from_config(&repo.config().unwrap());
+        assert_eq!(style().unwrap(), ConflictStyle::Merge);
+        let mut config = repo.config().unwrap();
+        config.set_str("merge.conflictStyle", "zdiff3").unwrap();
+        assert!(style().is_err());
+        config.set_str("merge.conflictStyle", "diff3").unwrap();
+        assert_eq!(style().unwrap(), ConflictStyle::Diff3);
+        config.set_str("merge.conflictStyle", "diff4").unwrap();
+        assert!(style().is_err());
+    }
 }
@@ -0,0 +1,319 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{Error, ErrorClass, ErrorCode};
+
+/// A lock on a file in a repository, held through git's `<file>.lock`
+/// convention.
+///
+/// Every git implementation (the command line client and libgit2 alike)
+/// creates `<file>.lock` exclusively before updating a file such as the
+/// index, a reference or the config, and gives up if it already exists.
+/// Holding a `LockFile` therefore keeps other git processes from writing
+/// to the file until the lock is released.
+///
+/// The lock is released when the `LockFile` is dropped. Data written to it
+/// is discarded unless `commit` is called, which atomically replaces the
+/// locked file with the written contents. `Index::write_locked` writes an
+/// index through the lock on its file.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+    written: bool,
+}
+
+/// Options controlling how to wait for a lock held by another process to be
+/// released.
+///
+/// Used by `LockFile::acquire`, the locking helpers of `Repository` and
+/// `Index`, and `Index::write_opts`.
+#[derive(Clone, Debug)]
+pub struct LockOptions {
+    timeout: Duration,
+    retry_interval: Duration,
+    stale_after: Option<Duration>,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockOptions {
+    /// Creates a new set of options which does not wait for the lock at all,
+    /// matching the behavior of libgit2.
+    pub fn new() -> LockOptions {
+        LockOptions {
+            timeout: Duration::from_secs(0),
+            retry_interval: Duration::from_millis(100),
+            stale_after: None,
+        }
+    }
+
+    /// Set how long to wait in total for the lock to be released before
+    /// giving up.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut LockOptions {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how long to sleep between attempts to take the lock.
+    ///
+    /// Defaults to 100 milliseconds.
+    pub fn retry_interval(&mut self, interval: Duration) -> &mut LockOptions {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Consider a lock file stale once it has not been modified for the
+    /// given duration.
+    ///
+    /// A stale lock file is most likely left behind by a process that crashed
+    /// and is removed instead of being waited on. By default lock files are
+    /// never considered stale.
+    pub fn stale_after(&mut self, age: Duration) -> &mut LockOptions {
+        self.stale_after = Some(age);
+        self
+    }
+
+    /// Returns whether the lock file at `lock` exists and is stale according
+    /// to these options.
+    pub fn is_stale(&self, lock: &Path) -> bool {
+        let stale_after = match self.stale_after {
+            Some(age) => age,
+            None => return false,
+        };
+        let modified = match fs::metadata(lock).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(..) => return false,
+        };
+        let now = match crate::opts::injected_time() {
+            Some(time) if time.seconds() >= 0 => {
+                SystemTime::UNIX_EPOCH + Duration::from_secs(time.seconds() as u64)
+            }
+            _ => SystemTime::now(),
+        };
+        match now.duration_since(modified) {
+            Ok(age) => age >= stale_after,
+            // modified in the future, so certainly not stale
+            Err(..) => false,
+        }
+    }
+
+    /// Calls `f` until it does not fail with a `Locked` error, removing the
+    /// lock file at `lock` if it becomes stale, or until the timeout elapses.
+    pub(crate) fn retry<T, F>(&self, lock: &Path, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let start = Instant::now();
+        loop {
+            match f() {
+                Err(ref e) if e.code() == ErrorCode::Locked => {}
+                res => return res,
+            }
+            if self.is_stale(lock) {
+                match fs::remove_file(lock) {
+                    Ok(()) => continue,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => {
+                        return Err(Error::from_str(&format!(
+                            "failed to remove stale lock '{}': {}",
+                            lock.display(),
+                            e
+                        )))
+                    }
+                }
+            }
+            if start.elapsed() >= self.timeout {
+                return Err(Error::new(
+                    ErrorCode::Locked,
+                    ErrorClass::Os,
+                    format!("timed out waiting for '{}' to be released", lock.display()),
+                ));
+            }
+            thread::sleep(self.retry_interval);
+        }
+    }
+}
+
+impl LockFile {
+    /// Acquire the lock for the file at `path`.
+    ///
+    /// If the lock is held by someone else, acquisition is retried as
+    /// configured by `opts`. A Locked error is returned if the lock could not
+    /// be acquired in time.
+    pub fn acquire(path: &Path, opts: &LockOptions) -> Result<LockFile, Error> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = opts.retry(&lock_path, || {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+            {
+                Ok(file) => Ok(file),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Err(Error::new(
+                    ErrorCode::Locked,
+                    ErrorClass::Os,
+                    format!(
+                        "failed to lock '{}': '{}' already exists",
+                        path.display(),
+                        lock_path.display()
+                    ),
+                )),
+                Err(e) => Err(Error::new(
+                    ErrorCode::GenericError,
+                    ErrorClass::Os,
+                    format!("failed to create '{}': {}", lock_path.display(), e),
+                )),
+            }
+        })?;
+        Ok(LockFile {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+            written: false,
+        })
+    }
+
+    /// Returns the path of the file this lock protects.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the `.lock` file itself.
+    pub fn lock_path(&self) -> &Path {
+        &self.lock_path
+    }
+
+    /// Replace the locked file with the data written to this lock, and
+    /// release the lock.
+    ///
+    /// Committing a lock nothing was written to would truncate the locked
+    /// file, so it is refused: the lock is released and an error is returned.
+    pub fn commit(mut self) -> Result<(), Error> {
+        if !self.written {
+            return Err(Error::from_str(&format!(
+                "nothing was written to '{}'",
+                self.lock_path.display()
+            )));
+        }
+        let file = self.file.take().unwrap();
+        let res = file
+            .sync_all()
+            .and_then(|()| {
+                drop(file);
+                fs::rename(&self.lock_path, &self.path)
+            })
+            .map_err(|e| {
+                Error::new(
+                    ErrorCode::GenericError,
+                    ErrorClass::Os,
+                    format!("failed to write '{}': {}", self.path.display(), e),
+                )
+            });
+        if res.is_err() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+        res
+    }
+
+    /// Delete the locked file and release the lock, the way git deletes a
+    /// reference.
+    ///
+    /// Data written to the lock is discarded. It is not an error for the
+    /// locked file not to exist.
+    pub fn remove(self) -> Result<(), Error> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to remove '{}': {}", self.path.display(), e),
+            )),
+        }
+    }
+}
+
+impl Write for LockFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.as_mut().unwrap().write(buf)?;
+        self.written |= n > 0;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockFile, LockOptions};
+    use crate::ErrorCode;
+    use std::fs;
+    use std::io::Write;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn smoke() {
+        let td = TempDir::new().unwrap();
+        let path = td.path().join("file");
+        t!(fs::write(&path, "old"));
+
+        let mut opts = LockOptions::new();
+        opts.timeout(Duration::from_millis(20))
+            .retry_interval(Duration::from_millis(5));
+        let lock = t!(LockFile::acquire(&path, &LockOptions::new()));
+        assert!(lock.lock_path().exists());
+        let err = LockFile::acquire(&path, &opts).err().unwrap();
+        assert_eq!(err.code(), ErrorCode::Locked);
+        drop(lock);
+        assert!(!path.with_extension("lock").exists());
+        assert_eq!(t!(fs::read_to_string(&path)), "old");
+
+        let lock = t!(LockFile::acquire(&path, &opts));
+        assert!(lock.commit().is_err());
+        assert!(!path.with_extension("lock").exists());
+        assert_eq!(t!(fs::read_to_string(&path)), "old");
+
+        let mut lock = t!(LockFile::acquire(&path, &opts));
+        t!(lock.write_all(b"new"));
+        t!(lock.commit());
+        assert_eq!(t!(fs::read_to_string(&path)), "new");
+        assert!(!path.with_extension("lock").exists());
+
+        t!(t!(LockFile::acquire(&path, &opts)).remove());
+        assert!(!path.exists());
+        assert!(!path.with_extension("lock").exists());
+        t!(fs::write(&path, "new"));
+
+        // a lock left behind is taken over once it is stale
+        let lock = t!(LockFile::acquire(&path, &opts));
+        let lock_path = lock.lock_path().to_path_buf();
+        std::mem::forget(lock);
+        assert!(!opts.is_stale(&lock_path));
+        opts.stale_after(Duration::from_secs(0));
+        assert!(opts.is_stale(&lock_path));
+        drop(t!(LockFile::acquire(&path, &opts)));
+        assert!(!lock_path.exists());
+    }
+}
@@ -0,0 +1,606 @@
+//! A reference database kept in memory, used by `Repository::init_in_memory`.
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::sync::Mutex;
+
+use libc::{c_char, c_int, c_void};
+
+use crate::util::Binding;
+use crate::{panic, raw, Error, ErrorClass, ErrorCode, Oid};
+
+#[derive(Clone, PartialEq)]
+enum Target {
+    Direct(Oid),
+    Symbolic(CString),
+}
+
+type Refs = BTreeMap<CString, Target>;
+
+#[repr(C)]
+struct MemoryRefdb {
+    parent: raw::git_refdb_backend,
+    refs: Mutex<Refs>,
+}
+
+#[repr(C)]
+struct MemoryRefIter {
+    parent: raw::git_reference_iterator,
+    refs: Vec<(CString, Target)>,
+    next: usize,
+}
+
+/// Creates a backend holding nothing but `HEAD`, a symbolic reference to the
+/// unborn branch `head`.
+pub(crate) unsafe fn new_backend(head: CString) -> *mut raw::git_refdb_backend {
+    let mut refs = Refs::new();
+    refs.insert(CString::new("HEAD").unwrap(), Target::Symbolic(head));
+    let mut backend = Box::new(MemoryRefdb {
+        parent: mem::zeroed(),
+        refs: Mutex::new(refs),
+    });
+    raw::git_refdb_init_backend(&mut backend.parent, raw::GIT_REFDB_BACKEND_VERSION);
+    backend.parent.exists = Some(refdb_exists);
+    backend.parent.lookup = Some(refdb_lookup);
+    backend.parent.iterator = Some(refdb_iterator);
+    backend.parent.write = Some(refdb_write);
+    backend.parent.rename = Some(refdb_rename);
+    backend.parent.del = Some(refdb_del);
+    backend.parent.compress = Some(refdb_compress);
+    backend.parent.has_log = Some(refdb_has_log);
+    backend.parent.ensure_log = Some(refdb_ensure_log);
+    backend.parent.free = Some(refdb_free);
+    backend.parent.reflog_read = Some(refdb_reflog_read);
+    backend.parent.reflog_write = Some(refdb_reflog_write);
+    backend.parent.reflog_rename = Some(refdb_reflog_rename);
+    backend.parent.reflog_delete = Some(refdb_reflog_delete);
+    backend.parent.lock = Some(refdb_lock);
+    backend.parent.unlock = Some(refdb_unlock);
+    Box::into_raw(backend) as *mut raw::git_refdb_backend
+}
+
+unsafe fn refs<'a>(backend: *mut raw::git_refdb_backend) -> &'a Mutex<Refs> {
+    &(*(backend as *mut MemoryRefdb)).refs
+}
+
+unsafe fn alloc(name: &CStr, target: &Target) -> *mut raw::git_reference {
+    match *target {
+        Target::Direct(id) => raw::git_reference__alloc(name.as_ptr(), id.raw(), ptr::null()),
+        Target::Symbolic(ref target) => {
+            raw::git_reference__alloc_symbolic(name.as_ptr(), target.as_ptr())
+        }
+    }
+}
+
+unsafe fn target_of(reference: *const raw::git_reference) -> Target {
+    match raw::git_reference_type(reference) {
+        raw::GIT_REFERENCE_SYMBOLIC => Target::Symbolic(
+            CStr::from_ptr(raw::git_reference_symbolic_target(reference)).to_owned(),
+        ),
+        _ => Target::Direct(Binding::from_raw(raw::git_reference_target(reference))),
+    }
+}
+
+fn not_found(name: &CStr) -> Error {
+    Error::new(
+        ErrorCode::NotFound,
+        ErrorClass::Reference,
+        format!("reference '{}' not found", name.to_string_lossy()),
+    )
+}
+
+/// Checks that `name` still has the value the caller expects, as given by
+/// an old id or an old symbolic target.
+unsafe fn check_old(
+    refs: &Refs,
+    name: &CStr,
+    old_id: *const raw::git_oid,
+    old_target: *const c_char,
+) -> Result<(), Error> {
+    if old_id.is_null() && old_target.is_null() {
+        return Ok(());
+    }
+    let current = match refs.get(name) {
+        Some(current) => current,
+        None if !old_id.is_null() && Oid::from_raw(old_id).is_zero() => return Ok(()),
+        None => return Err(not_found(name)),
+    };
+    let matches = match *current {
+        Target::Direct(id) => old_target.is_null() && id == Oid::from_raw(old_id),
+        Target::Symbolic(ref target) => {
+            old_id.is_null() && target.as_c_str() == CStr::from_ptr(old_target)
+        }
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorCode::Modified,
+            ErrorClass::Reference,
+            "old reference value does not match",
+        ))
+    }
+}
+
+/// Checks that `name` can be added without one reference name being a
+/// directory of the other, as with `refs/heads/a` and `refs/heads/a/b`.
+fn check_available(refs: &Refs, name: &CStr, force: bool) -> Result<(), Error> {
+    let bytes = name.to_bytes();
+    let conflict = refs.keys().map(|other| other.to_bytes()).find(|other| {
+        let (short, long) = if other.len() < bytes.len() {
+            (*other, bytes)
+        } else {
+            (bytes, *other)
+        };
+        short.len() < long.len() && long.starts_with(short) && long[short.len()] == b'/'
+    });
+    match conflict {
+        Some(other) => Err(Error::new(
+            ErrorCode::Exists,
+            ErrorClass::Reference,
+            format!(
+                "cannot write '{}': it conflicts with the reference '{}'",
+                name.to_string_lossy(),
+                String::from_utf8_lossy(other)
+            ),
+        )),
+        None if !force && refs.contains_key(name) => Err(Error::new(
+            ErrorCode::Exists,
+            ErrorClass::Reference,
+            format!(
+                "failed to write reference '{}': a reference with that name already exists.",
+                name.to_string_lossy()
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Matches `name` against an fnmatch pattern, as libgit2 does for reference
+/// globs: `*` also matches `/`.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| glob_match(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((b'[', rest)) => {
+            let negate = rest.first() == Some(&b'!') || rest.first() == Some(&b'^');
+            let start = if negate { 1 } else { 0 };
+            let end = rest
+                .get(start + 1..)
+                .and_then(|r| r.iter().position(|&b| b == b']'))
+                .map(|i| i + start + 1);
+            let end = match end {
+                Some(end) => end,
+                None => return name.first() == Some(&b'[') && glob_match(rest, &name[1..]),
+            };
+            let (c, name) = match name.split_first() {
+                Some((&c, name)) => (c, name),
+                None => return false,
+            };
+            let set = &rest[start..end];
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == b'-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negate && glob_match(&rest[end + 1..], name)
+        }
+        Some((c, rest)) => name.first() == Some(c) && glob_match(rest, &name[1..]),
+    }
+}
+
+fn report(e: Error) -> c_int {
+    let msg = CString::new(e.message()).unwrap_or_default();
+    unsafe { raw::git_error_set_str(e.raw_class() as c_int, msg.as_ptr()) };
+    e.raw_code()
+}
+
+extern "C" fn refdb_exists(
+    exists: *mut c_int,
+    backend: *mut raw::git_refdb_backend,
+    name: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let refs = refs(backend).lock().unwrap();
+        *exists = refs.contains_key(CStr::from_ptr(name)) as c_int;
+        0
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn refdb_lookup(
+    out: *mut *mut raw::git_reference,
+    backend: *mut raw::git_refdb_backend,
+    name: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let name = CStr::from_ptr(name);
+        let refs = refs(backend).lock().unwrap();
+        match refs.get(name) {
+            Some(target) => {
+                *out = alloc(name, target);
+                if (*out).is_null() {
+                    -1
+                } else {
+                    0
+                }
+            }
+            None => report(not_found(name)),
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn refdb_iterator(
+    out: *mut *mut raw::git_reference_iterator,
+    backend: *mut raw::git_refdb_backend,
+    glob: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let glob = if glob.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(glob).to_bytes())
+        };
+        let refs = refs(backend)
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| {
+                let name = name.to_bytes();
+                // Like the files backend, iterate over `refs/` only.
+                name.starts_with(b"refs/") && glob.map(|g| glob_match(g, name)).unwrap_or(true)
+            })
+            .map(|(name, target)| (name.clone(), target.clone()))
+            .collect();
+        let iter = Box::new(MemoryRefIter {
+            parent: raw::git_reference_iterator {
+                db: ptr::null_mut(),
+                next: Some(iter_next),
+                next_name: Some(iter_next_name),
+                free: Some(iter_free),
+            },
+            refs,
+            next: 0,
+        });
+        *out = Box::into_raw(iter) as *mut raw::git_reference_iterator;
+        0
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn iter_next(
+    out: *mut *mut raw::git_reference,
+    iter: *mut raw::git_reference_iterator,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let iter = &mut *(iter as *mut MemoryRefIter);
+        match iter.refs.get(iter.next) {
+            Some((name, target)) => {
+                iter.next += 1;
+                *out = alloc(name, target);
+                if (*out).is_null() {
+                    -1
+                } else {
+                    0
+                }
+            }
+            None => raw::GIT_ITEROVER as c_int,
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn iter_next_name(
+    out: *mut *const c_char,
+    iter: *mut raw::git_reference_iterator,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let iter = &mut *(iter as *mut MemoryRefIter);
+        match iter.refs.get(iter.next) {
+            Some((name, _)) => {
+                iter.next += 1;
+                *out = name.as_ptr();
+                0
+            }
+            None => raw::GIT_ITEROVER as c_int,
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn iter_free(iter: *mut raw::git_reference_iterator) {
+    let _ = panic::wrap(|| unsafe {
+        drop(Box::from_raw(iter as *mut MemoryRefIter));
+    });
+}
+
+extern "C" fn refdb_write(
+    backend: *mut raw::git_refdb_backend,
+    reference: *const raw::git_reference,
+    force: c_int,
+    _who: *const raw::git_signature,
+    _message: *const c_char,
+    old_id: *const raw::git_oid,
+    old_target: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let name = CStr::from_ptr(raw::git_reference_name(reference));
+        let mut refs = refs(backend).lock().unwrap();
+        let res = check_available(&refs, name, force != 0)
+            .and_then(|()| check_old(&refs, name, old_id, old_target));
+        match res {
+            Ok(()) => {
+                refs.insert(name.to_owned(), target_of(reference));
+                0
+            }
+            Err(e) => report(e),
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn refdb_rename(
+    out: *mut *mut raw::git_reference,
+    backend: *mut raw::git_refdb_backend,
+    old_name: *const c_char,
+    new_name: *const c_char,
+    force: c_int,
+    _who: *const raw::git_signature,
+    _message: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let (old_name, new_name) = (CStr::from_ptr(old_name), CStr::from_ptr(new_name));
+        let mut refs = refs(backend).lock().unwrap();
+        let target = match refs.remove(old_name) {
+            Some(target) => target,
+            None => return report(not_found(old_name)),
+        };
+        if let Err(e) = check_available(&refs, new_name, force != 0) {
+            refs.insert(old_name.to_owned(), target);
+            return report(e);
+        }
+        *out = alloc(new_name, &target);
+        refs.insert(new_name.to_owned(), target);
+        if (*out).is_null() {
+            -1
+        } else {
+            0
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn refdb_del(
+    backend: *mut raw::git_refdb_backend,
+    name: *const c_char,
+    old_id: *const raw::git_oid,
+    old_target: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let name = CStr::from_ptr(name);
+        let mut refs = refs(backend).lock().unwrap();
+        if let Err(e) = check_old(&refs, name, old_id, old_target) {
+            return report(e);
+        }
+        match refs.remove(name) {
+            Some(_) => 0,
+            None => report(not_found(name)),
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn refdb_compress(_backend: *mut raw::git_refdb_backend) -> c_int {
+    0
+}
+
+extern "C" fn refdb_has_log(_backend: *mut raw::git_refdb_backend, _name: *const c_char) -> c_int {
+    0
+}
+
+extern "C" fn refdb_ensure_log(
+    _backend: *mut raw::git_refdb_backend,
+    _name: *const c_char,
+) -> c_int {
+    0
+}
+
+extern "C" fn refdb_free(backend: *mut raw::git_refdb_backend) {
+    let _ = panic::wrap(|| unsafe {
+        drop(Box::from_raw(backend as *mut MemoryRefdb));
+    });
+}
+
+fn no_reflogs() -> c_int {
+    report(Error::new(
+        ErrorCode::NotFound,
+        ErrorClass::Reference,
+        "in-memory repositories do not keep reflogs",
+    ))
+}
+
+extern "C" fn refdb_reflog_read(
+    _out: *mut *mut raw::git_reflog,
+    _backend: *mut raw::git_refdb_backend,
+    _name: *const c_char,
+) -> c_int {
+    panic::wrap(no_reflogs).unwrap_or(-1)
+}
+
+extern "C" fn refdb_reflog_write(
+    _backend: *mut raw::git_refdb_backend,
+    _reflog: *mut raw::git_reflog,
+) -> c_int {
+    panic::wrap(no_reflogs).unwrap_or(-1)
+}
+
+extern "C" fn refdb_reflog_rename(
+    _backend: *mut raw::git_refdb_backend,
+    _old_name: *const c_char,
+    _new_name: *const c_char,
+) -> c_int {
+    0
+}
+
+extern "C" fn refdb_reflog_delete(
+    _backend: *mut raw::git_refdb_backend,
+    _name: *const c_char,
+) -> c_int {
+    0
+}
+
+extern "C" fn refdb_lock(
+    payload: *mut *mut c_void,
+    _backend: *mut raw::git_refdb_backend,
+    name: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let name = Box::new(CStr::from_ptr(name).to_owned());
+        *payload = Box::into_raw(name) as *mut c_void;
+        0
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn refdb_unlock(
+    backend: *mut raw::git_refdb_backend,
+    payload: *mut c_void,
+    success: c_int,
+    _update_reflog: c_int,
+    reference: *const raw::git_reference,
+    _who: *const raw::git_signature,
+    _message: *const c_char,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let name = Box::from_raw(payload as *mut CString);
+        let mut refs = refs(backend).lock().unwrap();
+        // 1 updates the reference, 2 deletes it, and 0 leaves it alone.
+        match success {
+            1 => {
+                refs.insert(*name, target_of(reference));
+            }
+            2 => {
+                refs.remove(&*name);
+            }
+            _ => {}
+        }
+        0
+    })
+    .unwrap_or(-1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+    use crate::{BranchType, ErrorCode, Oid, Repository, Signature};
+
+    #[test]
+    fn glob() {
+        assert!(glob_match(b"refs/heads/*", b"refs/heads/a/b"));
+        assert!(glob_match(b"refs/tags/v?.[0-9]", b"refs/tags/v1.2"));
+        assert!(!glob_match(b"refs/tags/v?.[!0-9]", b"refs/tags/v1.2"));
+        assert!(!glob_match(b"refs/heads/*", b"refs/tags/v1"));
+        assert!(glob_match(b"refs/[x", b"refs/[x"));
+    }
+
+    #[test]
+    fn init_in_memory() {
+        let repo = Repository::init_in_memory().unwrap();
+        assert!(repo.is_bare());
+        assert!(repo.workdir().is_none());
+        assert_eq!(repo.head().err().unwrap().code(), ErrorCode::UnbornBranch);
+
+        let sig = Signature::now("foo", "bar").unwrap();
+        let commit = |update_ref: &str, contents: &str, parents: &[Oid]| {
+            let mut tb = repo.treebuilder(None).unwrap();
+            tb.insert("file", repo.blob(contents.as_bytes()).unwrap(), 0o100644)
+                .unwrap();
+            let tree = repo.find_tree(tb.write().unwrap()).unwrap();
+            let parents = parents
+                .iter()
+                .map(|id| repo.find_commit(*id).unwrap())
+                .collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(Some(update_ref), &sig, &sig, contents, &tree, &parents)
+                .unwrap()
+        };
+        let base = commit("HEAD", "a\nb\nc\n", &[]);
+        assert_eq!(repo.head().unwrap().name(), Some("refs/heads/master"));
+        assert_eq!(repo.head().unwrap().target(), Some(base));
+
+        let base_commit = repo.find_commit(base).unwrap();
+        repo.branch("feature", &base_commit, false).unwrap();
+        assert!(repo.branch("feature", &base_commit, false).is_err());
+        let ours = commit("HEAD", "A\nb\nc\n", &[base]);
+        let theirs = commit("refs/heads/feature", "a\nb\nC\n", &[base]);
+        let branches = repo
+            .branches(Some(BranchType::Local))
+            .unwrap()
+            .map(|b| b.unwrap().0.name().unwrap().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(branches, ["feature", "master"]);
+
+        let (ours_commit, theirs_commit) = (
+            repo.find_commit(ours).unwrap(),
+            repo.find_commit(theirs).unwrap(),
+        );
+        let mut index = repo
+            .merge_commits(&ours_commit, &theirs_commit, None)
+            .unwrap();
+        assert!(!index.has_conflicts());
+        let tree = repo.find_tree(index.write_tree_to(&repo).unwrap()).unwrap();
+        let merge = repo
+            .commit(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                "merge",
+                &tree,
+                &[&ours_commit, &theirs_commit],
+            )
+            .unwrap();
+        let blob = tree.get_name("file").unwrap().id();
+        assert_eq!(repo.find_blob(blob).unwrap().content(), b"A\nb\nC\n");
+
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        let walked = walk.map(|id| id.unwrap()).collect::<Vec<_>>();
+        assert_eq!(walked.len(), 4);
+        assert_eq!(walked[0], merge);
+
+        let names = repo
+            .references_glob("refs/heads/f*")
+            .unwrap()
+            .names()
+            .map(|n| n.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["refs/heads/feature"]);
+        let err = repo
+            .reference_matching("refs/heads/feature", merge, true, base, "move")
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), ErrorCode::Modified);
+        assert!(repo
+            .reference("refs/heads/feature/x", base, false, "")
+            .is_err());
+        repo.find_reference("refs/heads/feature")
+            .unwrap()
+            .delete()
+            .unwrap();
+        assert!(repo.find_reference("refs/heads/feature").is_err());
+
+        let other = Repository::init_in_memory().unwrap();
+        assert!(other.find_commit(base).is_err());
+        assert!(other.references().unwrap().next().is_none());
+    }
+}
@@ -1,11 +1,17 @@
 use libc::c_uint;
+use std::ffi::CString;
+use std::io::Read;
 use std::marker;
 use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::str;
 
 use crate::call::Convert;
+use crate::util;
 use crate::util::Binding;
-use crate::{raw, Commit, FileFavor, Oid};
+use crate::{raw, Commit, ConflictKind, ConflictStyle, Error, FileFavor, Index, IndexEntry, Oid};
+use crate::{Pathspec, PathspecFlags};
 
 /// A structure to represent an annotated commit, the input to merge and rebase.
 ///
@@ -17,9 +23,72 @@ pub struct AnnotatedCommit<'repo> {
     _marker: marker::PhantomData<Commit<'repo>>,
 }
 
+/// An entry of `FETCH_HEAD`, as returned by
+/// `Repository::fetch_head_entries`.
+pub struct FetchHeadEntry<'repo> {
+    pub(crate) ref_name: Option<Vec<u8>>,
+    pub(crate) remote_url: Option<Vec<u8>>,
+    pub(crate) is_for_merge: bool,
+    pub(crate) commit: AnnotatedCommit<'repo>,
+}
+
 /// Options to specify when merging.
 pub struct MergeOptions {
     raw: raw::git_merge_options,
+    ancestor_label: Option<CString>,
+    our_label: Option<CString>,
+    their_label: Option<CString>,
+    marker_size: u16,
+    path_favors: Vec<(String, FileFavor)>,
+}
+
+/// How `Repository::merge_and_commit` words the message of a merge commit.
+///
+/// By default the message is the one libgit2 prepares in `MERGE_MSG`, such
+/// as `Merge branch 'topic'`.
+#[derive(Default)]
+pub struct CommitMessageTemplate {
+    message: Option<String>,
+    trailers: Vec<(String, String)>,
+}
+
+/// The result of `Repository::merge_commits_with_base`: the merged index,
+/// along with the merge base it was computed from.
+pub struct MergeWithBase {
+    pub(crate) index: Index,
+    pub(crate) base_tree: Option<Oid>,
+    pub(crate) base_commit: Option<Oid>,
+    pub(crate) bases: Vec<Oid>,
+    pub(crate) depth: usize,
+}
+
+/// A file which conflicted in `Repository::merge_commits_to_tree`, with its
+/// entries on each side of the merge.
+pub struct MergeConflict {
+    pub(crate) ancestor: Option<IndexEntry>,
+    pub(crate) our: Option<IndexEntry>,
+    pub(crate) their: Option<IndexEntry>,
+}
+
+/// One side of a file merged by `Repository::merge_file_stream`.
+pub struct MergeFileInput<'a> {
+    pub(crate) source: MergeFileSource<'a>,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) mode: u32,
+}
+
+pub(crate) enum MergeFileSource<'a> {
+    Blob(Oid),
+    Reader(Box<dyn Read + 'a>),
+}
+
+/// The outcome of `Repository::merge_file_stream`, whose merged contents
+/// were written out as they were produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeFileStatus {
+    pub(crate) automergeable: bool,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) mode: u32,
 }
 
 impl<'repo> AnnotatedCommit<'repo> {
@@ -41,6 +110,55 @@ impl<'repo> AnnotatedCommit<'repo> {
     }
 }
 
+impl<'repo> FetchHeadEntry<'repo> {
+    /// Returns the name of the fetched reference, such as
+    /// `refs/heads/main`.
+    ///
+    /// Returns `None` if the fetch was not of a reference, or if the name is
+    /// not valid utf-8.
+    pub fn ref_name(&self) -> Option<&str> {
+        self.ref_name_bytes().and_then(|s| str::from_utf8(s).ok())
+    }
+
+    /// Returns the name of the fetched reference as bytes.
+    pub fn ref_name_bytes(&self) -> Option<&[u8]> {
+        self.ref_name.as_deref()
+    }
+
+    /// Returns the url of the remote which was fetched from.
+    ///
+    /// Returns `None` if it was not recorded, or if it is not valid utf-8.
+    pub fn remote_url(&self) -> Option<&str> {
+        self.remote_url_bytes().and_then(|s| str::from_utf8(s).ok())
+    }
+
+    /// Returns the url of the remote which was fetched from as bytes.
+    pub fn remote_url_bytes(&self) -> Option<&[u8]> {
+        self.remote_url.as_deref()
+    }
+
+    /// Returns `true` if this is a head to merge, as opposed to a reference
+    /// marked `not-for-merge`.
+    pub fn is_for_merge(&self) -> bool {
+        self.is_for_merge
+    }
+
+    /// Returns the id of the fetched commit.
+    pub fn id(&self) -> Oid {
+        self.commit.id()
+    }
+
+    /// Returns the fetched commit, ready to be merged.
+    pub fn commit(&self) -> &AnnotatedCommit<'repo> {
+        &self.commit
+    }
+
+    /// Returns the fetched commit, consuming the entry.
+    pub fn into_commit(self) -> AnnotatedCommit<'repo> {
+        self.commit
+    }
+}
+
 impl Default for MergeOptions {
     fn default() -> Self {
         Self::new()
@@ -52,6 +170,11 @@ impl MergeOptions {
     pub fn new() -> MergeOptions {
         let mut opts = MergeOptions {
             raw: unsafe { mem::zeroed() },
+            ancestor_label: None,
+            our_label: None,
+            their_label: None,
+            marker_size: 0,
+            path_favors: Vec::new(),
         };
         assert_eq!(unsafe { raw::git_merge_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -119,6 +242,21 @@ impl MergeOptions {
         self
     }
 
+    /// Specify a side to favor for resolving conflicts in the files matching
+    /// `pathspec`, in place of the one set by `file_favor`.
+    ///
+    /// This is used by `Repository::merge_trees` and
+    /// `Repository::merge_commits`, which merge the contents of such files
+    /// again with this favor when they conflict. When several pathspecs
+    /// match a file, the one given last wins. Files resolved by a merge
+    /// driver, such as the `ours` driver of `merge_driver::register_builtins`
+    /// set with a `merge=ours` attribute, do not conflict, so the attribute
+    /// takes precedence.
+    pub fn path_file_favor(&mut self, pathspec: &str, favor: FileFavor) -> &mut MergeOptions {
+        self.path_favors.push((pathspec.to_string(), favor));
+        self
+    }
+
     fn file_flag(&mut self, opt: u32, val: bool) -> &mut MergeOptions {
         if val {
             self.raw.file_flags |= opt;
@@ -168,10 +306,295 @@ impl MergeOptions {
         self.file_flag(raw::GIT_MERGE_FILE_DIFF_MINIMAL as u32, minimal)
     }
 
+    /// Write conflicts in the given style.
+    pub fn conflict_style(&mut self, style: ConflictStyle) -> &mut MergeOptions {
+        let diff3 = style == ConflictStyle::Diff3;
+        self.file_flag(raw::GIT_MERGE_FILE_STYLE_MERGE as u32, !diff3);
+        self.file_flag(raw::GIT_MERGE_FILE_STYLE_DIFF3 as u32, diff3)
+    }
+
+    /// Set the label of the merge base in diff3-style conflicts written by
+    /// `Repository::merge_file_stream`, instead of its path.
+    pub fn ancestor_label(&mut self, label: &str) -> &mut MergeOptions {
+        self.ancestor_label = Some(CString::new(label).unwrap());
+        self
+    }
+
+    /// Set the label of our side in conflicts written by
+    /// `Repository::merge_file_stream`, instead of its path.
+    pub fn our_label(&mut self, label: &str) -> &mut MergeOptions {
+        self.our_label = Some(CString::new(label).unwrap());
+        self
+    }
+
+    /// Set the label of their side in conflicts written by
+    /// `Repository::merge_file_stream`, instead of its path.
+    pub fn their_label(&mut self, label: &str) -> &mut MergeOptions {
+        self.their_label = Some(CString::new(label).unwrap());
+        self
+    }
+
+    /// Set the length of the conflict markers written by
+    /// `Repository::merge_file_stream` (default 7), like the
+    /// `conflict-marker-size` attribute.
+    pub fn marker_size(&mut self, size: u16) -> &mut MergeOptions {
+        self.marker_size = size;
+        self
+    }
+
     /// Acquire a pointer to the underlying raw options.
     pub unsafe fn raw(&self) -> *const raw::git_merge_options {
         &self.raw as *const _
     }
+
+    /// Returns the file-level part of these options, as used to merge the
+    /// contents of a single file.
+    pub(crate) fn file_options(&self) -> raw::git_merge_file_options {
+        let mut opts: raw::git_merge_file_options = unsafe { mem::zeroed() };
+        opts.version = raw::GIT_MERGE_FILE_OPTIONS_VERSION;
+        opts.favor = self.raw.file_favor;
+        opts.flags = self.raw.file_flags;
+        opts.marker_size = self.marker_size;
+        let label = |label: &Option<CString>| label.as_ref().map_or(ptr::null(), |l| l.as_ptr());
+        opts.ancestor_label = label(&self.ancestor_label);
+        opts.our_label = label(&self.our_label);
+        opts.their_label = label(&self.their_label);
+        opts
+    }
+
+    pub(crate) fn has_path_favors(&self) -> bool {
+        !self.path_favors.is_empty()
+    }
+
+    /// Returns the favor given to `path` by `path_file_favor`, if any.
+    pub(crate) fn path_favor(&self, path: &Path) -> Result<Option<FileFavor>, Error> {
+        for (pathspec, favor) in self.path_favors.iter().rev() {
+            let pathspec = Pathspec::new(Some(pathspec.as_str()))?;
+            if pathspec.matches_path(path, PathspecFlags::DEFAULT) {
+                return Ok(Some(*favor));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns a copy of these options for merging a single file, with
+    /// `favor` in place of the file favor.
+    pub(crate) fn with_file_favor(&self, favor: FileFavor) -> MergeOptions {
+        let mut opts = MergeOptions {
+            raw: unsafe { std::ptr::read(&self.raw) },
+            ancestor_label: self.ancestor_label.clone(),
+            our_label: self.our_label.clone(),
+            their_label: self.their_label.clone(),
+            marker_size: self.marker_size,
+            path_favors: Vec::new(),
+        };
+        opts.file_favor(favor);
+        opts
+    }
+
+    pub(crate) fn is_no_recursive(&self) -> bool {
+        self.raw.flags & raw::GIT_MERGE_NO_RECURSIVE as u32 != 0
+    }
+
+    pub(crate) fn recursion_limit_value(&self) -> u32 {
+        self.raw.recursion_limit
+    }
+
+    /// Returns the options libgit2 uses to merge the merge bases of a
+    /// recursive merge into a virtual one: conflicts never fail the merge,
+    /// and conflicting file contents are kept with their conflict markers.
+    pub(crate) fn for_virtual_base(&self) -> MergeOptions {
+        // GIT_MERGE_FILE_FAVOR__CONFLICTED, which libgit2 keeps private.
+        const FAVOR_CONFLICTED: raw::git_merge_file_favor_t = 4;
+        let mut opts = MergeOptions {
+            raw: unsafe { std::ptr::read(&self.raw) },
+            ancestor_label: None,
+            our_label: None,
+            their_label: None,
+            marker_size: 0,
+            path_favors: Vec::new(),
+        };
+        opts.fail_on_conflict(false);
+        opts.raw.file_favor = FAVOR_CONFLICTED;
+        opts
+    }
+}
+
+impl<'a> MergeFileInput<'a> {
+    /// Merge the contents of the blob `id`, read from the object database.
+    pub fn blob(id: Oid) -> MergeFileInput<'a> {
+        MergeFileInput {
+            source: MergeFileSource::Blob(id),
+            path: None,
+            mode: 0o100644,
+        }
+    }
+
+    /// Merge the contents read from `reader`, which are streamed into the
+    /// object database as a blob rather than read into memory.
+    pub fn reader<R: Read + 'a>(reader: R) -> MergeFileInput<'a> {
+        MergeFileInput {
+            source: MergeFileSource::Reader(Box::new(reader)),
+            path: None,
+            mode: 0o100644,
+        }
+    }
+
+    /// Set the path of this side of the file, used to label its conflict
+    /// markers and to pick the path of the result.
+    pub fn path<P: AsRef<Path>>(&mut self, path: P) -> &mut MergeFileInput<'a> {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the file mode of this side of the file (default `0o100644`).
+    pub fn mode(&mut self, mode: u32) -> &mut MergeFileInput<'a> {
+        self.mode = mode;
+        self
+    }
+}
+
+impl MergeConflict {
+    /// Returns the entry of the common ancestor, if it had the file.
+    pub fn ancestor(&self) -> Option<&IndexEntry> {
+        self.ancestor.as_ref()
+    }
+
+    /// Returns our entry, if we have the file.
+    pub fn our(&self) -> Option<&IndexEntry> {
+        self.our.as_ref()
+    }
+
+    /// Returns their entry, if they have the file.
+    pub fn their(&self) -> Option<&IndexEntry> {
+        self.their.as_ref()
+    }
+
+    /// Returns the path of the conflicted file, preferring our path when the
+    /// sides renamed it.
+    pub fn path(&self) -> &Path {
+        let entry = self
+            .our
+            .as_ref()
+            .or(self.their.as_ref())
+            .or(self.ancestor.as_ref());
+        match entry {
+            Some(entry) => util::bytes2path(&entry.path),
+            None => Path::new(""),
+        }
+    }
+
+    /// Returns why the file conflicted.
+    ///
+    /// `BothModified` and `BothAdded` are conflicting contents, and the
+    /// other kinds are a file modified on one side and deleted or renamed on
+    /// the other.
+    pub fn kind(&self) -> ConflictKind {
+        ConflictKind::from_stages(
+            self.ancestor.is_some(),
+            self.our.is_some(),
+            self.their.is_some(),
+        )
+    }
+}
+
+impl MergeFileStatus {
+    /// Returns `true` if the file merged without conflicts.
+    pub fn is_automergeable(&self) -> bool {
+        self.automergeable
+    }
+
+    /// Returns the path of the merged file, or `None` if the sides were
+    /// renamed differently or had no path.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the file mode of the merged file.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+}
+
+impl CommitMessageTemplate {
+    /// Creates a template for the default merge message.
+    pub fn new() -> CommitMessageTemplate {
+        CommitMessageTemplate::default()
+    }
+
+    /// Use `message` instead of the default merge message.
+    pub fn message(&mut self, message: &str) -> &mut CommitMessageTemplate {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Add a `key: value` trailer, such as `Signed-off-by`, at the end of
+    /// the message.
+    pub fn trailer(&mut self, key: &str, value: &str) -> &mut CommitMessageTemplate {
+        self.trailers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Returns the message for a merge whose default message is `default`.
+    pub(crate) fn render(&self, default: &str) -> String {
+        let message = self.message.as_deref().unwrap_or(default);
+        let message = message.trim_end_matches('\n');
+        if self.trailers.is_empty() {
+            return format!("{}\n", message);
+        }
+        let last = message.rsplit("\n\n").next().unwrap_or("");
+        let in_trailers =
+            !last.is_empty() && message != last && last.lines().all(crate::cherrypick::is_trailer);
+        let mut out = format!("{}{}", message, if in_trailers { "\n" } else { "\n\n" });
+        for (key, value) in self.trailers.iter() {
+            out.push_str(&format!("{}: {}\n", key, value));
+        }
+        out
+    }
+}
+
+impl MergeWithBase {
+    /// Returns the index produced by the merge, as `merge_commits` would.
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Returns the index produced by the merge, consuming the result.
+    pub fn into_index(self) -> Index {
+        self.index
+    }
+
+    /// Returns the tree used as the common ancestor, or `None` if the
+    /// commits have no merge base.
+    ///
+    /// With several merge bases this is the tree of the virtual merge base,
+    /// which only exists in the object database.
+    pub fn base_tree(&self) -> Option<Oid> {
+        self.base_tree
+    }
+
+    /// Returns the commit used as the common ancestor, or `None` if a
+    /// virtual merge base was used or there is no merge base.
+    pub fn base_commit(&self) -> Option<Oid> {
+        self.base_commit
+    }
+
+    /// Returns whether the common ancestor is a virtual merge base,
+    /// synthesized by merging several merge bases.
+    pub fn is_virtual(&self) -> bool {
+        self.base_tree.is_some() && self.base_commit.is_none()
+    }
+
+    /// Returns the merge bases of the two commits.
+    pub fn bases(&self) -> &[Oid] {
+        &self.bases
+    }
+
+    /// Returns how deep the merges building the virtual merge base recursed,
+    /// which is 0 when there is at most one merge base.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
 }
 
 impl<'repo> Binding for AnnotatedCommit<'repo> {
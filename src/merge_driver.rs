@@ -0,0 +1,313 @@
+//! Interfaces for adding custom merge drivers to libgit2
+//!
+//! Besides the `text`, `binary` and `union` drivers of libgit2, the `ours`
+//! and `theirs` drivers can be added with `register_builtins`: they resolve
+//! a file changed on both sides to our or their version of it, for
+//! `.gitattributes` lines such as `CHANGELOG merge=ours`.
+
+use libc::{c_char, c_int};
+use std::ffi::{CStr, CString};
+use std::mem::{self, ManuallyDrop};
+use std::path::Path;
+
+use crate::util::{self, Binding};
+use crate::{panic, raw, AttrCheckFlags, AttrValue, Blob, Error, Repository};
+
+/// What a merge driver made of the files it was asked to merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeDriverResult {
+    /// The files were merged into these contents.
+    Merged(Vec<u8>),
+    /// The files could not be merged, and are left as a conflict.
+    Conflict,
+    /// The driver does not handle these files, which are merged as text
+    /// instead.
+    Passthrough,
+}
+
+/// The three sides of a file which a merge driver is asked to merge.
+pub struct MergeDriverSource<'a> {
+    repo: &'a Repository,
+    driver: &'a str,
+    path: &'a [u8],
+    ancestor: Option<Blob<'a>>,
+    ours: Option<Blob<'a>>,
+    theirs: Option<Blob<'a>>,
+}
+
+/// A registered merge driver, which must use `#[repr(C)]` to ensure that the
+/// C fields come first.
+#[repr(C)]
+struct MergeDriverData {
+    raw: raw::git_merge_driver,
+    apply: Box<ApplyFn>,
+}
+
+type ApplyFn = dyn Fn(&MergeDriverSource<'_>) -> MergeDriverResult + Send + Sync;
+
+/// Add a custom merge driver under `name`, to be used for the paths whose
+/// `merge` attribute is set to `name`, as by a `*.lock merge=lockfile` line
+/// in `.gitattributes`.
+///
+/// The driver is consulted by `Repository::merge_trees`,
+/// `Repository::merge_commits` and the other merges for each file which
+/// was changed on both sides, in place of the line-based text merge.
+///
+/// This function is unsafe as it needs to be externally synchronized with
+/// merges and with other registrations. A driver cannot be replaced without
+/// unregistering it first.
+pub unsafe fn register<F>(name: &str, driver: F) -> Result<(), Error>
+where
+    F: Fn(&MergeDriverSource<'_>) -> MergeDriverResult + Send + Sync + 'static,
+{
+    crate::init();
+    add(name, Box::new(driver))
+}
+
+/// Registers `driver` without initializing the library first.
+unsafe fn add(name: &str, driver: Box<ApplyFn>) -> Result<(), Error> {
+    let name = CString::new(name)?;
+    let mut data = Box::new(MergeDriverData {
+        raw: raw::git_merge_driver {
+            version: raw::GIT_MERGE_DRIVER_VERSION,
+            initialize: None,
+            shutdown: None,
+            apply: Some(merge_driver_apply),
+        },
+        apply: driver,
+    });
+    try_call!(raw::git_merge_driver_register(name, &mut data.raw));
+    mem::forget(data);
+    Ok(())
+}
+
+/// Remove the merge driver registered under `name`.
+///
+/// The memory of the driver is not reclaimed, and this function is unsafe
+/// for the same reasons as `register`.
+pub unsafe fn unregister(name: &str) -> Result<(), Error> {
+    crate::init();
+    let name = CString::new(name)?;
+    try_call!(raw::git_merge_driver_unregister(name));
+    Ok(())
+}
+
+/// Register the `ours` and `theirs` merge drivers, which resolve a file
+/// changed on both sides to our or their version of it.
+///
+/// A file which was deleted on the chosen side is left as a conflict. This
+/// fails, registering neither driver, if a driver is already registered
+/// under either name. This function is unsafe for the same reasons as
+/// `register`.
+pub unsafe fn register_builtins() -> Result<(), Error> {
+    crate::init();
+    fn side(contents: Option<&[u8]>) -> MergeDriverResult {
+        match contents {
+            Some(contents) => MergeDriverResult::Merged(contents.to_vec()),
+            None => MergeDriverResult::Conflict,
+        }
+    }
+    add("ours", Box::new(|src| side(src.ours())))?;
+    if let Err(e) = add("theirs", Box::new(|src| side(src.theirs()))) {
+        let _ = unregister("ours");
+        return Err(e);
+    }
+    Ok(())
+}
+
+impl<'a> MergeDriverSource<'a> {
+    /// Returns the repository being merged in.
+    pub fn repo(&self) -> &'a Repository {
+        self.repo
+    }
+
+    /// Returns the name of the driver, as set by the `merge` attribute.
+    pub fn driver(&self) -> &'a str {
+        self.driver
+    }
+
+    /// Returns the path of the file being merged.
+    pub fn path(&self) -> &'a Path {
+        util::bytes2path(self.path)
+    }
+
+    /// Returns the contents of the file in the merge base, or `None` if the
+    /// file was added on both sides.
+    pub fn ancestor(&self) -> Option<&[u8]> {
+        self.ancestor.as_ref().map(|blob| blob.content())
+    }
+
+    /// Returns the contents of our side of the file, or `None` if we deleted
+    /// it.
+    pub fn ours(&self) -> Option<&[u8]> {
+        self.ours.as_ref().map(|blob| blob.content())
+    }
+
+    /// Returns the contents of their side of the file, or `None` if they
+    /// deleted it.
+    pub fn theirs(&self) -> Option<&[u8]> {
+        self.theirs.as_ref().map(|blob| blob.content())
+    }
+
+    /// Returns the value of the attribute `name` for the file being merged,
+    /// such as a driver-specific setting next to its `merge` attribute.
+    pub fn attr(&self, name: &str) -> Result<AttrValue<'a>, Error> {
+        let value = self
+            .repo
+            .get_attr_bytes(self.path(), name, AttrCheckFlags::default())?;
+        Ok(AttrValue::from_bytes(value))
+    }
+}
+
+extern "C" fn merge_driver_apply(
+    driver: *mut raw::git_merge_driver,
+    path_out: *mut *const c_char,
+    mode_out: *mut u32,
+    merged_out: *mut raw::git_buf,
+    name: *const c_char,
+    src: *const raw::git_merge_driver_source,
+) -> c_int {
+    let res = panic::wrap(|| unsafe {
+        let data = &*(driver as *const MergeDriverData);
+        let repo = ManuallyDrop::new(Repository::from_raw(raw::git_merge_driver_source_repo(src)));
+        let ancestor = raw::git_merge_driver_source_ancestor(src).as_ref();
+        let ours = raw::git_merge_driver_source_ours(src).as_ref();
+        let theirs = raw::git_merge_driver_source_theirs(src).as_ref();
+        let primary = match ours.or(theirs).or(ancestor) {
+            Some(entry) => entry,
+            None => return raw::GIT_PASSTHROUGH as c_int,
+        };
+        let blob = |entry: Option<&raw::git_index_entry>| match entry {
+            Some(entry) => repo
+                .find_blob(Binding::from_raw(&entry.id as *const _))
+                .map(Some),
+            None => Ok(None),
+        };
+        let (ancestor, ours, theirs) = match (blob(ancestor), blob(ours), blob(theirs)) {
+            (Ok(ancestor), Ok(ours), Ok(theirs)) => (ancestor, ours, theirs),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return e.raw_code(),
+        };
+        let source = MergeDriverSource {
+            repo: &repo,
+            driver: CStr::from_ptr(name).to_str().unwrap_or(""),
+            path: CStr::from_ptr(primary.path).to_bytes(),
+            ancestor,
+            ours,
+            theirs,
+        };
+        match (data.apply)(&source) {
+            MergeDriverResult::Merged(merged) => {
+                let rc = raw::git_buf_set(merged_out, merged.as_ptr() as *const _, merged.len());
+                if rc < 0 {
+                    return rc;
+                }
+                *path_out = primary.path;
+                *mode_out = primary.mode;
+                0
+            }
+            MergeDriverResult::Conflict => raw::GIT_EMERGECONFLICT as c_int,
+            MergeDriverResult::Passthrough => raw::GIT_PASSTHROUGH as c_int,
+        }
+    });
+    res.unwrap_or(-1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MergeDriverResult;
+    use std::fs;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(
+            td.path().join(".gitattributes"),
+            "*.lock merge=test-sorted-lines\n*.txt merge=test-sorted-lines\n",
+        )
+        .unwrap();
+        unsafe {
+            super::register("test-sorted-lines", |src| {
+                if src.path().extension().and_then(|e| e.to_str()) != Some("lock") {
+                    return MergeDriverResult::Passthrough;
+                }
+                assert_eq!(src.driver(), "test-sorted-lines");
+                let mut lines = Vec::new();
+                for side in [src.ours(), src.theirs()].iter() {
+                    let side = String::from_utf8(side.unwrap().to_vec()).unwrap();
+                    lines.extend(side.lines().map(|l| format!("{}\n", l)));
+                }
+                lines.sort();
+                lines.dedup();
+                MergeDriverResult::Merged(lines.concat().into_bytes())
+            })
+            .unwrap();
+        }
+
+        let tree = |contents: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            for name in ["deps.lock", "notes.txt"].iter() {
+                let id = repo.blob(contents.as_bytes()).unwrap();
+                builder.insert(name, id, 0o100644).unwrap();
+            }
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let base = tree("a 1\nc 1\n");
+        let ours = tree("a 1\nb 1\nc 1\n");
+        let theirs = tree("a 1\nb 2\nc 1\n");
+        let index = repo.merge_trees(&base, &ours, &theirs, None).unwrap();
+
+        let conflicts = index.conflicts().unwrap().collect::<Vec<_>>();
+        assert_eq!(conflicts.len(), 1);
+        let conflict = conflicts[0].as_ref().unwrap();
+        assert_eq!(conflict.our.as_ref().unwrap().path, b"notes.txt");
+        let merged = index.get_path("deps.lock".as_ref(), 0).unwrap();
+        let merged = repo.find_blob(merged.id).unwrap();
+        assert_eq!(merged.content(), b"a 1\nb 1\nb 2\nc 1\n");
+
+        unsafe {
+            super::unregister("test-sorted-lines").unwrap();
+        }
+        let index = repo.merge_trees(&base, &ours, &theirs, None).unwrap();
+        assert_eq!(index.conflicts().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn builtins() {
+        let (td, repo) = crate::test::repo_init();
+        unsafe {
+            super::register_builtins().unwrap();
+            assert!(super::register_builtins().is_err());
+        }
+        fs::write(
+            td.path().join(".gitattributes"),
+            "*.ours merge=ours\n*.theirs merge=theirs\n*.union merge=union\n",
+        )
+        .unwrap();
+
+        let names = ["a.ours", "a.theirs", "a.union", "a.txt"];
+        let tree = |contents: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            for name in names.iter() {
+                let id = repo.blob(contents.as_bytes()).unwrap();
+                builder.insert(name, id, 0o100644).unwrap();
+            }
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let base = tree("1\n");
+        let ours = tree("1\nours\n");
+        let theirs = tree("1\ntheirs\n");
+        let index = repo.merge_trees(&base, &ours, &theirs, None).unwrap();
+
+        let conflicts = index.conflicts().unwrap().collect::<Vec<_>>();
+        assert_eq!(conflicts.len(), 1);
+        let conflict = conflicts[0].as_ref().unwrap();
+        assert_eq!(conflict.our.as_ref().unwrap().path, b"a.txt");
+        let merged = |name: &str| {
+            let entry = index.get_path(name.as_ref(), 0).unwrap();
+            repo.find_blob(entry.id).unwrap().content().to_vec()
+        };
+        assert_eq!(merged("a.ours"), b"1\nours\n");
+        assert_eq!(merged("a.theirs"), b"1\ntheirs\n");
+        assert_eq!(merged("a.union"), b"1\nours\ntheirs\n");
+    }
+}
@@ -31,8 +31,32 @@ fn _message_prettify(message: CString, comment_char: Option<u8>) -> Result<Strin
 /// The default comment character for `message_prettify` ('#')
 pub const DEFAULT_COMMENT_CHAR: Option<u8> = Some(b'#');
 
+/// Splits the list of conflicting files at the end of a prepared merge
+/// message off `message`.
+///
+/// libgit2 appends the list to `MERGE_MSG` as a `Conflicts:` line followed
+/// by a tab-indented line per file, without commenting it out like git
+/// does, so it would end up in the commit message. Lists commented out with
+/// `#` are recognized too. The returned list starts with the newline which
+/// precedes it.
+pub(crate) fn split_conflicts(message: &str) -> (&str, &str) {
+    for header in ["\nConflicts:\n", "\n#Conflicts:\n", "\n# Conflicts:\n"].iter() {
+        if let Some(i) = message.rfind(header) {
+            let files = &message[i + header.len()..];
+            if files
+                .lines()
+                .all(|l| l.starts_with('\t') || l.starts_with("#\t"))
+            {
+                return message.split_at(i);
+            }
+        }
+    }
+    (message, "")
+}
+
 #[cfg(test)]
 mod tests {
+    use super::split_conflicts;
     use crate::{message_prettify, DEFAULT_COMMENT_CHAR};
 
     #[test]
@@ -58,4 +82,23 @@ mod tests {
             "1\n"
         );
     }
+    #[test]
+    fn conflicts() {
+        assert_eq!(
+            split_conflicts("fix\nConflicts:\n\ta\n\tb/c\n"),
+            ("fix", "\nConflicts:\n\ta\n\tb/c\n")
+        );
+        assert_eq!(
+            split_conflicts("fix\n\n#Conflicts:\n#\ta\n"),
+            ("fix\n", "\n#Conflicts:\n#\ta\n")
+        );
+        assert_eq!(
+            split_conflicts("fix\n\n# Conflicts:\n#\ta\n"),
+            ("fix\n", "\n# Conflicts:\n#\ta\n")
+        );
+        assert_eq!(
+            split_conflicts("fix\n\nConflicts:\nsome text\n"),
+            ("fix\n\nConflicts:\nsome text\n", "")
+        );
+    }
 }
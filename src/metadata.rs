@@ -0,0 +1,387 @@
+use std::path::Path;
+
+use crate::build::TreeUpdateBuilder;
+use crate::{Commit, Error, ErrorCode, FileMode, Oid, Repository, Signature, Tree};
+
+/// A value which can be stored in a `MetadataStore`.
+///
+/// This is implemented for raw bytes and strings; structured payloads are
+/// stored by implementing it on top of a serialization format such as JSON
+/// or CBOR.
+pub trait MetadataValue: Sized {
+    /// Serialize the value.
+    fn to_bytes(&self) -> Result<Vec<u8>, Error>;
+
+    /// Deserialize a value previously serialized with `to_bytes`.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error>;
+}
+
+/// Callback resolving concurrent updates of the same entry of a
+/// `MetadataStore`.
+///
+/// The arguments are the annotated object, the value another writer stored
+/// concurrently (`None` if it deleted the entry) and the value being stored
+/// (`None` for a deletion). The callback returns the value to store.
+pub type MetadataResolve<'a> =
+    dyn FnMut(Oid, Option<&[u8]>, Option<&[u8]>) -> Result<Option<Vec<u8>>, Error> + 'a;
+
+/// A key/value store attaching small payloads to objects, kept in a notes
+/// reference.
+///
+/// Entries are stored the same way `git notes` stores notes, so they are
+/// fetched, pushed and shown like any other notes, but payloads may contain
+/// arbitrary bytes.
+///
+/// Each update creates a new commit on the notes reference and moves the
+/// reference only if nobody else moved it in the meantime. Otherwise the
+/// update is applied again on top of the concurrent one; if both changed the
+/// same entry, the resolver set with `resolve_conflicts` decides which value
+/// is kept (by default the value being stored wins).
+pub struct MetadataStore<'repo> {
+    repo: &'repo Repository,
+    notes_ref: String,
+    resolve: Option<Box<MetadataResolve<'repo>>>,
+    max_attempts: usize,
+}
+
+impl<'repo> MetadataStore<'repo> {
+    /// Creates a store kept in the notes reference `notes_ref`, such as
+    /// `refs/notes/ci`.
+    pub fn new(repo: &'repo Repository, notes_ref: &str) -> Result<MetadataStore<'repo>, Error> {
+        if !crate::Reference::is_valid_name(notes_ref) {
+            return Err(Error::from_str(&format!(
+                "invalid notes reference '{}'",
+                notes_ref
+            )));
+        }
+        Ok(MetadataStore {
+            repo,
+            notes_ref: notes_ref.to_string(),
+            resolve: None,
+            max_attempts: 10,
+        })
+    }
+
+    /// Returns the name of the notes reference of this store.
+    pub fn notes_ref(&self) -> &str {
+        &self.notes_ref
+    }
+
+    /// Set the callback resolving concurrent updates of the same entry.
+    pub fn resolve_conflicts<F>(&mut self, cb: F) -> &mut MetadataStore<'repo>
+    where
+        F: FnMut(Oid, Option<&[u8]>, Option<&[u8]>) -> Result<Option<Vec<u8>>, Error> + 'repo,
+    {
+        self.resolve = Some(Box::new(cb) as Box<MetadataResolve<'repo>>);
+        self
+    }
+
+    /// Set how many times an update is attempted when the notes reference
+    /// keeps being moved by other writers.
+    ///
+    /// Defaults to 10.
+    pub fn max_attempts(&mut self, attempts: usize) -> &mut MetadataStore<'repo> {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Read the value attached to `id`.
+    pub fn get<T: MetadataValue>(&self, id: Oid) -> Result<Option<T>, Error> {
+        match self.get_bytes(id)? {
+            Some(bytes) => T::from_bytes(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the raw payload attached to `id`.
+    pub fn get_bytes(&self, id: Oid) -> Result<Option<Vec<u8>>, Error> {
+        let tip = match self.tip()? {
+            Some(tip) => tip,
+            None => return Ok(None),
+        };
+        self.read(&tip.tree()?, id)
+    }
+
+    /// Attach `value` to `id`, replacing any previous value.
+    ///
+    /// Returns the id of the new commit of the notes reference.
+    pub fn set<T: MetadataValue>(
+        &mut self,
+        id: Oid,
+        value: &T,
+        signature: &Signature<'_>,
+    ) -> Result<Oid, Error> {
+        let bytes = value.to_bytes()?;
+        self.update(id, Some(bytes), signature)
+    }
+
+    /// Attach the raw payload `value` to `id`, replacing any previous value.
+    ///
+    /// Returns the id of the new commit of the notes reference.
+    pub fn set_bytes(
+        &mut self,
+        id: Oid,
+        value: &[u8],
+        signature: &Signature<'_>,
+    ) -> Result<Oid, Error> {
+        self.update(id, Some(value.to_vec()), signature)
+    }
+
+    /// Remove the value attached to `id`.
+    ///
+    /// Returns `false` if there was no value to remove.
+    pub fn delete(&mut self, id: Oid, signature: &Signature<'_>) -> Result<bool, Error> {
+        if self.get_bytes(id)?.is_none() {
+            return Ok(false);
+        }
+        self.update(id, None, signature)?;
+        Ok(true)
+    }
+
+    /// List the objects which have a value attached.
+    pub fn ids(&self) -> Result<Vec<Oid>, Error> {
+        if self.tip()?.is_none() {
+            return Ok(Vec::new());
+        }
+        self.repo
+            .notes(Some(&self.notes_ref))?
+            .map(|note| note.map(|(_, annotated)| annotated))
+            .collect()
+    }
+
+    fn tip(&self) -> Result<Option<Commit<'repo>>, Error> {
+        match self.repo.find_reference(&self.notes_ref) {
+            Ok(reference) => reference.peel_to_commit().map(Some),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read(&self, tree: &Tree<'_>, id: Oid) -> Result<Option<Vec<u8>>, Error> {
+        match find_entry(tree, id)? {
+            Some((_, blob)) => Ok(Some(self.repo.find_blob(blob)?.content().to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn update(
+        &mut self,
+        id: Oid,
+        value: Option<Vec<u8>>,
+        signature: &Signature<'_>,
+    ) -> Result<Oid, Error> {
+        let tip = self.tip()?;
+        self.update_from(tip, id, value, signature)
+    }
+
+    /// Applies an update on top of `tip`, the commit the notes reference was
+    /// last seen at.
+    fn update_from(
+        &mut self,
+        mut tip: Option<Commit<'repo>>,
+        id: Oid,
+        mut value: Option<Vec<u8>>,
+        signature: &Signature<'_>,
+    ) -> Result<Oid, Error> {
+        let mut expected = None;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let tree = match tip {
+                Some(ref tip) => tip.tree()?,
+                None => {
+                    let empty = self.repo.treebuilder(None)?.write()?;
+                    self.repo.find_tree(empty)?
+                }
+            };
+            let current = self.read(&tree, id)?;
+            // A retry after a concurrent update which also changed this entry.
+            if let Some(expected) = expected.take() {
+                if current != expected {
+                    if let Some(ref mut resolve) = self.resolve {
+                        value = resolve(id, current.as_deref(), value.as_deref())?;
+                    }
+                }
+            }
+
+            let mut update = TreeUpdateBuilder::new();
+            let hex = id.to_string();
+            if let Some((path, _)) = find_entry(&tree, id)? {
+                if value.is_none() || path != hex {
+                    update.remove(path);
+                }
+            }
+            if let Some(ref value) = value {
+                let blob = self.repo.blob(value)?;
+                update.upsert(hex, blob, FileMode::Blob);
+            }
+            let new_tree = self
+                .repo
+                .find_tree(update.create_updated(self.repo, &tree)?)?;
+            let message = match value {
+                Some(_) => format!("Notes added by 'MetadataStore' for {}", id),
+                None => format!("Notes removed by 'MetadataStore' for {}", id),
+            };
+            let parents = tip.iter().collect::<Vec<_>>();
+            let commit = self
+                .repo
+                .commit(None, signature, signature, &message, &new_tree, &parents)?;
+            let res = match tip {
+                Some(ref tip) => {
+                    self.repo
+                        .reference_matching(&self.notes_ref, commit, true, tip.id(), &message)
+                }
+                None => self
+                    .repo
+                    .reference(&self.notes_ref, commit, false, &message),
+            };
+            match res {
+                Ok(_) => return Ok(commit),
+                Err(ref e)
+                    if attempts < self.max_attempts
+                        && (e.code() == ErrorCode::Modified
+                            || e.code() == ErrorCode::Exists
+                            || e.code() == ErrorCode::Locked) =>
+                {
+                    expected = Some(current);
+                    tip = self.tip()?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Finds the entry for the notes of `id` in a notes tree, which may be
+/// fanned out into subdirectories named after the leading digits of ids.
+fn find_entry(tree: &Tree<'_>, id: Oid) -> Result<Option<(String, Oid)>, Error> {
+    let hex = id.to_string();
+    let mut prefix = 0;
+    while prefix < hex.len() {
+        let mut path = String::new();
+        let mut i = 0;
+        while i < prefix {
+            path.push_str(&hex[i..i + 2]);
+            path.push('/');
+            i += 2;
+        }
+        path.push_str(&hex[prefix..]);
+        match tree.get_path(Path::new(&path)) {
+            Ok(entry) => return Ok(Some((path, entry.id()))),
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        prefix += 2;
+    }
+    Ok(None)
+}
+
+impl MetadataValue for Vec<u8> {
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.clone())
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl MetadataValue for String {
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.as_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| Error::from_str("metadata value is not valid utf-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MetadataStore, Oid, Repository, Signature};
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        let sig = Signature::now("ci", "ci@example.com").unwrap();
+
+        let mut store = MetadataStore::new(&repo, "refs/notes/ci").unwrap();
+        assert!(store.get::<String>(head).unwrap().is_none());
+        assert!(store.ids().unwrap().is_empty());
+        assert!(!store.delete(head, &sig).unwrap());
+
+        store
+            .set(head, &"{\"status\":\"ok\"}".to_string(), &sig)
+            .unwrap();
+        assert_eq!(
+            store.get::<String>(head).unwrap().unwrap(),
+            "{\"status\":\"ok\"}"
+        );
+        let note = repo.find_note(Some("refs/notes/ci"), head).unwrap();
+        assert_eq!(note.message(), Some("{\"status\":\"ok\"}"));
+
+        store.set_bytes(head, b"\xa1\x00\x01", &sig).unwrap();
+        assert_eq!(store.get_bytes(head).unwrap().unwrap(), b"\xa1\x00\x01");
+        assert!(store.get::<String>(head).is_err());
+        assert_eq!(store.ids().unwrap(), vec![head]);
+
+        assert!(store.delete(head, &sig).unwrap());
+        assert!(store.get_bytes(head).unwrap().is_none());
+        assert!(MetadataStore::new(&repo, "refs/notes/..").is_err());
+    }
+
+    #[test]
+    fn reads_git_notes() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        let sig = repo.signature().unwrap();
+        repo.note(&sig, &sig, Some("refs/notes/ci"), head, "from notes", false)
+            .unwrap();
+        let mut store = MetadataStore::new(&repo, "refs/notes/ci").unwrap();
+        assert_eq!(store.get::<String>(head).unwrap().unwrap(), "from notes");
+        store.set(head, &"replaced".to_string(), &sig).unwrap();
+        let note = repo.find_note(Some("refs/notes/ci"), head).unwrap();
+        assert_eq!(note.message(), Some("replaced"));
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        let (td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        let sig = repo.signature().unwrap();
+        let other = Repository::open(td.path()).unwrap();
+        let mut concurrent = MetadataStore::new(&other, "refs/notes/ci").unwrap();
+
+        let mut store = MetadataStore::new(&repo, "refs/notes/ci").unwrap();
+        store.set(head, &"base".to_string(), &sig).unwrap();
+        let oid = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+
+        // Another writer moves the notes reference after the tip was read.
+        let stale = store.tip().unwrap();
+        concurrent.set(oid, &"other".to_string(), &sig).unwrap();
+        store
+            .update_from(stale, head, Some(b"ours".to_vec()), &sig)
+            .unwrap();
+        assert_eq!(store.get::<String>(head).unwrap().unwrap(), "ours");
+        assert_eq!(store.get::<String>(oid).unwrap().unwrap(), "other");
+
+        // Both writers change the same entry.
+        store.resolve_conflicts(|_, theirs, ours| {
+            let mut merged = theirs.unwrap().to_vec();
+            merged.extend_from_slice(b"+");
+            merged.extend_from_slice(ours.unwrap());
+            Ok(Some(merged))
+        });
+        let stale = store.tip().unwrap();
+        let theirs = concurrent.set(head, &"theirs".to_string(), &sig).unwrap();
+        store
+            .update_from(stale, head, Some(b"mine".to_vec()), &sig)
+            .unwrap();
+        assert_eq!(store.get::<String>(head).unwrap().unwrap(), "theirs+mine");
+        let tip = store.tip().unwrap().unwrap();
+        assert_eq!(tip.parent_count(), 1);
+        assert_eq!(tip.parent_id(0).unwrap(), theirs);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use crate::{Diff, DiffDelta, DiffLine, Error};
+
+/// The minimum number of alphanumeric characters in a block of lines for it
+/// to count as moved, as in git's `--color-moved`. Shorter blocks, such as
+/// lone closing braces, match by chance far too often.
+const MIN_ALNUM: usize = 20;
+
+/// The blocks of removed and added lines of a diff which are moves of each
+/// other, in the manner of `git diff --color-moved=blocks`.
+///
+/// Created with `Diff::moved_lines`. Each moved block has an id, shared by
+/// the removed lines and the added lines of the same block, so a UI can
+/// de-emphasize moved code or color each block differently.
+#[derive(Debug, Clone, Default)]
+pub struct MovedLines {
+    blocks: HashMap<(char, Vec<u8>, u32), usize>,
+    count: usize,
+}
+
+/// A removed or added line, in the order the diff produces them.
+struct Line {
+    origin: char,
+    path: Vec<u8>,
+    lineno: u32,
+    content: Vec<u8>,
+}
+
+impl MovedLines {
+    pub(crate) fn new(diff: &Diff<'_>) -> Result<MovedLines, Error> {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        diff.foreach(
+            &mut |_, _| true,
+            None,
+            None,
+            Some(&mut |delta, _, line| {
+                let (lines, file, lineno) = match line.origin() {
+                    '-' => (&mut removed, delta.old_file(), line.old_lineno()),
+                    '+' => (&mut added, delta.new_file(), line.new_lineno()),
+                    _ => return true,
+                };
+                let content = line.content();
+                let content = match content.split_last() {
+                    Some((&b'\n', rest)) => rest,
+                    _ => content,
+                };
+                lines.push(Line {
+                    origin: line.origin(),
+                    path: file.path_bytes().unwrap_or_default().to_vec(),
+                    lineno: lineno.unwrap_or(0),
+                    content: content.to_vec(),
+                });
+                true
+            }),
+        )?;
+
+        let mut candidates: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (i, line) in removed.iter().enumerate() {
+            candidates.entry(&line.content[..]).or_default().push(i);
+        }
+        let mut used = vec![false; removed.len()];
+        let mut moves = MovedLines::default();
+        let mut i = 0;
+        while i < added.len() {
+            let mut best = (0, 0);
+            for &start in candidates.get(&added[i].content[..]).into_iter().flatten() {
+                let mut len = 0;
+                while i + len < added.len()
+                    && start + len < removed.len()
+                    && !used[start + len]
+                    && added[i + len].content == removed[start + len].content
+                    && (len == 0
+                        || (follows(&added[i + len - 1], &added[i + len])
+                            && follows(&removed[start + len - 1], &removed[start + len])))
+                {
+                    len += 1;
+                }
+                if len > best.0 {
+                    best = (len, start);
+                }
+            }
+            let (len, start) = best;
+            let alnum = added[i..i + len]
+                .iter()
+                .flat_map(|line| line.content.iter())
+                .filter(|b| b.is_ascii_alphanumeric())
+                .count();
+            if len == 0 || alnum < MIN_ALNUM {
+                i += 1;
+                continue;
+            }
+            let id = moves.count;
+            moves.count += 1;
+            for line in added[i..i + len].iter().chain(&removed[start..start + len]) {
+                let key = (line.origin, line.path.clone(), line.lineno);
+                moves.blocks.insert(key, id);
+            }
+            for used in &mut used[start..start + len] {
+                *used = true;
+            }
+            i += len;
+        }
+        Ok(moves)
+    }
+
+    /// Returns the id of the moved block `line` of `delta` belongs to, or
+    /// `None` if the line was not moved.
+    ///
+    /// Only removed and added lines can be moved.
+    pub fn block(&self, delta: &DiffDelta<'_>, line: &DiffLine<'_>) -> Option<usize> {
+        let (file, lineno) = match line.origin() {
+            '-' => (delta.old_file(), line.old_lineno()?),
+            '+' => (delta.new_file(), line.new_lineno()?),
+            _ => return None,
+        };
+        let path = file.path_bytes().unwrap_or_default().to_vec();
+        self.blocks.get(&(line.origin(), path, lineno)).cloned()
+    }
+
+    /// Returns the number of moved blocks. Block ids range from 0 up to this
+    /// number.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns `true` if no code was moved.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// Whether `next` is the line of the same file right after `line`.
+fn follows(line: &Line, next: &Line) -> bool {
+    line.path == next.path && line.lineno + 1 == next.lineno
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        let block = "let total = compute_total(items);\nreport(total);\n";
+        let old_a = format!("fn one() {{\n{}}}\n", block);
+        let old_b = "fn two() {\n}\n".to_string();
+        fs::write(td.path().join("a"), &old_a).unwrap();
+        fs::write(td.path().join("b"), &old_b).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        index.add_path(Path::new("b")).unwrap();
+        index.write().unwrap();
+        fs::write(td.path().join("a"), "fn one() {\n}\n").unwrap();
+        fs::write(
+            td.path().join("b"),
+            format!("fn two() {{\n{}x();\n}}\n", block),
+        )
+        .unwrap();
+
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        let moves = diff.moved_lines().unwrap();
+        assert_eq!(moves.len(), 1);
+        let mut moved = Vec::new();
+        let mut unmoved = Vec::new();
+        diff.foreach(
+            &mut |_, _| true,
+            None,
+            None,
+            Some(&mut |delta, _, line| {
+                let content = String::from_utf8(line.content().to_vec()).unwrap();
+                match line.moved_block(&delta, &moves) {
+                    Some(id) => moved.push((id, line.origin(), content)),
+                    None if line.origin() != ' ' => unmoved.push(content),
+                    None => {}
+                }
+                true
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            moved,
+            [
+                (0, '-', "let total = compute_total(items);\n".to_string()),
+                (0, '-', "report(total);\n".to_string()),
+                (0, '+', "let total = compute_total(items);\n".to_string()),
+                (0, '+', "report(total);\n".to_string()),
+            ]
+        );
+        assert_eq!(unmoved, ["x();\n"]);
+    }
+}
@@ -0,0 +1,71 @@
+//! Canonical decomposition of Unicode paths, to compare paths whatever their
+//! normalization.
+//!
+//! The file systems of macOS store file names decomposed, while git, with
+//! `core.precomposeunicode`, records them composed. Comparing the
+//! decompositions of two paths tells whether they name the same file.
+
+use unicode_normalization::char::{canonical_combining_class, decompose_canonical};
+
+/// Returns the canonical decomposition (NFD) of `s`, as macOS stores file
+/// names.
+///
+/// Like macOS, this leaves the characters in the ranges U+2000 to U+2FFF,
+/// U+F900 to U+FAFF and U+2F800 to U+2FAFF alone. Combining marks are put
+/// in canonical order, so that two spellings of a character with several
+/// marks decompose the same way.
+pub(crate) fn decompose(s: &str) -> String {
+    if s.is_ascii() {
+        return s.to_string();
+    }
+    let mut out = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        if is_excluded(c) {
+            out.push(c);
+        } else {
+            decompose_canonical(c, |d| out.push(d));
+        }
+    }
+    // Sort each run of combining marks by combining class, keeping marks of
+    // the same class in order.
+    let mut start = 0;
+    while start < out.len() {
+        if canonical_combining_class(out[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let end = out[start..]
+            .iter()
+            .position(|&c| canonical_combining_class(c) == 0)
+            .map_or(out.len(), |n| start + n);
+        out[start..end].sort_by_key(|&c| canonical_combining_class(c));
+        start = end;
+    }
+    out.into_iter().collect()
+}
+
+/// Returns `true` for the characters macOS does not decompose.
+fn is_excluded(c: char) -> bool {
+    match c as u32 {
+        0x2000..=0x2fff | 0xf900..=0xfaff | 0x2f800..=0x2faff => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompose;
+
+    #[test]
+    fn smoke() {
+        assert_eq!(decompose("abc"), "abc");
+        assert_eq!(decompose("caf\u{e9}"), "cafe\u{301}");
+        assert_eq!(decompose("\u{d55c}"), "\u{1112}\u{1161}\u{11ab}");
+        // Marks are put in canonical order: dot below before dot above.
+        assert_eq!(decompose("\u{1e69}"), "s\u{323}\u{307}");
+        assert_eq!(decompose("s\u{307}\u{323}"), "s\u{323}\u{307}");
+        assert_eq!(decompose("\u{1e0b}\u{323}"), "d\u{323}\u{307}");
+        // macOS leaves these alone.
+        assert_eq!(decompose("\u{2126}\u{f900}"), "\u{2126}\u{f900}");
+    }
+}
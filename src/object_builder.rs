@@ -0,0 +1,496 @@
+use std::cmp::Ordering;
+
+use crate::{Error, ErrorClass, ErrorCode, FileMode, ObjectType, Oid, Repository, Signature, Tree};
+
+/// A builder for blob objects, which can compute the id of the blob without
+/// writing it.
+#[derive(Debug, Clone, Default)]
+pub struct BlobObjectBuilder {
+    data: Vec<u8>,
+}
+
+/// A builder for tree objects from their entries, which can compute the id
+/// of the tree without writing it.
+///
+/// Unlike `TreeBuilder`, this builder doesn't involve the repository until
+/// the tree is written, so the id of a tree can be known before deciding
+/// whether to create it. Entries are sorted the way git requires when the
+/// tree is serialized, and are validated before computing its id.
+#[derive(Debug, Clone, Default)]
+pub struct TreeObjectBuilder {
+    entries: Vec<(Vec<u8>, Oid, i32)>,
+}
+
+/// A builder for commit objects, which can compute the id of the commit
+/// without writing it.
+///
+/// The tree, author and committer must be set. The message defaults to
+/// being empty.
+#[derive(Default)]
+pub struct CommitObjectBuilder {
+    tree: Option<Oid>,
+    parents: Vec<Oid>,
+    author: Option<Signature<'static>>,
+    committer: Option<Signature<'static>>,
+    encoding: Option<String>,
+    message: String,
+}
+
+impl BlobObjectBuilder {
+    /// Creates a builder for an empty blob.
+    pub fn new() -> BlobObjectBuilder {
+        BlobObjectBuilder::default()
+    }
+
+    /// Append `data` to the contents of the blob.
+    pub fn append(&mut self, data: &[u8]) -> &mut BlobObjectBuilder {
+        self.data.extend_from_slice(data);
+        self
+    }
+
+    /// Returns the contents of the blob.
+    pub fn content(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Computes the id the blob will have, without writing it.
+    pub fn id_preview(&self) -> Result<Oid, Error> {
+        Oid::hash_object(ObjectType::Blob, &self.data)
+    }
+
+    /// Write the blob to the object database of `repo`, returning its id.
+    pub fn write(&self, repo: &Repository) -> Result<Oid, Error> {
+        repo.odb()?.write(ObjectType::Blob, &self.data)
+    }
+}
+
+impl TreeObjectBuilder {
+    /// Creates a builder for an empty tree.
+    pub fn new() -> TreeObjectBuilder {
+        TreeObjectBuilder::default()
+    }
+
+    /// Creates a builder starting with the entries of `tree`.
+    pub fn from_tree(tree: &Tree<'_>) -> TreeObjectBuilder {
+        let entries = tree
+            .iter()
+            .map(|entry| {
+                (
+                    entry.name_bytes().to_vec(),
+                    entry.id(),
+                    entry.filemode_raw(),
+                )
+            })
+            .collect();
+        TreeObjectBuilder { entries }
+    }
+
+    /// Add an entry named `name`, replacing any entry of the same name.
+    ///
+    /// The name is only checked by `validate`, so that invalid entries can
+    /// be reported along with the others.
+    pub fn insert<N: AsRef<[u8]>>(
+        &mut self,
+        name: N,
+        id: Oid,
+        mode: FileMode,
+    ) -> &mut TreeObjectBuilder {
+        let name = name.as_ref();
+        self.entries.retain(|(n, _, _)| &n[..] != name);
+        self.entries.push((name.to_vec(), id, mode.into()));
+        self
+    }
+
+    /// Remove the entry named `name`, if any.
+    pub fn remove<N: AsRef<[u8]>>(&mut self, name: N) -> &mut TreeObjectBuilder {
+        let name = name.as_ref();
+        self.entries.retain(|(n, _, _)| &n[..] != name);
+        self
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the tree has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Check that every entry has a valid name and mode.
+    ///
+    /// Names may not be empty, `.`, `..` or `.git`, nor contain `/` or NUL
+    /// bytes. Modes must be those of a blob, an executable blob, a symbolic
+    /// link, a tree or a submodule commit.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, _, mode) in self.entries.iter() {
+            let display = String::from_utf8_lossy(name);
+            if name.is_empty()
+                || name == b"."
+                || name == b".."
+                || name.eq_ignore_ascii_case(b".git")
+                || name.iter().any(|&b| b == b'/' || b == 0)
+            {
+                return Err(invalid(
+                    ErrorClass::Tree,
+                    format!("invalid tree entry name '{}'", display),
+                ));
+            }
+            if object_type(*mode).is_none() {
+                return Err(invalid(
+                    ErrorClass::Tree,
+                    format!("invalid mode {:o} for tree entry '{}'", mode, display),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the contents of the tree object, with its entries in git's
+    /// order, after validating them.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.validate()?;
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| entry_cmp(a, b));
+        let mut data = Vec::new();
+        for (name, id, mode) in entries {
+            data.extend_from_slice(format!("{:o} ", mode).as_bytes());
+            data.extend_from_slice(name);
+            data.push(0);
+            data.extend_from_slice(id.as_bytes());
+        }
+        Ok(data)
+    }
+
+    /// Computes the id the tree will have, without writing it.
+    pub fn id_preview(&self) -> Result<Oid, Error> {
+        Oid::hash_object(ObjectType::Tree, &self.to_bytes()?)
+    }
+
+    /// Write the tree to the object database of `repo`, returning its id.
+    ///
+    /// Besides the checks of `validate`, every entry other than a submodule
+    /// must refer to an existing object of the type its mode implies.
+    pub fn write(&self, repo: &Repository) -> Result<Oid, Error> {
+        let data = self.to_bytes()?;
+        let odb = repo.odb()?;
+        for (name, id, mode) in self.entries.iter() {
+            let kind = match object_type(*mode) {
+                Some(ObjectType::Commit) | None => continue,
+                Some(kind) => kind,
+            };
+            match odb.read_header(*id) {
+                Ok((_, found)) if found == kind => {}
+                _ => {
+                    return Err(invalid(
+                        ErrorClass::Tree,
+                        format!(
+                            "tree entry '{}' does not refer to a {}",
+                            String::from_utf8_lossy(name),
+                            kind
+                        ),
+                    ))
+                }
+            }
+        }
+        odb.write(ObjectType::Tree, &data)
+    }
+}
+
+impl CommitObjectBuilder {
+    /// Creates a builder for a commit without parents.
+    pub fn new() -> CommitObjectBuilder {
+        CommitObjectBuilder::default()
+    }
+
+    /// Set the tree of the commit.
+    pub fn tree(&mut self, id: Oid) -> &mut CommitObjectBuilder {
+        self.tree = Some(id);
+        self
+    }
+
+    /// Add a parent to the commit, after those added before.
+    pub fn parent(&mut self, id: Oid) -> &mut CommitObjectBuilder {
+        self.parents.push(id);
+        self
+    }
+
+    /// Set the author of the commit.
+    pub fn author(&mut self, signature: &Signature<'_>) -> &mut CommitObjectBuilder {
+        self.author = Some(signature.to_owned());
+        self
+    }
+
+    /// Set the committer of the commit.
+    pub fn committer(&mut self, signature: &Signature<'_>) -> &mut CommitObjectBuilder {
+        self.committer = Some(signature.to_owned());
+        self
+    }
+
+    /// Set the encoding of the message, recorded in the `encoding` header.
+    pub fn encoding(&mut self, encoding: &str) -> &mut CommitObjectBuilder {
+        self.encoding = Some(encoding.to_string());
+        self
+    }
+
+    /// Set the message of the commit, which is stored as is.
+    pub fn message(&mut self, message: &str) -> &mut CommitObjectBuilder {
+        self.message = message.to_string();
+        self
+    }
+
+    /// Check that the tree, author and committer are set, that parents are
+    /// not repeated, and that the signatures and encoding can be parsed back
+    /// from the commit headers.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.tree.is_none() {
+            return Err(invalid(
+                ErrorClass::Object,
+                "commit has no tree".to_string(),
+            ));
+        }
+        for (i, parent) in self.parents.iter().enumerate() {
+            if self.parents[..i].contains(parent) {
+                return Err(invalid(
+                    ErrorClass::Object,
+                    format!("duplicate parent {}", parent),
+                ));
+            }
+        }
+        for &(header, ref signature) in
+            [("author", &self.author), ("committer", &self.committer)].iter()
+        {
+            let signature = match signature {
+                Some(signature) => signature,
+                None => {
+                    return Err(invalid(
+                        ErrorClass::Object,
+                        format!("commit has no {}", header),
+                    ))
+                }
+            };
+            let bad = |b: &u8| *b == b'<' || *b == b'>' || *b == b'\n' || *b == 0;
+            if signature.name_bytes().iter().any(bad) || signature.email_bytes().iter().any(bad) {
+                return Err(invalid(
+                    ErrorClass::Object,
+                    format!("invalid {} signature", header),
+                ));
+            }
+        }
+        if let Some(ref encoding) = self.encoding {
+            if encoding.is_empty() || encoding.contains('\n') {
+                return Err(invalid(
+                    ErrorClass::Object,
+                    format!("invalid encoding '{}'", encoding),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the contents of the commit object, after validating it.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.validate()?;
+        let mut data = Vec::new();
+        data.extend_from_slice(format!("tree {}\n", self.tree.unwrap()).as_bytes());
+        for parent in self.parents.iter() {
+            data.extend_from_slice(format!("parent {}\n", parent).as_bytes());
+        }
+        signature_header(&mut data, "author", self.author.as_ref().unwrap());
+        signature_header(&mut data, "committer", self.committer.as_ref().unwrap());
+        if let Some(ref encoding) = self.encoding {
+            data.extend_from_slice(format!("encoding {}\n", encoding).as_bytes());
+        }
+        data.push(b'\n');
+        data.extend_from_slice(self.message.as_bytes());
+        Ok(data)
+    }
+
+    /// Computes the id the commit will have, without writing it.
+    pub fn id_preview(&self) -> Result<Oid, Error> {
+        Oid::hash_object(ObjectType::Commit, &self.to_bytes()?)
+    }
+
+    /// Write the commit to the object database of `repo`, returning its id.
+    ///
+    /// Besides the checks of `validate`, the tree and parents must exist in
+    /// `repo`. No reference is updated.
+    pub fn write(&self, repo: &Repository) -> Result<Oid, Error> {
+        let data = self.to_bytes()?;
+        let odb = repo.odb()?;
+        let objects = self.tree.iter().map(|id| (id, ObjectType::Tree));
+        let parents = self.parents.iter().map(|id| (id, ObjectType::Commit));
+        for (id, kind) in objects.chain(parents) {
+            match odb.read_header(*id) {
+                Ok((_, found)) if found == kind => {}
+                _ => {
+                    return Err(invalid(
+                        ErrorClass::Object,
+                        format!("{} is not a {} of this repository", id, kind),
+                    ))
+                }
+            }
+        }
+        odb.write(ObjectType::Commit, &data)
+    }
+}
+
+fn invalid(class: ErrorClass, message: String) -> Error {
+    Error::new(ErrorCode::Invalid, class, &message)
+}
+
+/// Returns the type of object a tree entry with `mode` refers to, or `None`
+/// if the mode is not valid.
+fn object_type(mode: i32) -> Option<ObjectType> {
+    match mode {
+        0o100644 | 0o100755 | 0o120000 => Some(ObjectType::Blob),
+        0o040000 => Some(ObjectType::Tree),
+        0o160000 => Some(ObjectType::Commit),
+        _ => None,
+    }
+}
+
+/// Orders tree entries as git does, comparing the names of trees as if they
+/// ended with `/`.
+fn entry_cmp(a: &(Vec<u8>, Oid, i32), b: &(Vec<u8>, Oid, i32)) -> Ordering {
+    let key = |(name, _, mode): &(Vec<u8>, Oid, i32)| {
+        let tree = if *mode == 0o040000 { Some(b'/') } else { None };
+        name.iter().cloned().chain(tree).collect::<Vec<_>>()
+    };
+    key(a).cmp(&key(b))
+}
+
+fn signature_header(data: &mut Vec<u8>, header: &str, signature: &Signature<'_>) {
+    let when = signature.when();
+    let offset = when.offset_minutes().abs();
+    data.extend_from_slice(header.as_bytes());
+    data.push(b' ');
+    data.extend_from_slice(signature.name_bytes());
+    data.extend_from_slice(b" <");
+    data.extend_from_slice(signature.email_bytes());
+    data.extend_from_slice(
+        format!(
+            "> {} {}{:02}{:02}\n",
+            when.seconds(),
+            when.sign(),
+            offset / 60,
+            offset % 60
+        )
+        .as_bytes(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BlobObjectBuilder, CommitObjectBuilder, ErrorCode, FileMode, Oid, Signature};
+    use crate::{Time, TreeObjectBuilder};
+
+    #[test]
+    fn blob() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut blob = BlobObjectBuilder::new();
+        blob.append(b"hello ").append(b"world");
+        assert_eq!(blob.content(), b"hello world");
+        let id = blob.id_preview().unwrap();
+        assert!(repo.find_blob(id).is_err());
+        assert_eq!(blob.write(&repo).unwrap(), id);
+        assert_eq!(repo.blob(b"hello world").unwrap(), id);
+    }
+
+    #[test]
+    fn tree() {
+        let (_td, repo) = crate::test::repo_init();
+        let blob = repo.blob(b"content").unwrap();
+        let empty = repo.treebuilder(None).unwrap().write().unwrap();
+
+        let mut tree = TreeObjectBuilder::new();
+        tree.insert("b.txt", blob, FileMode::Blob)
+            .insert("b", empty, FileMode::Tree)
+            .insert("b-c", blob, FileMode::BlobExecutable)
+            .insert("a", Oid::zero(), FileMode::Blob)
+            .insert("a", blob, FileMode::Link);
+        assert_eq!(tree.len(), 4);
+
+        let mut expected = repo.treebuilder(None).unwrap();
+        expected.insert("b.txt", blob, 0o100644).unwrap();
+        expected.insert("b", empty, 0o040000).unwrap();
+        expected.insert("b-c", blob, 0o100755).unwrap();
+        expected.insert("a", blob, 0o120000).unwrap();
+        let expected = expected.write().unwrap();
+
+        let id = tree.id_preview().unwrap();
+        assert_eq!(id, expected);
+        assert_eq!(tree.write(&repo).unwrap(), id);
+        let copy = TreeObjectBuilder::from_tree(&repo.find_tree(id).unwrap());
+        assert_eq!(copy.id_preview().unwrap(), id);
+
+        for name in &["", ".", "..", ".GIT", "a/b", "a\0b"] {
+            let mut bad = TreeObjectBuilder::new();
+            bad.insert(name, blob, FileMode::Blob);
+            assert_eq!(bad.validate().unwrap_err().code(), ErrorCode::Invalid);
+            assert!(bad.id_preview().is_err());
+        }
+        let mut bad = TreeObjectBuilder::new();
+        bad.insert("x", blob, FileMode::Unreadable);
+        assert!(bad.validate().is_err());
+        let mut missing = TreeObjectBuilder::new();
+        missing.insert("x", empty, FileMode::Blob);
+        assert!(missing.id_preview().is_ok());
+        assert!(missing.write(&repo).is_err());
+        missing.remove("x");
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn commit() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let author = Signature::new("Author", "author@example.com", &Time::new(1000, -90)).unwrap();
+        let committer = Signature::new("Committer", "c@example.com", &Time::new(2000, 60)).unwrap();
+
+        let mut commit = CommitObjectBuilder::new();
+        commit
+            .tree(tree.id())
+            .parent(head.id())
+            .author(&author)
+            .committer(&committer)
+            .message("subject\n\nbody\n");
+        let id = commit.id_preview().unwrap();
+        assert!(repo.find_commit(id).is_err());
+        let expected = repo
+            .commit(
+                None,
+                &author,
+                &committer,
+                "subject\n\nbody\n",
+                &tree,
+                &[&head],
+            )
+            .unwrap();
+        assert_eq!(id, expected);
+        assert_eq!(commit.write(&repo).unwrap(), id);
+
+        commit.encoding("ISO-8859-1");
+        let written = repo.find_commit(commit.write(&repo).unwrap()).unwrap();
+        assert_eq!(written.message_encoding(), Some("ISO-8859-1"));
+        assert_eq!(written.author().when().offset_minutes(), -90);
+
+        commit.parent(head.id());
+        assert_eq!(commit.validate().unwrap_err().code(), ErrorCode::Invalid);
+        assert!(CommitObjectBuilder::new()
+            .tree(tree.id())
+            .validate()
+            .is_err());
+
+        let mut orphan = CommitObjectBuilder::new();
+        orphan
+            .tree(tree.id())
+            .parent(tree.id())
+            .author(&author)
+            .committer(&committer);
+        assert!(orphan.id_preview().is_ok());
+        assert!(orphan.write(&repo).is_err());
+    }
+}
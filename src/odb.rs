@@ -1,16 +1,21 @@
 use std::io;
 use std::marker;
-use std::mem::MaybeUninit;
+use std::mem::{self, MaybeUninit};
+use std::path::Path;
 use std::ptr;
 use std::slice;
 
+use std::collections::HashSet;
 use std::ffi::CString;
 
 use libc::{c_char, c_int, c_void, size_t};
 
+use crate::odb_cache::{self, ObjectCache};
 use crate::panic;
 use crate::util::Binding;
-use crate::{raw, Error, IndexerProgress, Mempack, Object, ObjectType, Oid, Progress};
+use crate::{
+    raw, Error, ErrorCode, IndexerProgress, IntoCString, Mempack, Object, ObjectType, Oid, Progress,
+};
 
 /// A structure to represent a git object database
 pub struct Odb<'repo> {
@@ -220,6 +225,141 @@ impl<'repo> Odb<'repo> {
         }
     }
 
+    /// Copy the objects `ids` from this object database to `other`, e.g. to
+    /// seed a new repository from a template or create a fork, without
+    /// building and indexing a pack.
+    ///
+    /// If `recursive` is set, the objects reachable from `ids` are copied
+    /// too: the trees and parents of commits, the entries of trees and the
+    /// targets of tags. Commits of submodules are not followed. Objects
+    /// which `other` already has are not copied again, but when copying
+    /// recursively the objects they reference still are, so a commit whose
+    /// tree `other` lacks gets its tree.
+    ///
+    /// Returns the number of objects copied.
+    pub fn copy_objects_to(
+        &self,
+        other: &Odb<'_>,
+        ids: &[Oid],
+        recursive: bool,
+    ) -> Result<usize, Error> {
+        let mut pending = ids.to_vec();
+        let mut seen = HashSet::new();
+        let mut copied = 0;
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let present = other.exists(id);
+            if present && !recursive {
+                continue;
+            }
+            let object = match self.read(id) {
+                Err(ref e) if present && e.code() == ErrorCode::NotFound => other.read(id)?,
+                object => object?,
+            };
+            if recursive {
+                references(object.kind(), object.data(), &mut pending)?;
+            }
+            if !present {
+                other.write(object.kind(), object.data())?;
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Opens the loose objects in `objects_dir` and the pack whose index is
+    /// `pack_index`, but none of the other packs or alternates there, plus
+    /// all objects of the repository whose objects are in `alternate`.
+    pub(crate) fn open_partial(
+        objects_dir: &Path,
+        pack_index: Option<&Path>,
+        alternate: Option<&Path>,
+    ) -> Result<Odb<'static>, Error> {
+        let odb = Odb::new()?;
+        let objects_dir = objects_dir.into_c_string()?;
+        unsafe {
+            let mut backend = ptr::null_mut();
+            try_call!(raw::git_odb_backend_loose(
+                &mut backend,
+                objects_dir,
+                -1,
+                0,
+                0,
+                0
+            ));
+            odb.add_raw_backend(backend, 1)?;
+            if let Some(pack_index) = pack_index {
+                let pack_index = pack_index.into_c_string()?;
+                let mut backend = ptr::null_mut();
+                try_call!(raw::git_odb_backend_one_pack(&mut backend, pack_index));
+                odb.add_raw_backend(backend, 2)?;
+            }
+        }
+        if let Some(alternate) = alternate {
+            let alternate = alternate
+                .to_str()
+                .ok_or_else(|| Error::from_str("alternate path is not valid utf-8"))?;
+            odb.add_disk_alternate(alternate)?;
+        }
+        Ok(odb)
+    }
+
+    unsafe fn add_raw_backend(
+        &self,
+        backend: *mut raw::git_odb_backend,
+        priority: c_int,
+    ) -> Result<(), Error> {
+        let rc = raw::git_odb_add_backend(self.raw, backend, priority);
+        if rc < 0 {
+            (*backend).free.unwrap()(backend);
+            return Err(Error::last_error(rc).unwrap());
+        }
+        Ok(())
+    }
+
+    /// Returns an object which is reachable from `ids` but missing from this
+    /// object database, if there is one. Commits of submodules are not
+    /// followed.
+    pub(crate) fn find_missing(&self, ids: &[Oid]) -> Result<Option<Oid>, Error> {
+        let mut pending = ids.to_vec();
+        let mut seen = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let object = match self.read(id) {
+                Ok(object) => object,
+                Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(Some(id)),
+                Err(e) => return Err(e),
+            };
+            references(object.kind(), object.data(), &mut pending)?;
+        }
+        Ok(None)
+    }
+
+    /// Wrap this object database in a read-through cache.
+    ///
+    /// Returns a new object database whose reads are answered by `cache`
+    /// when possible. Objects read from this database on a cache miss are
+    /// added to the cache; writes and everything else go to this database
+    /// unchanged. Install the result with `Repository::set_odb` to make a
+    /// repository read through the cache.
+    pub fn wrap_with_cache<C>(self, cache: C) -> Result<Odb<'repo>, Error>
+    where
+        C: ObjectCache + Send + 'static,
+    {
+        let wrapped: Odb<'repo> = Odb::new()?;
+        unsafe {
+            let inner = self.raw;
+            mem::forget(self);
+            let backend = odb_cache::new_backend(inner, Box::new(cache));
+            wrapped.add_raw_backend(backend, 1)?;
+        }
+        Ok(wrapped)
+    }
+
     /// Create a new mempack backend, and add it to this odb with the given
     /// priority. Higher values give the backend higher precedence. The default
     /// loose and pack backends have priorities 1 and 2 respectively (hard-coded
@@ -545,6 +685,50 @@ extern "C" fn write_pack_progress_cb(
     }
 }
 
+/// Appends the ids of the objects referenced by an object of type `kind`
+/// with contents `data` to `ids`.
+fn references(kind: ObjectType, data: &[u8], ids: &mut Vec<Oid>) -> Result<(), Error> {
+    match kind {
+        ObjectType::Commit | ObjectType::Tag => {
+            for line in data.split(|&b| b == b'\n') {
+                if line.is_empty() {
+                    break;
+                }
+                let hex = match line.iter().position(|&b| b == b' ') {
+                    Some(n) if &line[..n] == b"tree" || &line[..n] == b"parent" => &line[n + 1..],
+                    Some(n) if &line[..n] == b"object" => &line[n + 1..],
+                    _ => continue,
+                };
+                let hex = std::str::from_utf8(hex).map_err(|_| corrupt(kind))?;
+                ids.push(Oid::from_str(hex)?);
+            }
+        }
+        ObjectType::Tree => {
+            let mut rest = data;
+            while !rest.is_empty() {
+                let nul = rest
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or_else(|| corrupt(kind))?;
+                if rest.len() < nul + 21 {
+                    return Err(corrupt(kind));
+                }
+                // Submodule commits live in another repository.
+                if !rest.starts_with(b"160000 ") {
+                    ids.push(Oid::from_bytes(&rest[nul + 1..nul + 21])?);
+                }
+                rest = &rest[nul + 21..];
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn corrupt(kind: ObjectType) -> Error {
+    Error::from_str(&format!("corrupt {} object", kind))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{Buf, ObjectType, Oid, Repository};
@@ -579,6 +763,46 @@ mod tests {
         assert_eq!(kind, ObjectType::Blob);
     }
 
+    #[test]
+    fn copy_objects_to() {
+        let (_td, src) = crate::test::repo_init();
+        let sig = src.signature().unwrap();
+        let commit = |content: &[u8], parents: &[&crate::Commit<'_>]| {
+            let blob = src.blob(content).unwrap();
+            let mut builder = src.treebuilder(None).unwrap();
+            builder.insert("file", blob, 0o100644).unwrap();
+            let tree = src.find_tree(builder.write().unwrap()).unwrap();
+            let id = src.commit(None, &sig, &sig, "m", &tree, parents).unwrap();
+            (id, tree.id(), blob)
+        };
+        let (c1, t1, b1) = commit(b"one", &[]);
+        let (c2, t2, b2) = commit(b"two", &[&src.find_commit(c1).unwrap()]);
+        let obj = src.find_object(c2, None).unwrap();
+        let tag = src.tag("v1", &obj, &sig, "tag", false).unwrap();
+
+        let td = TempDir::new().unwrap();
+        let dst = Repository::init_bare(td.path()).unwrap();
+        let (src_odb, dst_odb) = (src.odb().unwrap(), dst.odb().unwrap());
+        assert_eq!(src_odb.copy_objects_to(&dst_odb, &[b2], false).unwrap(), 1);
+        assert_eq!(src_odb.copy_objects_to(&dst_odb, &[c2], false).unwrap(), 1);
+        assert!(!dst_odb.exists(t2));
+        assert_eq!(src_odb.copy_objects_to(&dst_odb, &[c2], true).unwrap(), 4);
+        for id in &[c2, t2, b2, c1, t1, b1] {
+            assert!(dst_odb.exists(*id));
+        }
+        assert_eq!(src_odb.copy_objects_to(&dst_odb, &[c2], true).unwrap(), 0);
+
+        let dst = Repository::init_bare(td.path().join("other")).unwrap();
+        let dst_odb = dst.odb().unwrap();
+        let copied = src_odb.copy_objects_to(&dst_odb, &[tag], true).unwrap();
+        assert_eq!(copied, 7);
+        for id in &[tag, c2, t2, b2, c1, t1, b1] {
+            assert!(dst_odb.exists(*id));
+        }
+        let copied = dst.find_commit(c2).unwrap();
+        assert_eq!(copied.parent_id(0).unwrap(), c1);
+    }
+
     #[test]
     fn write() {
         let td = TempDir::new().unwrap();
@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use libc::{c_int, c_void, size_t};
+
+use crate::util::Binding;
+use crate::{panic, raw, ObjectType, OdbObject, Oid};
+
+/// A cache of objects read from an object database, installed with
+/// `Odb::wrap_with_cache`.
+///
+/// Implementations decide what to keep and for how long; `MemoryCache` keeps
+/// recently used objects in memory, and caches on local disk can be built by
+/// implementing this trait. A cache shared as `Arc<Mutex<_>>` can be
+/// inspected while it is in use.
+pub trait ObjectCache {
+    /// Look up an object, returning its type and contents.
+    fn get(&mut self, id: Oid) -> Option<(ObjectType, Vec<u8>)>;
+
+    /// Store an object which was read from the wrapped object database.
+    fn insert(&mut self, id: Oid, kind: ObjectType, data: &[u8]);
+}
+
+/// Counters describing the use of a `MemoryCache`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    /// Lookups answered from the cache.
+    pub hits: u64,
+    /// Lookups which were not in the cache, or had expired.
+    pub misses: u64,
+    /// Objects added to the cache.
+    pub insertions: u64,
+    /// Objects removed from the cache to respect its size limit, or because
+    /// they expired.
+    pub evictions: u64,
+}
+
+/// An `ObjectCache` keeping the most recently used objects in memory, up to
+/// a total size.
+pub struct MemoryCache {
+    max_bytes: usize,
+    ttl: Option<Duration>,
+    bytes: usize,
+    tick: u64,
+    entries: HashMap<Oid, CacheEntry>,
+    lru: BTreeMap<u64, Oid>,
+    metrics: CacheMetrics,
+}
+
+struct CacheEntry {
+    kind: ObjectType,
+    data: Vec<u8>,
+    inserted: Instant,
+    tick: u64,
+}
+
+impl MemoryCache {
+    /// Creates a cache holding at most `max_bytes` bytes of object contents.
+    ///
+    /// Objects larger than `max_bytes` are never cached.
+    pub fn new(max_bytes: usize) -> MemoryCache {
+        MemoryCache {
+            max_bytes,
+            ttl: None,
+            bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            metrics: CacheMetrics::default(),
+        }
+    }
+
+    /// Expire objects `ttl` after they were cached.
+    ///
+    /// Objects never change, so this only matters for caches fronting
+    /// storage where objects may be deleted, such as after a garbage
+    /// collection. By default objects do not expire.
+    pub fn ttl(&mut self, ttl: Duration) -> &mut MemoryCache {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the counters of this cache.
+    pub fn metrics(&self) -> CacheMetrics {
+        self.metrics
+    }
+
+    /// Returns the number of objects in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache holds no objects.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the total size of the objects in the cache.
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    /// Remove every object from the cache.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.bytes = 0;
+    }
+
+    fn remove(&mut self, id: Oid) {
+        if let Some(entry) = self.entries.remove(&id) {
+            self.lru.remove(&entry.tick);
+            self.bytes -= entry.data.len();
+            self.metrics.evictions += 1;
+        }
+    }
+}
+
+impl ObjectCache for MemoryCache {
+    fn get(&mut self, id: Oid) -> Option<(ObjectType, Vec<u8>)> {
+        let expired = match (self.entries.get(&id), self.ttl) {
+            (Some(entry), Some(ttl)) => entry.inserted.elapsed() >= ttl,
+            (Some(_), None) => false,
+            (None, _) => {
+                self.metrics.misses += 1;
+                return None;
+            }
+        };
+        if expired {
+            self.remove(id);
+            self.metrics.misses += 1;
+            return None;
+        }
+        self.tick += 1;
+        let entry = self.entries.get_mut(&id).unwrap();
+        self.lru.remove(&entry.tick);
+        entry.tick = self.tick;
+        self.lru.insert(self.tick, id);
+        self.metrics.hits += 1;
+        Some((entry.kind, entry.data.clone()))
+    }
+
+    fn insert(&mut self, id: Oid, kind: ObjectType, data: &[u8]) {
+        if data.len() > self.max_bytes || self.entries.contains_key(&id) {
+            return;
+        }
+        while self.bytes + data.len() > self.max_bytes {
+            let oldest = match self.lru.iter().next() {
+                Some((_, id)) => *id,
+                None => break,
+            };
+            self.remove(oldest);
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, id);
+        self.bytes += data.len();
+        self.entries.insert(
+            id,
+            CacheEntry {
+                kind,
+                data: data.to_vec(),
+                inserted: Instant::now(),
+                tick: self.tick,
+            },
+        );
+        self.metrics.insertions += 1;
+    }
+}
+
+impl<C: ObjectCache> ObjectCache for Arc<Mutex<C>> {
+    fn get(&mut self, id: Oid) -> Option<(ObjectType, Vec<u8>)> {
+        self.lock().unwrap().get(id)
+    }
+
+    fn insert(&mut self, id: Oid, kind: ObjectType, data: &[u8]) {
+        self.lock().unwrap().insert(id, kind, data)
+    }
+}
+
+/// An odb backend answering reads from a cache, and forwarding everything
+/// else to the wrapped object database.
+#[repr(C)]
+struct CacheBackend {
+    parent: raw::git_odb_backend,
+    inner: *mut raw::git_odb,
+    cache: Box<dyn ObjectCache + Send>,
+}
+
+/// Creates a backend wrapping `inner`, taking ownership of it.
+pub(crate) unsafe fn new_backend(
+    inner: *mut raw::git_odb,
+    cache: Box<dyn ObjectCache + Send>,
+) -> *mut raw::git_odb_backend {
+    let mut backend = Box::new(CacheBackend {
+        parent: std::mem::zeroed(),
+        inner,
+        cache,
+    });
+    raw::git_odb_init_backend(&mut backend.parent, raw::GIT_ODB_BACKEND_VERSION);
+    backend.parent.read = Some(cache_read);
+    backend.parent.read_prefix = Some(cache_read_prefix);
+    backend.parent.read_header = Some(cache_read_header);
+    backend.parent.write = Some(cache_write);
+    backend.parent.exists = Some(cache_exists);
+    backend.parent.exists_prefix = Some(cache_exists_prefix);
+    backend.parent.refresh = Some(cache_refresh);
+    backend.parent.foreach = Some(cache_foreach);
+    backend.parent.writepack = Some(cache_writepack);
+    backend.parent.free = Some(cache_free);
+    Box::into_raw(backend) as *mut raw::git_odb_backend
+}
+
+/// Reads an object through the cache, filling the cache on a miss.
+unsafe fn read_object(
+    backend: &mut CacheBackend,
+    id: Oid,
+    data: *mut *mut c_void,
+    len: *mut size_t,
+    kind: *mut raw::git_object_t,
+) -> c_int {
+    let (object_kind, contents) = match backend.cache.get(id) {
+        Some(hit) => hit,
+        None => {
+            let mut obj = ptr::null_mut();
+            let rc = raw::git_odb_read(&mut obj, backend.inner, id.raw());
+            if rc < 0 {
+                return rc;
+            }
+            let obj: OdbObject<'_> = Binding::from_raw(obj);
+            backend.cache.insert(id, obj.kind(), obj.data());
+            (obj.kind(), obj.data().to_vec())
+        }
+    };
+    // libgit2 takes ownership of the buffer; allocate one byte more so that
+    // empty objects still get a buffer.
+    let buf = raw::git_odb_backend_malloc(&mut backend.parent, contents.len() + 1);
+    if buf.is_null() {
+        return -1;
+    }
+    ptr::copy_nonoverlapping(contents.as_ptr(), buf as *mut u8, contents.len());
+    *(buf as *mut u8).add(contents.len()) = 0;
+    *data = buf;
+    *len = contents.len();
+    *kind = object_kind.raw();
+    0
+}
+
+extern "C" fn cache_read(
+    data: *mut *mut c_void,
+    len: *mut size_t,
+    kind: *mut raw::git_object_t,
+    backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        read_object(backend, Binding::from_raw(oid), data, len, kind)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_read_prefix(
+    out: *mut raw::git_oid,
+    data: *mut *mut c_void,
+    len: *mut size_t,
+    kind: *mut raw::git_object_t,
+    backend: *mut raw::git_odb_backend,
+    short_oid: *const raw::git_oid,
+    short_len: size_t,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        let rc = raw::git_odb_exists_prefix(out, backend.inner, short_oid, short_len);
+        if rc < 0 {
+            return rc;
+        }
+        read_object(backend, Binding::from_raw(out as *const _), data, len, kind)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_read_header(
+    len: *mut size_t,
+    kind: *mut raw::git_object_t,
+    backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        if let Some((object_kind, contents)) = backend.cache.get(Binding::from_raw(oid)) {
+            *len = contents.len();
+            *kind = object_kind.raw();
+            return 0;
+        }
+        raw::git_odb_read_header(len, kind, backend.inner, oid)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_write(
+    backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+    data: *const c_void,
+    len: size_t,
+    kind: raw::git_object_t,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        let mut out = *oid;
+        raw::git_odb_write(&mut out, backend.inner, data, len, kind)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_exists(backend: *mut raw::git_odb_backend, oid: *const raw::git_oid) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        raw::git_odb_exists(backend.inner, oid)
+    })
+    .unwrap_or(0)
+}
+
+extern "C" fn cache_exists_prefix(
+    out: *mut raw::git_oid,
+    backend: *mut raw::git_odb_backend,
+    short_oid: *const raw::git_oid,
+    len: size_t,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        raw::git_odb_exists_prefix(out, backend.inner, short_oid, len)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_refresh(backend: *mut raw::git_odb_backend) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        raw::git_odb_refresh(backend.inner)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_foreach(
+    backend: *mut raw::git_odb_backend,
+    cb: raw::git_odb_foreach_cb,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        raw::git_odb_foreach(backend.inner, cb, payload)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_writepack(
+    out: *mut *mut raw::git_odb_writepack,
+    backend: *mut raw::git_odb_backend,
+    _odb: *mut raw::git_odb,
+    progress_cb: raw::git_indexer_progress_cb,
+    progress_payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let backend = &mut *(backend as *mut CacheBackend);
+        raw::git_odb_write_pack(out, backend.inner, progress_cb, progress_payload)
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn cache_free(backend: *mut raw::git_odb_backend) {
+    let _ = panic::wrap(|| unsafe {
+        let backend = Box::from_raw(backend as *mut CacheBackend);
+        raw::git_odb_free(backend.inner);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryCache, ObjectCache};
+    use crate::{ObjectType, Oid};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn memory_cache() {
+        let a = Oid::hash_object(ObjectType::Blob, b"aaaa").unwrap();
+        let b = Oid::hash_object(ObjectType::Blob, b"bbbb").unwrap();
+        let c = Oid::hash_object(ObjectType::Blob, b"cccc").unwrap();
+        let mut cache = MemoryCache::new(8);
+        assert!(cache.get(a).is_none());
+        cache.insert(a, ObjectType::Blob, b"aaaa");
+        cache.insert(b, ObjectType::Blob, b"bbbb");
+        assert_eq!(cache.get(a), Some((ObjectType::Blob, b"aaaa".to_vec())));
+        // `b` is now the least recently used object.
+        cache.insert(c, ObjectType::Blob, b"cccc");
+        assert!(cache.get(b).is_none());
+        assert!(cache.get(a).is_some());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 8);
+        cache.insert(b, ObjectType::Blob, b"too large");
+        assert!(cache.get(b).is_none());
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.hits, 2);
+        assert_eq!(metrics.misses, 3);
+        assert_eq!(metrics.insertions, 3);
+        assert_eq!(metrics.evictions, 1);
+
+        let mut cache = MemoryCache::new(8);
+        cache.ttl(Duration::from_millis(10));
+        cache.insert(a, ObjectType::Blob, b"aaaa");
+        assert!(cache.get(a).is_some());
+        thread::sleep(Duration::from_millis(20));
+        assert!(cache.get(a).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn wrap_with_cache() {
+        let (_td, repo) = crate::test::repo_init();
+        let id = repo.blob(b"cached contents").unwrap();
+        let cache = Arc::new(Mutex::new(MemoryCache::new(1 << 20)));
+        let wrap = || repo.odb().unwrap().wrap_with_cache(cache.clone()).unwrap();
+
+        // libgit2 caches the objects it reads per object database, so use a
+        // fresh one for each read to reach the cache.
+        assert_eq!(wrap().read(id).unwrap().data(), b"cached contents");
+        assert_eq!(cache.lock().unwrap().metrics().misses, 1);
+        assert_eq!(cache.lock().unwrap().metrics().insertions, 1);
+        assert_eq!(wrap().read(id).unwrap().data(), b"cached contents");
+        assert_eq!(cache.lock().unwrap().metrics().hits, 1);
+        assert_eq!(wrap().read_header(id).unwrap(), (15, ObjectType::Blob));
+        assert_eq!(cache.lock().unwrap().metrics().hits, 2);
+
+        let odb = wrap();
+        assert!(odb.exists(id));
+        repo.set_odb(&odb).unwrap();
+        let written = repo.blob(b"").unwrap();
+        assert!(repo.odb().unwrap().exists(written));
+        assert_eq!(repo.find_blob(written).unwrap().content(), b"");
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.message(), Some("initial"));
+    }
+}
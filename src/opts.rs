@@ -1,9 +1,10 @@
 //! Bindings to libgit2's git_libgit2_opts function.
 
 use std::ffi::CString;
+use std::sync::{Arc, RwLock};
 
 use crate::util::Binding;
-use crate::{raw, Buf, ConfigLevel, Error, IntoCString};
+use crate::{raw, Buf, ConfigLevel, Error, IntoCString, Time};
 
 /// Set the search path for a level of config data. The search path applied to
 /// shared attributes and ignore files, too.
@@ -102,6 +103,210 @@ pub fn strict_hash_verification(enabled: bool) {
     debug_assert!(error >= 0);
 }
 
+/// Controls whether or not libgit2 will verify that a symbolic reference
+/// points at a valid reference name when creating it. Enabled by default.
+pub fn strict_symbolic_ref_creation(enabled: bool) {
+    let error = unsafe {
+        raw::git_libgit2_opts(
+            raw::GIT_OPT_ENABLE_STRICT_SYMBOLIC_REF_CREATION as libc::c_int,
+            enabled as libc::c_int,
+        )
+    };
+    // This function cannot actually fail, but the function has an error return
+    // for other options that can.
+    debug_assert!(error >= 0);
+}
+
+/// Controls whether or not libgit2 caches loaded objects. Enabled by
+/// default; disabling it lowers memory use at the cost of reloading objects
+/// which are looked up repeatedly.
+pub fn enable_caching(enabled: bool) {
+    let error = unsafe {
+        raw::git_libgit2_opts(
+            raw::GIT_OPT_ENABLE_CACHING as libc::c_int,
+            enabled as libc::c_int,
+        )
+    };
+    // This function cannot actually fail, but the function has an error return
+    // for other options that can.
+    debug_assert!(error >= 0);
+}
+
+/// Set the maximum amount of memory, in bytes, that can be held by the object
+/// cache. The default is 256MiB.
+pub fn set_cache_max_size(bytes: usize) -> Result<(), Error> {
+    crate::init();
+    unsafe {
+        try_call!(raw::git_libgit2_opts(
+            raw::GIT_OPT_SET_CACHE_MAX_SIZE as libc::c_int,
+            bytes as libc::ssize_t
+        ));
+    }
+    Ok(())
+}
+
+/// Get the current memory use of the object cache and the maximum allowed by
+/// `set_cache_max_size`, both in bytes.
+pub fn get_cached_memory() -> Result<(isize, isize), Error> {
+    crate::init();
+    let mut current: libc::ssize_t = 0;
+    let mut allowed: libc::ssize_t = 0;
+    unsafe {
+        try_call!(raw::git_libgit2_opts(
+            raw::GIT_OPT_GET_CACHED_MEMORY as libc::c_int,
+            &mut current as *mut libc::ssize_t as *const _,
+            &mut allowed as *mut libc::ssize_t as *const _
+        ));
+    }
+    Ok((current, allowed))
+}
+
+/// Controls whether or not libgit2 calls `fsync` on the files it writes in
+/// the git directory, such as loose objects, packs and references, before
+/// renaming them into place. Disabled by default.
+pub fn fsync_gitdir(enabled: bool) {
+    let error = unsafe {
+        raw::git_libgit2_opts(
+            raw::GIT_OPT_ENABLE_FSYNC_GITDIR as libc::c_int,
+            enabled as libc::c_int,
+        )
+    };
+    // This function cannot actually fail, but the function has an error return
+    // for other options that can.
+    debug_assert!(error >= 0);
+}
+
+/// A coherent set of the options in this module, recommended for a kind of
+/// deployment.
+///
+/// `Profile::apply` sets the options of a profile for the whole process.
+/// `Repository::open_with_profile` instead makes the durability setting part
+/// of the repository's configuration and only ever tightens the
+/// process-global options, so that opening one repository cannot weaken
+/// another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// For long-running services holding repositories other people push to.
+    ///
+    /// Everything written is checked and synced to disk before it becomes
+    /// visible, so a crash cannot leave a reference pointing at a truncated
+    /// object, and the object cache is larger to serve many requests.
+    Server,
+    /// For interactive tools working on a user's own checkouts.
+    ///
+    /// Objects and references are checked as they are created and read, but
+    /// not synced to disk, matching git's own defaults.
+    Desktop,
+}
+
+impl Profile {
+    /// Set the options of this profile.
+    ///
+    /// These options are process-global: they affect every repository, not
+    /// only those opened afterwards, and a `Desktop` profile applied after a
+    /// `Server` one turns `fsync_gitdir` off and shrinks the cache again.
+    ///
+    /// | option                         | `Server` | `Desktop` |
+    /// |--------------------------------|----------|-----------|
+    /// | `strict_object_creation`       | on       | on        |
+    /// | `strict_symbolic_ref_creation` | on       | on        |
+    /// | `strict_hash_verification`     | on       | on        |
+    /// | `enable_caching`               | on       | on        |
+    /// | `set_cache_max_size`           | 1GiB     | 256MiB    |
+    /// | `fsync_gitdir`                 | on       | off       |
+    ///
+    /// The `fsync_gitdir` option covers both object and reference writes.
+    /// The repository's own `core.fsyncObjectFiles` setting still applies on
+    /// top of it.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe as it mutates the global state but cannot guarantee
+    /// thread-safety. It needs to be externally synchronized with calls to access
+    /// the global state.
+    pub unsafe fn apply(&self) -> Result<(), Error> {
+        let server = *self == Profile::Server;
+        strict_object_creation(true);
+        strict_symbolic_ref_creation(true);
+        strict_hash_verification(true);
+        enable_caching(true);
+        set_cache_max_size(self.cache_max_size())?;
+        fsync_gitdir(server);
+        Ok(())
+    }
+
+    /// The object cache size of this profile, in bytes.
+    fn cache_max_size(&self) -> usize {
+        match *self {
+            Profile::Server => 1 << 30,
+            Profile::Desktop => 256 << 20,
+        }
+    }
+
+    /// Set the process-global options of this profile, without weakening any
+    /// which are already stricter: the cache is only ever grown, and
+    /// `fsync_gitdir` is left as it is.
+    ///
+    /// This is unsafe for the same reason as `apply`; callers must not race
+    /// with other code changing these options.
+    pub(crate) unsafe fn tighten(&self) -> Result<(), Error> {
+        strict_object_creation(true);
+        strict_symbolic_ref_creation(true);
+        strict_hash_verification(true);
+        enable_caching(true);
+        let (_, allowed) = get_cached_memory()?;
+        if (allowed as usize) < self.cache_max_size() {
+            set_cache_max_size(self.cache_max_size())?;
+        }
+        Ok(())
+    }
+}
+
+/// A clock returning the current time, installed with `set_time_source`.
+pub type TimeSource = dyn Fn() -> Time + Send + Sync;
+
+static TIME_SOURCE: RwLock<Option<Arc<TimeSource>>> = RwLock::new(None);
+
+/// Replace the clock used for the current time throughout this library.
+///
+/// The clock is used by `Signature::now` and `Repository::signature` for
+/// timestamps, and by `LockOptions` to decide whether a lock file is
+/// stale. A fixed or stepping clock makes commits created through those
+/// signatures, and hence their ids, identical from run to run, which is
+/// useful for property-based tests and reproducible pipelines.
+///
+/// The clock is process-global. The retry deadlines of lock acquisition
+/// keep using the system clock, since they measure elapsed time rather than
+/// the time of day.
+///
+/// Reflog entries are not covered either: libgit2 stamps the entries it
+/// writes when updating references with the system clock and has no hook
+/// to change that. Reflogs do not affect object ids; if their timestamps
+/// matter, write the entries with `Reflog::append` and a signature from
+/// `Repository::signature` instead.
+///
+/// There is no matching hook for random numbers: neither this library nor
+/// libgit2 uses randomness in anything that ends up in objects, references
+/// or output.
+pub fn set_time_source<F>(source: F)
+where
+    F: Fn() -> Time + Send + Sync + 'static,
+{
+    *TIME_SOURCE.write().unwrap() = Some(Arc::new(source));
+}
+
+/// Go back to using the system clock after `set_time_source`.
+pub fn reset_time_source() {
+    *TIME_SOURCE.write().unwrap() = None;
+}
+
+/// Returns the current time according to the clock installed with
+/// `set_time_source`, or `None` if the system clock is in use.
+pub(crate) fn injected_time() -> Option<Time> {
+    let source = TIME_SOURCE.read().unwrap().clone();
+    source.map(|source| source())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
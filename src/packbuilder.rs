@@ -1,10 +1,11 @@
 use libc::{c_int, c_uint, c_void, size_t};
 use std::marker;
+use std::path::Path;
 use std::ptr;
 use std::slice;
 
 use crate::util::Binding;
-use crate::{panic, raw, Buf, Error, Oid, Repository, Revwalk};
+use crate::{panic, raw, Buf, Error, IntoCString, Oid, Repository, Revwalk};
 
 #[derive(PartialEq, Eq, Clone, Debug, Copy)]
 /// Stages that are reported by the `PackBuilder` progress callback.
@@ -83,6 +84,25 @@ impl<'repo> PackBuilder<'repo> {
         Ok(())
     }
 
+    /// Write the new pack and its index to the directory `path`, such as the
+    /// `objects/pack` directory of a repository.
+    ///
+    /// The files are named after the pack's hash. A `mode` of 0 gives them
+    /// the default permissions.
+    pub fn write(&mut self, path: &Path, mode: u32) -> Result<(), Error> {
+        let path = path.into_c_string()?;
+        unsafe {
+            try_call!(raw::git_packbuilder_write(
+                self.raw,
+                path,
+                mode as c_uint,
+                None,
+                ptr::null_mut()
+            ));
+        }
+        Ok(())
+    }
+
     /// Create the new pack and pass each object to the callback.
     pub fn foreach<F>(&mut self, mut cb: F) -> Result<(), Error>
     where
@@ -159,10 +179,12 @@ impl<'repo> PackBuilder<'repo> {
 
     /// Get the packfile's hash. A packfile's name is derived from the sorted
     /// hashing of all object names. This is only correct after the packfile
-    /// has been written.
+    /// has been written, and `None` is returned if objects were inserted but
+    /// the pack has not been written with `write` yet.
     pub fn hash(&self) -> Option<Oid> {
-        if self.object_count() == 0 {
-            unsafe { Some(Binding::from_raw(raw::git_packbuilder_hash(self.raw))) }
+        let oid: Oid = unsafe { Binding::from_raw(raw::git_packbuilder_hash(self.raw)) };
+        if self.object_count() == 0 || !oid.is_zero() {
+            Some(oid)
         } else {
             None
         }
@@ -341,6 +363,18 @@ mod tests {
         assert_eq!(&buf[0..12], &*pack_header(3));
     }
 
+    #[test]
+    fn insert_commit_write() {
+        let (td, repo) = crate::test::repo_init();
+        let mut builder = t!(repo.packbuilder());
+        let (commit, _tree) = crate::test::commit(&repo);
+        t!(builder.insert_commit(commit));
+        t!(builder.write(td.path(), 0));
+        let name = format!("pack-{}", builder.hash().unwrap());
+        assert!(td.path().join(format!("{}.pack", name)).exists());
+        assert!(td.path().join(format!("{}.idx", name)).exists());
+    }
+
     #[test]
     fn progress_callback() {
         let mut progress_called = false;
@@ -0,0 +1,380 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::str;
+
+use crate::{Error, ErrorClass, ErrorCode, Oid};
+
+/// An indexed, read-only view of a `packed-refs` file, as returned by
+/// `Repository::packed_refs_view`.
+///
+/// The view keeps the offset of each reference in the file, ordered by name,
+/// so looking up a reference is a binary search and iterating over a
+/// namespace such as `refs/tags/` only visits the references in it, where
+/// `Repository::references_glob` matches the pattern against every
+/// reference. Neither creates a `Reference`, which matters on repositories
+/// with hundreds of thousands of references.
+///
+/// With the `mmap` feature the file is memory-mapped rather than read into
+/// memory. git and libgit2 replace `packed-refs` by renaming a new file over
+/// it, which leaves the mapping intact; a process rewriting the file in place
+/// while it is mapped could make the view crash.
+///
+/// The view is a snapshot of the packed references only: references stored
+/// as loose files, which override packed ones of the same name, are not
+/// included, and later changes to the file are not seen.
+pub struct PackedRefsView {
+    data: Data,
+    /// The offsets of the reference lines in `data`, in name order.
+    entries: Vec<usize>,
+}
+
+enum Data {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+/// A reference in a `PackedRefsView`.
+#[derive(Debug, Clone, Copy)]
+pub struct PackedRef<'a> {
+    name: &'a [u8],
+    target: Oid,
+    peeled: Option<Oid>,
+}
+
+/// An iterator over the references of a `PackedRefsView`, in name order.
+pub struct PackedRefs<'a> {
+    view: &'a PackedRefsView,
+    range: Range<usize>,
+}
+
+impl PackedRefsView {
+    /// Open and index the `packed-refs` file at `path`.
+    ///
+    /// A missing file is treated as an empty one, as git does.
+    pub fn open(path: &Path) -> Result<PackedRefsView, Error> {
+        match PackedRefsView::load(path) {
+            Ok(data) => PackedRefsView::new(data),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                PackedRefsView::from_bytes(Vec::new())
+            }
+            Err(e) => Err(Error::from_str(&format!(
+                "failed to read '{}': {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    fn load(path: &Path) -> io::Result<Data> {
+        let file = fs::File::open(path)?;
+        // Empty files cannot be mapped on every platform.
+        if file.metadata()?.len() == 0 {
+            return Ok(Data::Owned(Vec::new()));
+        }
+        // The file is only read, and is replaced rather than modified by
+        // writers; see the type-level docs.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Data::Mapped(map))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn load(path: &Path) -> io::Result<Data> {
+        fs::read(path).map(Data::Owned)
+    }
+
+    /// Index the contents of a `packed-refs` file.
+    pub fn from_bytes(data: Vec<u8>) -> Result<PackedRefsView, Error> {
+        PackedRefsView::new(Data::Owned(data))
+    }
+
+    fn new(data: Data) -> Result<PackedRefsView, Error> {
+        let mut entries = Vec::new();
+        let mut sorted = false;
+        // Whether the previous line was a reference, which a peeled line
+        // must directly follow.
+        let mut after_ref = false;
+        let mut start = 0;
+        for (n, line) in data.split(|&b| b == b'\n').enumerate() {
+            let line_start = start;
+            start += line.len() + 1;
+            let line = trim_cr(line);
+            let is_ref = !line.is_empty() && line[0] != b'#' && line[0] != b'^';
+            let peels = after_ref;
+            after_ref = is_ref;
+            if line.is_empty() {
+                continue;
+            }
+            if line[0] == b'#' {
+                if n == 0 {
+                    sorted = line.split(|&b| b == b' ').any(|t| t == b"sorted");
+                }
+                continue;
+            }
+            if line[0] == b'^' {
+                if !peels {
+                    return Err(corrupt(n));
+                }
+                parse_oid(&line[1..], n)?;
+                continue;
+            }
+            if line.len() < 42 || line[40] != b' ' {
+                return Err(corrupt(n));
+            }
+            parse_oid(&line[..40], n)?;
+            entries.push(line_start);
+        }
+        let mut view = PackedRefsView { data, entries };
+        if !sorted {
+            let mut entries = std::mem::replace(&mut view.entries, Vec::new());
+            entries.sort_by(|&a, &b| view.name(a).cmp(view.name(b)));
+            view.entries = entries;
+        }
+        Ok(view)
+    }
+
+    /// Look up the reference named `name`.
+    pub fn find(&self, name: &str) -> Option<PackedRef<'_>> {
+        let name = name.as_bytes();
+        self.entries
+            .binary_search_by(|&entry| self.name(entry).cmp(name))
+            .ok()
+            .map(|i| self.get(i))
+    }
+
+    /// Returns the references whose names start with `prefix`, such as
+    /// `refs/tags/`, in name order.
+    pub fn prefix(&self, prefix: &str) -> PackedRefs<'_> {
+        let prefix = prefix.as_bytes();
+        let start = self.bound(|name| name < prefix);
+        let end = self.bound(|name| name < prefix || name.starts_with(prefix));
+        PackedRefs {
+            view: self,
+            range: start..end,
+        }
+    }
+
+    /// Returns all references, in name order.
+    pub fn iter(&self) -> PackedRefs<'_> {
+        PackedRefs {
+            view: self,
+            range: 0..self.entries.len(),
+        }
+    }
+
+    /// Returns the number of references.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no packed references.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the line starting at `offset`, including a trailing `\r` but
+    /// not the `\n`.
+    fn line(&self, offset: usize) -> &[u8] {
+        let rest = &self.data[offset..];
+        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn name(&self, offset: usize) -> &[u8] {
+        &trim_cr(self.line(offset))[41..]
+    }
+
+    fn get(&self, i: usize) -> PackedRef<'_> {
+        let offset = self.entries[i];
+        let line = self.line(offset);
+        let next = self.data.get(offset + line.len() + 1..).unwrap_or(&[]);
+        // The oids were validated when the file was indexed.
+        let oid = |hex| parse_oid(hex, 0).unwrap();
+        let peeled = match next.first() {
+            Some(&b'^') => Some(oid(&next[1..41])),
+            _ => None,
+        };
+        PackedRef {
+            name: &trim_cr(line)[41..],
+            target: oid(&line[..40]),
+            peeled,
+        }
+    }
+
+    /// Returns the index of the first entry whose name does not satisfy
+    /// `before`, which must hold for a prefix of the entries.
+    fn bound<F: Fn(&[u8]) -> bool>(&self, before: F) -> usize {
+        let search = self.entries.binary_search_by(|&entry| {
+            if before(self.name(entry)) {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        });
+        match search {
+            Ok(i) | Err(i) => i,
+        }
+    }
+}
+
+impl Deref for Data {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Data::Owned(ref data) => data,
+            #[cfg(feature = "mmap")]
+            Data::Mapped(ref map) => map,
+        }
+    }
+}
+
+impl<'a> PackedRef<'a> {
+    /// Returns the full name of the reference, or `None` if it is not valid
+    /// utf-8.
+    pub fn name(&self) -> Option<&'a str> {
+        str::from_utf8(self.name).ok()
+    }
+
+    /// Returns the full name of the reference as a byte slice.
+    pub fn name_bytes(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// Returns the object the reference points at.
+    pub fn target(&self) -> Oid {
+        self.target
+    }
+
+    /// Returns the object an annotated tag reference ultimately points at,
+    /// if `git pack-refs` recorded it.
+    pub fn peeled(&self) -> Option<Oid> {
+        self.peeled
+    }
+}
+
+impl<'a> Iterator for PackedRefs<'a> {
+    type Item = PackedRef<'a>;
+    fn next(&mut self) -> Option<PackedRef<'a>> {
+        self.range.next().map(|i| self.view.get(i))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for PackedRefs<'a> {
+    fn next_back(&mut self) -> Option<PackedRef<'a>> {
+        self.range.next_back().map(|i| self.view.get(i))
+    }
+}
+
+impl<'a> ExactSizeIterator for PackedRefs<'a> {}
+
+fn trim_cr(line: &[u8]) -> &[u8] {
+    match line.last() {
+        Some(&b'\r') => &line[..line.len() - 1],
+        _ => line,
+    }
+}
+
+fn parse_oid(hex: &[u8], n: usize) -> Result<Oid, Error> {
+    str::from_utf8(hex)
+        .ok()
+        .filter(|hex| hex.len() == 40)
+        .and_then(|hex| Oid::from_str(hex).ok())
+        .ok_or_else(|| corrupt(n))
+}
+
+fn corrupt(n: usize) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Reference,
+        format!("corrupted packed-refs file at line {}", n + 1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PackedRefsView;
+    use crate::Oid;
+
+    #[test]
+    fn from_bytes() {
+        let a = "1111111111111111111111111111111111111111";
+        let b = "2222222222222222222222222222222222222222";
+        let data = format!(
+            "# pack-refs with: peeled fully-peeled \n\
+             {a} refs/tags/v2\n^{b}\n{b} refs/heads/master\n{a} refs/tags/v1\n{a} refs/heads/main\n",
+            a = a,
+            b = b
+        );
+        let view = PackedRefsView::from_bytes(data.into_bytes()).unwrap();
+        assert_eq!(view.len(), 4);
+        let names = view.iter().map(|r| r.name().unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "refs/heads/main",
+                "refs/heads/master",
+                "refs/tags/v1",
+                "refs/tags/v2"
+            ]
+        );
+
+        let v2 = view.find("refs/tags/v2").unwrap();
+        assert_eq!(v2.target(), Oid::from_str(a).unwrap());
+        assert_eq!(v2.peeled(), Some(Oid::from_str(b).unwrap()));
+        assert!(view.find("refs/tags/v3").is_none());
+        assert!(view.find("refs/tags").is_none());
+
+        let tags = view.prefix("refs/tags/").map(|r| r.name().unwrap());
+        assert_eq!(tags.collect::<Vec<_>>(), ["refs/tags/v1", "refs/tags/v2"]);
+        let heads = view.prefix("refs/heads/ma");
+        assert_eq!(heads.len(), 2);
+        assert_eq!(view.prefix("refs/remotes/").count(), 0);
+        assert_eq!(view.prefix("").count(), 4);
+
+        let crlf = format!(
+            "{a} refs/tags/v1\r\n^{b}\r\n{b} refs/tags/v0\r\n",
+            a = a,
+            b = b
+        );
+        let view = PackedRefsView::from_bytes(crlf.into_bytes()).unwrap();
+        let v1 = view.find("refs/tags/v1").unwrap();
+        assert_eq!(v1.peeled(), Some(Oid::from_str(b).unwrap()));
+        assert_eq!(view.find("refs/tags/v0").unwrap().peeled(), None);
+
+        let orphan = format!("{a} refs/tags/v1\n\n^{b}\n", a = a, b = b);
+        assert!(PackedRefsView::from_bytes(orphan.into_bytes()).is_err());
+        assert!(PackedRefsView::from_bytes(b"bogus\n".to_vec()).is_err());
+        assert!(PackedRefsView::from_bytes(Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repo() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        assert!(repo.packed_refs_view().unwrap().is_empty());
+
+        let mut data = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for i in 0..100 {
+            data.push_str(&format!("{} refs/tags/t{:03}\n", head, i));
+        }
+        std::fs::write(repo.path().join("packed-refs"), data).unwrap();
+        let view = repo.packed_refs_view().unwrap();
+        assert_eq!(view.len(), 100);
+        assert_eq!(view.find("refs/tags/t042").unwrap().target(), head);
+        assert_eq!(view.prefix("refs/tags/t09").count(), 10);
+        assert_eq!(
+            repo.find_reference("refs/tags/t042")
+                .unwrap()
+                .target()
+                .unwrap(),
+            head
+        );
+    }
+}
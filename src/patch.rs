@@ -1,5 +1,6 @@
 use libc::{c_int, c_void};
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::path::Path;
 use std::ptr;
 
@@ -15,6 +16,54 @@ pub struct Patch<'buffers> {
     buffers: PhantomData<&'buffers ()>,
 }
 
+/// How `Patch::intraline_changes` splits lines before comparing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntralineGranularity {
+    /// Compare lines character by character.
+    Char,
+    /// Compare lines word by word, like `git diff --word-diff`. A word is a
+    /// run of alphanumeric characters or underscores, a run of whitespace,
+    /// or any other single character.
+    Word,
+}
+
+/// The parts of a removed line and of the added line replacing it which
+/// differ from each other.
+///
+/// Ranges are byte offsets into the `content` of the respective lines, not
+/// including their line terminator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntralineChange {
+    old_line: usize,
+    new_line: usize,
+    old_ranges: Vec<Range<usize>>,
+    new_ranges: Vec<Range<usize>>,
+}
+
+impl IntralineChange {
+    /// Returns the index within its hunk of the removed line.
+    pub fn old_line(&self) -> usize {
+        self.old_line
+    }
+
+    /// Returns the index within its hunk of the added line.
+    pub fn new_line(&self) -> usize {
+        self.new_line
+    }
+
+    /// Returns the ranges of the removed line which are not in the added
+    /// line.
+    pub fn old_ranges(&self) -> &[Range<usize>] {
+        &self.old_ranges
+    }
+
+    /// Returns the ranges of the added line which are not in the removed
+    /// line.
+    pub fn new_ranges(&self) -> &[Range<usize>] {
+        &self.new_ranges
+    }
+}
+
 unsafe impl<'buffers> Send for Patch<'buffers> {}
 
 impl<'buffers> Binding for Patch<'buffers> {
@@ -185,6 +234,171 @@ impl<'buffers> Patch<'buffers> {
         }
     }
 
+    /// Get all DiffHunks of the Patch.
+    pub fn hunks(&self) -> Result<Vec<DiffHunk<'buffers>>, Error> {
+        (0..self.num_hunks())
+            .map(|idx| self.hunk(idx).map(|(hunk, _)| hunk))
+            .collect()
+    }
+
+    /// Get all DiffLines of a hunk of the Patch.
+    pub fn lines_in_hunk(&self, hunk_idx: usize) -> Result<Vec<DiffLine<'buffers>>, Error> {
+        (0..self.num_lines_in_hunk(hunk_idx)?)
+            .map(|idx| self.line_in_hunk(hunk_idx, idx))
+            .collect()
+    }
+
+    /// Find the line with the given line number in the old file.
+    ///
+    /// Returns the index of the hunk containing it and the index of the line
+    /// within that hunk, suitable for `line_in_hunk`, or `None` if the line is
+    /// not part of any hunk.
+    pub fn find_old_line(&self, old_lineno: u32) -> Result<Option<(usize, usize)>, Error> {
+        self.find_line(old_lineno, true)
+    }
+
+    /// Find the line with the given line number in the new file.
+    ///
+    /// Returns the index of the hunk containing it and the index of the line
+    /// within that hunk, suitable for `line_in_hunk`, or `None` if the line is
+    /// not part of any hunk.
+    pub fn find_new_line(&self, new_lineno: u32) -> Result<Option<(usize, usize)>, Error> {
+        self.find_line(new_lineno, false)
+    }
+
+    /// Translate a line number in the old file to the same line in the new
+    /// file.
+    ///
+    /// Lines outside of any hunk are shifted by the lines added and removed
+    /// before them. Returns `None` if the line was deleted.
+    pub fn old_to_new_line(&self, old_lineno: u32) -> Result<Option<u32>, Error> {
+        self.translate_line(old_lineno, true)
+    }
+
+    /// Translate a line number in the new file to the same line in the old
+    /// file.
+    ///
+    /// Lines outside of any hunk are shifted by the lines added and removed
+    /// before them. Returns `None` if the line was added.
+    pub fn new_to_old_line(&self, new_lineno: u32) -> Result<Option<u32>, Error> {
+        self.translate_line(new_lineno, false)
+    }
+
+    /// Returns the range of line numbers a hunk covers on one side.
+    ///
+    /// An empty hunk side, such as the old side of a pure addition, starts
+    /// right after the line it is anchored to.
+    fn hunk_range(hunk: &DiffHunk<'_>, old: bool) -> (u32, u32) {
+        let (start, lines) = if old {
+            (hunk.old_start(), hunk.old_lines())
+        } else {
+            (hunk.new_start(), hunk.new_lines())
+        };
+        let first = if lines == 0 { start + 1 } else { start };
+        (first, first + lines)
+    }
+
+    fn find_line(&self, lineno: u32, old: bool) -> Result<Option<(usize, usize)>, Error> {
+        for hunk_idx in 0..self.num_hunks() {
+            let (hunk, lines) = self.hunk(hunk_idx)?;
+            let (first, end) = Patch::hunk_range(&hunk, old);
+            if lineno < first {
+                break;
+            }
+            if lineno >= end {
+                continue;
+            }
+            for line_idx in 0..lines {
+                let line = self.line_in_hunk(hunk_idx, line_idx)?;
+                let found = if old {
+                    line.old_lineno()
+                } else {
+                    line.new_lineno()
+                };
+                if found == Some(lineno) {
+                    return Ok(Some((hunk_idx, line_idx)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn translate_line(&self, lineno: u32, old: bool) -> Result<Option<u32>, Error> {
+        if let Some((hunk_idx, line_idx)) = self.find_line(lineno, old)? {
+            let line = self.line_in_hunk(hunk_idx, line_idx)?;
+            return Ok(if old {
+                line.new_lineno()
+            } else {
+                line.old_lineno()
+            });
+        }
+        let mut offset = 0i64;
+        for hunk_idx in 0..self.num_hunks() {
+            let (hunk, _) = self.hunk(hunk_idx)?;
+            let (first, end) = Patch::hunk_range(&hunk, old);
+            let (other_first, other_end) = Patch::hunk_range(&hunk, !old);
+            if lineno < first {
+                break;
+            }
+            offset += (other_end - other_first) as i64 - (end - first) as i64;
+        }
+        Ok(Some((lineno as i64 + offset) as u32))
+    }
+
+    /// Find what changed within the lines of a hunk.
+    ///
+    /// Each block of removed lines directly followed by a block of added
+    /// lines is taken to be a rewrite of those lines; the first removed line
+    /// is paired with the first added line and so on. The lines of each pair
+    /// are then compared to each other. Lines left without a partner are not
+    /// reported.
+    pub fn intraline_changes(
+        &self,
+        hunk_idx: usize,
+        granularity: IntralineGranularity,
+    ) -> Result<Vec<IntralineChange>, Error> {
+        let lines = self.num_lines_in_hunk(hunk_idx)?;
+        let mut changes = Vec::new();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for line_idx in 0..=lines {
+            let origin = if line_idx < lines {
+                Some(self.line_in_hunk(hunk_idx, line_idx)?.origin())
+            } else {
+                None
+            };
+            match origin {
+                Some('-') if added.is_empty() => {
+                    removed.push(line_idx);
+                    continue;
+                }
+                Some('+') if !removed.is_empty() => {
+                    added.push(line_idx);
+                    continue;
+                }
+                _ => {}
+            }
+            for (&old, &new) in removed.iter().zip(added.iter()) {
+                let old_content = self.line_in_hunk(hunk_idx, old)?.content();
+                let new_content = self.line_in_hunk(hunk_idx, new)?.content();
+                let (old_ranges, new_ranges) =
+                    diff_line_content(old_content, new_content, granularity);
+                changes.push(IntralineChange {
+                    old_line: old,
+                    new_line: new,
+                    old_ranges,
+                    new_ranges,
+                });
+            }
+            removed.clear();
+            added.clear();
+            if origin == Some('-') {
+                removed.push(line_idx);
+            }
+        }
+        Ok(changes)
+    }
+
     /// Get the size of a Patch's diff data in bytes.
     pub fn size(
         &self,
@@ -233,3 +447,214 @@ impl<'buffers> std::fmt::Debug for Patch<'buffers> {
         ds.finish()
     }
 }
+
+/// Compares two lines token by token and returns the ranges of each which
+/// are not part of their longest common subsequence.
+fn diff_line_content(
+    old: &[u8],
+    new: &[u8],
+    granularity: IntralineGranularity,
+) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let old = trim_eol(old);
+    let new = trim_eol(new);
+    let old_tokens = tokenize(old, granularity);
+    let new_tokens = tokenize(new, granularity);
+    let same = |a: &Range<usize>, b: &Range<usize>| old[a.clone()] == new[b.clone()];
+
+    let prefix = old_tokens
+        .iter()
+        .zip(&new_tokens)
+        .take_while(|(a, b)| same(a, b))
+        .count();
+    let suffix = old_tokens[prefix..]
+        .iter()
+        .rev()
+        .zip(new_tokens[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+    let old_mid = &old_tokens[prefix..old_tokens.len() - suffix];
+    let new_mid = &new_tokens[prefix..new_tokens.len() - suffix];
+
+    let mut old_changed = vec![true; old_mid.len()];
+    let mut new_changed = vec![true; new_mid.len()];
+    // Very long lines are not worth a quadratic comparison; they are
+    // reported as changed as a whole, minus their common ends.
+    if old_mid.len() * new_mid.len() <= 1 << 20 {
+        let (n, m) = (old_mid.len(), new_mid.len());
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * (m + 1) + j] = if same(&old_mid[i], &new_mid[j]) {
+                    lcs[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if same(&old_mid[i], &new_mid[j]) {
+                old_changed[i] = false;
+                new_changed[j] = false;
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    (
+        merge_ranges(old_mid, &old_changed),
+        merge_ranges(new_mid, &new_changed),
+    )
+}
+
+fn trim_eol(mut content: &[u8]) -> &[u8] {
+    if content.ends_with(b"\n") {
+        content = &content[..content.len() - 1];
+    }
+    if content.ends_with(b"\r") {
+        content = &content[..content.len() - 1];
+    }
+    content
+}
+
+fn tokenize(content: &[u8], granularity: IntralineGranularity) -> Vec<Range<usize>> {
+    let class = |b: u8| {
+        if b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80 {
+            1
+        } else if b.is_ascii_whitespace() {
+            2
+        } else {
+            0
+        }
+    };
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < content.len() {
+        let mut end = start + 1;
+        match granularity {
+            IntralineGranularity::Char => {
+                // Keep multi-byte utf-8 sequences together.
+                while end < content.len() && content[end] & 0xc0 == 0x80 {
+                    end += 1;
+                }
+            }
+            IntralineGranularity::Word => {
+                let c = class(content[start]);
+                if c != 0 {
+                    while end < content.len() && class(content[end]) == c {
+                        end += 1;
+                    }
+                }
+            }
+        }
+        tokens.push(start..end);
+        start = end;
+    }
+    tokens
+}
+
+fn merge_ranges(tokens: &[Range<usize>], changed: &[bool]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (token, _) in tokens.iter().zip(changed).filter(|(_, c)| **c) {
+        match ranges.last_mut() {
+            Some(last) if last.end == token.start => last.end = token.end,
+            _ => ranges.push(token.clone()),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DiffOptions, IntralineGranularity, Patch};
+
+    const OLD: &[u8] = b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    const NEW: &[u8] = b"a\nc\nd\nE\nf\ng\nh\ni\nx\nj\n";
+
+    #[test]
+    fn translate_lines() {
+        let mut opts = DiffOptions::new();
+        opts.context_lines(0);
+        let patch = t!(Patch::from_buffers(OLD, None, NEW, None, Some(&mut opts)));
+        assert_eq!(patch.num_hunks(), 3);
+
+        let old_to_new = (1..=10)
+            .map(|l| t!(patch.old_to_new_line(l)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            old_to_new,
+            vec![
+                Some(1),
+                None,
+                Some(2),
+                Some(3),
+                None,
+                Some(5),
+                Some(6),
+                Some(7),
+                Some(8),
+                Some(10)
+            ]
+        );
+        let new_to_old = (1..=10)
+            .map(|l| t!(patch.new_to_old_line(l)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            new_to_old,
+            vec![
+                Some(1),
+                Some(3),
+                Some(4),
+                None,
+                Some(6),
+                Some(7),
+                Some(8),
+                Some(9),
+                None,
+                Some(10)
+            ]
+        );
+    }
+
+    #[test]
+    fn find_lines() {
+        let mut opts = DiffOptions::new();
+        opts.context_lines(0);
+        let patch = t!(Patch::from_buffers(OLD, None, NEW, None, Some(&mut opts)));
+        assert_eq!(t!(patch.find_old_line(2)), Some((0, 0)));
+        assert_eq!(t!(patch.find_old_line(5)), Some((1, 0)));
+        assert_eq!(t!(patch.find_new_line(4)), Some((1, 1)));
+        assert_eq!(t!(patch.find_new_line(9)), Some((2, 0)));
+        assert_eq!(t!(patch.find_new_line(3)), None);
+
+        let patch = t!(Patch::from_buffers(OLD, None, NEW, None, None));
+        let (hunk, line) = t!(patch.find_new_line(3)).unwrap();
+        let line = t!(patch.line_in_hunk(hunk, line));
+        assert_eq!(line.content(), b"d\n");
+        assert_eq!(line.old_lineno(), Some(4));
+    }
+
+    #[test]
+    fn intraline_changes() {
+        let old = b"fn main() {\n    let x = foo(1);\n    bar();\n}\n";
+        let new = b"fn main() {\n    let y = foo(12);\n    bar();\n    baz();\n}\n";
+        let patch = t!(Patch::from_buffers(old, None, new, None, None));
+        assert_eq!(patch.num_hunks(), 1);
+
+        let changes = t!(patch.intraline_changes(0, IntralineGranularity::Word));
+        assert_eq!(changes.len(), 1);
+        let change = &changes[0];
+        assert_eq!(t!(patch.line_in_hunk(0, change.old_line())).origin(), '-');
+        assert_eq!(t!(patch.line_in_hunk(0, change.new_line())).origin(), '+');
+        assert_eq!(change.old_ranges(), &[8..9, 16..17][..]);
+        assert_eq!(change.new_ranges(), &[8..9, 16..18][..]);
+
+        let changes = t!(patch.intraline_changes(0, IntralineGranularity::Char));
+        assert_eq!(changes[0].old_ranges(), &[8..9][..]);
+        assert_eq!(changes[0].new_ranges(), &[8..9, 17..18][..]);
+    }
+}
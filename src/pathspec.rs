@@ -54,6 +54,27 @@ impl Pathspec {
         }
     }
 
+    /// Creates a new pathspec from a list of specs, interpreting git's
+    /// pathspec magic in them.
+    ///
+    /// This allows excluding paths, for example with `:!target/` or
+    /// `:(exclude)*.log`. The supported magic is `:(top)` (or `:/`),
+    /// `:(exclude)` (or `:!` and `:^`), `:(literal)` and `:(glob)`; any other
+    /// magic results in an error. Excluding specs apply regardless of their
+    /// position, and when only excluding specs are given everything else is
+    /// matched.
+    pub fn with_magic<I, T>(specs: I) -> Result<Pathspec, Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let specs = specs
+            .into_iter()
+            .map(|s| s.into_c_string())
+            .collect::<Result<Vec<_>, _>>()?;
+        Pathspec::new(crate::util::pathspec_magic(specs)?)
+    }
+
     /// Match a pathspec against files in a diff.
     ///
     /// The list returned contains the list of all matched filenames (unless you
@@ -362,4 +383,18 @@ mod tests {
         assert_eq!(list.entries().len(), 1);
         assert_eq!(list.entries().next(), Some("a".as_bytes()));
     }
+
+    #[test]
+    fn with_magic() {
+        let ps = Pathspec::with_magic([":!*.log", "src"].iter()).unwrap();
+        assert!(ps.matches_path(Path::new("src/lib.rs"), PathspecFlags::DEFAULT));
+        assert!(!ps.matches_path(Path::new("src/build.log"), PathspecFlags::DEFAULT));
+        assert!(!ps.matches_path(Path::new("README"), PathspecFlags::DEFAULT));
+
+        let ps = Pathspec::with_magic([":(exclude)target"].iter()).unwrap();
+        assert!(ps.matches_path(Path::new("README"), PathspecFlags::DEFAULT));
+        assert!(!ps.matches_path(Path::new("target/debug"), PathspecFlags::DEFAULT));
+
+        assert!(Pathspec::with_magic([":(icase)a"].iter()).is_err());
+    }
 }
@@ -0,0 +1,240 @@
+use crate::{Commit, Diff, DiffOptions, Error, Oid, Patch, Repository, Revwalk};
+
+/// A search for changes touching some content, like `git log -S` and
+/// `git log -G`.
+///
+/// A pickaxe is applied to the deltas of a diff with `Diff::pickaxe`, or to
+/// the commits of a revision walk with `Pickaxe::walk`.
+pub struct Pickaxe<'a> {
+    kind: PickaxeKind<'a>,
+}
+
+enum PickaxeKind<'a> {
+    Occurrences(Vec<u8>),
+    Lines(Box<dyn FnMut(&[u8]) -> bool + 'a>),
+}
+
+/// An iterator over the commits of a `Revwalk` whose changes match a
+/// `Pickaxe`, created by `Pickaxe::walk`.
+pub struct PickaxeWalk<'repo, 'a> {
+    repo: &'repo Repository,
+    walk: Revwalk<'repo>,
+    pickaxe: Pickaxe<'a>,
+    diff_opts: Option<DiffOptions>,
+}
+
+impl<'a> Pickaxe<'a> {
+    /// Match changes which change the number of occurrences of `needle`, like
+    /// `git log -S`.
+    ///
+    /// Occurrences are counted in the lines removed and in the lines added
+    /// by a change, so a string which spans both changed and unchanged lines
+    /// is not seen.
+    pub fn occurrences<T: AsRef<[u8]>>(needle: T) -> Pickaxe<'a> {
+        Pickaxe {
+            kind: PickaxeKind::Occurrences(needle.as_ref().to_vec()),
+        }
+    }
+
+    /// Match changes which add or remove a line for which `matcher` returns
+    /// `true`, like `git log -G`.
+    ///
+    /// The matcher is given the content of each changed line without its
+    /// line terminator; a regular expression from a crate such as `regex` can
+    /// be plugged in here.
+    pub fn lines<F>(matcher: F) -> Pickaxe<'a>
+    where
+        F: FnMut(&[u8]) -> bool + 'a,
+    {
+        Pickaxe {
+            kind: PickaxeKind::Lines(Box::new(matcher)),
+        }
+    }
+
+    /// Test whether the change of the delta at index `idx` of `diff` matches.
+    ///
+    /// Binary files never match.
+    pub fn matches_delta(&mut self, diff: &Diff<'_>, idx: usize) -> Result<bool, Error> {
+        let patch = match Patch::from_diff(diff, idx)? {
+            Some(patch) => patch,
+            None => return Ok(false),
+        };
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for hunk_idx in 0..patch.num_hunks() {
+            for line_idx in 0..patch.num_lines_in_hunk(hunk_idx)? {
+                let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+                let side = match line.origin() {
+                    '-' => &mut removed,
+                    '+' => &mut added,
+                    _ => continue,
+                };
+                match self.kind {
+                    PickaxeKind::Occurrences(_) => side.extend_from_slice(line.content()),
+                    PickaxeKind::Lines(ref mut matcher) => {
+                        let mut content = line.content();
+                        if content.ends_with(b"\n") {
+                            content = &content[..content.len() - 1];
+                        }
+                        if matcher(content) {
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+        }
+        match self.kind {
+            PickaxeKind::Occurrences(ref needle) => {
+                Ok(count_occurrences(&removed, needle) != count_occurrences(&added, needle))
+            }
+            PickaxeKind::Lines(_) => Ok(false),
+        }
+    }
+
+    /// Test whether the changes a commit introduces match.
+    ///
+    /// The commit is compared with its parent, or with an empty tree if it
+    /// has none. Like `git log`, merge commits never match.
+    pub fn matches_commit(
+        &mut self,
+        repo: &Repository,
+        commit: &Commit<'_>,
+        opts: Option<&mut DiffOptions>,
+    ) -> Result<bool, Error> {
+        let parent = match commit.parent_count() {
+            0 => None,
+            1 => Some(commit.parent(0)?.tree()?),
+            _ => return Ok(false),
+        };
+        let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), opts)?;
+        for idx in 0..diff.deltas().len() {
+            if self.matches_delta(&diff, idx)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Filter a revision walk down to the commits whose changes match.
+    pub fn walk<'repo>(
+        self,
+        repo: &'repo Repository,
+        walk: Revwalk<'repo>,
+    ) -> PickaxeWalk<'repo, 'a> {
+        PickaxeWalk {
+            repo,
+            walk,
+            pickaxe: self,
+            diff_opts: None,
+        }
+    }
+}
+
+impl<'repo, 'a> PickaxeWalk<'repo, 'a> {
+    /// Set the options used to compute the changes of each commit, for
+    /// example to restrict the search to some paths.
+    pub fn diff_options(&mut self, opts: DiffOptions) -> &mut PickaxeWalk<'repo, 'a> {
+        self.diff_opts = Some(opts);
+        self
+    }
+
+    fn matches(&mut self, id: Oid) -> Result<bool, Error> {
+        let commit = self.repo.find_commit(id)?;
+        self.pickaxe
+            .matches_commit(self.repo, &commit, self.diff_opts.as_mut())
+    }
+}
+
+impl<'repo, 'a> Iterator for PickaxeWalk<'repo, 'a> {
+    type Item = Result<Oid, Error>;
+    fn next(&mut self) -> Option<Result<Oid, Error>> {
+        loop {
+            let id = match self.walk.next()? {
+                Ok(id) => id,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.matches(id) {
+                Ok(true) => return Some(Ok(id)),
+                Ok(false) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Counts the non-overlapping occurrences of `needle` in `haystack`.
+fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if &haystack[i..i + needle.len()] == needle {
+            count += 1;
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Pickaxe, Repository, Signature};
+    use std::fs;
+    use std::path::Path;
+
+    fn commit_file(repo: &Repository, contents: &str, message: &str) -> crate::Oid {
+        let root = repo.workdir().unwrap();
+        fs::write(root.join("file"), contents).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("name", "email").unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn search(repo: &Repository, pickaxe: Pickaxe<'_>) -> Vec<crate::Oid> {
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        pickaxe
+            .walk(repo, walk)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let add = commit_file(&repo, "one\nfoo()\n", "add foo");
+        let _other = commit_file(&repo, "two\nfoo()\n", "unrelated");
+        let touched = commit_file(&repo, "two\nfoo() // call\n", "comment foo");
+        let remove = commit_file(&repo, "two\n", "remove foo");
+
+        assert_eq!(
+            search(&repo, Pickaxe::occurrences("foo()")),
+            vec![remove, add]
+        );
+        assert_eq!(
+            search(&repo, Pickaxe::lines(|line| line.starts_with(b"foo"))),
+            vec![remove, touched, add]
+        );
+        assert!(search(&repo, Pickaxe::occurrences("bar")).is_empty());
+
+        let old = repo.find_commit(add).unwrap().tree().unwrap();
+        let new = repo.find_commit(remove).unwrap().tree().unwrap();
+        let diff = repo
+            .diff_tree_to_tree(Some(&old), Some(&new), None)
+            .unwrap();
+        let mut pickaxe = Pickaxe::occurrences("foo");
+        assert_eq!(diff.pickaxe(&mut pickaxe).unwrap(), vec![0]);
+        let mut pickaxe = Pickaxe::lines(|line| line == b"one");
+        assert_eq!(diff.pickaxe(&mut pickaxe).unwrap(), vec![0]);
+        let mut pickaxe = Pickaxe::lines(|line| line == b"three");
+        assert!(diff.pickaxe(&mut pickaxe).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,581 @@
+use std::fmt;
+
+use crate::{Error, ErrorClass, ErrorCode, Oid, PushUpdate, RemoteCallbacks, Repository};
+
+/// A proposed update of a reference, as evaluated by a `Policy`.
+///
+/// A zero `old` id means that the reference is being created, and a zero
+/// `new` id that it is being deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    refname: String,
+    old: Oid,
+    new: Oid,
+}
+
+/// A rule of a `Policy`, as reported by a `PolicyViolation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyRule {
+    /// Updates must fast-forward the reference.
+    NoForcePush,
+    /// The reference may not be deleted.
+    NoDeletion,
+    /// Every new commit must carry a signature.
+    RequireSignatures,
+    /// New commits may not be merge commits.
+    LinearHistory,
+    /// A rule registered with `Policy::rule`, with its name.
+    Custom(String),
+}
+
+/// A reference update which breaks a rule of a `Policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    rule: PolicyRule,
+    refname: String,
+    commit: Option<Oid>,
+    message: String,
+}
+
+/// Callback for a custom rule of a `Policy`.
+///
+/// The callback returns a message describing the violation, or `None` if
+/// the update is allowed.
+pub type PolicyCheck<'a> = dyn Fn(&Repository, &RefUpdate) -> Result<Option<String>, Error> + 'a;
+
+/// A set of rules reference updates must follow, such as branches which
+/// may not be force-pushed or which only accept signed commits.
+///
+/// Each rule applies to the references matching a pattern, in which `*`
+/// matches any sequence of characters (including `/`). A policy can be
+/// evaluated directly with `check`, for example from a `pre-receive` hook
+/// with the updates parsed by `RefUpdate::parse_hook_input`, or enforced on
+/// the pushes made through some `RemoteCallbacks` with `enforce`.
+pub struct Policy<'a> {
+    rules: Vec<Rule<'a>>,
+}
+
+struct Rule<'a> {
+    pattern: String,
+    kind: RuleKind<'a>,
+}
+
+enum RuleKind<'a> {
+    Builtin(PolicyRule),
+    Custom(String, Box<PolicyCheck<'a>>),
+}
+
+impl RefUpdate {
+    /// Creates an update of `refname` from `old` to `new`.
+    pub fn new(refname: &str, old: Oid, new: Oid) -> RefUpdate {
+        RefUpdate {
+            refname: refname.to_string(),
+            old,
+            new,
+        }
+    }
+
+    /// Parse the updates given on standard input to the `pre-receive`,
+    /// `post-receive` and `reference-transaction` hooks, one
+    /// `<old> <new> <refname>` line per update.
+    pub fn parse_hook_input(input: &str) -> Result<Vec<RefUpdate>, Error> {
+        let mut updates = Vec::new();
+        for line in input.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut parts = line.trim_end().splitn(3, ' ');
+            let (old, new, refname) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(old), Some(new), Some(refname)) => (old, new, refname),
+                _ => {
+                    return Err(Error::from_str(&format!(
+                        "invalid reference update '{}'",
+                        line
+                    )))
+                }
+            };
+            updates.push(RefUpdate::new(
+                refname,
+                Oid::from_str(old)?,
+                Oid::from_str(new)?,
+            ));
+        }
+        Ok(updates)
+    }
+
+    /// Returns the name of the updated reference.
+    pub fn refname(&self) -> &str {
+        &self.refname
+    }
+
+    /// Returns the previous target of the reference.
+    pub fn old(&self) -> Oid {
+        self.old
+    }
+
+    /// Returns the new target of the reference.
+    pub fn new_target(&self) -> Oid {
+        self.new
+    }
+
+    /// Returns `true` if the reference is being created.
+    pub fn is_create(&self) -> bool {
+        self.old.is_zero()
+    }
+
+    /// Returns `true` if the reference is being deleted.
+    pub fn is_delete(&self) -> bool {
+        self.new.is_zero()
+    }
+}
+
+impl<'a, 'b> From<&'b PushUpdate<'a>> for RefUpdate {
+    fn from(update: &'b PushUpdate<'a>) -> RefUpdate {
+        RefUpdate {
+            refname: String::from_utf8_lossy(update.dst_refname_bytes()).into_owned(),
+            old: update.src(),
+            new: update.dst(),
+        }
+    }
+}
+
+impl PolicyViolation {
+    /// Returns the rule which was broken.
+    pub fn rule(&self) -> &PolicyRule {
+        &self.rule
+    }
+
+    /// Returns the name of the reference whose update broke the rule.
+    pub fn refname(&self) -> &str {
+        &self.refname
+    }
+
+    /// Returns the offending commit, if the rule applies to single commits.
+    pub fn commit(&self) -> Option<Oid> {
+        self.commit
+    }
+
+    /// Returns a description of the violation.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.refname, self.message)
+    }
+}
+
+impl<'a> Default for Policy<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Policy<'a> {
+    /// Creates a policy without any rules.
+    pub fn new() -> Policy<'a> {
+        Policy { rules: Vec::new() }
+    }
+
+    /// Protect the references matching `pattern`: they may neither be
+    /// force-pushed nor deleted.
+    pub fn protect(&mut self, pattern: &str) -> &mut Policy<'a> {
+        self.builtin(pattern, PolicyRule::NoForcePush)
+            .builtin(pattern, PolicyRule::NoDeletion)
+    }
+
+    /// Require the new commits of the references matching `pattern` to be
+    /// signed.
+    ///
+    /// Only the presence of a signature is checked; verifying it is left to
+    /// a custom rule using `Repository::extract_signature`.
+    pub fn require_signatures(&mut self, pattern: &str) -> &mut Policy<'a> {
+        self.builtin(pattern, PolicyRule::RequireSignatures)
+    }
+
+    /// Forbid merge commits among the new commits of the references matching
+    /// `pattern`.
+    pub fn linear_history(&mut self, pattern: &str) -> &mut Policy<'a> {
+        self.builtin(pattern, PolicyRule::LinearHistory)
+    }
+
+    /// Register a custom rule named `name` for the references matching
+    /// `pattern`.
+    pub fn rule<F>(&mut self, name: &str, pattern: &str, check: F) -> &mut Policy<'a>
+    where
+        F: Fn(&Repository, &RefUpdate) -> Result<Option<String>, Error> + 'a,
+    {
+        self.rules.push(Rule {
+            pattern: pattern.to_string(),
+            kind: RuleKind::Custom(name.to_string(), Box::new(check)),
+        });
+        self
+    }
+
+    fn builtin(&mut self, pattern: &str, rule: PolicyRule) -> &mut Policy<'a> {
+        self.rules.push(Rule {
+            pattern: pattern.to_string(),
+            kind: RuleKind::Builtin(rule),
+        });
+        self
+    }
+
+    /// Evaluate `updates` against the rules of this policy, returning every
+    /// violation found.
+    ///
+    /// The new commits of an update are those reachable from its new target
+    /// but not from its old one. For a reference being created they are the
+    /// commits not reachable from any other reference of `repo`.
+    pub fn check(
+        &self,
+        repo: &Repository,
+        updates: &[RefUpdate],
+    ) -> Result<Vec<PolicyViolation>, Error> {
+        let mut violations = Vec::new();
+        for update in updates {
+            let mut new_commits = None;
+            for rule in self.rules.iter() {
+                if !pattern_matches(rule.pattern.as_bytes(), update.refname.as_bytes()) {
+                    continue;
+                }
+                let rule = match rule.kind {
+                    RuleKind::Builtin(ref rule) => rule,
+                    RuleKind::Custom(ref name, ref check) => {
+                        if let Some(message) = check(repo, update)? {
+                            violations.push(PolicyViolation {
+                                rule: PolicyRule::Custom(name.clone()),
+                                refname: update.refname.clone(),
+                                commit: None,
+                                message,
+                            });
+                        }
+                        continue;
+                    }
+                };
+                let commits = match *rule {
+                    PolicyRule::RequireSignatures | PolicyRule::LinearHistory => {
+                        if new_commits.is_none() {
+                            new_commits = Some(self::new_commits(repo, update)?);
+                        }
+                        new_commits.as_ref().unwrap()
+                    }
+                    _ => &[][..],
+                };
+                check_builtin(repo, rule, update, commits, &mut violations)?;
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Enforce this policy on the pushes made with `callbacks`.
+    ///
+    /// A push containing an update which breaks a rule is rejected before
+    /// anything is sent, and `Remote::push` returns an error listing the
+    /// violations.
+    pub fn enforce<'b>(&'b self, repo: &'b Repository, callbacks: &mut RemoteCallbacks<'b>)
+    where
+        'a: 'b,
+    {
+        callbacks.push_negotiation(move |updates| {
+            let updates = updates.iter().map(RefUpdate::from).collect::<Vec<_>>();
+            let violations = self.check(repo, &updates)?;
+            if violations.is_empty() {
+                return Ok(());
+            }
+            let message = violations
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            Err(Error::new(
+                ErrorCode::User,
+                ErrorClass::Callback,
+                &format!("push rejected by policy: {}", message),
+            ))
+        });
+    }
+}
+
+fn check_builtin(
+    repo: &Repository,
+    rule: &PolicyRule,
+    update: &RefUpdate,
+    commits: &[Oid],
+    violations: &mut Vec<PolicyViolation>,
+) -> Result<(), Error> {
+    let mut violation = |commit: Option<Oid>, message: String| {
+        violations.push(PolicyViolation {
+            rule: rule.clone(),
+            refname: update.refname.clone(),
+            commit,
+            message,
+        })
+    };
+    match *rule {
+        PolicyRule::NoForcePush => {
+            if update.is_create() || update.is_delete() || update.old == update.new {
+                return Ok(());
+            }
+            // An old target which is not known locally cannot be proven to
+            // be an ancestor, so it is treated as a rewrite.
+            if !repo
+                .graph_descendant_of(update.new, update.old)
+                .unwrap_or(false)
+            {
+                violation(
+                    None,
+                    format!(
+                        "update from {} to {} is not a fast-forward",
+                        update.old, update.new
+                    ),
+                );
+            }
+        }
+        PolicyRule::NoDeletion => {
+            if update.is_delete() && !update.is_create() {
+                violation(None, "the reference may not be deleted".to_string());
+            }
+        }
+        PolicyRule::RequireSignatures => {
+            for &id in commits {
+                match repo.extract_signature(&id, None) {
+                    Ok(_) => {}
+                    Err(ref e) if e.code() == ErrorCode::NotFound => {
+                        violation(Some(id), format!("commit {} is not signed", id));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        PolicyRule::LinearHistory => {
+            for &id in commits {
+                if repo.find_commit(id)?.parent_count() > 1 {
+                    violation(Some(id), format!("commit {} is a merge commit", id));
+                }
+            }
+        }
+        PolicyRule::Custom(_) => {}
+    }
+    Ok(())
+}
+
+/// Lists the commits an update adds to its reference.
+fn new_commits(repo: &Repository, update: &RefUpdate) -> Result<Vec<Oid>, Error> {
+    if update.is_delete() {
+        return Ok(Vec::new());
+    }
+    let mut walk = repo.revwalk()?;
+    walk.push(update.new)?;
+    if update.is_create() {
+        for reference in repo.references()? {
+            let reference = reference?;
+            if reference.name_bytes() == update.refname.as_bytes() {
+                continue;
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                walk.hide(commit.id())?;
+            }
+        }
+    } else if repo.find_commit(update.old).is_ok() {
+        walk.hide(update.old)?;
+    }
+    walk.collect()
+}
+
+/// Matches `name` against `pattern`, where `*` matches any sequence of bytes.
+fn pattern_matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((star, start)) = backtrack {
+            p = star + 1;
+            n = start + 1;
+            backtrack = Some((star, start + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pattern_matches;
+    use crate::{Oid, Policy, PolicyRule, PushOptions, RefUpdate, RemoteCallbacks, Repository};
+    use tempfile::TempDir;
+
+    fn commit(repo: &Repository, parents: &[Oid], message: &str) -> Oid {
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_commit(parents[0]).unwrap().tree().unwrap();
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        repo.commit(None, &sig, &sig, message, &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(pattern_matches(b"refs/heads/main", b"refs/heads/main"));
+        assert!(!pattern_matches(b"refs/heads/main", b"refs/heads/maint"));
+        assert!(pattern_matches(b"refs/heads/*", b"refs/heads/a/b"));
+        assert!(pattern_matches(
+            b"refs/*/release-*",
+            b"refs/heads/release-1"
+        ));
+        assert!(!pattern_matches(b"refs/*/release-*", b"refs/heads/main"));
+    }
+
+    #[test]
+    fn parse_hook_input() {
+        let zero = Oid::zero();
+        let id = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+        let input = format!(
+            "{} {} refs/heads/main\n{} {} refs/tags/v1\n",
+            zero, id, id, zero
+        );
+        let updates = RefUpdate::parse_hook_input(&input).unwrap();
+        assert_eq!(
+            updates,
+            vec![
+                RefUpdate::new("refs/heads/main", zero, id),
+                RefUpdate::new("refs/tags/v1", id, zero),
+            ]
+        );
+        assert!(updates[0].is_create());
+        assert!(updates[1].is_delete());
+        assert!(RefUpdate::parse_hook_input("garbage").is_err());
+    }
+
+    #[test]
+    fn protect() {
+        let (_td, repo) = crate::test::repo_init();
+        let c1 = repo.head().unwrap().target().unwrap();
+        let c2 = commit(&repo, &[c1], "second");
+
+        let mut policy = Policy::new();
+        policy.protect("refs/heads/main");
+        let check = |old, new| {
+            policy
+                .check(&repo, &[RefUpdate::new("refs/heads/main", old, new)])
+                .unwrap()
+                .into_iter()
+                .map(|v| v.rule().clone())
+                .collect::<Vec<_>>()
+        };
+        assert!(check(c1, c2).is_empty());
+        assert!(check(Oid::zero(), c2).is_empty());
+        assert_eq!(check(c2, c1), vec![PolicyRule::NoForcePush]);
+        assert_eq!(check(c2, Oid::zero()), vec![PolicyRule::NoDeletion]);
+
+        let topic = RefUpdate::new("refs/heads/topic", c2, c1);
+        assert!(policy.check(&repo, &[topic]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn new_commits() {
+        let (_td, repo) = crate::test::repo_init();
+        let c1 = repo.head().unwrap().target().unwrap();
+        let c2 = commit(&repo, &[c1], "second");
+        let c3 = commit(&repo, &[c1], "side");
+        let merge = commit(&repo, &[c2, c3], "merge");
+
+        let mut policy = Policy::new();
+        policy
+            .require_signatures("refs/heads/*")
+            .linear_history("refs/heads/*")
+            .rule("no-wip", "*", |_, update| {
+                Ok(if update.refname().contains("wip") {
+                    Some("work in progress".to_string())
+                } else {
+                    None
+                })
+            });
+
+        let violations = policy
+            .check(&repo, &[RefUpdate::new("refs/heads/main", c2, merge)])
+            .unwrap();
+        let mut found = violations
+            .iter()
+            .map(|v| (v.rule().clone(), v.commit()))
+            .collect::<Vec<_>>();
+        found.sort_by_key(|v| v.1);
+        let mut expected = vec![
+            (PolicyRule::RequireSignatures, Some(merge)),
+            (PolicyRule::RequireSignatures, Some(c3)),
+            (PolicyRule::LinearHistory, Some(merge)),
+        ];
+        expected.sort_by_key(|v| v.1);
+        assert_eq!(found.len(), expected.len());
+        for v in expected {
+            assert!(found.contains(&v));
+        }
+
+        let sig = repo.signature().unwrap();
+        let parent = repo.find_commit(c2).unwrap();
+        let tree = parent.tree().unwrap();
+        let buf = repo
+            .commit_create_buffer(&sig, &sig, "signed", &tree, &[&parent])
+            .unwrap();
+        let signed = repo
+            .commit_signed(buf.as_str().unwrap(), "fake signature", None)
+            .unwrap();
+        let update = RefUpdate::new("refs/heads/main", c2, signed);
+        assert!(policy.check(&repo, &[update]).unwrap().is_empty());
+
+        let update = RefUpdate::new("refs/wip/main", c2, merge);
+        let violations = policy.check(&repo, &[update]).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].rule(),
+            &PolicyRule::Custom("no-wip".to_string())
+        );
+        assert_eq!(violations[0].message(), "work in progress");
+    }
+
+    #[test]
+    fn enforce_on_push() {
+        let (_td, repo) = crate::test::repo_init();
+        let c1 = repo.head().unwrap().target().unwrap();
+        let td2 = TempDir::new().unwrap();
+        let url = crate::test::path2url(&td2.path());
+        let mut opts = crate::RepositoryInitOptions::new();
+        opts.bare(true);
+        opts.initial_head("main");
+        Repository::init_opts(td2.path(), &opts).unwrap();
+        let mut remote = repo.remote("origin", &url).unwrap();
+
+        let mut policy = Policy::new();
+        policy.protect("refs/heads/main");
+        let mut push = |refspec: &str| {
+            let mut callbacks = RemoteCallbacks::new();
+            policy.enforce(&repo, &mut callbacks);
+            let mut options = PushOptions::new();
+            options.remote_callbacks(callbacks);
+            remote.push(&[refspec], Some(&mut options))
+        };
+
+        let c2 = commit(&repo, &[c1], "second");
+        repo.reference("refs/heads/main", c2, true, "second")
+            .unwrap();
+        push("refs/heads/main").unwrap();
+        repo.reference("refs/heads/main", c1, true, "rewind")
+            .unwrap();
+        let err = push("+refs/heads/main").err().unwrap();
+        assert!(err.message().contains("refs/heads/main"));
+
+        let remote_repo = Repository::open(td2.path()).unwrap();
+        let head = remote_repo.refname_to_id("refs/heads/main").unwrap();
+        assert_eq!(head, c2);
+    }
+}
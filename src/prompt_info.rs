@@ -0,0 +1,407 @@
+use std::str;
+
+use crate::{BranchType, Error, ErrorCode, Oid, Repository, RepositoryState, Status};
+use crate::{StatusOptions, StatusShow};
+
+/// What a shell prompt or an editor shows about a repository: the current
+/// branch and its upstream, the counts of changed files and the state of an
+/// operation in progress.
+///
+/// Created with `Repository::prompt_info`, and encoded with `to_bytes` or
+/// `to_json` to be handed to a prompt by a daemon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptInfo {
+    head: Option<Oid>,
+    branch: Option<String>,
+    upstream: Option<String>,
+    state: RepositoryState,
+    ahead: usize,
+    behind: usize,
+    staged: usize,
+    unstaged: usize,
+    untracked: usize,
+    conflicted: usize,
+    stashes: usize,
+}
+
+const MAGIC: &[u8] = b"gp\x01";
+
+const STATES: &[(RepositoryState, &str)] = &[
+    (RepositoryState::Clean, "clean"),
+    (RepositoryState::Merge, "merge"),
+    (RepositoryState::Revert, "revert"),
+    (RepositoryState::RevertSequence, "revert-sequence"),
+    (RepositoryState::CherryPick, "cherry-pick"),
+    (RepositoryState::CherryPickSequence, "cherry-pick-sequence"),
+    (RepositoryState::Bisect, "bisect"),
+    (RepositoryState::Rebase, "rebase"),
+    (RepositoryState::RebaseInteractive, "rebase-interactive"),
+    (RepositoryState::RebaseMerge, "rebase-merge"),
+    (RepositoryState::ApplyMailbox, "am"),
+    (RepositoryState::ApplyMailboxOrRebase, "am-or-rebase"),
+];
+
+impl PromptInfo {
+    pub(crate) fn new(repo: &Repository) -> Result<PromptInfo, Error> {
+        let head_ref = repo.find_reference("HEAD")?;
+        let branch = head_ref
+            .symbolic_target()
+            .and_then(|name| name.strip_prefix("refs/heads/"))
+            .map(|name| name.to_string());
+        let head = head_ref.resolve().ok().and_then(|r| r.target());
+        let mut info = PromptInfo {
+            head,
+            branch,
+            upstream: None,
+            state: repo.state(),
+            ahead: 0,
+            behind: 0,
+            staged: 0,
+            unstaged: 0,
+            untracked: 0,
+            conflicted: 0,
+            stashes: 0,
+        };
+
+        if let (Some(name), Some(head)) = (info.branch.as_ref(), head) {
+            match repo.find_branch(name, BranchType::Local)?.upstream() {
+                Ok(upstream) => {
+                    let upstream = upstream.into_reference();
+                    info.upstream = upstream.shorthand().map(|s| s.to_string());
+                    if let Some(target) = upstream.target() {
+                        let (ahead, behind) = repo.graph_ahead_behind(head, target)?;
+                        info.ahead = ahead;
+                        info.behind = behind;
+                    }
+                }
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        match repo.reflog("refs/stash") {
+            Ok(reflog) => info.stashes = reflog.len(),
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if repo.is_bare() {
+            return Ok(info);
+        }
+        // Untracked directories are counted once instead of being walked, and
+        // neither renames nor submodules are looked at.
+        let mut opts = StatusOptions::new();
+        opts.show(StatusShow::IndexAndWorkdir)
+            .include_untracked(true)
+            .recurse_untracked_dirs(false)
+            .exclude_submodules(true);
+        for entry in repo.statuses(Some(&mut opts))?.iter() {
+            let status = entry.status();
+            if status.is_conflicted() {
+                info.conflicted += 1;
+                continue;
+            }
+            if status.is_wt_new() {
+                info.untracked += 1;
+            }
+            if status.intersects(
+                Status::INDEX_NEW
+                    | Status::INDEX_MODIFIED
+                    | Status::INDEX_DELETED
+                    | Status::INDEX_RENAMED
+                    | Status::INDEX_TYPECHANGE,
+            ) {
+                info.staged += 1;
+            }
+            if status.intersects(
+                Status::WT_MODIFIED
+                    | Status::WT_DELETED
+                    | Status::WT_RENAMED
+                    | Status::WT_TYPECHANGE,
+            ) {
+                info.unstaged += 1;
+            }
+        }
+        Ok(info)
+    }
+
+    /// Returns the commit `HEAD` points to, or `None` if the current branch
+    /// has no commits yet.
+    pub fn head(&self) -> Option<Oid> {
+        self.head
+    }
+
+    /// Returns the short name of the current branch, or `None` if `HEAD` is
+    /// detached.
+    pub fn branch(&self) -> Option<&str> {
+        self.branch.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns the short name of the upstream of the current branch, such
+    /// as `origin/main`.
+    pub fn upstream(&self) -> Option<&str> {
+        self.upstream.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns the state of an operation in progress, such as a merge.
+    pub fn state(&self) -> RepositoryState {
+        self.state
+    }
+
+    /// Returns the number of commits on the current branch which are not on
+    /// its upstream.
+    pub fn ahead(&self) -> usize {
+        self.ahead
+    }
+
+    /// Returns the number of commits on the upstream which are not on the
+    /// current branch.
+    pub fn behind(&self) -> usize {
+        self.behind
+    }
+
+    /// Returns the number of files with changes in the index.
+    pub fn staged(&self) -> usize {
+        self.staged
+    }
+
+    /// Returns the number of tracked files with changes in the working
+    /// directory which are not in the index.
+    pub fn unstaged(&self) -> usize {
+        self.unstaged
+    }
+
+    /// Returns the number of untracked files, an untracked directory
+    /// counting as one.
+    pub fn untracked(&self) -> usize {
+        self.untracked
+    }
+
+    /// Returns the number of conflicted files.
+    pub fn conflicted(&self) -> usize {
+        self.conflicted
+    }
+
+    /// Returns the number of stashes.
+    pub fn stashes(&self) -> usize {
+        self.stashes
+    }
+
+    /// Returns `true` if there are no changes to tracked or untracked files.
+    pub fn is_clean(&self) -> bool {
+        self.staged + self.unstaged + self.untracked + self.conflicted == 0
+    }
+
+    /// Encode this information compactly.
+    ///
+    /// After a three byte magic number, the format is a byte of flags for
+    /// the presence of the head, branch and upstream, the raw head id, a
+    /// byte for the state, the counts as LEB128 varints, and the branch and
+    /// upstream names prefixed by their length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        let flags = self.head.map(|_| 1).unwrap_or(0)
+            | self.branch.as_ref().map(|_| 2).unwrap_or(0)
+            | self.upstream.as_ref().map(|_| 4).unwrap_or(0);
+        out.push(flags);
+        if let Some(head) = self.head {
+            out.extend_from_slice(head.as_bytes());
+        }
+        let state = STATES.iter().position(|(s, _)| *s == self.state);
+        out.push(state.unwrap_or(0) as u8);
+        for &n in self.counts().iter() {
+            write_varint(&mut out, n);
+        }
+        for name in self.branch.iter().chain(self.upstream.iter()) {
+            write_varint(&mut out, name.len());
+            out.extend_from_slice(name.as_bytes());
+        }
+        out
+    }
+
+    /// Decode information written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PromptInfo, Error> {
+        let invalid = || Error::from_str("invalid prompt info");
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid());
+        }
+        let mut rest = &bytes[MAGIC.len()..];
+        let mut take = |n: usize| {
+            if rest.len() < n {
+                return Err(invalid());
+            }
+            let (taken, left) = rest.split_at(n);
+            rest = left;
+            Ok(taken)
+        };
+        let flags = take(1)?[0];
+        let head = match flags & 1 {
+            0 => None,
+            _ => Some(Oid::from_bytes(take(20)?)?),
+        };
+        let state = STATES.get(take(1)?[0] as usize).ok_or_else(invalid)?.0;
+        let mut counts = [0; 7];
+        for count in counts.iter_mut() {
+            *count = read_varint(&mut take)?;
+        }
+        let mut name = |present: bool| -> Result<Option<String>, Error> {
+            if !present {
+                return Ok(None);
+            }
+            let len = read_varint(&mut take)?;
+            let name = str::from_utf8(take(len)?).map_err(|_| invalid())?;
+            Ok(Some(name.to_string()))
+        };
+        let branch = name(flags & 2 != 0)?;
+        let upstream = name(flags & 4 != 0)?;
+        Ok(PromptInfo {
+            head,
+            branch,
+            upstream,
+            state,
+            ahead: counts[0],
+            behind: counts[1],
+            staged: counts[2],
+            unstaged: counts[3],
+            untracked: counts[4],
+            conflicted: counts[5],
+            stashes: counts[6],
+        })
+    }
+
+    /// Encode this information as a JSON object, with `null` for the
+    /// missing head, branch or upstream.
+    pub fn to_json(&self) -> String {
+        let string = |s: Option<&str>| s.map(json_string).unwrap_or_else(|| "null".to_string());
+        let head = self.head.map(|id| id.to_string());
+        let state = STATES
+            .iter()
+            .find(|(s, _)| *s == self.state)
+            .map(|(_, name)| *name)
+            .unwrap_or("clean");
+        let counts = self.counts();
+        format!(
+            "{{\"head\":{},\"branch\":{},\"upstream\":{},\"state\":\"{}\",\
+             \"ahead\":{},\"behind\":{},\"staged\":{},\"unstaged\":{},\
+             \"untracked\":{},\"conflicted\":{},\"stashes\":{}}}",
+            string(head.as_ref().map(|s| &s[..])),
+            string(self.branch()),
+            string(self.upstream()),
+            state,
+            counts[0],
+            counts[1],
+            counts[2],
+            counts[3],
+            counts[4],
+            counts[5],
+            counts[6]
+        )
+    }
+
+    fn counts(&self) -> [usize; 7] {
+        [
+            self.ahead,
+            self.behind,
+            self.staged,
+            self.unstaged,
+            self.untracked,
+            self.conflicted,
+            self.stashes,
+        ]
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint<'a, F>(take: &mut F) -> Result<usize, Error>
+where
+    F: FnMut(usize) -> Result<&'a [u8], Error>,
+{
+    let mut n = 0usize;
+    for shift in (0..64).step_by(7) {
+        let byte = take(1)?[0];
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(Error::from_str("invalid prompt info"))
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PromptInfo, RepositoryState};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn prompt_info() {
+        let (td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        repo.reference("refs/remotes/origin/main", head, false, "")
+            .unwrap();
+        repo.remote("origin", "https://example.com/repo").unwrap();
+        let mut branch = repo.find_branch("main", crate::BranchType::Local).unwrap();
+        branch.set_upstream(Some("origin/main")).unwrap();
+
+        fs::write(td.path().join("staged"), "staged\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged")).unwrap();
+        index.write().unwrap();
+        fs::create_dir(td.path().join("dir")).unwrap();
+        fs::write(td.path().join("dir/a"), "a\n").unwrap();
+        fs::write(td.path().join("dir/b"), "b\n").unwrap();
+
+        let info = repo.prompt_info().unwrap();
+        assert_eq!(info.head(), Some(head));
+        assert_eq!(info.branch(), Some("main"));
+        assert_eq!(info.upstream(), Some("origin/main"));
+        assert_eq!(info.state(), RepositoryState::Clean);
+        assert_eq!((info.ahead(), info.behind()), (0, 0));
+        assert_eq!(info.staged(), 1);
+        assert_eq!(info.unstaged(), 0);
+        assert_eq!(info.untracked(), 1);
+        assert_eq!(info.stashes(), 0);
+        assert!(!info.is_clean());
+
+        assert_eq!(PromptInfo::from_bytes(&info.to_bytes()).unwrap(), info);
+        assert!(PromptInfo::from_bytes(&info.to_bytes()[..30]).is_err());
+        assert_eq!(
+            info.to_json(),
+            format!(
+                "{{\"head\":\"{}\",\"branch\":\"main\",\"upstream\":\"origin/main\",\
+                 \"state\":\"clean\",\"ahead\":0,\"behind\":0,\"staged\":1,\
+                 \"unstaged\":0,\"untracked\":1,\"conflicted\":0,\"stashes\":0}}",
+                head
+            )
+        );
+
+        repo.set_head_detached(head).unwrap();
+        let info = repo.prompt_info().unwrap();
+        assert_eq!(info.branch(), None);
+        assert_eq!(info.upstream(), None);
+        assert_eq!(PromptInfo::from_bytes(&info.to_bytes()).unwrap(), info);
+        assert!(info.to_json().contains("\"branch\":null"));
+    }
+}
@@ -1,9 +1,14 @@
 use std::ffi::CString;
-use std::{marker, mem, ptr, str};
+use std::process::Command;
+use std::{fs, io, marker, mem, ptr, str};
 
 use crate::build::CheckoutBuilder;
 use crate::util::Binding;
-use crate::{raw, Error, Index, MergeOptions, Oid, Signature};
+use crate::MergeConflict;
+use crate::StatusOptions;
+use crate::{raw, Commit, Error, ErrorClass, ErrorCode, Index, MergeOptions, Oid, Repository};
+use crate::{RebaseTodo, RebaseTodoCommand, RebaseTodoItem};
+use crate::{Signature, Time};
 
 /// Rebase options
 ///
@@ -13,6 +18,9 @@ pub struct RebaseOptions<'cb> {
     rewrite_notes_ref: Option<CString>,
     merge_options: Option<MergeOptions>,
     checkout_options: Option<CheckoutBuilder<'cb>>,
+    autostash: bool,
+    committer_date_is_author_date: bool,
+    ignore_date: bool,
 }
 
 impl<'cb> Default for RebaseOptions<'cb> {
@@ -29,6 +37,9 @@ impl<'cb> RebaseOptions<'cb> {
             rewrite_notes_ref: None,
             merge_options: None,
             checkout_options: None,
+            autostash: false,
+            committer_date_is_author_date: false,
+            ignore_date: false,
         };
         assert_eq!(unsafe { raw::git_rebase_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -54,6 +65,61 @@ impl<'cb> RebaseOptions<'cb> {
         self
     }
 
+    /// Used by `Repository::rebase`, this will stash the changes of the index
+    /// and the working directory before a rebase which isn't in-memory, and
+    /// apply them again once the rebase is finished or aborted, like
+    /// `git rebase --autostash`. Untracked files are not stashed.
+    ///
+    /// The changes are kept in the stash list while the rebase runs. If
+    /// applying them again conflicts, they are left there and `finish()` or
+    /// `abort()` returns an error once the rebase itself is done.
+    pub fn autostash(&mut self, autostash: bool) -> &mut RebaseOptions<'cb> {
+        self.autostash = autostash;
+        self
+    }
+
+    /// Used by `Rebase::commit`, this will use the date of the author of
+    /// each commit as its committer date, like
+    /// `git rebase --committer-date-is-author-date`.
+    pub fn committer_date_is_author_date(&mut self, enabled: bool) -> &mut RebaseOptions<'cb> {
+        self.committer_date_is_author_date = enabled;
+        self
+    }
+
+    /// Used by `Rebase::commit`, this will use the date of the committer as
+    /// the author date of each commit instead of keeping the original one,
+    /// like `git rebase --ignore-date`.
+    pub fn ignore_date(&mut self, enabled: bool) -> &mut RebaseOptions<'cb> {
+        self.ignore_date = enabled;
+        self
+    }
+
+    /// Returns a copy of these options with `inmemory` set, leaving these
+    /// unchanged.
+    ///
+    /// The checkout options are not copied, as an in-memory rebase does not
+    /// check anything out. The merge options of the copy point into those of
+    /// `self`, so the copy must not outlive it.
+    pub(crate) fn inmemory_copy(&mut self) -> RebaseOptions<'cb> {
+        let mut copy = RebaseOptions::new();
+        copy.raw.quiet = self.raw.quiet;
+        copy.raw.inmemory = 1;
+        if let Some(opts) = self.merge_options.as_mut() {
+            unsafe {
+                ptr::copy_nonoverlapping(opts.raw(), &mut copy.raw.merge_options, 1);
+            }
+        }
+        copy.rewrite_notes_ref = self.rewrite_notes_ref.clone();
+        copy.autostash = self.autostash;
+        copy.committer_date_is_author_date = self.committer_date_is_author_date;
+        copy.ignore_date = self.ignore_date;
+        copy
+    }
+
+    pub(crate) fn autostash_enabled(&self) -> bool {
+        self.autostash && self.raw.inmemory == 0
+    }
+
     /// Used by `finish()`, this is the name of the notes reference
     /// used to rewrite notes for rebased commits when finishing the rebase;
     /// if NULL, the contents of the configuration option `notes.rewriteRef`
@@ -101,15 +167,58 @@ impl<'cb> RebaseOptions<'cb> {
 }
 
 /// Representation of a rebase
+///
+/// The plan of a rebase can be changed with `set_todo` before and during
+/// iteration. libgit2 only knows how to pick commits, so dropped commits,
+/// `exec` commands and the melding of `squash` and `fixup` commits into
+/// their predecessor are handled by this type.
 pub struct Rebase<'repo> {
     raw: *mut raw::git_rebase,
+    repo: Option<&'repo Repository>,
+    inmemory: bool,
+    dropped: usize,
+    execs: Vec<RebaseExec>,
+    ran_execs: Vec<RebaseExec>,
+    replaced: Option<(Oid, Oid)>,
+    exec_handler: Option<Box<ExecHandler<'repo>>>,
+    committer_date_is_author_date: bool,
+    ignore_date: bool,
+    signature_handler: Option<Box<SignatureHandler<'repo>>>,
     _marker: marker::PhantomData<&'repo raw::git_rebase>,
 }
 
+type ExecHandler<'a> = dyn FnMut(&str) -> Result<(), Error> + 'a;
+
+type SignatureHandler<'a> = dyn FnMut(
+        &Commit<'_>,
+        &Signature<'_>,
+        &Signature<'_>,
+    ) -> Option<(Signature<'static>, Signature<'static>)>
+    + 'a;
+
+/// An `exec` command scheduled to run before the operation at `before`.
+struct RebaseExec {
+    before: usize,
+    command: CString,
+    raw: Box<raw::git_rebase_operation>,
+}
+
 impl<'repo> Rebase<'repo> {
+    pub(crate) fn attach(&mut self, repo: &'repo Repository, opts: Option<&RebaseOptions<'_>>) {
+        self.repo = Some(repo);
+        if let Some(opts) = opts {
+            self.inmemory = opts.raw.inmemory != 0;
+            self.committer_date_is_author_date = opts.committer_date_is_author_date;
+            self.ignore_date = opts.ignore_date;
+        }
+    }
+
     /// Gets the count of rebase operations that are to be applied.
+    ///
+    /// Commits dropped with `set_todo` are not counted, and neither are
+    /// `exec` commands.
     pub fn len(&self) -> usize {
-        unsafe { raw::git_rebase_operation_entrycount(self.raw) }
+        unsafe { raw::git_rebase_operation_entrycount(self.raw) - self.dropped }
     }
 
     /// Gets the original `HEAD` ref name for merge rebases.
@@ -126,6 +235,9 @@ impl<'repo> Rebase<'repo> {
 
     ///  Gets the rebase operation specified by the given index.
     pub fn nth(&mut self, n: usize) -> Option<RebaseOperation<'_>> {
+        if n >= self.len() {
+            return None;
+        }
         unsafe {
             let op = raw::git_rebase_operation_byindex(self.raw, n);
             if op.is_null() {
@@ -148,6 +260,193 @@ impl<'repo> Rebase<'repo> {
         }
     }
 
+    /// Returns the operations that are still to be run, as a todo list.
+    ///
+    /// Each commit is written as its full id.
+    pub fn todo(&self) -> RebaseTodo {
+        let mut items = Vec::new();
+        let mut execs = self.execs.iter().peekable();
+        for n in self.next_index()..self.len() {
+            while let Some(exec) = execs.peek().filter(|e| e.before <= n) {
+                items.push(exec.item());
+                execs.next();
+            }
+            let op = unsafe { RebaseOperation::from_raw(self.op(n)) };
+            let command = match op.kind() {
+                Some(RebaseOperationType::Reword) => RebaseTodoCommand::Reword,
+                Some(RebaseOperationType::Edit) => RebaseTodoCommand::Edit,
+                Some(RebaseOperationType::Squash) => RebaseTodoCommand::Squash,
+                Some(RebaseOperationType::Fixup) => RebaseTodoCommand::Fixup,
+                _ => RebaseTodoCommand::Pick,
+            };
+            items.push(RebaseTodoItem::new(command, op.id().to_string()));
+        }
+        items.extend(execs.map(|e| e.item()));
+        RebaseTodo { items }
+    }
+
+    /// Replaces the operations that are still to be run.
+    ///
+    /// `todo` may reorder the pending commits and use `pick`, `reword`,
+    /// `edit`, `squash`, `fixup`, `drop`, `exec` and `noop`. Commits are
+    /// named by an unambiguous prefix of their id and must be among the
+    /// pending ones; pending commits left out of the list are dropped, like
+    /// the command line client does. Operations that were already applied
+    /// can't be changed.
+    ///
+    /// Committing a `squash` or `fixup` operation melds it into the previous
+    /// commit, combining the messages for `squash` and keeping the previous
+    /// message for `fixup`, unless a message is given to `commit`. `exec`
+    /// commands are returned by the iterator as operations of type
+    /// `RebaseOperationType::Exec` and are left to the caller to run.
+    ///
+    /// For a rebase in the working directory the new order and dropped
+    /// commits are saved to its state, but the operation types and `exec`
+    /// commands are only kept by this `Rebase`.
+    pub fn set_todo(&mut self, todo: &RebaseTodo) -> Result<(), Error> {
+        let start = self.next_index();
+        let end = self.len();
+        let pending = (start..end)
+            .map(|n| unsafe { Oid::from_raw(&(*self.op(n)).id) })
+            .collect::<Vec<_>>();
+        let mut used = vec![false; pending.len()];
+        let mut picks = Vec::new();
+        let mut execs = Vec::new();
+        for item in todo.items.iter() {
+            let kind = match item.command {
+                RebaseTodoCommand::Exec => {
+                    execs.push(RebaseExec::new(start + picks.len(), &item.args)?);
+                    continue;
+                }
+                RebaseTodoCommand::Noop => continue,
+                RebaseTodoCommand::Drop => None,
+                RebaseTodoCommand::Pick => Some(raw::GIT_REBASE_OPERATION_PICK),
+                RebaseTodoCommand::Reword => Some(raw::GIT_REBASE_OPERATION_REWORD),
+                RebaseTodoCommand::Edit => Some(raw::GIT_REBASE_OPERATION_EDIT),
+                RebaseTodoCommand::Squash => Some(raw::GIT_REBASE_OPERATION_SQUASH),
+                RebaseTodoCommand::Fixup => Some(raw::GIT_REBASE_OPERATION_FIXUP),
+                command => {
+                    let msg = format!("unsupported rebase command '{}'", command);
+                    return Err(Error::from_str(&msg));
+                }
+            };
+            let spec = item
+                .commit()
+                .ok_or_else(|| Error::from_str("rebase todo item is missing a commit"))?;
+            let n = find_pending(&pending, spec)?;
+            if used[n] {
+                let msg = format!("commit '{}' is listed more than once", spec);
+                return Err(Error::from_str(&msg));
+            }
+            used[n] = true;
+            if let Some(kind) = kind {
+                let meld = kind == raw::GIT_REBASE_OPERATION_SQUASH
+                    || kind == raw::GIT_REBASE_OPERATION_FIXUP;
+                if meld && start == 0 && picks.is_empty() {
+                    let msg = format!("cannot '{}' without a previous commit", item.command);
+                    return Err(Error::from_str(&msg));
+                }
+                picks.push((pending[n], kind));
+            }
+        }
+
+        let kept = picks.len();
+        let dropped = pending
+            .iter()
+            .zip(used.iter())
+            .filter(|&(_, used)| !used)
+            .map(|(id, _)| (*id, raw::GIT_REBASE_OPERATION_PICK))
+            .collect::<Vec<_>>();
+        picks.extend(dropped);
+        for (n, &(id, kind)) in picks.iter().enumerate() {
+            unsafe {
+                let op = self.op(start + n);
+                (*op).id = *id.raw();
+                (*op).kind = kind;
+            }
+        }
+        self.dropped += end - start - kept;
+        self.execs = execs;
+        self.save_plan(start, &picks, start + kept)
+    }
+
+    /// Adds an `exec` operation running `command` after each pending commit,
+    /// like `git rebase --exec`. Commits followed by a `squash` or `fixup`
+    /// are not tested on their own, the command runs after the last commit
+    /// melded into them.
+    pub fn exec_after_each(&mut self, command: &str) -> Result<(), Error> {
+        let todo = self.todo();
+        let mut items = Vec::new();
+        for (n, item) in todo.items.iter().enumerate() {
+            items.push(item.clone());
+            let melded = todo.items.get(n + 1).iter().any(|next| {
+                next.command == RebaseTodoCommand::Squash
+                    || next.command == RebaseTodoCommand::Fixup
+            });
+            if item.command.takes_commit() && !melded {
+                items.push(RebaseTodoItem::new(RebaseTodoCommand::Exec, command));
+            }
+        }
+        self.set_todo(&RebaseTodo { items })
+    }
+
+    /// Runs `exec` operations with `handler` instead of returning them from
+    /// the iterator.
+    ///
+    /// The handler is given the command. If it fails, the iterator returns
+    /// its error and the rebase pauses there, with the commits before it
+    /// applied: the caller can fix or amend them and call `next` again to
+    /// go on with the operation after the failed command.
+    pub fn exec_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&str) -> Result<(), Error> + 'repo,
+    {
+        self.exec_handler = Some(Box::new(handler));
+    }
+
+    /// Overrides the signatures of each commit made by `commit`.
+    ///
+    /// The handler is given the original commit and the author and committer
+    /// the rebased commit would get, after the date options of
+    /// `RebaseOptions` are applied, and can return the author and committer
+    /// to use instead.
+    pub fn signature_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(
+                &Commit<'_>,
+                &Signature<'_>,
+                &Signature<'_>,
+            ) -> Option<(Signature<'static>, Signature<'static>)>
+            + 'repo,
+    {
+        self.signature_handler = Some(Box::new(handler));
+    }
+
+    /// Runs `exec` operations with `sh -c` in the working directory, as
+    /// `git rebase` does. A command fails when it exits with a non-zero
+    /// status.
+    pub fn exec_in_shell(&mut self) -> Result<(), Error> {
+        let repo = self
+            .repo
+            .ok_or_else(|| Error::from_str("rebase is not associated with a repository"))?;
+        let dir = repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf();
+        self.exec_handler(move |command| {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .current_dir(&dir)
+                .status()
+                .map_err(|e| Error::from_str(&format!("failed to run '{}': {}", command, e)))?;
+            if status.success() {
+                Ok(())
+            } else {
+                let msg = format!("exec '{}' failed ({})", command, status);
+                Err(Error::from_str(&msg))
+            }
+        });
+        Ok(())
+    }
+
     /// Gets the index produced by the last operation, which is the result of
     /// `next()` and which will be committed by the next invocation of
     /// `commit()`. This is useful for resolving conflicts in an in-memory
@@ -163,6 +462,38 @@ impl<'repo> Rebase<'repo> {
         }
     }
 
+    /// Returns the conflicts of the current operation, with the commit it
+    /// applies, or `None` if it applied cleanly or no operation is running.
+    ///
+    /// The conflicts are read from the in-memory index of an in-memory
+    /// rebase, and from the repository's index otherwise.
+    pub fn conflicts(&mut self) -> Result<Option<RebaseConflicts>, Error> {
+        let commit = match self.operation_current() {
+            Some(n) => unsafe { Binding::from_raw(&(*self.op(n)).id as *const _) },
+            None => return Ok(None),
+        };
+        let index = if self.inmemory {
+            self.inmemory_index()?
+        } else {
+            self.repo
+                .ok_or_else(|| Error::from_str("rebase is not associated with a repository"))?
+                .index()?
+        };
+        if !index.has_conflicts() {
+            return Ok(None);
+        }
+        let mut files = Vec::new();
+        for conflict in index.conflicts()? {
+            let conflict = conflict?;
+            files.push(MergeConflict {
+                ancestor: conflict.ancestor,
+                our: conflict.our,
+                their: conflict.their,
+            });
+        }
+        Ok(Some(RebaseConflicts { commit, files }))
+    }
+
     /// Commits the current patch.  You must have resolved any conflicts that
     /// were introduced during the patch application from the `git_rebase_next`
     /// invocation. To keep the author and message from the original commit leave
@@ -173,9 +504,15 @@ impl<'repo> Rebase<'repo> {
         committer: &Signature<'_>,
         message: Option<&str>,
     ) -> Result<Oid, Error> {
+        let signatures = self.signatures(author, committer)?;
+        let (author, committer) = match signatures {
+            Some((ref author, ref committer)) => (Some(author), committer),
+            None => (author, committer),
+        };
         let mut id: raw::git_oid = unsafe { mem::zeroed() };
+        let has_message = message.is_some();
         let message = crate::opt_cstr(message)?;
-        unsafe {
+        let id = unsafe {
             try_call!(raw::git_rebase_commit(
                 &mut id,
                 self.raw,
@@ -184,28 +521,201 @@ impl<'repo> Rebase<'repo> {
                 ptr::null(),
                 message
             ));
-            Ok(Binding::from_raw(&id as *const _))
+            Binding::from_raw(&id as *const _)
+        };
+        self.meld(id, has_message)
+    }
+
+    /// Applies the date options and the signature handler to the signatures
+    /// of the current commit, if any of them is set.
+    fn signatures(
+        &mut self,
+        author: Option<&Signature<'_>>,
+        committer: &Signature<'_>,
+    ) -> Result<Option<(Signature<'static>, Signature<'static>)>, Error> {
+        if !self.ignore_date
+            && !self.committer_date_is_author_date
+            && self.signature_handler.is_none()
+        {
+            return Ok(None);
+        }
+        let (repo, n) = match (self.repo, self.operation_current()) {
+            (Some(repo), Some(n)) => (repo, n),
+            _ => return Ok(None),
+        };
+        let original =
+            repo.find_commit(unsafe { Binding::from_raw(&(*self.op(n)).id as *const _) })?;
+        let mut author = match author {
+            Some(author) => author.to_owned(),
+            None => original.author().to_owned(),
+        };
+        let mut committer = committer.to_owned();
+        if self.ignore_date {
+            author = redate(&author, &committer.when())?;
+        }
+        if self.committer_date_is_author_date {
+            committer = redate(&committer, &author.when())?;
+        }
+        if let Some(handler) = self.signature_handler.as_mut() {
+            if let Some(signatures) = handler(&original, &author, &committer) {
+                return Ok(Some(signatures));
+            }
         }
+        Ok(Some((author, committer)))
+    }
+
+    /// Rewrites a commit made by libgit2 to follow the plan: `squash` and
+    /// `fixup` operations are folded into the previous commit, and in an
+    /// in-memory rebase commits made on top of a folded commit are moved
+    /// onto its replacement.
+    fn meld(&mut self, id: Oid, has_message: bool) -> Result<Oid, Error> {
+        let kind = match self.operation_current() {
+            Some(n) => unsafe { (*self.op(n)).kind },
+            None => return Ok(id),
+        };
+        let fold =
+            kind == raw::GIT_REBASE_OPERATION_SQUASH || kind == raw::GIT_REBASE_OPERATION_FIXUP;
+        if !fold && self.replaced.is_none() {
+            return Ok(id);
+        }
+        let repo = self
+            .repo
+            .ok_or_else(|| Error::from_str("rebase is not associated with a repository"))?;
+        let commit = repo.find_commit(id)?;
+        let parent = commit.parent_id(0)?;
+        let parent = match self.replaced {
+            Some((from, to)) if from == parent => to,
+            _ => parent,
+        };
+        let new_id = if fold {
+            let previous = repo.find_commit(parent)?;
+            let message = if has_message {
+                commit.message_raw_bytes().to_vec()
+            } else if kind == raw::GIT_REBASE_OPERATION_FIXUP {
+                previous.message_raw_bytes().to_vec()
+            } else {
+                let mut message = previous.message_raw_bytes().to_vec();
+                message.extend_from_slice(b"\n\n");
+                message.extend_from_slice(commit.message_raw_bytes());
+                crate::message_prettify(message, None)?.into_bytes()
+            };
+            let message = str::from_utf8(&message)
+                .map_err(|_| Error::from_str("commit message is not valid utf-8"))?;
+            let parents = previous.parents().collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            let id = repo.commit(
+                None,
+                &previous.author(),
+                &commit.committer(),
+                message,
+                &commit.tree()?,
+                &parents,
+            )?;
+            id
+        } else if parent != commit.parent_id(0)? {
+            let parent = repo.find_commit(parent)?;
+            let message = commit
+                .message()
+                .ok_or_else(|| Error::from_str("commit message is not valid utf-8"))?;
+            repo.commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                message,
+                &commit.tree()?,
+                &[&parent],
+            )?
+        } else {
+            return Ok(id);
+        };
+        if self.inmemory {
+            self.replaced = Some((id, new_id));
+        } else {
+            repo.set_head_detached(new_id)?;
+        }
+        Ok(new_id)
+    }
+
+    fn op(&self, n: usize) -> *mut raw::git_rebase_operation {
+        unsafe { raw::git_rebase_operation_byindex(self.raw, n) }
+    }
+
+    /// Index of the operation the next call to `next` runs.
+    fn next_index(&self) -> usize {
+        let cur = unsafe { raw::git_rebase_operation_current(self.raw) };
+        if cur == raw::GIT_REBASE_NO_OPERATION {
+            0
+        } else {
+            cur + 1
+        }
+    }
+
+    /// Writes the new order of the operations from `start` on to the state
+    /// of a rebase in the working directory, keeping only `end` of them.
+    fn save_plan(&self, start: usize, ops: &[(Oid, u32)], end: usize) -> Result<(), Error> {
+        let dir = match self.repo {
+            Some(repo) if !self.inmemory => repo.path().join("rebase-merge"),
+            _ => return Ok(()),
+        };
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        let io_err = |e: io::Error| Error::from_str(&format!("{}: {}", dir.display(), e));
+        for (n, &(id, _)) in ops.iter().enumerate() {
+            let file = dir.join(format!("cmt.{}", start + n + 1));
+            fs::write(file, format!("{}\n", id)).map_err(io_err)?;
+        }
+        fs::write(dir.join("end"), format!("{}\n", end)).map_err(io_err)
     }
 
     /// Aborts a rebase that is currently in progress, resetting the repository
     /// and working directory to their state before rebase began.
     pub fn abort(&mut self) -> Result<(), Error> {
+        let stash = self.autostash_id();
         unsafe {
             try_call!(raw::git_rebase_abort(self.raw));
         }
 
-        Ok(())
+        self.apply_autostash(stash)
     }
 
     /// Finishes a rebase that is currently in progress once all patches have
     /// been applied.
     pub fn finish(&mut self, signature: Option<&Signature<'_>>) -> Result<(), Error> {
+        let stash = self.autostash_id();
         unsafe {
             try_call!(raw::git_rebase_finish(self.raw, signature.map(|s| s.raw())));
         }
 
-        Ok(())
+        self.apply_autostash(stash)
+    }
+
+    /// Records the stash made by `RebaseOptions::autostash` in the state of
+    /// the rebase, where `git rebase` keeps it too.
+    pub(crate) fn record_autostash(&self, id: Oid) -> Result<(), Error> {
+        let dir = match self.repo {
+            Some(repo) => repo.path().join("rebase-merge"),
+            None => return Ok(()),
+        };
+        let file = dir.join("autostash");
+        fs::write(&file, format!("{}\n", id))
+            .map_err(|e| Error::from_str(&format!("{}: {}", file.display(), e)))
+    }
+
+    fn autostash_id(&self) -> Option<Oid> {
+        let repo = match self.repo {
+            Some(repo) if !self.inmemory => repo,
+            _ => return None,
+        };
+        let id = fs::read_to_string(repo.path().join("rebase-merge/autostash")).ok()?;
+        Oid::from_str(id.trim()).ok()
+    }
+
+    fn apply_autostash(&self, stash: Option<Oid>) -> Result<(), Error> {
+        match (self.repo, stash) {
+            (Some(repo), Some(id)) => apply_autostash(repo, id),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -218,6 +728,24 @@ impl<'rebase> Iterator for Rebase<'rebase> {
     /// working directory will be updated with the changes.  If there are conflicts,
     /// you will need to address those before committing the changes.
     fn next(&mut self) -> Option<Result<RebaseOperation<'rebase>, Error>> {
+        let at = self.next_index();
+        while self.execs.first().filter(|e| e.before <= at).is_some() {
+            let exec = self.execs.remove(0);
+            let handler = match self.exec_handler {
+                Some(ref mut handler) => handler,
+                None => {
+                    let op = &*exec.raw as *const _;
+                    self.ran_execs.push(exec);
+                    return Some(Ok(unsafe { RebaseOperation::from_raw(op) }));
+                }
+            };
+            if let Err(e) = handler(&exec.command.to_string_lossy()) {
+                return Some(Err(e));
+            }
+        }
+        if at >= self.len() {
+            return None;
+        }
         let mut out = ptr::null_mut();
         unsafe {
             try_call_iter!(raw::git_rebase_next(&mut out, self.raw));
@@ -231,6 +759,16 @@ impl<'repo> Binding for Rebase<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_rebase) -> Rebase<'repo> {
         Rebase {
             raw: raw,
+            repo: None,
+            inmemory: false,
+            dropped: 0,
+            execs: Vec::new(),
+            ran_execs: Vec::new(),
+            replaced: None,
+            exec_handler: None,
+            committer_date_is_author_date: false,
+            ignore_date: false,
+            signature_handler: None,
             _marker: marker::PhantomData,
         }
     }
@@ -245,6 +783,129 @@ impl<'repo> Drop for Rebase<'repo> {
     }
 }
 
+impl RebaseExec {
+    fn new(before: usize, command: &str) -> Result<RebaseExec, Error> {
+        let command = CString::new(command)?;
+        let raw = Box::new(raw::git_rebase_operation {
+            kind: raw::GIT_REBASE_OPERATION_EXEC,
+            id: unsafe { mem::zeroed() },
+            exec: command.as_ptr(),
+        });
+        Ok(RebaseExec {
+            before,
+            command,
+            raw,
+        })
+    }
+
+    fn item(&self) -> RebaseTodoItem {
+        let command = self.command.to_string_lossy();
+        RebaseTodoItem::new(RebaseTodoCommand::Exec, command)
+    }
+}
+
+/// Stashes the changes of the index and the working directory of `repo`,
+/// if there are any, for `RebaseOptions::autostash`.
+pub(crate) fn save_autostash(repo: &Repository) -> Result<Option<Oid>, Error> {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(false)
+        .include_ignored(false)
+        .exclude_submodules(true);
+    if repo.statuses(Some(&mut opts))?.is_empty() {
+        return Ok(None);
+    }
+    let stasher = repo.signature()?;
+    let message = CString::new("autostash")?;
+    let mut id: raw::git_oid = unsafe { mem::zeroed() };
+    unsafe {
+        try_call!(raw::git_stash_save(
+            &mut id,
+            repo.raw(),
+            stasher.raw(),
+            message,
+            0
+        ));
+        Ok(Some(Binding::from_raw(&id as *const _)))
+    }
+}
+
+/// Applies the stash `id` made by `save_autostash` and drops it from the
+/// stash list.
+pub(crate) fn apply_autostash(repo: &Repository, id: Oid) -> Result<(), Error> {
+    let index = repo
+        .reflog("refs/stash")?
+        .iter()
+        .position(|entry| entry.id_new() == id)
+        .ok_or_else(|| {
+            let msg = format!("autostash {} is not in the stash list", id);
+            Error::new(ErrorCode::NotFound, ErrorClass::Stash, msg)
+        })?;
+    unsafe {
+        let rc = raw::git_stash_apply(repo.raw(), index, ptr::null());
+        if rc < 0 {
+            let msg = format!(
+                "applying the autostash failed; the changes are safe in the stash as {}",
+                id
+            );
+            crate::panic::check();
+            let code = Error::last_error(rc)
+                .map(|e| e.code())
+                .unwrap_or(ErrorCode::GenericError);
+            return Err(Error::new(code, ErrorClass::Stash, msg));
+        }
+        try_call!(raw::git_stash_drop(repo.raw(), index));
+    }
+    Ok(())
+}
+
+/// Finds the pending commit named by the abbreviated id `spec`.
+/// Copies a signature with another time.
+fn redate(signature: &Signature<'_>, time: &Time) -> Result<Signature<'static>, Error> {
+    Signature::new(
+        &String::from_utf8_lossy(signature.name_bytes()),
+        &String::from_utf8_lossy(signature.email_bytes()),
+        time,
+    )
+}
+
+fn find_pending(pending: &[Oid], spec: &str) -> Result<usize, Error> {
+    let spec = spec.to_lowercase();
+    let mut found = pending
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| spec.len() >= 4 && id.to_string().starts_with(&spec))
+        .map(|(n, _)| n);
+    match (found.next(), found.next()) {
+        (Some(n), None) => Ok(n),
+        _ => {
+            let msg = format!("'{}' does not name a single pending commit", spec);
+            Err(Error::from_str(&msg))
+        }
+    }
+}
+
+/// The conflicts a rebase stopped on, returned by `Rebase::conflicts`.
+pub struct RebaseConflicts {
+    commit: Oid,
+    files: Vec<MergeConflict>,
+}
+
+impl RebaseConflicts {
+    /// Returns the commit whose changes conflicted.
+    pub fn commit(&self) -> Oid {
+        self.commit
+    }
+
+    /// Returns the conflicted files.
+    ///
+    /// Our side of each file is the version it has in the commits rebased
+    /// so far, their side the version from `commit`, and the ancestor the
+    /// version from the parent of `commit`.
+    pub fn files(&self) -> &[MergeConflict] {
+        &self.files
+    }
+}
+
 /// A rebase operation
 ///
 /// Describes a single instruction/operation to be performed during the
@@ -335,9 +996,23 @@ impl<'rebase> Binding for RebaseOperation<'rebase> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{RebaseOperationType, RebaseOptions, Signature};
+    use crate::{Oid, RebaseOperationType, RebaseOptions, RebaseTodo, RebaseTodoCommand};
+    use crate::{Repository, Signature, Time};
     use std::{fs, path};
 
+    fn commit_file(repo: &Repository, name: &str, message: &str) -> Oid {
+        let workdir = repo.workdir().unwrap();
+        fs::write(workdir.join(name), name).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(path::Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("testname", "testemail").unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])
+            .unwrap()
+    }
+
     #[test]
     fn smoke() {
         let (_td, repo) = crate::test::repo_init();
@@ -438,4 +1113,264 @@ mod tests {
         }
         rebase.finish(None).unwrap();
     }
+
+    #[test]
+    fn set_todo() {
+        let (_td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().target().unwrap();
+        let a = commit_file(&repo, "a", "A");
+        let b = commit_file(&repo, "b", "B");
+        let c = commit_file(&repo, "c", "C");
+        let sig = Signature::now("testname", "testemail").unwrap();
+
+        let branch = repo.find_annotated_commit(c).unwrap();
+        let upstream = repo.find_annotated_commit(tip).unwrap();
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, None)
+            .unwrap();
+        assert_eq!(rebase.len(), 3);
+
+        let todo = format!("pick {:.7}\nsquash {:.7}\nexec make test\n", c, a);
+        rebase.set_todo(&todo.parse().unwrap()).unwrap();
+        assert_eq!(rebase.len(), 2);
+        let todo = rebase.todo();
+        let commands = todo.items.iter().map(|i| i.command).collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                RebaseTodoCommand::Pick,
+                RebaseTodoCommand::Squash,
+                RebaseTodoCommand::Exec
+            ]
+        );
+        assert_eq!(todo.items[1].args, a.to_string());
+
+        let bad: RebaseTodo = format!("pick {}\npick {}\n", c, c).parse().unwrap();
+        assert!(rebase.set_todo(&bad).is_err());
+        let bad: RebaseTodo = format!("fixup {}\n", c).parse().unwrap();
+        assert!(rebase.set_todo(&bad).is_err());
+
+        let op = rebase.next().unwrap().unwrap();
+        assert_eq!(op.id(), c);
+        rebase.commit(None, &sig, None).unwrap();
+        let op = rebase.next().unwrap().unwrap();
+        assert_eq!(op.kind(), Some(RebaseOperationType::Squash));
+        assert_eq!(op.id(), a);
+        let squashed = rebase.commit(None, &sig, None).unwrap();
+        let op = rebase.next().unwrap().unwrap();
+        assert_eq!(op.kind(), Some(RebaseOperationType::Exec));
+        assert_eq!(op.exec(), Some("make test"));
+        assert!(rebase.next().is_none());
+        rebase.finish(None).unwrap();
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id(), squashed);
+        assert_eq!(head.message(), Some("C\n\nA\n"));
+        assert_eq!(head.parent_ids().collect::<Vec<_>>(), [tip]);
+        let tree = head.tree().unwrap();
+        assert!(tree.get_name("a").is_some());
+        assert!(tree.get_name("b").is_none());
+        assert!(tree.get_name("c").is_some());
+        assert!(repo.find_commit(b).is_ok());
+    }
+
+    #[test]
+    fn set_todo_inmemory() {
+        let (_td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().target().unwrap();
+        commit_file(&repo, "a", "A");
+        let b = commit_file(&repo, "b", "B");
+        let c = commit_file(&repo, "c", "C");
+        let sig = Signature::now("testname", "testemail").unwrap();
+
+        let branch = repo.find_annotated_commit(c).unwrap();
+        let upstream = repo.find_annotated_commit(tip).unwrap();
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+
+        rebase.next().unwrap().unwrap();
+        let first = rebase.commit(None, &sig, None).unwrap();
+        let todo = format!("fixup {}\nreword {}\n", b, c);
+        rebase.set_todo(&todo.parse().unwrap()).unwrap();
+
+        rebase.next().unwrap().unwrap();
+        let fixed = rebase.commit(None, &sig, None).unwrap();
+        let fixed = repo.find_commit(fixed).unwrap();
+        assert_eq!(fixed.message(), Some("A"));
+        assert_eq!(fixed.parent_ids().collect::<Vec<_>>(), [tip]);
+        assert_ne!(fixed.id(), first);
+
+        let op = rebase.next().unwrap().unwrap();
+        assert_eq!(op.kind(), Some(RebaseOperationType::Reword));
+        let last = rebase.commit(None, &sig, Some("C'")).unwrap();
+        let last = repo.find_commit(last).unwrap();
+        assert_eq!(last.message(), Some("C'"));
+        assert_eq!(last.parent_ids().collect::<Vec<_>>(), [fixed.id()]);
+        assert_eq!(last.tree().unwrap().len(), 3);
+        assert!(rebase.next().is_none());
+        assert_eq!(repo.head().unwrap().target(), Some(c));
+    }
+
+    #[test]
+    fn autostash() {
+        let (td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().target().unwrap();
+        commit_file(&repo, "a", "A");
+        let b = commit_file(&repo, "b", "B");
+        let sig = Signature::now("testname", "testemail").unwrap();
+        fs::write(td.path().join("a"), "dirty\n").unwrap();
+
+        let branch = repo.find_annotated_commit(b).unwrap();
+        let upstream = repo.find_annotated_commit(tip).unwrap();
+        assert!(repo
+            .rebase(Some(&branch), Some(&upstream), None, None)
+            .is_err());
+
+        let mut opts = RebaseOptions::new();
+        opts.autostash(true);
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+        // The rebase starts from `upstream`, which does not have `a`.
+        assert!(!td.path().join("a").exists());
+        while let Some(op) = rebase.next() {
+            op.unwrap();
+            rebase.commit(None, &sig, None).unwrap();
+        }
+        rebase.finish(None).unwrap();
+        assert_eq!(fs::read_to_string(td.path().join("a")).unwrap(), "dirty\n");
+        assert!(repo.find_reference("refs/stash").is_err());
+
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+        rebase.next().unwrap().unwrap();
+        drop(rebase);
+        let mut rebase = repo.open_rebase(None).unwrap();
+        rebase.abort().unwrap();
+        assert_eq!(fs::read_to_string(td.path().join("a")).unwrap(), "dirty\n");
+        assert!(repo.find_reference("refs/stash").is_err());
+    }
+
+    #[test]
+    fn exec_after_each() {
+        let (td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().target().unwrap();
+        commit_file(&repo, "a", "A");
+        let b = commit_file(&repo, "b", "B");
+        let sig = Signature::now("testname", "testemail").unwrap();
+
+        let branch = repo.find_annotated_commit(b).unwrap();
+        let upstream = repo.find_annotated_commit(tip).unwrap();
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, None)
+            .unwrap();
+        rebase.exec_after_each("test -f b").unwrap();
+        let commands = rebase
+            .todo()
+            .items
+            .iter()
+            .map(|i| i.command)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                RebaseTodoCommand::Pick,
+                RebaseTodoCommand::Exec,
+                RebaseTodoCommand::Pick,
+                RebaseTodoCommand::Exec
+            ]
+        );
+        rebase.exec_in_shell().unwrap();
+
+        rebase.next().unwrap().unwrap();
+        rebase.commit(None, &sig, None).unwrap();
+        // The command fails for the first commit, which doesn't have "b".
+        assert!(rebase.next().unwrap().is_err());
+        assert!(!td.path().join("b").exists());
+        let op = rebase.next().unwrap().unwrap();
+        assert_eq!(op.id(), b);
+        rebase.commit(None, &sig, None).unwrap();
+        assert!(rebase.next().is_none());
+        rebase.finish(None).unwrap();
+    }
+
+    #[test]
+    fn signature_dates() {
+        let (_td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().target().unwrap();
+        let a = commit_file(&repo, "a", "A");
+        let authored = repo.find_commit(a).unwrap().author().when();
+        let sig = Signature::new("testname", "testemail", &Time::new(5000, 0)).unwrap();
+        let branch = repo.find_annotated_commit(a).unwrap();
+        let upstream = repo.find_annotated_commit(tip).unwrap();
+        let rebase = |opts: &mut RebaseOptions<'_>| {
+            opts.inmemory(true);
+            repo.rebase(Some(&branch), Some(&upstream), None, Some(opts))
+                .unwrap()
+        };
+
+        let mut rebase1 = rebase(RebaseOptions::new().committer_date_is_author_date(true));
+        rebase1.next().unwrap().unwrap();
+        let id = rebase1.commit(None, &sig, None).unwrap();
+        let commit = repo.find_commit(id).unwrap();
+        assert_eq!(commit.committer().when().seconds(), authored.seconds());
+        assert_eq!(commit.committer().name(), Some("testname"));
+        assert_eq!(commit.author().when().seconds(), authored.seconds());
+
+        let mut rebase2 = rebase(RebaseOptions::new().ignore_date(true));
+        rebase2.signature_handler(|original, author, committer| {
+            assert_eq!(original.id(), a);
+            assert_eq!(author.when().seconds(), 5000);
+            let author = Signature::new("other", "other@example.com", &author.when()).unwrap();
+            Some((author, committer.to_owned()))
+        });
+        rebase2.next().unwrap().unwrap();
+        let id = rebase2.commit(None, &sig, None).unwrap();
+        let commit = repo.find_commit(id).unwrap();
+        assert_eq!(commit.author().name(), Some("other"));
+        assert_eq!(commit.author().when().seconds(), 5000);
+        assert_eq!(commit.committer().when().seconds(), 5000);
+    }
+
+    #[test]
+    fn conflicts() {
+        let (_td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().target().unwrap();
+        let base = repo.find_commit(tip).unwrap();
+        let sig = Signature::now("testname", "testemail").unwrap();
+        let commit = |content: &[u8], message: &str| {
+            let mut builder = repo.treebuilder(Some(&base.tree().unwrap())).unwrap();
+            let blob = repo.blob(content).unwrap();
+            builder.insert("a", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let id = repo
+                .commit(None, &sig, &sig, message, &tree, &[&base])
+                .unwrap();
+            (id, blob)
+        };
+        let (ours, our_blob) = commit(b"ours", "ours");
+        let (theirs, their_blob) = commit(b"theirs", "theirs");
+
+        let branch = repo.find_annotated_commit(theirs).unwrap();
+        let onto = repo.find_annotated_commit(ours).unwrap();
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo
+            .rebase(Some(&branch), None, Some(&onto), Some(&mut opts))
+            .unwrap();
+        assert!(rebase.conflicts().unwrap().is_none());
+        rebase.next().unwrap().unwrap();
+        let conflicts = rebase.conflicts().unwrap().unwrap();
+        assert_eq!(conflicts.commit(), theirs);
+        assert_eq!(conflicts.files().len(), 1);
+        let file = &conflicts.files()[0];
+        assert_eq!(file.path(), path::Path::new("a"));
+        assert!(file.ancestor().is_none());
+        assert_eq!(file.our().unwrap().id, our_blob);
+        assert_eq!(file.their().unwrap().id, their_blob);
+    }
 }
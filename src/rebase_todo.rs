@@ -0,0 +1,282 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Error;
+
+/// A command in a rebase todo list or sequencer todo list.
+///
+/// These are the instructions understood by `git rebase --interactive`,
+/// `git cherry-pick` and `git revert` when they are run by the command line
+/// client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseTodoCommand {
+    /// Use the commit (`pick`, `p`).
+    Pick,
+    /// Revert the commit, only used by `git revert` (`revert`).
+    Revert,
+    /// Use the commit, but stop for amending (`edit`, `e`).
+    Edit,
+    /// Use the commit, but edit its message (`reword`, `r`).
+    Reword,
+    /// Meld the commit into the previous one, discarding its message
+    /// (`fixup`, `f`).
+    Fixup,
+    /// Meld the commit into the previous one, combining the messages
+    /// (`squash`, `s`).
+    Squash,
+    /// Run a shell command (`exec`, `x`).
+    Exec,
+    /// Stop here, to be continued later with `git rebase --continue`
+    /// (`break`, `b`).
+    Break,
+    /// Remove the commit (`drop`, `d`).
+    Drop,
+    /// Label the current `HEAD` with a name (`label`, `l`).
+    Label,
+    /// Reset `HEAD` to a label (`reset`, `t`).
+    Reset,
+    /// Create a merge commit (`merge`, `m`).
+    Merge,
+    /// Do nothing (`noop`).
+    Noop,
+}
+
+/// A single instruction of a todo list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebaseTodoItem {
+    /// The command to run.
+    pub command: RebaseTodoCommand,
+    /// Everything following the command on its line, such as
+    /// `<commit> <subject>` for `pick` or the shell command for `exec`.
+    pub args: String,
+}
+
+/// A todo list, as found in the `git-rebase-todo` and `done` files of an
+/// interactive rebase or in the `todo` file of the sequencer.
+///
+/// Comments and blank lines are dropped when parsing, as the command line
+/// client ignores them as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RebaseTodo {
+    /// The instructions, in the order they are run.
+    pub items: Vec<RebaseTodoItem>,
+}
+
+impl RebaseTodoCommand {
+    /// Returns the full name of this command as written in todo lists.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            RebaseTodoCommand::Pick => "pick",
+            RebaseTodoCommand::Revert => "revert",
+            RebaseTodoCommand::Edit => "edit",
+            RebaseTodoCommand::Reword => "reword",
+            RebaseTodoCommand::Fixup => "fixup",
+            RebaseTodoCommand::Squash => "squash",
+            RebaseTodoCommand::Exec => "exec",
+            RebaseTodoCommand::Break => "break",
+            RebaseTodoCommand::Drop => "drop",
+            RebaseTodoCommand::Label => "label",
+            RebaseTodoCommand::Reset => "reset",
+            RebaseTodoCommand::Merge => "merge",
+            RebaseTodoCommand::Noop => "noop",
+        }
+    }
+
+    /// Looks up a command by its full name or its one-letter abbreviation.
+    pub fn from_name(name: &str) -> Option<RebaseTodoCommand> {
+        match name {
+            "pick" | "p" => Some(RebaseTodoCommand::Pick),
+            "revert" => Some(RebaseTodoCommand::Revert),
+            "edit" | "e" => Some(RebaseTodoCommand::Edit),
+            "reword" | "r" => Some(RebaseTodoCommand::Reword),
+            "fixup" | "f" => Some(RebaseTodoCommand::Fixup),
+            "squash" | "s" => Some(RebaseTodoCommand::Squash),
+            "exec" | "x" => Some(RebaseTodoCommand::Exec),
+            "break" | "b" => Some(RebaseTodoCommand::Break),
+            "drop" | "d" => Some(RebaseTodoCommand::Drop),
+            "label" | "l" => Some(RebaseTodoCommand::Label),
+            "reset" | "t" => Some(RebaseTodoCommand::Reset),
+            "merge" | "m" => Some(RebaseTodoCommand::Merge),
+            "noop" => Some(RebaseTodoCommand::Noop),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the first argument of this command names a commit.
+    pub fn takes_commit(&self) -> bool {
+        match *self {
+            RebaseTodoCommand::Pick
+            | RebaseTodoCommand::Revert
+            | RebaseTodoCommand::Edit
+            | RebaseTodoCommand::Reword
+            | RebaseTodoCommand::Fixup
+            | RebaseTodoCommand::Squash
+            | RebaseTodoCommand::Drop => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RebaseTodoCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl RebaseTodoItem {
+    /// Creates a new instruction.
+    pub fn new<S: Into<String>>(command: RebaseTodoCommand, args: S) -> RebaseTodoItem {
+        RebaseTodoItem {
+            command,
+            args: args.into(),
+        }
+    }
+
+    /// Returns the commit this instruction applies to, as written in the
+    /// todo list (usually an abbreviated id).
+    ///
+    /// For `merge`, this is the commit whose message is reused, if any.
+    pub fn commit(&self) -> Option<&str> {
+        let mut words = self.args.split_whitespace();
+        match self.command {
+            RebaseTodoCommand::Fixup => match words.next() {
+                Some("-C") | Some("-c") => words.next(),
+                word => word,
+            },
+            RebaseTodoCommand::Merge => match words.next() {
+                Some("-C") | Some("-c") => words.next(),
+                _ => None,
+            },
+            command if command.takes_commit() => words.next(),
+            _ => None,
+        }
+    }
+
+    /// Returns the labels or commits merged into `HEAD` by a `merge`
+    /// instruction, and nothing for other commands.
+    pub fn merge_parents(&self) -> Vec<&str> {
+        if self.command != RebaseTodoCommand::Merge {
+            return Vec::new();
+        }
+        let mut words = self.args.split_whitespace().peekable();
+        if let Some(&"-C") | Some(&"-c") = words.peek() {
+            words.next();
+            words.next();
+        }
+        words.take_while(|word| !word.starts_with('#')).collect()
+    }
+}
+
+impl fmt::Display for RebaseTodoItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.args.is_empty() {
+            write!(f, "{}", self.command)
+        } else {
+            write!(f, "{} {}", self.command, self.args)
+        }
+    }
+}
+
+impl RebaseTodo {
+    /// Parses the contents of a todo list.
+    ///
+    /// Lines starting with `comment_char` (usually `#`, see
+    /// `core.commentChar`) are treated as comments.
+    pub fn parse(text: &str, comment_char: char) -> Result<RebaseTodo, Error> {
+        let mut items = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(comment_char) {
+                continue;
+            }
+            let (name, args) = match line.find(char::is_whitespace) {
+                Some(end) => (&line[..end], line[end..].trim_start()),
+                None => (line, ""),
+            };
+            let command = RebaseTodoCommand::from_name(name).ok_or_else(|| {
+                Error::from_str(&format!(
+                    "invalid command '{}' on line {} of todo list",
+                    name,
+                    i + 1
+                ))
+            })?;
+            items.push(RebaseTodoItem::new(command, args));
+        }
+        Ok(RebaseTodo { items })
+    }
+}
+
+impl FromStr for RebaseTodo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RebaseTodo, Error> {
+        RebaseTodo::parse(s, '#')
+    }
+}
+
+impl fmt::Display for RebaseTodo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            writeln!(f, "{}", item)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RebaseTodo, RebaseTodoCommand, RebaseTodoItem};
+
+    #[test]
+    fn parse() {
+        let text = "pick 1234567 first\n\
+                    f -C 89abcde second\n\
+                    \n\
+                    # a comment\n\
+                    x cargo test\n\
+                    merge -C fedcba9 topic # Merge topic\n\
+                    break\n";
+        let todo = text.parse::<RebaseTodo>().unwrap();
+        let commands = todo.items.iter().map(|i| i.command).collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            vec![
+                RebaseTodoCommand::Pick,
+                RebaseTodoCommand::Fixup,
+                RebaseTodoCommand::Exec,
+                RebaseTodoCommand::Merge,
+                RebaseTodoCommand::Break,
+            ]
+        );
+        let commits = todo.items.iter().map(|i| i.commit()).collect::<Vec<_>>();
+        assert_eq!(
+            commits,
+            vec![
+                Some("1234567"),
+                Some("89abcde"),
+                None,
+                Some("fedcba9"),
+                None
+            ]
+        );
+        assert_eq!(todo.items[2].args, "cargo test");
+        assert_eq!(todo.items[3].merge_parents(), ["topic"]);
+        assert!(todo.items[0].merge_parents().is_empty());
+
+        assert_eq!(
+            todo.to_string(),
+            "pick 1234567 first\n\
+             fixup -C 89abcde second\n\
+             exec cargo test\n\
+             merge -C fedcba9 topic # Merge topic\n\
+             break\n"
+        );
+
+        assert!("frobnicate 1234567".parse::<RebaseTodo>().is_err());
+        let todo = RebaseTodo::parse("; comment\nnoop\n", ';').unwrap();
+        assert_eq!(
+            todo.items,
+            vec![RebaseTodoItem::new(RebaseTodoCommand::Noop, "")]
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::BTreeSet;
+
+use crate::backup::{Manifest, RefTarget};
+use crate::{Error, Oid, Repository};
+
+/// A summary of how the references of a repository changed between two
+/// points in time, such as before and after a fetch.
+///
+/// Created with `Repository::ref_delta_report` from two `Manifest`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefDeltaReport {
+    refs: Vec<RefDelta>,
+}
+
+/// How a single reference changed, as part of a `RefDeltaReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefDelta {
+    name: String,
+    old: Option<Oid>,
+    new: Option<Oid>,
+    forced: bool,
+    new_commits: Vec<Oid>,
+    removed_commits: Vec<Oid>,
+}
+
+impl RefDeltaReport {
+    pub(crate) fn new(
+        repo: &Repository,
+        before: &Manifest,
+        after: &Manifest,
+    ) -> Result<RefDeltaReport, Error> {
+        let names = before
+            .refs()
+            .chain(after.refs())
+            .map(|(name, _)| name)
+            .collect::<BTreeSet<_>>();
+        let mut refs = Vec::new();
+        for name in names {
+            let old = direct(before, name);
+            let new = direct(after, name);
+            if old == new {
+                continue;
+            }
+            let mut delta = RefDelta {
+                name: name.to_string(),
+                old,
+                new,
+                forced: false,
+                new_commits: Vec::new(),
+                removed_commits: Vec::new(),
+            };
+            if !delta.is_tag() {
+                let old = old.and_then(|id| peel(repo, id));
+                let new = new.and_then(|id| peel(repo, id));
+                delta.new_commits = commits_between(repo, new, old)?;
+                delta.removed_commits = commits_between(repo, old, new)?;
+                if let (Some(old), Some(new)) = (old, new) {
+                    delta.forced = old != new && !repo.graph_descendant_of(new, old)?;
+                }
+            }
+            refs.push(delta);
+        }
+        Ok(RefDeltaReport { refs })
+    }
+
+    /// Returns every reference which changed, sorted by name.
+    pub fn refs(&self) -> &[RefDelta] {
+        &self.refs
+    }
+
+    /// Returns the references outside of `refs/tags/` which changed, such as
+    /// local and remote-tracking branches.
+    pub fn branches(&self) -> impl Iterator<Item = &RefDelta> {
+        self.refs.iter().filter(|delta| !delta.is_tag())
+    }
+
+    /// Returns the tags which were created.
+    pub fn new_tags(&self) -> impl Iterator<Item = &RefDelta> {
+        self.refs
+            .iter()
+            .filter(|delta| delta.is_tag() && delta.is_created())
+    }
+
+    /// Returns the references which were updated without the new target
+    /// descending from the old one.
+    pub fn forced(&self) -> impl Iterator<Item = &RefDelta> {
+        self.refs.iter().filter(|delta| delta.is_forced())
+    }
+
+    /// Returns `true` if no reference changed.
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+}
+
+impl RefDelta {
+    /// Returns the full name of the reference.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the previous target, or `None` if the reference was created.
+    pub fn old(&self) -> Option<Oid> {
+        self.old
+    }
+
+    /// Returns the new target, or `None` if the reference was deleted.
+    pub fn new_target(&self) -> Option<Oid> {
+        self.new
+    }
+
+    /// Returns `true` if the reference is a tag.
+    pub fn is_tag(&self) -> bool {
+        self.name.starts_with("refs/tags/")
+    }
+
+    /// Returns `true` if the reference was created.
+    pub fn is_created(&self) -> bool {
+        self.old.is_none()
+    }
+
+    /// Returns `true` if the reference was deleted.
+    pub fn is_deleted(&self) -> bool {
+        self.new.is_none()
+    }
+
+    /// Returns `true` if the reference was updated in a way that is not a
+    /// fast-forward, i.e. it was force-pushed.
+    ///
+    /// This is always `false` for tags.
+    pub fn is_forced(&self) -> bool {
+        self.forced
+    }
+
+    /// Returns the commits reachable from the new target but not the old
+    /// one, newest first.
+    ///
+    /// This is always empty for tags.
+    pub fn new_commits(&self) -> &[Oid] {
+        &self.new_commits
+    }
+
+    /// Returns the commits reachable from the old target but not the new
+    /// one, newest first; they were dropped by a forced update or deletion.
+    ///
+    /// This is always empty for tags.
+    pub fn removed_commits(&self) -> &[Oid] {
+        &self.removed_commits
+    }
+}
+
+fn direct(manifest: &Manifest, name: &str) -> Option<Oid> {
+    match manifest.get(name) {
+        Some(&RefTarget::Direct(id)) => Some(id),
+        _ => None,
+    }
+}
+
+fn peel(repo: &Repository, id: Oid) -> Option<Oid> {
+    repo.find_object(id, None)
+        .and_then(|object| object.peel_to_commit())
+        .map(|commit| commit.id())
+        .ok()
+}
+
+fn commits_between(
+    repo: &Repository,
+    tip: Option<Oid>,
+    base: Option<Oid>,
+) -> Result<Vec<Oid>, Error> {
+    let tip = match tip {
+        Some(tip) => tip,
+        None => return Ok(Vec::new()),
+    };
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(crate::Sort::TOPOLOGICAL | crate::Sort::TIME)?;
+    walk.push(tip)?;
+    if let Some(base) = base {
+        walk.hide(base)?;
+    }
+    walk.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backup::Manifest;
+    use crate::{Oid, Repository, Signature};
+
+    fn commit(repo: &Repository, parent: Oid, message: &str) -> Oid {
+        let sig = Signature::now("name", "email").unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let tree = parent.tree().unwrap();
+        repo.commit(None, &sig, &sig, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let root = repo.head().unwrap().target().unwrap();
+        let branch = repo.head().unwrap().name().unwrap().to_string();
+        let a = commit(&repo, root, "a");
+        repo.reference("refs/heads/rewrite", a, false, "").unwrap();
+        repo.reference("refs/heads/gone", a, false, "").unwrap();
+        repo.reference("refs/tags/v0", root, false, "").unwrap();
+        let before = Manifest::from_repo(&repo).unwrap();
+
+        let b = commit(&repo, root, "b");
+        let c = commit(&repo, root, "c");
+        let d = commit(&repo, c, "d");
+        repo.reference(&branch, d, true, "").unwrap();
+        repo.reference("refs/heads/rewrite", b, true, "").unwrap();
+        repo.reference("refs/heads/feature", c, false, "").unwrap();
+        repo.find_reference("refs/heads/gone")
+            .unwrap()
+            .delete()
+            .unwrap();
+        repo.reference("refs/tags/v1", d, false, "").unwrap();
+        let after = Manifest::from_repo(&repo).unwrap();
+
+        let report = repo.ref_delta_report(&before, &after).unwrap();
+        let names = report.refs().iter().map(|r| r.name()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "refs/heads/feature",
+                "refs/heads/gone",
+                &branch[..],
+                "refs/heads/rewrite",
+                "refs/tags/v1",
+            ]
+        );
+        let delta = |name: &str| report.refs().iter().find(|r| r.name() == name).unwrap();
+
+        let head = delta(&branch);
+        assert!(!head.is_forced());
+        assert_eq!(head.old(), Some(root));
+        assert_eq!(head.new_target(), Some(d));
+        assert_eq!(head.new_commits(), &[d, c][..]);
+        assert!(head.removed_commits().is_empty());
+
+        let rewrite = delta("refs/heads/rewrite");
+        assert!(rewrite.is_forced());
+        assert_eq!(rewrite.new_commits(), &[b][..]);
+        assert_eq!(rewrite.removed_commits(), &[a][..]);
+
+        let feature = delta("refs/heads/feature");
+        assert!(feature.is_created());
+        assert_eq!(feature.new_commits(), &[c, root][..]);
+
+        let gone = delta("refs/heads/gone");
+        assert!(gone.is_deleted());
+        assert_eq!(gone.removed_commits(), &[a, root][..]);
+
+        let forced = report.forced().map(|r| r.name()).collect::<Vec<_>>();
+        assert_eq!(forced, ["refs/heads/rewrite"]);
+        let tags = report.new_tags().map(|r| r.name()).collect::<Vec<_>>();
+        assert_eq!(tags, ["refs/tags/v1"]);
+        assert_eq!(report.branches().count(), 4);
+
+        assert!(repo.ref_delta_report(&after, &after).unwrap().is_empty());
+    }
+}
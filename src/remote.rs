@@ -9,7 +9,7 @@ use std::str;
 
 use crate::string_array::StringArray;
 use crate::util::Binding;
-use crate::{raw, Buf, Direction, Error, FetchPrune, Oid, ProxyOptions, Refspec};
+use crate::{raw, BranchName, Buf, Direction, Error, FetchPrune, Oid, ProxyOptions, Refspec};
 use crate::{AutotagOption, Progress, RemoteCallbacks, Repository};
 
 /// A structure representing a [remote][1] of a git repository.
@@ -142,6 +142,17 @@ impl<'repo> Remote<'repo> {
         }
     }
 
+    /// Get the remote's default branch as a `BranchName`.
+    ///
+    /// Like `default_branch`, this requires the remote to have connected.
+    pub fn default_branch_name(&self) -> Result<BranchName, Error> {
+        let buf = self.default_branch()?;
+        let name = buf
+            .as_str()
+            .ok_or_else(|| Error::from_str("default branch is not valid utf-8"))?;
+        BranchName::new(name)
+    }
+
     /// Open a connection to a remote.
     pub fn connect(&mut self, dir: Direction) -> Result<(), Error> {
         // TODO: can callbacks be exposed safely?
@@ -635,6 +646,11 @@ impl<'repo, 'connection, 'cb> RemoteConnection<'repo, 'connection, 'cb> {
         self.remote.default_branch()
     }
 
+    /// Get the remote's default branch as a `BranchName`.
+    pub fn default_branch_name(&self) -> Result<BranchName, Error> {
+        self.remote.default_branch_name()
+    }
+
     /// access remote bound to this connection
     pub fn remote(&mut self) -> &mut Remote<'repo> {
         self.remote
@@ -748,6 +764,23 @@ mod tests {
         t!(repo.remote_add_fetch("origin", "bar"));
     }
 
+    #[test]
+    fn default_branch_name() {
+        let (remote_td, _remote) = crate::test::repo_init();
+        let (_td, repo) = crate::test::repo_init();
+        let url = crate::test::path2url(remote_td.path());
+        let mut origin = repo.remote("origin", &url).unwrap();
+        assert!(origin.default_branch_name().is_err());
+        {
+            let connection = origin.connect_auth(Direction::Fetch, None, None).unwrap();
+            let name = connection.default_branch_name().unwrap();
+            assert_eq!(name.name(), "main");
+            assert_eq!(name.refname(), "refs/heads/main");
+        }
+        let name = origin.default_branch_name().unwrap();
+        assert_eq!(name.refname(), "refs/heads/main");
+    }
+
     #[test]
     fn rename_remote() {
         let (_td, repo) = crate::test::repo_init();
@@ -9,6 +9,7 @@ use crate::cert::Cert;
 use crate::util::Binding;
 use crate::{
     panic, raw, Cred, CredentialType, Error, IndexerProgress, Oid, PackBuilderStage, Progress,
+    PushUpdate,
 };
 
 /// A structure to contain the callbacks which are invoked when a repository is
@@ -25,6 +26,7 @@ pub struct RemoteCallbacks<'a> {
     update_tips: Option<Box<UpdateTips<'a>>>,
     certificate_check: Option<Box<CertificateCheck<'a>>>,
     push_update_reference: Option<Box<PushUpdateReference<'a>>>,
+    push_negotiation: Option<Box<PushNegotiation<'a>>>,
 }
 
 /// Callback used to acquire credentials for when a remote is fetched.
@@ -60,6 +62,12 @@ pub type CertificateCheck<'a> = dyn FnMut(&Cert<'_>, &str) -> bool + 'a;
 /// was rejected by the remote server with a reason why.
 pub type PushUpdateReference<'a> = dyn FnMut(&str, Option<&str>) -> Result<(), Error> + 'a;
 
+/// Callback invoked with the reference updates a push is about to perform,
+/// once they are known and before any data is sent.
+///
+/// Returning an error aborts the push.
+pub type PushNegotiation<'a> = dyn FnMut(&[PushUpdate<'_>]) -> Result<(), Error> + 'a;
+
 /// Callback for push transfer progress
 ///
 /// Parameters:
@@ -93,6 +101,7 @@ impl<'a> RemoteCallbacks<'a> {
             update_tips: None,
             certificate_check: None,
             push_update_reference: None,
+            push_negotiation: None,
             push_progress: None,
         }
     }
@@ -181,6 +190,19 @@ impl<'a> RemoteCallbacks<'a> {
         self
     }
 
+    /// Set a callback to inspect the reference updates of a push before they
+    /// are sent to the remote.
+    ///
+    /// Returning an error from the callback aborts the push, and the error is
+    /// returned from `Remote::push`.
+    pub fn push_negotiation<F>(&mut self, cb: F) -> &mut RemoteCallbacks<'a>
+    where
+        F: FnMut(&[PushUpdate<'_>]) -> Result<(), Error> + 'a,
+    {
+        self.push_negotiation = Some(Box::new(cb) as Box<PushNegotiation<'a>>);
+        self
+    }
+
     /// The callback through which progress of push transfer is monitored
     pub fn push_transfer_progress<F>(&mut self, cb: F) -> &mut RemoteCallbacks<'a>
     where
@@ -230,6 +252,9 @@ impl<'a> Binding for RemoteCallbacks<'a> {
             if self.push_update_reference.is_some() {
                 callbacks.push_update_reference = Some(push_update_reference_cb);
             }
+            if self.push_negotiation.is_some() {
+                callbacks.push_negotiation = Some(push_negotiation_cb);
+            }
             if self.push_progress.is_some() {
                 callbacks.push_transfer_progress = Some(push_transfer_progress_cb);
             }
@@ -409,6 +434,30 @@ extern "C" fn push_update_reference_cb(
     .unwrap_or(-1)
 }
 
+extern "C" fn push_negotiation_cb(
+    updates: *mut *const raw::git_push_update,
+    len: size_t,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let payload = &mut *(payload as *mut RemoteCallbacks<'_>);
+        let callback = match payload.push_negotiation {
+            Some(ref mut c) => c,
+            None => return 0,
+        };
+        let updates = slice::from_raw_parts(updates as *const PushUpdate<'_>, len as usize);
+        match callback(updates) {
+            Ok(()) => 0,
+            Err(e) => {
+                let s = CString::new(e.message()).unwrap();
+                raw::git_error_set_str(e.raw_class() as c_int, s.as_ptr());
+                e.raw_code() as c_int
+            }
+        }
+    })
+    .unwrap_or(-1)
+}
+
 extern "C" fn push_transfer_progress_cb(
     progress: c_uint,
     total: c_uint,
@@ -1,38 +1,83 @@
 use libc::{c_char, c_int, c_uint, c_void, size_t};
+use std::cell::Cell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::ffi::{CStr, CString, OsStr};
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs;
+use std::io::{self, Write};
 use std::iter::IntoIterator;
 use std::mem;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 use std::ptr;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use crate::build::{CheckoutBuilder, RepoBuilder};
+use crate::backup::Manifest;
+use crate::build::{CheckoutBuilder, RepoBuilder, TreeUpdateBuilder};
 use crate::diff::{
     binary_cb_c, file_cb_c, hunk_cb_c, line_cb_c, BinaryCb, DiffCallbacks, FileCb, HunkCb, LineCb,
 };
+use crate::merge::MergeFileSource;
 use crate::oid_array::OidArray;
+use crate::opts::Profile;
+use crate::repo_lock::RepoLock;
 use crate::stash::{stash_cb, StashApplyOptions, StashCbData};
+use crate::status::{status_cb, StatusCb};
 use crate::string_array::StringArray;
 use crate::tagforeach::{tag_foreach_cb, TagForeachCB, TagForeachData};
 use crate::util::{self, path_to_repo_path, Binding};
 use crate::worktree::{Worktree, WorktreeAddOptions};
+use crate::BlobPathCache;
 use crate::CherrypickOptions;
+use crate::NoFastForwardReason;
+use crate::RebaseOperationType;
 use crate::RevertOptions;
+use crate::UntrackedEntry;
+use crate::{message_prettify, CommitMessageTemplate, MergeOutcome, DEFAULT_COMMENT_CHAR};
 use crate::{
     raw, AttrCheckFlags, Buf, Error, Object, Remote, RepositoryOpenFlags, RepositoryState, Revspec,
     StashFlags,
 };
 use crate::{
-    AnnotatedCommit, MergeAnalysis, MergeOptions, MergePreference, SubmoduleIgnore,
+    AnnotatedCommit, MergeAnalysis, MergeOptions, MergePreference, MergeWithBase, SubmoduleIgnore,
     SubmoduleStatus, SubmoduleUpdate,
 };
-use crate::{ApplyLocation, ApplyOptions, Rebase, RebaseOptions};
-use crate::{Blame, BlameOptions, Reference, References, ResetType, Signature, Submodule};
-use crate::{Blob, BlobWriter, Branch, BranchType, Branches, Commit, Config, Index, Oid, Tree};
-use crate::{Describe, IntoCString, Reflog, RepositoryInitMode, RevparseMode};
-use crate::{DescribeOptions, Diff, DiffOptions, Odb, PackBuilder, TreeBuilder};
+use crate::{ApplyLocation, ApplyOptions, Rebase, RebaseOptions, RebaseTodo, RejectedFile};
+use crate::{AttrValue, CombinedDiff, DiffDriver, DiffFile, FileMode, Patch, RefDeltaReport};
+use crate::{Blame, BlameOptions, BlameSummary, Pathspec, PathspecFlags, TreeWalkMode};
+use crate::{
+    Blob, BlobWriter, Branch, BranchName, BranchType, Branches, Commit, Config, Index, Oid, Tree,
+};
+use crate::{CommitQuery, CommitSearchOptions, Decorations, PromptInfo, SetUpstream};
+use crate::{Delta, ErrorClass, ErrorCode, FsyncMode, IndexEntry, StatusShow};
+use crate::{
+    Describe, IgnoreFile, IntoCString, LockFile, LockOptions, Reflog, RepositoryInitMode,
+    RevparseMode,
+};
+use crate::{
+    DescribeOptions, Diff, DiffOptions, Odb, PackBuilder, PackedRefsView, TreeBuilder, TreeRules,
+};
+use crate::{FetchHeadEntry, MergeConflict, MergeDecision, MergeFileInput, MergeFileStatus};
 use crate::{Note, Notes, ObjectType, Revwalk, Status, StatusOptions, Statuses, Tag};
+use crate::{RebaseTodoCommand, RebaseTodoItem, Sort};
+use crate::{Reference, References, ResetType, Signature, Submodule, TreeWalkResult};
+use crate::{RepositoryReadGuard, RepositoryWriteGuard};
+
+/// A merge base computed by `Repository::virtual_merge_base`.
+struct MergeBase {
+    tree: Oid,
+    /// The merge base, unless it is virtual.
+    commit: Option<Oid>,
+    /// The commits merge bases with other commits are computed from: the
+    /// merge base itself, or the merge bases a virtual one was made of.
+    heads: Vec<Oid>,
+}
+
+static TEXTCONV_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 /// An owned git repository, representing all state associated with the
 /// underlying filesystem.
@@ -77,6 +122,44 @@ impl Repository {
         }
     }
 
+    /// Open the repository at `path`, as with `open`, set up for `profile`.
+    ///
+    /// For `Profile::Server` the repository is made durable with
+    /// `set_fsync_mode`, which is written to its configuration; `Desktop`
+    /// leaves the configured mode alone. Packs written when fetching are only
+    /// synced under the process-global `opts::fsync_gitdir`, which this
+    /// function does not change.
+    ///
+    /// The strictness and caching options of the profile are process-global.
+    /// They are only ever tightened here, so opening a repository with
+    /// `Desktop` does not affect repositories opened with `Server`; use
+    /// `Profile::apply` to set them exactly.
+    ///
+    /// Changing those options is not synchronized with libgit2 reading them.
+    /// Opening repositories with profiles while other threads use libgit2
+    /// may let those threads see the old and new values of an option, in
+    /// either order. Since this only ever turns checks on and grows the
+    /// cache, as the corresponding setters in `opts` would, that is harmless
+    /// unless other code lowers the same options concurrently; open the
+    /// first repository with a profile before starting other threads to
+    /// avoid it entirely.
+    pub fn open_with_profile<P: AsRef<Path>>(
+        path: P,
+        profile: Profile,
+    ) -> Result<Repository, Error> {
+        crate::init();
+        // Only ever enables checks and grows the cache; see above.
+        unsafe { profile.tighten()? };
+        let repo = Repository::open(path.as_ref())?;
+        if profile == Profile::Server && repo.fsync_mode()? != FsyncMode::Durable {
+            // The setting is read when the object database and reference
+            // backends are loaded, so reopen to pick it up.
+            repo.set_fsync_mode(FsyncMode::Durable)?;
+            return Repository::open(path);
+        }
+        Ok(repo)
+    }
+
     /// Attempt to open an already-existing bare repository at `path`.
     ///
     /// The path can point to only a bare repository.
@@ -231,6 +314,53 @@ impl Repository {
         }
     }
 
+    /// Creates a new bare repository which lives entirely in memory, for
+    /// unit tests which need a repository without touching the disk.
+    ///
+    /// Objects are kept in a mempack object database and references in an
+    /// in-memory reference database, whose `HEAD` points at the unborn
+    /// `master` branch, so commits, branches, merges and revision walks work
+    /// as usual. Each such repository is independent of all others, so tests
+    /// can create them in parallel.
+    ///
+    /// The repository has no path: `path` panics, and anything reading or
+    /// writing files in the repository directory, such as reflogs, the
+    /// repository state or `Index::write`, fails. Its configuration is empty
+    /// and cannot be written, so signatures must be given explicitly.
+    pub fn init_in_memory() -> Result<Repository, Error> {
+        crate::init();
+        let mut ret = ptr::null_mut();
+        let repo: Repository = unsafe {
+            try_call!(raw::git_repository_new(&mut ret));
+            Binding::from_raw(ret)
+        };
+
+        let odb = Odb::new()?;
+        odb.add_new_mempack_backend(1)?;
+        repo.set_odb(&odb)?;
+
+        let mut refdb = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_refdb_new(&mut refdb, repo.raw));
+            let backend = crate::memory_refdb::new_backend(CString::new("refs/heads/master")?);
+            let rc = raw::git_refdb_set_backend(refdb, backend);
+            let rc = if rc < 0 {
+                rc
+            } else {
+                raw::git_repository_set_refdb(repo.raw, refdb)
+            };
+            raw::git_refdb_free(refdb);
+            crate::call::c_try(rc)?;
+        }
+
+        let config = Config::new()?;
+        unsafe {
+            try_call!(raw::git_repository_set_config(repo.raw, config.raw()));
+        }
+        repo.set_index(&mut Index::new()?)?;
+        Ok(repo)
+    }
+
     /// Clone a remote repository.
     ///
     /// See the `RepoBuilder` struct for more information. This function will
@@ -426,6 +556,97 @@ impl Repository {
         Ok(())
     }
 
+    /// Convert a path to a file in the working directory into the path of
+    /// that file relative to the working directory, as used in the index,
+    /// with `/` separators.
+    ///
+    /// Relative paths are taken relative to the current directory. The file
+    /// does not need to exist. The path is first compared with the working
+    /// directory as given, ignoring case when `core.ignorecase` is set, and
+    /// then once more with symbolic links resolved on both sides, so that a
+    /// working directory reached through a symlink is handled.
+    ///
+    /// Returns an error if the path is outside of the working directory or if
+    /// the repository is bare.
+    pub fn to_repo_relative(&self, path: &Path) -> Result<PathBuf, Error> {
+        let workdir = self.workdir_or_err()?;
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            env::current_dir()
+                .map_err(|e| Error::from_str(&format!("cannot get current directory: {}", e)))?
+                .join(path)
+        };
+        let ignore_case = self.config()?.get_bool("core.ignorecase").unwrap_or(false);
+
+        let normalized = util::normalize_path(&path);
+        let components =
+            util::strip_dir_prefix(&normalized, &util::normalize_path(workdir), ignore_case)
+                .map(|c| c.iter().map(|c| c.to_os_string()).collect::<Vec<_>>())
+                .or_else(|| {
+                    let path = util::canonicalize_existing(&normalized);
+                    let workdir = util::canonicalize_existing(workdir);
+                    util::strip_dir_prefix(&path, &workdir, ignore_case)
+                        .map(|c| c.iter().map(|c| c.to_os_string()).collect())
+                })
+                .ok_or_else(|| {
+                    Error::from_str(&format!(
+                        "'{}' is outside of the working directory '{}'",
+                        path.display(),
+                        workdir.display()
+                    ))
+                })?;
+        let mut rel = OsString::new();
+        for (i, component) in components.iter().enumerate() {
+            if i > 0 {
+                rel.push("/");
+            }
+            rel.push(component);
+        }
+        Ok(PathBuf::from(rel))
+    }
+
+    /// Convert a path relative to the working directory, such as a path in
+    /// the index, into an absolute path in the working directory.
+    ///
+    /// Returns an error if the path is absolute, would escape the working
+    /// directory through `..`, or if the repository is bare.
+    pub fn to_workdir_absolute(&self, relpath: &Path) -> Result<PathBuf, Error> {
+        let workdir = self.workdir_or_err()?;
+        let mut path = workdir.to_path_buf();
+        let mut depth = 0usize;
+        for component in relpath.components() {
+            match component {
+                Component::Normal(name) => {
+                    path.push(name);
+                    depth += 1;
+                }
+                Component::CurDir => {}
+                Component::ParentDir if depth > 0 => {
+                    path.pop();
+                    depth -= 1;
+                }
+                _ => {
+                    return Err(Error::from_str(&format!(
+                        "'{}' is not a path inside the working directory",
+                        relpath.display()
+                    )))
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    fn workdir_or_err(&self) -> Result<&Path, Error> {
+        self.workdir().ok_or_else(|| {
+            Error::new(
+                ErrorCode::BareRepo,
+                ErrorClass::Repository,
+                "cannot resolve paths in a bare repository",
+            )
+        })
+    }
+
     /// Get the currently active namespace for this repository.
     ///
     /// If there is no namespace, or the namespace is not a valid utf8 string,
@@ -481,6 +702,261 @@ impl Repository {
         }
     }
 
+    /// Read the commit message template configured with `commit.template`.
+    ///
+    /// The setting usually names a file, which is resolved relative to the
+    /// working directory (or the git directory of a bare repository) and may
+    /// start with `~`. If no such file exists, the setting is looked up as a
+    /// revision naming a blob instead, such as `HEAD:.gitmessage`.
+    ///
+    /// Returns `None` if no template is configured.
+    pub fn commit_template(&self) -> Result<Option<String>, Error> {
+        let config = self.config()?;
+        let spec = match config.get_string("commit.template") {
+            Ok(spec) => spec,
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let path = config.get_path("commit.template")?;
+        let path = self.workdir().unwrap_or_else(|| self.path()).join(path);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let blob = self
+                    .revparse_single(&spec)
+                    .and_then(|obj| obj.peel_to_blob())
+                    .map_err(|_| {
+                        Error::from_str(&format!("could not read commit template '{}'", spec))
+                    })?;
+                blob.content().to_vec()
+            }
+            Err(e) => {
+                return Err(Error::from_str(&format!(
+                    "could not read commit template '{}': {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        String::from_utf8(contents)
+            .map(Some)
+            .map_err(|_| Error::from_str("commit template is not valid utf-8"))
+    }
+
+    /// Run the `prepare-commit-msg` hook on a commit message, as `git commit`
+    /// does before handing the message to the editor.
+    ///
+    /// The message is written to `COMMIT_EDITMSG` in the git directory and
+    /// the hook is invoked on it with `source` and `commit` as its optional
+    /// arguments (for example `Some("message")` or `Some("commit")` and the
+    /// commit being amended). The hooks directory is taken from
+    /// `core.hooksPath` when set. The possibly rewritten message is returned;
+    /// it is returned unchanged when there is no executable hook.
+    ///
+    /// Returns an error if the hook exits unsuccessfully, in which case the
+    /// commit should be aborted.
+    pub fn prepare_commit_msg(
+        &self,
+        message: &str,
+        source: Option<&str>,
+        commit: Option<Oid>,
+    ) -> Result<String, Error> {
+        let hook = self.hooks_dir()?.join("prepare-commit-msg");
+        if !util::is_executable(&hook) {
+            return Ok(message.to_string());
+        }
+        let msg_path = self.path().join("COMMIT_EDITMSG");
+        let io_err = |e: io::Error| Error::from_str(&format!("{}: {}", msg_path.display(), e));
+        fs::write(&msg_path, message).map_err(io_err)?;
+
+        let mut cmd = Command::new(&hook);
+        cmd.arg(&msg_path)
+            .current_dir(self.workdir().unwrap_or_else(|| self.path()));
+        if let Some(source) = source {
+            cmd.arg(source);
+            if let Some(commit) = commit {
+                cmd.arg(commit.to_string());
+            }
+        }
+        let status = cmd
+            .status()
+            .map_err(|e| Error::from_str(&format!("failed to run {}: {}", hook.display(), e)))?;
+        if !status.success() {
+            return Err(Error::from_str(&format!(
+                "prepare-commit-msg hook failed ({})",
+                status
+            )));
+        }
+        let message = fs::read(&msg_path).map_err(io_err)?;
+        String::from_utf8(message).map_err(|_| Error::from_str("commit message is not valid utf-8"))
+    }
+
+    /// Returns the directory hooks are run from.
+    fn hooks_dir(&self) -> Result<PathBuf, Error> {
+        let config = self.config()?;
+        match config.get_path("core.hooksPath") {
+            Ok(path) => return Ok(self.workdir().unwrap_or_else(|| self.path()).join(path)),
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.item_path(raw::GIT_REPOSITORY_ITEM_HOOKS)
+    }
+
+    pub(crate) fn item_path(&self, item: raw::git_repository_item_t) -> Result<PathBuf, Error> {
+        let buf = Buf::new();
+        unsafe {
+            try_call!(raw::git_repository_item_path(buf.raw(), self.raw, item));
+        }
+        Ok(util::bytes2path(&buf).to_path_buf())
+    }
+
+    /// Lock the index file of this repository, the way `git add` or
+    /// `git commit` do while they update it.
+    ///
+    /// Other git processes, including libgit2 ones, fail to write the index
+    /// while the returned guard is alive. The lock is taken as configured by
+    /// `opts`, and `Index::write_locked` writes the index through it.
+    pub fn lock_index(&self, opts: &LockOptions) -> Result<LockFile, Error> {
+        LockFile::acquire(&self.item_path(raw::GIT_REPOSITORY_ITEM_INDEX)?, opts)
+    }
+
+    /// Wait for shared access to this repository, for an operation which
+    /// only reads it, such as rebuilding a status cache.
+    ///
+    /// The lock is advisory and in-process: it is shared by every
+    /// `Repository` opened on the same repository (or on one of its
+    /// worktrees) in this process, and is only respected by code which takes
+    /// it. Readers queue behind a waiting writer, so a thread must not take
+    /// a read lock while it already holds one. Other processes are not
+    /// excluded, see `lock_index` for that.
+    pub fn read_lock(&self) -> Result<RepositoryReadGuard, Error> {
+        Ok(self.repo_lock()?.read())
+    }
+
+    /// Like `read_lock`, but returns `None` instead of waiting if a writer
+    /// holds or is waiting for the lock.
+    pub fn try_read_lock(&self) -> Result<Option<RepositoryReadGuard>, Error> {
+        Ok(self.repo_lock()?.try_read())
+    }
+
+    /// Wait for exclusive access to this repository, for an operation which
+    /// modifies it, such as a fetch updating references or a checkout.
+    ///
+    /// See `read_lock` for the scope of the lock.
+    pub fn write_lock(&self) -> Result<RepositoryWriteGuard, Error> {
+        Ok(self.repo_lock()?.write())
+    }
+
+    /// Like `write_lock`, but returns `None` instead of waiting if the lock
+    /// is held.
+    pub fn try_write_lock(&self) -> Result<Option<RepositoryWriteGuard>, Error> {
+        Ok(self.repo_lock()?.try_write())
+    }
+
+    fn repo_lock(&self) -> Result<Arc<RepoLock>, Error> {
+        Ok(RepoLock::get(
+            &self.item_path(raw::GIT_REPOSITORY_ITEM_COMMONDIR)?,
+        ))
+    }
+
+    /// Lock a reference, such as `HEAD` or `refs/heads/main`, the way git
+    /// does while it updates it.
+    ///
+    /// This only holds off updates of the loose reference; packing
+    /// references also takes the `packed-refs` lock, which can be obtained
+    /// with `LockFile::acquire` directly.
+    pub fn lock_reference(&self, name: &str, opts: &LockOptions) -> Result<LockFile, Error> {
+        if !Reference::is_valid_name(name) {
+            return Err(Error::new(
+                ErrorCode::InvalidSpec,
+                ErrorClass::Reference,
+                &format!("invalid reference name '{}'", name),
+            ));
+        }
+        // Pseudo refs like `HEAD` are private to each worktree, everything
+        // under `refs/` is shared.
+        let dir = if name.starts_with("refs/") {
+            self.item_path(raw::GIT_REPOSITORY_ITEM_COMMONDIR)?
+        } else {
+            self.path().to_path_buf()
+        };
+        LockFile::acquire(&dir.join(name), opts)
+    }
+
+    /// Lock the repository's config file, the way `git config` does while it
+    /// updates it.
+    pub fn lock_config(&self, opts: &LockOptions) -> Result<LockFile, Error> {
+        LockFile::acquire(&self.item_path(raw::GIT_REPOSITORY_ITEM_CONFIG)?, opts)
+    }
+
+    /// Read the remaining steps of an interactive rebase started by the
+    /// command line client, from `rebase-merge/git-rebase-todo`.
+    ///
+    /// Returns a NotFound error if no such rebase is in progress.
+    pub fn rebase_todo(&self) -> Result<RebaseTodo, Error> {
+        self.read_todo(&self.path().join("rebase-merge/git-rebase-todo"))
+    }
+
+    /// Replace the remaining steps of an interactive rebase started by the
+    /// command line client.
+    ///
+    /// The rebase is then continued as usual, with `git rebase --continue`.
+    pub fn set_rebase_todo(&self, todo: &RebaseTodo) -> Result<(), Error> {
+        self.write_todo(&self.path().join("rebase-merge/git-rebase-todo"), todo)
+    }
+
+    /// Read the steps of an interactive rebase started by the command line
+    /// client which are already done, from `rebase-merge/done`.
+    pub fn rebase_done(&self) -> Result<RebaseTodo, Error> {
+        self.read_todo(&self.path().join("rebase-merge/done"))
+    }
+
+    /// Read the remaining steps of a multi-commit cherry-pick or revert
+    /// started by the command line client, from `sequencer/todo`.
+    pub fn sequencer_todo(&self) -> Result<RebaseTodo, Error> {
+        self.read_todo(&self.path().join("sequencer/todo"))
+    }
+
+    /// Replace the remaining steps of a multi-commit cherry-pick or revert
+    /// started by the command line client.
+    pub fn set_sequencer_todo(&self, todo: &RebaseTodo) -> Result<(), Error> {
+        self.write_todo(&self.path().join("sequencer/todo"), todo)
+    }
+
+    fn read_todo(&self, path: &Path) -> Result<RebaseTodo, Error> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            let code = match e.kind() {
+                io::ErrorKind::NotFound => ErrorCode::NotFound,
+                _ => ErrorCode::GenericError,
+            };
+            let msg = format!("could not read '{}': {}", path.display(), e);
+            Error::new(code, ErrorClass::Os, &msg)
+        })?;
+        RebaseTodo::parse(&text, self.comment_char()?)
+    }
+
+    fn write_todo(&self, path: &Path, todo: &RebaseTodo) -> Result<(), Error> {
+        if !path.exists() {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Os,
+                &format!("'{}' does not exist", path.display()),
+            ));
+        }
+        let tmp = path.with_extension("lock");
+        fs::write(&tmp, todo.to_string())
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| Error::from_str(&format!("could not write '{}': {}", path.display(), e)))
+    }
+
+    fn comment_char(&self) -> Result<char, Error> {
+        match self.config()?.get_string("core.commentChar") {
+            Ok(s) => Ok(s.chars().next().filter(|_| s != "auto").unwrap_or('#')),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok('#'),
+            Err(e) => Err(e),
+        }
+    }
+
     /// List all remotes for a given repository
     pub fn remotes(&self) -> Result<StringArray, Error> {
         let mut arr = raw::git_strarray {
@@ -679,6 +1155,7 @@ impl Repository {
             ));
             let opts = checkout.map(|c| {
                 c.configure(&mut opts);
+                c.set_repository(self);
                 &mut opts
             });
             try_call!(raw::git_reset(self.raw, target.raw(), kind, opts));
@@ -856,18 +1333,69 @@ impl Repository {
     /// status, then the results from rename detection (if you enable it) may
     /// not be accurate. To do rename detection properly, this must be called
     /// with no pathspec so that all files can be considered.
-    pub fn statuses(&self, options: Option<&mut StatusOptions>) -> Result<Statuses<'_>, Error> {
+    pub fn statuses(&self, mut options: Option<&mut StatusOptions>) -> Result<Statuses<'_>, Error> {
         let mut ret = ptr::null_mut();
+        if let Some(options) = options.as_mut() {
+            options.prepare_pathspecs()?;
+            options.refresh_index(self)?;
+        }
+        let baseline = match options.as_ref().and_then(|o| o.baseline_id()) {
+            Some(id) => Some(self.find_tree(id)?),
+            None => None,
+        };
         unsafe {
+            let opts = options.map(|s| s.raw_with_baseline(baseline.as_ref()));
             try_call!(raw::git_status_list_new(
                 &mut ret,
                 self.raw,
-                options.map(|s| s.raw()).unwrap_or(ptr::null())
+                opts.as_ref().map(|o| o as *const _).unwrap_or(ptr::null())
             ));
             Ok(Binding::from_raw(ret))
         }
     }
 
+    /// Gather file status information and invoke `callback` for each file.
+    ///
+    /// This is like `statuses`, but each path and its status are handed to
+    /// the callback rather than collected into a `Statuses` list, and the
+    /// iteration stops as soon as the callback returns `false`. This makes
+    /// checks such as "is anything dirty?" cheap to write.
+    ///
+    /// This is a convenience wrapper around `git_status_foreach_ext`, which
+    /// computes the full status list before it invokes the callback for the
+    /// first entry. Stopping early therefore does not save the time or
+    /// memory of the scan itself, only that of building a `Statuses` list
+    /// and handling the remaining entries.
+    pub fn status_foreach_ext<C>(
+        &self,
+        mut options: Option<&mut StatusOptions>,
+        mut callback: C,
+    ) -> Result<(), Error>
+    where
+        C: FnMut(&Path, Status) -> bool,
+    {
+        if let Some(options) = options.as_mut() {
+            options.prepare_pathspecs()?;
+            options.refresh_index(self)?;
+        }
+        let baseline = match options.as_ref().and_then(|o| o.baseline_id()) {
+            Some(id) => Some(self.find_tree(id)?),
+            None => None,
+        };
+        let mut callback: &mut StatusCb<'_> = &mut callback;
+        unsafe {
+            let cb: raw::git_status_cb = Some(status_cb);
+            let opts = options.map(|s| s.raw_with_baseline(baseline.as_ref()));
+            try_call!(raw::git_status_foreach_ext(
+                self.raw,
+                opts.as_ref().map(|o| o as *const _).unwrap_or(ptr::null()),
+                cb,
+                &mut callback as *mut _ as *mut c_void
+            ));
+        }
+        Ok(())
+    }
+
     /// Test if the ignore rules apply to a given file.
     ///
     /// This function checks the ignore rules to see if they would apply to the
@@ -910,6 +1438,128 @@ impl Repository {
         Ok(Status::from_bits_truncate(ret as u32))
     }
 
+    /// Get file status for a list of files.
+    ///
+    /// This answers the same question as `status_file` for every path, in
+    /// order, but gathers the status of all of them in a single pass over
+    /// the index and working directory instead of one pass per path.
+    ///
+    /// Returns NotFound for the first path which matches no file, and
+    /// Ambiguous for a path naming a directory.
+    pub fn status_files(&self, paths: &[&Path]) -> Result<Vec<Status>, Error> {
+        let paths = paths
+            .iter()
+            .map(|p| path_to_repo_path(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut opts = StatusOptions::new();
+        opts.show(StatusShow::IndexAndWorkdir)
+            .include_ignored(true)
+            .recurse_ignored_dirs(true)
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_unmodified(true)
+            .disable_pathspec_match(true);
+        for path in &paths {
+            opts.pathspec(path.as_bytes());
+        }
+        let statuses = self.statuses(Some(&mut opts))?;
+        let found = statuses
+            .iter()
+            .map(|entry| (entry.path_bytes().to_vec(), entry.status()))
+            .collect::<HashMap<_, _>>();
+
+        paths
+            .iter()
+            .map(|path| {
+                let path = path.as_bytes();
+                if let Some(status) = found.get(path) {
+                    return Ok(*status);
+                }
+                let display = String::from_utf8_lossy(path);
+                let is_dir = found
+                    .keys()
+                    .any(|p| p.starts_with(path) && p.get(path.len()) == Some(&b'/'));
+                if is_dir {
+                    Err(Error::new(
+                        ErrorCode::Ambiguous,
+                        ErrorClass::Invalid,
+                        format!("ambiguous path '{}' given to status_files", display),
+                    ))
+                } else {
+                    Err(Error::new(
+                        ErrorCode::NotFound,
+                        ErrorClass::Invalid,
+                        format!("attempt to get status of nonexistent file '{}'", display),
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    /// List the untracked files and directories of the working directory,
+    /// with untracked directories rolled up into a single entry.
+    ///
+    /// Like `git status` without `--untracked-files=all`, a directory which
+    /// contains no tracked files is reported by itself rather than file by
+    /// file, but each such entry also knows how many untracked files it
+    /// contains. Ignored files are not counted.
+    ///
+    /// The given options can be used to restrict the listing with
+    /// pathspecs; `include_untracked` is turned on and
+    /// `recurse_untracked_dirs` off regardless of their settings.
+    pub fn untracked_entries(
+        &self,
+        options: Option<&mut StatusOptions>,
+    ) -> Result<Vec<UntrackedEntry>, Error> {
+        let mut default = StatusOptions::new();
+        let options = options.unwrap_or(&mut default);
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(false);
+        let mut entries = self
+            .statuses(Some(options))?
+            .iter()
+            .filter(|entry| entry.status().contains(Status::WT_NEW))
+            .map(|entry| UntrackedEntry::new(entry.path_bytes().to_vec()))
+            .collect::<Vec<_>>();
+
+        let mut dirs = HashMap::new();
+        let mut opts = StatusOptions::new();
+        opts.show(StatusShow::Workdir)
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .disable_pathspec_match(true);
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.is_dir() {
+                let dir = entry.path_bytes();
+                opts.pathspec(&dir[..dir.len() - 1]);
+                dirs.insert(dir.to_vec(), i);
+            }
+        }
+        if dirs.is_empty() {
+            return Ok(entries);
+        }
+        for entry in self.statuses(Some(&mut opts))?.iter() {
+            if !entry.status().contains(Status::WT_NEW) {
+                continue;
+            }
+            let path = entry.path_bytes();
+            let dir = path
+                .iter()
+                .enumerate()
+                .filter(|&(_, b)| *b == b'/')
+                .find_map(|(i, _)| dirs.get(&path[..=i]));
+            if let Some(&i) = dir {
+                entries[i].add_file();
+            }
+        }
+        Ok(entries)
+    }
+
     /// Create an iterator which loops over the requested branches.
     pub fn branches(&self, filter: Option<BranchType>) -> Result<Branches<'_>, Error> {
         let mut raw = ptr::null_mut();
@@ -1002,6 +1652,16 @@ impl Repository {
         }
     }
 
+    /// Returns the attribute and ignore rules of `tree`, reading its
+    /// `.gitattributes` and `.gitignore` files rather than those of the
+    /// working directory.
+    ///
+    /// This applies the same rules a checkout of `tree` would, which makes it
+    /// usable in bare repositories.
+    pub fn tree_rules(&self, tree: &Tree<'_>) -> Result<TreeRules, Error> {
+        TreeRules::new(self, tree)
+    }
+
     /// Write an in-memory buffer to the ODB as a blob.
     ///
     /// The Oid returned can in turn be passed to `find_blob` to get a handle to
@@ -1093,6 +1753,175 @@ impl Repository {
         Ok(())
     }
 
+    /// Seed this repository from `template`, creating a reference for each
+    /// of its references matching `refs_glob`, and returning how many were
+    /// created.
+    ///
+    /// No objects are copied: the template's object database is added to
+    /// this repository's `objects/info/alternates`, so the template must be
+    /// kept around until `dissociate` is called. References which already
+    /// exist in this repository are left alone.
+    pub fn seed_from(&self, template: &Repository, refs_glob: &str) -> Result<usize, Error> {
+        let objects = template.item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?;
+        let objects = objects.canonicalize().unwrap_or(objects);
+        let info = self
+            .item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?
+            .join("info");
+        let alternates = info.join("alternates");
+        let io_err = |e: io::Error| Error::from_str(&format!("{}: {}", alternates.display(), e));
+        let mut contents = match fs::read(&alternates) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(io_err(e)),
+        };
+        let line = objects.as_path().into_c_string()?.into_bytes();
+        if !contents.split(|&b| b == b'\n').any(|l| l == &line[..]) {
+            if !contents.is_empty() && !contents.ends_with(b"\n") {
+                contents.push(b'\n');
+            }
+            contents.extend_from_slice(&line);
+            contents.push(b'\n');
+            fs::create_dir_all(&info)
+                .and_then(|()| fs::write(&alternates, contents))
+                .map_err(io_err)?;
+            let path = objects
+                .to_str()
+                .ok_or_else(|| Error::from_str("template path is not valid utf-8"))?;
+            self.odb()?.add_disk_alternate(path)?;
+        }
+
+        let message = format!("seed: from {}", template.path().display());
+        let mut created = 0;
+        for reference in template.references_glob(refs_glob)? {
+            let reference = reference?;
+            let name = match reference.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            if self.find_reference(name).is_ok() {
+                continue;
+            }
+            match (reference.target(), reference.symbolic_target()) {
+                (Some(id), _) => self.reference(name, id, false, &message)?,
+                (None, Some(target)) => self.reference_symbolic(name, target, false, &message)?,
+                (None, None) => continue,
+            };
+            created += 1;
+        }
+        Ok(created)
+    }
+
+    /// Copy the objects this repository borrows through
+    /// `objects/info/alternates` into it and stop borrowing them, like
+    /// `git repack -a -d` followed by removing the alternates.
+    ///
+    /// This is the counterpart of `seed_from` for when the template is going
+    /// away. The objects reachable from the references, `HEAD`, the index and
+    /// the reflogs are written to a new pack, which is checked to be complete
+    /// before the alternates are removed.
+    pub fn dissociate(&self) -> Result<(), Error> {
+        let objects = self.item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?;
+        let alternates = objects.join("info/alternates");
+        if !alternates.exists() {
+            return Ok(());
+        }
+        self.pack_reachable(None)?;
+        fs::remove_file(&alternates)
+            .map_err(|e| Error::from_str(&format!("{}: {}", alternates.display(), e)))
+    }
+
+    /// Writes a pack to `objects/pack` with the objects reachable from the
+    /// references, `HEAD`, the index and the reflogs, leaving out the history
+    /// reachable from the references of `hide`.
+    ///
+    /// Returns an error, after writing the pack, if the loose objects, the
+    /// new pack and `hide` together lack any reachable object, so that
+    /// callers can safely drop other copies of the objects. Otherwise
+    /// returns the hash of the new pack, or `None` if there was nothing to
+    /// pack.
+    pub(crate) fn pack_reachable(&self, hide: Option<&Repository>) -> Result<Option<Oid>, Error> {
+        let odb = self.odb()?;
+        let mut names = vec!["HEAD".to_string()];
+        let mut tips = Vec::new();
+        if let Ok(id) = self.refname_to_id("HEAD") {
+            tips.push(id);
+        }
+        for reference in self.references()? {
+            let reference = reference?;
+            tips.extend(reference.target());
+            names.extend(reference.name().map(|name| name.to_string()));
+        }
+        for name in &names {
+            for entry in self.reflog(name)?.iter() {
+                tips.push(entry.id_old());
+                tips.push(entry.id_new());
+            }
+        }
+        if !self.is_bare() {
+            let index = self.index()?;
+            let submodule = u32::from(FileMode::Commit);
+            tips.extend(index.iter().filter(|e| e.mode != submodule).map(|e| e.id));
+        }
+        // Objects the repository itself lacks, like those of a reflog entry
+        // that has been pruned, cannot be packed.
+        tips.retain(|&id| !id.is_zero() && odb.exists(id));
+        tips.sort();
+        tips.dedup();
+
+        // History is packed through the walk, so that `hide` can cut it off;
+        // anything else is inserted with what it references.
+        let mut walk = self.revwalk()?;
+        let mut builder = self.packbuilder()?;
+        for &id in &tips {
+            let object = self.find_object(id, None)?;
+            if object.kind() != Some(ObjectType::Commit) {
+                builder.insert_recursive(id, None)?;
+            }
+            if let Ok(commit) = object.peel_to_commit() {
+                walk.push(commit.id())?;
+            }
+        }
+        if let Some(hide) = hide {
+            for reference in hide.references()? {
+                if let Ok(commit) = reference?.peel_to_commit() {
+                    walk.hide(commit.id())?;
+                }
+            }
+        }
+        builder.insert_walk(&mut walk)?;
+
+        let pack_dir = self
+            .item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?
+            .join("pack");
+        let mut hash = None;
+        if builder.object_count() > 0 {
+            builder.write(&pack_dir, 0)?;
+            hash = Some(
+                builder
+                    .hash()
+                    .ok_or_else(|| Error::from_str("the new pack has no name"))?,
+            );
+        }
+        let pack_index = hash.map(|hash| pack_dir.join(format!("pack-{}.idx", hash)));
+        let hide_objects = match hide {
+            Some(hide) => Some(hide.item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?),
+            None => None,
+        };
+        let packed = Odb::open_partial(
+            &self.item_path(raw::GIT_REPOSITORY_ITEM_OBJECTS)?,
+            pack_index.as_deref(),
+            hide_objects.as_deref(),
+        )?;
+        if let Some(id) = packed.find_missing(&tips)? {
+            return Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Odb,
+                format!("the new pack is missing object {}", id),
+            ));
+        }
+        Ok(hash)
+    }
+
     /// Create a new branch pointing at a target commit
     ///
     /// A new direct reference will be created pointing to this target commit.
@@ -1161,6 +1990,183 @@ impl Repository {
         }
     }
 
+    /// Guess the default branch of the repository, for a clone UI to check
+    /// out or a new branch to be based on.
+    ///
+    /// This is the branch `refs/remotes/origin/HEAD` points to, as recorded
+    /// by a clone or by `git remote set-head`, falling back on the
+    /// `init.defaultBranch` configuration and then on `master`, git's
+    /// built-in default.
+    pub fn guess_default_branch(&self) -> Result<BranchName, Error> {
+        match self.find_reference("refs/remotes/origin/HEAD") {
+            Ok(head) => {
+                let target = head.symbolic_target();
+                if let Some(name) = target.and_then(|t| t.strip_prefix("refs/remotes/origin/")) {
+                    return BranchName::new(name);
+                }
+            }
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        match self.config()?.get_string("init.defaultBranch") {
+            Ok(name) => BranchName::new(&name),
+            Err(ref e) if e.code() == ErrorCode::NotFound => BranchName::new("master"),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Point HEAD at a new, unborn branch named `name`, like
+    /// `git checkout --orphan`.
+    ///
+    /// The branch is created by the next commit to HEAD, which will have no
+    /// parents. The index and working directory are left untouched. Returns
+    /// an `Exists` error if the branch already exists.
+    pub fn create_orphan_branch(&self, name: &str) -> Result<(), Error> {
+        let refname = format!("refs/heads/{}", name);
+        if !Reference::is_valid_name(&refname) {
+            return Err(Error::new(
+                ErrorCode::InvalidSpec,
+                ErrorClass::Reference,
+                &format!("'{}' is not a valid branch name", name),
+            ));
+        }
+        if self.find_reference(&refname).is_ok() {
+            return Err(Error::new(
+                ErrorCode::Exists,
+                ErrorClass::Reference,
+                &format!("a branch named '{}' already exists", name),
+            ));
+        }
+        self.set_head(&refname)
+    }
+
+    /// Create a branch named `name` at `start_point` and switch to it, like
+    /// `git switch -c name start_point`.
+    ///
+    /// The tree of `start_point` is checked out first, safely, so local
+    /// changes which don't conflict with it are carried over and conflicting
+    /// ones make this fail before anything else is changed. The branch and
+    /// `HEAD` are then updated with reflog entries, and the upstream is set
+    /// up as requested. If a later step fails, the branch and its
+    /// configuration are removed and the previous `HEAD` is checked out
+    /// again before the error is returned.
+    ///
+    /// Returns an `Exists` error if the branch already exists.
+    pub fn create_branch_and_switch(
+        &self,
+        name: &str,
+        start_point: &str,
+        upstream: SetUpstream,
+    ) -> Result<Branch<'_>, Error> {
+        let refname = format!("refs/heads/{}", name);
+        if !Reference::is_valid_name(&refname) {
+            return Err(Error::new(
+                ErrorCode::InvalidSpec,
+                ErrorClass::Reference,
+                &format!("'{}' is not a valid branch name", name),
+            ));
+        }
+        if self.find_reference(&refname).is_ok() {
+            return Err(Error::new(
+                ErrorCode::Exists,
+                ErrorClass::Reference,
+                &format!("a branch named '{}' already exists", name),
+            ));
+        }
+        let commit = self.revparse_single(start_point)?.peel_to_commit()?;
+        let merge = match upstream {
+            SetUpstream::None => None,
+            SetUpstream::Origin => {
+                self.find_remote("origin")?;
+                let tracked = self
+                    .resolve_reference_from_short_name(start_point)
+                    .ok()
+                    .and_then(|r| r.name().map(|n| n.to_string()));
+                match tracked.and_then(|n| n.strip_prefix("refs/remotes/origin/").map(String::from))
+                {
+                    Some(branch) if branch != "HEAD" => Some(format!("refs/heads/{}", branch)),
+                    _ => Some(refname.clone()),
+                }
+            }
+        };
+        let previous = self.head().ok().and_then(|h| h.peel_to_commit().ok());
+
+        let mut checkout = CheckoutBuilder::new();
+        checkout.safe();
+        self.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+        let switch = || -> Result<(), Error> {
+            let msg = format!("branch: Created from {}", start_point);
+            self.reference(&refname, commit.id(), false, &msg)?;
+            if let Some(ref merge) = merge {
+                let mut config = self.config()?;
+                config.set_str(&format!("branch.{}.remote", name), "origin")?;
+                config.set_str(&format!("branch.{}.merge", name), merge)?;
+            }
+            self.set_head(&refname)
+        };
+        if let Err(e) = switch() {
+            if let Ok(mut reference) = self.find_reference(&refname) {
+                let _ = reference.delete();
+            }
+            if let Ok(mut config) = self.config() {
+                let _ = config.remove(&format!("branch.{}.remote", name));
+                let _ = config.remove(&format!("branch.{}.merge", name));
+            }
+            if let Some(previous) = previous {
+                let _ = self.checkout_tree(previous.as_object(), Some(&mut checkout));
+            }
+            return Err(e);
+        }
+        self.find_branch(name, BranchType::Local)
+    }
+
+    /// Commit the current index on top of `update_ref`, like `git commit`.
+    ///
+    /// `update_ref` defaults to "HEAD". Its current target becomes the parent
+    /// of the commit; if it is unborn, such as after `create_orphan_branch`,
+    /// a root commit is created.
+    ///
+    /// Unless `allow_empty` is set, an error is returned if the commit would
+    /// not change anything, i.e. if the index matches the parent's tree, or
+    /// is empty for a root commit. Setting it creates an empty commit, like
+    /// `git commit --allow-empty`, which is useful as a marker.
+    pub fn commit_empty(
+        &self,
+        update_ref: Option<&str>,
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        message: &str,
+        allow_empty: bool,
+    ) -> Result<Oid, Error> {
+        let update_ref = update_ref.unwrap_or("HEAD");
+        let parent = match self.refname_to_id(update_ref) {
+            Ok(id) => Some(self.find_commit(id)?),
+            Err(ref e) if e.code() == ErrorCode::NotFound => None,
+            Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(e),
+        };
+        let tree_id = self.index()?.write_tree()?;
+        let tree = self.find_tree(tree_id)?;
+        let unchanged = match parent {
+            Some(ref parent) => parent.tree_id() == tree_id,
+            None => tree.is_empty(),
+        };
+        if unchanged && !allow_empty {
+            return Err(Error::from_str(
+                "nothing to commit; set allow_empty to create an empty commit",
+            ));
+        }
+        let parents = parent.iter().collect::<Vec<_>>();
+        self.commit(
+            Some(update_ref),
+            author,
+            committer,
+            message,
+            &tree,
+            &parents,
+        )
+    }
+
     /// Create new commit in the repository
     ///
     /// If the `update_ref` is not `None`, name of the reference that will be
@@ -1559,19 +2565,106 @@ impl Repository {
         }
     }
 
-    /// Create a new action signature with default user and now timestamp.
+    /// Reads the entries of `FETCH_HEAD`, written by the last fetch, with
+    /// their commits as annotated commits which can be passed to `merge`.
     ///
-    /// This looks up the user.name and user.email from the configuration and
-    /// uses the current time as the timestamp, and creates a new signature
-    /// based on that information. It will return `NotFound` if either the
-    /// user.name or user.email are not set.
-    pub fn signature(&self) -> Result<Signature<'static>, Error> {
-        let mut ret = ptr::null_mut();
+    /// Entries are in the order of the file, so the heads to merge come
+    /// first. Returns an error of code `NotFound` if there is no
+    /// `FETCH_HEAD`.
+    pub fn fetch_head_entries(&self) -> Result<Vec<FetchHeadEntry<'_>>, Error> {
+        struct Line {
+            ref_name: Option<Vec<u8>>,
+            remote_url: Option<Vec<u8>>,
+            id: Oid,
+            is_for_merge: bool,
+        }
+        let mut lines: Vec<Line> = Vec::new();
         unsafe {
-            try_call!(raw::git_signature_default(&mut ret, self.raw()));
-            Ok(Binding::from_raw(ret))
+            let cb: raw::git_repository_fetchhead_foreach_cb = Some(append);
+            try_call!(raw::git_repository_fetchhead_foreach(
+                self.raw,
+                cb,
+                &mut lines as *mut _ as *mut c_void
+            ));
         }
-    }
+
+        return lines
+            .into_iter()
+            .map(|line| {
+                let commit = match (&line.ref_name, &line.remote_url) {
+                    (Some(name), Some(url)) => {
+                        let name = CString::new(&name[..])?;
+                        let url = CString::new(&url[..])?;
+                        let mut ret = ptr::null_mut();
+                        unsafe {
+                            try_call!(raw::git_annotated_commit_from_fetchhead(
+                                &mut ret,
+                                self.raw(),
+                                name,
+                                url,
+                                line.id.raw()
+                            ));
+                            AnnotatedCommit::from_raw(ret)
+                        }
+                    }
+                    _ => self.find_annotated_commit(line.id)?,
+                };
+                Ok(FetchHeadEntry {
+                    ref_name: line.ref_name,
+                    remote_url: line.remote_url,
+                    is_for_merge: line.is_for_merge,
+                    commit,
+                })
+            })
+            .collect();
+
+        extern "C" fn append(
+            ref_name: *const c_char,
+            remote_url: *const c_char,
+            oid: *const raw::git_oid,
+            is_merge: c_uint,
+            data: *mut c_void,
+        ) -> c_int {
+            unsafe {
+                let bytes = |s: *const c_char| {
+                    if s.is_null() {
+                        None
+                    } else {
+                        Some(CStr::from_ptr(s).to_bytes().to_vec())
+                    }
+                };
+                let lines = &mut *(data as *mut Vec<Line>);
+                lines.push(Line {
+                    ref_name: bytes(ref_name),
+                    remote_url: bytes(remote_url),
+                    id: Binding::from_raw(oid),
+                    is_for_merge: is_merge != 0,
+                });
+            }
+            0
+        }
+    }
+
+    /// Create a new action signature with default user and now timestamp.
+    ///
+    /// This looks up the user.name and user.email from the configuration and
+    /// uses the current time as the timestamp, and creates a new signature
+    /// based on that information. It will return `NotFound` if either the
+    /// user.name or user.email are not set.
+    ///
+    /// The timestamp comes from the clock installed with
+    /// `opts::set_time_source`, if any.
+    pub fn signature(&self) -> Result<Signature<'static>, Error> {
+        let mut ret = ptr::null_mut();
+        let signature: Signature<'static> = unsafe {
+            try_call!(raw::git_signature_default(&mut ret, self.raw()));
+            Binding::from_raw(ret)
+        };
+        match crate::opts::injected_time() {
+            Some(time) => signature.with_time(&time),
+            None => Ok(signature),
+        }
+    }
 
     /// Set up a new git submodule for checkout.
     ///
@@ -1857,6 +2950,7 @@ impl Repository {
             ));
             if let Some(c) = opts {
                 c.configure(&mut raw_opts);
+                c.set_repository(self);
             }
 
             try_call!(raw::git_checkout_head(self.raw, &raw_opts));
@@ -1880,6 +2974,7 @@ impl Repository {
             ));
             if let Some(c) = opts {
                 c.configure(&mut raw_opts);
+                c.set_repository(self);
             }
 
             try_call!(raw::git_checkout_index(
@@ -1906,6 +3001,7 @@ impl Repository {
             ));
             if let Some(c) = opts {
                 c.configure(&mut raw_opts);
+                c.set_repository(self);
             }
 
             try_call!(raw::git_checkout_tree(self.raw, &*treeish.raw(), &raw_opts));
@@ -1926,6 +3022,15 @@ impl Repository {
         annotated_commits: &[&AnnotatedCommit<'_>],
         merge_opts: Option<&mut MergeOptions>,
         checkout_opts: Option<&mut CheckoutBuilder<'_>>,
+    ) -> Result<(), Error> {
+        self.merge_with(annotated_commits, merge_opts.map(|o| &*o), checkout_opts)
+    }
+
+    fn merge_with(
+        &self,
+        annotated_commits: &[&AnnotatedCommit<'_>],
+        merge_opts: Option<&MergeOptions>,
+        checkout_opts: Option<&mut CheckoutBuilder<'_>>,
     ) -> Result<(), Error> {
         unsafe {
             let mut raw_checkout_opts = mem::zeroed();
@@ -1935,6 +3040,7 @@ impl Repository {
             ));
             if let Some(c) = checkout_opts {
                 c.configure(&mut raw_checkout_opts);
+                c.set_repository(self);
             }
 
             let mut commit_ptrs = annotated_commits
@@ -1953,6 +3059,98 @@ impl Repository {
         Ok(())
     }
 
+    /// Merge `their_head` into `HEAD` and commit the result, like `git merge`.
+    ///
+    /// Nothing is done if the commit is already merged. Otherwise `HEAD` is
+    /// fast-forwarded when possible and allowed by `merge.ff`, and the merge
+    /// is refused with a `NotFastForward` error when `merge.ff` is `only`
+    /// and a fast-forward is not possible. In all other cases the commit is
+    /// merged into the working directory and, if there are no conflicts, a
+    /// merge commit is created with the message from `template` and the
+    /// repository's merging state is cleaned up.
+    ///
+    /// If there are conflicts, the repository is left merging, for the
+    /// conflicts to be resolved and committed, and the conflicting files are
+    /// returned. The message from `template` is prepared in `MERGE_MSG`,
+    /// followed by the conflicting files commented out, like git does.
+    pub fn merge_and_commit(
+        &self,
+        their_head: &AnnotatedCommit<'_>,
+        opts: &MergeOptions,
+        template: &CommitMessageTemplate,
+    ) -> Result<MergeOutcome, Error> {
+        let name = match their_head.refname() {
+            Some(name) => name.to_string(),
+            None => their_head.id().to_string(),
+        };
+        let id = match self.merge_decision(their_head)? {
+            MergeDecision::UpToDate => return Ok(MergeOutcome::UpToDate),
+            MergeDecision::Unborn(id) => {
+                let head = self.find_reference("HEAD")?;
+                let branch = head
+                    .symbolic_target()
+                    .ok_or_else(|| Error::from_str("HEAD is not a symbolic reference"))?;
+                let commit = self.find_commit(id)?;
+                self.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+                let msg = format!("merge {}: Fast-forward", name);
+                self.reference(branch, id, false, &msg)?;
+                return Ok(MergeOutcome::FastForward(id));
+            }
+            MergeDecision::FastForward(id) => {
+                let commit = self.find_commit(id)?;
+                self.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+                let msg = format!("merge {}: Fast-forward", name);
+                self.head()?.set_target(id, &msg)?;
+                return Ok(MergeOutcome::FastForward(id));
+            }
+            MergeDecision::Abort(reason) => {
+                return Err(Error::new(
+                    ErrorCode::NotFastForward,
+                    ErrorClass::Merge,
+                    format!("not possible to fast-forward ({:?}), aborting", reason),
+                ))
+            }
+            MergeDecision::Merge(_) => their_head.id(),
+        };
+
+        self.merge_with(&[their_head], Some(opts), None)?;
+        let merge_msg = self.path().join("MERGE_MSG");
+        let prepared =
+            fs::read_to_string(&merge_msg).unwrap_or_else(|_| format!("Merge commit '{}'\n", id));
+        // libgit2 lists the conflicts uncommented, so the list is replaced by
+        // one commented out like git's below.
+        let default = crate::message::split_conflicts(&prepared).0;
+        let mut index = self.index()?;
+        if index.has_conflicts() {
+            let mut paths = Vec::new();
+            for conflict in index.conflicts()? {
+                let conflict = conflict?;
+                let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+                if let Some(entry) = entry {
+                    paths.push(util::bytes2path(&entry.path).to_path_buf());
+                }
+            }
+            let mut message = template.render(default);
+            message.push_str("\n# Conflicts:\n");
+            for path in paths.iter() {
+                message.push_str(&format!("#\t{}\n", path.display()));
+            }
+            fs::write(&merge_msg, message).map_err(|e| {
+                Error::from_str(&format!("failed to write {}: {}", merge_msg.display(), e))
+            })?;
+            return Ok(MergeOutcome::Conflicts(paths));
+        }
+
+        let tree = self.find_tree(index.write_tree()?)?;
+        let message = message_prettify(template.render(default), DEFAULT_COMMENT_CHAR)?;
+        let sig = self.signature()?;
+        let head = self.head()?.peel_to_commit()?;
+        let theirs = self.find_commit(id)?;
+        let merge = self.commit(Some("HEAD"), &sig, &sig, &message, &tree, &[&head, &theirs])?;
+        self.cleanup_state()?;
+        Ok(MergeOutcome::Merged(merge))
+    }
+
     /// Merge two commits, producing an index that reflects the result of
     /// the merge. The index may be written as-is to the working directory or
     /// checked out. If the index is to be converted to a tree, the caller
@@ -1972,8 +3170,12 @@ impl Repository {
                 their_commit.raw(),
                 opts.map(|o| o.raw())
             ));
-            Ok(Binding::from_raw(raw))
         }
+        let mut index = unsafe { Binding::from_raw(raw) };
+        if let Some(opts) = opts {
+            self.apply_path_favors(&mut index, opts)?;
+        }
+        Ok(index)
     }
 
     /// Merge two trees, producing an index that reflects the result of
@@ -1997,8 +3199,338 @@ impl Repository {
                 their_tree.raw(),
                 opts.map(|o| o.raw())
             ));
-            Ok(Binding::from_raw(raw))
         }
+        let mut index = unsafe { Binding::from_raw(raw) };
+        if let Some(opts) = opts {
+            self.apply_path_favors(&mut index, opts)?;
+        }
+        Ok(index)
+    }
+
+    /// Merge two commits without touching the working directory or the
+    /// repository's index, returning the id of the merged tree, which is
+    /// written to the object database, and the files which conflicted.
+    ///
+    /// This is meant for checking whether commits merge cleanly, such as on
+    /// a server with a bare repository: the merge is clean if there are no
+    /// conflicts. Like `git merge-tree --write-tree`, files whose contents
+    /// conflict are written to the tree with conflict markers, and a file
+    /// which only one side still has, such as one modified on one side and
+    /// deleted on the other, is kept as that side has it.
+    pub fn merge_commits_to_tree(
+        &self,
+        our_commit: &Commit<'_>,
+        their_commit: &Commit<'_>,
+        opts: Option<&MergeOptions>,
+    ) -> Result<(Oid, Vec<MergeConflict>), Error> {
+        let merged = self.merge_commits(our_commit, their_commit, opts)?;
+        let mut index = Index::new()?;
+        for entry in merged.iter() {
+            if entry.flags & raw::GIT_INDEX_ENTRY_STAGEMASK == 0 {
+                index.add(&entry)?;
+            }
+        }
+        let mut conflicts = Vec::new();
+        for conflict in merged.conflicts()? {
+            let conflict = conflict?;
+            let conflict = MergeConflict {
+                ancestor: conflict.ancestor,
+                our: conflict.our,
+                their: conflict.their,
+            };
+            if let Some(entry) = self.conflict_to_tree_entry(&conflict, opts)? {
+                index.add(&entry)?;
+            }
+            conflicts.push(conflict);
+        }
+        Ok((index.write_tree_to(self)?, conflicts))
+    }
+
+    /// Returns the entry `merge_commits_to_tree` writes for a conflicted
+    /// file, or `None` if neither side has it.
+    fn conflict_to_tree_entry(
+        &self,
+        conflict: &MergeConflict,
+        opts: Option<&MergeOptions>,
+    ) -> Result<Option<IndexEntry>, Error> {
+        // Only regular files have contents to merge; for symlinks and
+        // submodules our side is kept.
+        let (our, their) = match (&conflict.our, &conflict.their) {
+            (Some(our), Some(their)) if is_regular_file(our) && is_regular_file(their) => {
+                (our, their)
+            }
+            (Some(side), _) | (None, Some(side)) => return Ok(Some(resolved_entry(side))),
+            (None, None) => return Ok(None),
+        };
+        let ancestor = conflict.ancestor.as_ref();
+        let (entry, _) = self.merge_conflicted_file(ancestor, our, their, opts)?;
+        Ok(Some(entry))
+    }
+
+    /// Merges the contents of the conflicting regular files `our` and
+    /// `their`, writing the result to the object database. Returns an
+    /// unconflicted entry for it, whose contents have conflict markers
+    /// unless the status says the file merged cleanly.
+    fn merge_conflicted_file(
+        &self,
+        ancestor: Option<&IndexEntry>,
+        our: &IndexEntry,
+        their: &IndexEntry,
+        opts: Option<&MergeOptions>,
+    ) -> Result<(IndexEntry, MergeFileStatus), Error> {
+        let input = |entry: &IndexEntry| {
+            let mut input = MergeFileInput::blob(entry.id);
+            input.path(util::bytes2path(&entry.path)).mode(entry.mode);
+            input
+        };
+        let ancestor = ancestor.filter(|a| is_regular_file(a)).map(input);
+        let mut contents = Vec::new();
+        let status =
+            self.merge_file_stream(ancestor, input(our), input(their), opts, &mut contents)?;
+        let mut entry = resolved_entry(our);
+        entry.id = self.blob(&contents)?;
+        entry.file_size = contents.len() as u32;
+        if status.mode() != 0 {
+            entry.mode = status.mode();
+        }
+        Ok((entry, status))
+    }
+
+    /// Resolves the conflicts of `index` whose paths have a favor of their
+    /// own in `opts` by merging their contents again with that favor.
+    fn apply_path_favors(&self, index: &mut Index, opts: &MergeOptions) -> Result<(), Error> {
+        if !opts.has_path_favors() {
+            return Ok(());
+        }
+        let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+        for conflict in conflicts {
+            let (our, their) = match (&conflict.our, &conflict.their) {
+                (Some(our), Some(their)) if is_regular_file(our) && is_regular_file(their) => {
+                    (our, their)
+                }
+                _ => continue,
+            };
+            let path = util::bytes2path(&our.path);
+            let favor = match opts.path_favor(path)? {
+                Some(favor) => favor,
+                None => continue,
+            };
+            let favored = opts.with_file_favor(favor);
+            let ancestor = conflict.ancestor.as_ref();
+            let (entry, status) =
+                self.merge_conflicted_file(ancestor, our, their, Some(&favored))?;
+            if !status.is_automergeable() {
+                continue;
+            }
+            for stage in 1..4 {
+                if index.get_path(path, stage).is_some() {
+                    index.remove(path, stage)?;
+                }
+            }
+            index.add(&entry)?;
+        }
+        Ok(())
+    }
+
+    /// Merge the contents of a single file, writing the merged contents,
+    /// with conflict markers if the sides conflict, to `out`.
+    ///
+    /// This is the streaming form of a three-way file merge, for files of
+    /// hundreds of megabytes: sides given as readers are streamed into the
+    /// object database instead of being read into memory, and the merged
+    /// contents are written straight from libgit2's buffer. libgit2 still
+    /// holds the three sides and the result in memory while merging, but no
+    /// copy of them is made on the Rust side.
+    ///
+    /// Without an `ancestor` the file is merged as added on both sides. Only
+    /// the file favor and file flags of `opts` are used; conflict markers
+    /// are labelled with the paths of the sides.
+    pub fn merge_file_stream<W: Write>(
+        &self,
+        ancestor: Option<MergeFileInput<'_>>,
+        ours: MergeFileInput<'_>,
+        theirs: MergeFileInput<'_>,
+        opts: Option<&MergeOptions>,
+        out: &mut W,
+    ) -> Result<MergeFileStatus, Error> {
+        struct Input {
+            id: Oid,
+            path: Option<CString>,
+            mode: u32,
+        }
+        let prepare = |input: MergeFileInput<'_>| -> Result<Input, Error> {
+            let id = match input.source {
+                MergeFileSource::Blob(id) => id,
+                MergeFileSource::Reader(mut reader) => {
+                    let mut writer = self.blob_writer(None)?;
+                    io::copy(&mut reader, &mut writer)
+                        .map_err(|e| Error::from_str(&format!("failed to stream blob: {}", e)))?;
+                    writer.commit()?
+                }
+            };
+            let path = match input.path {
+                Some(path) => Some(path.into_c_string()?),
+                None => None,
+            };
+            Ok(Input {
+                id,
+                path,
+                mode: input.mode,
+            })
+        };
+        let entry = |input: &Input| {
+            let mut entry: raw::git_index_entry = unsafe { mem::zeroed() };
+            entry.id = unsafe { *input.id.raw() };
+            entry.mode = input.mode;
+            entry.path = input.path.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+            entry
+        };
+
+        let ancestor = match ancestor {
+            Some(ancestor) => Some(prepare(ancestor)?),
+            None => None,
+        };
+        let (ours, theirs) = (prepare(ours)?, prepare(theirs)?);
+        let ancestor_entry = ancestor.as_ref().map(entry);
+        let (ours_entry, theirs_entry) = (entry(&ours), entry(&theirs));
+        let default_opts = MergeOptions::new();
+        let file_opts = opts.unwrap_or(&default_opts).file_options();
+        let mut result: raw::git_merge_file_result = unsafe { mem::zeroed() };
+        unsafe {
+            try_call!(raw::git_merge_file_from_index(
+                &mut result,
+                self.raw,
+                ancestor_entry
+                    .as_ref()
+                    .map_or(ptr::null(), |e| e as *const _),
+                &ours_entry,
+                &theirs_entry,
+                &file_opts
+            ));
+        }
+
+        let merged = if result.len == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(result.ptr as *const u8, result.len) }
+        };
+        let written = out.write_all(merged);
+        let status = MergeFileStatus {
+            automergeable: result.automergeable != 0,
+            path: unsafe { crate::opt_bytes(&result, result.path) }
+                .map(|p| util::bytes2path(p).to_path_buf()),
+            mode: result.mode,
+        };
+        unsafe { raw::git_merge_file_result_free(&mut result) };
+        written.map_err(|e| Error::from_str(&format!("failed to write merged file: {}", e)))?;
+        Ok(status)
+    }
+
+    /// Merge two commits like `merge_commits`, and also return the merge base
+    /// used as their common ancestor.
+    ///
+    /// When the commits have several merge bases, as after criss-cross
+    /// merges, they are merged into a virtual merge base the way libgit2
+    /// does, recursively if they have several merge bases themselves.
+    /// Conflicting contents are kept in the files of the virtual merge base
+    /// with their conflict markers, which is what a conflict UI should show
+    /// as the base version.
+    pub fn merge_commits_with_base(
+        &self,
+        our_commit: &Commit<'_>,
+        their_commit: &Commit<'_>,
+        opts: Option<&MergeOptions>,
+    ) -> Result<MergeWithBase, Error> {
+        let default = MergeOptions::new();
+        let opts = opts.unwrap_or(&default);
+        let mut depth = 0;
+        let (base, bases) =
+            self.virtual_merge_base(&[our_commit.id()], their_commit.id(), opts, 0, &mut depth)?;
+        let ancestor = match base {
+            Some(ref base) => self.find_tree(base.tree)?,
+            None => self.find_tree(self.treebuilder(None)?.write()?)?,
+        };
+        let index = self.merge_trees(
+            &ancestor,
+            &our_commit.tree()?,
+            &their_commit.tree()?,
+            Some(opts),
+        )?;
+        Ok(MergeWithBase {
+            index,
+            base_tree: base.as_ref().map(|base| base.tree),
+            base_commit: base.and_then(|base| base.commit),
+            bases,
+            depth,
+        })
+    }
+
+    /// Computes the merge base of `one`, which is a commit or the heads of
+    /// a virtual commit, and of the commit `two`, following libgit2's
+    /// `compute_base`. Also returns the merge bases which were found.
+    fn virtual_merge_base(
+        &self,
+        one: &[Oid],
+        two: Oid,
+        opts: &MergeOptions,
+        mut level: usize,
+        depth: &mut usize,
+    ) -> Result<(Option<MergeBase>, Vec<Oid>), Error> {
+        let mut heads = vec![two];
+        heads.extend_from_slice(one);
+        let mut bases = match self.merge_bases_many(&heads) {
+            Ok(bases) => bases.iter().cloned().collect::<Vec<_>>(),
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok((None, Vec::new())),
+            Err(e) => return Err(e),
+        };
+        let count = if opts.is_no_recursive() {
+            1
+        } else {
+            bases.reverse();
+            bases.len()
+        };
+
+        let mut base = MergeBase {
+            tree: self.find_commit(bases[0])?.tree_id(),
+            commit: Some(bases[0]),
+            heads: vec![bases[0]],
+        };
+        let limit = opts.recursion_limit_value() as usize;
+        for &other in &bases[1..count] {
+            level += 1;
+            if limit != 0 && level > limit {
+                break;
+            }
+            *depth = cmp::max(*depth, level);
+
+            let virtual_opts = opts.for_virtual_base();
+            let (ancestor, _) =
+                self.virtual_merge_base(&base.heads, other, &virtual_opts, level + 1, depth)?;
+            let ancestor = match ancestor {
+                Some(ancestor) => self.find_tree(ancestor.tree)?,
+                None => self.find_tree(self.treebuilder(None)?.write()?)?,
+            };
+            let merged = self.merge_trees(
+                &ancestor,
+                &self.find_tree(base.tree)?,
+                &self.find_commit(other)?.tree()?,
+                Some(&virtual_opts),
+            )?;
+            // Like libgit2, leave out the entries which still conflict.
+            let mut index = Index::new()?;
+            for entry in merged.iter() {
+                if (entry.flags >> raw::GIT_INDEX_ENTRY_STAGESHIFT) & 3 == 0 {
+                    index.add(&entry)?;
+                }
+            }
+            base.heads.push(other);
+            base = MergeBase {
+                tree: index.write_tree_to(self)?,
+                commit: None,
+                heads: base.heads,
+            };
+        }
+        Ok((Some(base), bases))
     }
 
     /// Remove all the metadata associated with an ongoing command like merge,
@@ -2037,8 +3569,151 @@ impl Repository {
         }
     }
 
+    /// Explains why merging `their_heads` into `HEAD` cannot be done by
+    /// fast-forwarding.
+    ///
+    /// Returns `None` if it can, and also if `HEAD` is unborn or the heads
+    /// are already merged, as no merge commit is needed then either.
+    pub fn no_fast_forward_reason(
+        &self,
+        their_heads: &[&AnnotatedCommit<'_>],
+    ) -> Result<Option<NoFastForwardReason>, Error> {
+        self.fast_forward_blocker(None, their_heads)
+    }
+
+    /// Explains why merging `their_heads` into `our_ref` cannot be done by
+    /// fast-forwarding, like `no_fast_forward_reason` does for `HEAD`.
+    pub fn no_fast_forward_reason_for_ref(
+        &self,
+        our_ref: &Reference<'_>,
+        their_heads: &[&AnnotatedCommit<'_>],
+    ) -> Result<Option<NoFastForwardReason>, Error> {
+        self.fast_forward_blocker(Some(our_ref), their_heads)
+    }
+
+    /// Runs `merge_analysis_for_ref` for `our_ref`, or `merge_analysis` for
+    /// `HEAD` when it is `None`.
+    fn analyze_merge(
+        &self,
+        our_ref: Option<&Reference<'_>>,
+        their_heads: &[&AnnotatedCommit<'_>],
+    ) -> Result<MergeAnalysis, Error> {
+        let (analysis, _) = match our_ref {
+            Some(our_ref) => self.merge_analysis_for_ref(our_ref, their_heads)?,
+            None => self.merge_analysis(their_heads)?,
+        };
+        Ok(analysis)
+    }
+
+    fn fast_forward_blocker(
+        &self,
+        our_ref: Option<&Reference<'_>>,
+        their_heads: &[&AnnotatedCommit<'_>],
+    ) -> Result<Option<NoFastForwardReason>, Error> {
+        let analysis = self.analyze_merge(our_ref, their_heads)?;
+        if analysis.is_up_to_date() || analysis.is_unborn() || analysis.is_fast_forward() {
+            return Ok(None);
+        }
+        if their_heads.len() > 1 {
+            return Ok(Some(NoFastForwardReason::Octopus));
+        }
+        let ours = match our_ref {
+            Some(our_ref) => our_ref.peel_to_commit()?.id(),
+            None => self.head()?.peel_to_commit()?.id(),
+        };
+        match self.merge_base(ours, their_heads[0].id()) {
+            Ok(_) => Ok(Some(NoFastForwardReason::Diverged)),
+            Err(ref e) if e.code() == ErrorCode::NotFound => {
+                Ok(Some(NoFastForwardReason::NoMergeBase))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Recommends how to merge `theirs` into `HEAD`, like `git merge` would
+    /// given the `merge.ff` configuration.
+    pub fn merge_decision(&self, theirs: &AnnotatedCommit<'_>) -> Result<MergeDecision, Error> {
+        self.decide_merge(None, theirs, &["merge.ff"])
+    }
+
+    /// Recommends how to merge `theirs` into `our_ref`, like `merge_decision`
+    /// does for `HEAD`.
+    ///
+    /// This works on any branch, including in bare repositories, so that a
+    /// server can tell whether a branch would be fast-forwarded or needs a
+    /// merge commit before merging into it.
+    pub fn merge_decision_for_ref(
+        &self,
+        our_ref: &Reference<'_>,
+        theirs: &AnnotatedCommit<'_>,
+    ) -> Result<MergeDecision, Error> {
+        self.decide_merge(Some(our_ref), theirs, &["merge.ff"])
+    }
+
+    /// Recommends how to merge `theirs` into `HEAD` at the end of a pull, like
+    /// `git pull` would: `pull.ff` takes precedence over `merge.ff` when it
+    /// is set.
+    pub fn pull_decision(&self, theirs: &AnnotatedCommit<'_>) -> Result<MergeDecision, Error> {
+        self.decide_merge(None, theirs, &["pull.ff", "merge.ff"])
+    }
+
+    /// Decides how to merge `theirs` into `our_ref`, or `HEAD` when it is
+    /// `None`, given the first of the `ff_keys` configuration entries which
+    /// is set.
+    fn decide_merge(
+        &self,
+        our_ref: Option<&Reference<'_>>,
+        theirs: &AnnotatedCommit<'_>,
+        ff_keys: &[&str],
+    ) -> Result<MergeDecision, Error> {
+        let analysis = self.analyze_merge(our_ref, &[theirs])?;
+        if analysis.is_up_to_date() {
+            return Ok(MergeDecision::UpToDate);
+        }
+        if analysis.is_unborn() {
+            return Ok(MergeDecision::Unborn(theirs.id()));
+        }
+
+        let config = self.config()?;
+        let (mut allow_ff, mut ff_only) = (true, false);
+        for key in ff_keys {
+            match config.get_string(key) {
+                Ok(ref value) if value == "only" => {
+                    ff_only = true;
+                    break;
+                }
+                Ok(value) => {
+                    allow_ff = Config::parse_bool(value)?;
+                    break;
+                }
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let reason = if analysis.is_fast_forward() {
+            if allow_ff {
+                return Ok(MergeDecision::FastForward(theirs.id()));
+            }
+            NoFastForwardReason::Disabled
+        } else {
+            self.fast_forward_blocker(our_ref, &[theirs])?
+                .unwrap_or(NoFastForwardReason::Diverged)
+        };
+        if ff_only {
+            Ok(MergeDecision::Abort(reason))
+        } else {
+            Ok(MergeDecision::Merge(reason))
+        }
+    }
+
     /// Analyzes the given branch(es) and determines the opportunities for
     /// merging them into a reference.
+    ///
+    /// Unlike `merge_analysis`, this does not involve `HEAD`, so it can be
+    /// used on any branch, including in bare repositories. The reference is
+    /// considered unborn only when it is a symbolic `HEAD` pointing at a
+    /// branch which does not exist yet.
     pub fn merge_analysis_for_ref(
         &self,
         our_ref: &Reference<'_>,
@@ -2076,33 +3751,405 @@ impl Repository {
         onto: Option<&AnnotatedCommit<'_>>,
         opts: Option<&mut RebaseOptions<'_>>,
     ) -> Result<Rebase<'_>, Error> {
-        let mut rebase: *mut raw::git_rebase = ptr::null_mut();
-        unsafe {
-            try_call!(raw::git_rebase_init(
-                &mut rebase,
-                self.raw(),
-                branch.map(|c| c.raw()),
-                upstream.map(|c| c.raw()),
-                onto.map(|c| c.raw()),
-                opts.map(|o| o.raw()).unwrap_or(ptr::null())
-            ));
+        let mut opts = opts;
+        let raw_opts = match opts {
+            Some(ref mut opts) => opts.raw(),
+            None => ptr::null(),
+        };
+        let autostash = opts.as_ref().map(|o| o.autostash_enabled()) == Some(true);
+        let init = || -> Result<*mut raw::git_rebase, Error> {
+            let mut rebase: *mut raw::git_rebase = ptr::null_mut();
+            unsafe {
+                try_call!(raw::git_rebase_init(
+                    &mut rebase,
+                    self.raw(),
+                    branch.map(|c| c.raw()),
+                    upstream.map(|c| c.raw()),
+                    onto.map(|c| c.raw()),
+                    raw_opts
+                ));
+            }
+            Ok(rebase)
+        };
 
-            Ok(Rebase::from_raw(rebase))
+        // libgit2 refuses to rebase a dirty working directory, so the
+        // autostash has to be made first, and applied again if the rebase
+        // can't be started.
+        let stash = if autostash {
+            crate::rebase::save_autostash(self)?
+        } else {
+            None
+        };
+        let rebase = match (init(), stash) {
+            (Ok(rebase), _) => rebase,
+            (Err(e), Some(id)) => {
+                crate::rebase::apply_autostash(self, id)?;
+                return Err(e);
+            }
+            (Err(e), None) => return Err(e),
+        };
+        let mut rebase = unsafe { Rebase::from_raw(rebase) };
+        rebase.attach(self, opts.as_deref());
+        if let Some(id) = stash {
+            rebase.record_autostash(id)?;
         }
+        Ok(rebase)
     }
 
     /// Opens an existing rebase that was previously started by either an
     /// invocation of `rebase()` or by another client.
     pub fn open_rebase(&self, opts: Option<&mut RebaseOptions<'_>>) -> Result<Rebase<'_>, Error> {
         let mut rebase: *mut raw::git_rebase = ptr::null_mut();
+        let mut opts = opts;
         unsafe {
             try_call!(raw::git_rebase_open(
                 &mut rebase,
                 self.raw(),
-                opts.map(|o| o.raw()).unwrap_or(ptr::null())
+                opts.as_mut().map(|o| o.raw()).unwrap_or(ptr::null())
             ));
-            Ok(Rebase::from_raw(rebase))
+            let mut rebase = Rebase::from_raw(rebase);
+            rebase.attach(self, opts.as_deref());
+            Ok(rebase)
+        }
+    }
+
+    /// Rebases `branch` onto `onto`, or onto `upstream` if it is `None`,
+    /// entirely in memory.
+    ///
+    /// Unlike `rebase`, this never changes `HEAD`, the index, the working
+    /// directory or any reference, so it works in bare repositories. Each
+    /// commit keeps its author and message and is committed by `committer`.
+    /// A commit that becomes empty when applied, for example because the
+    /// same change was already made upstream, is left out; commits are not
+    /// otherwise compared with the upstream ones. Returns the rebased
+    /// commits, in order, as pairs of their original and new ids; the new id
+    /// of the last one is the rebased branch.
+    ///
+    /// `opts` is used as given except that the rebase is always in-memory;
+    /// the caller's options are not modified.
+    ///
+    /// Returns a `MergeConflict` error naming the commit if one of them
+    /// doesn't apply cleanly.
+    pub fn rebase_in_memory(
+        &self,
+        branch: Option<&AnnotatedCommit<'_>>,
+        upstream: Option<&AnnotatedCommit<'_>>,
+        onto: Option<&AnnotatedCommit<'_>>,
+        committer: &Signature<'_>,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<Vec<(Oid, Oid)>, Error> {
+        let mut opts = match opts {
+            Some(opts) => opts.inmemory_copy(),
+            None => {
+                let mut opts = RebaseOptions::new();
+                opts.inmemory(true);
+                opts
+            }
+        };
+        let mut rebase = self.rebase(branch, upstream, onto, Some(&mut opts))?;
+        let mut rebased = Vec::new();
+        while let Some(op) = rebase.next() {
+            let op = op?;
+            if op.kind() == Some(RebaseOperationType::Exec) {
+                continue;
+            }
+            let id = op.id();
+            // libgit2 refuses to commit a conflicted in-memory index with a
+            // generic error, so conflicts are checked for beforehand.
+            if rebase.inmemory_index()?.has_conflicts() {
+                rebase.abort()?;
+                return Err(Error::new(
+                    ErrorCode::MergeConflict,
+                    ErrorClass::Rebase,
+                    format!("could not apply {}", id),
+                ));
+            }
+            match rebase.commit(None, committer, None) {
+                Ok(new_id) => rebased.push((id, new_id)),
+                Err(ref e) if e.code() == ErrorCode::Applied => {}
+                Err(e) => return Err(e),
+            }
+        }
+        rebase.finish(Some(committer))?;
+        Ok(rebased)
+    }
+
+    /// Rebase `branch` onto `onto`, or onto `upstream` if it is `None`,
+    /// keeping its merge commits, like `git rebase --rebase-merges`.
+    ///
+    /// This is `rebase_merges_todo` followed by `apply_rebase_todo`. If
+    /// `branch` was looked up from a reference, the reference is updated and,
+    /// when it is the current branch, the new commit is checked out. The
+    /// rebased commit is returned.
+    pub fn rebase_merges(
+        &self,
+        branch: &AnnotatedCommit<'_>,
+        upstream: &AnnotatedCommit<'_>,
+        onto: Option<&AnnotatedCommit<'_>>,
+        opts: Option<&MergeOptions>,
+    ) -> Result<Oid, Error> {
+        let todo = self.rebase_merges_todo(branch.id(), upstream.id())?;
+        let onto = onto.unwrap_or(upstream).id();
+        let id = self.apply_rebase_todo(&todo, onto, opts)?;
+        let refname = match branch.refname() {
+            Some(refname) => refname,
+            None => return Ok(id),
+        };
+        let head = self.find_reference("HEAD")?;
+        if head.symbolic_target_bytes() == Some(refname.as_bytes()) {
+            let commit = self.find_commit(id)?;
+            self.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        }
+        let msg = format!("rebase (merges): {} onto {}", refname, onto);
+        self.reference(refname, id, true, &msg)?;
+        Ok(id)
+    }
+
+    /// Builds the todo list of `git rebase --rebase-merges` for the commits
+    /// reachable from `head` but not from `upstream`.
+    ///
+    /// The list starts with `label onto`. Each line of history is picked
+    /// after a `reset` to the commit it branches from, the commits other
+    /// lines start from or merge get a `label`, and merge commits become
+    /// `merge -C <commit> <label>` instructions. Branches are labelled after
+    /// the name in the subject of the merge commit, such as `feature` for
+    /// "Merge branch 'feature'", and branch points `branch-point`. Every
+    /// line is rebased onto `onto`, even those which did not branch from
+    /// `upstream` itself.
+    pub fn rebase_merges_todo(&self, head: Oid, upstream: Oid) -> Result<RebaseTodo, Error> {
+        let mut walk = self.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL)?;
+        walk.push(head)?;
+        walk.hide(upstream)?;
+        let commits = walk.collect::<Result<Vec<_>, _>>()?;
+
+        let mut plan = MergesPlan {
+            range: commits.iter().cloned().collect(),
+            emitted: HashSet::new(),
+            labels: HashMap::new(),
+        };
+        let mut children = HashMap::new();
+        let mut taken = HashSet::new();
+        taken.insert("onto".to_string());
+        for &id in commits.iter() {
+            let commit = self.find_commit(id)?;
+            for (n, parent) in commit.parent_ids().enumerate() {
+                *children.entry(parent).or_insert(0) += 1;
+                if n > 0 && plan.range.contains(&parent) && !plan.labels.contains_key(&parent) {
+                    let name = branch_label(commit.summary_bytes().unwrap_or(b""));
+                    let name = unique_label(&mut taken, name.as_deref().unwrap_or("branch"));
+                    plan.labels.insert(parent, name);
+                }
+            }
+        }
+        for &id in commits.iter().rev() {
+            if children.get(&id).cloned().unwrap_or(0) > 1 && !plan.labels.contains_key(&id) {
+                let name = unique_label(&mut taken, "branch-point");
+                plan.labels.insert(id, name);
+            }
+        }
+
+        let mut items = vec![RebaseTodoItem::new(RebaseTodoCommand::Label, "onto")];
+        self.emit_rebase_line(head, &mut plan, &mut items)?;
+        Ok(RebaseTodo { items })
+    }
+
+    /// Adds the instructions for the line of history ending at `tip`, after
+    /// those of the lines it merges.
+    fn emit_rebase_line(
+        &self,
+        tip: Oid,
+        plan: &mut MergesPlan,
+        items: &mut Vec<RebaseTodoItem>,
+    ) -> Result<(), Error> {
+        let mut line = Vec::new();
+        let mut id = tip;
+        while plan.range.contains(&id) && !plan.emitted.contains(&id) {
+            line.push(id);
+            match self.find_commit(id)?.parent_id(0) {
+                Ok(parent) => id = parent,
+                Err(_) => break,
+            }
+        }
+        line.reverse();
+        for &id in line.iter() {
+            for parent in self.find_commit(id)?.parent_ids().skip(1) {
+                if plan.range.contains(&parent) && !plan.emitted.contains(&parent) {
+                    self.emit_rebase_line(parent, plan, items)?;
+                }
+            }
+        }
+
+        // The lines merged in may have picked the start of this one.
+        let start = line
+            .iter()
+            .rposition(|id| plan.emitted.contains(id))
+            .map(|n| n + 1)
+            .unwrap_or(0);
+        let base = match start {
+            0 => match line.first() {
+                Some(&first) => self.find_commit(first)?.parent_id(0).ok(),
+                None => return Ok(()),
+            },
+            n => Some(line[n - 1]),
+        };
+        let base = match base {
+            Some(base) if plan.range.contains(&base) => plan.label(base)?,
+            _ => "onto".to_string(),
+        };
+        if start == line.len() {
+            return Ok(());
+        }
+        items.push(RebaseTodoItem::new(RebaseTodoCommand::Reset, base));
+        for &id in line[start..].iter() {
+            plan.emitted.insert(id);
+            let commit = self.find_commit(id)?;
+            if commit.parent_count() > 1 {
+                let mut args = format!("-C {}", id);
+                for parent in commit.parent_ids().skip(1) {
+                    args.push(' ');
+                    if plan.range.contains(&parent) {
+                        args.push_str(&plan.label(parent)?);
+                    } else {
+                        args.push_str(&parent.to_string());
+                    }
+                }
+                items.push(RebaseTodoItem::new(RebaseTodoCommand::Merge, args));
+            } else {
+                items.push(RebaseTodoItem::new(RebaseTodoCommand::Pick, id.to_string()));
+            }
+            if let Some(label) = plan.labels.get(&id) {
+                items.push(RebaseTodoItem::new(RebaseTodoCommand::Label, label.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a todo list in memory on top of `onto` and returns the last
+    /// commit, without touching `HEAD`, the index or the working directory.
+    ///
+    /// `pick`, `reword`, `label`, `reset`, `merge`, `drop` and `noop` are
+    /// supported, with `reword` keeping the message and `merge` merging a
+    /// single label or commit. `reset` and `merge` take labels set earlier
+    /// or revisions. Commits whose parents are unchanged are kept as they
+    /// are; other commits keep their author and message and are committed
+    /// with the repository's signature.
+    ///
+    /// Returns a `MergeConflict` error naming the commit if one of them
+    /// doesn't apply cleanly.
+    pub fn apply_rebase_todo(
+        &self,
+        todo: &RebaseTodo,
+        onto: Oid,
+        opts: Option<&MergeOptions>,
+    ) -> Result<Oid, Error> {
+        let committer = self.signature()?;
+        let mut labels = HashMap::new();
+        let mut current = onto;
+        let resolve = |labels: &HashMap<&str, Oid>, name: &str| match labels.get(name) {
+            Some(&id) => Ok(id),
+            None => self.revparse_single(name)?.peel_to_commit().map(|c| c.id()),
+        };
+        let conflict = |id: Oid| {
+            Error::new(
+                ErrorCode::MergeConflict,
+                ErrorClass::Rebase,
+                format!("could not apply {}", id),
+            )
+        };
+        for item in todo.items.iter() {
+            match item.command {
+                RebaseTodoCommand::Pick | RebaseTodoCommand::Reword => {
+                    let spec = item
+                        .commit()
+                        .ok_or_else(|| Error::from_str("rebase todo item is missing a commit"))?;
+                    let commit = self.revparse_single(spec)?.peel_to_commit()?;
+                    if commit.parent_count() > 1 {
+                        let msg = format!("{} is a merge; use 'merge -C' for it", commit.id());
+                        return Err(Error::from_str(&msg));
+                    }
+                    if commit.parent_id(0).ok() == Some(current) {
+                        current = commit.id();
+                        continue;
+                    }
+                    let ours = self.find_commit(current)?;
+                    let mut index = self.cherrypick_commit(&commit, &ours, 0, opts)?;
+                    if index.has_conflicts() {
+                        return Err(conflict(commit.id()));
+                    }
+                    let tree = self.find_tree(index.write_tree_to(self)?)?;
+                    let message = String::from_utf8_lossy(commit.message_raw_bytes());
+                    current = self.commit(
+                        None,
+                        &commit.author(),
+                        &committer,
+                        &message,
+                        &tree,
+                        &[&ours],
+                    )?;
+                }
+                RebaseTodoCommand::Label => {
+                    labels.insert(item.args.trim(), current);
+                }
+                RebaseTodoCommand::Reset => {
+                    current = resolve(&labels, item.args.trim())?;
+                }
+                RebaseTodoCommand::Merge => {
+                    let parents = item.merge_parents();
+                    if parents.len() != 1 {
+                        return Err(Error::from_str("octopus merges are not supported"));
+                    }
+                    let theirs = self.find_commit(resolve(&labels, parents[0])?)?;
+                    let original = match item.commit() {
+                        Some(spec) => Some(self.revparse_single(spec)?.peel_to_commit()?),
+                        None => None,
+                    };
+                    if let Some(ref original) = original {
+                        let unchanged = [current, theirs.id()];
+                        if original.parent_ids().eq(unchanged.iter().cloned()) {
+                            current = original.id();
+                            continue;
+                        }
+                    }
+                    let ours = self.find_commit(current)?;
+                    let mut index = self.merge_commits(&ours, &theirs, opts)?;
+                    if index.has_conflicts() {
+                        return Err(conflict(original.map(|c| c.id()).unwrap_or(theirs.id())));
+                    }
+                    let tree = self.find_tree(index.write_tree_to(self)?)?;
+                    current = match original {
+                        Some(original) => {
+                            let message = String::from_utf8_lossy(original.message_raw_bytes());
+                            self.commit(
+                                None,
+                                &original.author(),
+                                &committer,
+                                &message,
+                                &tree,
+                                &[&ours, &theirs],
+                            )?
+                        }
+                        None => {
+                            let message = format!("Merge branch '{}'\n", parents[0]);
+                            self.commit(
+                                None,
+                                &committer,
+                                &committer,
+                                &message,
+                                &tree,
+                                &[&ours, &theirs],
+                            )?
+                        }
+                    };
+                }
+                RebaseTodoCommand::Drop | RebaseTodoCommand::Noop => {}
+                command => {
+                    let msg = format!("unsupported rebase command '{}'", command);
+                    return Err(Error::from_str(&msg));
+                }
+            }
         }
+        Ok(current)
     }
 
     /// Add a note for an object
@@ -2235,9 +4282,87 @@ impl Repository {
         }
     }
 
-    /// Find a merge base between two commits
-    pub fn merge_base(&self, one: Oid, two: Oid) -> Result<Oid, Error> {
-        let mut raw = raw::git_oid {
+    /// Summarize the blame of every file of `commit` matching `pathspec`.
+    ///
+    /// Each matching file is blamed as of `commit`, and the lines are counted
+    /// per last-changing commit and per author. Pass an empty `pathspec` to
+    /// include every file. Only the limits of `opts` such as `oldest_commit`
+    /// and its flags are used; `newest_commit` is always `commit`.
+    ///
+    /// The files are split across `threads` worker threads, each of which
+    /// opens its own handle on this repository, since a `Repository` cannot
+    /// be shared between threads.
+    pub fn blame_summary<I, T>(
+        &self,
+        commit: &Commit<'_>,
+        pathspec: I,
+        opts: Option<&BlameOptions>,
+        threads: usize,
+    ) -> Result<BlameSummary, Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let pathspec = Pathspec::new(pathspec)?;
+        let mut paths = Vec::new();
+        commit.tree()?.walk(TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                let mut path = root.as_bytes().to_vec();
+                path.extend_from_slice(entry.name_bytes());
+                let path = util::bytes2path(&path).to_path_buf();
+                if pathspec.matches_path(&path, PathspecFlags::DEFAULT) {
+                    paths.push(path);
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+
+        let mut opts = opts.map(|o| o.duplicate()).unwrap_or_default();
+        opts.newest_commit(commit.id());
+        let threads = threads.max(1).min(paths.len());
+        if threads <= 1 {
+            let mut summary = BlameSummary::default();
+            for path in &paths {
+                summary.add_blame(&self.blame_file(path, Some(&mut opts))?);
+            }
+            return Ok(summary);
+        }
+
+        let queue = Arc::new(Mutex::new(paths));
+        let handles = (0..threads)
+            .map(|_| {
+                let queue = queue.clone();
+                let gitdir = self.path().to_path_buf();
+                let mut opts = opts.duplicate();
+                thread::spawn(move || -> Result<BlameSummary, Error> {
+                    let repo = Repository::open(gitdir)?;
+                    let mut summary = BlameSummary::default();
+                    loop {
+                        let path = match queue.lock().unwrap().pop() {
+                            Some(path) => path,
+                            None => return Ok(summary),
+                        };
+                        summary.add_blame(&repo.blame_file(&path, Some(&mut opts))?);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut summary = BlameSummary::default();
+        let mut result = Ok(());
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(part)) => summary.merge(part),
+                Ok(Err(e)) => result = result.and(Err(e)),
+                Err(e) => std::panic::resume_unwind(e),
+            }
+        }
+        result.map(|()| summary)
+    }
+
+    /// Find a merge base between two commits
+    pub fn merge_base(&self, one: Oid, two: Oid) -> Result<Oid, Error> {
+        let mut raw = raw::git_oid {
             id: [0; raw::GIT_OID_RAWSZ],
         };
         unsafe {
@@ -2335,6 +4460,76 @@ impl Repository {
         }
     }
 
+    /// Summarize how references changed between two manifests, e.g. taken
+    /// with `backup::Manifest::from_repo` before and after a fetch.
+    ///
+    /// For every changed reference outside of `refs/tags/` the report lists
+    /// the new and removed commits and whether the update was forced; tags
+    /// are only reported as created, moved or deleted.
+    pub fn ref_delta_report(
+        &self,
+        before: &Manifest,
+        after: &Manifest,
+    ) -> Result<RefDeltaReport, Error> {
+        RefDeltaReport::new(self, before, after)
+    }
+
+    /// Searches the history of `HEAD`, or of the commits pushed to `opts`,
+    /// for commits whose message or author matches `query`.
+    ///
+    /// Commits are returned newest first. Without an index this walks and
+    /// reads the whole history; with `CommitSearchOptions::index` only the
+    /// commits added since the last search are read, and the others are
+    /// looked up in the index.
+    pub fn search_commits(
+        &self,
+        query: &mut CommitQuery<'_>,
+        opts: Option<&CommitSearchOptions>,
+    ) -> Result<Vec<Oid>, Error> {
+        let default = CommitSearchOptions::new();
+        crate::commit_search::search_commits(self, query, opts.unwrap_or(&default))
+    }
+
+    /// Maps each object pointed to by a reference to its decorations:
+    /// `HEAD`, local and remote-tracking branches, tags and other references.
+    ///
+    /// All references are read once. If `peel` is set, annotated tags are
+    /// listed on the commit they point to rather than on the tag object.
+    pub fn decorations(&self, peel: bool) -> Result<Decorations, Error> {
+        Decorations::new(self, peel)
+    }
+
+    /// Returns the paths where the blob `id` appears in the tree of
+    /// `commitish`, such as `HEAD` or a commit id.
+    ///
+    /// The tree is traversed once per call, unless `cache` is given, in which
+    /// case the tree is indexed there on first use and later lookups in the
+    /// same tree don't traverse it again.
+    pub fn find_paths_containing_blob(
+        &self,
+        commitish: &str,
+        id: Oid,
+        cache: Option<&mut BlobPathCache>,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let tree = self.revparse_single(commitish)?.peel_to_tree()?;
+        match cache {
+            Some(cache) => Ok(cache.paths_of(&tree, id)?.to_vec()),
+            None => tree.paths_of(id),
+        }
+    }
+
+    /// Collects what a shell prompt shows about this repository in one
+    /// call: the current branch, its upstream and how far apart they are,
+    /// the counts of staged, unstaged, untracked and conflicted files, the
+    /// number of stashes and the operation in progress.
+    ///
+    /// This takes the cheapest path to each value: the file counts come
+    /// from a single status pass which neither detects renames, looks into
+    /// submodules nor walks untracked directories.
+    pub fn prompt_info(&self) -> Result<PromptInfo, Error> {
+        PromptInfo::new(self)
+    }
+
     /// Read the reflog for the given reference
     ///
     /// If there is no reflog file for the given reference yet, an empty reflog
@@ -2390,7 +4585,16 @@ impl Repository {
     /// Performs a describe operation on the current commit and the worktree.
     /// After performing a describe on HEAD, a status is run and description is
     /// considered to be dirty if there are.
+    ///
+    /// Untracked files count as changes only if
+    /// `DescribeOptions::dirty_untracked` is set.
     pub fn describe(&self, opts: &DescribeOptions) -> Result<Describe<'_>, Error> {
+        if opts.is_dirty_untracked() {
+            let mut status_opts = StatusOptions::new();
+            status_opts.include_untracked(true);
+            let statuses = self.statuses(Some(&mut status_opts))?;
+            return self.describe_with_statuses(opts, &statuses);
+        }
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_describe_workdir(&mut ret, self.raw, opts.raw()));
@@ -2398,6 +4602,43 @@ impl Repository {
         }
     }
 
+    /// Performs a describe operation on the current commit like `describe`,
+    /// but decides whether the worktree is dirty from `statuses` instead of
+    /// running a status again.
+    ///
+    /// This saves tools which need the status anyway, such as build scripts
+    /// stamping a version, from scanning the working directory twice. Ignored
+    /// entries never make the description dirty, and untracked ones only if
+    /// `DescribeOptions::dirty_untracked` is set and `statuses` includes
+    /// them.
+    pub fn describe_with_statuses(
+        &self,
+        opts: &DescribeOptions,
+        statuses: &Statuses<'_>,
+    ) -> Result<Describe<'_>, Error> {
+        let head = self.head()?.peel(ObjectType::Commit)?;
+        let mut ret = ptr::null_mut();
+        let mut describe: Describe<'_> = unsafe {
+            try_call!(raw::git_describe_commit(&mut ret, head.raw(), opts.raw()));
+            Binding::from_raw(ret)
+        };
+        describe.set_dirty(crate::describe::is_dirty(
+            statuses,
+            opts.is_dirty_untracked(),
+        ));
+        Ok(describe)
+    }
+
+    /// Create a combined diff of a merge commit against all of its parents.
+    ///
+    /// This is the diff shown by `git show -c` for a merge, or by
+    /// `git diff --cc` if `dense` is set, which further omits hunks that
+    /// match one of the parents, i.e. take one side's changes unmodified.
+    /// Only files which differ from every parent are included.
+    pub fn combined_diff(&self, commit: &Commit<'_>, dense: bool) -> Result<CombinedDiff, Error> {
+        CombinedDiff::new(self, commit, dense)
+    }
+
     /// Directly run a diff on two blobs.
     ///
     /// Compared to a file, a blob lacks some contextual information. As such, the
@@ -2484,6 +4725,7 @@ impl Repository {
         new_tree: Option<&Tree<'_>>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        let function_context = opts.as_ref().and_then(|o| o.function_context_lines());
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_tree_to_tree(
@@ -2493,7 +4735,10 @@ impl Repository {
                 new_tree.map(|s| s.raw()),
                 opts.map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(ret))
+            let mut diff: Diff<'_> = Binding::from_raw(ret);
+            diff.set_function_context(function_context);
+            diff.set_repo(self);
+            Ok(diff)
         }
     }
 
@@ -2516,6 +4761,7 @@ impl Repository {
         index: Option<&Index>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        let function_context = opts.as_ref().and_then(|o| o.function_context_lines());
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_tree_to_index(
@@ -2525,7 +4771,10 @@ impl Repository {
                 index.map(|s| s.raw()),
                 opts.map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(ret))
+            let mut diff: Diff<'_> = Binding::from_raw(ret);
+            diff.set_function_context(function_context);
+            diff.set_repo(self);
+            Ok(diff)
         }
     }
 
@@ -2539,6 +4788,7 @@ impl Repository {
         new_index: &Index,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        let function_context = opts.as_ref().and_then(|o| o.function_context_lines());
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_index_to_index(
@@ -2548,7 +4798,10 @@ impl Repository {
                 new_index.raw(),
                 opts.map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(ret))
+            let mut diff: Diff<'_> = Binding::from_raw(ret);
+            diff.set_function_context(function_context);
+            diff.set_repo(self);
+            Ok(diff)
         }
     }
 
@@ -2570,6 +4823,7 @@ impl Repository {
         index: Option<&Index>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        let function_context = opts.as_ref().and_then(|o| o.function_context_lines());
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_index_to_workdir(
@@ -2578,7 +4832,10 @@ impl Repository {
                 index.map(|s| s.raw()),
                 opts.map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(ret))
+            let mut diff: Diff<'_> = Binding::from_raw(ret);
+            diff.set_function_context(function_context);
+            diff.set_repo(self);
+            Ok(diff)
         }
     }
 
@@ -2605,6 +4862,7 @@ impl Repository {
         old_tree: Option<&Tree<'_>>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        let function_context = opts.as_ref().and_then(|o| o.function_context_lines());
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_tree_to_workdir(
@@ -2613,7 +4871,10 @@ impl Repository {
                 old_tree.map(|s| s.raw()),
                 opts.map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(ret))
+            let mut diff: Diff<'_> = Binding::from_raw(ret);
+            diff.set_function_context(function_context);
+            diff.set_repo(self);
+            Ok(diff)
         }
     }
 
@@ -2628,6 +4889,7 @@ impl Repository {
         old_tree: Option<&Tree<'_>>,
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
+        let function_context = opts.as_ref().and_then(|o| o.function_context_lines());
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_diff_tree_to_workdir_with_index(
@@ -2636,8 +4898,193 @@ impl Repository {
                 old_tree.map(|s| s.raw()),
                 opts.map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(ret))
+            let mut diff: Diff<'_> = Binding::from_raw(ret);
+            diff.set_function_context(function_context);
+            diff.set_repo(self);
+            Ok(diff)
+        }
+    }
+
+    /// Open and index the repository's `packed-refs` file, for fast lookups
+    /// and prefix iteration over a large number of references.
+    ///
+    /// See `PackedRefsView` for what the view does and does not include.
+    pub fn packed_refs_view(&self) -> Result<PackedRefsView, Error> {
+        PackedRefsView::open(&self.item_path(raw::GIT_REPOSITORY_ITEM_PACKED_REFS)?)
+    }
+
+    /// Choose how durably objects and references of this repository are
+    /// written.
+    ///
+    /// This sets `core.fsyncObjectFiles` in the repository configuration,
+    /// which libgit2 honors for both loose objects and references. The
+    /// setting is read when the object database and reference backends are
+    /// first loaded, so it applies to the `Repository` values opened after
+    /// this call.
+    ///
+    /// `FsyncMode::Buffered` cannot opt a repository out of the
+    /// process-global `opts::fsync_gitdir`, which also covers packs written
+    /// when fetching; that option is off unless enabled, e.g. by
+    /// `opts::Profile::Server`.
+    pub fn set_fsync_mode(&self, mode: FsyncMode) -> Result<(), Error> {
+        let mut config = self.config()?;
+        config.set_bool("core.fsyncObjectFiles", mode == FsyncMode::Durable)
+    }
+
+    /// Returns how durably objects and references of this repository are
+    /// written according to its configuration, ignoring the process-global
+    /// `opts::fsync_gitdir`.
+    pub fn fsync_mode(&self) -> Result<FsyncMode, Error> {
+        match self.config()?.get_bool("core.fsyncObjectFiles") {
+            Ok(true) => Ok(FsyncMode::Durable),
+            Ok(false) => Ok(FsyncMode::Buffered),
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(FsyncMode::Buffered),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Register the function-name pattern used by the diff driver `driver`.
+    ///
+    /// This sets `diff.<driver>.xfuncname` in the repository configuration.
+    /// Paths are assigned to a driver with a `diff=<driver>` entry in
+    /// `.gitattributes`; hunk headers of such paths then show the nearest
+    /// preceding line matching `pattern` (or its first capture group), as
+    /// `git diff` does. Patterns already present in the configuration, and
+    /// libgit2's built-in drivers such as `cpp` or `python`, are honored
+    /// without calling this.
+    ///
+    /// Drivers are loaded once per `Repository` and cached afterwards, so a
+    /// pattern must be registered before the first diff using its driver.
+    pub fn set_diff_driver_xfuncname(&self, driver: &str, pattern: &str) -> Result<(), Error> {
+        let mut config = self.config()?;
+        config.set_str(&format!("diff.{}.xfuncname", driver), pattern)
+    }
+
+    /// Register the diff driver `name` in the repository configuration.
+    ///
+    /// Settings left unset in `driver` are not touched. Like with
+    /// `set_diff_driver_xfuncname`, drivers must be registered before the
+    /// first diff using them.
+    pub fn register_diff_driver(&self, name: &str, driver: &DiffDriver) -> Result<(), Error> {
+        let mut config = self.config()?;
+        for (key, value) in driver.entries() {
+            config.set_str(&format!("diff.{}.{}", name, key), &value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the diff driver assigned to `path` by a
+    /// `diff=<driver>` attribute, if any.
+    pub fn diff_driver(&self, path: &Path) -> Result<Option<String>, Error> {
+        let value = self.get_attr_bytes(path, "diff", AttrCheckFlags::default())?;
+        Ok(match AttrValue::from_bytes(value) {
+            AttrValue::String(s) => Some(s.to_string()),
+            AttrValue::Bytes(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            _ => None,
+        })
+    }
+
+    /// Convert `content`, the contents of `path`, to text with the textconv
+    /// command of the path's diff driver.
+    ///
+    /// Returns `None` if the path's driver has no textconv command.
+    pub fn textconv(&self, path: &Path, content: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let driver = match self.diff_driver(path)? {
+            Some(driver) => driver,
+            None => return Ok(None),
+        };
+        let config = self.config()?;
+        let command = match config.get_string(&format!("diff.{}.textconv", driver)) {
+            Ok(command) => command,
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        self.run_textconv(&command, content).map(Some)
+    }
+
+    /// Generate the patch of the delta at index `idx` of `diff` after
+    /// converting both sides with the textconv command of its diff driver,
+    /// like `git diff` does.
+    ///
+    /// Returns `None` if the delta's path has no textconv command, in which
+    /// case `Patch::from_diff` produces the same patch git would.
+    pub fn textconv_patch(
+        &self,
+        diff: &Diff<'_>,
+        idx: usize,
+        opts: Option<&mut DiffOptions>,
+    ) -> Result<Option<Buf>, Error> {
+        let delta = match diff.get_delta(idx) {
+            Some(delta) => delta,
+            None => return Err(Error::from_str("delta index out of range")),
+        };
+        let (old_file, new_file) = (delta.old_file(), delta.new_file());
+        let path = match new_file.path().or_else(|| old_file.path()) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let old = self.diff_file_content(&old_file)?;
+        let old = match self.textconv(path, &old)? {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let new = self.diff_file_content(&new_file)?;
+        let new = self.textconv(path, &new)?.unwrap_or(new);
+        let mut patch = Patch::from_buffers(&old, old_file.path(), &new, new_file.path(), opts)?;
+        patch.to_buf().map(Some)
+    }
+
+    /// Reads the contents of one side of a delta, from the object database
+    /// or from the working directory.
+    fn diff_file_content(&self, file: &DiffFile<'_>) -> Result<Vec<u8>, Error> {
+        if !file.exists() {
+            return Ok(Vec::new());
         }
+        if !file.id().is_zero() {
+            if let Ok(blob) = self.find_blob(file.id()) {
+                return Ok(blob.content().to_vec());
+            }
+        }
+        let path = match file.path() {
+            Some(path) => self.workdir_or_err()?.join(path),
+            None => return Ok(Vec::new()),
+        };
+        fs::read(&path).map_err(|e| Error::from_str(&format!("{}: {}", path.display(), e)))
+    }
+
+    /// Runs a textconv command on `content`, like git: the command is run by
+    /// the shell with a temporary file holding the contents as argument.
+    fn run_textconv(&self, command: &str, content: &[u8]) -> Result<Vec<u8>, Error> {
+        let io_err =
+            |e: io::Error| Error::from_str(&format!("failed to run textconv '{}': {}", command, e));
+        let tmp = env::temp_dir().join(format!(
+            "git2-textconv-{}-{}",
+            std::process::id(),
+            TEXTCONV_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let output = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+            .and_then(|mut file| file.write_all(content))
+            .and_then(|()| {
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(format!("{} \"$@\"", command))
+                    .arg(command)
+                    .arg(&tmp)
+                    .current_dir(self.workdir().unwrap_or_else(|| self.path()))
+                    .output()
+            });
+        let _ = fs::remove_file(&tmp);
+        let output = output.map_err(io_err)?;
+        if !output.status.success() {
+            return Err(Error::from_str(&format!(
+                "textconv '{}' failed ({})",
+                command, output.status
+            )));
+        }
+        Ok(output.stdout)
     }
 
     /// Create a PackBuilder
@@ -2772,22 +5219,103 @@ impl Repository {
         Ok(ignored == 1)
     }
 
+    /// Append a pattern to an ignore file, creating the file if needed.
+    ///
+    /// Comments and all other lines of the file are preserved. Returns
+    /// `false` without touching the file if it already contains the pattern.
+    /// The change is effective right away for `status_should_ignore`,
+    /// `is_path_ignored` and status listings.
+    pub fn add_ignore_pattern(&self, file: &IgnoreFile, pattern: &str) -> Result<bool, Error> {
+        let pattern = check_ignore_pattern(pattern)?;
+        let path = self.ignore_file_path(file)?;
+        update_ignore_file(&path, |contents| {
+            if contents.lines().any(|l| l == pattern) {
+                return None;
+            }
+            let eol = if contents.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            };
+            let mut new = contents.to_string();
+            if !new.is_empty() && !new.ends_with('\n') {
+                new.push_str(eol);
+            }
+            new.push_str(pattern);
+            new.push_str(eol);
+            Some(new)
+        })
+    }
+
+    /// Remove every line of an ignore file consisting of the given pattern.
+    ///
+    /// All other lines, including comments, are preserved. Returns whether
+    /// the pattern was found.
+    pub fn remove_ignore_pattern(&self, file: &IgnoreFile, pattern: &str) -> Result<bool, Error> {
+        let pattern = check_ignore_pattern(pattern)?;
+        let path = self.ignore_file_path(file)?;
+        update_ignore_file(&path, |contents| {
+            let mut found = false;
+            let mut new = String::new();
+            let mut rest = contents;
+            while !rest.is_empty() {
+                let end = rest.find('\n').map(|i| i + 1).unwrap_or(rest.len());
+                let (line, tail) = rest.split_at(end);
+                if line.trim_end_matches('\n').trim_end_matches('\r') == pattern {
+                    found = true;
+                } else {
+                    new.push_str(line);
+                }
+                rest = tail;
+            }
+            if found {
+                Some(new)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn ignore_file_path(&self, file: &IgnoreFile) -> Result<PathBuf, Error> {
+        match file {
+            IgnoreFile::InfoExclude => Ok(self
+                .item_path(raw::GIT_REPOSITORY_ITEM_INFO)?
+                .join("exclude")),
+            IgnoreFile::Gitignore(dir) => Ok(self.to_workdir_absolute(dir)?.join(".gitignore")),
+        }
+    }
+
     /// Perform a cherrypick
     pub fn cherrypick(
         &self,
         commit: &Commit<'_>,
         options: Option<&mut CherrypickOptions<'_>>,
     ) -> Result<(), Error> {
-        let raw_opts = options.map(|o| o.raw());
+        let append_origin = match options {
+            Some(ref opts) => opts.is_append_origin(),
+            None => false,
+        };
+        let raw_opts = options.map(|o| {
+            let raw = o.raw();
+            o.set_repository(self);
+            raw
+        });
         let ptr_raw_opts = match raw_opts.as_ref() {
             Some(v) => v,
             None => 0 as *const _,
         };
         unsafe {
             try_call!(raw::git_cherrypick(self.raw(), commit.raw(), ptr_raw_opts));
+        }
 
-            Ok(())
+        if append_origin {
+            let path = self.path().join("MERGE_MSG");
+            let io_err = |e: io::Error| Error::from_str(&format!("{}: {}", path.display(), e));
+            let message = fs::read_to_string(&path).map_err(io_err)?;
+            let message = crate::cherrypick::append_origin(&message, commit.id());
+            fs::write(&path, message).map_err(io_err)?;
         }
+        Ok(())
     }
 
     /// Create an index of uncommitted changes, representing the result of
@@ -2813,6 +5341,177 @@ impl Repository {
         }
     }
 
+    /// Commit a cherry-pick whose conflicts have been resolved, like
+    /// `git cherry-pick --continue`.
+    ///
+    /// The commit keeps the author of the picked commit from
+    /// `CHERRY_PICK_HEAD`. Its message is `message` if given, and otherwise
+    /// the one prepared in `MERGE_MSG`, with comments such as the list of
+    /// conflicts stripped. The cherry-pick state is cleaned up afterwards.
+    ///
+    /// Returns an `Unmerged` error, leaving everything as it is, if the index
+    /// still has conflicts.
+    pub fn continue_cherrypick(&self, message: Option<&str>) -> Result<Oid, Error> {
+        let picked = self.sequence_head(ErrorClass::CherryPick)?;
+        let default = String::from_utf8_lossy(picked.message_raw_bytes()).into_owned();
+        let author = picked.author();
+        self.commit_resolved(ErrorClass::CherryPick, message, default, &author)
+    }
+
+    /// Commit a revert whose conflicts have been resolved, like
+    /// `git revert --continue`.
+    ///
+    /// The commit is authored by the repository's signature. Its message is
+    /// `message` if given, and otherwise the one prepared in `MERGE_MSG`,
+    /// with comments and the list of conflicts stripped. The revert state,
+    /// including `REVERT_HEAD`, is cleaned up afterwards.
+    ///
+    /// Returns an `Unmerged` error, leaving everything as it is, if the index
+    /// still has conflicts.
+    pub fn continue_revert(&self, message: Option<&str>) -> Result<Oid, Error> {
+        let reverted = self.sequence_head(ErrorClass::Revert)?;
+        let default = format!(
+            "Revert \"{}\"\n\nThis reverts commit {}.\n",
+            reverted.summary().unwrap_or(""),
+            reverted.id()
+        );
+        let author = self.signature()?;
+        self.commit_resolved(ErrorClass::Revert, message, default, &author)
+    }
+
+    /// Commits the index on top of `HEAD` to conclude a cherry-pick or a
+    /// revert, then cleans up its state.
+    fn commit_resolved(
+        &self,
+        class: ErrorClass,
+        message: Option<&str>,
+        default: String,
+        author: &Signature<'_>,
+    ) -> Result<Oid, Error> {
+        if self.index()?.has_conflicts() {
+            return Err(Error::new(
+                ErrorCode::Unmerged,
+                class,
+                format!(
+                    "cannot continue the {}: conflicts are not resolved",
+                    operation_name(class)
+                ),
+            ));
+        }
+        let tree = self.find_tree(self.index()?.write_tree()?)?;
+        let message = match message {
+            Some(message) => message.to_string(),
+            None => match fs::read_to_string(self.path().join("MERGE_MSG")) {
+                Ok(prepared) => crate::message::split_conflicts(&prepared).0.to_string(),
+                Err(_) => default,
+            },
+        };
+        let message = message_prettify(message, DEFAULT_COMMENT_CHAR)?;
+        let committer = self.signature()?;
+        let head = self.head()?.peel_to_commit()?;
+        let id = self.commit(Some("HEAD"), author, &committer, &message, &tree, &[&head])?;
+        self.cleanup_sequence_state()?;
+        Ok(id)
+    }
+
+    /// Abort a cherry-pick, like `git cherry-pick --abort`.
+    ///
+    /// The files changed by the cherry-pick, including the conflicting
+    /// ones, are restored to their state in `HEAD` in the index and the
+    /// working directory, other changes are kept, and the cherry-pick state
+    /// is cleaned up.
+    pub fn abort_cherrypick(&self) -> Result<(), Error> {
+        self.sequence_head(ErrorClass::CherryPick)?;
+        self.restore_head_paths()
+    }
+
+    /// Abort a revert, like `git revert --abort` for a single commit.
+    ///
+    /// The files changed by the revert are restored to their state in
+    /// `HEAD` in the index and the working directory, other changes are
+    /// kept, and the revert state is cleaned up.
+    pub fn abort_revert(&self) -> Result<(), Error> {
+        self.sequence_head(ErrorClass::Revert)?;
+        self.restore_head_paths()
+    }
+
+    /// Restores the files which differ between `HEAD` and the index to
+    /// their state in `HEAD`, then cleans up the state of the operation.
+    fn restore_head_paths(&self) -> Result<(), Error> {
+        let head = self.head()?.peel_to_commit()?;
+        let diff = self.diff_tree_to_index(Some(&head.tree()?), None, None)?;
+        let mut paths = Vec::new();
+        for delta in diff.deltas() {
+            for file in [delta.old_file(), delta.new_file()].iter() {
+                if let Some(path) = file.path_bytes() {
+                    if !paths.iter().any(|p: &Vec<u8>| p.as_slice() == path) {
+                        paths.push(path.to_vec());
+                    }
+                }
+            }
+        }
+        if !paths.is_empty() {
+            self.reset_default(Some(head.as_object()), paths.iter())?;
+            let mut checkout = CheckoutBuilder::new();
+            checkout.force();
+            for path in paths.iter() {
+                checkout.path(path.as_slice());
+            }
+            self.checkout_head(Some(&mut checkout))?;
+        }
+        self.cleanup_sequence_state()
+    }
+
+    /// Removes the state of the cherry-pick or revert of a single commit.
+    ///
+    /// Unlike `cleanup_state`, this keeps the `sequencer` directory, so a
+    /// multi-commit cherry-pick or revert can go on with its next commit.
+    pub(crate) fn cleanup_sequence_state(&self) -> Result<(), Error> {
+        for name in ["CHERRY_PICK_HEAD", "REVERT_HEAD", "MERGE_MSG", "MERGE_MODE"].iter() {
+            let path = self.path().join(name);
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(Error::from_str(&format!(
+                        "could not remove '{}': {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the commit being cherry-picked or reverted, from
+    /// `CHERRY_PICK_HEAD` or `REVERT_HEAD`, or an error if no such operation
+    /// is in progress.
+    fn sequence_head(&self, class: ErrorClass) -> Result<Commit<'_>, Error> {
+        let (states, head) = match class {
+            ErrorClass::Revert => (
+                [RepositoryState::Revert, RepositoryState::RevertSequence],
+                "REVERT_HEAD",
+            ),
+            _ => (
+                [
+                    RepositoryState::CherryPick,
+                    RepositoryState::CherryPickSequence,
+                ],
+                "CHERRY_PICK_HEAD",
+            ),
+        };
+        if !states.contains(&self.state()) {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                class,
+                format!("no {} in progress", operation_name(class)),
+            ));
+        }
+        let id = self.refname_to_id(head)?;
+        self.find_commit(id)
+    }
+
     /// Find the remote name of a remote-tracking branch
     pub fn branch_remote_name(&self, refname: &str) -> Result<Buf, Error> {
         let refname = CString::new(refname)?;
@@ -2867,13 +5566,275 @@ impl Repository {
         }
     }
 
+    /// Apply a Diff to the provided tree, and return the resulting Index.
+    ///
+    /// If `ApplyOptions::three_way` is set and the diff does not apply
+    /// cleanly, it is instead applied to the blobs it was made from and
+    /// merged into `tree`, like `git apply --3way`. Files whose merge
+    /// conflicts are left in the returned index as conflict entries, with the
+    /// blob the diff was made from at stage 1, the one in `tree` at stage 2
+    /// and the patched one at stage 3. The hunk callback is not consulted for
+    /// this merge.
+    pub fn apply_to_tree(
+        &self,
+        tree: &Tree<'_>,
+        diff: &Diff<'_>,
+        mut options: Option<&mut ApplyOptions<'_>>,
+    ) -> Result<Index, Error> {
+        let three_way = match options {
+            Some(ref opts) => opts.is_three_way(),
+            None => false,
+        };
+        let mut ret = ptr::null_mut();
+        let rc = unsafe {
+            raw::git_apply_to_tree(
+                &mut ret,
+                self.raw,
+                tree.raw(),
+                diff.raw(),
+                match options {
+                    Some(ref mut opts) => opts.raw(),
+                    None => ptr::null(),
+                },
+            )
+        };
+        match crate::call::c_try(rc) {
+            Ok(_) => unsafe { Ok(Binding::from_raw(ret)) },
+            Err(ref e) if three_way && e.code() == ErrorCode::ApplyFail => {
+                self.apply_to_tree_3way(tree, diff, options)
+            }
+            Err(e) => {
+                crate::panic::check();
+                Err(e)
+            }
+        }
+    }
+
+    fn apply_to_tree_3way(
+        &self,
+        tree: &Tree<'_>,
+        diff: &Diff<'_>,
+        mut options: Option<&mut ApplyOptions<'_>>,
+    ) -> Result<Index, Error> {
+        let odb = self.odb()?;
+        let mut base = TreeUpdateBuilder::new();
+        let mut theirs = TreeUpdateBuilder::new();
+        for (idx, delta) in diff.deltas().enumerate() {
+            let (old, new) = (delta.old_file(), delta.new_file());
+            if let Some(ref mut opts) = options {
+                if !opts.accepts_delta(delta) {
+                    continue;
+                }
+            }
+            let old_path = old.path().map(path_to_repo_path).transpose()?;
+            let new_path = new.path().map(path_to_repo_path).transpose()?;
+            if old.mode() == FileMode::Commit || new.mode() == FileMode::Commit {
+                if let (Some(path), true) = (old_path.as_ref(), old.exists()) {
+                    base.upsert(path.clone(), old.id(), old.mode());
+                }
+                if let (Some(path), true) = (new_path.as_ref(), new.exists()) {
+                    theirs.upsert(path.clone(), new.id(), new.mode());
+                }
+                continue;
+            }
+
+            let preimage = if old.exists() {
+                let id = match old.id_abbrev() {
+                    n if n < raw::GIT_OID_HEXSZ => odb.exists_prefix(old.id(), n).ok(),
+                    _ if odb.exists(old.id()) => Some(old.id()),
+                    _ => None,
+                };
+                match id {
+                    Some(id) => Some(self.find_blob(id)?),
+                    None => {
+                        return Err(Error::new(
+                            ErrorCode::ApplyFail,
+                            ErrorClass::Patch,
+                            format!(
+                                "repository lacks the blob {} needed to fall back on \
+                                 a three-way merge",
+                                old.id()
+                            ),
+                        ))
+                    }
+                }
+            } else {
+                None
+            };
+            let postimage = self.apply_to_blob(diff, idx, preimage.as_ref())?;
+
+            match (old_path, preimage.as_ref()) {
+                (Some(path), Some(preimage)) => {
+                    base.upsert(path.clone(), preimage.id(), old.mode());
+                    if postimage.is_none() || new_path.as_ref() != Some(&path) {
+                        theirs.remove(path);
+                    }
+                }
+                _ => {
+                    if let (Some(path), Some(file)) = (new_path.as_ref(), new.path()) {
+                        if tree.get_path(file).is_ok() {
+                            base.remove(path.clone());
+                        }
+                    }
+                }
+            }
+            if let (Some(path), Some(id)) = (new_path, postimage) {
+                theirs.upsert(path, id, new.mode());
+            }
+        }
+        let base = self.find_tree(base.create_updated(self, tree)?)?;
+        let theirs = self.find_tree(theirs.create_updated(self, &base)?)?;
+        self.merge_trees(&base, tree, &theirs, None)
+    }
+
+    /// Apply the delta at index `idx` of `diff` to the blob `base`, and
+    /// return the id of the resulting blob.
+    ///
+    /// `base` is the old side of the delta, or `None` for a delta which
+    /// creates its file. Binary deltas are applied like text ones, provided
+    /// the diff carries their payloads (see `Diff::binary`). Returns `None`
+    /// if the delta deletes its file.
+    pub fn apply_to_blob(
+        &self,
+        diff: &Diff<'_>,
+        idx: usize,
+        base: Option<&Blob<'_>>,
+    ) -> Result<Option<Oid>, Error> {
+        let delta = match diff.get_delta(idx) {
+            Some(delta) => delta,
+            None => return Err(Error::from_str("delta index out of range")),
+        };
+        let old_path = delta.old_file().path().map(|p| p.to_path_buf());
+        let new_path = delta.new_file().path().map(|p| p.to_path_buf());
+        let new_path = match new_path.clone().or_else(|| old_path.clone()) {
+            Some(path) => path,
+            None => return Err(Error::from_str("delta has no path")),
+        };
+
+        let empty = self.find_tree(self.treebuilder(None)?.write()?)?;
+        let mut preimage = TreeUpdateBuilder::new();
+        if let (Some(base), Some(path)) = (base, old_path.as_ref()) {
+            let mode = match delta.old_file().mode() {
+                FileMode::BlobExecutable => FileMode::BlobExecutable,
+                _ => FileMode::Blob,
+            };
+            preimage.upsert(path_to_repo_path(path)?, base.id(), mode);
+        }
+        let preimage = self.find_tree(preimage.create_updated(self, &empty)?)?;
+
+        let mut opts = ApplyOptions::new();
+        opts.delta_callback(|d| match d {
+            Some(d) => {
+                d.old_file().path() == old_path.as_deref()
+                    && d.new_file().path() == Some(new_path.as_path())
+            }
+            None => false,
+        });
+        let index = self.apply_to_tree(&preimage, diff, Some(&mut opts))?;
+        Ok(index.get_path(&new_path, 0).map(|entry| entry.id))
+    }
+
+    /// Apply the hunks of `diff` which apply to `location`, and return the
+    /// ones which do not, like `git apply --reject`.
+    ///
+    /// The hunks of each file are checked against its current contents in
+    /// `location` (the index for `ApplyLocation::Both`), so a hunk which does
+    /// not apply does not keep the rest of its file from being patched. A
+    /// file none of whose hunks apply, or which is deleted by the diff and
+    /// has a rejected hunk, is left alone. Problems which are not about the
+    /// contents of a hunk, such as a file to patch which is missing, are
+    /// still returned as errors and nothing is applied.
+    pub fn apply_with_rejects(
+        &self,
+        diff: &Diff<'_>,
+        location: ApplyLocation,
+    ) -> Result<Vec<RejectedFile>, Error> {
+        let mut accepted: Vec<Option<Vec<bool>>> = Vec::new();
+        let mut rejects = Vec::new();
+        let index = match location {
+            ApplyLocation::WorkDir => None,
+            ApplyLocation::Index | ApplyLocation::Both => Some(self.index()?),
+        };
+        for idx in 0..diff.deltas().len() {
+            let delta = diff.get_delta(idx).unwrap();
+            let patch = match Patch::from_diff(diff, idx)? {
+                Some(ref patch) if delta.flags().is_binary() => {
+                    accepted.push(Some(vec![true; patch.num_hunks()]));
+                    continue;
+                }
+                Some(patch) => patch,
+                None => {
+                    accepted.push(Some(Vec::new()));
+                    continue;
+                }
+            };
+            // A missing preimage is reported by the final apply.
+            let source = match (delta.status(), delta.old_file().path()) {
+                (Delta::Added, _) | (_, None) => Some(Vec::new()),
+                (_, Some(path)) => match index {
+                    Some(ref index) => match index.get_path(path, 0) {
+                        Some(entry) => Some(self.find_blob(entry.id)?.content().to_vec()),
+                        None => None,
+                    },
+                    None => {
+                        let workdir = self.workdir().ok_or_else(|| {
+                            Error::from_str("cannot apply to the workdir of a bare repository")
+                        })?;
+                        fs::read(workdir.join(path)).ok()
+                    }
+                },
+            };
+            let rejected = match source {
+                Some(source) => crate::apply::rejected_hunks(&patch, &source)?,
+                None => Vec::new(),
+            };
+            if rejected.is_empty() {
+                accepted.push(Some(vec![true; patch.num_hunks()]));
+                continue;
+            }
+            rejects.push(RejectedFile::new(&patch, &rejected)?);
+            if delta.status() == Delta::Deleted || rejected.len() == patch.num_hunks() {
+                accepted.push(None);
+            } else {
+                let hunks = (0..patch.num_hunks())
+                    .map(|h| rejected.binary_search(&h).is_err())
+                    .collect();
+                accepted.push(Some(hunks));
+            }
+        }
+
+        let next_delta = Cell::new(0);
+        let next_hunk = Cell::new(0);
+        let mut opts = ApplyOptions::new();
+        opts.delta_callback(|_| {
+            let d = next_delta.get();
+            next_delta.set(d + 1);
+            next_hunk.set(0);
+            accepted[d].is_some()
+        });
+        opts.hunk_callback(|_| {
+            let h = next_hunk.get();
+            next_hunk.set(h + 1);
+            match accepted[next_delta.get() - 1] {
+                Some(ref hunks) => hunks.get(h).cloned().unwrap_or(true),
+                None => false,
+            }
+        });
+        self.apply(diff, location, Some(&mut opts))?;
+        Ok(rejects)
+    }
+
     /// Reverts the given commit, producing changes in the index and working directory.
     pub fn revert(
         &self,
         commit: &Commit<'_>,
         options: Option<&mut RevertOptions<'_>>,
     ) -> Result<(), Error> {
-        let raw_opts = options.map(|o| o.raw());
+        let raw_opts = options.map(|o| {
+            let raw = o.raw();
+            o.set_repository(self);
+            raw
+        });
         let ptr_raw_opts = match raw_opts.as_ref() {
             Some(v) => v,
             None => 0 as *const _,
@@ -3131,14 +6092,160 @@ impl RepositoryInitOptions {
     }
 }
 
+/// Checks that a pattern fits on a single line of an ignore file and trims
+/// surrounding whitespace, which git would not keep either.
+fn check_ignore_pattern(pattern: &str) -> Result<&str, Error> {
+    // Leading and escaped trailing spaces are part of a pattern.
+    let pattern = pattern.trim_end_matches('\n').trim_end_matches('\r');
+    if pattern.is_empty()
+        || pattern.starts_with('#')
+        || pattern.contains('\n')
+        || pattern.contains('\r')
+    {
+        return Err(Error::from_str(&format!(
+            "invalid ignore pattern '{}'",
+            pattern
+        )));
+    }
+    Ok(pattern)
+}
+
+/// Rewrites the ignore file at `path` with the contents `update` returns for
+/// its current contents, if any. The file is locked while it is read, so
+/// concurrent updates are not lost. Returns whether the file was rewritten.
+fn update_ignore_file<F>(path: &Path, update: F) -> Result<bool, Error>
+where
+    F: FnOnce(&str) -> Option<String>,
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            Error::from_str(&format!("could not create '{}': {}", parent.display(), e))
+        })?;
+    }
+    let mut lock = LockFile::acquire(path, &LockOptions::new())?;
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(Error::from_str(&format!(
+                "could not read '{}': {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    let new = match update(&contents) {
+        Some(new) => new,
+        None => return Ok(false),
+    };
+    if new.is_empty() {
+        lock.remove()?;
+        return Ok(true);
+    }
+    lock.write_all(new.as_bytes())
+        .map_err(|e| Error::from_str(&format!("could not write '{}': {}", path.display(), e)))?;
+    lock.commit()?;
+    Ok(true)
+}
+
+/// The state of `Repository::rebase_merges_todo`.
+struct MergesPlan {
+    /// The commits to rebase.
+    range: HashSet<Oid>,
+    /// The commits already added to the todo list.
+    emitted: HashSet<Oid>,
+    /// The labels of the commits other lines start from or merge.
+    labels: HashMap<Oid, String>,
+}
+
+impl MergesPlan {
+    fn label(&self, id: Oid) -> Result<String, Error> {
+        self.labels
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Error::from_str(&format!("no label for {}", id)))
+    }
+}
+
+/// Returns the branch named in the subject of a merge commit, such as
+/// `feature` for "Merge branch 'feature' into main".
+fn branch_label(subject: &[u8]) -> Option<String> {
+    let subject = str::from_utf8(subject).ok()?;
+    let name = subject.split('\'').nth(1)?;
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || c == '#' {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Returns `name`, or `name` with a number appended if it was taken.
+fn unique_label(taken: &mut HashSet<String>, name: &str) -> String {
+    let mut label = name.to_string();
+    let mut n = 1;
+    while taken.contains(&label) {
+        n += 1;
+        label = format!("{}-{}", name, n);
+    }
+    taken.insert(label.clone());
+    label
+}
+
+fn is_regular_file(entry: &IndexEntry) -> bool {
+    entry.mode & 0o170000 == 0o100000
+}
+
+/// Copies `entry` as an entry which is not part of a conflict.
+fn resolved_entry(entry: &IndexEntry) -> IndexEntry {
+    IndexEntry {
+        ctime: entry.ctime,
+        mtime: entry.mtime,
+        dev: entry.dev,
+        ino: entry.ino,
+        mode: entry.mode,
+        uid: entry.uid,
+        gid: entry.gid,
+        file_size: entry.file_size,
+        id: entry.id,
+        flags: entry.flags & !raw::GIT_INDEX_ENTRY_STAGEMASK,
+        flags_extended: entry.flags_extended,
+        path: entry.path.clone(),
+    }
+}
+
+fn operation_name(class: ErrorClass) -> &'static str {
+    match class {
+        ErrorClass::Revert => "revert",
+        _ => "cherry-pick",
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::build::CheckoutBuilder;
+    use crate::build::{CheckoutBuilder, TreeUpdateBuilder};
     use crate::CherrypickOptions;
-    use crate::{ObjectType, Oid, Repository, ResetType, SubmoduleIgnore, SubmoduleUpdate};
+    use crate::{BranchType, IgnoreFile, LockOptions, SetUpstream, Status};
+    use crate::{CommitMessageTemplate, ConflictKind, FileFavor, FileMode, MergeOutcome};
+    use crate::{ConflictStyle, DiffDriver, ErrorCode, FsyncMode};
+    use crate::{MergeDecision, MergeFileInput, NoFastForwardReason};
+    use crate::{
+        MergeOptions, ObjectType, Oid, Repository, ResetType, SubmoduleIgnore, SubmoduleUpdate,
+    };
+    use crate::{RebaseTodoCommand, RebaseTodoItem};
     use std::ffi::OsStr;
     use std::fs;
     use std::path::Path;
+    use std::time::Duration;
     use tempfile::TempDir;
 
     #[test]
@@ -3583,14 +6690,85 @@ mod tests {
     }
 
     #[test]
-    fn smoke_revparse_ext() {
-        let (_td, repo) = graph_repo_init();
+    fn fsync_mode() {
+        let (td, repo) = crate::test::repo_init();
+        assert_eq!(repo.fsync_mode().unwrap(), FsyncMode::Buffered);
+        repo.set_fsync_mode(FsyncMode::Durable).unwrap();
+        assert_eq!(repo.fsync_mode().unwrap(), FsyncMode::Durable);
 
-        {
-            let short_refname = "main";
-            let expected_refname = "refs/heads/main";
-            let (obj, reference) = repo.revparse_ext(short_refname).unwrap();
-            let expected_obj = repo.revparse_single(expected_refname).unwrap();
+        let repo = Repository::open(td.path()).unwrap();
+        assert_eq!(repo.fsync_mode().unwrap(), FsyncMode::Durable);
+        let id = repo.blob(b"durable").unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        repo.reference("refs/heads/durable", head, false, "")
+            .unwrap();
+        assert_eq!(repo.find_blob(id).unwrap().content(), b"durable");
+
+        repo.set_fsync_mode(FsyncMode::Buffered).unwrap();
+        assert_eq!(repo.fsync_mode().unwrap(), FsyncMode::Buffered);
+    }
+
+    #[test]
+    fn orphan_branch_and_empty_commits() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+
+        assert_eq!(
+            repo.create_orphan_branch("main").unwrap_err().code(),
+            ErrorCode::Exists
+        );
+        assert!(repo.create_orphan_branch("bad..name").is_err());
+        repo.create_orphan_branch("gh-pages").unwrap();
+        match repo.head() {
+            Err(e) => assert_eq!(e.code(), ErrorCode::UnbornBranch),
+            Ok(_) => panic!("HEAD should be unborn"),
+        }
+        assert_eq!(
+            repo.find_reference("HEAD").unwrap().symbolic_target(),
+            Some("refs/heads/gh-pages")
+        );
+
+        let mut index = repo.index().unwrap();
+        index.clear().unwrap();
+        index.write().unwrap();
+        assert!(repo.commit_empty(None, &sig, &sig, "root", false).is_err());
+        let root = repo.commit_empty(None, &sig, &sig, "root", true).unwrap();
+        let root = repo.find_commit(root).unwrap();
+        assert_eq!(root.parent_count(), 0);
+        assert_eq!(root.tree().unwrap().len(), 0);
+        assert_eq!(
+            repo.refname_to_id("refs/heads/gh-pages").unwrap(),
+            root.id()
+        );
+        assert_eq!(repo.refname_to_id("refs/heads/main").unwrap(), head);
+
+        assert!(repo
+            .commit_empty(None, &sig, &sig, "marker", false)
+            .is_err());
+        let marker = repo.commit_empty(None, &sig, &sig, "marker", true).unwrap();
+        let marker = repo.find_commit(marker).unwrap();
+        assert_eq!(marker.parent_id(0).unwrap(), root.id());
+        assert_eq!(marker.tree_id(), root.tree_id());
+
+        fs::write(td.path().join("index.html"), "<html>").unwrap();
+        index.add_path(Path::new("index.html")).unwrap();
+        index.write().unwrap();
+        let page = repo.commit_empty(None, &sig, &sig, "page", false).unwrap();
+        let page = repo.find_commit(page).unwrap();
+        assert_eq!(page.parent_id(0).unwrap(), marker.id());
+        assert!(page.tree().unwrap().get_name("index.html").is_some());
+    }
+
+    #[test]
+    fn smoke_revparse_ext() {
+        let (_td, repo) = graph_repo_init();
+
+        {
+            let short_refname = "main";
+            let expected_refname = "refs/heads/main";
+            let (obj, reference) = repo.revparse_ext(short_refname).unwrap();
+            let expected_obj = repo.revparse_single(expected_refname).unwrap();
             assert_eq!(obj.id(), expected_obj.id());
             assert_eq!(reference.unwrap().name().unwrap(), expected_refname);
         }
@@ -3679,8 +6857,14 @@ mod tests {
         repo.reset(commit1.as_object(), ResetType::Hard, None)
             .unwrap();
         let mut cherrypick_opts = CherrypickOptions::new();
+        cherrypick_opts.append_origin(true);
         repo.cherrypick(&commit3, Some(&mut cherrypick_opts))
             .unwrap();
+        let message = fs::read_to_string(repo.path().join("MERGE_MSG")).unwrap();
+        assert_eq!(
+            message,
+            format!("commit 3\n\n(cherry picked from commit {})\n", oid3)
+        );
         let id = repo.index().unwrap().write_tree().unwrap();
         let tree_d = repo.find_tree(id).unwrap();
         let oid4 = repo
@@ -3693,6 +6877,121 @@ mod tests {
         assert!(p2.exists());
     }
 
+    #[test]
+    fn continue_and_abort_cherrypick() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let author = crate::Signature::now("picked", "picked@example.com").unwrap();
+        let commit_f = |content: &str, msg: &str, who: &crate::Signature<'_>| {
+            fs::write(td.path().join("f"), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("f")).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let head = repo.head().unwrap().peel_to_commit().unwrap();
+            repo.commit(Some("HEAD"), who, &sig, msg, &tree, &[&head])
+                .unwrap()
+        };
+        let base = commit_f("base\n", "base", &sig);
+        let theirs = commit_f("theirs\n", "theirs", &author);
+        let base = repo.find_commit(base).unwrap();
+        repo.reset(base.as_object(), ResetType::Hard, None).unwrap();
+        commit_f("ours\n", "ours", &sig);
+        let theirs = repo.find_commit(theirs).unwrap();
+
+        assert!(repo.abort_cherrypick().is_err());
+        repo.cherrypick(&theirs, None).unwrap();
+        assert_eq!(repo.state(), crate::RepositoryState::CherryPick);
+        let err = repo.continue_cherrypick(None).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Unmerged);
+
+        repo.abort_cherrypick().unwrap();
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+        assert!(!repo.index().unwrap().has_conflicts());
+        assert_eq!(fs::read_to_string(td.path().join("f")).unwrap(), "ours\n");
+
+        repo.cherrypick(&theirs, None).unwrap();
+        fs::write(td.path().join("f"), "resolved\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("f")).unwrap();
+        index.write().unwrap();
+        let id = repo.continue_cherrypick(None).unwrap();
+        let commit = repo.find_commit(id).unwrap();
+        assert_eq!(commit.message(), Some("theirs\n"));
+        assert_eq!(commit.author().name(), Some("picked"));
+        assert_eq!(repo.head().unwrap().target(), Some(id));
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+    }
+
+    #[test]
+    fn rebase_merges() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let base = repo.head().unwrap().target().unwrap();
+        let commit = |parents: &[Oid], files: &[&str], msg: &str| {
+            let first = repo.find_commit(parents[0]).unwrap();
+            let mut builder = repo.treebuilder(Some(&first.tree().unwrap())).unwrap();
+            for file in files {
+                let blob = repo.blob(file.as_bytes()).unwrap();
+                builder.insert(file, blob, 0o100644).unwrap();
+            }
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents = parents
+                .iter()
+                .map(|id| repo.find_commit(*id).unwrap())
+                .collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(None, &sig, &sig, msg, &tree, &parents).unwrap()
+        };
+        let upstream = commit(&[base], &["u"], "upstream");
+        let a = commit(&[base], &["a"], "a");
+        let b = commit(&[a], &["b"], "b");
+        let c = commit(&[base], &["c"], "c");
+        let m = commit(&[c, b], &["a", "b"], "Merge branch 'feature'");
+        repo.reference("refs/heads/topic", m, false, "topic")
+            .unwrap();
+
+        let todo = repo.rebase_merges_todo(m, upstream).unwrap();
+        assert_eq!(
+            todo.to_string(),
+            format!(
+                "label onto\n\
+                 reset onto\n\
+                 pick {}\n\
+                 pick {}\n\
+                 label feature\n\
+                 reset onto\n\
+                 pick {}\n\
+                 merge -C {} feature\n",
+                a, b, c, m
+            )
+        );
+
+        // Nothing moves when rebasing onto the original base.
+        let unchanged = repo.apply_rebase_todo(&todo, base, None).unwrap();
+        assert_eq!(unchanged, m);
+
+        let topic = repo.find_reference("refs/heads/topic").unwrap();
+        let branch = repo.reference_to_annotated_commit(&topic).unwrap();
+        let upstream = repo.find_annotated_commit(upstream).unwrap();
+        let id = repo.rebase_merges(&branch, &upstream, None, None).unwrap();
+        assert_eq!(repo.refname_to_id("refs/heads/topic").unwrap(), id);
+        let merge = repo.find_commit(id).unwrap();
+        assert_eq!(merge.message(), Some("Merge branch 'feature'"));
+        assert_eq!(merge.parent_count(), 2);
+        let c2 = merge.parent(0).unwrap();
+        let b2 = merge.parent(1).unwrap();
+        let a2 = b2.parent(0).unwrap();
+        assert_eq!(c2.message(), Some("c"));
+        assert_eq!(a2.message(), Some("a"));
+        assert_eq!(c2.parent_id(0).unwrap(), upstream.id());
+        assert_eq!(a2.parent_id(0).unwrap(), upstream.id());
+        let tree = merge.tree().unwrap();
+        for file in ["a", "b", "c", "u"].iter() {
+            assert!(tree.get_name(file).is_some());
+        }
+    }
+
     #[test]
     fn smoke_revert() {
         let (_td, repo) = crate::test::repo_init();
@@ -3827,4 +7126,980 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn smoke_status_files() {
+        let (td, repo) = crate::test::repo_init();
+        crate::test::commit(&repo);
+        fs::write(td.path().join("foo"), "changed").unwrap();
+        fs::write(td.path().join("bar"), "new").unwrap();
+        fs::create_dir(td.path().join("dir")).unwrap();
+        fs::write(td.path().join("dir/a"), "a").unwrap();
+        fs::write(td.path().join("dir/b"), "b").unwrap();
+
+        let paths = [Path::new("bar"), Path::new("dir/b"), Path::new("foo")];
+        let statuses = repo.status_files(&paths).unwrap();
+        assert_eq!(
+            statuses,
+            vec![Status::WT_NEW, Status::WT_NEW, Status::WT_MODIFIED]
+        );
+        for (path, status) in paths.iter().zip(&statuses) {
+            assert_eq!(repo.status_file(path).unwrap(), *status);
+        }
+        assert!(repo.status_files(&[]).unwrap().is_empty());
+
+        let err = repo.status_files(&[Path::new("missing")]).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        let err = repo.status_files(&[Path::new("dir")]).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Ambiguous);
+    }
+
+    #[test]
+    fn smoke_commit_template() {
+        let (td, repo) = crate::test::repo_init();
+        assert_eq!(repo.commit_template().unwrap(), None);
+
+        let mut config = repo.config().unwrap();
+        fs::write(td.path().join("template"), "subject\n\n# body\n").unwrap();
+        config.set_str("commit.template", "template").unwrap();
+        assert_eq!(
+            repo.commit_template().unwrap().as_deref(),
+            Some("subject\n\n# body\n")
+        );
+
+        let blob = repo.blob(b"from a blob\n").unwrap();
+        config
+            .set_str("commit.template", &blob.to_string())
+            .unwrap();
+        assert_eq!(
+            repo.commit_template().unwrap().as_deref(),
+            Some("from a blob\n")
+        );
+
+        config.set_str("commit.template", "missing").unwrap();
+        assert!(repo.commit_template().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn smoke_prepare_commit_msg() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (td, repo) = crate::test::repo_init();
+        assert_eq!(
+            repo.prepare_commit_msg("msg\n", None, None).unwrap(),
+            "msg\n"
+        );
+
+        let hooks = td.path().join("hooks");
+        fs::create_dir(&hooks).unwrap();
+        let hook = hooks.join("prepare-commit-msg");
+        fs::write(&hook, "#!/bin/sh\necho \"source: $2\" >> \"$1\"\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        repo.config()
+            .unwrap()
+            .set_str("core.hooksPath", "hooks")
+            .unwrap();
+        assert_eq!(
+            repo.prepare_commit_msg("msg\n", Some("message"), None)
+                .unwrap(),
+            "msg\nsource: message\n"
+        );
+
+        fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        assert!(repo.prepare_commit_msg("msg\n", None, None).is_err());
+    }
+
+    #[test]
+    fn smoke_rebase_todo() {
+        let (_td, repo) = crate::test::repo_init();
+        let err = repo.rebase_todo().unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+
+        let dir = repo.path().join("rebase-merge");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("done"), "pick 1111111 one\n").unwrap();
+        fs::write(
+            dir.join("git-rebase-todo"),
+            "pick 2222222 two\npick 3333333 three\n\n# Rebase 0000000..3333333\n",
+        )
+        .unwrap();
+        assert_eq!(repo.rebase_done().unwrap().items.len(), 1);
+
+        let mut todo = repo.rebase_todo().unwrap();
+        assert_eq!(todo.items.len(), 2);
+        todo.items[0].command = RebaseTodoCommand::Reword;
+        todo.items
+            .insert(1, RebaseTodoItem::new(RebaseTodoCommand::Exec, "make"));
+        repo.set_rebase_todo(&todo).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("git-rebase-todo")).unwrap(),
+            "reword 2222222 two\nexec make\npick 3333333 three\n"
+        );
+        assert_eq!(repo.rebase_todo().unwrap(), todo);
+
+        assert!(repo.set_sequencer_todo(&todo).is_err());
+    }
+
+    #[test]
+    fn smoke_lock_files() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.refname_to_id("HEAD").unwrap();
+
+        let opts = LockOptions::new();
+        let lock = repo.lock_index(&opts).unwrap();
+        assert_eq!(lock.path(), repo.path().join("index"));
+        let mut index = repo.index().unwrap();
+        assert_eq!(index.write().unwrap_err().code(), ErrorCode::Locked);
+        drop(lock);
+        index.write().unwrap();
+
+        let lock = repo.lock_reference("refs/heads/main", &opts).unwrap();
+        assert!(repo.reference("refs/heads/main", head, true, "").is_err());
+        drop(lock);
+        repo.reference("refs/heads/main", head, true, "").unwrap();
+
+        let lock = repo.lock_config(&opts).unwrap();
+        let err = repo
+            .lock_config(LockOptions::new().timeout(Duration::from_millis(10)))
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), ErrorCode::Locked);
+        drop(lock);
+
+        assert!(repo.lock_reference("refs/heads/..", &opts).is_err());
+    }
+
+    #[test]
+    fn smoke_untracked_entries() {
+        let (td, repo) = crate::test::repo_init();
+        crate::test::commit(&repo);
+        fs::create_dir_all(td.path().join("a/b")).unwrap();
+        fs::create_dir_all(td.path().join("tracked")).unwrap();
+        fs::write(td.path().join("a/one"), "").unwrap();
+        fs::write(td.path().join("a/b/two"), "").unwrap();
+        fs::write(td.path().join("a/b/ignored.o"), "").unwrap();
+        fs::write(td.path().join("tracked/three"), "").unwrap();
+        fs::write(td.path().join("tracked/new"), "").unwrap();
+        fs::write(td.path().join("top"), "").unwrap();
+        fs::write(td.path().join(".gitignore"), "*.o\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked/three")).unwrap();
+        index.write().unwrap();
+
+        let entries = repo.untracked_entries(None).unwrap();
+        let summary = entries
+            .iter()
+            .map(|e| (e.path().unwrap(), e.is_dir(), e.file_count()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (".gitignore", false, 1),
+                ("a/", true, 2),
+                ("top", false, 1),
+                ("tracked/new", false, 1),
+            ]
+        );
+
+        let mut opts = crate::StatusOptions::new();
+        opts.pathspec("a");
+        let entries = repo.untracked_entries(Some(&mut opts)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_count(), 2);
+    }
+
+    #[test]
+    fn smoke_repo_relative_paths() {
+        let (td, repo) = crate::test::repo_init();
+        let workdir = repo.workdir().unwrap();
+        assert_eq!(
+            repo.to_repo_relative(&workdir.join("a").join("b.txt"))
+                .unwrap(),
+            Path::new("a/b.txt")
+        );
+        assert_eq!(
+            repo.to_repo_relative(&workdir.join("a/../c")).unwrap(),
+            Path::new("c")
+        );
+        assert!(repo.to_repo_relative(&workdir.join("../c")).is_err());
+        assert!(repo
+            .to_repo_relative(&td.path().parent().unwrap().join("elsewhere"))
+            .is_err());
+
+        assert_eq!(
+            repo.to_workdir_absolute(Path::new("a/b.txt")).unwrap(),
+            workdir.join("a").join("b.txt")
+        );
+        assert!(repo.to_workdir_absolute(Path::new("../x")).is_err());
+        assert!(repo.to_workdir_absolute(&workdir.join("x")).is_err());
+
+        // A case-insensitive file system may hand out the working directory
+        // with different casing.
+        let upper = workdir
+            .to_str()
+            .unwrap()
+            .trim_end_matches('/')
+            .to_uppercase();
+        let path = Path::new(&upper).join("f");
+        repo.config()
+            .unwrap()
+            .set_bool("core.ignorecase", true)
+            .unwrap();
+        assert_eq!(repo.to_repo_relative(&path).unwrap(), Path::new("f"));
+
+        #[cfg(unix)]
+        {
+            let dir = TempDir::new().unwrap();
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(workdir, &link).unwrap();
+            assert_eq!(
+                repo.to_repo_relative(&link.join("a/b")).unwrap(),
+                Path::new("a/b")
+            );
+        }
+
+        let bare = TempDir::new().unwrap();
+        let bare = Repository::init_bare(bare.path()).unwrap();
+        let err = bare.to_repo_relative(Path::new("/x")).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::BareRepo);
+    }
+
+    #[test]
+    fn smoke_ignore_patterns() {
+        let (td, repo) = crate::test::repo_init();
+        let exclude = IgnoreFile::InfoExclude;
+        assert!(!repo.status_should_ignore(Path::new("a.log")).unwrap());
+        assert!(repo.add_ignore_pattern(&exclude, "*.log").unwrap());
+        assert!(repo.status_should_ignore(Path::new("a.log")).unwrap());
+        assert!(!repo.add_ignore_pattern(&exclude, "*.log\n").unwrap());
+        assert!(repo.remove_ignore_pattern(&exclude, "*.log").unwrap());
+        assert!(!repo.status_should_ignore(Path::new("a.log")).unwrap());
+        assert!(!repo.remove_ignore_pattern(&exclude, "*.log").unwrap());
+
+        fs::create_dir(td.path().join("sub")).unwrap();
+        let gitignore = td.path().join("sub/.gitignore");
+        fs::write(&gitignore, "# build output\ntarget").unwrap();
+        let sub = IgnoreFile::Gitignore(Path::new("sub").to_path_buf());
+        assert!(repo.add_ignore_pattern(&sub, "build/").unwrap());
+        assert_eq!(
+            fs::read_to_string(&gitignore).unwrap(),
+            "# build output\ntarget\nbuild/\n"
+        );
+        assert!(repo.status_should_ignore(Path::new("sub/build/x")).unwrap());
+        assert!(!repo.status_should_ignore(Path::new("build/x")).unwrap());
+        assert!(repo.remove_ignore_pattern(&sub, "target").unwrap());
+        assert_eq!(
+            fs::read_to_string(&gitignore).unwrap(),
+            "# build output\nbuild/\n"
+        );
+        // whitespace at the start of a pattern is part of it
+        assert!(repo.add_ignore_pattern(&sub, " build/").unwrap());
+        assert!(repo.remove_ignore_pattern(&sub, "build/").unwrap());
+        assert_eq!(
+            fs::read_to_string(&gitignore).unwrap(),
+            "# build output\n build/\n"
+        );
+
+        assert!(repo.add_ignore_pattern(&exclude, "# comment").is_err());
+        assert!(repo.add_ignore_pattern(&exclude, "a\nb").is_err());
+        let outside = IgnoreFile::Gitignore(Path::new("..").to_path_buf());
+        assert!(repo.add_ignore_pattern(&outside, "x").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn smoke_diff_drivers() {
+        let (td, repo) = crate::test::repo_init();
+        let mut upper = DiffDriver::new();
+        upper.textconv("tr a-z A-Z <");
+        repo.register_diff_driver("upper", &upper).unwrap();
+        let mut bin = DiffDriver::new();
+        bin.binary(true);
+        repo.register_diff_driver("bin", &bin).unwrap();
+        let config = repo.config().unwrap();
+        assert_eq!(
+            config.get_string("diff.upper.textconv").unwrap(),
+            "tr a-z A-Z <"
+        );
+        assert!(config.get_bool("diff.bin.binary").unwrap());
+        assert!(config.get_string("diff.bin.xfuncname").is_err());
+
+        fs::write(
+            td.path().join(".gitattributes"),
+            "*.up diff=upper\n*.dat diff=bin\n",
+        )
+        .unwrap();
+        assert_eq!(
+            repo.diff_driver(Path::new("a.up")).unwrap().as_deref(),
+            Some("upper")
+        );
+        assert_eq!(repo.diff_driver(Path::new("a.txt")).unwrap(), None);
+        assert_eq!(
+            repo.textconv(Path::new("a.up"), b"abc\n").unwrap(),
+            Some(b"ABC\n".to_vec())
+        );
+        assert_eq!(repo.textconv(Path::new("a.txt"), b"abc\n").unwrap(), None);
+
+        fs::write(td.path().join("a.up"), "one\n").unwrap();
+        fs::write(td.path().join("a.dat"), "text\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a.up")).unwrap();
+        index.add_path(Path::new("a.dat")).unwrap();
+        index.write().unwrap();
+        fs::write(td.path().join("a.up"), "two\n").unwrap();
+        fs::write(td.path().join("a.dat"), "more text\n").unwrap();
+
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        let paths = diff
+            .deltas()
+            .map(|d| d.new_file().path().unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![Path::new("a.dat"), Path::new("a.up")]);
+        let patch = crate::Patch::from_diff(&diff, 0).unwrap().unwrap();
+        assert!(patch.delta().flags().is_binary());
+        assert!(repo.textconv_patch(&diff, 0, None).unwrap().is_none());
+
+        let buf = repo.textconv_patch(&diff, 1, None).unwrap().unwrap();
+        let text = buf.as_str().unwrap();
+        assert!(text.contains("-ONE\n"), "{}", text);
+        assert!(text.contains("+TWO\n"), "{}", text);
+    }
+
+    #[test]
+    fn seed_from() {
+        let (_td, template) = crate::test::repo_init();
+        let head = template.head().unwrap().target().unwrap();
+        let commit = template.find_commit(head).unwrap();
+        template.branch("feature", &commit, false).unwrap();
+        template
+            .tag_lightweight("v1", commit.as_object(), false)
+            .unwrap();
+
+        let td = TempDir::new().unwrap();
+        let repo = Repository::init_bare(td.path()).unwrap();
+        assert_eq!(repo.seed_from(&template, "refs/heads/*").unwrap(), 2);
+        assert_eq!(repo.seed_from(&template, "refs/heads/*").unwrap(), 0);
+        assert!(repo.find_reference("refs/tags/v1").is_err());
+        let feature = repo.find_reference("refs/heads/feature").unwrap();
+        assert_eq!(feature.target(), Some(head));
+        assert!(repo.find_commit(head).is_ok());
+        let alternates = td.path().join("objects/info/alternates");
+        let contents = fs::read_to_string(&alternates).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+
+        // A tag of a tree which, like its blob, only the template has.
+        let blob = template.blob(b"tagged").unwrap();
+        let mut builder = template.treebuilder(None).unwrap();
+        builder.insert("file", blob, 0o100644).unwrap();
+        let tree = builder.write().unwrap();
+        let sig = template.signature().unwrap();
+        let tree_object = repo.find_object(tree, None).unwrap();
+        let tag = repo.tag("tree", &tree_object, &sig, "", false).unwrap();
+
+        repo.dissociate().unwrap();
+        assert!(!alternates.exists());
+        let repo = Repository::open(td.path()).unwrap();
+        assert!(repo.find_commit(head).is_ok());
+        assert!(repo.find_tree(commit.tree_id()).is_ok());
+        assert_eq!(repo.find_tag(tag).unwrap().target_id(), tree);
+        assert!(repo.find_tree(tree).is_ok());
+        assert!(repo.find_blob(blob).is_ok());
+    }
+
+    #[test]
+    fn guess_default_branch() {
+        let (_td, repo) = crate::test::repo_init();
+        repo.config()
+            .unwrap()
+            .set_str("init.defaultBranch", "trunk")
+            .unwrap();
+        assert_eq!(repo.guess_default_branch().unwrap().name(), "trunk");
+
+        let head = repo.head().unwrap().target().unwrap();
+        repo.reference("refs/remotes/origin/develop", head, false, "")
+            .unwrap();
+        repo.reference_symbolic(
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/develop",
+            false,
+            "",
+        )
+        .unwrap();
+        let name = repo.guess_default_branch().unwrap();
+        assert_eq!(name.refname(), "refs/heads/develop");
+    }
+
+    #[test]
+    fn merge_commits_with_base() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let commit = |f: &str, g: &str, parents: &[Oid]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder
+                .insert("f", repo.blob(f.as_bytes()).unwrap(), 0o100644)
+                .unwrap();
+            builder
+                .insert("g", repo.blob(g.as_bytes()).unwrap(), 0o100644)
+                .unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents = parents
+                .iter()
+                .map(|id| repo.find_commit(*id).unwrap())
+                .collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(None, &sig, &sig, "m", &tree, &parents).unwrap()
+        };
+        // A criss-cross history, where a1 and c1 are both merge bases of a3
+        // and c3.
+        let base = commit("1\n2\n3\n", "x\n", &[]);
+        let a1 = commit("1a\n2\n3\n", "x\n", &[base]);
+        let c1 = commit("1\n2\n3c\n", "x\n", &[base]);
+        let a2 = commit("1a\n2\n3c\n", "x\n", &[a1, c1]);
+        let c2 = commit("1a\n2\n3c\n", "x\n", &[c1, a1]);
+        let a3 = commit("1a\n2a\n3c\n", "x\n", &[a2]);
+        let c3 = commit("1a\n2\n3c\n", "y\n", &[c2]);
+        let a3 = repo.find_commit(a3).unwrap();
+        let c3 = repo.find_commit(c3).unwrap();
+
+        let merge = repo.merge_commits_with_base(&a3, &c3, None).unwrap();
+        assert!(merge.is_virtual());
+        assert_eq!(merge.base_commit(), None);
+        assert_eq!(merge.depth(), 1);
+        let mut bases = merge.bases().to_vec();
+        bases.sort();
+        let mut expected = vec![a1, c1];
+        expected.sort();
+        assert_eq!(bases, expected);
+        let base_tree = repo.find_tree(merge.base_tree().unwrap()).unwrap();
+        let f = base_tree.get_name("f").unwrap().id();
+        assert_eq!(repo.find_blob(f).unwrap().content(), b"1a\n2\n3c\n");
+        assert!(!merge.index().has_conflicts());
+        let f = merge.index().get_path(Path::new("f"), 0).unwrap();
+        assert_eq!(repo.find_blob(f.id).unwrap().content(), b"1a\n2a\n3c\n");
+
+        let mut opts = MergeOptions::new();
+        opts.no_recursive(true);
+        let merge = repo.merge_commits_with_base(&a3, &c3, Some(&opts)).unwrap();
+        assert!(!merge.is_virtual());
+        assert_eq!(merge.depth(), 0);
+        assert!(merge.base_commit() == Some(a1) || merge.base_commit() == Some(c1));
+    }
+
+    #[test]
+    fn merge_decision() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let ahead = repo
+            .commit(None, &sig, &sig, "ahead", &tree, &[&head])
+            .unwrap();
+        let ahead = repo.find_annotated_commit(ahead).unwrap();
+        let old = repo.find_annotated_commit(head.id()).unwrap();
+        let orphan = repo.commit(None, &sig, &sig, "orphan", &tree, &[]).unwrap();
+        let orphan = repo.find_annotated_commit(orphan).unwrap();
+
+        assert_eq!(repo.merge_decision(&old).unwrap(), MergeDecision::UpToDate);
+        assert_eq!(
+            repo.merge_decision(&ahead).unwrap(),
+            MergeDecision::FastForward(ahead.id())
+        );
+        assert_eq!(repo.no_fast_forward_reason(&[&ahead]).unwrap(), None);
+        assert_eq!(
+            repo.no_fast_forward_reason(&[&orphan]).unwrap(),
+            Some(NoFastForwardReason::NoMergeBase)
+        );
+
+        let mut config = repo.config().unwrap();
+        config.set_str("merge.ff", "false").unwrap();
+        assert_eq!(
+            repo.merge_decision(&ahead).unwrap(),
+            MergeDecision::Merge(NoFastForwardReason::Disabled)
+        );
+        config.set_str("pull.ff", "only").unwrap();
+        assert_eq!(
+            repo.pull_decision(&ahead).unwrap(),
+            MergeDecision::FastForward(ahead.id())
+        );
+        assert_eq!(
+            repo.pull_decision(&orphan).unwrap(),
+            MergeDecision::Abort(NoFastForwardReason::NoMergeBase)
+        );
+
+        let side = repo
+            .commit(Some("HEAD"), &sig, &sig, "side", &tree, &[&head])
+            .unwrap();
+        assert_ne!(side, ahead.id());
+        assert_eq!(
+            repo.merge_decision(&ahead).unwrap(),
+            MergeDecision::Merge(NoFastForwardReason::Diverged)
+        );
+    }
+
+    #[test]
+    fn merge_file_stream() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = "a\nb\nc\n".repeat(1000);
+        let ours = base.replacen("a\n", "A\n", 1);
+        let theirs = format!("{}d\n", base);
+
+        let mut out = Vec::new();
+        let status = repo
+            .merge_file_stream(
+                Some(MergeFileInput::blob(repo.blob(base.as_bytes()).unwrap())),
+                MergeFileInput::reader(ours.as_bytes()),
+                MergeFileInput::reader(theirs.as_bytes()),
+                None,
+                &mut out,
+            )
+            .unwrap();
+        assert!(status.is_automergeable());
+        assert_eq!(status.mode(), 0o100644);
+        assert_eq!(out, format!("{}d\n", ours).into_bytes());
+
+        let mut ours = MergeFileInput::reader("x\n".as_bytes());
+        ours.path("file.txt");
+        let mut theirs = MergeFileInput::reader("y\n".as_bytes());
+        theirs.path("file.txt");
+        let mut out = Vec::new();
+        let status = repo
+            .merge_file_stream(None, ours, theirs, None, &mut out)
+            .unwrap();
+        assert!(!status.is_automergeable());
+        assert_eq!(status.path(), Some(Path::new("file.txt")));
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("<<<<<<< file.txt\nx\n=======\ny\n>>>>>>> file.txt\n"));
+
+        let mut ours = MergeFileInput::reader("x\n".as_bytes());
+        ours.path("file.txt");
+        let mut out = Vec::new();
+        let mut opts = MergeOptions::new();
+        opts.file_favor(crate::FileFavor::Theirs);
+        let status = repo
+            .merge_file_stream(
+                None,
+                ours,
+                MergeFileInput::reader("y\n".as_bytes()),
+                Some(&opts),
+                &mut out,
+            )
+            .unwrap();
+        assert!(status.is_automergeable());
+        assert_eq!(out, b"y\n");
+
+        let mut opts = MergeOptions::new();
+        opts.conflict_style(ConflictStyle::Diff3)
+            .ancestor_label("base")
+            .our_label("HEAD")
+            .their_label("topic")
+            .marker_size(3);
+        let mut out = Vec::new();
+        repo.merge_file_stream(
+            Some(MergeFileInput::reader("a\n".as_bytes())),
+            MergeFileInput::reader("x\n".as_bytes()),
+            MergeFileInput::reader("y\n".as_bytes()),
+            Some(&opts),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<<< HEAD\nx\n||| base\na\n===\ny\n>>> topic\n"
+        );
+    }
+
+    #[test]
+    fn merge_decision_for_ref() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let tree = head.tree().unwrap();
+        let ahead = repo
+            .commit(Some("HEAD"), &sig, &sig, "ahead", &tree, &[&head])
+            .unwrap();
+        let ahead = repo.find_annotated_commit(ahead).unwrap();
+        let behind = repo
+            .branch("behind", &head, false)
+            .unwrap()
+            .into_reference();
+        let side = repo
+            .commit(None, &sig, &sig, "side", &tree, &[&head])
+            .unwrap();
+        let side = repo.branch("side", &repo.find_commit(side).unwrap(), false);
+        let side = side.unwrap().into_reference();
+
+        let bare = Repository::open_bare(repo.path()).unwrap();
+        let behind = bare.find_reference(behind.name().unwrap()).unwrap();
+        let side = bare.find_reference(side.name().unwrap()).unwrap();
+        let (analysis, _) = bare.merge_analysis_for_ref(&behind, &[&ahead]).unwrap();
+        assert!(analysis.is_fast_forward());
+        assert_eq!(
+            bare.merge_decision_for_ref(&behind, &ahead).unwrap(),
+            MergeDecision::FastForward(ahead.id())
+        );
+        assert_eq!(
+            bare.no_fast_forward_reason_for_ref(&side, &[&ahead])
+                .unwrap(),
+            Some(NoFastForwardReason::Diverged)
+        );
+        assert_eq!(
+            bare.merge_decision_for_ref(&side, &ahead).unwrap(),
+            MergeDecision::Merge(NoFastForwardReason::Diverged)
+        );
+        let head = bare.find_reference("refs/heads/main").unwrap();
+        assert_eq!(
+            bare.merge_decision_for_ref(&head, &ahead).unwrap(),
+            MergeDecision::UpToDate
+        );
+    }
+
+    #[test]
+    fn merge_and_commit() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let commit_file = |branch: &str, path: &str, contents: &str| {
+            let parent = repo
+                .find_reference(branch)
+                .unwrap()
+                .peel_to_commit()
+                .unwrap();
+            let mut tb = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            let blob = repo.blob(contents.as_bytes()).unwrap();
+            tb.insert(path, blob, 0o100644).unwrap();
+            let tree = repo.find_tree(tb.write().unwrap()).unwrap();
+            let id = repo
+                .commit(Some(branch), &sig, &sig, path, &tree, &[&parent])
+                .unwrap();
+            repo.checkout_head(Some(CheckoutBuilder::new().force()))
+                .unwrap();
+            id
+        };
+        let opts = MergeOptions::new();
+        let template = CommitMessageTemplate::new();
+
+        repo.branch("topic", &head, false).unwrap();
+        let topic = commit_file("refs/heads/topic", "a", "a\n");
+        let topic_ref = repo.find_reference("refs/heads/topic").unwrap();
+        let theirs = repo.reference_to_annotated_commit(&topic_ref).unwrap();
+        assert_eq!(
+            repo.merge_and_commit(&theirs, &opts, &template).unwrap(),
+            MergeOutcome::FastForward(topic)
+        );
+        assert_eq!(repo.head().unwrap().target(), Some(topic));
+        assert!(td.path().join("a").exists());
+        assert_eq!(
+            repo.merge_and_commit(&theirs, &opts, &template).unwrap(),
+            MergeOutcome::UpToDate
+        );
+
+        commit_file("refs/heads/topic", "b", "b\n");
+        commit_file("HEAD", "c", "c\n");
+        let topic_ref = repo.find_reference("refs/heads/topic").unwrap();
+        let theirs = repo.reference_to_annotated_commit(&topic_ref).unwrap();
+        let mut template = CommitMessageTemplate::new();
+        template.trailer("Signed-off-by", "foo <bar>");
+        let merge = match repo.merge_and_commit(&theirs, &opts, &template).unwrap() {
+            MergeOutcome::Merged(id) => repo.find_commit(id).unwrap(),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        assert_eq!(merge.parent_count(), 2);
+        assert_eq!(
+            merge.message(),
+            Some("Merge branch 'topic'\n\nSigned-off-by: foo <bar>\n")
+        );
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+        assert!(td.path().join("b").exists());
+
+        commit_file("refs/heads/topic", "a", "theirs\n");
+        commit_file("HEAD", "a", "ours\n");
+        let topic_ref = repo.find_reference("refs/heads/topic").unwrap();
+        let theirs = repo.reference_to_annotated_commit(&topic_ref).unwrap();
+        assert_eq!(
+            repo.merge_and_commit(&theirs, &opts, &template).unwrap(),
+            MergeOutcome::Conflicts(vec![Path::new("a").to_path_buf()])
+        );
+        assert_eq!(repo.state(), crate::RepositoryState::Merge);
+        let message = fs::read_to_string(repo.path().join("MERGE_MSG")).unwrap();
+        assert_eq!(
+            message,
+            "Merge branch 'topic'\n\nSigned-off-by: foo <bar>\n\n# Conflicts:\n#\ta\n"
+        );
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.reset(head.as_object(), ResetType::Hard, None).unwrap();
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+
+        repo.config().unwrap().set_str("merge.ff", "only").unwrap();
+        let err = repo
+            .merge_and_commit(&theirs, &opts, &template)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::NotFastForward);
+    }
+
+    #[test]
+    fn merge_commits_to_tree() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let change = |parent: Oid, changes: &[(&str, Option<&str>)]| {
+            let parent = repo.find_commit(parent).unwrap();
+            let mut tb = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            for &(path, contents) in changes {
+                match contents {
+                    Some(contents) => {
+                        let blob = repo.blob(contents.as_bytes()).unwrap();
+                        tb.insert(path, blob, 0o100644).unwrap();
+                    }
+                    None => tb.remove(path).unwrap(),
+                }
+            }
+            let tree = repo.find_tree(tb.write().unwrap()).unwrap();
+            repo.commit(None, &sig, &sig, "change", &tree, &[&parent])
+                .unwrap()
+        };
+        let contents = |tree: Oid, path: &str| {
+            let tree = repo.find_tree(tree).unwrap();
+            let entry = tree.get_path(Path::new(path)).unwrap();
+            let blob = repo.find_blob(entry.id()).unwrap();
+            String::from_utf8(blob.content().to_vec()).unwrap()
+        };
+
+        let root = repo.head().unwrap().target().unwrap();
+        let base = change(root, &[("a", Some("1\n2\n3\n")), ("b", Some("b\n"))]);
+        let ours = change(base, &[("a", Some("1\nours\n3\n")), ("b", Some("ours\n"))]);
+        let theirs = change(
+            base,
+            &[
+                ("a", Some("1\ntheirs\n3\n")),
+                ("b", None),
+                ("c", Some("c\n")),
+            ],
+        );
+        let ours = repo.find_commit(ours).unwrap();
+        let theirs = repo.find_commit(theirs).unwrap();
+
+        let (tree, conflicts) = repo.merge_commits_to_tree(&ours, &theirs, None).unwrap();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].path(), Path::new("a"));
+        assert_eq!(conflicts[0].kind(), ConflictKind::BothModified);
+        let their_a = Oid::hash_object(ObjectType::Blob, b"1\ntheirs\n3\n").unwrap();
+        assert_eq!(conflicts[0].their().unwrap().id, their_a);
+        assert_eq!(conflicts[1].path(), Path::new("b"));
+        assert_eq!(conflicts[1].kind(), ConflictKind::DeletedByThem);
+        assert!(conflicts[1].their().is_none());
+        assert!(contents(tree, "a").contains("<<<<<<< a\nours\n=======\ntheirs\n>>>>>>> a\n"));
+        assert_eq!(contents(tree, "b"), "ours\n");
+        assert_eq!(contents(tree, "c"), "c\n");
+        assert!(!td.path().join("a").exists());
+        assert!(repo.index().unwrap().is_empty());
+
+        let clean = repo
+            .find_commit(change(ours.id(), &[("d", Some("d\n"))]))
+            .unwrap();
+        let (tree, conflicts) = repo.merge_commits_to_tree(&clean, &ours, None).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(tree, clean.tree_id());
+    }
+
+    #[test]
+    fn fetch_head_entries() {
+        let (_td, repo) = crate::test::repo_init();
+        assert_eq!(
+            repo.fetch_head_entries().err().unwrap().code(),
+            ErrorCode::NotFound
+        );
+
+        let id = repo.head().unwrap().target().unwrap();
+        let fetch_head = format!(
+            "{id}\t\tbranch 'main' of https://example.com/repo\n\
+             {id}\tnot-for-merge\ttag 'v1' of https://example.com/repo\n",
+            id = id
+        );
+        fs::write(repo.path().join("FETCH_HEAD"), fetch_head).unwrap();
+
+        let entries = repo.fetch_head_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].ref_name(), Some("refs/heads/main"));
+        assert_eq!(entries[0].remote_url(), Some("https://example.com/repo"));
+        assert!(entries[0].is_for_merge());
+        assert_eq!(entries[0].id(), id);
+        assert_eq!(entries[0].commit().refname(), Some("refs/heads/main"));
+        assert_eq!(entries[1].ref_name(), Some("refs/tags/v1"));
+        assert!(!entries[1].is_for_merge());
+
+        let merge_heads = entries
+            .into_iter()
+            .filter(|entry| entry.is_for_merge())
+            .map(|entry| entry.into_commit())
+            .collect::<Vec<_>>();
+        let (analysis, _) = repo.merge_analysis(&[&merge_heads[0]]).unwrap();
+        assert!(analysis.is_up_to_date());
+    }
+
+    #[test]
+    fn merge_trees_path_file_favor() {
+        let (_td, repo) = crate::test::repo_init();
+        let names = ["docs/a.md", "docs/b.txt", "src/c.rs"];
+        let empty = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let tree = |contents: &str| {
+            let mut builder = TreeUpdateBuilder::new();
+            for name in names.iter() {
+                let id = repo.blob(contents.as_bytes()).unwrap();
+                builder.upsert(*name, id, FileMode::Blob);
+            }
+            let id = builder.create_updated(&repo, &empty).unwrap();
+            repo.find_tree(id).unwrap()
+        };
+        let base = tree("1\n");
+        let ours = tree("1\nours\n");
+        let theirs = tree("1\ntheirs\n");
+
+        let mut opts = MergeOptions::new();
+        opts.path_file_favor("docs", FileFavor::Ours)
+            .path_file_favor("*.txt", FileFavor::Theirs);
+        let index = repo
+            .merge_trees(&base, &ours, &theirs, Some(&opts))
+            .unwrap();
+        let merged = |name: &str| {
+            let entry = index.get_path(Path::new(name), 0).unwrap();
+            repo.find_blob(entry.id).unwrap().content().to_vec()
+        };
+        assert_eq!(merged("docs/a.md"), b"1\nours\n");
+        assert_eq!(merged("docs/b.txt"), b"1\ntheirs\n");
+        let conflicts = index.conflicts().unwrap().collect::<Vec<_>>();
+        assert_eq!(conflicts.len(), 1);
+        let conflict = conflicts[0].as_ref().unwrap();
+        assert_eq!(conflict.our.as_ref().unwrap().path, b"src/c.rs");
+        assert!(index.get_path(Path::new("docs/a.md"), 2).is_none());
+    }
+
+    #[test]
+    fn rebase_in_memory() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let base = repo.head().unwrap().target().unwrap();
+        let commit = |parent: Oid, file: &str, content: &str| {
+            let parent = repo.find_commit(parent).unwrap();
+            let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(file, blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            repo.commit(None, &sig, &sig, file, &tree, &[&parent])
+                .unwrap()
+        };
+        let upstream = commit(base, "u", "u");
+        let a = commit(base, "a", "a");
+        let b = commit(a, "b", "b");
+        let conflicting = commit(b, "u", "other");
+
+        let bare = Repository::open_bare(td.path().join(".git")).unwrap();
+        assert!(bare.is_bare());
+        let upstream_commit = bare.find_annotated_commit(upstream).unwrap();
+        let branch = bare.find_annotated_commit(b).unwrap();
+        let committer = crate::Signature::now("rebaser", "rebaser@example.com").unwrap();
+        let rebased = bare
+            .rebase_in_memory(
+                Some(&branch),
+                None,
+                Some(&upstream_commit),
+                &committer,
+                None,
+            )
+            .unwrap();
+        assert_eq!(rebased.len(), 2);
+        assert_eq!(rebased[0].0, a);
+        assert_eq!(rebased[1].0, b);
+        let tip = bare.find_commit(rebased[1].1).unwrap();
+        assert_eq!(tip.parent_id(0).unwrap(), rebased[0].1);
+        assert_eq!(
+            bare.find_commit(rebased[0].1)
+                .unwrap()
+                .parent_id(0)
+                .unwrap(),
+            upstream
+        );
+        assert_eq!(tip.committer().name(), Some("rebaser"));
+        assert!(tip.tree().unwrap().get_name("u").is_some());
+        assert_eq!(repo.head().unwrap().target(), Some(base));
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+
+        let branch = bare.find_annotated_commit(conflicting).unwrap();
+        let mut opts = crate::RebaseOptions::new();
+        let err = bare
+            .rebase_in_memory(
+                Some(&branch),
+                None,
+                Some(&upstream_commit),
+                &committer,
+                Some(&mut opts),
+            )
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::MergeConflict);
+        assert_eq!(unsafe { (*opts.raw()).inmemory }, 0);
+        assert!(err.message().contains(&conflicting.to_string()));
+        assert_eq!(repo.head().unwrap().target(), Some(base));
+    }
+
+    #[test]
+    fn create_branch_and_switch() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut builder = repo.treebuilder(Some(&head.tree().unwrap())).unwrap();
+        builder
+            .insert("file", repo.blob(b"remote\n").unwrap(), 0o100644)
+            .unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let remote = repo
+            .commit(None, &sig, &sig, "remote", &tree, &[&head])
+            .unwrap();
+        repo.remote("origin", "https://example.com/repo").unwrap();
+        repo.reference("refs/remotes/origin/feature", remote, false, "")
+            .unwrap();
+
+        let branch = repo
+            .create_branch_and_switch("feature", "origin/feature", SetUpstream::Origin)
+            .unwrap();
+        assert_eq!(branch.get().target(), Some(remote));
+        assert_eq!(
+            branch.upstream().unwrap().get().name(),
+            Some("refs/remotes/origin/feature")
+        );
+        assert_eq!(repo.head().unwrap().name(), Some("refs/heads/feature"));
+        assert_eq!(fs::read(td.path().join("file")).unwrap(), b"remote\n");
+        let reflog = repo.reflog("refs/heads/feature").unwrap();
+        assert_eq!(
+            reflog.get(0).unwrap().message(),
+            Some("branch: Created from origin/feature")
+        );
+        assert!(repo.statuses(None).unwrap().is_empty());
+
+        let branch = repo
+            .create_branch_and_switch("topic", "main", SetUpstream::Origin)
+            .unwrap();
+        assert_eq!(branch.get().target(), Some(head.id()));
+        let config = repo.config().unwrap();
+        assert_eq!(
+            config.get_string("branch.topic.merge").unwrap(),
+            "refs/heads/topic"
+        );
+        assert!(!td.path().join("file").exists());
+
+        let err = repo
+            .create_branch_and_switch("topic", "main", SetUpstream::None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), ErrorCode::Exists);
+
+        // A conflicting local change stops the switch before anything changes.
+        fs::write(td.path().join("file"), "local\n").unwrap();
+        assert!(repo
+            .create_branch_and_switch("other", "origin/feature", SetUpstream::None)
+            .is_err());
+        assert!(repo.find_branch("other", BranchType::Local).is_err());
+        assert_eq!(repo.head().unwrap().name(), Some("refs/heads/topic"));
+        assert_eq!(fs::read(td.path().join("file")).unwrap(), b"local\n");
+    }
 }
@@ -0,0 +1,158 @@
+//! Advisory, in-process locks shared by all the `Repository` handles opened
+//! on the same repository.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+/// The lock of a repository, shared by the handles opened on it for as long
+/// as a guard or a waiter holds it.
+#[derive(Default)]
+pub(crate) struct RepoLock {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+}
+
+/// Shared access to a repository, released when dropped.
+///
+/// Created by `Repository::read_lock`. Any number of read guards can be held
+/// at once, but none while a write guard is.
+pub struct RepositoryReadGuard {
+    lock: Arc<RepoLock>,
+}
+
+/// Exclusive access to a repository, released when dropped.
+///
+/// Created by `Repository::write_lock`.
+pub struct RepositoryWriteGuard {
+    lock: Arc<RepoLock>,
+}
+
+static REGISTRY: Mutex<BTreeMap<PathBuf, Weak<RepoLock>>> = Mutex::new(BTreeMap::new());
+
+impl RepoLock {
+    /// Returns the lock of the repository whose common directory is `path`.
+    pub(crate) fn get(path: &Path) -> Arc<RepoLock> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let mut locks = REGISTRY.lock().unwrap();
+        if let Some(lock) = locks.get(&path).and_then(Weak::upgrade) {
+            return lock;
+        }
+        locks.retain(|_, lock| lock.upgrade().is_some());
+        let lock = Arc::new(RepoLock::default());
+        locks.insert(path, Arc::downgrade(&lock));
+        lock
+    }
+
+    /// Waits for shared access. Readers queue behind waiting writers, so
+    /// that a steady stream of readers cannot starve a writer.
+    pub(crate) fn read(self: Arc<Self>) -> RepositoryReadGuard {
+        {
+            let mut state = self.state.lock().unwrap();
+            while state.writer || state.waiting_writers > 0 {
+                state = self.cond.wait(state).unwrap();
+            }
+            state.readers += 1;
+        }
+        RepositoryReadGuard { lock: self }
+    }
+
+    pub(crate) fn try_read(self: Arc<Self>) -> Option<RepositoryReadGuard> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.writer || state.waiting_writers > 0 {
+                return None;
+            }
+            state.readers += 1;
+        }
+        Some(RepositoryReadGuard { lock: self })
+    }
+
+    /// Waits for exclusive access.
+    pub(crate) fn write(self: Arc<Self>) -> RepositoryWriteGuard {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.waiting_writers += 1;
+            while state.writer || state.readers > 0 {
+                state = self.cond.wait(state).unwrap();
+            }
+            state.waiting_writers -= 1;
+            state.writer = true;
+        }
+        RepositoryWriteGuard { lock: self }
+    }
+
+    pub(crate) fn try_write(self: Arc<Self>) -> Option<RepositoryWriteGuard> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.writer || state.readers > 0 {
+                return None;
+            }
+            state.writer = true;
+        }
+        Some(RepositoryWriteGuard { lock: self })
+    }
+}
+
+impl Drop for RepositoryReadGuard {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock().unwrap();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.lock.cond.notify_all();
+        }
+    }
+}
+
+impl Drop for RepositoryWriteGuard {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock().unwrap();
+        state.writer = false;
+        self.lock.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Repository;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        let other = Repository::open(td.path()).unwrap();
+
+        let read = repo.read_lock().unwrap();
+        let read2 = other.try_read_lock().unwrap();
+        assert!(read2.is_some());
+        assert!(other.try_write_lock().unwrap().is_none());
+        drop(read);
+        drop(read2);
+
+        let write = other.write_lock().unwrap();
+        assert!(repo.try_read_lock().unwrap().is_none());
+        assert!(repo.try_write_lock().unwrap().is_none());
+
+        let (tx, rx) = mpsc::channel();
+        let path = td.path().to_path_buf();
+        let reader = thread::spawn(move || {
+            let repo = Repository::open(path).unwrap();
+            let _read = repo.read_lock().unwrap();
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(write);
+        rx.recv().unwrap();
+        reader.join().unwrap();
+        assert!(repo.try_write_lock().unwrap().is_some());
+    }
+}
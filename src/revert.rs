@@ -2,7 +2,7 @@ use std::mem;
 
 use crate::build::CheckoutBuilder;
 use crate::merge::MergeOptions;
-use crate::raw;
+use crate::{raw, Repository};
 use std::ptr;
 
 /// Options to specify when reverting
@@ -42,6 +42,14 @@ impl<'cb> RevertOptions<'cb> {
         self
     }
 
+    /// Set the repository the checkout runs in, see
+    /// `CheckoutBuilder::set_repository`.
+    pub(crate) fn set_repository(&mut self, repo: &Repository) {
+        if let Some(ref mut cb) = self.checkout_builder {
+            cb.set_repository(repo);
+        }
+    }
+
     /// Obtain the raw struct
     pub fn raw(&mut self) -> raw::git_revert_options {
         unsafe {
@@ -0,0 +1,559 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::{CherrypickOptions, Config, RevertOptions};
+use crate::{Error, ErrorClass, ErrorCode, Oid, Repository, RepositoryState, ResetType, Sort};
+use crate::{RebaseTodo, RebaseTodoCommand, RebaseTodoItem};
+
+/// Cherry-picks or reverts a list of commits one at a time, keeping its
+/// progress in `.git/sequencer` like `git cherry-pick A..B` and
+/// `git revert A..B`, so that it can stop on conflicts and be continued,
+/// skipped or aborted later, possibly by another process or by the command
+/// line client.
+///
+/// The `todo` file holds the `pick` or `revert` instructions left to run,
+/// starting with the one the sequencer stopped on, `head` the commit `HEAD`
+/// was at when it started, `abort-safety` the last commit it made and
+/// `opts` the mainline parent used for merge commits.
+pub struct Sequencer<'repo> {
+    repo: &'repo Repository,
+    todo: RebaseTodo,
+    mainline: Option<u32>,
+}
+
+/// What `Sequencer::run` and the other methods running instructions did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SequencerOutcome {
+    /// Every commit was picked or reverted and `HEAD` is now at this
+    /// commit. The sequencer state is removed.
+    Done(Oid),
+    /// Picking or reverting this commit stopped on conflicts. The
+    /// repository is left cherry-picking or reverting it, with the
+    /// conflicts in the index and the working directory; resolve them and
+    /// call `resume`, or call `skip` or `abort`.
+    Conflicts(Oid),
+}
+
+impl<'repo> Sequencer<'repo> {
+    /// Prepares to pick `commits` on top of `HEAD`, in order.
+    ///
+    /// Returns a `Locked` error if a sequencer is in progress already, and
+    /// an `Invalid` one if another operation such as a merge is.
+    pub fn start(repo: &'repo Repository, commits: &[Oid]) -> Result<Sequencer<'repo>, Error> {
+        Sequencer::begin(repo, RebaseTodoCommand::Pick, commits)
+    }
+
+    /// Prepares to pick the commits of `range`, such as `A..B`, oldest
+    /// first, like `git cherry-pick A..B`.
+    pub fn start_range(repo: &'repo Repository, range: &str) -> Result<Sequencer<'repo>, Error> {
+        let commits = walk_range(repo, range, Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        Sequencer::start(repo, &commits)
+    }
+
+    /// Prepares to revert `commits` on top of `HEAD`, in order, each in its
+    /// own commit.
+    ///
+    /// Fails like `start` if another operation is in progress.
+    pub fn start_revert(
+        repo: &'repo Repository,
+        commits: &[Oid],
+    ) -> Result<Sequencer<'repo>, Error> {
+        Sequencer::begin(repo, RebaseTodoCommand::Revert, commits)
+    }
+
+    /// Prepares to revert the commits of `range`, such as `A..B`, newest
+    /// first, like `git revert A..B`.
+    pub fn start_revert_range(
+        repo: &'repo Repository,
+        range: &str,
+    ) -> Result<Sequencer<'repo>, Error> {
+        let commits = walk_range(repo, range, Sort::TOPOLOGICAL)?;
+        Sequencer::start_revert(repo, &commits)
+    }
+
+    fn begin(
+        repo: &'repo Repository,
+        command: RebaseTodoCommand,
+        commits: &[Oid],
+    ) -> Result<Sequencer<'repo>, Error> {
+        if dir(repo).exists() {
+            return Err(Error::new(
+                ErrorCode::Locked,
+                ErrorClass::CherryPick,
+                "a cherry-pick or revert is already in progress",
+            ));
+        }
+        if repo.state() != RepositoryState::Clean {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::CherryPick,
+                "another operation is in progress",
+            ));
+        }
+        let mut todo = RebaseTodo::default();
+        for id in commits {
+            let commit = repo.find_commit(*id)?;
+            let args = format!("{} {}", id, commit.summary().unwrap_or(""));
+            todo.items.push(RebaseTodoItem::new(command, args));
+        }
+        let head = repo.head()?.peel_to_commit()?.id();
+        fs::create_dir_all(dir(repo)).map_err(io_err)?;
+        let sequencer = Sequencer {
+            repo,
+            todo,
+            mainline: None,
+        };
+        sequencer.write("head", &head.to_string())?;
+        sequencer.write("abort-safety", &head.to_string())?;
+        sequencer.save()?;
+        Ok(sequencer)
+    }
+
+    /// Opens the sequencer in progress in `repo`, such as one which stopped
+    /// on conflicts in another process.
+    ///
+    /// Returns a `NotFound` error if there is none.
+    pub fn open(repo: &'repo Repository) -> Result<Sequencer<'repo>, Error> {
+        let todo = match fs::read_to_string(dir(repo).join("todo")) {
+            Ok(todo) => todo,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::CherryPick,
+                    "no cherry-pick in progress",
+                ))
+            }
+            Err(e) => return Err(io_err(e)),
+        };
+        let todo = todo.parse::<RebaseTodo>()?;
+        let opts = dir(repo).join("opts");
+        let mainline = if opts.exists() {
+            match Config::open(&opts)?.get_i32("options.mainline") {
+                Ok(mainline) => Some(mainline as u32),
+                Err(ref e) if e.code() == ErrorCode::NotFound => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        Ok(Sequencer {
+            repo,
+            todo,
+            mainline,
+        })
+    }
+
+    /// Sets the parent of merge commits relative to which their changes are
+    /// picked or reverted, starting from 1, like the `-m` option of
+    /// `git cherry-pick` and `git revert`.
+    ///
+    /// The setting is saved with the rest of the state. Without it, merge
+    /// commits can't be picked or reverted.
+    pub fn set_mainline(&mut self, mainline: Option<u32>) -> Result<(), Error> {
+        let path = dir(self.repo).join("opts");
+        match mainline {
+            Some(mainline) => {
+                let mut opts = Config::open(&path)?;
+                opts.set_i32("options.mainline", mainline as i32)?;
+            }
+            None if path.exists() => fs::remove_file(&path).map_err(io_err)?,
+            None => {}
+        }
+        self.mainline = mainline;
+        Ok(())
+    }
+
+    /// Returns the mainline parent set with `set_mainline`.
+    pub fn mainline(&self) -> Option<u32> {
+        self.mainline
+    }
+
+    /// Returns the commits left to pick, starting with the one the
+    /// sequencer stopped on, if any.
+    pub fn todo(&self) -> &RebaseTodo {
+        &self.todo
+    }
+
+    /// Picks or reverts the remaining commits until all are done or one
+    /// conflicts.
+    ///
+    /// Picked commits keep their author and message; reverts get the usual
+    /// `Revert "..."` message. Commits which don't change `HEAD` are
+    /// skipped.
+    pub fn run(&mut self) -> Result<SequencerOutcome, Error> {
+        let repo = self.repo;
+        while let Some(item) = self.todo.items.first() {
+            let spec = match (item.command, item.commit()) {
+                (RebaseTodoCommand::Pick, Some(spec)) | (RebaseTodoCommand::Revert, Some(spec)) => {
+                    spec
+                }
+                _ => {
+                    return Err(Error::from_str(&format!(
+                        "invalid sequencer instruction '{}'",
+                        item
+                    )))
+                }
+            };
+            let commit = repo.revparse_single(spec)?.peel_to_commit()?;
+            // libgit2 rejects a mainline for commits which are not merges.
+            let mainline = match commit.parent_count() {
+                1 => 0,
+                _ => self.mainline.unwrap_or(0),
+            };
+            let revert = item.command == RebaseTodoCommand::Revert;
+            if revert {
+                let mut opts = RevertOptions::new();
+                opts.mainline(mainline);
+                repo.revert(&commit, Some(&mut opts))?;
+            } else {
+                let mut opts = CherrypickOptions::new();
+                opts.mainline(mainline);
+                repo.cherrypick(&commit, Some(&mut opts))?;
+            }
+            let mut index = repo.index()?;
+            if index.has_conflicts() {
+                return Ok(SequencerOutcome::Conflicts(commit.id()));
+            }
+            if index.write_tree()? == repo.head()?.peel_to_tree()?.id() {
+                repo.cleanup_sequence_state()?;
+            } else {
+                let id = if revert {
+                    repo.continue_revert(None)?
+                } else {
+                    repo.continue_cherrypick(None)?
+                };
+                self.write("abort-safety", &id.to_string())?;
+            }
+            self.todo.items.remove(0);
+            self.save()?;
+        }
+        let head = repo.head()?.peel_to_commit()?.id();
+        remove_dir(repo)?;
+        Ok(SequencerOutcome::Done(head))
+    }
+
+    /// Commits the pick or revert the sequencer stopped on once its
+    /// conflicts are resolved and goes on with the remaining ones, like
+    /// `git cherry-pick --continue` and `git revert --continue`.
+    ///
+    /// If the conflicted commit was already committed by hand, it is not
+    /// committed again. Returns an `Unmerged` error if the index still has
+    /// conflicts.
+    pub fn resume(&mut self) -> Result<SequencerOutcome, Error> {
+        let id = match stopped(self.repo) {
+            Some(RebaseTodoCommand::Revert) => Some(self.repo.continue_revert(None)?),
+            Some(_) => Some(self.repo.continue_cherrypick(None)?),
+            None => None,
+        };
+        if let Some(id) = id {
+            self.write("abort-safety", &id.to_string())?;
+        }
+        self.next()
+    }
+
+    /// Drops the changes of the commit the sequencer stopped on and goes on
+    /// with the remaining ones, like `git cherry-pick --skip`.
+    pub fn skip(&mut self) -> Result<SequencerOutcome, Error> {
+        match stopped(self.repo) {
+            Some(RebaseTodoCommand::Revert) => self.repo.abort_revert()?,
+            Some(_) => self.repo.abort_cherrypick()?,
+            None => {}
+        }
+        self.next()
+    }
+
+    /// Stops and resets `HEAD`, the index and the working directory to the
+    /// commit `HEAD` was at when the sequencer started, like
+    /// `git cherry-pick --abort` and `git revert --abort`.
+    ///
+    /// Returns a `Modified` error, leaving everything as it is, if `HEAD`
+    /// was moved since the last commit the sequencer made.
+    pub fn abort(self) -> Result<(), Error> {
+        let repo = self.repo;
+        let read = |name: &str| -> Result<Oid, Error> {
+            let id = fs::read_to_string(dir(repo).join(name)).map_err(io_err)?;
+            Oid::from_str(id.trim())
+        };
+        if repo.head()?.peel_to_commit()?.id() != read("abort-safety")? {
+            return Err(Error::new(
+                ErrorCode::Modified,
+                ErrorClass::CherryPick,
+                "HEAD has moved since the last cherry-pick, not rewinding",
+            ));
+        }
+        if stopped(repo).is_some() {
+            repo.cleanup_sequence_state()?;
+        }
+        let head = repo.find_commit(read("head")?)?;
+        // A hard reset cleans up the state of every operation, including
+        // the sequencer directory.
+        repo.reset(head.as_object(), ResetType::Hard, None)?;
+        remove_dir(repo)
+    }
+
+    fn next(&mut self) -> Result<SequencerOutcome, Error> {
+        if !self.todo.items.is_empty() {
+            self.todo.items.remove(0);
+        }
+        self.save()?;
+        self.run()
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        self.write("todo", &self.todo.to_string())
+    }
+
+    fn write(&self, name: &str, contents: &str) -> Result<(), Error> {
+        fs::write(dir(self.repo).join(name), contents).map_err(io_err)
+    }
+}
+
+/// Returns whether a commit is being picked or reverted. libgit2 reports
+/// these as `CherryPickSequence` and `RevertSequence` while the sequencer
+/// has a todo list.
+fn stopped(repo: &Repository) -> Option<RebaseTodoCommand> {
+    match repo.state() {
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+            Some(RebaseTodoCommand::Pick)
+        }
+        RepositoryState::Revert | RepositoryState::RevertSequence => {
+            Some(RebaseTodoCommand::Revert)
+        }
+        _ => None,
+    }
+}
+
+fn walk_range(repo: &Repository, range: &str, sort: Sort) -> Result<Vec<Oid>, Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(sort)?;
+    walk.push_range(range)?;
+    walk.collect()
+}
+
+fn dir(repo: &Repository) -> PathBuf {
+    repo.path().join("sequencer")
+}
+
+fn remove_dir(repo: &Repository) -> Result<(), Error> {
+    match fs::remove_dir_all(dir(repo)) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(io_err(e)),
+    }
+}
+
+fn io_err(e: io::Error) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Os,
+        format!("failed to update the sequencer state: {}", e),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorCode, Oid, Repository, Sequencer, SequencerOutcome, Signature};
+    use std::fs;
+    use std::path::Path;
+
+    fn commit_file(repo: &Repository, parent: Oid, name: &str, content: &str) -> Oid {
+        let parent = repo.find_commit(parent).unwrap();
+        let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let sig = Signature::now("author", "author@example.com").unwrap();
+        repo.commit(None, &sig, &sig, name, &tree, &[&parent])
+            .unwrap()
+    }
+
+    fn setup() -> (tempfile::TempDir, Repository, Oid, Oid, Oid) {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_file(&repo, base, "a", "side\n");
+        let b = commit_file(&repo, a, "b", "b\n");
+        let main = commit_file(&repo, base, "a", "main\n");
+        repo.reset(
+            repo.find_commit(main).unwrap().as_object(),
+            crate::ResetType::Hard,
+            None,
+        )
+        .unwrap();
+        (td, repo, base, a, b)
+    }
+
+    #[test]
+    fn pick_range() {
+        let (td, repo, base, _a, b) = setup();
+        let c = commit_file(&repo, b, "c", "c\n");
+        let range = format!("{}..{}", b, c);
+        let mut sequencer = Sequencer::start_range(&repo, &range).unwrap();
+        assert_eq!(sequencer.todo().items.len(), 1);
+        let head = match sequencer.run().unwrap() {
+            SequencerOutcome::Done(head) => head,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        let head = repo.find_commit(head).unwrap();
+        assert_eq!(head.summary(), Some("c"));
+        assert_eq!(head.author().name(), Some("author"));
+        assert!(td.path().join("c").exists());
+        assert!(!repo.path().join("sequencer").exists());
+
+        let err = Sequencer::open(&repo).err().unwrap();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert!(Sequencer::start_range(&repo, &format!("{}..{}", base, b)).is_ok());
+        let err = Sequencer::start(&repo, &[b]).err().unwrap();
+        assert_eq!(err.code(), ErrorCode::Locked);
+    }
+
+    #[test]
+    fn conflicts() {
+        let (td, repo, base, a, b) = setup();
+        let main = repo.head().unwrap().target().unwrap();
+        let range = format!("{}..{}", base, b);
+        let mut sequencer = Sequencer::start_range(&repo, &range).unwrap();
+        assert_eq!(sequencer.run().unwrap(), SequencerOutcome::Conflicts(a));
+        let todo = fs::read_to_string(repo.path().join("sequencer/todo")).unwrap();
+        assert_eq!(todo, format!("pick {} a\npick {} b\n", a, b));
+
+        // Continue in a new process after resolving the conflict.
+        let mut sequencer = Sequencer::open(&repo).unwrap();
+        assert_eq!(
+            sequencer.resume().err().unwrap().code(),
+            ErrorCode::Unmerged
+        );
+        fs::write(td.path().join("a"), "resolved\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        index.write().unwrap();
+        let head = match sequencer.resume().unwrap() {
+            SequencerOutcome::Done(head) => head,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        let head = repo.find_commit(head).unwrap();
+        assert_eq!(head.summary(), Some("b"));
+        let picked_a = head.parent(0).unwrap();
+        assert_eq!(picked_a.summary(), Some("a"));
+        assert_eq!(picked_a.parent_id(0).unwrap(), main);
+        assert_eq!(
+            fs::read_to_string(td.path().join("a")).unwrap(),
+            "resolved\n"
+        );
+
+        // Skip the conflicted commit.
+        repo.reset(
+            repo.find_commit(main).unwrap().as_object(),
+            crate::ResetType::Hard,
+            None,
+        )
+        .unwrap();
+        let mut sequencer = Sequencer::start_range(&repo, &range).unwrap();
+        assert_eq!(sequencer.run().unwrap(), SequencerOutcome::Conflicts(a));
+        let head = match sequencer.skip().unwrap() {
+            SequencerOutcome::Done(head) => head,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        let head = repo.find_commit(head).unwrap();
+        assert_eq!(head.summary(), Some("b"));
+        assert_eq!(head.parent_id(0).unwrap(), main);
+        assert_eq!(fs::read_to_string(td.path().join("a")).unwrap(), "main\n");
+
+        // Abort after the first pick.
+        repo.reset(
+            repo.find_commit(main).unwrap().as_object(),
+            crate::ResetType::Hard,
+            None,
+        )
+        .unwrap();
+        let c = commit_file(&repo, b, "c", "c\n");
+        let d = commit_file(&repo, c, "a", "d\n");
+        let mut sequencer = Sequencer::start(&repo, &[c, d]).unwrap();
+        assert_eq!(sequencer.run().unwrap(), SequencerOutcome::Conflicts(d));
+        assert!(td.path().join("c").exists());
+        Sequencer::open(&repo).unwrap().abort().unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(main));
+        assert!(!td.path().join("c").exists());
+        assert_eq!(repo.state(), crate::RepositoryState::Clean);
+        assert!(!repo.path().join("sequencer").exists());
+    }
+
+    #[test]
+    fn revert() {
+        let (td, repo, _base, a, _b) = setup();
+        let main = repo.head().unwrap().target().unwrap();
+        let c = commit_file(&repo, main, "c", "c\n");
+        let sig = Signature::now("author", "author@example.com").unwrap();
+        let tree = repo.find_commit(c).unwrap().tree().unwrap();
+        let parents = [repo.find_commit(c).unwrap(), repo.find_commit(a).unwrap()];
+        let mut builder = repo.treebuilder(Some(&tree)).unwrap();
+        builder
+            .insert("m", repo.blob(b"m\n").unwrap(), 0o100644)
+            .unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let merge = repo
+            .commit(
+                None,
+                &sig,
+                &sig,
+                "merge",
+                &tree,
+                &[&parents[0], &parents[1]],
+            )
+            .unwrap();
+        let d = commit_file(&repo, merge, "d", "d\n");
+        repo.reset(
+            repo.find_commit(d).unwrap().as_object(),
+            crate::ResetType::Hard,
+            None,
+        )
+        .unwrap();
+
+        let range = format!("{}..{}", c, d);
+        let mut sequencer = Sequencer::start_revert_range(&repo, &range).unwrap();
+        assert_eq!(sequencer.todo().items.len(), 3);
+        assert_eq!(sequencer.todo().items[0].commit(), Some(&d.to_string()[..]));
+        sequencer.set_mainline(Some(1)).unwrap();
+        let sequencer = Sequencer::open(&repo).unwrap();
+        assert_eq!(sequencer.mainline(), Some(1));
+        Sequencer::open(&repo).unwrap().abort().unwrap();
+
+        let mut sequencer = Sequencer::start_revert(&repo, &[d, merge]).unwrap();
+        sequencer.set_mainline(Some(1)).unwrap();
+        let head = match sequencer.run().unwrap() {
+            SequencerOutcome::Done(head) => head,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        let head = repo.find_commit(head).unwrap();
+        assert_eq!(head.summary(), Some("Revert \"merge\""));
+        assert_eq!(head.parent(0).unwrap().summary(), Some("Revert \"d\""));
+        assert!(!td.path().join("d").exists());
+        assert!(!td.path().join("m").exists());
+        assert!(td.path().join("c").exists());
+
+        // A conflicting revert stops with REVERT_HEAD, and continues.
+        let e = commit_file(&repo, head.id(), "c", "e\n");
+        repo.reset(
+            repo.find_commit(e).unwrap().as_object(),
+            crate::ResetType::Hard,
+            None,
+        )
+        .unwrap();
+        let mut sequencer = Sequencer::start_revert(&repo, &[c]).unwrap();
+        assert_eq!(sequencer.run().unwrap(), SequencerOutcome::Conflicts(c));
+        assert!(repo.path().join("REVERT_HEAD").exists());
+        assert_eq!(repo.state(), crate::RepositoryState::RevertSequence);
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("c")).unwrap();
+        index.write().unwrap();
+        fs::remove_file(td.path().join("c")).unwrap();
+        let head = match Sequencer::open(&repo).unwrap().resume().unwrap() {
+            SequencerOutcome::Done(head) => head,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        let head = repo.find_commit(head).unwrap();
+        assert_eq!(head.summary(), Some("Revert \"c\""));
+        assert_eq!(head.author().name(), repo.signature().unwrap().name());
+        assert!(!repo.path().join("REVERT_HEAD").exists());
+    }
+}
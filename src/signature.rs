@@ -28,7 +28,13 @@ impl<'a> Signature<'a> {
     /// Create a new action signature with a timestamp of 'now'.
     ///
     /// See `new` for more information
+    ///
+    /// The timestamp comes from the clock installed with
+    /// `opts::set_time_source`, if any.
     pub fn now(name: &str, email: &str) -> Result<Signature<'static>, Error> {
+        if let Some(time) = crate::opts::injected_time() {
+            return Signature::new(name, email, &time);
+        }
         crate::init();
         let mut ret = ptr::null_mut();
         let name = CString::new(name)?;
@@ -62,6 +68,22 @@ impl<'a> Signature<'a> {
         }
     }
 
+    /// Returns a copy of this signature with the timestamp replaced by
+    /// `time`.
+    pub(crate) fn with_time(&self, time: &Time) -> Result<Signature<'static>, Error> {
+        let mut ret = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_signature_new(
+                &mut ret,
+                (*self.raw).name,
+                (*self.raw).email,
+                time.seconds() as raw::git_time_t,
+                time.offset_minutes() as libc::c_int
+            ));
+            Ok(Binding::from_raw(ret))
+        }
+    }
+
     /// Gets the name on the signature.
     ///
     /// Returns `None` if the name is not valid utf-8
@@ -1,12 +1,18 @@
-use libc::{c_char, c_uint, size_t};
-use std::ffi::CString;
+use libc::{c_char, c_int, c_uint, c_void, size_t};
+use std::ffi::{CStr, CString};
+use std::fs;
 use std::marker;
 use std::mem;
 use std::ops::Range;
+use std::path::Path;
+use std::ptr;
 use std::str;
+use std::thread;
 
 use crate::util::{self, Binding};
-use crate::{raw, DiffDelta, IntoCString, Repository, Status};
+use crate::{panic, raw, Delta, DiffDelta, DiffFile, Error, IntoCString, Oid, Repository, Status};
+use crate::{FileMode, IndexEntry, IndexTime};
+use crate::{ReferenceType, SubmoduleIgnore, SubmoduleStatus, Tree};
 
 /// Options that can be provided to `repo.statuses()` to control how the status
 /// information is gathered.
@@ -14,6 +20,10 @@ pub struct StatusOptions {
     raw: raw::git_status_options,
     pathspec: Vec<CString>,
     ptrs: Vec<*const c_char>,
+    pathspec_magic: bool,
+    magic_pathspec: Vec<CString>,
+    baseline: Option<Oid>,
+    threads: usize,
 }
 
 /// Enumeration of possible methods of what can be shown through a status
@@ -58,6 +68,20 @@ pub struct StatusEntry<'statuses> {
     _marker: marker::PhantomData<&'statuses DiffDelta<'statuses>>,
 }
 
+/// An untracked file or directory, as listed by
+/// `Repository::untracked_entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UntrackedEntry {
+    path: Vec<u8>,
+    files: usize,
+}
+
+/// Options controlling how `Statuses::porcelain_v2` renders a status list.
+pub struct PorcelainV2Options {
+    branch: bool,
+    nul_terminated: bool,
+}
+
 impl Default for StatusOptions {
     fn default() -> Self {
         Self::new()
@@ -75,6 +99,10 @@ impl StatusOptions {
                 raw: raw,
                 pathspec: Vec::new(),
                 ptrs: Vec::new(),
+                pathspec_magic: false,
+                magic_pathspec: Vec::new(),
+                baseline: None,
+                threads: 1,
             }
         }
     }
@@ -104,6 +132,79 @@ impl StatusOptions {
         self
     }
 
+    /// Interpret git's pathspec magic in the pathspecs given to `pathspec`.
+    ///
+    /// This allows excluding paths from the status, for example with
+    /// `:!target/` or `:(exclude)*.log`. The supported magic is `:(top)` (or
+    /// `:/`), `:(exclude)` (or `:!` and `:^`), `:(literal)` and `:(glob)`.
+    /// Excluding pathspecs apply regardless of their position, and when only
+    /// excluding pathspecs are given everything else is included. Any other
+    /// magic makes `Repository::statuses` return an error.
+    pub fn pathspec_magic(&mut self, enabled: bool) -> &mut StatusOptions {
+        self.pathspec_magic = enabled;
+        self
+    }
+
+    /// Translate the pathspec magic, if enabled, into the pathspecs handed
+    /// to libgit2.
+    pub(crate) fn prepare_pathspecs(&mut self) -> Result<(), Error> {
+        if self.pathspec_magic {
+            self.magic_pathspec = util::pathspec_magic(self.pathspec.clone())?;
+            self.ptrs = self.magic_pathspec.iter().map(|s| s.as_ptr()).collect();
+        } else {
+            self.ptrs = self.pathspec.iter().map(|s| s.as_ptr()).collect();
+        }
+        Ok(())
+    }
+
+    /// Compare the index against the given tree instead of `HEAD`.
+    ///
+    /// This makes it possible to ask which files changed since an arbitrary
+    /// commit, such as a tag. The tree is looked up again in the repository
+    /// passed to `Repository::statuses`, so it must exist there.
+    pub fn baseline_tree(&mut self, tree: &Tree<'_>) -> &mut StatusOptions {
+        self.baseline = Some(tree.id());
+        self
+    }
+
+    /// Returns the id of the tree set with `baseline_tree`, if any.
+    pub(crate) fn baseline_id(&self) -> Option<Oid> {
+        self.baseline
+    }
+
+    /// Set the number of threads used to check the working directory when
+    /// `update_index` is set.
+    ///
+    /// libgit2 gathers the status on a single thread. With more than one
+    /// thread and `update_index` set, the index entries whose stat
+    /// information is out of date are first checked in parallel: each file
+    /// is hashed with the filters from its attributes applied, like `git add`
+    /// would, and the entries whose content turns out to be unchanged get
+    /// their stat information refreshed. The index is then written, as
+    /// libgit2 does for `update_index`, so the serial status pass does not
+    /// have to hash these files again. Without `update_index` the index is
+    /// never written and this setting has no effect. The reported statuses
+    /// are the same either way.
+    pub fn threads(&mut self, threads: usize) -> &mut StatusOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Refresh the stat information of unchanged index entries of `repo`
+    /// in parallel, if more than one thread was requested and the index may
+    /// be updated.
+    pub(crate) fn refresh_index(&self, repo: &Repository) -> Result<(), Error> {
+        let workdir = match repo.workdir() {
+            Some(workdir) => workdir,
+            None => return Ok(()),
+        };
+        let update = self.raw.flags & raw::GIT_STATUS_OPT_UPDATE_INDEX as c_uint != 0;
+        if !update || self.threads <= 1 || self.raw.show == raw::GIT_STATUS_SHOW_INDEX_ONLY {
+            return Ok(());
+        }
+        refresh_stat(repo, workdir, self.threads)
+    }
+
     fn flag(&mut self, flag: raw::git_status_opt_t, val: bool) -> &mut StatusOptions {
         if val {
             self.raw.flags |= flag as c_uint;
@@ -225,6 +326,188 @@ impl StatusOptions {
         self.raw.pathspec.count = self.ptrs.len() as size_t;
         &self.raw
     }
+
+    /// Like `raw`, but returns a copy of the options pointing at the
+    /// resolved baseline tree, so that the options themselves never keep a
+    /// pointer to it. The copy must not outlive the tree or these options.
+    pub(crate) unsafe fn raw_with_baseline(
+        &mut self,
+        baseline: Option<&Tree<'_>>,
+    ) -> raw::git_status_options {
+        let mut raw = ptr::read(self.raw());
+        raw.baseline = baseline.map(|t| t.raw()).unwrap_or(ptr::null_mut());
+        raw
+    }
+}
+
+/// Checks the unmerged regular files of the index of `repo` on `threads`
+/// threads, and writes back the index with the stat information of the
+/// unchanged ones refreshed.
+fn refresh_stat(repo: &Repository, workdir: &Path, threads: usize) -> Result<(), Error> {
+    let mut index = repo.index()?;
+    let entries = index
+        .iter()
+        .filter(|e| e.flags & raw::GIT_INDEX_ENTRY_STAGEMASK == 0 && e.flags_extended == 0)
+        .filter(|e| {
+            e.mode == u32::from(FileMode::Blob) || e.mode == u32::from(FileMode::BlobExecutable)
+        })
+        .collect::<Vec<_>>();
+    let chunk = (entries.len() + threads - 1) / threads;
+    if chunk == 0 {
+        return Ok(());
+    }
+
+    // A repository handle must not be used from several threads at once, so
+    // each thread hashes with its own.
+    let mut entries = entries.into_iter();
+    let handles = (0..threads)
+        .map(|_| {
+            let gitdir = repo.path().to_path_buf();
+            let workdir = workdir.to_path_buf();
+            let entries = entries.by_ref().take(chunk).collect::<Vec<_>>();
+            thread::spawn(move || -> Result<Vec<IndexEntry>, Error> {
+                let repo = Repository::open(&gitdir)?;
+                repo.set_workdir(&workdir, false)?;
+                let mut refreshed = Vec::new();
+                for entry in entries {
+                    if let Some(entry) = refresh_entry(&repo, &workdir, entry) {
+                        refreshed.push(entry);
+                    }
+                }
+                Ok(refreshed)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut refreshed = Vec::new();
+    for handle in handles {
+        match handle.join() {
+            Ok(entries) => refreshed.extend(entries?),
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+    if refreshed.is_empty() {
+        return Ok(());
+    }
+    for entry in &refreshed {
+        index.add(entry)?;
+    }
+    index.write()
+}
+
+/// Returns `entry` with the stat information of its file, if that is out of
+/// date but the file still has the content of the entry.
+fn refresh_entry(repo: &Repository, workdir: &Path, mut entry: IndexEntry) -> Option<IndexEntry> {
+    let path = workdir.join(util::bytes2path(&entry.path));
+    let meta = fs::symlink_metadata(&path).ok()?;
+    // The index keeps only the low 32 bits of the size, inode and device,
+    // and libgit2 compares against its own stat truncated the same way.
+    if !meta.file_type().is_file() || meta.len() as u32 != entry.file_size {
+        return None;
+    }
+    let stat = IndexStat::new(&meta)?;
+    if entry.mtime == stat.mtime
+        && entry.ctime == stat.ctime
+        && entry.ino == stat.ino
+        && entry.uid == stat.uid
+        && entry.gid == stat.gid
+    {
+        return None;
+    }
+    let as_path = CString::new(&entry.path[..]).ok()?;
+    let path = path.into_c_string().ok()?;
+    let mut id = raw::git_oid {
+        id: [0; raw::GIT_OID_RAWSZ],
+    };
+    let rc = unsafe {
+        raw::git_repository_hashfile(
+            &mut id,
+            repo.raw(),
+            path.as_ptr(),
+            raw::GIT_OBJECT_BLOB,
+            as_path.as_ptr(),
+        )
+    };
+    if rc < 0 || unsafe { Oid::from_raw(&id) } != entry.id {
+        return None;
+    }
+    entry.mtime = stat.mtime;
+    entry.ctime = stat.ctime;
+    entry.dev = stat.dev;
+    entry.ino = stat.ino;
+    entry.uid = stat.uid;
+    entry.gid = stat.gid;
+    Some(entry)
+}
+
+/// The stat information of a file as libgit2 records it in an index entry.
+struct IndexStat {
+    mtime: IndexTime,
+    ctime: IndexTime,
+    dev: u32,
+    ino: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl IndexStat {
+    #[cfg(unix)]
+    fn new(meta: &fs::Metadata) -> Option<IndexStat> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(IndexStat {
+            mtime: IndexTime::new(meta.mtime() as i32, meta.mtime_nsec() as u32),
+            ctime: IndexTime::new(meta.ctime() as i32, meta.ctime_nsec() as u32),
+            // libgit2 records `st_rdev`, which is zero for regular files.
+            dev: meta.rdev() as u32,
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new(meta: &fs::Metadata) -> Option<IndexStat> {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        fn time(t: SystemTime) -> Option<IndexTime> {
+            let t = t.duration_since(UNIX_EPOCH).ok()?;
+            Some(IndexTime::new(t.as_secs() as i32, t.subsec_nanos()))
+        }
+
+        // libgit2 reports the creation time as `st_ctime` on Windows, and no
+        // inode or owner. The device is never compared, so it is left zero.
+        Some(IndexStat {
+            mtime: time(meta.modified().ok()?)?,
+            ctime: time(meta.created().ok()?)?,
+            dev: 0,
+            ino: 0,
+            uid: 0,
+            gid: 0,
+        })
+    }
+}
+
+/// Callback invoked by `Repository::status_foreach_ext` for each file.
+///
+/// Return `true` to continue, or `false` to stop gathering statuses.
+pub type StatusCb<'a> = dyn FnMut(&Path, Status) -> bool + 'a;
+
+pub(crate) extern "C" fn status_cb(
+    path: *const c_char,
+    status: c_uint,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let callback = &mut *(payload as *mut &mut StatusCb<'_>);
+        let path = util::bytes2path(CStr::from_ptr(path).to_bytes());
+        if callback(path, Status::from_bits_truncate(status as u32)) {
+            0
+        } else {
+            1
+        }
+    })
+    .unwrap_or(-1)
 }
 
 impl<'repo> Statuses<'repo> {
@@ -258,6 +541,137 @@ impl<'repo> Statuses<'repo> {
             range: 0..self.len(),
         }
     }
+
+    /// Renders this list in the format of `git status --porcelain=v2`.
+    ///
+    /// To match the output of the command line client, the list should be
+    /// gathered with `include_untracked(true)` and
+    /// `renames_head_to_index(true)`, plus `include_ignored(true)` for
+    /// `--ignored`. `repo` must be the repository the list was created from;
+    /// it is used for the branch header, conflicts and submodules.
+    pub fn porcelain_v2(
+        &self,
+        repo: &Repository,
+        opts: Option<&PorcelainV2Options>,
+    ) -> Result<Vec<u8>, Error> {
+        let default = PorcelainV2Options::new();
+        let opts = opts.unwrap_or(&default);
+        let eol = if opts.nul_terminated { b'\0' } else { b'\n' };
+        let mut out = Vec::new();
+        if opts.branch {
+            porcelain_v2_branch(repo, &mut out, eol)?;
+        }
+
+        let mut index = None;
+        let mut untracked = Vec::new();
+        let mut ignored = Vec::new();
+        for entry in self.iter() {
+            let status = entry.status();
+            if status.is_empty() {
+                continue;
+            }
+            if status.contains(Status::IGNORED) {
+                ignored.push(entry.path_bytes().to_vec());
+                continue;
+            }
+            if status.contains(Status::CONFLICTED) {
+                if index.is_none() {
+                    index = Some(repo.index()?);
+                }
+                let index = index.as_ref().unwrap();
+                porcelain_v2_unmerged(repo, index, &entry, &mut out, opts)?;
+                continue;
+            }
+            if status.contains(Status::WT_NEW) {
+                // A file deleted from the index but still on disk shows up
+                // both as a change and as untracked.
+                untracked.push(entry.path_bytes().to_vec());
+                if (status - Status::WT_NEW).is_empty() {
+                    continue;
+                }
+            }
+            porcelain_v2_changed(repo, &entry, &mut out, opts)?;
+        }
+        for (prefix, paths) in [(b'?', untracked), (b'!', ignored)].iter() {
+            for path in paths {
+                out.push(*prefix);
+                out.push(b' ');
+                push_path(&mut out, path, opts.nul_terminated);
+                out.push(eol);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl UntrackedEntry {
+    pub(crate) fn new(path: Vec<u8>) -> UntrackedEntry {
+        let files = if path.ends_with(b"/") { 0 } else { 1 };
+        UntrackedEntry { path, files }
+    }
+
+    pub(crate) fn add_file(&mut self) {
+        self.files += 1;
+    }
+
+    /// Access the bytes of this entry's path. Directories end with a `/`.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Access this entry's path as a string.
+    ///
+    /// Returns `None` if the path is not valid utf-8.
+    pub fn path(&self) -> Option<&str> {
+        str::from_utf8(&self.path).ok()
+    }
+
+    /// Returns whether this entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.path.ends_with(b"/")
+    }
+
+    /// Returns the number of untracked files this entry stands for: the
+    /// number of files below a directory, or 1 for a file.
+    ///
+    /// A directory may count no files, for instance when it is a nested
+    /// repository.
+    pub fn file_count(&self) -> usize {
+        self.files
+    }
+}
+
+impl Default for PorcelainV2Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PorcelainV2Options {
+    /// Creates a new blank set of rendering options.
+    pub fn new() -> PorcelainV2Options {
+        PorcelainV2Options {
+            branch: false,
+            nul_terminated: false,
+        }
+    }
+
+    /// Emit the `# branch.*` header lines, like `git status --branch`.
+    ///
+    /// Defaults to `false`.
+    pub fn branch(&mut self, branch: bool) -> &mut PorcelainV2Options {
+        self.branch = branch;
+        self
+    }
+
+    /// Terminate entries with NUL instead of LF and don't quote paths, like
+    /// `git status -z`.
+    ///
+    /// Defaults to `false`.
+    pub fn nul_terminated(&mut self, nul: bool) -> &mut PorcelainV2Options {
+        self.nul_terminated = nul;
+        self
+    }
 }
 
 impl<'repo> Binding for Statuses<'repo> {
@@ -317,6 +731,83 @@ impl<'statuses> StatusEntry<'statuses> {
         str::from_utf8(self.path_bytes()).ok()
     }
 
+    /// Access the path this entry was renamed from as bytes.
+    ///
+    /// Returns `None` unless rename detection was requested and found a rename
+    /// from `HEAD` to the index or from the index to the working directory.
+    pub fn renamed_from_bytes(&self) -> Option<&[u8]> {
+        self.rename_delta().map(|_| self.path_bytes())
+    }
+
+    /// Access the path this entry was renamed from as a string.
+    ///
+    /// Returns `None` if this entry is not a rename or if the path is not
+    /// valid utf-8.
+    pub fn renamed_from(&self) -> Option<&str> {
+        self.renamed_from_bytes()
+            .and_then(|p| str::from_utf8(p).ok())
+    }
+
+    /// Access the path this entry was renamed to as bytes.
+    ///
+    /// When the file was renamed both in the index and in the working
+    /// directory, this is its path in the working directory.
+    pub fn renamed_to_bytes(&self) -> Option<&[u8]> {
+        self.rename_delta()?;
+        let delta = self.index_to_workdir().or_else(|| self.head_to_index())?;
+        delta.new_file().path_bytes()
+    }
+
+    /// Access the path this entry was renamed to as a string.
+    ///
+    /// Returns `None` if this entry is not a rename or if the path is not
+    /// valid utf-8.
+    pub fn renamed_to(&self) -> Option<&str> {
+        self.renamed_to_bytes().and_then(|p| str::from_utf8(p).ok())
+    }
+
+    /// Access the similarity score of the rename, between 0 and 100.
+    ///
+    /// When the file was renamed both in the index and in the working
+    /// directory, this is the score of the rename from `HEAD` to the index.
+    pub fn rename_similarity(&self) -> Option<u16> {
+        self.rename_delta().map(|d| d.similarity())
+    }
+
+    fn rename_delta(&self) -> Option<DiffDelta<'statuses>> {
+        self.head_to_index()
+            .into_iter()
+            .chain(self.index_to_workdir())
+            .find(|d| d.status() == Delta::Renamed)
+    }
+
+    /// Returns whether this entry is a submodule in `HEAD`, the index or the
+    /// working directory.
+    pub fn is_submodule(&self) -> bool {
+        self.head_to_index()
+            .into_iter()
+            .chain(self.index_to_workdir())
+            .any(|d| raw_mode(&d.old_file()) == GITLINK || raw_mode(&d.new_file()) == GITLINK)
+    }
+
+    /// Look up the state of the submodule this entry refers to.
+    ///
+    /// A modified submodule only carries `Status::WT_MODIFIED`; this tells
+    /// whether it has new commits, modified content or untracked content, as
+    /// `git status` reports. The submodule's `ignore` setting is honored.
+    /// `repo` must be the repository this entry was created from.
+    ///
+    /// Returns `None` if this entry is not a submodule.
+    pub fn submodule_status(&self, repo: &Repository) -> Result<Option<SubmoduleStatus>, Error> {
+        if !self.is_submodule() {
+            return Ok(None);
+        }
+        let name = str::from_utf8(self.path_bytes())
+            .map_err(|_| Error::from_str("submodule path is not valid utf-8"))?;
+        repo.submodule_status(name, SubmoduleIgnore::Unspecified)
+            .map(Some)
+    }
+
     /// Access the status flags for this file
     pub fn status(&self) -> Status {
         Status::from_bits_truncate(unsafe { (*self.raw).status as u32 })
@@ -349,9 +840,250 @@ impl<'statuses> Binding for StatusEntry<'statuses> {
     }
 }
 
+fn porcelain_v2_branch(repo: &Repository, out: &mut Vec<u8>, eol: u8) -> Result<(), Error> {
+    let head = repo.find_reference("HEAD")?;
+    let oid = head.resolve().ok().and_then(|r| r.target());
+    let branch = match head.kind() {
+        Some(ReferenceType::Symbolic) => head.symbolic_target(),
+        _ => None,
+    };
+
+    out.extend_from_slice(b"# branch.oid ");
+    match oid {
+        Some(oid) => out.extend_from_slice(oid.to_string().as_bytes()),
+        None => out.extend_from_slice(b"(initial)"),
+    }
+    out.push(eol);
+    out.extend_from_slice(b"# branch.head ");
+    match branch {
+        Some(name) => out.extend_from_slice(shorten_refname(name).as_bytes()),
+        None => out.extend_from_slice(b"(detached)"),
+    }
+    out.push(eol);
+
+    let upstream = match branch.map(|b| repo.branch_upstream_name(b)) {
+        Some(Ok(upstream)) => upstream,
+        _ => return Ok(()),
+    };
+    let upstream = match upstream.as_str() {
+        Some(upstream) => upstream,
+        None => return Ok(()),
+    };
+    out.extend_from_slice(b"# branch.upstream ");
+    out.extend_from_slice(shorten_refname(upstream).as_bytes());
+    out.push(eol);
+    if let (Some(local), Ok(remote)) = (oid, repo.refname_to_id(upstream)) {
+        let (ahead, behind) = repo.graph_ahead_behind(local, remote)?;
+        out.extend_from_slice(format!("# branch.ab +{} -{}", ahead, behind).as_bytes());
+        out.push(eol);
+    }
+    Ok(())
+}
+
+fn shorten_refname(name: &str) -> &str {
+    ["refs/heads/", "refs/remotes/", "refs/tags/", "refs/"]
+        .iter()
+        .find(|prefix| name.starts_with(*prefix))
+        .map(|prefix| &name[prefix.len()..])
+        .unwrap_or(name)
+}
+
+fn porcelain_v2_changed(
+    repo: &Repository,
+    entry: &StatusEntry<'_>,
+    out: &mut Vec<u8>,
+    opts: &PorcelainV2Options,
+) -> Result<(), Error> {
+    let status = entry.status();
+    let head_to_index = entry.head_to_index();
+    let index_to_workdir = entry
+        .index_to_workdir()
+        .filter(|d| d.status() != Delta::Untracked && d.status() != Delta::Ignored);
+
+    let (mode_index, id_index) = match (&head_to_index, &index_to_workdir) {
+        (Some(d), _) => file_info(&d.new_file()),
+        (None, Some(d)) => file_info(&d.old_file()),
+        (None, None) => (0, Oid::zero()),
+    };
+    let (mode_head, id_head) = match &head_to_index {
+        Some(d) => file_info(&d.old_file()),
+        None => (mode_index, id_index),
+    };
+    let mode_workdir = match &index_to_workdir {
+        Some(d) => file_info(&d.new_file()).0,
+        None => mode_index,
+    };
+
+    let x = if status.contains(Status::INDEX_NEW) {
+        'A'
+    } else if status.contains(Status::INDEX_DELETED) {
+        'D'
+    } else if status.contains(Status::INDEX_RENAMED) {
+        'R'
+    } else if status.contains(Status::INDEX_TYPECHANGE) {
+        'T'
+    } else if status.contains(Status::INDEX_MODIFIED) {
+        'M'
+    } else {
+        '.'
+    };
+    let y = if status.contains(Status::WT_DELETED) {
+        'D'
+    } else if status.contains(Status::WT_RENAMED) {
+        'R'
+    } else if status.contains(Status::WT_TYPECHANGE) {
+        'T'
+    } else if status.contains(Status::WT_MODIFIED) {
+        'M'
+    } else {
+        '.'
+    };
+
+    let rename = head_to_index
+        .iter()
+        .chain(index_to_workdir.iter())
+        .find(|d| d.status() == Delta::Renamed);
+    let sub = submodule_field(repo, entry.path_bytes(), entry.is_submodule());
+    let kind = if rename.is_some() { '2' } else { '1' };
+    out.extend_from_slice(
+        format!(
+            "{} {}{} {} {:06o} {:06o} {:06o} {} {} ",
+            kind, x, y, sub, mode_head, mode_index, mode_workdir, id_head, id_index
+        )
+        .as_bytes(),
+    );
+    match rename {
+        Some(delta) => {
+            out.extend_from_slice(format!("R{} ", delta.similarity()).as_bytes());
+            let new_path = delta.new_file().path_bytes().unwrap_or(b"");
+            let old_path = delta.old_file().path_bytes().unwrap_or(b"");
+            push_path(out, new_path, opts.nul_terminated);
+            out.push(if opts.nul_terminated { b'\0' } else { b'\t' });
+            push_path(out, old_path, opts.nul_terminated);
+        }
+        None => push_path(out, entry.path_bytes(), opts.nul_terminated),
+    }
+    out.push(if opts.nul_terminated { b'\0' } else { b'\n' });
+    Ok(())
+}
+
+fn porcelain_v2_unmerged(
+    repo: &Repository,
+    index: &crate::Index,
+    entry: &StatusEntry<'_>,
+    out: &mut Vec<u8>,
+    opts: &PorcelainV2Options,
+) -> Result<(), Error> {
+    let path = entry.path_bytes();
+    let stages = (1..4)
+        .map(|stage| index.get_path(&util::bytes2path(path), stage))
+        .collect::<Vec<_>>();
+    let mask = stages
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.is_some())
+        .fold(0, |mask, (i, _)| mask | (1 << i));
+    let xy = match mask {
+        1 => "DD",
+        2 => "AU",
+        3 => "UD",
+        4 => "UA",
+        5 => "DU",
+        6 => "AA",
+        _ => "UU",
+    };
+    let mode_workdir = repo
+        .workdir()
+        .and_then(|w| w.join(util::bytes2path(path)).symlink_metadata().ok())
+        .map(|meta| u32::from(util::file_mode(&meta)))
+        .unwrap_or(0);
+    let info = |i: usize| {
+        stages[i]
+            .as_ref()
+            .map(|e| (e.mode, e.id))
+            .unwrap_or((0, Oid::zero()))
+    };
+    let (m1, h1) = info(0);
+    let (m2, h2) = info(1);
+    let (m3, h3) = info(2);
+    let submodule = [m1, m2, m3, mode_workdir].contains(&GITLINK);
+    let sub = submodule_field(repo, path, submodule);
+    out.extend_from_slice(
+        format!(
+            "u {} {} {:06o} {:06o} {:06o} {:06o} {} {} {} ",
+            xy, sub, m1, m2, m3, mode_workdir, h1, h2, h3
+        )
+        .as_bytes(),
+    );
+    push_path(out, path, opts.nul_terminated);
+    out.push(if opts.nul_terminated { b'\0' } else { b'\n' });
+    Ok(())
+}
+
+const GITLINK: u32 = 0o160000;
+
+fn raw_mode(file: &DiffFile<'_>) -> u32 {
+    // `DiffFile::mode` only knows about the modes git itself writes, while
+    // the working directory side may report anything.
+    unsafe { (*file.raw()).mode as u32 }
+}
+
+fn file_info(file: &DiffFile<'_>) -> (u32, Oid) {
+    (raw_mode(file), file.id())
+}
+
+fn submodule_field(repo: &Repository, path: &[u8], submodule: bool) -> String {
+    if !submodule {
+        return "N...".to_string();
+    }
+    let status = str::from_utf8(path).ok().and_then(|name| {
+        repo.submodule_status(name, SubmoduleIgnore::Unspecified)
+            .ok()
+    });
+    let status = match status {
+        Some(status) => status,
+        None => return "S...".to_string(),
+    };
+    let flag = |set: bool, c: char| if set { c } else { '.' };
+    format!(
+        "S{}{}{}",
+        flag(status.has_new_commits(), 'C'),
+        flag(status.has_modified_content(), 'M'),
+        flag(status.has_untracked_content(), 'U'),
+    )
+}
+
+/// Appends `path`, quoted the way git quotes paths unless `-z` is in effect.
+fn push_path(out: &mut Vec<u8>, path: &[u8], nul_terminated: bool) {
+    let needs_quotes = path
+        .iter()
+        .any(|&b| b < 0x20 || b >= 0x7f || b == b'"' || b == b'\\');
+    if nul_terminated || !needs_quotes {
+        out.extend_from_slice(path);
+        return;
+    }
+    out.push(b'"');
+    for &b in path {
+        match b {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            0x07 => out.extend_from_slice(b"\\a"),
+            0x08 => out.extend_from_slice(b"\\b"),
+            0x0b => out.extend_from_slice(b"\\v"),
+            0x0c => out.extend_from_slice(b"\\f"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b if b < 0x20 || b >= 0x7f => out.extend_from_slice(format!("\\{:03o}", b).as_bytes()),
+            b => out.push(b),
+        }
+    }
+    out.push(b'"');
+}
+
 #[cfg(test)]
 mod tests {
-    use super::StatusOptions;
+    use super::{PorcelainV2Options, StatusOptions};
     use std::fs::File;
     use std::io::prelude::*;
     use std::path::Path;
@@ -414,4 +1146,255 @@ mod tests {
             assert!(status.contains(crate::Status::WT_NEW));
         }
     }
+
+    #[test]
+    fn baseline_tree() {
+        let (_td, repo) = crate::test::repo_init();
+        let initial = t!(t!(repo.head()).peel_to_tree());
+        crate::test::commit(&repo);
+        assert_eq!(t!(repo.statuses(None)).len(), 0);
+
+        let mut opts = StatusOptions::new();
+        opts.baseline_tree(&initial);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        assert_eq!(statuses.len(), 1);
+        let status = statuses.get(0).unwrap();
+        assert_eq!(status.path(), Some("foo"));
+        assert_eq!(status.status(), crate::Status::INDEX_NEW);
+    }
+
+    #[test]
+    fn renamed_entry() {
+        let (td, repo) = crate::test::repo_init();
+        t!(t!(File::create(td.path().join("foo"))).write_all(b"some content\n"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new("foo")));
+        let id = t!(index.write_tree());
+        let tree = t!(repo.find_tree(id));
+        let sig = t!(repo.signature());
+        let parent = t!(t!(repo.head()).peel_to_commit());
+        t!(repo.commit(Some("HEAD"), &sig, &sig, "foo", &tree, &[&parent]));
+
+        t!(std::fs::rename(
+            td.path().join("foo"),
+            td.path().join("bar")
+        ));
+        t!(index.remove_path(Path::new("foo")));
+        t!(index.add_path(Path::new("bar")));
+        t!(index.write());
+
+        let mut opts = StatusOptions::new();
+        opts.renames_head_to_index(true);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        assert_eq!(statuses.len(), 1);
+        let status = statuses.get(0).unwrap();
+        assert_eq!(status.status(), crate::Status::INDEX_RENAMED);
+        assert_eq!(status.renamed_from(), Some("foo"));
+        assert_eq!(status.renamed_to(), Some("bar"));
+        assert_eq!(status.rename_similarity(), Some(100));
+
+        opts.renames_head_to_index(false);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        assert_eq!(statuses.len(), 2);
+        for status in statuses.iter() {
+            assert!(status.renamed_from().is_none());
+            assert!(status.renamed_to().is_none());
+            assert!(status.rename_similarity().is_none());
+        }
+    }
+
+    #[test]
+    fn foreach_ext() {
+        let (td, repo) = crate::test::repo_init();
+        t!(File::create(&td.path().join("foo")));
+        t!(File::create(&td.path().join("bar")));
+
+        let mut seen = Vec::new();
+        t!(repo.status_foreach_ext(None, |path, status| {
+            seen.push((path.to_path_buf(), status));
+            true
+        }));
+        assert_eq!(
+            seen,
+            vec![
+                (Path::new("bar").to_path_buf(), crate::Status::WT_NEW),
+                (Path::new("foo").to_path_buf(), crate::Status::WT_NEW),
+            ]
+        );
+
+        let mut count = 0;
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true);
+        t!(repo.status_foreach_ext(Some(&mut opts), |_, _| {
+            count += 1;
+            false
+        }));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn pathspec_magic() {
+        let (td, repo) = crate::test::repo_init();
+        t!(std::fs::create_dir(td.path().join("target")));
+        t!(File::create(&td.path().join("target/out")));
+        t!(File::create(&td.path().join("foo")));
+        t!(File::create(&td.path().join("bar")));
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .pathspec_magic(true)
+            .pathspec(":!target/");
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        let paths = statuses.iter().map(|s| s.path().unwrap().to_string());
+        assert_eq!(paths.collect::<Vec<_>>(), vec!["bar", "foo"]);
+
+        opts.pathspec(":(icase)foo");
+        assert!(repo.statuses(Some(&mut opts)).is_err());
+    }
+
+    #[test]
+    fn porcelain_v2() {
+        let (td, repo) = crate::test::repo_init();
+        let head = t!(repo.refname_to_id("HEAD"));
+        t!(t!(File::create(td.path().join("staged"))).write_all(b"hello\n"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new("staged")));
+        t!(index.write());
+        t!(File::create(td.path().join("caf\u{e9}")));
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).renames_head_to_index(true);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        let blob = t!(crate::Oid::hash_object(crate::ObjectType::Blob, b"hello\n"));
+        let expected = format!(
+            "# branch.oid {}\n\
+             # branch.head main\n\
+             1 A. N... 000000 100644 100644 {} {} staged\n\
+             ? \"caf\\303\\251\"\n",
+            head,
+            crate::Oid::zero(),
+            blob
+        );
+        let mut porcelain = PorcelainV2Options::new();
+        porcelain.branch(true);
+        let out = t!(statuses.porcelain_v2(&repo, Some(&porcelain)));
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        porcelain.branch(false).nul_terminated(true);
+        let out = t!(statuses.porcelain_v2(&repo, Some(&porcelain)));
+        assert!(out.ends_with("staged\0? caf\u{e9}\0".as_bytes()));
+    }
+
+    #[test]
+    fn submodule_status() {
+        let (_td, repo1) = crate::test::repo_init();
+        crate::test::commit(&repo1);
+        let (td, repo) = crate::test::repo_init();
+        let url = crate::test::path2url(repo1.workdir().unwrap());
+        let mut s = t!(repo.submodule(&url, Path::new("bar"), true));
+        t!(std::fs::remove_dir_all(td.path().join("bar")));
+        let sub = t!(crate::Repository::clone(&url, td.path().join("bar")));
+        let mut config = t!(sub.config());
+        t!(config.set_str("user.name", "name"));
+        t!(config.set_str("user.email", "email"));
+        t!(s.add_to_index(false));
+        t!(s.add_finalize());
+        let mut index = t!(repo.index());
+        let tree = t!(repo.find_tree(t!(index.write_tree())));
+        let sig = t!(repo.signature());
+        let parent = t!(t!(repo.head()).peel_to_commit());
+        t!(repo.commit(Some("HEAD"), &sig, &sig, "add bar", &tree, &[&parent]));
+        assert_eq!(t!(repo.statuses(None)).len(), 0);
+
+        t!(File::create(td.path().join("bar/untracked")));
+        let statuses = t!(repo.statuses(None));
+        assert_eq!(statuses.len(), 1);
+        let entry = statuses.get(0).unwrap();
+        assert_eq!(entry.path(), Some("bar"));
+        assert_eq!(entry.status(), crate::Status::WT_MODIFIED);
+        assert!(entry.is_submodule());
+        let status = t!(entry.submodule_status(&repo)).unwrap();
+        assert!(status.has_untracked_content());
+        assert!(!status.has_new_commits());
+        assert!(!status.has_modified_content());
+
+        crate::test::commit(&sub);
+        let statuses = t!(repo.statuses(None));
+        let status = t!(statuses.get(0).unwrap().submodule_status(&repo)).unwrap();
+        assert!(status.has_new_commits());
+
+        t!(File::create(td.path().join("other")));
+        let statuses = t!(repo.statuses(None));
+        let entry = statuses.iter().find(|e| e.path() == Some("other")).unwrap();
+        assert!(!entry.is_submodule());
+        assert!(t!(entry.submodule_status(&repo)).is_none());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn threads() {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let (td, repo) = crate::test::repo_init();
+        for name in &["a", "b", "c"] {
+            t!(t!(File::create(td.path().join(name))).write_all(name.as_bytes()));
+        }
+        // `crlf` is staged with CRLF line endings, which the clean filter
+        // converts once `.gitattributes` asks for LF.
+        t!(t!(File::create(td.path().join("crlf"))).write_all(b"x\r\n"));
+        // Write the index after the files, so that libgit2 does not consider
+        // their entries racily clean and recheck them anyway.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let mut index = t!(repo.index());
+        for name in &["a", "b", "c", "crlf"] {
+            t!(index.add_path(Path::new(name)));
+        }
+        t!(index.write());
+        t!(t!(File::create(td.path().join(".gitattributes"))).write_all(b"crlf text eol=lf\n"));
+
+        // Only touch the ctime of `a` and `crlf`, and change `b`.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        for name in &["a", "crlf"] {
+            t!(fs::set_permissions(
+                td.path().join(name),
+                Permissions::from_mode(0o644)
+            ));
+        }
+        t!(t!(File::create(td.path().join("b"))).write_all(b"B"));
+
+        let statuses = |threads, update| {
+            let mut opts = StatusOptions::new();
+            opts.threads(threads).update_index(update);
+            let statuses = t!(repo.statuses(Some(&mut opts)));
+            let mut paths = statuses
+                .iter()
+                .map(|s| (s.path().unwrap().to_string(), s.status()))
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+        let ctime = |name| {
+            let index = t!(repo.index());
+            let entry = index.get_path(Path::new(name), 0).unwrap();
+            (entry.ctime.seconds(), entry.ctime.nanoseconds())
+        };
+        let staged = ctime("a");
+        let expected = statuses(1, false);
+        assert!(expected.contains(&(
+            "crlf".to_string(),
+            crate::Status::INDEX_NEW | crate::Status::WT_MODIFIED
+        )));
+        // Without `update_index` the index is left alone.
+        assert_eq!(statuses(4, false), expected);
+        assert_eq!(ctime("a"), staged);
+        assert_eq!(statuses(4, true), expected);
+        assert_eq!(statuses(1, false), expected);
+
+        let meta = t!(fs::metadata(td.path().join("a")));
+        let (seconds, nanoseconds) = ctime("a");
+        assert_eq!(seconds as i64, meta.ctime());
+        assert_eq!(nanoseconds as i64, meta.ctime_nsec());
+    }
 }
@@ -0,0 +1,1217 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+#[cfg(feature = "precompose-unicode")]
+use crate::nfd;
+use crate::util;
+use crate::{
+    raw, Error, ErrorClass, ErrorCode, IndexEntry, LockFile, LockOptions, Oid, Repository, Status,
+};
+use crate::{StatusOptions, StatusShow};
+
+/// A snapshot of the status of every file in a working directory which can
+/// be saved to a file and reused by later processes, as long as nothing has
+/// changed.
+///
+/// Shell prompts and editors want the status of a repository as soon as
+/// they start, but a full scan hashes files and walks untracked
+/// directories. A `StatusCache` records, besides the statuses, what the
+/// scan depended on: the id of `HEAD`, the size and modification time of
+/// the index, and those of the directories of the working directory and of
+/// the files which were not current. `is_fresh` checks these with a stat of
+/// each file and directory, and `StatusCache::open` only rescans when they
+/// changed.
+///
+/// Like git's own checks, changes which keep a file's size and modification
+/// time are not noticed, and neither are changes to ignore rules outside of
+/// the working directory, such as `core.excludesFile`. Submodules are
+/// scanned, but changes inside them do not make the cache stale.
+///
+/// As in `git status`, a submodule is a single entry with a summarized
+/// status, and directory statuses stop at it. `scan_recursive` also
+/// records the files of submodules, which count for the directories inside
+/// of them.
+///
+/// Conflicted files also record the stages of their conflict, read from the
+/// index during the scan, which `conflict` returns.
+///
+/// Renames are detected, both from `HEAD` to the index and from the index
+/// to the working directory. A renamed file is recorded under its new path,
+/// and its status is also that of its old path, so a directory a file was
+/// moved out of shows the rename instead of a deletion.
+///
+/// Paths may be given relative to the working directory or absolute. An
+/// absolute path may reach the working directory through symbolic links,
+/// such as `/tmp` on macOS or a network mount: the working directory is
+/// resolved once when the cache is scanned or opened, and each other
+/// prefix which leads to it is resolved the first time it is seen, instead
+/// of canonicalizing every queried path.
+///
+/// With the `precompose-unicode` feature and `core.precomposeunicode` set,
+/// as on macOS, paths are also found whatever their Unicode normalization;
+/// see `set_normalization_insensitive`.
+pub struct StatusCache {
+    head: Option<Oid>,
+    index: Option<Stamp>,
+    dirs: BTreeMap<Vec<u8>, Option<Stamp>>,
+    /// The files which are not current, in path order so that those in a
+    /// directory are found with a range scan.
+    entries: BTreeMap<Vec<u8>, CachedEntry>,
+    /// The new path of each renamed file, by its old path.
+    renamed: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The paths of the submodules, where directory statuses stop.
+    submodules: BTreeSet<Vec<u8>>,
+    /// Whether the files of submodules were scanned too.
+    recursive: bool,
+    root: Option<Root>,
+    /// The decompositions of the paths which are not ASCII, when lookups
+    /// ignore normalization.
+    #[cfg(feature = "precompose-unicode")]
+    normalized: Option<HashMap<String, Vec<u8>>>,
+}
+
+/// Where the working directory of a scanned or opened cache is, to
+/// resolve absolute paths.
+struct Root {
+    workdir: PathBuf,
+    canonical: PathBuf,
+    ignore_case: bool,
+    /// Other directories found to lead to the working directory through
+    /// symbolic links.
+    aliases: Mutex<Vec<PathBuf>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Stamp {
+    seconds: i64,
+    nanoseconds: u32,
+    size: u64,
+}
+
+struct CachedEntry {
+    status: Status,
+    stamp: Option<Stamp>,
+    conflict: Option<ConflictDetail>,
+    renamed_from: Option<Vec<u8>>,
+}
+
+/// The stages of a conflicted file in the index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConflictDetail {
+    /// The id of the common ancestor's version, if it had the file.
+    pub ancestor: Option<Oid>,
+    /// The id of our version, if we have the file.
+    pub our: Option<Oid>,
+    /// The id of their version, if they have the file.
+    pub their: Option<Oid>,
+}
+
+/// How the two sides of a conflict changed a file, as shown by
+/// `git status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides modified the file (`UU`).
+    BothModified,
+    /// Both sides added the file (`AA`).
+    BothAdded,
+    /// Both sides deleted the file (`DD`), as happens when both renamed it
+    /// differently.
+    BothDeleted,
+    /// We added the file and it was not in the ancestor (`AU`).
+    AddedByUs,
+    /// They added the file and it was not in the ancestor (`UA`).
+    AddedByThem,
+    /// We deleted the file which they modified (`DU`).
+    DeletedByUs,
+    /// They deleted the file which we modified (`UD`).
+    DeletedByThem,
+}
+
+impl ConflictDetail {
+    /// Returns how the two sides changed the file, based on which stages
+    /// are present.
+    pub fn kind(&self) -> ConflictKind {
+        ConflictKind::from_stages(
+            self.ancestor.is_some(),
+            self.our.is_some(),
+            self.their.is_some(),
+        )
+    }
+}
+
+impl ConflictKind {
+    /// Classifies a conflict by which of its ancestor, our and their stages
+    /// are present.
+    pub(crate) fn from_stages(ancestor: bool, our: bool, their: bool) -> ConflictKind {
+        match (ancestor, our, their) {
+            (true, true, true) => ConflictKind::BothModified,
+            (false, true, true) => ConflictKind::BothAdded,
+            (true, false, true) => ConflictKind::DeletedByUs,
+            (true, true, false) => ConflictKind::DeletedByThem,
+            (false, true, false) => ConflictKind::AddedByUs,
+            (false, false, true) => ConflictKind::AddedByThem,
+            (_, false, false) => ConflictKind::BothDeleted,
+        }
+    }
+}
+
+const HEADER: &str = "git2-status-cache 4";
+
+impl StatusCache {
+    /// Scan the status of the working directory of `repo`, including
+    /// untracked files.
+    pub fn scan(repo: &Repository) -> Result<StatusCache, Error> {
+        let mut cache = StatusCache::scan_repo(repo, false)?;
+        cache.follow_precompose_unicode(repo);
+        Ok(cache)
+    }
+
+    /// Scan the status of the working directory of `repo` and of the files
+    /// of its checked out submodules, recursively.
+    ///
+    /// A submodule keeps its own summarized status, and the statuses of its
+    /// files only count for the directories inside of it. Changes to the
+    /// directories and files of submodules make the cache stale, but
+    /// changes to their index and `HEAD` do not.
+    pub fn scan_recursive(repo: &Repository) -> Result<StatusCache, Error> {
+        let mut cache = StatusCache::scan_repo(repo, true)?;
+        cache.follow_precompose_unicode(repo);
+        Ok(cache)
+    }
+
+    fn scan_repo(repo: &Repository, recursive: bool) -> Result<StatusCache, Error> {
+        let workdir = workdir(repo)?;
+        // Stamps are taken before scanning, so that changes made during the
+        // scan make the cache stale instead of going unnoticed.
+        let mut cache = StatusCache {
+            head: head_id(repo),
+            index: stamp(&repo.path().join("index")),
+            dirs: BTreeMap::new(),
+            entries: BTreeMap::new(),
+            renamed: BTreeMap::new(),
+            submodules: BTreeSet::new(),
+            recursive: false,
+            root: Some(Root::new(repo)?),
+            #[cfg(feature = "precompose-unicode")]
+            normalized: None,
+        };
+        cache.add_dir(workdir, b"");
+        let mut conflicts = HashMap::new();
+        cache.recursive = recursive;
+        for entry in repo.index()?.iter() {
+            cache.add_parents(workdir, &entry.path);
+            if entry.mode == 0o160000 {
+                cache.submodules.insert(entry.path.clone());
+            }
+            let stage =
+                (entry.flags & raw::GIT_INDEX_ENTRY_STAGEMASK) >> raw::GIT_INDEX_ENTRY_STAGESHIFT;
+            if stage == 0 {
+                continue;
+            }
+            let detail = conflicts.entry(entry.path).or_insert(ConflictDetail {
+                ancestor: None,
+                our: None,
+                their: None,
+            });
+            match stage {
+                1 => detail.ancestor = Some(entry.id),
+                2 => detail.our = Some(entry.id),
+                _ => detail.their = Some(entry.id),
+            }
+        }
+
+        let mut opts = StatusOptions::new();
+        opts.show(StatusShow::IndexAndWorkdir)
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .renames_head_to_index(true)
+            .renames_index_to_workdir(true);
+        let statuses = repo.statuses(Some(&mut opts))?;
+        for entry in statuses.iter() {
+            let (path, renamed_from) = match entry.renamed_to_bytes() {
+                Some(to) => (to.to_vec(), Some(entry.path_bytes().to_vec())),
+                None => (entry.path_bytes().to_vec(), None),
+            };
+            let status = entry.status();
+            if status.is_wt_new() || status.is_wt_renamed() {
+                cache.add_parents(workdir, &path);
+            }
+            let conflict = if status.is_conflicted() {
+                conflicts.remove(&path)
+            } else {
+                None
+            };
+            if let Some(ref from) = renamed_from {
+                cache.renamed.insert(from.clone(), path.clone());
+            }
+            cache.entries.insert(
+                path.clone(),
+                CachedEntry {
+                    status,
+                    stamp: stamp(&workdir.join(util::bytes2path(&path))),
+                    conflict,
+                    renamed_from,
+                },
+            );
+        }
+        if recursive {
+            for submodule in repo.submodules()? {
+                if let Ok(sub) = submodule.open() {
+                    let prefix = util::path_to_repo_path(submodule.path())?.into_bytes();
+                    cache.add_submodule(&prefix, StatusCache::scan_repo(&sub, true)?);
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Adds the entries and directories of the cache of the submodule at
+    /// `prefix`.
+    fn add_submodule(&mut self, prefix: &[u8], sub: StatusCache) {
+        let join = |path: Vec<u8>| {
+            let mut joined = prefix.to_vec();
+            if !path.is_empty() {
+                joined.push(b'/');
+                joined.extend(path);
+            }
+            joined
+        };
+        for (dir, stamp) in sub.dirs {
+            self.dirs.insert(join(dir), stamp);
+        }
+        for (path, mut entry) in sub.entries {
+            entry.renamed_from = entry.renamed_from.map(join);
+            self.entries.insert(join(path), entry);
+        }
+        for (from, to) in sub.renamed {
+            self.renamed.insert(join(from), join(to));
+        }
+        self.submodules.extend(sub.submodules.into_iter().map(join));
+    }
+
+    /// Load the cache saved at `cache_path` if it is still fresh, and
+    /// otherwise scan `repo` and save the result there.
+    ///
+    /// A missing or unreadable cache file is not an error; failing to save
+    /// the new cache, such as because another process is saving one, is not
+    /// either.
+    pub fn open(repo: &Repository, cache_path: &Path) -> Result<StatusCache, Error> {
+        StatusCache::open_repo(repo, cache_path, false)
+    }
+
+    /// Like `open`, but for a cache of `scan_recursive`.
+    pub fn open_recursive(repo: &Repository, cache_path: &Path) -> Result<StatusCache, Error> {
+        StatusCache::open_repo(repo, cache_path, true)
+    }
+
+    fn open_repo(
+        repo: &Repository,
+        cache_path: &Path,
+        recursive: bool,
+    ) -> Result<StatusCache, Error> {
+        if let Ok(data) = fs::read(cache_path) {
+            if let Ok(mut cache) = StatusCache::from_bytes(&data) {
+                if cache.recursive == recursive && cache.is_fresh(repo)? {
+                    cache.root = Some(Root::new(repo)?);
+                    cache.follow_precompose_unicode(repo);
+                    return Ok(cache);
+                }
+            }
+        }
+        let mut cache = StatusCache::scan_repo(repo, recursive)?;
+        cache.follow_precompose_unicode(repo);
+        let _ = cache.save(cache_path);
+        Ok(cache)
+    }
+
+    /// Save this cache to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut lock = LockFile::acquire(path, &LockOptions::new())?;
+        lock.write_all(&self.to_bytes()).map_err(|e| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!("failed to write '{}': {}", path.display(), e),
+            )
+        })?;
+        lock.commit()
+    }
+
+    /// Returns `true` if nothing this cache depends on has changed since it
+    /// was scanned, so its statuses are still those of the working
+    /// directory.
+    pub fn is_fresh(&self, repo: &Repository) -> Result<bool, Error> {
+        let workdir = workdir(repo)?;
+        if head_id(repo) != self.head || stamp(&repo.path().join("index")) != self.index {
+            return Ok(false);
+        }
+        let path = |p: &[u8]| workdir.join(util::bytes2path(p));
+        if self.dirs.iter().any(|(dir, s)| stamp(&path(dir)) != *s) {
+            return Ok(false);
+        }
+        if self
+            .entries
+            .iter()
+            .any(|(file, entry)| stamp(&path(file)) != entry.stamp)
+        {
+            return Ok(false);
+        }
+        // The old paths of files renamed in the working directory are gone
+        // from it, and the stamps of their entries cover them.
+        for entry in repo.index()?.iter() {
+            let stage = entry.flags & raw::GIT_INDEX_ENTRY_STAGEMASK;
+            if stage != 0
+                || entry.mode == 0o160000
+                || self.entries.contains_key(&entry.path)
+                || self.renamed.contains_key(&entry.path)
+            {
+                continue;
+            }
+            match stamp(&path(&entry.path)) {
+                Some(s) if matches_index(&entry, s) => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the status of `path`, relative to the working directory or
+    /// absolute.
+    ///
+    /// For a directory, this is the union of the statuses of the files in
+    /// it, so a directory with a modified file is `WT_MODIFIED`. The status
+    /// of a renamed file counts both at its old and its new path. Files
+    /// which are not in the cache, including those outside of the working
+    /// directory, are current. Absolute paths are only understood by caches
+    /// which were scanned or opened, as `from_bytes` does not know where
+    /// the working directory is.
+    ///
+    /// Like `git status`, a submodule has its own summarized status, such as
+    /// `WT_MODIFIED` when it has changes, and the files of caches scanned
+    /// with `scan_recursive` only count for the directories inside of it.
+    pub fn get(&self, path: &Path) -> Status {
+        let key = match self.key(path) {
+            Some(key) => key,
+            None => return Status::CURRENT,
+        };
+        if let Some(entry) = self.entries.get(&key) {
+            return entry.status;
+        }
+        if let Some((_, entry)) = self.renamed_out_of(&key) {
+            return entry.status;
+        }
+        if self.submodules.contains(&key) {
+            return Status::CURRENT;
+        }
+        let mut prefix = key;
+        if !prefix.is_empty() {
+            prefix.push(b'/');
+        }
+        let nested = under_set(&self.submodules, &prefix).collect::<Vec<_>>();
+        let outside = |path: &[u8]| {
+            !nested.iter().any(|sub| {
+                path.len() > sub.len() && path.starts_with(sub) && path[sub.len()] == b'/'
+            })
+        };
+        let moved_out = under(&self.renamed, &prefix)
+            .filter(|(from, _)| outside(from))
+            .filter_map(|(_, to)| self.entries.get(to));
+        under(&self.entries, &prefix)
+            .filter(|(path, _)| outside(path))
+            .map(|(_, entry)| entry)
+            .chain(moved_out)
+            .fold(Status::CURRENT, |status, entry| status | entry.status)
+    }
+
+    /// Returns the path `path` was renamed from, if it is the new path of a
+    /// renamed file.
+    pub fn renamed_from(&self, path: &Path) -> Option<&Path> {
+        let key = self.key(path)?;
+        let from = self.entries.get(&key)?.renamed_from.as_ref()?;
+        Some(util::bytes2path(from))
+    }
+
+    /// Returns the path `path` was renamed to, if it is the old path of a
+    /// renamed file.
+    pub fn renamed_to(&self, path: &Path) -> Option<&Path> {
+        let key = self.key(path)?;
+        self.renamed_out_of(&key)
+            .map(|(to, _)| util::bytes2path(to))
+    }
+
+    /// Sets whether paths are found whatever their Unicode normalization.
+    ///
+    /// The file systems of macOS return file names with decomposed
+    /// characters, while git records them composed when
+    /// `core.precomposeunicode` is set, so a path read from the file system
+    /// may not match the path of its status. With this set, a path matches
+    /// a file or directory of the cache whose path has the same canonical
+    /// decomposition. Caches which are scanned or opened set this from
+    /// `core.precomposeunicode`.
+    ///
+    /// Only available with the `precompose-unicode` feature.
+    #[cfg(feature = "precompose-unicode")]
+    pub fn set_normalization_insensitive(&mut self, enabled: bool) {
+        if !enabled {
+            self.normalized = None;
+            return;
+        }
+        let mut normalized = HashMap::new();
+        let paths = self
+            .entries
+            .iter()
+            .flat_map(|(path, entry)| std::iter::once(path).chain(entry.renamed_from.iter()))
+            .chain(self.dirs.keys());
+        for path in paths {
+            if let Ok(s) = str::from_utf8(path) {
+                if !s.is_ascii() {
+                    normalized.insert(nfd::decompose(s), path.clone());
+                }
+            }
+        }
+        self.normalized = Some(normalized);
+    }
+
+    /// Returns the stages of `path` if it is conflicted.
+    pub fn conflict(&self, path: &Path) -> Option<ConflictDetail> {
+        let key = self.key(path)?;
+        self.entries.get(&key).and_then(|entry| entry.conflict)
+    }
+
+    /// Returns the files which are not current, with their statuses, in
+    /// path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, Status)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (util::bytes2path(path), entry.status))
+    }
+
+    /// Returns the number of files which are not current.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if all files are current.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serialize this cache.
+    ///
+    /// The format is a header line followed by a `head` and an `index`
+    /// line, a `scan recursive` line for caches of `scan_recursive`, then a
+    /// `submodule <path>` line per submodule, a `dir <stamp> <path>` line
+    /// per directory and an `entry <status> <stamp> <path>` line per file.
+    /// A conflicted file's entry line is followed by a
+    /// `conflict <ancestor> <our> <their> <path>` line, with `-` for a
+    /// missing stage, and a renamed file's by a `renamed <old path>` line.
+    /// Newlines and backslashes in paths are escaped with a backslash.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("{}\n", HEADER).into_bytes();
+        match self.head {
+            Some(id) => out.extend(format!("head {}\n", id).into_bytes()),
+            None => out.extend(b"head -\n"),
+        }
+        out.extend(format!("index {}\n", format_stamp(self.index)).into_bytes());
+        if self.recursive {
+            out.extend(b"scan recursive\n");
+        }
+        for path in self.submodules.iter() {
+            out.extend(b"submodule ");
+            escape(&mut out, path);
+        }
+        for (dir, stamp) in self.dirs.iter() {
+            out.extend(format!("dir {} ", format_stamp(*stamp)).into_bytes());
+            escape(&mut out, dir);
+        }
+        for (path, entry) in self.entries.iter() {
+            let line = format!(
+                "entry {} {} ",
+                entry.status.bits(),
+                format_stamp(entry.stamp)
+            );
+            out.extend(line.into_bytes());
+            escape(&mut out, path);
+            if let Some(conflict) = entry.conflict {
+                let line = format!(
+                    "conflict {} {} {} ",
+                    format_id(conflict.ancestor),
+                    format_id(conflict.our),
+                    format_id(conflict.their)
+                );
+                out.extend(line.into_bytes());
+                escape(&mut out, path);
+            }
+            if let Some(ref from) = entry.renamed_from {
+                out.extend(b"renamed ");
+                escape(&mut out, from);
+            }
+        }
+        out
+    }
+
+    /// Deserialize a cache written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<StatusCache, Error> {
+        let mut lines = bytes.split(|&b| b == b'\n');
+        if lines.next() != Some(HEADER.as_bytes()) {
+            return Err(invalid("not a status cache"));
+        }
+        let mut cache = StatusCache {
+            head: None,
+            index: None,
+            dirs: BTreeMap::new(),
+            entries: BTreeMap::new(),
+            renamed: BTreeMap::new(),
+            submodules: BTreeSet::new(),
+            recursive: false,
+            root: None,
+            #[cfg(feature = "precompose-unicode")]
+            normalized: None,
+        };
+        let mut last_entry = None;
+        for line in lines.filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(2, |&b| b == b' ');
+            let (kind, rest) = match (parts.next(), parts.next()) {
+                (Some(kind), Some(rest)) => (kind, rest),
+                _ => return Err(invalid_line(line)),
+            };
+            match kind {
+                b"head" if rest == b"-" => cache.head = None,
+                b"head" => {
+                    let id = str::from_utf8(rest).map_err(|_| invalid_line(line))?;
+                    cache.head = Some(Oid::from_str(id)?);
+                }
+                b"index" => cache.index = parse_stamp(rest).ok_or_else(|| invalid_line(line))?,
+                b"scan" if rest == b"recursive" => cache.recursive = true,
+                b"submodule" => {
+                    cache.submodules.insert(unescape(rest));
+                }
+                b"dir" => {
+                    let mut parts = rest.splitn(2, |&b| b == b' ');
+                    let stamp = parts.next().and_then(parse_stamp);
+                    match (stamp, parts.next()) {
+                        (Some(stamp), Some(path)) => {
+                            cache.dirs.insert(unescape(path), stamp);
+                        }
+                        _ => return Err(invalid_line(line)),
+                    }
+                }
+                b"entry" => {
+                    let mut parts = rest.splitn(3, |&b| b == b' ');
+                    let status = parts
+                        .next()
+                        .and_then(|s| str::from_utf8(s).ok())
+                        .and_then(|s| s.parse().ok())
+                        .map(Status::from_bits_truncate);
+                    let stamp = parts.next().and_then(parse_stamp);
+                    match (status, stamp, parts.next()) {
+                        (Some(status), Some(stamp), Some(path)) => {
+                            let entry = CachedEntry {
+                                status,
+                                stamp,
+                                conflict: None,
+                                renamed_from: None,
+                            };
+                            let path = unescape(path);
+                            cache.entries.insert(path.clone(), entry);
+                            last_entry = Some(path);
+                        }
+                        _ => return Err(invalid_line(line)),
+                    }
+                }
+                b"conflict" => {
+                    let mut parts = rest.splitn(4, |&b| b == b' ');
+                    let mut id = || parts.next().and_then(parse_id);
+                    let (ancestor, our, their) = (id(), id(), id());
+                    let entry = parts
+                        .next()
+                        .and_then(|path| cache.entries.get_mut(&unescape(path)));
+                    match (ancestor, our, their, entry) {
+                        (Some(ancestor), Some(our), Some(their), Some(entry)) => {
+                            entry.conflict = Some(ConflictDetail {
+                                ancestor,
+                                our,
+                                their,
+                            });
+                        }
+                        _ => return Err(invalid_line(line)),
+                    }
+                }
+                b"renamed" => {
+                    let entry = match last_entry {
+                        Some(ref path) => cache.entries.get_mut(path).map(|entry| (path, entry)),
+                        None => None,
+                    };
+                    match entry {
+                        Some((path, entry)) => {
+                            let from = unescape(rest);
+                            entry.renamed_from = Some(from.clone());
+                            cache.renamed.insert(from, path.clone());
+                        }
+                        None => return Err(invalid_line(line)),
+                    }
+                }
+                _ => return Err(invalid_line(line)),
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Returns the key of `path`, its path relative to the working
+    /// directory with `/` separators, spelled as in the cache if lookups
+    /// ignore normalization.
+    #[cfg(feature = "precompose-unicode")]
+    fn key(&self, path: &Path) -> Option<Vec<u8>> {
+        let key = self.relative_key(path)?;
+        let normalized = match self.normalized {
+            Some(ref normalized) if !self.entries.contains_key(&key) => normalized,
+            _ => return Some(key),
+        };
+        let known = str::from_utf8(&key)
+            .ok()
+            .and_then(|key| normalized.get(&nfd::decompose(key)));
+        Some(known.cloned().unwrap_or(key))
+    }
+
+    #[cfg(not(feature = "precompose-unicode"))]
+    fn key(&self, path: &Path) -> Option<Vec<u8>> {
+        self.relative_key(path)
+    }
+
+    /// Makes lookups ignore normalization if `core.precomposeunicode` is
+    /// set for `repo`.
+    #[cfg(feature = "precompose-unicode")]
+    fn follow_precompose_unicode(&mut self, repo: &Repository) {
+        let enabled = repo
+            .config()
+            .and_then(|config| config.get_bool("core.precomposeunicode"))
+            .unwrap_or(false);
+        self.set_normalization_insensitive(enabled);
+    }
+
+    #[cfg(not(feature = "precompose-unicode"))]
+    fn follow_precompose_unicode(&mut self, _repo: &Repository) {}
+
+    fn relative_key(&self, path: &Path) -> Option<Vec<u8>> {
+        if !path.is_absolute() {
+            if path.as_os_str().is_empty() {
+                return Some(Vec::new());
+            }
+            return util::path_to_repo_path(path).ok().map(|p| p.into_bytes());
+        }
+        let components = self.root.as_ref()?.strip(&util::normalize_path(path))?;
+        if components.is_empty() {
+            return Some(Vec::new());
+        }
+        let rel = components.iter().collect::<PathBuf>();
+        util::path_to_repo_path(&rel).ok().map(|p| p.into_bytes())
+    }
+
+    /// Returns the new path and the entry of the file renamed from `path`.
+    fn renamed_out_of(&self, path: &[u8]) -> Option<(&Vec<u8>, &CachedEntry)> {
+        self.entries.get_key_value(self.renamed.get(path)?)
+    }
+
+    fn add_dir(&mut self, workdir: &Path, dir: &[u8]) {
+        if !self.dirs.contains_key(dir) {
+            let stamp = stamp(&workdir.join(util::bytes2path(dir)));
+            self.dirs.insert(dir.to_vec(), stamp);
+        }
+    }
+
+    /// Records the directories containing `path`.
+    fn add_parents(&mut self, workdir: &Path, path: &[u8]) {
+        let path = match path.last() {
+            Some(b'/') => &path[..path.len() - 1],
+            _ => path,
+        };
+        for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+            self.add_dir(workdir, &path[..i]);
+        }
+    }
+}
+
+impl Root {
+    fn new(repo: &Repository) -> Result<Root, Error> {
+        let workdir = util::normalize_path(workdir(repo)?);
+        Ok(Root {
+            canonical: util::canonicalize_existing(&workdir),
+            workdir,
+            ignore_case: repo.config()?.get_bool("core.ignorecase").unwrap_or(false),
+            aliases: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Returns the components of the normalized absolute `path` below the
+    /// working directory, or `None` if it is outside of it.
+    fn strip(&self, path: &Path) -> Option<Vec<OsString>> {
+        let strip = |dir: &Path| util::strip_dir_prefix(path, dir, self.ignore_case);
+        if let Some(components) = strip(&self.workdir).or_else(|| strip(&self.canonical)) {
+            return Some(components);
+        }
+        let mut aliases = self.aliases.lock().unwrap();
+        if let Some(components) = aliases.iter().find_map(|alias| strip(alias)) {
+            return Some(components);
+        }
+
+        // Resolve the path once, and remember the prefix which led to the
+        // working directory so that later paths through it don't have to be.
+        let canonical = util::canonicalize_existing(path);
+        let components = util::strip_dir_prefix(&canonical, &self.canonical, self.ignore_case)?;
+        let alias = path.ancestors().nth(components.len())?;
+        if util::canonicalize_existing(alias) == self.canonical {
+            aliases.push(alias.to_path_buf());
+        }
+        Some(components)
+    }
+}
+
+/// Returns the items of `map` whose keys start with `prefix`.
+fn under<'a, V>(
+    map: &'a BTreeMap<Vec<u8>, V>,
+    prefix: &'a [u8],
+) -> impl Iterator<Item = (&'a Vec<u8>, &'a V)> + 'a {
+    map.range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(move |(path, _)| path.starts_with(prefix))
+}
+
+/// Returns the items of `set` which start with `prefix`.
+fn under_set<'a>(
+    set: &'a BTreeSet<Vec<u8>>,
+    prefix: &'a [u8],
+) -> impl Iterator<Item = &'a Vec<u8>> + 'a {
+    set.range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(move |path| path.starts_with(prefix))
+}
+
+fn workdir(repo: &Repository) -> Result<&Path, Error> {
+    repo.workdir()
+        .ok_or_else(|| Error::from_str("cannot cache the status of a bare repository"))
+}
+
+fn head_id(repo: &Repository) -> Option<Oid> {
+    repo.refname_to_id("HEAD").ok()
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(Stamp {
+        seconds: mtime.as_secs() as i64,
+        nanoseconds: mtime.subsec_nanos(),
+        size: meta.len(),
+    })
+}
+
+/// Returns `true` if a file with `stamp` looks unchanged from the index
+/// entry, comparing nanoseconds only if the index recorded them.
+fn matches_index(entry: &IndexEntry, stamp: Stamp) -> bool {
+    entry.mtime.seconds() as i64 == stamp.seconds
+        && (entry.mtime.nanoseconds() == 0 || entry.mtime.nanoseconds() == stamp.nanoseconds)
+        && entry.file_size == stamp.size as u32
+}
+
+fn format_stamp(stamp: Option<Stamp>) -> String {
+    match stamp {
+        Some(s) => format!("{}.{}.{}", s.seconds, s.nanoseconds, s.size),
+        None => "-".to_string(),
+    }
+}
+
+/// Parses a stamp written by `format_stamp`, returning `None` if it is
+/// malformed and `Some(None)` for a missing file.
+fn parse_stamp(s: &[u8]) -> Option<Option<Stamp>> {
+    if s == b"-" {
+        return Some(None);
+    }
+    let s = str::from_utf8(s).ok()?;
+    let mut parts = s.split('.');
+    let stamp = Stamp {
+        seconds: parts.next()?.parse().ok()?,
+        nanoseconds: parts.next()?.parse().ok()?,
+        size: parts.next()?.parse().ok()?,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Some(stamp))
+}
+
+fn format_id(id: Option<Oid>) -> String {
+    match id {
+        Some(id) => id.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// Parses an id written by `format_id`, returning `None` if it is malformed
+/// and `Some(None)` for a missing stage.
+fn parse_id(s: &[u8]) -> Option<Option<Oid>> {
+    if s == b"-" {
+        return Some(None);
+    }
+    let s = str::from_utf8(s).ok()?;
+    Oid::from_str(s).ok().map(Some)
+}
+
+pub(crate) fn escape(out: &mut Vec<u8>, path: &[u8]) {
+    for &b in path {
+        match b {
+            b'\\' => out.extend(b"\\\\"),
+            b'\n' => out.extend(b"\\n"),
+            b => out.push(b),
+        }
+    }
+    out.push(b'\n');
+}
+
+pub(crate) fn unescape(path: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(path.len());
+    let mut bytes = path.iter();
+    while let Some(&b) = bytes.next() {
+        match (b, bytes.clone().next()) {
+            (b'\\', Some(b'n')) => {
+                out.push(b'\n');
+                bytes.next();
+            }
+            (b'\\', Some(b'\\')) => {
+                out.push(b'\\');
+                bytes.next();
+            }
+            (b, _) => out.push(b),
+        }
+    }
+    out
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorCode::Invalid, ErrorClass::Invalid, msg)
+}
+
+fn invalid_line(line: &[u8]) -> Error {
+    invalid(&format!(
+        "invalid status cache line '{}'",
+        String::from_utf8_lossy(line)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatusCache, HEADER};
+    use crate::{ConflictKind, ObjectType, Oid, Repository, ResetType, Status};
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        fs::create_dir(td.path().join("dir")).unwrap();
+        fs::write(td.path().join("dir/new"), "new\n").unwrap();
+        fs::write(td.path().join("dir/line\nbreak"), "new\n").unwrap();
+        let mut index = repo.index().unwrap();
+        fs::write(td.path().join("staged"), "staged\n").unwrap();
+        index.add_path(Path::new("staged")).unwrap();
+        index.write().unwrap();
+
+        let cache_path = repo.path().join("status-cache");
+        let cache = StatusCache::open(&repo, &cache_path).unwrap();
+        assert!(cache_path.exists());
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(Path::new("staged")), Status::INDEX_NEW);
+        assert_eq!(cache.get(Path::new("dir/new")), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("dir")), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("")), Status::WT_NEW | Status::INDEX_NEW);
+        assert_eq!(cache.get(Path::new("other")), Status::CURRENT);
+        assert!(cache.is_fresh(&repo).unwrap());
+
+        let loaded = StatusCache::from_bytes(&fs::read(&cache_path).unwrap()).unwrap();
+        assert_eq!(loaded.to_bytes(), cache.to_bytes());
+        assert_eq!(loaded.get(Path::new("dir/line\nbreak")), Status::WT_NEW);
+        assert!(loaded.is_fresh(&repo).unwrap());
+        assert!(StatusCache::from_bytes(b"bogus\n").is_err());
+
+        // Make sure the modification times differ even on coarse filesystems.
+        thread::sleep(Duration::from_millis(1100));
+        fs::write(td.path().join("dir/new"), "changed contents\n").unwrap();
+        assert!(!loaded.is_fresh(&repo).unwrap());
+        let cache = StatusCache::open(&repo, &cache_path).unwrap();
+        assert!(cache.is_fresh(&repo).unwrap());
+
+        fs::write(td.path().join("dir/another"), "x\n").unwrap();
+        assert!(!cache.is_fresh(&repo).unwrap());
+        let cache = StatusCache::open(&repo, &cache_path).unwrap();
+        assert_eq!(cache.len(), 4);
+
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "staged", &tree, &[&head])
+            .unwrap();
+        assert!(!cache.is_fresh(&repo).unwrap());
+        let cache = StatusCache::open(&repo, &cache_path).unwrap();
+        assert_eq!(cache.get(Path::new("staged")), Status::CURRENT);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn conflicts() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let change = |parent: Oid, changes: &[(&str, Option<&str>)]| {
+            let parent = repo.find_commit(parent).unwrap();
+            let mut tb = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            for &(path, contents) in changes {
+                match contents {
+                    Some(contents) => {
+                        let blob = repo.blob(contents.as_bytes()).unwrap();
+                        tb.insert(path, blob, 0o100644).unwrap();
+                    }
+                    None => tb.remove(path).unwrap(),
+                }
+            }
+            let tree = repo.find_tree(tb.write().unwrap()).unwrap();
+            repo.commit(None, &sig, &sig, "change", &tree, &[&parent])
+                .unwrap()
+        };
+        let blob =
+            |contents: &str| Oid::hash_object(ObjectType::Blob, contents.as_bytes()).unwrap();
+
+        let root = repo.head().unwrap().target().unwrap();
+        let base = change(root, &[("a", Some("base\n")), ("b", Some("base\n"))]);
+        let ours = change(base, &[("a", Some("ours\n")), ("b", Some("ours\n"))]);
+        let theirs = change(base, &[("a", Some("theirs\n")), ("b", None)]);
+        let ours = repo.find_object(ours, None).unwrap();
+        repo.reset(&ours, ResetType::Hard, None).unwrap();
+        repo.cherrypick(&repo.find_commit(theirs).unwrap(), None)
+            .unwrap();
+
+        let cache = StatusCache::scan(&repo).unwrap();
+        assert!(cache.get(Path::new("a")).is_conflicted());
+        let a = cache.conflict(Path::new("a")).unwrap();
+        assert_eq!(a.kind(), ConflictKind::BothModified);
+        assert_eq!(a.ancestor, Some(blob("base\n")));
+        assert_eq!(a.our, Some(blob("ours\n")));
+        assert_eq!(a.their, Some(blob("theirs\n")));
+        let b = cache.conflict(Path::new("b")).unwrap();
+        assert_eq!(b.kind(), ConflictKind::DeletedByThem);
+        assert_eq!(b.their, None);
+        assert_eq!(cache.conflict(Path::new("c")), None);
+
+        let loaded = StatusCache::from_bytes(&cache.to_bytes()).unwrap();
+        assert_eq!(loaded.conflict(Path::new("a")), Some(a));
+        assert_eq!(loaded.conflict(Path::new("b")), Some(b));
+        // A conflict line must follow the entry of its file.
+        let bytes = format!("{}\nconflict - - - a\n", HEADER);
+        let err = StatusCache::from_bytes(bytes.as_bytes()).err().unwrap();
+        assert_eq!(
+            err.message(),
+            "invalid status cache line 'conflict - - - a'"
+        );
+        let bytes = format!("{}\nentry 0 - a\nconflict x - - a\n", HEADER);
+        let err = StatusCache::from_bytes(bytes.as_bytes()).err().unwrap();
+        assert_eq!(
+            err.message(),
+            "invalid status cache line 'conflict x - - a'"
+        );
+    }
+
+    #[test]
+    fn renames() {
+        let (td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        for dir in &["old", "keep"] {
+            fs::create_dir(td.path().join(dir)).unwrap();
+            let path = format!("{}/file", dir);
+            fs::write(td.path().join(&path), format!("contents of {}\n", dir)).unwrap();
+            index.add_path(Path::new(&path)).unwrap();
+        }
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "files", &tree, &[&head])
+            .unwrap();
+
+        // A staged rename and a rename in the working directory only.
+        fs::create_dir(td.path().join("new")).unwrap();
+        fs::rename(td.path().join("old/file"), td.path().join("new/file")).unwrap();
+        index.remove_path(Path::new("old/file")).unwrap();
+        index.add_path(Path::new("new/file")).unwrap();
+        index.write().unwrap();
+        fs::create_dir(td.path().join("moved")).unwrap();
+        fs::rename(td.path().join("keep/file"), td.path().join("moved/file")).unwrap();
+
+        let cache = StatusCache::scan(&repo).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(Path::new("new/file")), Status::INDEX_RENAMED);
+        assert_eq!(cache.get(Path::new("old/file")), Status::INDEX_RENAMED);
+        assert_eq!(cache.get(Path::new("old")), Status::INDEX_RENAMED);
+        assert_eq!(cache.get(Path::new("new")), Status::INDEX_RENAMED);
+        assert_eq!(cache.get(Path::new("keep")), Status::WT_RENAMED);
+        assert_eq!(cache.get(Path::new("moved")), Status::WT_RENAMED);
+        assert_eq!(
+            cache.renamed_from(Path::new("new/file")),
+            Some(Path::new("old/file"))
+        );
+        assert_eq!(
+            cache.renamed_to(Path::new("keep/file")),
+            Some(Path::new("moved/file"))
+        );
+        assert_eq!(cache.renamed_from(Path::new("old/file")), None);
+        assert!(cache.is_fresh(&repo).unwrap());
+
+        let loaded = StatusCache::from_bytes(&cache.to_bytes()).unwrap();
+        assert_eq!(loaded.to_bytes(), cache.to_bytes());
+        assert_eq!(
+            loaded.renamed_to(Path::new("old/file")),
+            Some(Path::new("new/file"))
+        );
+        // A renamed line must follow the entry of the renamed file.
+        let bytes = format!("{}\nrenamed a\n", HEADER);
+        let err = StatusCache::from_bytes(bytes.as_bytes()).err().unwrap();
+        assert_eq!(err.message(), "invalid status cache line 'renamed a'");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinked_workdir() {
+        let (td, _repo) = crate::test::repo_init();
+        fs::create_dir(td.path().join("dir")).unwrap();
+        fs::write(td.path().join("dir/new"), "new\n").unwrap();
+        let links = TempDir::new().unwrap();
+        let link = links.path().join("link");
+        std::os::unix::fs::symlink(td.path(), &link).unwrap();
+        let workdir = crate::test::realpath(td.path()).unwrap();
+
+        let repo = Repository::open(&link).unwrap();
+        let cache = StatusCache::scan(&repo).unwrap();
+        assert_eq!(cache.get(&workdir.join("dir/new")), Status::WT_NEW);
+        assert_eq!(cache.get(&link.join("dir/new")), Status::WT_NEW);
+        assert_eq!(cache.get(&link.join("dir")), Status::WT_NEW);
+        assert_eq!(cache.get(&link.join("dir/../dir/new")), Status::WT_NEW);
+        assert_eq!(cache.get(&link), Status::WT_NEW);
+        assert_eq!(cache.get(&links.path().join("dir/new")), Status::CURRENT);
+        {
+            // The link was resolved once, when it was first seen.
+            let root = cache.root.as_ref().unwrap();
+            let aliases = root.aliases.lock().unwrap();
+            if root.workdir != link {
+                assert_eq!(*aliases, vec![link.clone()]);
+            }
+        }
+
+        let loaded = StatusCache::from_bytes(&cache.to_bytes()).unwrap();
+        assert_eq!(loaded.get(&link.join("dir/new")), Status::CURRENT);
+        assert_eq!(loaded.get(Path::new("dir/new")), Status::WT_NEW);
+    }
+
+    #[test]
+    #[cfg(feature = "precompose-unicode")]
+    fn normalization_insensitive() {
+        let (td, repo) = crate::test::repo_init();
+        fs::create_dir(td.path().join("d\u{e9}j\u{e0}")).unwrap();
+        fs::write(td.path().join("d\u{e9}j\u{e0}/caf\u{e9}"), "new\n").unwrap();
+        let decomposed = Path::new("de\u{301}ja\u{300}/cafe\u{301}");
+
+        let mut cache = StatusCache::scan(&repo).unwrap();
+        assert_eq!(cache.get(decomposed), Status::CURRENT);
+        cache.set_normalization_insensitive(true);
+        assert_eq!(cache.get(decomposed), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("de\u{301}ja\u{300}")), Status::WT_NEW);
+        assert_eq!(cache.get(&td.path().join(decomposed)), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("cafe\u{301}")), Status::CURRENT);
+        cache.set_normalization_insensitive(false);
+        assert_eq!(cache.get(decomposed), Status::CURRENT);
+
+        let mut config = repo.config().unwrap();
+        config.set_bool("core.precomposeunicode", true).unwrap();
+        let cache = StatusCache::scan(&repo).unwrap();
+        assert_eq!(cache.get(decomposed), Status::WT_NEW);
+    }
+
+    #[test]
+    fn directory_prefixes() {
+        let (td, repo) = crate::test::repo_init();
+        for dir in &["dir", "dir-x", "dir2", "a/b"] {
+            fs::create_dir_all(td.path().join(dir)).unwrap();
+        }
+        fs::write(td.path().join("dir/x"), "x\n").unwrap();
+        fs::write(td.path().join("dir-x/y"), "y\n").unwrap();
+        fs::write(td.path().join("a/b/c"), "c\n").unwrap();
+        fs::write(td.path().join("dir2/z"), "z\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("dir2/z")).unwrap();
+        index.add_path(Path::new("dir-x/y")).unwrap();
+        index.write().unwrap();
+
+        let cache = StatusCache::scan(&repo).unwrap();
+        assert_eq!(cache.get(Path::new("dir")), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("dir-x")), Status::INDEX_NEW);
+        assert_eq!(cache.get(Path::new("dir2")), Status::INDEX_NEW);
+        assert_eq!(cache.get(Path::new("di")), Status::CURRENT);
+        assert_eq!(cache.get(Path::new("a")), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("a/b")), Status::WT_NEW);
+        assert_eq!(cache.get(Path::new("a/b/c/d")), Status::CURRENT);
+        let paths = cache.iter().map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            ["a/b/c", "dir-x/y", "dir/x", "dir2/z"]
+                .iter()
+                .map(Path::new)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn submodules() {
+        let (_td, repo1) = crate::test::repo_init();
+        let (td, repo2) = crate::test::repo_init();
+        let url = url::Url::from_file_path(repo1.workdir().unwrap()).unwrap();
+        let mut sub = repo2
+            .submodule(url.as_str(), Path::new("lib/sub"), true)
+            .unwrap();
+        fs::remove_dir_all(td.path().join("lib/sub")).unwrap();
+        Repository::clone(url.as_str(), td.path().join("lib/sub")).unwrap();
+        sub.add_to_index(false).unwrap();
+        sub.add_finalize().unwrap();
+        let mut index = repo2.index().unwrap();
+        index.add_path(Path::new(".gitmodules")).unwrap();
+        index.write().unwrap();
+        let sig = repo2.signature().unwrap();
+        let tree = repo2.find_tree(index.write_tree().unwrap()).unwrap();
+        let head = repo2.head().unwrap().peel_to_commit().unwrap();
+        repo2
+            .commit(Some("HEAD"), &sig, &sig, "sub", &tree, &[&head])
+            .unwrap();
+        fs::write(td.path().join("lib/sub/new"), "new\n").unwrap();
+
+        let cache = StatusCache::scan(&repo2).unwrap();
+        assert_eq!(cache.get(Path::new("lib/sub")), Status::WT_MODIFIED);
+        assert_eq!(cache.get(Path::new("lib")), Status::WT_MODIFIED);
+        assert_eq!(cache.get(Path::new("lib/sub/new")), Status::CURRENT);
+
+        let cache = StatusCache::scan_recursive(&repo2).unwrap();
+        assert_eq!(cache.get(Path::new("lib/sub")), Status::WT_MODIFIED);
+        assert_eq!(cache.get(Path::new("lib")), Status::WT_MODIFIED);
+        assert_eq!(cache.get(Path::new("lib/sub/new")), Status::WT_NEW);
+        let loaded = StatusCache::from_bytes(&cache.to_bytes()).unwrap();
+        assert_eq!(loaded.to_bytes(), cache.to_bytes());
+        assert_eq!(loaded.get(Path::new("")), Status::WT_MODIFIED);
+
+        let cache_path = repo2.path().join("status-cache");
+        cache.save(&cache_path).unwrap();
+        let opened = StatusCache::open(&repo2, &cache_path).unwrap();
+        assert_eq!(opened.get(Path::new("lib/sub/new")), Status::CURRENT);
+        let opened = StatusCache::open_recursive(&repo2, &cache_path).unwrap();
+        assert_eq!(opened.get(Path::new("lib/sub/new")), Status::WT_NEW);
+    }
+}
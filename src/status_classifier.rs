@@ -0,0 +1,265 @@
+use crate::Status;
+
+/// Maps the flags of a `Status` to the state a tool displays for a file.
+///
+/// A `Status` can have several flags at once, such as `INDEX_NEW |
+/// WT_MODIFIED` for a file which was added and then changed again. How
+/// these are shown depends on the tool, so instead of picking one flag,
+/// implementations decide what each combination means. Closures taking a
+/// `Status` are classifiers as well.
+pub trait StatusClassifier {
+    /// The state a status is mapped to.
+    type Class;
+
+    /// Returns the state of a file with the given status.
+    fn classify(&self, status: Status) -> Self::Class;
+}
+
+impl<F, C> StatusClassifier for F
+where
+    F: Fn(Status) -> C,
+{
+    type Class = C;
+
+    fn classify(&self, status: Status) -> C {
+        self(status)
+    }
+}
+
+/// How one side of a file, its index or its working directory version,
+/// differs from the other side, as shown by `git status --short`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatusChange {
+    /// Not changed (` `).
+    Unmodified,
+    /// Added (`A`).
+    Added,
+    /// Modified (`M`).
+    Modified,
+    /// Deleted (`D`).
+    Deleted,
+    /// Renamed (`R`).
+    Renamed,
+    /// Changed to a different type of file (`T`).
+    TypeChanged,
+    /// Not tracked (`?`).
+    Untracked,
+    /// Ignored (`!`).
+    Ignored,
+    /// Unmerged (`U`).
+    Conflicted,
+}
+
+impl StatusChange {
+    /// Returns how the index differs from `HEAD`.
+    ///
+    /// Untracked, ignored and conflicted files are reported as such on
+    /// both sides, as `git status --short` does.
+    pub fn index(status: Status) -> StatusChange {
+        StatusChange::shared(status).unwrap_or_else(|| {
+            if status.is_index_new() {
+                StatusChange::Added
+            } else if status.is_index_deleted() {
+                StatusChange::Deleted
+            } else if status.is_index_renamed() {
+                StatusChange::Renamed
+            } else if status.is_index_typechange() {
+                StatusChange::TypeChanged
+            } else if status.is_index_modified() {
+                StatusChange::Modified
+            } else {
+                StatusChange::Unmodified
+            }
+        })
+    }
+
+    /// Returns how the working directory differs from the index.
+    pub fn workdir(status: Status) -> StatusChange {
+        StatusChange::shared(status).unwrap_or_else(|| {
+            if status.is_wt_deleted() {
+                StatusChange::Deleted
+            } else if status.is_wt_renamed() {
+                StatusChange::Renamed
+            } else if status.is_wt_typechange() {
+                StatusChange::TypeChanged
+            } else if status.is_wt_modified() {
+                StatusChange::Modified
+            } else {
+                StatusChange::Unmodified
+            }
+        })
+    }
+
+    fn shared(status: Status) -> Option<StatusChange> {
+        if status.is_conflicted() {
+            Some(StatusChange::Conflicted)
+        } else if status.is_ignored() {
+            Some(StatusChange::Ignored)
+        } else if status.is_wt_new() {
+            Some(StatusChange::Untracked)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the letter `git status --short` uses for this change.
+    pub fn code(&self) -> char {
+        match *self {
+            StatusChange::Unmodified => ' ',
+            StatusChange::Added => 'A',
+            StatusChange::Modified => 'M',
+            StatusChange::Deleted => 'D',
+            StatusChange::Renamed => 'R',
+            StatusChange::TypeChanged => 'T',
+            StatusChange::Untracked => '?',
+            StatusChange::Ignored => '!',
+            StatusChange::Conflicted => 'U',
+        }
+    }
+}
+
+/// Classifies a status as the changes of its index and of its working
+/// directory, so that no combination of flags is lost; `INDEX_NEW |
+/// WT_MODIFIED` is `(Added, Modified)`, shown as `AM`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShortStatus;
+
+impl StatusClassifier for ShortStatus {
+    type Class = (StatusChange, StatusChange);
+
+    fn classify(&self, status: Status) -> (StatusChange, StatusChange) {
+        (StatusChange::index(status), StatusChange::workdir(status))
+    }
+}
+
+/// Classifies statuses with a list of rules, the first one which matches
+/// winning.
+///
+/// A rule matches a status which has all of its flags, so rules for
+/// combinations such as `INDEX_NEW | WT_MODIFIED` should come before the
+/// rules for their flags. Statuses which match no rule get the fallback
+/// class.
+///
+/// The default classifier maps to a single `StatusChange`, with conflicts
+/// first, then ignored and untracked files, deletions, renames, type
+/// changes and modifications, each preferring the index over the working
+/// directory.
+#[derive(Clone, Debug)]
+pub struct PrecedenceClassifier<C> {
+    rules: Vec<(Status, C)>,
+    fallback: C,
+}
+
+impl<C: Clone> PrecedenceClassifier<C> {
+    /// Creates a classifier with no rules, mapping every status to
+    /// `fallback`.
+    pub fn new(fallback: C) -> PrecedenceClassifier<C> {
+        PrecedenceClassifier {
+            rules: Vec::new(),
+            fallback,
+        }
+    }
+
+    /// Adds a rule mapping statuses with all of `flags` to `class`, with a
+    /// lower precedence than the rules added before it.
+    pub fn rule(&mut self, flags: Status, class: C) -> &mut PrecedenceClassifier<C> {
+        self.rules.push((flags, class));
+        self
+    }
+
+    /// Adds a rule mapping statuses with all of `flags` to `class`, with a
+    /// higher precedence than the rules added before it.
+    pub fn override_rule(&mut self, flags: Status, class: C) -> &mut PrecedenceClassifier<C> {
+        self.rules.insert(0, (flags, class));
+        self
+    }
+}
+
+impl Default for PrecedenceClassifier<StatusChange> {
+    fn default() -> Self {
+        let mut classifier = PrecedenceClassifier::new(StatusChange::Unmodified);
+        classifier
+            .rule(Status::CONFLICTED, StatusChange::Conflicted)
+            .rule(Status::IGNORED, StatusChange::Ignored)
+            .rule(Status::WT_NEW, StatusChange::Untracked)
+            .rule(Status::INDEX_NEW, StatusChange::Added)
+            .rule(Status::INDEX_DELETED, StatusChange::Deleted)
+            .rule(Status::WT_DELETED, StatusChange::Deleted)
+            .rule(Status::INDEX_RENAMED, StatusChange::Renamed)
+            .rule(Status::WT_RENAMED, StatusChange::Renamed)
+            .rule(Status::INDEX_TYPECHANGE, StatusChange::TypeChanged)
+            .rule(Status::WT_TYPECHANGE, StatusChange::TypeChanged)
+            .rule(Status::INDEX_MODIFIED, StatusChange::Modified)
+            .rule(Status::WT_MODIFIED, StatusChange::Modified);
+        classifier
+    }
+}
+
+impl<C: Clone> StatusClassifier for PrecedenceClassifier<C> {
+    type Class = C;
+
+    fn classify(&self, status: Status) -> C {
+        self.rules
+            .iter()
+            .find(|(flags, _)| status.contains(*flags))
+            .map(|(_, class)| class.clone())
+            .unwrap_or_else(|| self.fallback.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PrecedenceClassifier, ShortStatus, Status, StatusChange, StatusClassifier};
+
+    #[test]
+    fn short_status() {
+        let code = |status| {
+            let (index, workdir) = ShortStatus.classify(status);
+            format!("{}{}", index.code(), workdir.code())
+        };
+        assert_eq!(code(Status::CURRENT), "  ");
+        assert_eq!(code(Status::INDEX_NEW | Status::WT_MODIFIED), "AM");
+        assert_eq!(code(Status::INDEX_MODIFIED | Status::WT_DELETED), "MD");
+        assert_eq!(code(Status::INDEX_RENAMED | Status::WT_MODIFIED), "RM");
+        assert_eq!(code(Status::WT_TYPECHANGE), " T");
+        assert_eq!(code(Status::WT_NEW), "??");
+        assert_eq!(code(Status::IGNORED), "!!");
+        assert_eq!(code(Status::CONFLICTED | Status::INDEX_MODIFIED), "UU");
+    }
+
+    #[test]
+    fn precedence() {
+        let default = PrecedenceClassifier::default();
+        assert_eq!(default.classify(Status::CURRENT), StatusChange::Unmodified);
+        assert_eq!(
+            default.classify(Status::INDEX_NEW | Status::WT_MODIFIED),
+            StatusChange::Added
+        );
+        assert_eq!(
+            default.classify(Status::INDEX_MODIFIED | Status::WT_DELETED),
+            StatusChange::Deleted
+        );
+
+        let mut custom = PrecedenceClassifier::new("clean");
+        custom
+            .rule(Status::WT_MODIFIED, "dirty")
+            .rule(Status::INDEX_MODIFIED, "staged")
+            .override_rule(
+                Status::INDEX_NEW | Status::WT_MODIFIED,
+                "added, then changed",
+            );
+        assert_eq!(
+            custom.classify(Status::INDEX_NEW | Status::WT_MODIFIED),
+            "added, then changed"
+        );
+        assert_eq!(
+            custom.classify(Status::INDEX_MODIFIED | Status::WT_MODIFIED),
+            "dirty"
+        );
+        assert_eq!(custom.classify(Status::INDEX_MODIFIED), "staged");
+        assert_eq!(custom.classify(Status::INDEX_NEW), "clean");
+
+        let closure = |status: Status| status.is_wt_new();
+        assert!(closure.classify(Status::WT_NEW));
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{Delta, Diff, DiffLineType, Error, Patch};
+
+/// An owned, serializable representation of a `Diff`, as returned by
+/// `Diff::to_structured`.
+///
+/// With the `serde` feature enabled, all structured diff types implement
+/// `Serialize` and `Deserialize`, so a diff can be sent to a client as JSON
+/// (or as JSON lines, one `StructuredFile` per line) instead of as unified
+/// diff text. Paths and line contents which are not valid utf-8 are
+/// converted lossily.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructuredDiff {
+    files: Vec<StructuredFile>,
+}
+
+/// A file in a `StructuredDiff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructuredFile {
+    status: Delta,
+    old_path: Option<String>,
+    new_path: Option<String>,
+    old_id: String,
+    new_id: String,
+    binary: bool,
+    hunks: Vec<StructuredHunk>,
+}
+
+/// A hunk of a `StructuredFile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructuredHunk {
+    header: String,
+    old_start: u32,
+    old_lines: u32,
+    new_start: u32,
+    new_lines: u32,
+    lines: Vec<StructuredLine>,
+}
+
+/// A line of a `StructuredHunk`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructuredLine {
+    origin: char,
+    old_lineno: Option<u32>,
+    new_lineno: Option<u32>,
+    content_offset: i64,
+    content: String,
+}
+
+impl StructuredDiff {
+    pub(crate) fn from_diff(diff: &Diff<'_>) -> Result<StructuredDiff, Error> {
+        let mut files = Vec::new();
+        for (idx, delta) in diff.deltas().enumerate() {
+            let patch = Patch::from_diff(diff, idx)?;
+            let binary = match patch {
+                Some(ref patch) => patch.delta().flags().is_binary(),
+                None => delta.flags().is_binary(),
+            };
+            let mut hunks = Vec::new();
+            if let Some(ref patch) = patch {
+                for (hunk_idx, hunk) in patch.hunks()?.iter().enumerate() {
+                    let lines = patch
+                        .lines_in_hunk(hunk_idx)?
+                        .iter()
+                        .map(|line| StructuredLine {
+                            origin: line.origin(),
+                            old_lineno: line.old_lineno(),
+                            new_lineno: line.new_lineno(),
+                            content_offset: line.content_offset(),
+                            content: String::from_utf8_lossy(line.content()).into_owned(),
+                        })
+                        .collect();
+                    hunks.push(StructuredHunk {
+                        header: String::from_utf8_lossy(hunk.header()).into_owned(),
+                        old_start: hunk.old_start(),
+                        old_lines: hunk.old_lines(),
+                        new_start: hunk.new_start(),
+                        new_lines: hunk.new_lines(),
+                        lines,
+                    });
+                }
+            }
+            let path = |p: Option<&[u8]>| p.map(|p| String::from_utf8_lossy(p).into_owned());
+            files.push(StructuredFile {
+                status: delta.status(),
+                old_path: path(delta.old_file().path_bytes()),
+                new_path: path(delta.new_file().path_bytes()),
+                old_id: delta.old_file().id().to_string(),
+                new_id: delta.new_file().id().to_string(),
+                binary,
+                hunks,
+            });
+        }
+        Ok(StructuredDiff { files })
+    }
+
+    /// Returns the files of this diff.
+    pub fn files(&self) -> &[StructuredFile] {
+        &self.files
+    }
+}
+
+impl StructuredFile {
+    /// Returns the kind of change to this file.
+    pub fn status(&self) -> Delta {
+        self.status
+    }
+
+    /// Returns the path of the file before the change, if it existed.
+    pub fn old_path(&self) -> Option<&str> {
+        self.old_path.as_deref()
+    }
+
+    /// Returns the path of the file after the change, if it exists.
+    pub fn new_path(&self) -> Option<&str> {
+        self.new_path.as_deref()
+    }
+
+    /// Returns the hex id of the blob before the change, which is all zeros
+    /// if the file did not exist or was not hashed.
+    pub fn old_id(&self) -> &str {
+        &self.old_id
+    }
+
+    /// Returns the hex id of the blob after the change, which is all zeros
+    /// if the file does not exist or was not hashed.
+    pub fn new_id(&self) -> &str {
+        &self.new_id
+    }
+
+    /// Returns `true` if the file is binary, in which case it has no hunks.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Returns the hunks of the change to this file.
+    pub fn hunks(&self) -> &[StructuredHunk] {
+        &self.hunks
+    }
+}
+
+impl StructuredHunk {
+    /// Returns the header of this hunk, e.g. `@@ -1,3 +1,4 @@ fn main()`.
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Returns the starting line number in the old file.
+    pub fn old_start(&self) -> u32 {
+        self.old_start
+    }
+
+    /// Returns the number of lines in the old file.
+    pub fn old_lines(&self) -> u32 {
+        self.old_lines
+    }
+
+    /// Returns the starting line number in the new file.
+    pub fn new_start(&self) -> u32 {
+        self.new_start
+    }
+
+    /// Returns the number of lines in the new file.
+    pub fn new_lines(&self) -> u32 {
+        self.new_lines
+    }
+
+    /// Returns the lines of this hunk.
+    pub fn lines(&self) -> &[StructuredLine] {
+        &self.lines
+    }
+}
+
+impl StructuredLine {
+    /// Returns the origin of this line, as for `DiffLine::origin`.
+    pub fn origin(&self) -> char {
+        self.origin
+    }
+
+    /// Returns the kind of this line.
+    pub fn origin_value(&self) -> DiffLineType {
+        match self.origin {
+            '+' => DiffLineType::Addition,
+            '-' => DiffLineType::Deletion,
+            '=' => DiffLineType::ContextEOFNL,
+            '>' => DiffLineType::AddEOFNL,
+            '<' => DiffLineType::DeleteEOFNL,
+            'F' => DiffLineType::FileHeader,
+            'H' => DiffLineType::HunkHeader,
+            'B' => DiffLineType::Binary,
+            _ => DiffLineType::Context,
+        }
+    }
+
+    /// Returns the line number in the old file, or `None` for added lines.
+    pub fn old_lineno(&self) -> Option<u32> {
+        self.old_lineno
+    }
+
+    /// Returns the line number in the new file, or `None` for removed lines.
+    pub fn new_lineno(&self) -> Option<u32> {
+        self.new_lineno
+    }
+
+    /// Returns the byte offset of this line in the file it comes from, or
+    /// -1 for lines which are not file content.
+    pub fn content_offset(&self) -> i64 {
+        self.content_offset
+    }
+
+    /// Returns the content of this line, including its line terminator.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Delta, DiffLineType};
+    use std::fs;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join("foo"), "a\nb\nc\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path("foo".as_ref()).unwrap();
+        index.write().unwrap();
+        fs::write(td.path().join("foo"), "a\nB\nc\nd\n").unwrap();
+
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        let structured = diff.to_structured().unwrap();
+        assert_eq!(structured.files().len(), 1);
+        let file = &structured.files()[0];
+        assert_eq!(file.status(), Delta::Modified);
+        assert_eq!(file.old_path(), Some("foo"));
+        assert_eq!(file.new_path(), Some("foo"));
+        assert!(!file.is_binary());
+        assert_eq!(file.hunks().len(), 1);
+        let hunk = &file.hunks()[0];
+        assert_eq!(hunk.header(), "@@ -1,3 +1,4 @@\n");
+        let lines = hunk
+            .lines()
+            .iter()
+            .map(|l| (l.origin(), l.old_lineno(), l.new_lineno(), l.content()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                (' ', Some(1), Some(1), "a\n"),
+                ('-', Some(2), None, "b\n"),
+                ('+', None, Some(2), "B\n"),
+                (' ', Some(3), Some(3), "c\n"),
+                ('+', None, Some(4), "d\n"),
+            ]
+        );
+        assert_eq!(hunk.lines()[2].content_offset(), 2);
+        assert_eq!(hunk.lines()[2].origin_value(), DiffLineType::Addition);
+    }
+}
@@ -1,7 +1,7 @@
 use std::marker;
 use std::mem;
 use std::os::raw::c_int;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::str;
 
@@ -248,6 +248,164 @@ impl<'repo> Drop for Submodule<'repo> {
     }
 }
 
+/// How a submodule changed in a diff, as returned by
+/// `DiffDelta::submodule_change` and `DiffDelta::submodule_summary`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleChange {
+    path: PathBuf,
+    old: Option<Oid>,
+    new: Option<Oid>,
+    summary: Option<SubmoduleSummary>,
+}
+
+/// A summary of a `SubmoduleChange`, computed from the submodule's own
+/// repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmoduleSummary {
+    ahead: usize,
+    behind: usize,
+    modified_content: bool,
+    untracked_content: bool,
+}
+
+impl SubmoduleChange {
+    pub(crate) fn new(path: PathBuf, old: Option<Oid>, new: Option<Oid>) -> SubmoduleChange {
+        SubmoduleChange {
+            path,
+            old,
+            new,
+            summary: None,
+        }
+    }
+
+    /// Fills in the summary, unless the submodule is not checked out or the
+    /// commits are missing from it.
+    pub(crate) fn summarize(&mut self, repo: &Repository) -> Result<(), Error> {
+        let path = match self.path.to_str() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let sub = match repo.find_submodule(path).and_then(|s| s.open()) {
+            Ok(sub) => sub,
+            Err(_) => return Ok(()),
+        };
+        for &id in self.old.iter().chain(self.new.iter()) {
+            if sub.find_commit(id).is_err() {
+                return Ok(());
+            }
+        }
+        let (ahead, behind) = match (self.old, self.new) {
+            (Some(old), Some(new)) => sub.graph_ahead_behind(new, old)?,
+            (None, Some(new)) => (count_commits(&sub, new)?, 0),
+            (Some(old), None) => (0, count_commits(&sub, old)?),
+            (None, None) => (0, 0),
+        };
+        let status = repo.submodule_status(path, SubmoduleIgnore::None)?;
+        self.summary = Some(SubmoduleSummary {
+            ahead,
+            behind,
+            modified_content: status.has_modified_content(),
+            untracked_content: status.has_untracked_content(),
+        });
+        Ok(())
+    }
+
+    /// Returns the path of the submodule.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the commit recorded for the submodule on the old side, or
+    /// `None` if the submodule was added.
+    pub fn old_id(&self) -> Option<Oid> {
+        self.old
+    }
+
+    /// Returns the commit recorded for, or checked out in, the submodule on
+    /// the new side, or `None` if the submodule was deleted.
+    pub fn new_id(&self) -> Option<Oid> {
+        self.new
+    }
+
+    /// Returns the summary of the change, if it was requested with
+    /// `DiffDelta::submodule_summary` and the submodule and its commits are
+    /// available.
+    pub fn summary(&self) -> Option<&SubmoduleSummary> {
+        self.summary.as_ref()
+    }
+
+    /// Returns the `Submodule <path> <old>..<new>` line `git diff
+    /// --submodule` prints for this change, without a trailing newline, or
+    /// `None` if the recorded commit did not change.
+    ///
+    /// Without a summary, the line reports the commits as not present, as
+    /// git does when it cannot find them.
+    pub fn header(&self) -> Option<String> {
+        if self.old == self.new {
+            return None;
+        }
+        let (separator, suffix) = match (self.old, self.new, self.summary) {
+            (None, _, _) => ("...", " (new submodule)"),
+            (_, None, _) => ("...", " (submodule deleted)"),
+            (_, _, None) => ("...", " (commits not present)"),
+            (_, _, Some(summary)) if summary.behind == 0 => ("..", ":"),
+            (_, _, Some(summary)) if summary.ahead == 0 => ("..", " (rewind):"),
+            (_, _, Some(_)) => ("...", ":"),
+        };
+        Some(format!(
+            "Submodule {} {}{}{}{}",
+            self.path.display(),
+            abbrev(self.old),
+            separator,
+            abbrev(self.new),
+            suffix
+        ))
+    }
+}
+
+impl SubmoduleSummary {
+    /// Returns the number of commits on the new side which are not on the
+    /// old side, shown with `>` by `git diff --submodule=log`.
+    pub fn ahead(&self) -> usize {
+        self.ahead
+    }
+
+    /// Returns the number of commits on the old side which are not on the
+    /// new side, shown with `<` by `git diff --submodule=log`.
+    pub fn behind(&self) -> usize {
+        self.behind
+    }
+
+    /// Returns whether tracked files in the submodule's working directory are
+    /// modified.
+    pub fn has_modified_content(&self) -> bool {
+        self.modified_content
+    }
+
+    /// Returns whether the submodule's working directory contains untracked
+    /// files.
+    pub fn has_untracked_content(&self) -> bool {
+        self.untracked_content
+    }
+}
+
+fn count_commits(repo: &Repository, tip: Oid) -> Result<usize, Error> {
+    let mut walk = repo.revwalk()?;
+    walk.push(tip)?;
+    let mut count = 0;
+    for id in walk {
+        id?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn abbrev(id: Option<Oid>) -> String {
+    let mut hex = id.unwrap_or_else(Oid::zero).to_string();
+    hex.truncate(7);
+    hex
+}
+
 /// Options to update a submodule.
 pub struct SubmoduleUpdateOptions<'cb> {
     checkout_builder: CheckoutBuilder<'cb>,
@@ -362,6 +520,63 @@ mod tests {
         t!(s.add_finalize());
     }
 
+    #[test]
+    fn diff_submodule_change() {
+        let (_td, repo1) = crate::test::repo_init();
+        let (td, repo2) = crate::test::repo_init();
+
+        let url = Url::from_file_path(&repo1.workdir().unwrap()).unwrap();
+        let mut s = repo2
+            .submodule(&url.to_string(), Path::new("bar"), true)
+            .unwrap();
+        t!(fs::remove_dir_all(td.path().join("bar")));
+        let sub = t!(Repository::clone(&url.to_string(), td.path().join("bar")));
+        let mut config = t!(sub.config());
+        t!(config.set_str("user.name", "name"));
+        t!(config.set_str("user.email", "email"));
+        t!(s.add_to_index(false));
+        t!(s.add_finalize());
+        let old = t!(sub.head()).target().unwrap();
+
+        let sig = t!(sub.signature());
+        let head = t!(sub.find_commit(old));
+        let tree = t!(head.tree());
+        let new = t!(sub.commit(Some("HEAD"), &sig, &sig, "next", &tree, &[&head]));
+        t!(fs::write(td.path().join("bar/untracked"), "x"));
+
+        let diff = t!(repo2.diff_index_to_workdir(None, None));
+        let delta = diff
+            .deltas()
+            .find(|d| d.new_file().path() == Some(Path::new("bar")))
+            .unwrap();
+        let change = delta.submodule_change().unwrap();
+        assert_eq!(change.path(), Path::new("bar"));
+        assert_eq!(change.old_id(), Some(old));
+        assert_eq!(change.new_id(), Some(new));
+        assert!(change.summary().is_none());
+
+        let change = t!(delta.submodule_summary(&repo2)).unwrap();
+        let summary = change.summary().unwrap();
+        assert_eq!((summary.ahead(), summary.behind()), (1, 0));
+        assert!(summary.has_untracked_content());
+        assert!(!summary.has_modified_content());
+        assert_eq!(
+            change.header().unwrap(),
+            format!(
+                "Submodule bar {}..{}:",
+                &old.to_string()[..7],
+                &new.to_string()[..7]
+            )
+        );
+
+        let gitmodules = diff
+            .deltas()
+            .find(|d| d.new_file().path() == Some(Path::new(".gitmodules")));
+        if let Some(delta) = gitmodules {
+            assert!(delta.submodule_change().is_none());
+        }
+    }
+
     #[test]
     fn update_submodule() {
         // -----------------------------------
@@ -0,0 +1,153 @@
+//! Helpers for the test suites of code using this crate.
+//!
+//! Only available with the `test-support` feature, which is meant to be
+//! enabled from `[dev-dependencies]`.
+
+use std::collections::HashMap;
+
+use crate::{Repository, Status, StatusChange, StatusOptions};
+
+/// Asserts that the files of `repo` have the given statuses.
+///
+/// Paths are relative to the working directory, with `/` separators, and a
+/// path which has no changes is `Status::CURRENT`. On failure, this panics
+/// with the table of `check_statuses`.
+pub fn assert_statuses(repo: &Repository, expected: &[(&str, Status)]) {
+    if let Err(table) = check_statuses(repo, expected) {
+        panic!("{}", table);
+    }
+}
+
+/// Compares the statuses of the files of `repo` to `expected`.
+///
+/// On a mismatch, the error is a table of every expected path with its
+/// expected and actual status, both as the two letter code of
+/// `git status --short` and as flags, and with the differing rows marked.
+/// Renames are not detected, so a renamed file shows as deleted and new.
+pub fn check_statuses(repo: &Repository, expected: &[(&str, Status)]) -> Result<(), String> {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(true)
+        .recurse_ignored_dirs(true);
+    let statuses = repo
+        .statuses(Some(&mut opts))
+        .map_err(|e| format!("failed to read the statuses: {}", e))?;
+    let actual = statuses
+        .iter()
+        .map(|entry| (entry.path_bytes().to_vec(), entry.status()))
+        .collect::<HashMap<_, _>>();
+
+    let mut rows = vec![(
+        String::new(),
+        "path".to_string(),
+        "expected".to_string(),
+        "actual".to_string(),
+    )];
+    let mut mismatches = 0;
+    for &(path, status) in expected {
+        let found = actual
+            .get(path.as_bytes())
+            .cloned()
+            .unwrap_or(Status::CURRENT);
+        let marker = if found == status {
+            ""
+        } else {
+            mismatches += 1;
+            "!"
+        };
+        rows.push((
+            marker.to_string(),
+            path.to_string(),
+            describe(status),
+            describe(found),
+        ));
+    }
+    if mismatches == 0 {
+        return Ok(());
+    }
+
+    let width = |column: fn(&(String, String, String, String)) -> &String| {
+        rows.iter().map(|row| column(row).len()).max().unwrap_or(0)
+    };
+    let (path_width, expected_width) = (width(|row| &row.1), width(|row| &row.2));
+    let mut table = format!(
+        "{} of {} paths have unexpected statuses:\n",
+        mismatches,
+        expected.len()
+    );
+    for (marker, path, expected, actual) in rows {
+        let line = format!(
+            "{:1} {:pw$}  {:ew$}  {}",
+            marker,
+            path,
+            expected,
+            actual,
+            pw = path_width,
+            ew = expected_width
+        );
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    Err(table)
+}
+
+/// Formats a status as `XY (FLAGS)`.
+fn describe(status: Status) -> String {
+    let code = format!(
+        "{}{}",
+        StatusChange::index(status).code(),
+        StatusChange::workdir(status).code()
+    );
+    if status.is_empty() {
+        format!("{} (CURRENT)", code)
+    } else {
+        format!("{} ({:?})", code, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{assert_statuses, check_statuses};
+    use crate::Status;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn statuses() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join("added"), "added\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("added")).unwrap();
+        index.write().unwrap();
+        fs::write(td.path().join("added"), "changed\n").unwrap();
+        fs::create_dir(td.path().join("dir")).unwrap();
+        fs::write(td.path().join("dir/new"), "new\n").unwrap();
+
+        assert_statuses(
+            &repo,
+            &[
+                ("added", Status::INDEX_NEW | Status::WT_MODIFIED),
+                ("dir/new", Status::WT_NEW),
+                ("other", Status::CURRENT),
+            ],
+        );
+
+        let table = check_statuses(
+            &repo,
+            &[
+                ("added", Status::INDEX_NEW),
+                ("dir/new", Status::WT_NEW),
+                ("other", Status::WT_DELETED),
+            ],
+        )
+        .unwrap_err();
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "2 of 3 paths have unexpected statuses:");
+        assert!(lines[2].starts_with("! added"));
+        assert!(lines[2].ends_with("AM (INDEX_NEW | WT_MODIFIED)"));
+        assert!(lines[3].starts_with("  dir/new"));
+        assert!(lines[4].starts_with("! other"));
+        assert!(lines[4].ends_with("   (CURRENT)"));
+    }
+}
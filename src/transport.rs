@@ -50,19 +50,49 @@ pub trait SmartSubtransport: Send + 'static {
     ///
     /// 1. UploadPackLs -> UploadPack
     /// 2. ReceivePackLs -> ReceivePack
-    fn close(&self) -> Result<(), Error>;
+    ///
+    /// The default implementation does nothing, which suits subtransports
+    /// whose connections are closed by dropping their streams.
+    fn close(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 /// Actions that a smart transport can ask a subtransport to perform
-#[derive(Copy, Clone, PartialEq)]
-#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Service {
+    /// List the references of the remote before a fetch.
     UploadPackLs,
+    /// Negotiate and download a packfile for a fetch.
     UploadPack,
+    /// List the references of the remote before a push.
     ReceivePackLs,
+    /// Upload a packfile and update references for a push.
     ReceivePack,
 }
 
+impl Service {
+    /// Returns the git program serving this action on the remote,
+    /// `git-upload-pack` or `git-receive-pack`.
+    pub fn program(&self) -> &'static str {
+        match *self {
+            Service::UploadPackLs | Service::UploadPack => "git-upload-pack",
+            Service::ReceivePackLs | Service::ReceivePack => "git-receive-pack",
+        }
+    }
+
+    /// Returns `true` for the actions listing the references of the remote,
+    /// which start a new connection.
+    pub fn is_ls(&self) -> bool {
+        *self == Service::UploadPackLs || *self == Service::ReceivePackLs
+    }
+
+    /// Returns `true` for the actions of a push.
+    pub fn is_push(&self) -> bool {
+        *self == Service::ReceivePackLs || *self == Service::ReceivePack
+    }
+}
+
 /// An instance of a stream over which a smart transport will communicate with a
 /// remote.
 ///
@@ -121,6 +151,30 @@ where
     Ok(())
 }
 
+/// Add a custom smart transport for urls starting with `prefix`, such as
+/// `"echo://"`.
+///
+/// This is a shorthand for `register` with a factory wrapping each
+/// subtransport returned by `factory` in `Transport::smart`, so that a
+/// transport is implemented entirely through the safe `SmartSubtransport`
+/// trait. The `rpc` argument is as for `Transport::smart`.
+///
+/// See the `echo-transport` example for a subtransport forwarding the
+/// protocol to `git upload-pack` and `git receive-pack`.
+///
+/// This function is unsafe for the same reason as `register`: libgit2 does
+/// not synchronize its list of transports, so this must not run while other
+/// threads may be creating transports.
+pub unsafe fn register_smart<F, S>(prefix: &str, rpc: bool, factory: F) -> Result<(), Error>
+where
+    F: Fn(&Remote<'_>) -> Result<S, Error> + Send + Sync + 'static,
+    S: SmartSubtransport,
+{
+    register(prefix, move |remote| {
+        Transport::smart(remote, rpc, factory(remote)?)
+    })
+}
+
 impl Transport {
     /// Creates a new transport which will use the "smart" transport protocol
     /// for transferring data.
@@ -379,7 +433,7 @@ extern "C" fn stream_free(stream: *mut raw::git_smart_subtransport_stream) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{ErrorClass, ErrorCode};
+    use crate::{ErrorClass, ErrorCode, Oid};
     use std::sync::Once;
 
     struct DummyTransport;
@@ -426,4 +480,99 @@ mod tests {
             Err(e) => assert_eq!(e, dummy_error()),
         }
     }
+
+    // Serves a fixed reference advertisement and records the actions
+    // requested.
+    struct CannedTransport {
+        services: std::sync::Arc<std::sync::Mutex<Vec<Service>>>,
+        advertisement: Vec<u8>,
+    }
+
+    struct CannedStream {
+        data: io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for CannedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.data.read(buf)
+        }
+    }
+
+    impl Write for CannedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SmartSubtransport for CannedTransport {
+        fn action(
+            &self,
+            _url: &str,
+            service: Service,
+        ) -> Result<Box<dyn SmartSubtransportStream>, Error> {
+            self.services.lock().unwrap().push(service);
+            Ok(Box::new(CannedStream {
+                data: io::Cursor::new(self.advertisement.clone()),
+            }))
+        }
+    }
+
+    fn pkt_line(line: &str) -> String {
+        format!("{:04x}{}", line.len() + 4, line)
+    }
+
+    #[test]
+    fn register_smart_lists_refs() {
+        static INIT: Once = Once::new();
+        let id = Oid::hash_object(crate::ObjectType::Blob, b"canned").unwrap();
+        let services = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let advertisement = format!(
+            "{}{}0000",
+            pkt_line(&format!("{} HEAD\0side-band-64k\n", id)),
+            pkt_line(&format!("{} refs/heads/main\n", id))
+        );
+        let shared = services.clone();
+        unsafe {
+            INIT.call_once(|| {
+                register_smart("canned", false, move |_remote| {
+                    Ok(CannedTransport {
+                        services: shared.clone(),
+                        advertisement: advertisement.clone().into_bytes(),
+                    })
+                })
+                .unwrap();
+            })
+        }
+
+        let (_td, repo) = crate::test::repo_init();
+        let mut origin = repo.remote_anonymous("canned://example").unwrap();
+        let connection = origin
+            .connect_auth(crate::Direction::Fetch, None, None)
+            .unwrap();
+        let heads = connection
+            .list()
+            .unwrap()
+            .iter()
+            .map(|head| (head.name().to_string(), head.oid()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            heads,
+            [
+                ("HEAD".to_string(), id),
+                ("refs/heads/main".to_string(), id)
+            ]
+        );
+        drop(connection);
+        assert_eq!(*services.lock().unwrap(), [Service::UploadPackLs]);
+
+        assert_eq!(Service::UploadPack.program(), "git-upload-pack");
+        assert!(Service::ReceivePackLs.is_ls());
+        assert!(Service::ReceivePack.is_push());
+        assert!(!Service::UploadPack.is_ls());
+    }
 }
@@ -4,11 +4,11 @@ use std::ffi::{CStr, CString};
 use std::marker;
 use std::mem;
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::str;
 
-use crate::util::{c_cmp_to_ordering, path_to_repo_path, Binding};
+use crate::util::{self, c_cmp_to_ordering, path_to_repo_path, Binding};
 use crate::{panic, raw, Error, Object, ObjectType, Oid, Repository};
 
 /// A structure to represent a git [tree][1]
@@ -187,6 +187,130 @@ impl<'repo> Tree<'repo> {
         }
     }
 
+    /// Check whether each of the given paths exists in this tree or in any of
+    /// its subtrees.
+    ///
+    /// This answers the same question as calling `get_path` for every path,
+    /// but the paths are sorted first so that each subtree along the way is
+    /// only looked up once, which is much faster for long lists of paths.
+    pub fn contains_paths<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Vec<bool>, Error> {
+        let mut items = Vec::with_capacity(paths.len());
+        for (i, path) in paths.iter().enumerate() {
+            if path.as_ref().as_os_str().is_empty() {
+                continue;
+            }
+            let path = path_to_repo_path(path.as_ref())?;
+            let components = path
+                .as_bytes()
+                .split(|b| *b == b'/')
+                .filter(|c| !c.is_empty())
+                .map(|c| c.to_vec())
+                .collect::<Vec<_>>();
+            if !components.is_empty() {
+                items.push((components, i));
+            }
+        }
+        items.sort();
+        let mut found = vec![false; paths.len()];
+        self.find_paths(&items, 0, &mut found)?;
+        Ok(found)
+    }
+
+    fn find_paths(
+        &self,
+        mut items: &[(Vec<Vec<u8>>, usize)],
+        depth: usize,
+        found: &mut [bool],
+    ) -> Result<(), Error> {
+        while let Some((components, _)) = items.first() {
+            let name = &components[depth];
+            let len = items.iter().take_while(|(c, _)| c[depth] == *name).count();
+            let (group, rest) = items.split_at(len);
+            items = rest;
+
+            let name = CString::new(name.clone())?;
+            let entry = unsafe { call!(raw::git_tree_entry_byname(&*self.raw(), name)) };
+            if entry.is_null() {
+                continue;
+            }
+            // Sorting puts the paths ending at this entry before the deeper
+            // ones.
+            let leaves = group
+                .iter()
+                .take_while(|(c, _)| c.len() == depth + 1)
+                .count();
+            for (_, i) in &group[..leaves] {
+                found[*i] = true;
+            }
+            let deeper = &group[leaves..];
+            unsafe {
+                if deeper.is_empty() || raw::git_tree_entry_type(entry) != raw::GIT_OBJECT_TREE {
+                    continue;
+                }
+                let mut subtree = ptr::null_mut();
+                try_call!(raw::git_tree_lookup(
+                    &mut subtree,
+                    raw::git_tree_owner(&*self.raw()),
+                    raw::git_tree_entry_id(entry)
+                ));
+                let subtree: Tree<'repo> = Binding::from_raw(subtree);
+                subtree.find_paths(deeper, depth + 1, found)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of the blobs with id `id` in this tree and its
+    /// subtrees, in the order `git ls-tree -r` lists them.
+    ///
+    /// The tree is traversed once. To look up many blobs in the same tree,
+    /// use a `BlobPathCache`, which indexes the tree on the first lookup.
+    pub fn paths_of(&self, id: Oid) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = Vec::new();
+        self.for_each_blob(&mut |blob, path| {
+            if blob == id {
+                paths.push(path.to_path_buf());
+            }
+        })?;
+        Ok(paths)
+    }
+
+    /// Calls `f` with the id and path of every blob in this tree and its
+    /// subtrees. Submodules are skipped.
+    pub(crate) fn for_each_blob(&self, f: &mut dyn FnMut(Oid, &Path)) -> Result<(), Error> {
+        self.visit_blobs(&mut Vec::new(), f)
+    }
+
+    fn visit_blobs(
+        &self,
+        prefix: &mut Vec<u8>,
+        f: &mut dyn FnMut(Oid, &Path),
+    ) -> Result<(), Error> {
+        for entry in self.iter() {
+            let len = prefix.len();
+            prefix.extend_from_slice(entry.name_bytes());
+            match entry.kind() {
+                Some(ObjectType::Blob) => f(entry.id(), util::bytes2path(prefix)),
+                Some(ObjectType::Tree) => {
+                    let mut subtree = ptr::null_mut();
+                    unsafe {
+                        try_call!(raw::git_tree_lookup(
+                            &mut subtree,
+                            raw::git_tree_owner(&*self.raw()),
+                            entry.id().raw()
+                        ));
+                    }
+                    let subtree: Tree<'repo> = unsafe { Binding::from_raw(subtree) };
+                    prefix.push(b'/');
+                    subtree.visit_blobs(prefix, f)?;
+                }
+                _ => {}
+            }
+            prefix.truncate(len);
+        }
+        Ok(())
+    }
+
     /// Casts this Tree to be usable as an `Object`
     pub fn as_object(&self) -> &Object<'repo> {
         unsafe { &*(self as *const _ as *const Object<'repo>) }
@@ -400,7 +524,7 @@ impl<'tree> ExactSizeIterator for TreeIter<'tree> {}
 mod tests {
     use super::{TreeWalkMode, TreeWalkResult};
     use crate::{Object, ObjectType, Repository, Tree, TreeEntry};
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::prelude::*;
     use std::path::Path;
     use tempfile::TempDir;
@@ -556,4 +680,31 @@ mod tests {
         .unwrap();
         assert_eq!(ct, 1);
     }
+
+    #[test]
+    fn contains_paths() {
+        let (td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+        for path in &["a/b/c", "a/b/d", "a/e", "f"] {
+            let path = td.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap();
+        }
+        index
+            .add_all(["*"].iter(), crate::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let paths = [
+            "f", "a/b/d", "a/x", "a", "a/b/c/d", "f/g", "a/e", "", "a/b/c", "f",
+        ];
+        let found = tree.contains_paths(&paths).unwrap();
+        assert_eq!(
+            found,
+            vec![true, true, false, true, false, false, true, false, true, true]
+        );
+        for (path, found) in paths.iter().zip(found).filter(|(p, _)| !p.is_empty()) {
+            assert_eq!(tree.get_path(Path::new(path)).is_ok(), found);
+        }
+    }
 }
@@ -0,0 +1,235 @@
+use std::path::Path;
+use std::str;
+
+use crate::{AttrCheckFlags, Error, Index, Repository, Tree, TreeWalkMode, TreeWalkResult};
+use crate::{ErrorClass, ErrorCode, ObjectType};
+
+/// The attribute and ignore rules of a tree, as a checkout of the tree
+/// would apply them.
+///
+/// The `.gitattributes` and `.gitignore` files are read from the tree rather
+/// than from the working directory, which makes the rules usable in bare
+/// repositories, for example to process pushed commits on a server. Rules
+/// which do not come from the tree, such as `info/attributes`,
+/// `info/exclude` and the files configured by `core.attributesFile` and
+/// `core.excludesFile`, apply as well.
+///
+/// The rules are evaluated on repository handles of their own, so creating
+/// and using a `TreeRules` does not affect the repository it was created
+/// from. Created by `Repository::tree_rules`.
+pub struct TreeRules {
+    attrs: Repository,
+    ignores: Repository,
+}
+
+impl TreeRules {
+    pub(crate) fn new(repo: &Repository, tree: &Tree<'_>) -> Result<TreeRules, Error> {
+        let attrs = if repo.is_bare() {
+            Repository::open_bare(repo.path())?
+        } else {
+            Repository::open(repo.path())?
+        };
+        let mut index = Index::new()?;
+        index.read_tree(tree)?;
+        attrs.set_index(&mut index)?;
+
+        // A bare handle, so that libgit2 does not read the `.gitignore` files
+        // of the working directory. Those of the tree are added as rules of
+        // the handle instead, which take precedence over the other ignore
+        // files like the `.gitignore` files would.
+        let ignores = Repository::open_bare(repo.path())?;
+        let mut files = Vec::new();
+        let mut error = None;
+        tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.name_bytes() == b".gitignore" && entry.kind() == Some(ObjectType::Blob) {
+                match repo.find_blob(entry.id()) {
+                    Ok(blob) => files.push((dir.to_string(), blob.content().to_vec())),
+                    Err(e) => {
+                        error = Some(e);
+                        return TreeWalkResult::Abort;
+                    }
+                }
+            }
+            TreeWalkResult::Ok
+        })
+        .or_else(|e| match error.take() {
+            Some(error) => Err(error),
+            None => Err(e),
+        })?;
+        // Rules added later win, so deeper directories come last.
+        files.sort_by_key(|(dir, _)| dir.matches('/').count());
+        for (dir, contents) in files {
+            let contents = String::from_utf8_lossy(&contents);
+            let rules = contents
+                .lines()
+                .filter_map(|line| scope_ignore_rule(&dir, line))
+                .collect::<Vec<_>>();
+            if !rules.is_empty() {
+                ignores.add_ignore_rule(&rules.join("\n"))?;
+            }
+        }
+        Ok(TreeRules { attrs, ignores })
+    }
+
+    /// Get the value of a git attribute for a path as a string.
+    ///
+    /// See `get_attr_bytes`.
+    pub fn get_attr(
+        &self,
+        path: &Path,
+        name: &str,
+        flags: AttrCheckFlags,
+    ) -> Result<Option<&str>, Error> {
+        Ok(self
+            .get_attr_bytes(path, name, flags)?
+            .and_then(|a| str::from_utf8(a).ok()))
+    }
+
+    /// Get the value of a git attribute for a path as a byte slice, as
+    /// `Repository::get_attr_bytes` does.
+    ///
+    /// The source bits of `flags` are ignored, the `.gitattributes` files are
+    /// always read from the tree; only `AttrCheckFlags::NO_SYSTEM` is
+    /// honored.
+    pub fn get_attr_bytes(
+        &self,
+        path: &Path,
+        name: &str,
+        flags: AttrCheckFlags,
+    ) -> Result<Option<&[u8]>, Error> {
+        let flags = (flags & AttrCheckFlags::NO_SYSTEM) | AttrCheckFlags::INDEX_ONLY;
+        self.attrs.get_attr_bytes(path, name, flags)
+    }
+
+    /// Test if the ignore rules apply to a given path, as
+    /// `Repository::is_path_ignored` does.
+    ///
+    /// Since nothing is read from the working directory, a path names a
+    /// directory only if it ends with a `/`.
+    pub fn is_path_ignored(&self, path: &Path) -> Result<bool, Error> {
+        if path.is_absolute() {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Invalid,
+                format!("path '{}' is not relative to the tree", path.display()),
+            ));
+        }
+        self.ignores.is_path_ignored(path)
+    }
+}
+
+/// Rewrites a line of the `.gitignore` file in the directory `dir` of a tree
+/// (empty or ending with a `/`) into a rule for the root of the tree, or
+/// returns `None` if the line holds no rule.
+fn scope_ignore_rule(dir: &str, line: &str) -> Option<String> {
+    let line = line.trim_end_matches('\r');
+    if line.trim().is_empty() || line.starts_with('#') {
+        return None;
+    }
+    if dir.is_empty() {
+        return Some(line.to_string());
+    }
+    let (negate, pattern) = match line.strip_prefix('!') {
+        Some(pattern) => ("!", pattern),
+        None => ("", line),
+    };
+    // A pattern with a slash other than a trailing one is relative to the
+    // directory of the file, any other pattern matches at any depth below
+    // it.
+    if pattern.trim_end_matches('/').contains('/') {
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        Some(format!("{}/{}{}", negate, dir, pattern))
+    } else {
+        Some(format!("{}/{}**/{}", negate, dir, pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scope_ignore_rule;
+    use crate::{AttrCheckFlags, AttrValue, Repository};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn smoke() {
+        let td = TempDir::new().unwrap();
+        let repo = Repository::init_bare(td.path()).unwrap();
+        let attributes = repo.blob(b"*.txt text\n*.bin -text\n").unwrap();
+        let root = repo.blob(b"*.log\n/build/\n").unwrap();
+        let nested = repo.blob(b"!keep.log\ntmp\n/only-here\n").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert(".gitignore", nested, 0o100644).unwrap();
+        let sub = repo.find_tree(builder.write().unwrap()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder
+            .insert(".gitattributes", attributes, 0o100644)
+            .unwrap();
+        builder.insert(".gitignore", root, 0o100644).unwrap();
+        builder.insert("sub", sub.id(), 0o040000).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+        let rules = repo.tree_rules(&tree).unwrap();
+        let flags = AttrCheckFlags::NO_SYSTEM;
+        let attr = |path: &str| {
+            let value = rules
+                .get_attr_bytes(Path::new(path), "text", flags)
+                .unwrap();
+            AttrValue::from_bytes(value)
+        };
+        assert_eq!(attr("a.txt"), AttrValue::True);
+        assert_eq!(attr("a.bin"), AttrValue::False);
+        let value = rules.get_attr(Path::new("a.rs"), "text", flags).unwrap();
+        assert_eq!(AttrValue::from_string(value), AttrValue::Unspecified);
+        assert_eq!(repo.index().unwrap().len(), 0);
+
+        let ignored = |path: &str| rules.is_path_ignored(Path::new(path)).unwrap();
+        assert!(ignored("a.log"));
+        assert!(ignored("sub/a.log"));
+        assert!(!ignored("sub/keep.log"));
+        assert!(ignored("keep.log"));
+        assert!(ignored("build/x"));
+        assert!(!ignored("sub/build/x"));
+        assert!(ignored("sub/x/tmp"));
+        assert!(ignored("sub/tmp"));
+        assert!(!ignored("tmp"));
+        assert!(ignored("sub/only-here"));
+        assert!(!ignored("sub/x/only-here"));
+        assert!(rules.is_path_ignored(Path::new("/a.log")).is_err());
+    }
+
+    #[test]
+    fn worktree_files_are_not_read() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join(".gitignore"), "*.log\n").unwrap();
+        fs::write(td.path().join(".gitattributes"), "*.txt text\n").unwrap();
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        let rules = repo.tree_rules(&tree).unwrap();
+        assert!(repo.is_path_ignored("a.log").unwrap());
+        assert!(!rules.is_path_ignored(Path::new("a.log")).unwrap());
+        let value = rules
+            .get_attr(Path::new("a.txt"), "text", AttrCheckFlags::NO_SYSTEM)
+            .unwrap();
+        assert_eq!(AttrValue::from_string(value), AttrValue::Unspecified);
+    }
+
+    #[test]
+    fn scope() {
+        assert_eq!(scope_ignore_rule("", "*.log"), Some("*.log".to_string()));
+        assert_eq!(scope_ignore_rule("a/", "# comment"), None);
+        assert_eq!(scope_ignore_rule("a/", "  "), None);
+        assert_eq!(
+            scope_ignore_rule("a/", "*.log"),
+            Some("/a/**/*.log".to_string())
+        );
+        assert_eq!(
+            scope_ignore_rule("a/", "!b/"),
+            Some("!/a/**/b/".to_string())
+        );
+        assert_eq!(
+            scope_ignore_rule("a/", "/b/c\r"),
+            Some("/a/b/c".to_string())
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use libc::{c_char, c_int, size_t};
 use std::cmp::Ordering;
 use std::ffi::{CString, OsStr, OsString};
+use std::fs;
 use std::iter::IntoIterator;
 use std::path::{Component, Path, PathBuf};
 
-use crate::{raw, Error};
+use crate::{raw, Error, FileMode};
 
 #[doc(hidden)]
 pub trait IsNull {
@@ -235,10 +236,186 @@ pub fn path_to_repo_path(path: &Path) -> Result<CString, Error> {
     }
 }
 
+/// Determines the mode git records for a file in the working directory from
+/// its (symlink) metadata.
+pub fn file_mode(meta: &fs::Metadata) -> FileMode {
+    if meta.file_type().is_symlink() {
+        return FileMode::Link;
+    }
+    if meta.is_dir() {
+        return FileMode::Tree;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::prelude::*;
+        if meta.permissions().mode() & 0o111 != 0 {
+            return FileMode::BlobExecutable;
+        }
+    }
+    FileMode::Blob
+}
+
+/// Returns whether `path` is a file that git would run as a hook.
+pub fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(meta) => {
+            meta.is_file() && (cfg!(not(unix)) || file_mode(&meta) == FileMode::BlobExecutable)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Resolves `.` and `..` components without touching the file system.
+///
+/// A `..` at the root stays at the root.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut out: Vec<Component<'_>> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.last() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // `..` in the root directory is the root directory.
+                Some(Component::RootDir) => {}
+                _ => out.push(component),
+            },
+            c => out.push(c),
+        }
+    }
+    out.iter().collect()
+}
+
+/// Canonicalizes the longest existing ancestor of `path` and appends the
+/// rest of it, so that paths which don't exist yet can be resolved too.
+pub fn canonicalize_existing(path: &Path) -> PathBuf {
+    let mut rest = Vec::new();
+    let mut base = path;
+    loop {
+        if let Ok(canonical) = fs::canonicalize(base) {
+            return rest.iter().rev().fold(canonical, |p, c| p.join(c));
+        }
+        match (base.parent(), base.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                base = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Returns the components of `path` below `dir`, or `None` if `path` is not
+/// inside `dir`. Both paths should be normalized.
+pub fn strip_dir_prefix(path: &Path, dir: &Path, ignore_case: bool) -> Option<Vec<OsString>> {
+    let mut components = path.components();
+    for expected in dir.components() {
+        let actual = components.next()?;
+        let same = match (actual.as_os_str().to_str(), expected.as_os_str().to_str()) {
+            (Some(a), Some(b)) if ignore_case => a.to_lowercase() == b.to_lowercase(),
+            _ => actual == expected,
+        };
+        if !same {
+            return None;
+        }
+    }
+    Some(components.map(|c| c.as_os_str().to_os_string()).collect())
+}
+
 pub fn cstring_to_repo_path<T: IntoCString>(path: T) -> Result<CString, Error> {
     fixup_windows_path(path.into_c_string()?)
 }
 
+/// Translates git's pathspec magic into the pathspec syntax understood by
+/// libgit2.
+///
+/// Excluding pathspecs are turned into negative patterns and moved to the
+/// front, as libgit2 stops at the first pattern that matches a path. If only
+/// excluding pathspecs are given, a pattern matching everything is appended.
+pub fn pathspec_magic(pathspecs: Vec<CString>) -> Result<Vec<CString>, Error> {
+    let mut excludes = Vec::new();
+    let mut includes = Vec::new();
+    for spec in pathspecs {
+        let bytes = spec.as_bytes();
+        if bytes.first() != Some(&b':') {
+            includes.push(spec);
+            continue;
+        }
+
+        let mut exclude = false;
+        let mut literal = false;
+        let pattern;
+        if bytes.get(1) == Some(&b'(') {
+            let end = match bytes.iter().position(|b| *b == b')') {
+                Some(end) => end,
+                None => {
+                    return Err(Error::from_str(&format!(
+                        "missing ')' at the end of pathspec magic in '{}'",
+                        String::from_utf8_lossy(bytes)
+                    )))
+                }
+            };
+            for magic in bytes[2..end].split(|b| *b == b',') {
+                match magic {
+                    b"top" | b"glob" | b"" => {}
+                    b"exclude" => exclude = true,
+                    b"literal" => literal = true,
+                    _ => {
+                        return Err(Error::from_str(&format!(
+                            "unsupported pathspec magic '{}'",
+                            String::from_utf8_lossy(magic)
+                        )))
+                    }
+                }
+            }
+            pattern = &bytes[end + 1..];
+        } else {
+            let mut rest = &bytes[1..];
+            while let Some((&b, tail)) = rest.split_first() {
+                match b {
+                    b'/' => {}
+                    b'!' | b'^' => exclude = true,
+                    b':' => {
+                        rest = tail;
+                        break;
+                    }
+                    _ => break,
+                }
+                rest = tail;
+            }
+            pattern = rest;
+        }
+
+        let mut out = Vec::with_capacity(pattern.len() + 1);
+        if exclude {
+            out.push(b'!');
+        }
+        for &b in pattern {
+            if literal && (b == b'\\' || b == b'*' || b == b'?' || b == b'[') {
+                out.push(b'\\');
+            }
+            out.push(b);
+        }
+        if out.is_empty() {
+            // An empty pattern after magic (e.g. `:/`) means the whole tree.
+            out.push(b'*');
+        }
+        let out = CString::new(out)?;
+        if exclude {
+            excludes.push(out);
+        } else {
+            includes.push(out);
+        }
+    }
+
+    if includes.is_empty() && !excludes.is_empty() {
+        includes.push(CString::new("*")?);
+    }
+    excludes.extend(includes);
+    Ok(excludes)
+}
+
 #[cfg(windows)]
 fn fixup_windows_path<P: Into<Vec<u8>>>(path: P) -> Result<CString, Error> {
     let mut bytes: Vec<u8> = path.into();
@@ -280,6 +457,49 @@ mod tests {
         };
     }
 
+    #[test]
+    fn normalize_and_strip() {
+        assert_eq!(normalize_path(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize_path(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(normalize_path(Path::new("../../x")), Path::new("../../x"));
+        assert_eq!(normalize_path(Path::new("a/../../x")), Path::new("../x"));
+        assert_eq!(
+            strip_dir_prefix(Path::new("/a/b/c"), Path::new("/a"), false),
+            Some(vec![OsString::from("b"), OsString::from("c")])
+        );
+        assert_eq!(
+            strip_dir_prefix(Path::new("/ab/c"), Path::new("/a"), false),
+            None
+        );
+        assert_eq!(
+            strip_dir_prefix(Path::new("/A/b"), Path::new("/a"), false),
+            None
+        );
+        assert_eq!(
+            strip_dir_prefix(Path::new("/A/b"), Path::new("/a"), true),
+            Some(vec![OsString::from("b")])
+        );
+    }
+
+    #[test]
+    fn pathspec_magic_translate() {
+        fn magic(specs: &[&str]) -> Vec<String> {
+            let specs = specs.iter().map(|s| CString::new(*s).unwrap()).collect();
+            pathspec_magic(specs)
+                .unwrap()
+                .into_iter()
+                .map(|s| s.into_string().unwrap())
+                .collect()
+        }
+        assert_eq!(magic(&["foo", ":!bar"]), ["!bar", "foo"]);
+        assert_eq!(magic(&[":(exclude)bar"]), ["!bar", "*"]);
+        assert_eq!(magic(&[":/src"]), ["src"]);
+        assert_eq!(magic(&[":(top,literal)a*b"]), ["a\\*b"]);
+        assert_eq!(magic(&[":/"]), ["*"]);
+        assert!(pathspec_magic(vec![CString::new(":(icase)a").unwrap()]).is_err());
+        assert!(pathspec_magic(vec![CString::new(":(top").unwrap()]).is_err());
+    }
+
     #[test]
     #[cfg(windows)]
     fn path_to_repo_path_translate() {
@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+use std::str;
+
+use crate::{Error, Oid, Repository, Revwalk, Sort};
+
+/// The state of a `ResumableWalk`, which can be serialized and used to
+/// resume the walk later, possibly in another process.
+///
+/// A checkpoint records the commits the walk starts from and the commits it
+/// hides. A new checkpoint describes a walk from scratch; the checkpoint of
+/// a walk in progress starts from the commits it has not reached yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalkCheckpoint {
+    tips: Vec<Oid>,
+    hidden: Vec<Oid>,
+    first_parent: bool,
+}
+
+/// A walk over commits in topological order which can be interrupted and
+/// resumed from a `WalkCheckpoint`.
+///
+/// This lets incremental indexers process huge histories in bounded
+/// batches: take some commits from the walk, save its `checkpoint`, and
+/// resume from it in the next run. Every commit of the original walk is
+/// yielded exactly once across the runs, parents always after their
+/// children.
+pub struct ResumableWalk<'repo> {
+    repo: &'repo Repository,
+    walk: Revwalk<'repo>,
+    hidden: Vec<Oid>,
+    first_parent: bool,
+    frontier: Vec<Oid>,
+    frontier_set: HashSet<Oid>,
+}
+
+impl WalkCheckpoint {
+    /// Creates a checkpoint for a walk which has not started, without any
+    /// commits to walk.
+    pub fn new() -> WalkCheckpoint {
+        WalkCheckpoint::default()
+    }
+
+    /// Walk the commits reachable from `oid`, like `Revwalk::push`.
+    pub fn push(&mut self, oid: Oid) -> &mut WalkCheckpoint {
+        if !self.tips.contains(&oid) {
+            self.tips.push(oid);
+        }
+        self
+    }
+
+    /// Skip the commits reachable from `oid`, like `Revwalk::hide`.
+    pub fn hide(&mut self, oid: Oid) -> &mut WalkCheckpoint {
+        if !self.hidden.contains(&oid) {
+            self.hidden.push(oid);
+        }
+        self
+    }
+
+    /// Only follow the first parent of each commit, like
+    /// `Revwalk::simplify_first_parent`.
+    pub fn first_parent(&mut self, first_parent: bool) -> &mut WalkCheckpoint {
+        self.first_parent = first_parent;
+        self
+    }
+
+    /// Returns the commits the walk starts from.
+    pub fn tips(&self) -> &[Oid] {
+        &self.tips
+    }
+
+    /// Returns the commits whose history the walk skips.
+    pub fn hidden(&self) -> &[Oid] {
+        &self.hidden
+    }
+
+    /// Returns `true` if there are no commits left to walk.
+    pub fn is_finished(&self) -> bool {
+        self.tips.is_empty()
+    }
+
+    /// Serialize this checkpoint.
+    ///
+    /// The format is a line per commit, `tip <id>` or `hide <id>`, and a
+    /// `first-parent` line if set.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+        for tip in self.tips.iter() {
+            out.push_str(&format!("tip {}\n", tip));
+        }
+        for hidden in self.hidden.iter() {
+            out.push_str(&format!("hide {}\n", hidden));
+        }
+        if self.first_parent {
+            out.push_str("first-parent\n");
+        }
+        out.into_bytes()
+    }
+
+    /// Deserialize a checkpoint written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<WalkCheckpoint, Error> {
+        let text = str::from_utf8(bytes)
+            .map_err(|_| Error::from_str("walk checkpoint is not valid utf-8"))?;
+        let mut checkpoint = WalkCheckpoint::new();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("tip"), Some(id)) => {
+                    checkpoint.push(Oid::from_str(id)?);
+                }
+                (Some("hide"), Some(id)) => {
+                    checkpoint.hide(Oid::from_str(id)?);
+                }
+                (Some("first-parent"), None) => {
+                    checkpoint.first_parent(true);
+                }
+                (Some(""), None) => {}
+                _ => {
+                    return Err(Error::from_str(&format!(
+                        "invalid walk checkpoint line '{}'",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(checkpoint)
+    }
+}
+
+impl<'repo> ResumableWalk<'repo> {
+    /// Start or resume the walk described by `checkpoint`.
+    pub fn new(
+        repo: &'repo Repository,
+        checkpoint: &WalkCheckpoint,
+    ) -> Result<ResumableWalk<'repo>, Error> {
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        if checkpoint.first_parent {
+            walk.simplify_first_parent()?;
+        }
+        for tip in checkpoint.tips.iter() {
+            walk.push(*tip)?;
+        }
+        for hidden in checkpoint.hidden.iter() {
+            walk.hide(*hidden)?;
+        }
+        Ok(ResumableWalk {
+            repo,
+            walk,
+            hidden: checkpoint.hidden.clone(),
+            first_parent: checkpoint.first_parent,
+            frontier: checkpoint.tips.clone(),
+            frontier_set: checkpoint.tips.iter().cloned().collect(),
+        })
+    }
+
+    /// Returns a checkpoint from which the rest of this walk can be resumed.
+    ///
+    /// Since commits are yielded in topological order, the commits left to
+    /// walk are exactly those reachable from the parents of the commits
+    /// walked so far (and from the tips not reached yet), which is what the
+    /// checkpoint records. Parents which are hidden, or reachable from a
+    /// hidden commit, are left out, so the checkpoint of a walk which has
+    /// yielded everything is finished.
+    pub fn checkpoint(&self) -> WalkCheckpoint {
+        WalkCheckpoint {
+            tips: self
+                .frontier
+                .iter()
+                .filter(|id| self.frontier_set.contains(id))
+                .filter(|id| !self.is_hidden(**id))
+                .cloned()
+                .collect(),
+            hidden: self.hidden.clone(),
+            first_parent: self.first_parent,
+        }
+    }
+
+    fn is_hidden(&self, id: Oid) -> bool {
+        self.hidden.iter().any(|hidden| {
+            *hidden == id || self.repo.graph_descendant_of(*hidden, id).unwrap_or(false)
+        })
+    }
+
+    fn visit(&mut self, id: Oid) -> Result<(), Error> {
+        self.frontier_set.remove(&id);
+        let commit = self.repo.find_commit(id)?;
+        let parents = if self.first_parent {
+            commit.parent_ids().take(1).collect::<Vec<_>>()
+        } else {
+            commit.parent_ids().collect()
+        };
+        for parent in parents {
+            if self.frontier_set.insert(parent) {
+                self.frontier.push(parent);
+            }
+        }
+        // Drop visited commits once they make up most of the list.
+        if self.frontier.len() > 2 * self.frontier_set.len() + 16 {
+            let set = &self.frontier_set;
+            self.frontier.retain(|id| set.contains(id));
+        }
+        Ok(())
+    }
+}
+
+impl<'repo> Iterator for ResumableWalk<'repo> {
+    type Item = Result<Oid, Error>;
+    fn next(&mut self) -> Option<Result<Oid, Error>> {
+        let id = match self.walk.next()? {
+            Ok(id) => id,
+            Err(e) => return Some(Err(e)),
+        };
+        match self.visit(id) {
+            Ok(()) => Some(Ok(id)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Oid, Repository, ResumableWalk, Signature, WalkCheckpoint};
+
+    fn commit(repo: &Repository, parents: &[Oid], message: &str) -> Oid {
+        let sig = Signature::now("name", "email").unwrap();
+        let tree = repo.find_commit(parents[0]).unwrap().tree().unwrap();
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        repo.commit(None, &sig, &sig, message, &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn resume_in_batches() {
+        let (_td, repo) = crate::test::repo_init();
+        let root = repo.head().unwrap().target().unwrap();
+        let mut main = root;
+        let mut side = root;
+        for i in 0..4 {
+            main = commit(&repo, &[main], &format!("main {}", i));
+            side = commit(&repo, &[side], &format!("side {}", i));
+        }
+        let merge = commit(&repo, &[main, side], "merge");
+        let tip = commit(&repo, &[merge], "tip");
+
+        let mut checkpoint = WalkCheckpoint::new();
+        checkpoint.push(tip).hide(root);
+        let all = ResumableWalk::new(&repo, &checkpoint)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(all.len(), 10);
+
+        let mut seen = Vec::new();
+        let mut state = checkpoint.to_bytes();
+        let mut runs = 0;
+        loop {
+            let checkpoint = WalkCheckpoint::from_bytes(&state).unwrap();
+            if checkpoint.is_finished() {
+                break;
+            }
+            runs += 1;
+            let mut walk = ResumableWalk::new(&repo, &checkpoint).unwrap();
+            for id in walk.by_ref().take(3) {
+                let id = id.unwrap();
+                // Parents always come after their children.
+                let commit = repo.find_commit(id).unwrap();
+                for parent in commit.parent_ids() {
+                    assert!(!seen.contains(&parent));
+                }
+                seen.push(id);
+            }
+            state = walk.checkpoint().to_bytes();
+        }
+        assert_eq!(runs, 4);
+        let mut expected = all.clone();
+        expected.sort();
+        seen.sort();
+        assert_eq!(seen, expected);
+
+        let mut checkpoint = WalkCheckpoint::new();
+        checkpoint.push(tip).hide(root).first_parent(true);
+        let bytes = checkpoint.to_bytes();
+        assert_eq!(WalkCheckpoint::from_bytes(&bytes).unwrap(), checkpoint);
+        let mut walk = ResumableWalk::new(&repo, &checkpoint).unwrap();
+        assert_eq!(walk.by_ref().take(2).count(), 2);
+        let rest = ResumableWalk::new(&repo, &walk.checkpoint())
+            .unwrap()
+            .count();
+        assert_eq!(rest, 4);
+        assert!(WalkCheckpoint::from_bytes(b"tip nope\n").is_err());
+    }
+}
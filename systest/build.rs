@@ -13,6 +13,7 @@ fn main() {
         .header("git2/sys/odb_backend.h")
         .header("git2/sys/mempack.h")
         .header("git2/sys/repository.h")
+        .header("git2/sys/index.h")
         .header("git2/sys/cred.h")
         .header("git2/cred_helpers.h")
         .type_name(|s, _, _| s.to_string());
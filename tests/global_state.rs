@@ -45,3 +45,63 @@ fn search_path() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// Test for the process-wide clock installed with `opts::set_time_source`,
+// which would leak into the timestamps of tests running in parallel.
+#[test]
+fn time_source() -> Result<(), Box<dyn std::error::Error>> {
+    use git2::{LockOptions, Repository, Signature, Time};
+    use std::time::Duration;
+
+    let td = tempfile::TempDir::new()?;
+    let repo = Repository::init(td.path())?;
+    let mut config = repo.config()?;
+    config.set_str("user.name", "name")?;
+    config.set_str("user.email", "email")?;
+    let lock = td.path().join("file.lock");
+    std::fs::write(&lock, "")?;
+    let mut opts = LockOptions::new();
+    opts.stale_after(Duration::from_secs(3600));
+
+    opts::set_time_source(|| Time::new(1234567890, 60));
+    let now = Signature::now("foo", "bar")?;
+    let default = repo.signature()?;
+    // The lock file was modified after 2009, so it is not stale yet.
+    let stale_in_2009 = opts.is_stale(&lock);
+    opts::set_time_source(|| Time::new(i32::max_value() as i64, 0));
+    let stale_in_2038 = opts.is_stale(&lock);
+    opts::reset_time_source();
+
+    assert_eq!(now.when().seconds(), 1234567890);
+    assert_eq!(now.when().offset_minutes(), 60);
+    assert!(default.when() == now.when());
+    assert_eq!(default.name(), Some("name"));
+    assert!(!stale_in_2009);
+    assert!(stale_in_2038);
+    assert!(!opts.is_stale(&lock));
+    assert!(Signature::now("foo", "bar")?.when().seconds() > 1234567890);
+    Ok(())
+}
+
+// Opening a repository with a profile changes the process-global cache size,
+// which would leak into other tests if it were not kept in its own binary.
+#[test]
+fn profile() -> Result<(), Box<dyn std::error::Error>> {
+    use git2::opts::Profile;
+    use git2::{FsyncMode, Repository};
+
+    let td = tempfile::TempDir::new()?;
+    Repository::init(td.path())?;
+    let repo = Repository::open_with_profile(td.path(), Profile::Server)?;
+    assert_eq!(repo.fsync_mode()?, FsyncMode::Durable);
+    assert_eq!(opts::get_cached_memory()?.1, 1 << 30);
+
+    // Opening with a weaker profile keeps what the stronger one set.
+    let repo = Repository::open_with_profile(td.path(), Profile::Desktop)?;
+    assert_eq!(repo.fsync_mode()?, FsyncMode::Durable);
+    assert_eq!(opts::get_cached_memory()?.1, 1 << 30);
+
+    unsafe { Profile::Desktop.apply()? };
+    assert_eq!(opts::get_cached_memory()?.1, 256 << 20);
+    Ok(())
+}